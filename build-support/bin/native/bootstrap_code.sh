@@ -33,6 +33,10 @@ case "$MODE" in
   *) MODE_FLAG="--release" ;;
 esac
 
+# N.B. Set $NATIVE_ENGINE_FEATURES to a space separated list of optional cargo features of the
+# engine (such as "profiling") to build them. Some require a newer toolchain than `rust-toolchain`.
+readonly NATIVE_ENGINE_FEATURES="${NATIVE_ENGINE_FEATURES:-}"
+
 readonly NATIVE_ENGINE_CACHE_DIR=${CACHE_ROOT}/bin/native-engine
 
 function calculate_current_hash() {
@@ -44,6 +48,7 @@ function calculate_current_hash() {
   (
    cd "${REPO_ROOT}" || exit 1
    (echo "${MODE_FLAG}"
+    echo "${NATIVE_ENGINE_FEATURES}"
     echo "${RUST_TOOLCHAIN}"
     uname
     python --version 2>&1
@@ -64,7 +69,7 @@ function _build_native_code() {
   (
     cd "${REPO_ROOT}"
     "${REPO_ROOT}/build-support/bin/native/cargo" build ${MODE_FLAG} \
-      --manifest-path "${NATIVE_ROOT}/Cargo.toml" -p engine
+      --manifest-path "${NATIVE_ROOT}/Cargo.toml" -p engine --features "${NATIVE_ENGINE_FEATURES}"
   ) || die
   echo "${NATIVE_ROOT}/target/${MODE}/libengine.${LIB_EXTENSION}"
 }
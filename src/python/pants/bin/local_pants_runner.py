@@ -5,6 +5,7 @@
 from __future__ import absolute_import, division, print_function, unicode_literals
 
 import logging
+import os
 from builtins import object, str
 
 from pants.base.build_environment import get_buildroot
@@ -94,14 +95,23 @@ class LocalPantsRunner(object):
       return graph_session
 
     native = Native()
-    native.set_panic_handler()
+    native.set_panic_handler(global_options.native_engine_crash_report_dir)
     graph_scheduler_helper = EngineInitializer.setup_legacy_graph(
       native,
       options_bootstrapper,
       build_config
     )
 
-    return graph_scheduler_helper.new_session(global_options.v2_ui)
+    return graph_scheduler_helper.new_session(global_options.v2_ui, global_options.v2_timeout,
+                                              global_options.v2_ui_level,
+                                              global_options.process_execution_strategy,
+                                              global_options.v2_execution_policy)
+
+  @staticmethod
+  def _maybe_start_engine_profiling(graph_session, global_options):
+    if global_options.native_engine_profile_to:
+      graph_session.scheduler_session.start_profiling(
+        global_options.native_engine_profile_frequency)
 
   @staticmethod
   def _maybe_init_target_roots(target_roots, graph_session, options, build_root):
@@ -159,6 +169,8 @@ class LocalPantsRunner(object):
       global_options
     )
 
+    cls._maybe_start_engine_profiling(graph_session, global_options)
+
     target_roots = cls._maybe_init_target_roots(
       target_roots,
       graph_session,
@@ -217,6 +229,11 @@ class LocalPantsRunner(object):
     self._run_start_time = start_time
     self._reporting.initialize(self._run_tracker, self._options, start_time=self._run_start_time)
 
+    # Keep a copy of everything the engine writes to the console alongside the other run info.
+    if self._graph_session:
+      self._graph_session.scheduler_session.tee_console_to(
+        os.path.join(self._run_tracker.run_info_dir, 'console.log'))
+
     # Capture a repro of the 'before' state for this build, if needed.
     self._repro = Reproducer.global_instance().create_repro()
     if self._repro:
@@ -275,6 +292,24 @@ class LocalPantsRunner(object):
       self._target_roots,
     )
 
+  def _maybe_write_run_report(self):
+    run_report_path = self._global_options.v2_run_report
+    if run_report_path and self._graph_session:
+      try:
+        self._graph_session.scheduler_session.write_run_report(run_report_path)
+      except Exception as e:
+        # A failure to write the report should not fail the run.
+        logger.warning('Failed to write the run report to {}: {}'.format(run_report_path, e))
+
+  def _maybe_write_engine_profile(self):
+    profile_path = self._global_options.native_engine_profile_to
+    if profile_path and self._graph_session:
+      try:
+        self._graph_session.scheduler_session.write_profile(profile_path)
+      except Exception as e:
+        # A failure to write the profile should not fail the run.
+        logger.warning('Failed to write the engine profile to {}: {}'.format(profile_path, e))
+
   @staticmethod
   def _compute_final_exit_code(*codes):
     """Returns the exit code with higher abs value in case of negative values."""
@@ -291,6 +326,8 @@ class LocalPantsRunner(object):
       engine_result = self._maybe_run_v2()
       goal_runner_result = self._maybe_run_v1()
     finally:
+      self._maybe_write_run_report()
+      self._maybe_write_engine_profile()
       try:
         run_tracker_result = self._run_tracker.end()
       except ValueError as e:
@@ -9,10 +9,32 @@ import sys
 from colors import blue, green, red
 
 
+class _TeeStream(object):
+  """Wraps a stream so that everything written to it is also passed to a tee function."""
+
+  def __init__(self, stream, tee):
+    self._stream = stream
+    self._tee = tee
+
+  def write(self, payload):
+    self._stream.write(payload)
+    self._tee(payload)
+
+  def __getattr__(self, name):
+    return getattr(self._stream, name)
+
+
 class Console(object):
-  def __init__(self, stdout=None, stderr=None, use_colors=True):
+  def __init__(self, stdout=None, stderr=None, use_colors=True, tee=None):
+    """
+    :param tee: If set, a function which is passed a copy of everything written to the console
+                (such as `SchedulerSession.write_to_console_tee`).
+    """
     self._stdout = stdout or sys.stdout
     self._stderr = stderr or sys.stderr
+    if tee:
+      self._stdout = _TeeStream(self._stdout, tee)
+      self._stderr = _TeeStream(self._stderr, tee)
     self._use_colors = use_colors
 
   @property
@@ -13,7 +13,7 @@ from pants.engine.rules import RootRule
 from pants.option.custom_types import GlobExpansionConjunction
 from pants.option.global_options import GlobMatchErrorBehavior
 from pants.util.dirutil import maybe_read_file, safe_delete, safe_file_dump
-from pants.util.objects import Exactly, datatype
+from pants.util.objects import Exactly, datatype, enum
 
 
 class FileContent(datatype([('path', text_type), ('content', binary_type)])):
@@ -26,21 +26,54 @@ class FileContent(datatype([('path', text_type), ('content', binary_type)])):
     return repr(self)
 
 
+class FileContentView(datatype([('path', text_type), ('content', memoryview)])):
+  """The content of a file, as a read-only view of memory owned by the engine.
+
+  Unlike a FileContent, the content is not copied when it is passed to python, which makes this
+  type preferable for rules which inspect large files. Call `.tobytes()` on the content to get a
+  copy as `bytes`.
+  """
+
+  def __repr__(self):
+    return 'FileContentView(path={}, content=(len:{}))'.format(self.path, len(self.content))
+
+  def __str__(self):
+    return repr(self)
+
+
+class SpecialFileBehavior(enum(['warn', 'error'])):
+  """Describe the action to perform when a glob matches a FIFO, socket or device node.
+
+  Such special files cannot be captured, so are either skipped with a warning, or fail the capture
+  with an error naming them.
+
+  NB: this object is interpreted from within Snapshot::lift_path_globs() -- that method will need to
+  be aware of any changes to this object's definition.
+  """
+
+
 class PathGlobs(datatype([
     'include',
     'exclude',
     ('glob_match_error_behavior', GlobMatchErrorBehavior),
     ('conjunction', GlobExpansionConjunction),
+    ('max_file_bytes', Exactly(int, type(None))),
+    ('max_total_bytes', Exactly(int, type(None))),
+    ('special_file_behavior', SpecialFileBehavior),
 ])):
   """A wrapper around sets of filespecs to include and exclude.
 
   The syntax supported is roughly git's glob syntax.
 
+  If `max_file_bytes` or `max_total_bytes` are set, they override the configured
+  --snapshot-max-file-bytes and --snapshot-max-total-bytes limits for captures of these globs.
+
   NB: this object is interpreted from within Snapshot::lift_path_globs() -- that method will need to
   be aware of any changes to this object's definition.
   """
 
-  def __new__(cls, include, exclude=(), glob_match_error_behavior=None, conjunction=None):
+  def __new__(cls, include, exclude=(), glob_match_error_behavior=None, conjunction=None,
+              max_file_bytes=None, max_total_bytes=None, special_file_behavior=None):
     """Given various file patterns create a PathGlobs object (without using filesystem operations).
 
     :param include: A list of filespecs to include.
@@ -49,6 +82,10 @@ class PathGlobs(datatype([
                                                              files.
     :param GlobExpansionConjunction conjunction: Whether all globs are expected to match at least
                                                  one file, or if any glob matching is ok.
+    :param int max_file_bytes: If set, the maximum size of any one captured file.
+    :param int max_total_bytes: If set, the maximum total size of the captured files.
+    :param SpecialFileBehavior special_file_behavior: How to respond to globs matching FIFOs,
+                                                      sockets or device nodes.
     :rtype: :class:`PathGlobs`
     """
     return super(PathGlobs, cls).__new__(
@@ -56,7 +93,10 @@ class PathGlobs(datatype([
       include=tuple(include),
       exclude=tuple(exclude),
       glob_match_error_behavior=(glob_match_error_behavior or GlobMatchErrorBehavior.ignore),
-      conjunction=(conjunction or GlobExpansionConjunction.any_match))
+      conjunction=(conjunction or GlobExpansionConjunction.any_match),
+      max_file_bytes=max_file_bytes,
+      max_total_bytes=max_total_bytes,
+      special_file_behavior=(special_file_behavior or SpecialFileBehavior.warn))
 
 
 class Digest(datatype([('fingerprint', text_type), ('serialized_bytes_length', int)])):
@@ -118,6 +158,7 @@ class PathGlobsAndRoot(datatype([
     ('path_globs', PathGlobs),
     ('root', text_type),
     ('digest_hint', Exactly(Digest, type(None))),
+    ('remote_only', bool),
 ])):
   """A set of PathGlobs to capture relative to some root (which may exist outside of the buildroot).
 
@@ -125,10 +166,14 @@ class PathGlobsAndRoot(datatype([
   expand and Digest the globs. The hint is an optimization that allows for bypassing filesystem
   operations in cases where the expected Digest is known, and the content for the Digest is already
   stored.
+
+  If `remote_only` is set, the content of the captured files is stored directly in the remote store
+  (which must be configured) rather than in the local store. This avoids doubling the local disk
+  usage of enormous inputs which will never be read locally, such as release artifacts.
   """
 
-  def __new__(cls, path_globs, root, digest_hint=None):
-    return super(PathGlobsAndRoot, cls).__new__(cls, path_globs, root, digest_hint)
+  def __new__(cls, path_globs, root, digest_hint=None, remote_only=False):
+    return super(PathGlobsAndRoot, cls).__new__(cls, path_globs, root, digest_hint, remote_only)
 
 
 class Snapshot(datatype([('directory_digest', Digest), ('files', tuple), ('dirs', tuple)])):
@@ -152,11 +197,53 @@ class DirectoryWithPrefixToStrip(datatype([('directory_digest', Digest), ('prefi
   pass
 
 
+class DirectoryWithPrefixToAdd(datatype([('directory_digest', Digest), ('prefix', text_type)])):
+  """A request to nest the contents of a directory digest beneath the given relative prefix."""
+
+
+class SnapshotSubset(datatype([('directory_digest', Digest), ('globs', PathGlobs)])):
+  """A request for a Snapshot of the files in a directory digest which match the given globs."""
+
+
+class ArchiveToExtract(datatype([('directory_digest', Digest)])):
+  """A request to extract the single archive file in a directory digest into a new directory digest.
+
+  The archive format (zip, tar, tar.gz or tar.zst) is determined by the extension of the file name.
+  """
+
+
+class DirectoryToArchive(datatype([('directory_digest', Digest), ('archive_name', text_type)])):
+  """A request to pack the contents of a directory digest into an archive file.
+
+  The result is a directory digest containing only the archive, named `archive_name`. The archive
+  format (zip, tar, tar.gz or tar.zst) is determined by the extension of that name, and is
+  reproducible: entries are sorted and timestamps, ownership and permissions are fixed.
+  """
+
+
 class DirectoryToMaterialize(datatype([('path', text_type), ('directory_digest', Digest)])):
   """A request to materialize the contents of a directory digest at the provided path."""
   pass
 
 
+class WorkspaceWrite(datatype([('path', text_type),
+                               ('directory_digest', Digest),
+                               ('conflict_policy', text_type)])):
+  """A request to write the contents of a directory digest into the workspace at the provided path.
+
+  Unlike DirectoryToMaterialize, the path may already contain files. `conflict_policy` determines
+  what happens to files which would be replaced: one of 'error' (fail before writing anything),
+  'overwrite', or 'backup' (move them aside with a `.bak` suffix).
+  """
+
+  def __new__(cls, path, directory_digest, conflict_policy='error'):
+    return super(WorkspaceWrite, cls).__new__(cls, path, directory_digest, conflict_policy)
+
+
+class WorkspaceManifest(datatype([('written', tuple), ('backed_up', tuple)])):
+  """The paths written by a WorkspaceWrite, and the backups made of any paths that they replaced."""
+
+
 class UrlToFetch(datatype([('url', text_type), ('digest', Digest)])):
   pass
 
@@ -164,6 +251,9 @@ class UrlToFetch(datatype([('url', text_type), ('digest', Digest)])):
 FilesContent = Collection.of(FileContent)
 
 
+FilesContentView = Collection.of(FileContentView)
+
+
 # TODO: don't recreate this in python, get this from fs::EMPTY_DIGEST somehow.
 _EMPTY_FINGERPRINT = 'e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855'
 
@@ -187,5 +277,9 @@ def create_fs_rules():
     RootRule(DirectoriesToMerge),
     RootRule(PathGlobs),
     RootRule(DirectoryWithPrefixToStrip),
+    RootRule(DirectoryWithPrefixToAdd),
+    RootRule(SnapshotSubset),
+    RootRule(ArchiveToExtract),
+    RootRule(DirectoryToArchive),
     RootRule(UrlToFetch),
   ]
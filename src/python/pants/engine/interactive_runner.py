@@ -0,0 +1,175 @@
+# coding=utf-8
+# Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+# Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+from __future__ import absolute_import, division, print_function, unicode_literals
+
+import time
+
+from future.utils import binary_type
+
+from pants.engine.fs import EMPTY_DIRECTORY_DIGEST, Digest
+from pants.util.objects import (Exactly, datatype, hashable_string_list, string_optional,
+                                string_type)
+
+
+class InteractiveProcessRequest(datatype([
+  ('argv', hashable_string_list),
+  ('env', hashable_string_list),
+  ('input_files', Digest),
+  ('description', string_type),
+])):
+  """Request to run a process in the foreground, with exclusive use of the console.
+
+  The process runs locally (even if remote execution is configured) in a sandbox containing its
+  input files, with the real stdin, stdout and stderr of pants. Its outputs are not captured, and
+  it has no timeout.
+  """
+
+  def __new__(cls, argv, env=None, input_files=EMPTY_DIRECTORY_DIGEST, description=''):
+    if env is None:
+      env = ()
+    else:
+      if not isinstance(env, dict):
+        raise cls.make_type_error(
+          "arg 'env' was invalid: value {} (with type {}) must be a dict".format(env, type(env)))
+      env = tuple(item for pair in env.items() for item in pair)
+    return super(InteractiveProcessRequest, cls).__new__(
+      cls, argv, env, input_files, description or ' '.join(argv))
+
+
+class InteractiveProcessResult(datatype([('exit_code', int)])):
+  pass
+
+
+class BackgroundProcessRequest(datatype([
+  ('argv', hashable_string_list),
+  ('env', hashable_string_list),
+  ('input_files', Digest),
+  ('description', string_type),
+])):
+  """Request to start a process in the background (such as a dev server), which runs while the
+  engine continues.
+
+  The process runs locally (even if remote execution is configured) in a sandbox containing its
+  input files. Its stdout and stderr are captured as they are written, and it has no timeout: it runs
+  until it exits, until it is stopped, or until the end of the run.
+  """
+
+  def __new__(cls, argv, env=None, input_files=EMPTY_DIRECTORY_DIGEST, description=''):
+    if env is None:
+      env = ()
+    else:
+      if not isinstance(env, dict):
+        raise cls.make_type_error(
+          "arg 'env' was invalid: value {} (with type {}) must be a dict".format(env, type(env)))
+      env = tuple(item for pair in env.items() for item in pair)
+    return super(BackgroundProcessRequest, cls).__new__(
+      cls, argv, env, input_files, description or ' '.join(argv))
+
+
+class ServiceRequest(datatype([
+  ('name', string_type),
+  ('process', BackgroundProcessRequest),
+  ('ready_stdout', string_optional),
+  ('startup_timeout_seconds', Exactly(float, int)),
+])):
+  """Request for a named, long-running process (such as a database or an emulator), which is
+  shared by every requester within a run rather than started by each of them.
+
+  The service is started in the background when it is first requested, and is healthy once it has
+  written `ready_stdout` to its stdout (or immediately, if `ready_stdout` is None). It is restarted if
+  it has exited when it is next requested, and is stopped at the end of the run.
+  """
+
+  def __new__(cls, name, process, ready_stdout=None, startup_timeout_seconds=60):
+    return super(ServiceRequest, cls).__new__(
+      cls, name, process, ready_stdout, startup_timeout_seconds)
+
+
+class BackgroundProcessProgress(datatype([
+  ('stdout_digest', Digest),
+  ('stderr_digest', Digest),
+  ('stdout', binary_type),
+  ('exit_code', Exactly(int, type(None))),
+  ('elapsed_seconds', float),
+])):
+  """The outputs of a background process so far, and its exit code if it has exited."""
+
+  @property
+  def running(self):
+    return self.exit_code is None
+
+
+class BackgroundProcess(object):
+  """A handle to a process which was started in the background."""
+
+  def __init__(self, scheduler_session, process_id):
+    self._scheduler_session = scheduler_session
+    self._process_id = process_id
+    self._stdout_seen = 0
+
+  def wait_for_progress(self, heartbeat_seconds=1.0):
+    """Blocks until the process writes more stdout, until it exits, or (as a heartbeat) until
+    `heartbeat_seconds` have elapsed, and returns a BackgroundProcessProgress."""
+    progress = self._scheduler_session.background_process_progress(
+      self._process_id, self._stdout_seen, heartbeat_seconds)
+    self._stdout_seen = len(progress.stdout)
+    return progress
+
+  def wait_until_ready(self, is_ready, timeout_seconds, heartbeat_seconds=1.0):
+    """Waits until `is_ready` returns True for the progress of the process.
+
+    :param is_ready: A function from a BackgroundProcessProgress to a bool, which is called when
+      the process writes to stdout, and at least every `heartbeat_seconds`.
+    :returns: The BackgroundProcessProgress which was ready.
+    :raises: ValueError if the process exits or `timeout_seconds` elapse before it is ready.
+    """
+    deadline = time.time() + timeout_seconds
+    while True:
+      remaining = deadline - time.time()
+      progress = self.wait_for_progress(max(0.0, min(heartbeat_seconds, remaining)))
+      if is_ready(progress):
+        return progress
+      if not progress.running:
+        raise ValueError('Background process exited with code {} before it was ready.'
+                         .format(progress.exit_code))
+      if time.time() >= deadline:
+        raise ValueError('Background process was not ready after {} seconds.'
+                         .format(timeout_seconds))
+
+  def stop(self):
+    """Kills the process (if it has not already exited), and returns its exit code."""
+    return self._scheduler_session.stop_background_process(self._process_id)
+
+
+class InteractiveRunner(object):
+  """An engine-managed handle to the console, which a @console_rule may request in order to run
+  interactive processes (such as a REPL, or a test under a debugger).
+
+  While an interactive process runs, the v2 UI is paused.
+  """
+
+  def __init__(self, scheduler_session):
+    self._scheduler_session = scheduler_session
+
+  def run_local_interactive_process(self, request):
+    """Runs the given InteractiveProcessRequest, and returns an InteractiveProcessResult."""
+    return self._scheduler_session.run_local_interactive_process(request)
+
+  def start_background_process(self, request):
+    """Starts the given BackgroundProcessRequest, and returns a BackgroundProcess.
+
+    The process is killed at the end of the run if it has not already exited.
+    """
+    return BackgroundProcess(
+      self._scheduler_session, self._scheduler_session.start_background_process(request))
+
+  def ensure_service(self, request):
+    """Returns a BackgroundProcess for the given ServiceRequest, which is started (and waited for
+    until it is healthy) unless it is already running in this run.
+
+    Since a service is shared, callers should not stop it: it is stopped at the end of the run.
+    """
+    return BackgroundProcess(
+      self._scheduler_session, self._scheduler_session.ensure_service(request))
@@ -6,7 +6,7 @@ from __future__ import absolute_import, division, print_function, unicode_litera
 
 import logging
 
-from future.utils import binary_type
+from future.utils import binary_type, text_type
 
 from pants.engine.fs import Digest
 from pants.engine.rules import RootRule, rule
@@ -28,6 +28,31 @@ class ExecuteProcessRequest(datatype([
   # NB: timeout_seconds covers the whole remote operation including queuing and setup.
   ('timeout_seconds', Exactly(float, int)),
   ('jdk_home', string_optional),
+  # NB: A JDK version spec (for example `11` or `zulu-17`), which the engine resolves to one of the
+  # JDKs in --process-execution-jdk-search-paths. Mutually exclusive with jdk_home.
+  ('jdk', string_optional),
+  # NB: If max_output_bytes is None, any --process-execution-max-output-bytes default applies.
+  ('max_output_bytes', Exactly(int, type(None))),
+  ('output_overflow_policy', string_optional),
+  # NB: Scheduling hints for remote execution, which must be allowed by
+  # --remote-execution-allowed-platform-property-keys. Ignored by local execution.
+  ('platform_properties', hashable_string_list),
+  # NB: The Digest of a file (rather than of a directory) whose content is written to the stdin of
+  # the process. Not supported by remote execution.
+  ('stdin_digest', Exactly(Digest, type(None))),
+  # NB: The names of env vars whose values are secret: they are redacted from logs, and the process
+  # is never cached remotely.
+  ('secret_env', hashable_string_list),
+  # NB: If True, any output_directories which the process does not create are included in the
+  # output digest as empty directories (rather than being omitted).
+  ('preserve_empty_output_directories', bool),
+  # NB: If True, the process has effects beyond its outputs (publishing or deploying, for example):
+  # it is never served from a cache or run more than once per run, and it is given a unique
+  # PANTS_IDEMPOTENCY_TOKEN env var to pass to the services it affects.
+  ('side_effecting', bool),
+  # NB: An estimate of the memory (in MB) which the process uses, which counts against any
+  # --process-execution-memory-budget-mb while it runs.
+  ('memory_estimate_mb', Exactly(int, type(None))),
 ])):
   """Request for execution with args and snapshots to extract."""
 
@@ -43,6 +68,15 @@ class ExecuteProcessRequest(datatype([
     output_directories=(),
     timeout_seconds=_default_timeout_seconds,
     jdk_home=None,
+    jdk=None,
+    max_output_bytes=None,
+    output_overflow_policy=None,
+    platform_properties=None,
+    stdin_digest=None,
+    secret_env=(),
+    preserve_empty_output_directories=False,
+    side_effecting=False,
+    memory_estimate_mb=None,
   ):
     if env is None:
       env = ()
@@ -52,6 +86,25 @@ class ExecuteProcessRequest(datatype([
           "arg 'env' was invalid: value {} (with type {}) must be a dict".format(env, type(env)))
       env = tuple(item for pair in env.items() for item in pair)
 
+    if platform_properties is None:
+      platform_properties = ()
+    else:
+      if not isinstance(platform_properties, dict):
+        raise cls.make_type_error(
+          "arg 'platform_properties' was invalid: value {} (with type {}) must be a dict"
+          .format(platform_properties, type(platform_properties)))
+      platform_properties = tuple(
+        item for pair in sorted(platform_properties.items()) for item in pair)
+
+    if jdk_home is not None and jdk is not None:
+      raise cls.make_type_error("args 'jdk_home' and 'jdk' are mutually exclusive")
+
+    secret_env = tuple(sorted(secret_env))
+    undefined_secret_env = [name for name in secret_env if name not in env[::2]]
+    if undefined_secret_env:
+      raise cls.make_type_error(
+        "arg 'secret_env' was invalid: {} must be set in env".format(undefined_secret_env))
+
     return super(ExecuteProcessRequest, cls).__new__(
       cls,
       argv=argv,
@@ -62,9 +115,50 @@ class ExecuteProcessRequest(datatype([
       output_directories=output_directories,
       timeout_seconds=timeout_seconds,
       jdk_home=jdk_home,
+      jdk=jdk,
+      max_output_bytes=max_output_bytes,
+      output_overflow_policy=output_overflow_policy,
+      platform_properties=platform_properties,
+      stdin_digest=stdin_digest,
+      secret_env=secret_env,
+      preserve_empty_output_directories=preserve_empty_output_directories,
+      side_effecting=side_effecting,
+      memory_estimate_mb=memory_estimate_mb,
     )
 
 
+class ProcessShard(datatype([('args', hashable_string_list)])):
+  """The args which are appended to the argv of the template of a ShardedExecuteProcessRequest to
+  execute one of its shards."""
+
+  def __new__(cls, args):
+    return super(ProcessShard, cls).__new__(cls, tuple(args))
+
+
+class ShardedExecuteProcessRequest(datatype([
+  ('template', ExecuteProcessRequest),
+  ('shards', tuple),
+])):
+  """Request for the execution of one process per shard (a test shard, for example), each of which
+  is the template with the args of the shard appended to its argv.
+
+  The shards share one upload of the input files of the template, and each is executed (and
+  memoized and cached) independently, so that only the shards which failed or were affected by a
+  change need to be re-executed."""
+
+  def __new__(cls, template, shards):
+    shards = tuple(shard if isinstance(shard, ProcessShard) else ProcessShard(shard)
+                   for shard in shards)
+    if not shards:
+      raise cls.make_type_error("arg 'shards' was invalid: at least one shard is required")
+    return super(ShardedExecuteProcessRequest, cls).__new__(cls, template, shards)
+
+
+class ShardedFallibleExecuteProcessResults(datatype([('results', tuple)])):
+  """The FallibleExecuteProcessResult of each shard of a ShardedExecuteProcessRequest, in the
+  order of its shards."""
+
+
 class ExecuteProcessResult(datatype([('stdout', binary_type),
                                      ('stderr', binary_type),
                                      ('output_directory_digest', Digest)
@@ -77,12 +171,33 @@ class ExecuteProcessResult(datatype([('stdout', binary_type),
 class FallibleExecuteProcessResult(datatype([('stdout', binary_type),
                                              ('stderr', binary_type),
                                              ('exit_code', int),
-                                             ('output_directory_digest', Digest)
+                                             ('output_directory_digest', Digest),
+                                             ('runner', text_type),
+                                             ('attempt_count', int),
+                                             ('timeout_seconds', Exactly(float, type(None))),
+                                             ('elapsed_seconds', Exactly(float, type(None))),
                                              ])):
   """Result of executing a process.
 
+  `runner` describes the runner which produced the result (either "local", or "remote(<address>)"),
+  and `attempt_count` is the number of attempts which that runner made to execute the process.
+
+  If the process exceeded its timeout, `timeout_seconds` and `elapsed_seconds` are the timeout and
+  the time which had elapsed when it was killed (or abandoned), and `stdout` and `stderr` are the
+  output which was captured before then.
+
   Requesting one of these will not raise an exception if the exit code is non-zero."""
 
+  def __new__(cls, stdout, stderr, exit_code, output_directory_digest, runner, attempt_count,
+              timeout_seconds=None, elapsed_seconds=None):
+    return super(FallibleExecuteProcessResult, cls).__new__(
+      cls, stdout, stderr, exit_code, output_directory_digest, runner, attempt_count,
+      timeout_seconds, elapsed_seconds)
+
+  @property
+  def timed_out(self):
+    return self.timeout_seconds is not None
+
 
 class ProcessExecutionFailure(Exception):
   """Used to denote that a process exited, but was unsuccessful in some way.
@@ -90,30 +205,80 @@ class ProcessExecutionFailure(Exception):
   For example, exiting with a non-zero code.
   """
 
-  MSG_FMT = """process '{desc}' failed with exit code {code}.
+  MSG_FMT = """process '{desc}' failed with exit code {code}{runner_info}.
 stdout:
 {stdout}
 stderr:
 {stderr}
 """
 
-  def __init__(self, exit_code, stdout, stderr, process_description):
+  def __init__(self, exit_code, stdout, stderr, process_description, runner=None,
+               attempt_count=None):
     # These are intentionally "public" members.
     self.exit_code = exit_code
     self.stdout = stdout
     self.stderr = stderr
+    self.runner = runner
+    self.attempt_count = attempt_count
 
+    runner_info = ''
+    if runner is not None:
+      runner_info = ' (runner: {}, attempts: {})'.format(runner, attempt_count)
     msg = self.MSG_FMT.format(
-      desc=process_description, code=exit_code, stdout=stdout, stderr=stderr)
+      desc=process_description, code=exit_code, runner_info=runner_info, stdout=stdout,
+      stderr=stderr)
 
     super(ProcessExecutionFailure, self).__init__(msg)
 
 
+class ProcessTimedOut(ProcessExecutionFailure):
+  """Used to denote that a process was killed (or abandoned) because it exceeded its timeout."""
+
+  MSG_FMT = """process '{desc}' timed out after {elapsed:.1f}s (its timeout was {timeout:.1f}s){runner_info}.
+If it needs more time, increase the timeout_seconds of its ExecuteProcessRequest.
+stdout (before the timeout):
+{stdout}
+stderr (before the timeout):
+{stderr}
+"""
+
+  def __init__(self, timeout_seconds, elapsed_seconds, stdout, stderr, process_description,
+               runner=None, attempt_count=None):
+    # These are intentionally "public" members.
+    self.timeout_seconds = timeout_seconds
+    self.elapsed_seconds = elapsed_seconds
+    self.exit_code = None
+    self.stdout = stdout
+    self.stderr = stderr
+    self.runner = runner
+    self.attempt_count = attempt_count
+
+    runner_info = ''
+    if runner is not None:
+      runner_info = ' (runner: {}, attempts: {})'.format(runner, attempt_count)
+    msg = self.MSG_FMT.format(
+      desc=process_description, elapsed=elapsed_seconds, timeout=timeout_seconds,
+      runner_info=runner_info, stdout=stdout, stderr=stderr)
+
+    # NB: Skips ProcessExecutionFailure.__init__, which would render a different message.
+    Exception.__init__(self, msg)
+
+
 @rule(ExecuteProcessResult, [FallibleExecuteProcessResult, ExecuteProcessRequest])
 def fallible_to_exec_result_or_raise(fallible_result, request):
   """Converts a FallibleExecuteProcessResult to a ExecuteProcessResult or raises an error."""
 
-  if fallible_result.exit_code == 0:
+  if fallible_result.timed_out:
+    raise ProcessTimedOut(
+      fallible_result.timeout_seconds,
+      fallible_result.elapsed_seconds,
+      fallible_result.stdout,
+      fallible_result.stderr,
+      request.description,
+      runner=fallible_result.runner,
+      attempt_count=fallible_result.attempt_count,
+    )
+  elif fallible_result.exit_code == 0:
     return ExecuteProcessResult(
       fallible_result.stdout,
       fallible_result.stderr,
@@ -124,7 +289,9 @@ def fallible_to_exec_result_or_raise(fallible_result, request):
       fallible_result.exit_code,
       fallible_result.stdout,
       fallible_result.stderr,
-      request.description
+      request.description,
+      runner=fallible_result.runner,
+      attempt_count=fallible_result.attempt_count,
     )
 
 
@@ -132,5 +299,6 @@ def create_process_rules():
   """Creates rules that consume the intrinsic filesystem types."""
   return [
     RootRule(ExecuteProcessRequest),
+    RootRule(ShardedExecuteProcessRequest),
     fallible_to_exec_result_or_raise
   ]
@@ -30,7 +30,7 @@ from pants.engine.mapper import AddressMapper
 from pants.engine.objects import Collection
 from pants.engine.parser import HydratedStruct
 from pants.engine.rules import RootRule, rule
-from pants.engine.selectors import Get
+from pants.engine.selectors import Get, GetAll
 from pants.option.global_options import GlobMatchErrorBehavior
 from pants.source.filespec import any_matches_filespec
 from pants.source.wrapped_globs import EagerFilesetWithSpec, FilesetRelPathWrapper
@@ -463,14 +463,14 @@ def transitive_hydrated_targets(build_file_addresses):
 
 @rule(TransitiveHydratedTarget, [HydratedTarget])
 def transitive_hydrated_target(root):
-  dependencies = yield [Get(TransitiveHydratedTarget, Address, d) for d in root.dependencies]
+  dependencies = yield GetAll(TransitiveHydratedTarget, Address, root.dependencies)
   yield TransitiveHydratedTarget(root, dependencies)
 
 
 @rule(HydratedTargets, [BuildFileAddresses])
 def hydrated_targets(build_file_addresses):
   """Requests HydratedTarget instances for BuildFileAddresses."""
-  targets = yield [Get(HydratedTarget, Address, a) for a in build_file_addresses.addresses]
+  targets = yield GetAll(HydratedTarget, Address, build_file_addresses.addresses)
   yield HydratedTargets(targets)
 
 
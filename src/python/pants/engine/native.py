@@ -10,6 +10,7 @@ import os
 import re
 import sys
 import sysconfig
+import threading
 import traceback
 from builtins import bytes, object, open, str
 from contextlib import closing
@@ -19,7 +20,7 @@ import pkg_resources
 from future.utils import PY2, binary_type, text_type
 from twitter.common.collections.orderedset import OrderedSet
 
-from pants.engine.selectors import Get
+from pants.engine.selectors import Get, GetAll
 from pants.util.contextutil import temporary_dir
 from pants.util.dirutil import read_file, safe_mkdir, safe_mkdtemp
 from pants.util.memo import memoized_classproperty, memoized_property
@@ -369,6 +370,29 @@ class _FFISpecification(object):
     c = self._ffi.from_handle(context_handle)
     return c.to_value(binary_type(self._ffi.buffer(bytes_ptr, bytes_len)))
 
+  @_extern_decl('Handle', ['ExternContext*', 'uint8_t*', 'uint64_t', 'BufferOwner*'])
+  def extern_store_buffer(self, context_handle, bytes_ptr, bytes_len, buffer_owner_ptr):
+    """Given a context and a buffer owned by rust, return a Handle to a read-only memoryview of it.
+
+    Where the interpreter supports read-only views of foreign memory, the bytes are not copied:
+    rust keeps them alive until the returned memoryview (and any views derived from it) have been
+    garbage collected. Otherwise, they are copied, and the rust buffer is released immediately.
+    The memory is owned by the Store, so python must never be able to write to it.
+    """
+    c = self._ffi.from_handle(context_handle)
+    lib = self._lib
+    if PY2:
+      # A py2 `buffer` is a read-only wrapper of the writable cffi buffer.
+      owned_ptr = self._ffi.gc(bytes_ptr, lambda _: lib.buffer_owner_destroy(buffer_owner_ptr))
+      view = memoryview(buffer(self._ffi.buffer(owned_ptr, bytes_len)))  # noqa: F821
+    elif hasattr(memoryview, 'toreadonly'):
+      owned_ptr = self._ffi.gc(bytes_ptr, lambda _: lib.buffer_owner_destroy(buffer_owner_ptr))
+      view = memoryview(self._ffi.buffer(owned_ptr, bytes_len)).toreadonly()
+    else:
+      view = memoryview(binary_type(self._ffi.buffer(bytes_ptr, bytes_len)))
+      lib.buffer_owner_destroy(buffer_owner_ptr)
+    return c.to_value(view)
+
   @_extern_decl('Handle', ['ExternContext*', 'uint8_t*', 'uint64_t'])
   def extern_store_utf8(self, context_handle, utf8_ptr, utf8_len):
     """Given a context and UTF8 bytes, return a new Handle to represent the content."""
@@ -419,13 +443,31 @@ class _FFISpecification(object):
     msg = self.to_py_str(msg_ptr, msg_len)
     return c.to_value(Exception(msg))
 
-  @_extern_decl('PyGeneratorResponse', ['ExternContext*', 'Handle*', 'Handle*'])
-  def extern_generator_send(self, context_handle, func, arg):
-    """Given a generator, send it the given value and return a response."""
+  @_extern_decl('void',
+                ['ExternContext*', 'uint64_t', 'uint8_t*', 'uint64_t', 'uint8_t*', 'uint64_t'])
+  def extern_log(self, context_handle, level, target_ptr, target_len, msg_ptr, msg_len):
+    """Given a level, target and utf8 message string, log a record emitted by the engine.
+
+    The record is logged to the python logger named by its target, so that it is filtered and
+    formatted alongside python's own records.
+    """
+    target = self.to_py_str(target_ptr, target_len)
+    msg = self.to_py_str(msg_ptr, msg_len)
+    logging.getLogger(target).log(level, msg)
+
+  @_extern_decl('PyGeneratorResponse', ['ExternContext*', 'Handle*', 'Handle*', '_Bool'])
+  def extern_generator_send(self, context_handle, func, arg, is_throw):
+    """Given a generator, send it the given value (or throw it the given exception), and return a
+    response.
+    """
     c = self._ffi.from_handle(context_handle)
     response = self._ffi.new('PyGeneratorResponse*')
     try:
-      res = c.from_value(func[0]).send(c.from_value(arg[0]))
+      generator = c.from_value(func[0])
+      if is_throw:
+        res = generator.throw(c.from_value(arg[0]))
+      else:
+        res = generator.send(c.from_value(arg[0]))
       if isinstance(res, Get):
         # Get.
         response.tag = self._lib.Get
@@ -434,6 +476,14 @@ class _FFISpecification(object):
             c.to_value(res.subject),
             c.identify(res.subject),
           )
+      elif isinstance(res, GetAll):
+        # GetAll.
+        response.tag = self._lib.GetAll
+        response.get_all = (
+            TypeId(c.to_id(res.product)),
+            c.vals_buf([c.to_value(s) for s in res.subjects]),
+            c.identities_buf([c.identify(s) for s in res.subjects]),
+          )
       elif type(res) in (tuple, list):
         # GetMulti.
         response.tag = self._lib.GetMulti
@@ -574,6 +624,9 @@ class Native(Singleton):
   """Encapsulates fetching a platform specific version of the native portion of the engine."""
 
   _errors_during_execution = None
+  # Externs run on engine threads on behalf of any number of concurrent python callers, so the
+  # stored errors are guarded by a lock.
+  _errors_lock = threading.Lock()
 
   class CFFIExternMethodRuntimeErrorInfo(datatype([
       ('exc_type', type),
@@ -594,14 +647,17 @@ class Native(Singleton):
     """
 
   def reset_cffi_extern_method_runtime_exceptions(self):
-    self._errors_during_execution = []
+    with self._errors_lock:
+      self._errors_during_execution = []
 
   def cffi_extern_method_runtime_exceptions(self):
-    return self._errors_during_execution
+    with self._errors_lock:
+      return list(self._errors_during_execution)
 
   def add_cffi_extern_method_runtime_exception(self, error_info):
     assert isinstance(error_info, self.CFFIExternMethodRuntimeErrorInfo)
-    self._errors_during_execution.append(error_info)
+    with self._errors_lock:
+      self._errors_during_execution.append(error_info)
 
   class BinaryLocationError(Exception): pass
 
@@ -673,13 +729,15 @@ class Native(Singleton):
                            self.ffi_lib.extern_store_set,
                            self.ffi_lib.extern_store_dict,
                            self.ffi_lib.extern_store_bytes,
+                           self.ffi_lib.extern_store_buffer,
                            self.ffi_lib.extern_store_utf8,
                            self.ffi_lib.extern_store_i64,
                            self.ffi_lib.extern_store_f64,
                            self.ffi_lib.extern_store_bool,
                            self.ffi_lib.extern_project_ignoring_type,
                            self.ffi_lib.extern_project_multi,
-                           self.ffi_lib.extern_create_exception)
+                           self.ffi_lib.extern_create_exception,
+                           self.ffi_lib.extern_log)
       return context
 
     return self.ffi.init_once(init_externs, 'ExternContext singleton')
@@ -747,8 +805,20 @@ class Native(Singleton):
       self.lib.execution_request_create(),
       self.lib.execution_request_destroy)
 
-  def new_session(self, scheduler, should_render_ui, ui_worker_count):
-    return self.gc(self.lib.session_create(scheduler, should_render_ui, ui_worker_count), self.lib.session_destroy)
+  def new_session(self, scheduler, should_render_ui, ui_worker_count, ui_level='info',
+                  timeout_secs=None, execution_strategy='default',
+                  execution_policy='keep_going'):
+    return self.gc(
+      self.lib.session_create(
+        scheduler,
+        should_render_ui,
+        ui_worker_count,
+        self.context.utf8_buf(ui_level),
+        timeout_secs or 0,
+        self.context.utf8_buf(execution_strategy),
+        self.context.utf8_buf(execution_policy),
+      ),
+      self.lib.session_destroy)
 
   def new_scheduler(self,
                     tasks,
@@ -762,19 +832,29 @@ class Native(Singleton):
                     construct_snapshot,
                     construct_file_content,
                     construct_files_content,
+                    construct_file_content_view,
+                    construct_files_content_view,
                     construct_process_result,
+                    construct_sharded_process_results,
                     type_address,
                     type_path_globs,
                     type_directory_digest,
                     type_snapshot,
                     type_merge_snapshots_request,
                     type_directory_with_prefix_to_strip,
+                    type_directory_with_prefix_to_add,
+                    type_snapshot_subset,
+                    type_archive_to_extract,
+                    type_directory_to_archive,
                     type_files_content,
+                    type_files_content_view,
                     type_dir,
                     type_file,
                     type_link,
                     type_process_request,
                     type_process_result,
+                    type_sharded_process_request,
+                    type_sharded_process_results,
                     type_generator,
                     type_url_to_fetch):
     """Create and return an ExternContext and native Scheduler."""
@@ -791,7 +871,10 @@ class Native(Singleton):
         func(construct_snapshot),
         func(construct_file_content),
         func(construct_files_content),
+        func(construct_file_content_view),
+        func(construct_files_content_view),
         func(construct_process_result),
+        func(construct_sharded_process_results),
         # Types.
         ti(type_address),
         ti(type_path_globs),
@@ -799,12 +882,19 @@ class Native(Singleton):
         ti(type_snapshot),
         ti(type_merge_snapshots_request),
         ti(type_directory_with_prefix_to_strip),
+        ti(type_directory_with_prefix_to_add),
+        ti(type_snapshot_subset),
+        ti(type_archive_to_extract),
+        ti(type_directory_to_archive),
         ti(type_files_content),
+        ti(type_files_content_view),
         ti(type_dir),
         ti(type_file),
         ti(type_link),
         ti(type_process_request),
         ti(type_process_result),
+        ti(type_sharded_process_request),
+        ti(type_sharded_process_results),
         ti(type_generator),
         ti(type_url_to_fetch),
         ti(text_type),
@@ -813,10 +903,17 @@ class Native(Singleton):
         self.context.utf8_buf(build_root),
         self.context.utf8_buf(work_dir),
         self.context.utf8_buf(local_store_dir),
+        # We can't currently pass Options to the rust side, so we pass empty strings for None.
+        self.context.utf8_buf(execution_options.local_store_metadata_dir or ""),
+        execution_options.offline,
+        self.context.utf8_buf(execution_options.http_proxy or ""),
+        self.context.utf8_buf(execution_options.https_proxy or ""),
+        self.context.utf8_buf_buf(execution_options.no_proxy),
         self.context.utf8_buf_buf(ignore_patterns),
         self.to_ids_buf(root_subject_types),
         # Remote execution config.
         self.context.utf8_buf_buf(execution_options.remote_store_server),
+        self.context.utf8_buf_buf(execution_options.remote_store_central_server),
         # We can't currently pass Options to the rust side, so we pass empty strings for None.
         self.context.utf8_buf(execution_options.remote_execution_server or ""),
         self.context.utf8_buf(execution_options.remote_execution_process_cache_namespace or ""),
@@ -825,16 +922,55 @@ class Native(Singleton):
         self.context.utf8_buf(execution_options.remote_oauth_bearer_token_path or ""),
         execution_options.remote_store_thread_count,
         execution_options.remote_store_chunk_bytes,
+        execution_options.remote_store_upload_window_chunks,
         execution_options.remote_store_chunk_upload_timeout_seconds,
+        execution_options.remote_store_find_missing_blobs_timeout_seconds,
+        execution_options.remote_store_read_timeout_seconds,
         execution_options.remote_store_rpc_retries,
+        execution_options.remote_store_read_only,
+        # We pass zero for unlimited transfers.
+        execution_options.remote_store_max_concurrent_transfers or 0,
+        execution_options.remote_store_max_bytes_per_second or 0,
         self.context.utf8_buf_buf(execution_options.remote_execution_extra_platform_properties),
+        self.context.utf8_buf_buf(
+          execution_options.remote_execution_allowed_platform_property_keys),
+        execution_options.remote_execution_skip_cache_lookup,
+        execution_options.remote_execution_action_cache_writes,
+        execution_options.remote_execution_verify_cache_hits,
+        execution_options.remote_execution_stream_timeout_seconds,
+        execution_options.remote_execution_action_cache_timeout_seconds,
+        self.context.utf8_buf(execution_options.remote_execution_verify_report or ""),
         execution_options.process_execution_parallelism,
+        # We pass zero for an unlimited memory budget.
+        execution_options.process_execution_memory_budget_mb or 0,
         execution_options.process_execution_cleanup_local_dirs,
+        # We pass zero for an unlimited output size.
+        execution_options.process_execution_max_output_bytes or 0,
+        self.context.utf8_buf(execution_options.process_execution_output_overflow_policy),
+        self.context.utf8_buf(execution_options.process_execution_provenance_file or ""),
+        self.context.utf8_buf(execution_options.process_execution_artifact_index or ""),
+        self.context.utf8_buf(execution_options.process_execution_unused_inputs_report or ""),
+        # We pass zero for unlimited process executions.
+        execution_options.process_execution_max_per_session or 0,
+        execution_options.process_execution_max_per_minute or 0,
+        self.context.utf8_buf(execution_options.process_execution_audit_log or ""),
+        self.context.utf8_buf_buf(execution_options.process_execution_audit_redact_env),
+        execution_options.process_execution_lint_hermeticity,
+        self.context.utf8_buf(execution_options.process_execution_determinism_check_report or ""),
+        self.context.utf8_buf_buf(
+          execution_options.process_execution_determinism_check_descriptions),
+        execution_options.process_execution_determinism_check_vary_sandbox,
+        self.context.utf8_buf_buf(execution_options.process_execution_jdk_search_paths),
+        # We pass zero for unlimited snapshot sizes.
+        execution_options.snapshot_max_file_bytes or 0,
+        execution_options.snapshot_max_total_bytes or 0,
+        self.context.utf8_buf(execution_options.snapshot_unicode_normalization),
+        execution_options.engine_io_threads,
+        execution_options.engine_cpu_threads,
       )
     return self.gc(scheduler, self.lib.scheduler_destroy)
 
-  def set_panic_handler(self):
-    if os.getenv("RUST_BACKTRACE", "0") == "0":
-      # The panic handler hides a lot of rust tracing which may be useful.
-      # Don't activate it when the user explicitly asks for rust backtraces.
-      self.lib.set_panic_handler()
+  def set_panic_handler(self, crash_report_dir=None):
+    # The panic handler captures the rust backtrace of each panic, and attaches it to the exception
+    # which the panic is converted into.
+    self.lib.set_panic_handler((crash_report_dir or "").encode("utf-8"))
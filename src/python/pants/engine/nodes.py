@@ -49,8 +49,29 @@ class Return(datatype(['value']), State):
     return (self.value,)
 
 
-class Throw(datatype(['exc']), State):
-  """Indicates that a Node should have been able to return a value, but failed."""
+class Throw(datatype(['exc', 'python_traceback', 'engine_traceback']), State):
+  """Indicates that a Node should have been able to return a value, but failed.
+
+  :param exc: The exception which caused the failure.
+  :param python_traceback: The formatted python traceback for the exception, if known.
+  :param engine_traceback: Descriptions of the rule invocations which led to the failure, outermost
+    first.
+  """
+
+  def __new__(cls, exc, python_traceback=None, engine_traceback=()):
+    return super(Throw, cls).__new__(cls, exc, python_traceback, tuple(engine_traceback))
+
+  def format_traceback(self):
+    """Render the engine traceback followed by the python traceback of this failure."""
+    lines = []
+    if self.engine_traceback:
+      lines.append('Engine traceback:')
+      lines.extend('  in {}'.format(frame) for frame in self.engine_traceback)
+    if self.python_traceback:
+      lines.append(self.python_traceback.rstrip())
+    else:
+      lines.append('{}: {}'.format(type(self.exc).__name__, self.exc))
+    return '\n'.join(lines)
 
 
 class Runnable(datatype(['func', 'args', 'cacheable']), State):
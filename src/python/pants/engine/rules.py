@@ -15,7 +15,7 @@ import asttokens
 from twitter.common.collections import OrderedSet
 
 from pants.engine.goal import Goal
-from pants.engine.selectors import Get
+from pants.engine.selectors import Get, GetAll
 from pants.util.collections import assert_single_element
 from pants.util.collections_abc_backport import Iterable, OrderedDict
 from pants.util.memo import memoized
@@ -30,7 +30,7 @@ _type_field = SubclassesOf(type)
 
 
 class _RuleVisitor(ast.NodeVisitor):
-  """Pull `Get` calls out of an @rule body and validate `yield` statements."""
+  """Pull `Get` and `GetAll` calls out of an @rule body and validate `yield` statements."""
 
   def __init__(self, func, func_node, func_source, orig_indent, parents_table):
     super(_RuleVisitor, self).__init__()
@@ -147,6 +147,8 @@ The rule defined by function `{func_name}` begins at:
   def visit_Call(self, node):
     if isinstance(node.func, ast.Name) and node.func.id == Get.__name__:
       self._gets.append(Get.extract_constraints(node))
+    elif isinstance(node.func, ast.Name) and node.func.id == GetAll.__name__:
+      self._gets.append(GetAll.extract_constraints(node))
 
   def visit_Assign(self, node):
     if isinstance(node.value, ast.Yield):
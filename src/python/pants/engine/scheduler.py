@@ -4,6 +4,7 @@
 
 from __future__ import absolute_import, division, print_function, unicode_literals
 
+import json
 import logging
 import multiprocessing
 import os
@@ -11,16 +12,22 @@ import sys
 import time
 import traceback
 from builtins import object, open, str, zip
+from collections import OrderedDict
 from textwrap import dedent
 from types import GeneratorType
 
 from pants.base.exiter import PANTS_FAILED_EXIT_CODE
 from pants.base.project_tree import Dir, File, Link
 from pants.build_graph.address import Address
-from pants.engine.fs import (Digest, DirectoriesToMerge, DirectoryToMaterialize,
-                             DirectoryWithPrefixToStrip, FileContent, FilesContent, PathGlobs,
-                             PathGlobsAndRoot, Snapshot, UrlToFetch)
-from pants.engine.isolated_process import ExecuteProcessRequest, FallibleExecuteProcessResult
+from pants.engine.fs import (ArchiveToExtract, Digest, DirectoriesToMerge, DirectoryToArchive,
+                             DirectoryToMaterialize, DirectoryWithPrefixToAdd,
+                             DirectoryWithPrefixToStrip, FileContent, FileContentView, FilesContent,
+                             FilesContentView, PathGlobs, PathGlobsAndRoot, Snapshot,
+                             SnapshotSubset, UrlToFetch, WorkspaceManifest)
+from pants.engine.interactive_runner import BackgroundProcessProgress, InteractiveProcessResult
+from pants.engine.isolated_process import (ExecuteProcessRequest, FallibleExecuteProcessResult,
+                                           ShardedExecuteProcessRequest,
+                                           ShardedFallibleExecuteProcessResults)
 from pants.engine.native import Function, TypeId
 from pants.engine.nodes import Return, Throw
 from pants.engine.objects import Collection
@@ -107,19 +114,29 @@ class Scheduler(object):
       construct_snapshot=Snapshot,
       construct_file_content=FileContent,
       construct_files_content=FilesContent,
+      construct_file_content_view=FileContentView,
+      construct_files_content_view=FilesContentView,
       construct_process_result=FallibleExecuteProcessResult,
+      construct_sharded_process_results=ShardedFallibleExecuteProcessResults,
       type_address=Address,
       type_path_globs=PathGlobs,
       type_directory_digest=Digest,
       type_snapshot=Snapshot,
       type_merge_snapshots_request=DirectoriesToMerge,
       type_directory_with_prefix_to_strip=DirectoryWithPrefixToStrip,
+      type_directory_with_prefix_to_add=DirectoryWithPrefixToAdd,
+      type_snapshot_subset=SnapshotSubset,
+      type_archive_to_extract=ArchiveToExtract,
+      type_directory_to_archive=DirectoryToArchive,
       type_files_content=FilesContent,
+      type_files_content_view=FilesContentView,
       type_dir=Dir,
       type_file=File,
       type_link=Link,
       type_process_request=ExecuteProcessRequest,
       type_process_result=FallibleExecuteProcessResult,
+      type_sharded_process_request=ShardedExecuteProcessRequest,
+      type_sharded_process_results=ShardedFallibleExecuteProcessResults,
       type_generator=GeneratorType,
       type_url_to_fetch=UrlToFetch,
     )
@@ -212,8 +229,14 @@ class Scheduler(object):
 
     self._native.lib.tasks_task_end(self._tasks)
 
-  def visualize_graph_to_file(self, session, filename):
-    res = self._native.lib.graph_visualize(self._scheduler, session, filename.encode('utf-8'))
+  def visualize_graph_to_file(self, session, filename, node_types=(), max_depth=None):
+    res = self._native.lib.graph_visualize(
+      self._scheduler,
+      session,
+      filename.encode('utf-8'),
+      self._native.context.utf8_buf_buf(tuple(node_types)),
+      -1 if max_depth is None else max_depth,
+    )
     self._raise_or_return(res)
 
   def visualize_rule_graph_to_file(self, filename):
@@ -254,15 +277,51 @@ class Scheduler(object):
   def invalidate_all_files(self):
     return self._native.lib.graph_invalidate_all_paths(self._scheduler)
 
+  def start_watching(self, backend='notify', watchman_socket_path=None, invalidation_globs=()):
+    """Watches the build root for changes, and invalidates the graph as they occur.
+
+    Once started, calls to `invalidate_files` are unnecessary for changes within the build root.
+
+    :param string backend: Either `notify`, to use the native filesystem watching APIs of the
+      platform, or `watchman`, to subscribe to changes from a running watchman daemon (which can
+      avoid inotify watch limits on large repositories).
+    :param string watchman_socket_path: The path to the socket of the watchman daemon, which is
+      required for the `watchman` backend.
+    :param list invalidation_globs: Paths that pants itself depends on, such as config files,
+      plugin requirements or the native engine binary. Relative entries are globs within the build
+      root, and absolute entries are files outside of it. Changes to them cause a full invalidation
+      of the graph, and are reported by `restart_required`.
+    """
+    res = self._native.lib.scheduler_start_watching(
+      self._scheduler,
+      self._native.context.utf8_buf(backend),
+      self._native.context.utf8_buf(watchman_socket_path or ''),
+      self._native.context.utf8_buf_buf(tuple(invalidation_globs)),
+    )
+    self._raise_or_return(res)
+
+  def restart_required(self):
+    """Returns True if any of the invalidation globs have changed since watching started."""
+    return self._native.lib.scheduler_restart_required(self._scheduler)
+
   def graph_len(self):
     return self._native.lib.graph_len(self._scheduler)
 
-  def add_root_selection(self, execution_request, subject_or_params, product):
+  def collect_interned_keys(self):
+    """Frees interned keys which are no longer referenced by the graph, and returns their count.
+
+    NB: Interned keys are shared by all Schedulers in the process, so this is only safe when this is
+    the only Scheduler, and only between runs (as pantsd does).
+    """
+    return self._native.lib.scheduler_collect_interns(self._scheduler)
+
+  def add_root_selection(self, session, execution_request, subject_or_params, product):
     if isinstance(subject_or_params, Params):
       params = subject_or_params.params
     else:
       params = [subject_or_params]
     res = self._native.lib.execution_add_root_select(self._scheduler,
+                                                     session,
                                                      execution_request,
                                                      self._to_vals_buf(params),
                                                      self._to_type(product))
@@ -274,6 +333,73 @@ class Scheduler(object):
   def _metrics(self, session):
     return self._from_value(self._native.lib.scheduler_metrics(self._scheduler, session))
 
+  def _observation_metrics(self, session):
+    return self._from_value(
+      self._native.lib.scheduler_observation_metrics(self._scheduler, session))
+
+  def _memory_summary(self):
+    res = self._native.lib.scheduler_memory_summary(self._scheduler)
+    return json.loads(self._raise_or_return(res))
+
+  def _write_to_console_tee(self, session, payload):
+    if not isinstance(payload, bytes):
+      payload = payload.encode('utf-8')
+    self._native.lib.session_write_to_console_tee(session, self._native.context.buf(payload))
+
+  def _tee_console(self, session, path):
+    res = self._native.lib.session_tee_console(session, path.encode('utf-8'))
+    self._raise_or_return(res)
+
+  def _write_run_report(self, session, path):
+    res = self._native.lib.session_write_run_report(self._scheduler, session, path.encode('utf-8'))
+    self._raise_or_return(res)
+
+  def _start_profiling(self, session, frequency):
+    res = self._native.lib.session_start_profiling(session, frequency)
+    self._raise_or_return(res)
+
+  def _write_profile(self, session, path):
+    res = self._native.lib.session_write_profile(session, path.encode('utf-8'))
+    self._raise_or_return(res)
+
+  def _run_local_interactive_process(self, session, request):
+    res = self._native.lib.session_run_interactive_process(
+      self._scheduler,
+      session,
+      self._to_value(request),
+    )
+    return self._raise_or_return(res)
+
+  def _start_background_process(self, session, request):
+    res = self._native.lib.session_start_background_process(
+      self._scheduler,
+      session,
+      self._to_value(request),
+    )
+    return self._raise_or_return(res)
+
+  def _ensure_service(self, session, request):
+    res = self._native.lib.session_ensure_service(
+      self._scheduler,
+      session,
+      self._to_value(request),
+    )
+    return self._raise_or_return(res)
+
+  def _background_process_progress(self, session, process_id, stdout_seen, timeout_seconds):
+    res = self._native.lib.session_background_process_progress(
+      self._scheduler,
+      session,
+      process_id,
+      stdout_seen,
+      int(timeout_seconds * 1000),
+    )
+    return self._raise_or_return(res)
+
+  def _stop_background_process(self, session, process_id):
+    res = self._native.lib.session_stop_background_process(session, process_id)
+    return self._raise_or_return(res)
+
   def with_fork_context(self, func):
     """See the rustdocs for `scheduler_fork_context` for more information."""
     res = self._native.lib.scheduler_fork_context(self._scheduler, Function(self._to_key(func)))
@@ -285,7 +411,8 @@ class Scheduler(object):
       roots = []
       for raw_root in self._native.unpack(raw_roots.nodes_ptr, raw_roots.nodes_len):
         if raw_root.is_throw:
-          state = Throw(self._from_value(raw_root.handle))
+          exc, python_traceback, engine_traceback = self._from_value(raw_root.handle)
+          state = Throw(exc, python_traceback, engine_traceback)
         else:
           state = Return(self._from_value(raw_root.handle))
         roots.append(state)
@@ -320,9 +447,32 @@ class Scheduler(object):
     )
     return self._raise_or_return(result)
 
-  def materialize_directories(self, directories_paths_and_digests):
+  def remote_execution_dry_run(self, execute_process_request):
+    """Constructs (without executing) the protos which would execute a process remotely.
+
+    :param execute_process_request: An ExecuteProcessRequest.
+    :returns: A dict containing the digests of the Action, Command and input root, and the Action
+              and Command both as hex-encoded serialized bytes and in protobuf text format.
+    """
+    result = self._native.lib.remote_execution_dry_run(
+      self._scheduler,
+      self._to_value(execute_process_request),
+    )
+    return json.loads(self._raise_or_return(result))
+
+  def select_jdk(self, spec):
+    """Selects one of the JDKs in --process-execution-jdk-search-paths by version spec.
+
+    :param spec: A JDK version spec, for example `11` or `zulu-17`.
+    :returns: A dict containing the home, version, vendor and fingerprint of the selected JDK.
+    """
+    result = self._native.lib.jdk_select(self._scheduler, self._to_utf8_buf(spec))
+    return json.loads(self._raise_or_return(result))
+
+  def materialize_directories(self, session, directories_paths_and_digests):
     """Creates the specified directories on the file system.
 
+    :param session: The native Session which the directories are materialized for.
     :param directories_paths_and_digests tuple<DirectoryToMaterialize>: Tuple of the path and
            digest of the directories to materialize.
     :returns: Nothing or an error.
@@ -333,19 +483,52 @@ class Scheduler(object):
 
     result = self._native.lib.materialize_directories(
       self._scheduler,
+      session,
       self._to_value(_DirectoriesToMaterialize(directories_paths_and_digests)),
     )
     return self._raise_or_return(result)
 
+  def write_to_workspace(self, session, workspace_write):
+    """Writes the contents of a directory digest into a (possibly non-empty) path in the workspace.
+
+    :param session: The native Session which the directory is written for.
+    :param workspace_write WorkspaceWrite: The path and digest to write, and how to handle files
+           which already exist.
+    :returns: A WorkspaceManifest of the paths which were written and backed up.
+    """
+    result = self._native.lib.write_to_workspace(
+      self._scheduler,
+      session,
+      self._to_value(workspace_write),
+    )
+    manifest = json.loads(self._raise_or_return(result))
+    return WorkspaceManifest(tuple(manifest['written']), tuple(manifest['backed_up']))
+
   def lease_files_in_graph(self):
     self._native.lib.lease_files_in_graph(self._scheduler)
 
   def garbage_collect_store(self):
     self._native.lib.garbage_collect_store(self._scheduler)
 
-  def new_session(self, v2_ui=False):
-    """Creates a new SchedulerSession for this Scheduler."""
-    return SchedulerSession(self, self._native.new_session(self._scheduler, v2_ui, multiprocessing.cpu_count()))
+  def new_session(self, v2_ui=False, timeout_secs=None, v2_ui_level='info',
+                  execution_strategy='default', execution_policy='keep_going'):
+    """Creates a new SchedulerSession for this Scheduler.
+
+    :param int timeout_secs: If set, the number of seconds after which executions in the session fail
+      rather than continuing to wait for their results.
+    :param string v2_ui_level: The most verbose level (trace, debug or info) of work which is
+      rendered by the UI and included in the run report.
+    :param string execution_strategy: Where processes are executed in the session, regardless of
+      the configured runners: one of default, local_only, remote_only or remote_cache_only.
+    :param string execution_policy: Whether the first root of an execution to fail cancels its
+      other roots (and the processes that they are running): one of fail_fast or keep_going.
+    """
+    return SchedulerSession(
+      self,
+      self._native.new_session(self._scheduler, v2_ui, multiprocessing.cpu_count(),
+                               ui_level=v2_ui_level, timeout_secs=timeout_secs,
+                               execution_strategy=execution_strategy,
+                               execution_policy=execution_policy))
 
 
 _PathGlobsAndRootCollection = Collection.of(PathGlobsAndRoot)
@@ -379,12 +562,18 @@ class SchedulerSession(object):
     for line in self._scheduler.graph_trace(execution_request.native):
       yield line
 
-  def visualize_graph_to_file(self, filename):
+  def visualize_graph_to_file(self, filename, node_types=(), max_depth=None):
     """Visualize a graph walk by writing graphviz `dot` output to a file.
 
+    If the filename has a `.json` extension, JSON is written instead, including the state (running,
+    completed, etc) of each Node: this is useful for capturing what a hung run is doing.
+
     :param str filename: The filename to output the graphviz output to.
+    :param node_types: If non-empty, the names of the types of Nodes to render (e.g.
+      `ExecuteProcess` or `Task`).
+    :param int max_depth: If set, only Nodes within this many edges of the roots are rendered.
     """
-    self._scheduler.visualize_graph_to_file(self._session, filename)
+    self._scheduler.visualize_graph_to_file(self._session, filename, node_types, max_depth)
 
   def visualize_rule_graph_to_file(self, filename):
     self._scheduler.visualize_rule_graph_to_file(filename)
@@ -392,7 +581,7 @@ class SchedulerSession(object):
   def execution_request_literal(self, request_specs):
     native_execution_request = self._scheduler._native.new_execution_request()
     for subject, product in request_specs:
-      self._scheduler.add_root_selection(native_execution_request, subject, product)
+      self._scheduler.add_root_selection(self._session, native_execution_request, subject, product)
     return ExecutionRequest(request_specs, native_execution_request)
 
   def execution_request(self, products, subjects):
@@ -426,6 +615,18 @@ class SchedulerSession(object):
     self._maybe_visualize()
     return invalidated
 
+  def start_watching(self, backend='notify', watchman_socket_path=None, invalidation_globs=()):
+    """Invalidates the internal product Graph as files in the build root change.
+
+    See `Scheduler.start_watching` for the available backends and the meaning of
+    `invalidation_globs`.
+    """
+    self._scheduler.start_watching(backend, watchman_socket_path, invalidation_globs)
+
+  def restart_required(self):
+    """Returns True if a path that pants itself depends on has changed since watching started."""
+    return self._scheduler.restart_required()
+
   def node_count(self):
     return self._scheduler.graph_len()
 
@@ -433,6 +634,90 @@ class SchedulerSession(object):
     """Returns metrics for this SchedulerSession as a dict of metric name to metric value."""
     return self._scheduler._metrics(self._session)
 
+  def observation_metrics(self):
+    """Returns summaries of the engine's histograms for this SchedulerSession.
+
+    The result is a dict of metric name to a dict of count, min, max, mean, p50, p90 and p99.
+    """
+    return self._scheduler._observation_metrics(self._session)
+
+  def memory_summary(self):
+    """Returns a summary of the approximate memory held by each subsystem of the engine.
+
+    The result is a dict with the count and bytes of graph entries per node type, of the retained
+    workunits, and of interned keys (and their types), and the size of each open LMDB map's file.
+    Sizes exclude python objects.
+    """
+    return self._scheduler._memory_summary()
+
+  def tee_console_to(self, path):
+    """Writes a copy of the engine's console output for this session to the given file.
+
+    ANSI escape sequences are stripped from the copy. The tee ends when the session is destroyed.
+    """
+    self._scheduler._tee_console(self._session, path)
+
+  def write_to_console_tee(self, payload):
+    """Copies console output which was written on behalf of this session to its tee, if any.
+
+    See `tee_console_to`.
+    """
+    self._scheduler._write_to_console_tee(self._session, payload)
+
+  def write_run_report(self, path):
+    """Writes a JSON report of this session to the given file.
+
+    The report contains the outcome of each requested root, counts of executed processes, cache and
+    transfer statistics, and the slowest process executions.
+    """
+    self._scheduler._write_run_report(self._session, path)
+
+  def start_profiling(self, frequency):
+    """Starts sampling the stacks of the engine at the given frequency (in Hz) for this session."""
+    self._scheduler._start_profiling(self._session, frequency)
+
+  def write_profile(self, path):
+    """Writes the engine stack samples taken so far in this session to the given file.
+
+    A path with an `.svg` extension is written as a flamegraph, and any other as a pprof profile.
+    """
+    self._scheduler._write_profile(self._session, path)
+
+  def run_local_interactive_process(self, request):
+    """Runs an InteractiveProcessRequest in the foreground, pausing the UI while it runs.
+
+    :returns: An InteractiveProcessResult.
+    """
+    exit_code = self._scheduler._run_local_interactive_process(self._session, request)
+    return InteractiveProcessResult(exit_code)
+
+  def start_background_process(self, request):
+    """Starts a BackgroundProcessRequest, which runs until it exits or this session ends.
+
+    :returns: The id of the process.
+    """
+    return self._scheduler._start_background_process(self._session, request)
+
+  def ensure_service(self, request):
+    """Starts the ServiceRequest unless it is already running within this session.
+
+    :returns: The id of the background process of the service.
+    """
+    return self._scheduler._ensure_service(self._session, request)
+
+  def background_process_progress(self, process_id, stdout_seen, timeout_seconds):
+    """Blocks until the background process writes more than `stdout_seen` bytes of stdout, until
+    it exits, or until `timeout_seconds` elapse.
+
+    :returns: A BackgroundProcessProgress.
+    """
+    return BackgroundProcessProgress(*self._scheduler._background_process_progress(
+      self._session, process_id, stdout_seen, timeout_seconds))
+
+  def stop_background_process(self, process_id):
+    """Kills the background process (if it has not already exited), and returns its exit code."""
+    return self._scheduler._stop_background_process(self._session, process_id)
+
   def with_fork_context(self, func):
     return self._scheduler.with_fork_context(func)
 
@@ -464,12 +749,16 @@ class SchedulerSession(object):
     throws = tuple((root, state) for root, state in roots if type(state) is Throw)
     return returns, throws
 
-  def _trace_on_error(self, unique_exceptions, request):
+  def _trace_on_error(self, throws):
+    # The same failure may be reached via multiple roots: render it once.
+    unique_throws = list(OrderedDict((t.exc, t) for t in throws).values())
+    unique_exceptions = tuple(t.exc for t in unique_throws)
     exception_noun = pluralize(len(unique_exceptions), 'Exception')
     if self._scheduler.include_trace_on_error:
-      cumulative_trace = '\n'.join(self.trace(request))
       raise ExecutionError(
-        '{} encountered:\n{}'.format(exception_noun, cumulative_trace),
+        '{} encountered:\n\n{}\n'.format(
+          exception_noun,
+          '\n\n'.join(t.format_traceback() for t in unique_throws)),
         unique_exceptions,
       )
     else:
@@ -491,8 +780,7 @@ class SchedulerSession(object):
 
     if throws:
       _, state = throws[0]
-      exc = state.exc
-      self._trace_on_error([exc], request)
+      self._trace_on_error([state])
       return PANTS_FAILED_EXIT_CODE
     _, state = returns[0]
     return state.value.exit_code
@@ -504,6 +792,34 @@ class SchedulerSession(object):
     :param list subjects: A list of subjects or Params instances for the request.
     :returns: A list of the requested products, with length match len(subjects).
     """
+    request = self._product_execution_request(product, subjects)
+    returns, throws = self.execute(request)
+
+    # Throw handling.
+    if throws:
+      self._trace_on_error([t for _, t in throws])
+
+    # Everything is a Return: we rely on the fact that roots are ordered to preserve subject
+    # order in output lists.
+    return [ret.value for _, ret in returns]
+
+  def partial_product_request(self, product, subjects):
+    """Executes a request for a single product for some subjects, and returns a result per subject.
+
+    Unlike `product_request`, the failure of some subjects does not raise, so that a caller can
+    report all of the failures of a run (rather than only the first).
+
+    :param class product: A product type for the request.
+    :param list subjects: A list of subjects or Params instances for the request.
+    :returns: A list with length matching len(subjects), containing either a `Return` of the
+      product or a `Throw` of the failure for each subject.
+    """
+    request = self._product_execution_request(product, subjects)
+    results = self._scheduler._run_and_return_roots(self._session, request.native)
+    self._maybe_visualize()
+    return results
+
+  def _product_execution_request(self, product, subjects):
     request = None
     raised_exception = None
     try:
@@ -554,16 +870,7 @@ class SchedulerSession(object):
             '\n\n{}'.format(raised_exception_message) if raised_exception_message else '')
         ))
 
-    returns, throws = self.execute(request)
-
-    # Throw handling.
-    if throws:
-      unique_exceptions = tuple({t.exc for _, t in throws})
-      self._trace_on_error(unique_exceptions, request)
-
-    # Everything is a Return: we rely on the fact that roots are ordered to preserve subject
-    # order in output lists.
-    return [ret.value for _, ret in returns]
+    return request
 
   def capture_snapshots(self, path_globs_and_roots):
     """Synchronously captures Snapshots for each matching PathGlobs rooted at a its root directory.
@@ -579,6 +886,12 @@ class SchedulerSession(object):
   def merge_directories(self, directory_digests):
     return self._scheduler.merge_directories(directory_digests)
 
+  def remote_execution_dry_run(self, execute_process_request):
+    return self._scheduler.remote_execution_dry_run(execute_process_request)
+
+  def select_jdk(self, spec):
+    return self._scheduler.select_jdk(spec)
+
   def materialize_directories(self, directories_paths_and_digests):
     """Creates the specified directories on the file system.
 
@@ -586,7 +899,10 @@ class SchedulerSession(object):
            digest of the directories to materialize.
     :returns: Nothing or an error.
     """
-    return self._scheduler.materialize_directories(directories_paths_and_digests)
+    return self._scheduler.materialize_directories(self._session, directories_paths_and_digests)
+
+  def write_to_workspace(self, workspace_write):
+    return self._scheduler.write_to_workspace(self._session, workspace_write)
 
   def lease_files_in_graph(self):
     self._scheduler.lease_files_in_graph()
@@ -94,6 +94,39 @@ Get({product!r}, {subject_type!r}, {subject!r})
     return super(Get, cls).__new__(cls, product, subject_declared_type, subject)
 
 
+class GetAll(datatype([
+  ('product', _type_field),
+  ('subject_declared_type', _type_field),
+  ('subjects', tuple),
+])):
+  """Requests the product for each of a collection of subjects, in a single batch.
+
+  Equivalent to `[Get(product, subject_declared_type, s) for s in subjects]`, but the engine
+  creates the Gets (rather than python), which is much faster for large collections. Like a list
+  of Gets, yielding a GetAll returns a tuple of the products, in the order of the subjects:
+
+    hydrated_targets = yield GetAll(HydratedTarget, Address, addresses.dependencies)
+  """
+
+  @staticmethod
+  def extract_constraints(call_node):
+    """Parses a `GetAll(..)` call to return its type constraints.
+
+    :param call_node: An `ast.Call` node representing a call to `GetAll(..)`.
+    :return: A tuple of product type id and subject type id.
+    """
+    if len(call_node.args) != 3 or not all(isinstance(a, ast.Name) for a in call_node.args[:2]):
+      raise ValueError(
+        '{} expected (product_type, subject_declared_type, subjects), but got: ({})'.format(
+          GetAll.__name__,
+          ', '.join(getattr(a, 'id', type(a).__name__) for a in call_node.args)))
+    product_type, subject_declared_type, _ = call_node.args
+    return (product_type.id, subject_declared_type.id)
+
+  def __new__(cls, product, subject_declared_type, subjects):
+    return super(GetAll, cls).__new__(cls, product, subject_declared_type, tuple(subjects))
+
+
 class Params(datatype([('params', tuple)])):
   """A set of values with distinct types.
 
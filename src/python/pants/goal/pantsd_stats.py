@@ -12,10 +12,14 @@ class PantsDaemonStats(object):
 
   def __init__(self):
     self.scheduler_metrics = {}
+    self.observation_metrics = {}
 
   def set_scheduler_metrics(self, scheduler_metrics):
     self.scheduler_metrics = scheduler_metrics
 
+  def set_observation_metrics(self, observation_metrics):
+    self.observation_metrics = observation_metrics
+
   def set_target_root_size(self, size):
     self.scheduler_metrics['target_root_size'] = size
 
@@ -26,3 +30,6 @@ class PantsDaemonStats(object):
     for key in ['target_root_size', 'affected_targets_size']:
       self.scheduler_metrics.setdefault(key, 0)
     return self.scheduler_metrics
+
+  def get_observation_metrics(self):
+    return self.observation_metrics
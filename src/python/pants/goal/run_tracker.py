@@ -425,6 +425,7 @@ class RunTracker(Subsystem):
         'run_info': self.run_information(),
         'artifact_cache_stats': self.artifact_cache_stats.get_all(),
         'pantsd_stats': self.pantsd_stats.get_all(),
+        'engine_observation_metrics': self.pantsd_stats.get_observation_metrics(),
         'workunits': self.json_reporter.results,
       }
     else:
@@ -435,6 +436,7 @@ class RunTracker(Subsystem):
         'critical_path_timings': self.get_critical_path_timings().get_all(),
         'artifact_cache_stats': self.artifact_cache_stats.get_all(),
         'pantsd_stats': self.pantsd_stats.get_all(),
+        'engine_observation_metrics': self.pantsd_stats.get_observation_metrics(),
         'outcomes': self.outcomes,
         'recorded_options': self._get_options_to_record(),
       }
@@ -23,6 +23,7 @@ from pants.engine.build_files import create_graph_rules
 from pants.engine.console import Console
 from pants.engine.fs import create_fs_rules
 from pants.engine.goal import Goal
+from pants.engine.interactive_runner import InteractiveRunner
 from pants.engine.isolated_process import create_process_rules
 from pants.engine.legacy.address_mapper import LegacyAddressMapper
 from pants.engine.legacy.graph import (LegacyBuildGraph, TransitiveHydratedTargets,
@@ -154,8 +155,10 @@ def _make_target_adaptor(base_class, target_type):
 class LegacyGraphScheduler(datatype(['scheduler', 'build_file_aliases', 'goal_map'])):
   """A thin wrapper around a Scheduler configured with @rules for a symbol table."""
 
-  def new_session(self, v2_ui=False):
-    session = self.scheduler.new_session(v2_ui)
+  def new_session(self, v2_ui=False, timeout_secs=None, v2_ui_level='info',
+                  execution_strategy='default', execution_policy='keep_going'):
+    session = self.scheduler.new_session(v2_ui, timeout_secs, v2_ui_level, execution_strategy,
+                                         execution_policy)
     return LegacyGraphSession(session, self.build_file_aliases, self.goal_map)
 
 
@@ -197,11 +200,13 @@ class LegacyGraphSession(datatype(['scheduler_session', 'build_file_aliases', 'g
     """
     subject = target_roots.specs
     console = Console(
-      use_colors=options_bootstrapper.bootstrap_options.for_global_scope().colors
+      use_colors=options_bootstrapper.bootstrap_options.for_global_scope().colors,
+      tee=self.scheduler_session.write_to_console_tee,
     )
+    interactive_runner = InteractiveRunner(self.scheduler_session)
     for goal in goals:
       goal_product = self.goal_map[goal]
-      params = Params(subject, options_bootstrapper, console)
+      params = Params(subject, options_bootstrapper, console, interactive_runner)
       logger.debug('requesting {} to satisfy execution of `{}` goal'.format(goal_product, goal))
       try:
         exit_code = self.scheduler_session.run_console_rule(goal_product, params)
@@ -361,6 +366,7 @@ class EngineInitializer(object):
     rules = (
       [
         RootRule(Console),
+        RootRule(InteractiveRunner),
         glob_match_error_behavior_singleton,
         build_configuration_singleton,
         symbol_table_singleton,
@@ -72,6 +72,14 @@ def setup_logging_from_options(bootstrap_options):
 
 
 class NativeHandler(StreamHandler):
+  """Routes python log records to the rust logger.
+
+  Records emitted by the engine are forwarded to the python logger named by their target, and so
+  reach this handler after python has filtered them. The rust logger then adds timestamps and
+  levels to records from both python and the engine, so that they interleave consistently. A logger
+  should only ever have one NativeHandler attached: the rust logger decides which destination
+  (stderr or the pantsd log) a record goes to.
+  """
 
   def __init__(self, level, native=None, stream=None, native_filename=None):
     super(NativeHandler, self).__init__(stream)
@@ -80,7 +88,11 @@ class NativeHandler(StreamHandler):
     self.setLevel(level)
 
   def emit(self, record):
-    self.native.write_log(self.format(record), record.levelno, "{}:pid={}".format(record.name, os.getpid()))
+    try:
+      msg = self.format(record)
+      self.native.write_log(msg, record.levelno, record.name)
+    except Exception:
+      self.handleError(record)
 
   def flush(self):
     self.native.flush_log()
@@ -169,13 +181,15 @@ def setup_logging(level, console_stream=None, log_dir=None, scope=None, log_name
   logging.Logger.trace = trace
 
   logger = logging.getLogger(scope)
-  for handler in logger.handlers:
+  for handler in list(logger.handlers):
     logger.removeHandler(handler)
 
-
+  # NB: Each NativeHandler forwards every record to the rust logger, which in turn writes it to the
+  # destination for the current thread. Registering one handler per destination would duplicate
+  # every record, so we set up each destination natively and then attach a single handler.
+  native_handler = None
   if console_stream:
     native_handler = create_native_stderr_log_handler(level, native, stream=console_stream)
-    logger.addHandler(native_handler)
 
   if log_dir:
     safe_mkdir(log_dir)
@@ -183,8 +197,9 @@ def setup_logging(level, console_stream=None, log_dir=None, scope=None, log_name
 
     native_handler = create_native_pantsd_file_log_handler(level, native, log_filename)
     file_handler = native_handler
-    logger.addHandler(native_handler)
 
+  if native_handler:
+    logger.addHandler(native_handler)
 
   logger.setLevel(level)
 
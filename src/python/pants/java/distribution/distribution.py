@@ -30,6 +30,11 @@ from pants.util.process_handler import subprocess
 logger = logging.getLogger(__name__)
 
 
+# The identities of java binaries, keyed by their real path and modification time: each binary is
+# probed only once, but re-pointing a symlinked JDK resolves to a different binary.
+_RESOLVED_IDENTITIES = {}
+
+
 def _parse_java_version(name, version):
   # Java version strings have been well defined since release 1.3.1 as defined here:
   #  http://www.oracle.com/technetwork/java/javase/versioning-naming-139433.html
@@ -88,6 +93,9 @@ class Distribution(object):
 
     self._home = home_path
     self._bin_path = bin_path or (os.path.join(home_path, 'bin') if home_path else '/usr/bin')
+    # NB: Validation re-locates the bin path to the (resolved) java.home, so the configured java is
+    # recorded in order to notice when the JDK it links to changes.
+    self._configured_java = os.path.join(self._bin_path, 'java')
 
     self._minimum_version = _parse_java_version("minimum_version", minimum_version)
     self._maximum_version = _parse_java_version("maximum_version", maximum_version)
@@ -115,6 +123,25 @@ class Distribution(object):
     """
     return self._get_version(self.java)
 
+  @property
+  def resolved_identity(self):
+    """Returns a tuple of the vendor and versions of the JVM that this distribution resolves to.
+
+    Unlike the configured paths of the distribution, the identity changes if a symlinked JDK is
+    switched to a different JVM. It is probed once per resolved java binary, and then cached.
+    """
+    java = self._configured_java if self._is_executable(self._configured_java) else self.java
+    real_java = os.path.realpath(java)
+    key = (real_java, os.stat(real_java).st_mtime)
+    identity = _RESOLVED_IDENTITIES.get(key)
+    if identity is None:
+      props = self._probe_system_properties(real_java)
+      identity = tuple(props.get(name, '') for name in ('java.vendor',
+                                                         'java.version',
+                                                         'java.vm.version'))
+      _RESOLVED_IDENTITIES[key] = identity
+    return identity
+
   def find_libs(self, names):
     """Looks for jars in the distribution lib folder(s).
 
@@ -234,24 +261,27 @@ class Distribution(object):
 
   def _get_system_properties(self, java):
     if not self._system_properties:
-      with temporary_dir() as classpath:
-        with open(os.path.join(classpath, 'SystemProperties.class'), 'w+b') as fp:
-          fp.write(pkgutil.get_data(__name__, 'SystemProperties.class'))
-        cmd = [java, '-cp', classpath, 'SystemProperties']
-        process = subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
-        stdout, stderr = process.communicate()
-        if process.returncode != 0:
-          raise self.Error('Failed to determine java system properties for {} with {} - exit code'
-                           ' {}: {}'.format(java, ' '.join(cmd), process.returncode, stderr.decode('utf-8')))
-
-      props = {}
-      for line in stdout.decode('utf-8').split(os.linesep):
-        key, _, val = line.partition('=')
-        props[key] = val
-      self._system_properties = props
-
+      self._system_properties = self._probe_system_properties(java)
     return self._system_properties
 
+  @classmethod
+  def _probe_system_properties(cls, java):
+    with temporary_dir() as classpath:
+      with open(os.path.join(classpath, 'SystemProperties.class'), 'w+b') as fp:
+        fp.write(pkgutil.get_data(__name__, 'SystemProperties.class'))
+      cmd = [java, '-cp', classpath, 'SystemProperties']
+      process = subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
+      stdout, stderr = process.communicate()
+      if process.returncode != 0:
+        raise cls.Error('Failed to determine java system properties for {} with {} - exit code'
+                        ' {}: {}'.format(java, ' '.join(cmd), process.returncode, stderr.decode('utf-8')))
+
+    props = {}
+    for line in stdout.decode('utf-8').split(os.linesep):
+      key, _, val = line.partition('=')
+      props[key] = val
+    return props
+
   def _validate_executable(self, name):
     def bin_paths():
       yield self._bin_path
@@ -11,6 +11,7 @@ import re
 import select
 import threading
 import time
+from collections import OrderedDict
 from contextlib import closing
 
 from future.utils import PY3, string_types
@@ -77,6 +78,11 @@ class NailgunExecutor(Executor, FingerprintedProcessManager):
   _NAILGUN_SPAWN_LOCK = threading.Lock()
   _PROCESS_NAME = 'java'
 
+  # The JVM writes fatal error logs named for the crashed pid into the nailgun workdir.
+  _HS_ERR_FILE_FMT = 'hs_err_pid{pid}.log'
+  # How much of the end of the nailgun's stdout and stderr to collect when it crashes.
+  _CRASH_LOG_TAIL_BYTES = 16 * 1024
+
   def __init__(self, identity, workdir, nailgun_classpath, distribution,
                startup_timeout=10, connect_timeout=10, connect_attempts=5,
                metadata_base_dir=None):
@@ -109,21 +115,26 @@ class NailgunExecutor(Executor, FingerprintedProcessManager):
   def _create_fingerprint_arg(self, fingerprint):
     return '='.join((self.FINGERPRINT_CMD_KEY, fingerprint))
 
+  def _create_error_file_arg(self, workdir):
+    # Directs fatal error logs into the workdir, where they can be collected if the server crashes.
+    return '-XX:ErrorFile={}'.format(os.path.join(workdir, self._HS_ERR_FILE_FMT.format(pid='%p')))
+
   @staticmethod
-  def _fingerprint(jvm_options, classpath, java_version):
+  def _fingerprint(jvm_options, classpath, java_identity):
     """Compute a fingerprint for this invocation of a Java task.
 
        :param list jvm_options: JVM options passed to the java invocation
        :param list classpath: The -cp arguments passed to the java invocation
-       :param Revision java_version: return value from Distribution.version()
+       :param tuple java_identity: return value from Distribution.resolved_identity, which changes
+                                   if the JVM under the distribution's path is switched
        :return: a hexstring representing a fingerprint of the java invocation
     """
     digest = hashlib.sha1()
     # TODO(John Sirois): hash classpath contents?
     encoded_jvm_options = [option.encode('utf-8') for option in sorted(jvm_options)]
     encoded_classpath = [cp.encode('utf-8') for cp in sorted(classpath)]
-    encoded_java_version = repr(java_version).encode('utf-8')
-    for item in (encoded_jvm_options, encoded_classpath, encoded_java_version):
+    encoded_java_identity = repr(java_identity).encode('utf-8')
+    for item in (encoded_jvm_options, encoded_classpath, encoded_java_identity):
       digest.update(str(item).encode('utf-8'))
     return digest.hexdigest() if PY3 else digest.hexdigest().decode('utf-8')
 
@@ -147,7 +158,17 @@ class NailgunExecutor(Executor, FingerprintedProcessManager):
           logger.debug('Executing via {ng_desc}: {cmd}'.format(ng_desc=nailgun, cmd=this.cmd))
           return nailgun.execute(main, cwd, *args)
         except (NailgunClient.NailgunError, self.InitialNailgunConnectTimedOut) as e:
+          # NB: Crash logs must be collected before terminating, which clears the server's pid.
+          crash_logs = self._collect_crash_logs()
+          pid = self.pid
           self.terminate()
+          if crash_logs is not None:
+            raise self.NailgunServerCrashed(identity=self._identity,
+                                            pid=pid,
+                                            main=main,
+                                            args=args,
+                                            cause=e,
+                                            crash_logs=crash_logs)
           raise self.Error('Problem launching via {ng_desc} command {main} {args}: {msg}'
                            .format(ng_desc=nailgun or '<no nailgun connection>',
                                    main=main,
@@ -170,7 +191,8 @@ class NailgunExecutor(Executor, FingerprintedProcessManager):
     """This (somewhat unfortunately) is the main entrypoint to this class via the Runner. It handles
        creation of the running nailgun server as well as creation of the client."""
     classpath = self._nailgun_classpath + classpath
-    new_fingerprint = self._fingerprint(jvm_options, classpath, self._distribution.version)
+    new_fingerprint = self._fingerprint(jvm_options, classpath,
+                                        self._distribution.resolved_identity)
 
     with self._NAILGUN_SPAWN_LOCK:
       running, updated = self._check_nailgun_state(new_fingerprint)
@@ -196,6 +218,70 @@ Stderr:
       msg = self._msg_fmt.format(timeout=timeout, stdout=stdout, stderr=stderr)
       super(NailgunExecutor.InitialNailgunConnectTimedOut, self).__init__(msg)
 
+  class NailgunServerCrashed(Executor.Error):
+    """Indicates that a nailgun server exited unexpectedly while serving a client.
+
+    :API: public
+    """
+
+    def __init__(self, identity, pid, main, args, cause, crash_logs):
+      """
+      :param string identity: The identity of the nailgun server which crashed.
+      :param int pid: The pid of the crashed nailgun server.
+      :param string main: The main which was being executed when the server crashed.
+      :param list args: The args which were being passed to `main`.
+      :param Exception cause: The client error which surfaced the crash.
+      :param OrderedDict crash_logs: A mapping from a workunit output name to the bytes of a log
+                                     collected from the crashed server's workdir.
+      """
+      self.identity = identity
+      self.pid = pid
+      self.crash_logs = crash_logs
+      msg = ('Nailgun server for {identity} (pid={pid}) crashed while running {main} {args}: '
+             '{cause}\nCollected crash logs: {logs}'
+             .format(identity=identity,
+                     pid=pid,
+                     main=main,
+                     args=' '.join(args),
+                     cause=cause,
+                     logs=', '.join(crash_logs.keys())))
+      super(NailgunExecutor.NailgunServerCrashed, self).__init__(msg)
+
+  def _read_tail(self, path):
+    """Returns up to the last `_CRASH_LOG_TAIL_BYTES` of the given file, or None if unreadable."""
+    try:
+      with open(path, 'rb') as fh:
+        fh.seek(0, os.SEEK_END)
+        fh.seek(max(0, fh.tell() - self._CRASH_LOG_TAIL_BYTES))
+        return fh.read()
+    except (IOError, OSError):
+      return None
+
+  def _collect_crash_logs(self):
+    """Collects logs for the nailgun server if it was started but has since exited unexpectedly.
+
+    :returns: An OrderedDict of workunit output name to log content, or None if the server is still
+              alive or was never started.
+    """
+    pid = self.pid
+    if pid is None or self.is_alive():
+      return None
+
+    crash_logs = OrderedDict()
+    hs_err_file = self._HS_ERR_FILE_FMT.format(pid=pid)
+    hs_err_path = os.path.join(self._workdir, hs_err_file)
+    if os.path.isfile(hs_err_path):
+      # The JVM's summary of the fatal error is at the head of the file, so it is kept whole.
+      crash_logs[os.path.splitext(hs_err_file)[0]] = read_file(hs_err_path, binary_mode=True)
+    for name, path in (('nailgun_stdout', self._ng_stdout), ('nailgun_stderr', self._ng_stderr)):
+      content = self._read_tail(path)
+      if content:
+        crash_logs[name] = content
+
+    logger.debug('Nailgun server {i} with pid={pid} exited unexpectedly, collected: {logs}'
+                 .format(i=self._identity, pid=pid, logs=list(crash_logs.keys())))
+    return crash_logs
+
   def _await_socket(self, timeout):
     """Blocks for the nailgun subprocess to bind and emit a listening port in the nailgun stdout."""
     with safe_open(self._ng_stdout, 'r') as ng_stdout:
@@ -249,7 +335,8 @@ Stderr:
 
     jvm_options = jvm_options + [self._PANTS_NG_BUILDROOT_ARG,
                                  self._create_owner_arg(self._workdir),
-                                 self._create_fingerprint_arg(fingerprint)]
+                                 self._create_fingerprint_arg(fingerprint),
+                                 self._create_error_file_arg(self._workdir)]
 
     post_fork_child_opts = dict(fingerprint=fingerprint,
                                 jvm_options=jvm_options,
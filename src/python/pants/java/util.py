@@ -151,9 +151,16 @@ def execute_runner(runner, workunit_factory=None, workunit_name=None, workunit_l
 
     with workunit_factory(name=workunit_name, labels=workunit_labels,
                           cmd=runner.cmd, log_config=workunit_log_config) as workunit:
-      ret = runner.run(stdout=workunit.output('stdout'),
-                       stderr=workunit.output('stderr'),
-                       stdin=stdin)
+      try:
+        ret = runner.run(stdout=workunit.output('stdout'),
+                         stderr=workunit.output('stderr'),
+                         stdin=stdin)
+      except NailgunExecutor.NailgunServerCrashed as e:
+        # Attach whatever the crashed server left behind so that it is visible in the run report.
+        for name, content in e.crash_logs.items():
+          workunit.output(name).write(content)
+        workunit.set_outcome(WorkUnit.FAILURE)
+        raise
       workunit.set_outcome(WorkUnit.FAILURE if ret else WorkUnit.SUCCESS)
       return ret
 
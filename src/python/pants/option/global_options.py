@@ -30,19 +30,58 @@ class GlobMatchErrorBehavior(enum(['ignore', 'warn', 'error'])):
 
 
 class ExecutionOptions(datatype([
+  'local_store_metadata_dir',
+  'offline',
+  'http_proxy',
+  'https_proxy',
+  'no_proxy',
   'remote_store_server',
+  'remote_store_central_server',
   'remote_store_thread_count',
   'remote_execution_server',
   'remote_store_chunk_bytes',
+  'remote_store_upload_window_chunks',
   'remote_store_chunk_upload_timeout_seconds',
+  'remote_store_find_missing_blobs_timeout_seconds',
+  'remote_store_read_timeout_seconds',
   'remote_store_rpc_retries',
+  'remote_store_read_only',
+  'remote_store_max_concurrent_transfers',
+  'remote_store_max_bytes_per_second',
   'process_execution_parallelism',
+  'process_execution_memory_budget_mb',
   'process_execution_cleanup_local_dirs',
+  'process_execution_max_output_bytes',
+  'process_execution_output_overflow_policy',
+  'process_execution_provenance_file',
+  'process_execution_artifact_index',
+  'process_execution_unused_inputs_report',
+  'process_execution_max_per_session',
+  'process_execution_max_per_minute',
+  'process_execution_audit_log',
+  'process_execution_audit_redact_env',
+  'process_execution_lint_hermeticity',
+  'process_execution_determinism_check_report',
+  'process_execution_determinism_check_descriptions',
+  'process_execution_determinism_check_vary_sandbox',
+  'process_execution_jdk_search_paths',
+  'snapshot_max_file_bytes',
+  'snapshot_max_total_bytes',
+  'snapshot_unicode_normalization',
+  'engine_io_threads',
+  'engine_cpu_threads',
   'remote_execution_process_cache_namespace',
   'remote_instance_name',
   'remote_ca_certs_path',
   'remote_oauth_bearer_token_path',
   'remote_execution_extra_platform_properties',
+  'remote_execution_allowed_platform_property_keys',
+  'remote_execution_skip_cache_lookup',
+  'remote_execution_action_cache_writes',
+  'remote_execution_verify_cache_hits',
+  'remote_execution_stream_timeout_seconds',
+  'remote_execution_action_cache_timeout_seconds',
+  'remote_execution_verify_report',
 ])):
   """A collection of all options related to (remote) execution of processes.
 
@@ -53,36 +92,115 @@ class ExecutionOptions(datatype([
   @classmethod
   def from_bootstrap_options(cls, bootstrap_options):
     return cls(
+      local_store_metadata_dir=bootstrap_options.local_store_metadata_dir,
+      offline=bootstrap_options.offline,
+      http_proxy=bootstrap_options.http_proxy,
+      https_proxy=bootstrap_options.https_proxy,
+      no_proxy=bootstrap_options.no_proxy,
       remote_store_server=bootstrap_options.remote_store_server,
+      remote_store_central_server=bootstrap_options.remote_store_central_server,
       remote_execution_server=bootstrap_options.remote_execution_server,
       remote_store_thread_count=bootstrap_options.remote_store_thread_count,
       remote_store_chunk_bytes=bootstrap_options.remote_store_chunk_bytes,
+      remote_store_upload_window_chunks=bootstrap_options.remote_store_upload_window_chunks,
       remote_store_chunk_upload_timeout_seconds=bootstrap_options.remote_store_chunk_upload_timeout_seconds,
+      remote_store_find_missing_blobs_timeout_seconds=bootstrap_options.remote_store_find_missing_blobs_timeout_seconds,
+      remote_store_read_timeout_seconds=bootstrap_options.remote_store_read_timeout_seconds,
       remote_store_rpc_retries=bootstrap_options.remote_store_rpc_retries,
+      remote_store_read_only=bootstrap_options.remote_store_read_only,
+      remote_store_max_concurrent_transfers=bootstrap_options.remote_store_max_concurrent_transfers,
+      remote_store_max_bytes_per_second=bootstrap_options.remote_store_max_bytes_per_second,
       process_execution_parallelism=bootstrap_options.process_execution_parallelism,
+      process_execution_memory_budget_mb=bootstrap_options.process_execution_memory_budget_mb,
       process_execution_cleanup_local_dirs=bootstrap_options.process_execution_cleanup_local_dirs,
+      process_execution_max_output_bytes=bootstrap_options.process_execution_max_output_bytes,
+      process_execution_output_overflow_policy=bootstrap_options.process_execution_output_overflow_policy,
+      process_execution_provenance_file=bootstrap_options.process_execution_provenance_file,
+      process_execution_artifact_index=bootstrap_options.process_execution_artifact_index,
+      process_execution_unused_inputs_report=bootstrap_options.process_execution_unused_inputs_report,
+      process_execution_max_per_session=bootstrap_options.process_execution_max_per_session,
+      process_execution_max_per_minute=bootstrap_options.process_execution_max_per_minute,
+      process_execution_audit_log=bootstrap_options.process_execution_audit_log,
+      process_execution_audit_redact_env=bootstrap_options.process_execution_audit_redact_env,
+      process_execution_lint_hermeticity=bootstrap_options.process_execution_lint_hermeticity,
+      process_execution_determinism_check_report=bootstrap_options.process_execution_determinism_check_report,
+      process_execution_determinism_check_descriptions=bootstrap_options.process_execution_determinism_check_descriptions,
+      process_execution_determinism_check_vary_sandbox=bootstrap_options.process_execution_determinism_check_vary_sandbox,
+      process_execution_jdk_search_paths=bootstrap_options.process_execution_jdk_search_paths,
+      snapshot_max_file_bytes=bootstrap_options.snapshot_max_file_bytes,
+      snapshot_max_total_bytes=bootstrap_options.snapshot_max_total_bytes,
+      snapshot_unicode_normalization=bootstrap_options.snapshot_unicode_normalization,
+      engine_io_threads=bootstrap_options.engine_io_threads,
+      engine_cpu_threads=bootstrap_options.engine_cpu_threads,
       remote_execution_process_cache_namespace=bootstrap_options.remote_execution_process_cache_namespace,
       remote_instance_name=bootstrap_options.remote_instance_name,
       remote_ca_certs_path=bootstrap_options.remote_ca_certs_path,
       remote_oauth_bearer_token_path=bootstrap_options.remote_oauth_bearer_token_path,
       remote_execution_extra_platform_properties=bootstrap_options.remote_execution_extra_platform_properties,
+      remote_execution_allowed_platform_property_keys=bootstrap_options.remote_execution_allowed_platform_property_keys,
+      remote_execution_skip_cache_lookup=bootstrap_options.remote_execution_skip_cache_lookup,
+      remote_execution_action_cache_writes=bootstrap_options.remote_execution_action_cache_writes,
+      remote_execution_verify_cache_hits=bootstrap_options.remote_execution_verify_cache_hits,
+      remote_execution_stream_timeout_seconds=bootstrap_options.remote_execution_stream_timeout_seconds,
+      remote_execution_action_cache_timeout_seconds=bootstrap_options.remote_execution_action_cache_timeout_seconds,
+      remote_execution_verify_report=bootstrap_options.remote_execution_verify_report,
     )
 
 
 DEFAULT_EXECUTION_OPTIONS = ExecutionOptions(
+    local_store_metadata_dir=None,
+    offline=False,
+    http_proxy=None,
+    https_proxy=None,
+    no_proxy=[],
     remote_store_server=[],
+    remote_store_central_server=[],
     remote_store_thread_count=1,
     remote_execution_server=None,
-    remote_store_chunk_bytes=1024*1024,
+    # Just under gRPC's default 4MB message size limit.
+    remote_store_chunk_bytes=3*1024*1024,
+    remote_store_upload_window_chunks=16,
     remote_store_chunk_upload_timeout_seconds=60,
+    remote_store_find_missing_blobs_timeout_seconds=30,
+    remote_store_read_timeout_seconds=60,
     remote_store_rpc_retries=2,
+    remote_store_read_only=False,
+    remote_store_max_concurrent_transfers=None,
+    remote_store_max_bytes_per_second=None,
     process_execution_parallelism=multiprocessing.cpu_count()*2,
+    process_execution_memory_budget_mb=None,
     process_execution_cleanup_local_dirs=True,
+    process_execution_max_output_bytes=None,
+    process_execution_output_overflow_policy='truncate_head',
+    process_execution_provenance_file=None,
+    process_execution_artifact_index=None,
+    process_execution_unused_inputs_report=None,
+    process_execution_max_per_session=None,
+    process_execution_max_per_minute=None,
+    process_execution_audit_log=None,
+    process_execution_audit_redact_env=['*KEY*', '*PASSWORD*', '*SECRET*', '*TOKEN*'],
+    process_execution_lint_hermeticity=False,
+    process_execution_determinism_check_report=None,
+    process_execution_determinism_check_descriptions=[],
+    process_execution_determinism_check_vary_sandbox=False,
+    process_execution_jdk_search_paths=['/usr/lib/jvm', '/Library/Java/JavaVirtualMachines'],
+    snapshot_max_file_bytes=None,
+    snapshot_max_total_bytes=None,
+    snapshot_unicode_normalization='nfc',
+    engine_io_threads=multiprocessing.cpu_count()*4,
+    engine_cpu_threads=multiprocessing.cpu_count(),
     remote_execution_process_cache_namespace=None,
     remote_instance_name=None,
     remote_ca_certs_path=None,
     remote_oauth_bearer_token_path=None,
     remote_execution_extra_platform_properties=[],
+    remote_execution_allowed_platform_property_keys=[],
+    remote_execution_skip_cache_lookup=False,
+    remote_execution_action_cache_writes=True,
+    remote_execution_verify_cache_hits=False,
+    remote_execution_stream_timeout_seconds=120,
+    remote_execution_action_cache_timeout_seconds=10,
+    remote_execution_verify_report=None,
   )
 
 
@@ -277,6 +395,9 @@ class GlobalOptionsRegistrar(SubsystemClientMixin, Optionable):
     register('--native-engine-visualize-to', advanced=True, default=None, type=dir_option, daemon=False,
              help='A directory to write execution and rule graphs to as `dot` files. The contents '
                   'of the directory will be overwritten if any filenames collide.')
+    register('--native-engine-crash-report-dir', advanced=True, default=None, type=dir_option,
+             help='A directory to write a report (including the rust backtrace) of each panic in '
+                  'the native engine to. Panics are otherwise only reported as exceptions.')
     register('--print-exception-stacktrace', advanced=True, type=bool,
              help='Print to console the full exception stack trace if encountered.')
 
@@ -311,6 +432,16 @@ class GlobalOptionsRegistrar(SubsystemClientMixin, Optionable):
              help='The directory to log pantsd output to.')
     register('--pantsd-invalidation-globs', advanced=True, type=list, default=[],
              help='Filesystem events matching any of these globs will trigger a daemon restart.')
+    register('--pantsd-watcher', advanced=True,
+             choices=['fs-event-service', 'engine-notify', 'engine-watchman'],
+             default='fs-event-service',
+             help='How pantsd watches the buildroot for changes. `fs-event-service` subscribes to '
+                  'watchman from python, and invalidates the engine with each batch of events. '
+                  '`engine-notify` and `engine-watchman` instead watch from within the engine, '
+                  'using either the native filesystem events of the platform (which does not '
+                  'require watchman at all) or a subscription to the watchman daemon. Only '
+                  '`engine-notify` reports renames, which allows the digests of renamed files to '
+                  'be reused rather than re-computed.')
 
     # Watchman options.
     register('--watchman-version', advanced=True, default='4.9.0-pants1', help='Watchman version.')
@@ -338,8 +469,37 @@ class GlobalOptionsRegistrar(SubsystemClientMixin, Optionable):
              # This default is also hard-coded into the engine's rust code in
              # fs::Store::default_path
              default=os.path.expanduser('~/.cache/pants/lmdb_store'))
+    register('--local-store-metadata-dir', advanced=True, default=None,
+             help='If set, the leases of the local store are kept in this (per-repo) directory, '
+                  'rather than alongside its content in --local-store-dir. This allows the '
+                  'content to be shared between the checkouts of many repos on one machine, while '
+                  'what each of them keeps alive remains separate: garbage collection respects the '
+                  'leases of every repo which shares the content. The leases which are already '
+                  'kept in --local-store-dir are adopted the first time this directory is used.')
+    register('--offline', type=bool, advanced=True, default=DEFAULT_EXECUTION_OPTIONS.offline,
+             help='Fail every operation of the engine which needs the network (reading from or '
+                  'writing to --remote-store-server, executing processes on '
+                  '--remote-execution-server, and downloading URLs) immediately, with an error '
+                  'naming the operation, rather than waiting for connections to time out. '
+                  'Operations which can be satisfied by the local store still succeed.')
+    register('--http-proxy', advanced=True, default=None,
+             help='The http:// URL of the proxy via which the engine makes insecure connections '
+                  '(to remote stores, remote execution servers and downloaded URLs). Overrides '
+                  'the http_proxy environment variable.')
+    register('--https-proxy', advanced=True, default=None,
+             help='The http:// URL of the proxy via which the engine makes TLS connections, which '
+                  'are tunneled through it with HTTP CONNECT. Overrides the https_proxy '
+                  'environment variable.')
+    register('--no-proxy', advanced=True, type=list, default=[],
+             help='Hosts (each of which also matches its subdomains) to which the engine connects '
+                  'directly rather than via a proxy, or * for all hosts. If set, overrides the '
+                  'no_proxy environment variable.')
     register('--remote-store-server', advanced=True, type=list, default=[],
              help='host:port of grpc server to use as remote execution file store.')
+    register('--remote-store-central-server', advanced=True, type=list, default=[],
+             help='host:port of a central grpc file store, for which --remote-store-server is a '
+                  'nearby cache. Files missing from --remote-store-server are fetched from here, '
+                  'and uploaded files are asynchronously written back here.')
     register('--remote-store-thread-count', type=int, advanced=True,
              default=DEFAULT_EXECUTION_OPTIONS.remote_store_thread_count,
              help='Thread count to use for the pool that interacts with the remote file store.')
@@ -348,12 +508,39 @@ class GlobalOptionsRegistrar(SubsystemClientMixin, Optionable):
     register('--remote-store-chunk-bytes', type=int, advanced=True,
              default=DEFAULT_EXECUTION_OPTIONS.remote_store_chunk_bytes,
              help='Size in bytes of chunks transferred to/from the remote file store.')
+    register('--remote-store-upload-window-chunks', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_store_upload_window_chunks,
+             help='Number of chunks of an upload to the remote file store which may be in flight '
+                  'at once. Larger values improve throughput on high latency links.')
     register('--remote-store-chunk-upload-timeout-seconds', type=int, advanced=True,
              default=DEFAULT_EXECUTION_OPTIONS.remote_store_chunk_upload_timeout_seconds,
              help='Timeout (in seconds) for uploads of individual chunks to the remote file store.')
+    register('--remote-store-find-missing-blobs-timeout-seconds', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_store_find_missing_blobs_timeout_seconds,
+             help='Timeout (in seconds) for requests to the remote file store which ask which '
+                  'files it is missing.')
+    register('--remote-store-read-timeout-seconds', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_store_read_timeout_seconds,
+             help='Timeout (in seconds) for downloads of individual files from the remote file '
+                  'store.')
     register('--remote-store-rpc-retries', type=int, advanced=True,
              default=DEFAULT_EXECUTION_OPTIONS.remote_store_rpc_retries,
              help='Number of times to retry any RPC to the remote store before giving up.')
+    register('--remote-store-read-only', type=bool, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_store_read_only,
+             help='Use --remote-store-server as a read-only upstream for the local store: files '
+                  'and directories missing locally are fetched from it and persisted locally, '
+                  'but nothing is ever uploaded to it. Incompatible with '
+                  '--remote-execution-server.')
+    register('--remote-store-max-concurrent-transfers', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_store_max_concurrent_transfers,
+             help='The maximum number of concurrent uploads and downloads to and from the remote '
+                  'file store. If not specified, transfers are not limited.')
+    register('--remote-store-max-bytes-per-second', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_store_max_bytes_per_second,
+             help='The maximum combined rate of uploads and downloads to and from the remote file '
+                  'store, in bytes per second. Useful on constrained network links. If not '
+                  'specified, transfers are not limited.')
     register('--remote-execution-process-cache-namespace', advanced=True,
              help="The cache namespace for remote process execution. "
                   "Bump this to invalidate every artifact's remote execution. "
@@ -375,15 +562,157 @@ class GlobalOptionsRegistrar(SubsystemClientMixin, Optionable):
                   'Format: property=value. Multiple values should be specified as multiple '
                   'occurrences of this flag. Pants itself may add additional platform properties.',
                    type=list, default=[])
+    register('--remote-execution-allowed-platform-property-keys', advanced=True,
+             type=list, default=[],
+             help='Platform property keys which individual processes may set as scheduling hints '
+                  '(for example, to prefer or avoid a pool of workers) via the platform_properties '
+                  'of their ExecuteProcessRequest. Remotely executing a process which sets any '
+                  'other key is an error.')
+    register('--remote-execution-skip-cache-lookup', type=bool, default=False, advanced=True,
+             help='Execute processes remotely even if the remote ActionCache already contains a '
+                  'result for them (which is then replaced by the new result). Useful for '
+                  'debugging suspected stale cache entries.')
+    register('--remote-execution-action-cache-writes', type=bool, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_execution_action_cache_writes,
+             help='Whether the results of remotely executed processes may be written to the remote '
+                  'ActionCache. Disable this on untrusted machines (such as developer laptops) so '
+                  'that only trusted writers (such as CI builds of the main branch) populate the '
+                  'cache: cached results are then looked up directly, and processes without one '
+                  'are executed without caching their results.')
+    register('--remote-execution-verify-cache-hits', type=bool, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_execution_verify_cache_hits,
+             help='Whether to check that the remote CAS still has all of the outputs of a cached '
+                  'result before using it. A result whose outputs have been garbage collected is '
+                  'executed again instead of failing on the missing outputs.')
+    register('--remote-execution-stream-timeout-seconds', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_execution_stream_timeout_seconds,
+             help='Timeout (in seconds) to wait for the remote execution server to respond to a '
+                  'request to execute a process, or for the status of an execution. This is '
+                  'separate from the timeout of the process itself.')
+    register('--remote-execution-action-cache-timeout-seconds', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_execution_action_cache_timeout_seconds,
+             help='Timeout (in seconds) for looking up cached results in the remote ActionCache.')
+    register('--remote-execution-verify-report', advanced=True, default=None,
+             help='If set, every process is executed both locally and remotely (using the local '
+                  'result), and a JSON record of each process whose exit code or outputs differed '
+                  '(including diffs of differing files) is appended to this file. Useful for '
+                  'finding non-hermetic processes. Requires --remote-execution-server.')
 
     # This should eventually deprecate the RunTracker worker count, which is used for legacy cache
     # lookups via CacheSetup in TaskBase.
     register('--process-execution-parallelism', type=int, default=multiprocessing.cpu_count(),
              advanced=True,
              help='Number of concurrent processes that may be executed either locally and remotely.')
+    register('--process-execution-memory-budget-mb', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.process_execution_memory_budget_mb,
+             help='The total memory (in MB) which concurrently executing local processes are '
+                  'estimated to use, beyond which a process which sets a memory_estimate_mb waits '
+                  'for others to complete. A process whose estimate exceeds the budget runs alone. '
+                  'If not specified, only the number of processes is limited.')
     register('--process-execution-cleanup-local-dirs', type=bool, default=True, advanced=True,
              help='Whether or not to cleanup directories used for local process execution '
                   '(primarily useful for e.g. debugging).')
+    register('--process-execution-max-output-bytes', type=int, default=None, advanced=True,
+             help='The maximum size of the stdout and stderr (each) that will be captured for a '
+                  'process execution which does not set its own limit. If not specified, outputs '
+                  'are unlimited.')
+    register('--process-execution-output-overflow-policy', advanced=True,
+             choices=['truncate_head', 'truncate_tail', 'fail', 'spill'], default='truncate_head',
+             help='What to do with a process output which exceeds '
+                  '--process-execution-max-output-bytes: keep only its end (truncate_head) or its '
+                  'beginning (truncate_tail), fail the process execution, or store it and capture '
+                  'only a message containing its digest (spill).')
+    register('--process-execution-provenance-file', advanced=True, default=None,
+             help='If set, a JSON record of each executed process (its action, input and output '
+                  'digests, argv and duration, among others) is appended to this file. Records '
+                  'can be queried with `fs_util provenance`.')
+    register('--process-execution-artifact-index', advanced=True, default=None,
+             help='If set, a JSON record of each file materialized in the workspace which was '
+                  'output by a process (its path, and the action digest, workunit id and '
+                  'description of the process) is written to this file. The file is rewritten '
+                  'for each run, except that concurrent runs append to it.')
+    register('--process-execution-unused-inputs-report', advanced=True, default=None,
+             help='If set, each process which succeeds is re-run locally under strace, and a JSON '
+                  'record of the declared input files which it never accessed is appended to this '
+                  'file. Useful for slimming the inputs (and so cache keys) of processes. Only '
+                  'supported on Linux, and requires strace to be on the PATH.')
+    register('--process-execution-max-per-session', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.process_execution_max_per_session,
+             help='The maximum number of processes which may be executed in one run, beyond which '
+                  'the run fails with a summary of the most frequently executed processes. A '
+                  'safety valve for misconfigured rules. If not specified, executions are not '
+                  'limited.')
+    register('--process-execution-max-per-minute', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.process_execution_max_per_minute,
+             help='The maximum number of processes which may be started by one run in any one '
+                  'minute, beyond which the run fails with a summary of the most frequently '
+                  'executed processes. If not specified, executions are not limited.')
+    register('--process-execution-audit-log', advanced=True, default=None,
+             help='If specified, a JSON record of the argv, environment and description of every '
+                  'process is appended to this file before it is executed, for security review, '
+                  'followed by a record of the runner which executed it once it completes. The '
+                  'values of environment variables matching '
+                  '--process-execution-audit-redact-env are not recorded.')
+    register('--process-execution-audit-redact-env', type=list, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.process_execution_audit_redact_env,
+             help='Case-insensitive glob patterns for the names of environment variables whose '
+                  'values should be redacted from the --process-execution-audit-log.')
+    register('--process-execution-lint-hermeticity', type=bool, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.process_execution_lint_hermeticity,
+             help='Scan the stdout, stderr and output files of each successful process for the '
+                  'absolute paths of its sandbox, of the buildroot, and of $HOME, and warn about '
+                  'any that it finds. Such outputs differ between machines, and so poison any '
+                  'cache which they are shared through.')
+    register('--process-execution-determinism-check-report', advanced=True, default=None,
+             help='If set, each process is executed twice, and a JSON record of each process '
+                  'whose two executions produced different exit codes or outputs (with a diff of '
+                  'each differing file) is appended to this file. Processes which pass can be '
+                  'safely shared via a remote cache. Requires local execution.')
+    register('--process-execution-determinism-check-descriptions', type=list, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.process_execution_determinism_check_descriptions,
+             help='Glob patterns for the descriptions of the processes to check with '
+                  '--process-execution-determinism-check-report. If empty, all processes are '
+                  'checked.')
+    register('--process-execution-determinism-check-vary-sandbox', type=bool, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.process_execution_determinism_check_vary_sandbox,
+             help='Run the second execution of each process checked by '
+                  '--process-execution-determinism-check-report in a sandbox below a different '
+                  'directory, to detect outputs which depend on the absolute path of the sandbox.')
+    register('--process-execution-jdk-search-paths', type=list, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.process_execution_jdk_search_paths,
+             help='JDK homes, or directories containing JDK homes, from which the engine selects a '
+                  'JDK for each process which requests one by version spec (for example `11` or '
+                  '`zulu-17`). The newest JDK matching the spec is selected, and its fingerprint '
+                  'is part of the cache key of the process.')
+    register('--snapshot-max-file-bytes', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.snapshot_max_file_bytes,
+             help='The maximum size of any one file captured into a snapshot, beyond which the '
+                  'capture fails with the offending path rather than storing the file. May be '
+                  'raised for particular captures via PathGlobs(max_file_bytes=...). If not '
+                  'specified, file sizes are not limited.')
+    register('--snapshot-max-total-bytes', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.snapshot_max_total_bytes,
+             help='The maximum total size of the files captured into one snapshot, beyond which '
+                  'the capture fails with a summary of the largest files. May be raised for '
+                  'particular captures via PathGlobs(max_total_bytes=...). If not specified, '
+                  'snapshot sizes are not limited.')
+    register('--snapshot-unicode-normalization', advanced=True,
+             choices=['preserve', 'nfc', 'nfd'],
+             default=DEFAULT_EXECUTION_OPTIONS.snapshot_unicode_normalization,
+             help='The unicode normalization form to apply to the names of captured and '
+                  'materialized files, so that the same logical names have the same digests '
+                  'regardless of how the filesystem stores them (macOS decomposes names to NFD, '
+                  'while Linux usually stores them as NFC). Use preserve to record names exactly '
+                  'as the filesystem stores them.')
+    register('--engine-io-threads', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.engine_io_threads,
+             help='The number of threads on which the engine performs blocking IO: reads and '
+                  'writes of the local store, and of files in the build root. Transfers to and '
+                  'from a remote store use the separate --remote-store-thread-count threads.')
+    register('--engine-cpu-threads', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.engine_cpu_threads,
+             help='The number of threads on which the engine performs CPU-bound work, such as '
+                  'running rules, hashing files, and parsing the protos of directories.')
 
   @classmethod
   def register_options(cls, register):
@@ -410,6 +739,40 @@ class GlobalOptionsRegistrar(SubsystemClientMixin, Optionable):
     register('--v2-ui', default=False, type=bool, daemon=False,
              help='Whether to show v2 engine execution progress. '
                   'This requires the --v2 flag to take effect.')
+    register('--v2-ui-level', choices=['info', 'debug', 'trace'], default='info', daemon=False,
+             help='The most verbose level of work which is shown by --v2-ui and included in '
+                  '--v2-run-report: info shows goals, processes and downloads; debug adds rules '
+                  'and snapshots; trace adds per-file operations such as hashing.')
+    register('--native-engine-profile-to', advanced=True, default=None, daemon=False,
+             help='If set, a path to write a profile of the stacks of the native engine\'s threads '
+                  'during the run to: a flamegraph if the path ends in `.svg`, and otherwise a '
+                  'pprof profile. Requires a native engine built with the `profiling` feature '
+                  '(by setting NATIVE_ENGINE_FEATURES=profiling).')
+    register('--native-engine-profile-frequency', advanced=True, type=int, default=99,
+             daemon=False,
+             help='The frequency (in Hz) at which --native-engine-profile-to samples stacks.')
+    register('--process-execution-strategy', advanced=True, daemon=False,
+             choices=['default', 'local_only', 'remote_only', 'remote_cache_only'],
+             default='default',
+             help='Where processes are executed for this run, regardless of how execution is '
+                  'configured: with the configured runners (default), locally, remotely, or '
+                  'locally unless a result is found in the remote cache (remote_cache_only). '
+                  'Useful for quickly bisecting whether a failure is related to remote '
+                  'execution infrastructure.')
+    register('--v2-timeout', type=int, default=None, daemon=False,
+             help='If set, the number of seconds after which v2 engine execution fails with an '
+                  'error listing the work which was still running, rather than continuing to '
+                  'wait for it.')
+    register('--v2-execution-policy', choices=['fail_fast', 'keep_going'], default='keep_going',
+             daemon=False,
+             help='Whether the first failure of a v2 engine execution cancels its other work '
+                  '(including any processes that are running) so that the run fails quickly '
+                  '(fail_fast), or whether all of its work runs to completion (keep_going).')
+    register('--v2-run-report', advanced=True, default=None, daemon=False,
+             help='If set, a path to write a machine-readable (JSON) report of the v2 engine\'s '
+                  'work to at the end of the run: the outcome of each requested root, counts of '
+                  'executed processes, cache and transfer statistics, and the slowest process '
+                  'executions.')
 
     loop_flag = '--loop'
     register(loop_flag, type=bool,
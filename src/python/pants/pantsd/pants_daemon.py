@@ -197,10 +197,15 @@ class PantsDaemon(FingerprintedProcessManager):
       :returns: A PantsServices instance.
       """
       should_shutdown_after_run = bootstrap_options.shutdown_pantsd_after_run
-      fs_event_service = FSEventService(
-        watchman,
-        build_root,
-      )
+      watcher_backend = PantsDaemon.engine_watcher_backend(bootstrap_options)
+      if watcher_backend:
+        # The engine watches the buildroot itself, so there are no watchman subscriptions.
+        fs_event_service = None
+      else:
+        fs_event_service = FSEventService(
+          watchman,
+          build_root,
+        )
 
       pidfile_absolute = PantsDaemon.metadata_file_path('pantsd', 'pid', bootstrap_options.pants_subprocessdir)
       if pidfile_absolute.startswith(build_root):
@@ -219,6 +224,8 @@ class PantsDaemon(FingerprintedProcessManager):
         build_root,
         PantsDaemon.compute_invalidation_globs(bootstrap_options),
         pidfile,
+        watcher_backend=watcher_backend,
+        watchman_socket_path=watchman.socket if watcher_backend == 'watchman' else None,
       )
 
       pailgun_service = PailgunService(
@@ -230,11 +237,24 @@ class PantsDaemon(FingerprintedProcessManager):
 
       store_gc_service = StoreGCService(legacy_graph_scheduler.scheduler)
 
+      services = (scheduler_service, pailgun_service, store_gc_service)
+      if fs_event_service:
+        services = (fs_event_service,) + services
       return PantsServices(
-        services=(fs_event_service, scheduler_service, pailgun_service, store_gc_service),
+        services=services,
         port_map=dict(pailgun=pailgun_service.pailgun_port),
       )
 
+  @staticmethod
+  def engine_watcher_backend(bootstrap_options):
+    """Returns the backend with which the engine watches the buildroot, if it does so itself.
+
+    :returns: `notify` or `watchman`, or None if watchman events are handled by the FSEventService.
+    """
+    watcher = bootstrap_options.pantsd_watcher
+    prefix = 'engine-'
+    return watcher[len(prefix):] if watcher.startswith(prefix) else None
+
   @staticmethod
   def compute_invalidation_globs(bootstrap_options):
     """
@@ -436,7 +456,7 @@ class PantsDaemon(FingerprintedProcessManager):
         global_bootstrap_options.print_exception_stacktrace)
       ExceptionSink.reset_log_location(global_bootstrap_options.pants_workdir)
 
-      self._native.set_panic_handler()
+      self._native.set_panic_handler(global_bootstrap_options.native_engine_crash_report_dir)
 
       # Set the process name in ps output to 'pantsd' vs './pants compile src/etc:: -ldebug'.
       set_process_title('pantsd [{}]'.format(self._build_root))
@@ -480,7 +500,9 @@ class PantsDaemon(FingerprintedProcessManager):
     :rtype: PantsDaemon.Handle
     """
     self.terminate(include_watchman=False)
-    self.watchman_launcher.maybe_launch()
+    # Watching with the platform's native filesystem events does not require watchman.
+    if self.engine_watcher_backend(self._bootstrap_options.for_global_scope()) != 'notify':
+      self.watchman_launcher.maybe_launch()
     self._logger.debug('launching pantsd')
     self.daemon_spawn()
     # Wait up to 60 seconds for pantsd to write its pidfile.
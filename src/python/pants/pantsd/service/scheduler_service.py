@@ -22,13 +22,16 @@ from pants.pantsd.service.pants_service import PantsService
 class SchedulerService(PantsService):
   """The pantsd scheduler service.
 
-  This service holds an online Scheduler instance that is primed via watchman filesystem events.
-  This provides for a quick fork of pants runs (via the pailgun) with a fully primed ProductGraph
-  in memory.
+  This service holds an online Scheduler instance that is primed via watchman filesystem events,
+  or which watches the build root itself when a `watcher_backend` is given. This provides for a
+  quick fork of pants runs (via the pailgun) with a fully primed ProductGraph in memory.
   """
 
   QUEUE_SIZE = 64
 
+  # How often (in seconds) to check for restarts when the engine is watching the build root.
+  ENGINE_WATCHER_POLL_SECONDS = 0.5
+
   def __init__(
     self,
     fs_event_service,
@@ -36,16 +39,23 @@ class SchedulerService(PantsService):
     build_root,
     invalidation_globs,
     pantsd_pidfile,
+    watcher_backend=None,
+    watchman_socket_path=None,
   ):
     """
     :param FSEventService fs_event_service: An unstarted FSEventService instance for setting up
-                                            filesystem event handlers.
+                                            filesystem event handlers, or None if a
+                                            `watcher_backend` is given.
     :param LegacyGraphScheduler legacy_graph_scheduler: The LegacyGraphScheduler instance for graph
                                                         construction.
     :param str build_root: The current build root.
     :param list invalidation_globs: A list of `globs` that when encountered in filesystem event
                                     subscriptions will tear down the daemon.
     :param string pantsd_pidfile: The path to the pantsd pidfile for fs event monitoring.
+    :param string watcher_backend: If set, the engine watches the build root itself using this
+                                   backend (see `Scheduler.start_watching`), rather than being
+                                   invalidated by the events of the FSEventService.
+    :param string watchman_socket_path: The watchman socket, for the `watchman` watcher_backend.
     """
     super(SchedulerService, self).__init__()
     self._fs_event_service = fs_event_service
@@ -53,6 +63,10 @@ class SchedulerService(PantsService):
     self._invalidation_globs = invalidation_globs
     self._build_root = build_root
     self._pantsd_pidfile = pantsd_pidfile
+    self._watcher_backend = watcher_backend
+    self._watchman_socket_path = watchman_socket_path
+    # Whether the pidfile has been observed to contain our PID, after which changes to it are fatal.
+    self._pidfile_written = False
 
     self._scheduler = legacy_graph_scheduler.scheduler
     self._scheduler_session = self._scheduler.new_session(False)
@@ -72,6 +86,17 @@ class SchedulerService(PantsService):
   def setup(self, services):
     """Service setup."""
     super(SchedulerService, self).setup(services)
+    if self._watcher_backend:
+      # Changes to the invalidation globs are reported by `restart_required`, and the pidfile is
+      # polled for changes, so no handlers need to be registered.
+      self._scheduler.start_watching(self._watcher_backend,
+                                     self._watchman_socket_path,
+                                     self._invalidation_globs)
+      self._logger.info('watching the build root using the {} backend'
+                        .format(self._watcher_backend))
+      self._watchman_is_running.set()
+      return
+
     # Register filesystem event handlers on an FSEventService instance.
     self._fs_event_service.register_all_files_handler(self._enqueue_fs_event)
 
@@ -110,6 +135,21 @@ class SchedulerService(PantsService):
       ))
       self.terminate()
 
+  def _check_engine_watcher(self):
+    """Terminates the daemon if a restart is required while the engine watches the build root."""
+    if self._scheduler.restart_required():
+      self._logger.fatal(
+        'saw file events covered by invalidation globs [{}], terminating the daemon.'
+          .format(self._invalidation_globs))
+      self.terminate()
+    elif self._pantsd_pidfile:
+      # The pidfile is written once all services have started, so it is only checked for changes
+      # once it has been seen to contain our PID.
+      if self._check_pid_changed() is False:
+        self._pidfile_written = True
+      elif self._pidfile_written:
+        self._maybe_invalidate_scheduler_pidfile()
+
   def _check_pid_changed(self):
     """Reads pidfile and returns False if its PID is ours, else a printable (maybe falsey) value."""
     try:
@@ -179,8 +219,13 @@ class SchedulerService(PantsService):
     if graph_len > 0:
       self._logger.debug('graph len was {}, waiting for initial watchman event'.format(graph_len))
       self._watchman_is_running.wait()
-    v2_ui = options.for_global_scope().v2_ui
-    session = self._graph_helper.new_session(v2_ui)
+    # Free interned keys which are no longer referenced by the graph before the next run.
+    self._scheduler.collect_interned_keys()
+    global_options = options.for_global_scope()
+    session = self._graph_helper.new_session(global_options.v2_ui, global_options.v2_timeout,
+                                             global_options.v2_ui_level,
+                                             global_options.process_execution_strategy,
+                                             global_options.v2_execution_policy)
 
     if options.for_global_scope().loop:
       prefork_fn = self._prefork_loop
@@ -237,8 +282,12 @@ class SchedulerService(PantsService):
   def run(self):
     """Main service entrypoint."""
     while not self._state.is_terminating:
-      self._process_event_queue()
-      self._state.maybe_pause()
+      if self._watcher_backend:
+        self._check_engine_watcher()
+        self._state.maybe_pause(timeout=self.ENGINE_WATCHER_POLL_SECONDS)
+      else:
+        self._process_event_queue()
+        self._state.maybe_pause()
 
 
 class LoopCondition(object):
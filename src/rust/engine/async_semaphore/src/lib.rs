@@ -28,17 +28,61 @@
 
 use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use futures::future::Future;
+use futures::future::{self, Either, Future};
 use futures::task::{self, Task};
 use futures::{Async, Poll};
+use futures_timer::{Delay, TimerHandle};
 use parking_lot::Mutex;
 
+///
+/// The error returned by `AsyncSemaphore::with_acquired_timeout`.
+///
+#[derive(Debug, Eq, PartialEq)]
+pub enum AcquireError<E> {
+  // A permit could not be acquired before the timeout elapsed, so the Future was never created.
+  Timeout(Duration),
+  // The Future which was run under the semaphore failed.
+  Failed(E),
+}
+
+struct Waiter {
+  id: usize,
+  permits: usize,
+  task: Task,
+}
+
 struct Inner {
-  waiters: VecDeque<Task>,
+  // Tasks which are waiting for a permit, in the order that they began waiting. Only the waiter
+  // at the front of the queue may acquire a permit, so that later arrivals cannot starve it.
+  waiters: VecDeque<Waiter>,
   available_permits: usize,
+  total_permits: usize,
+  next_waiter_id: usize,
+}
+
+impl Inner {
+  ///
+  /// If enough permits are available for the waiter at the front of the queue, returns its Task to
+  /// be notified (after the lock has been released).
+  ///
+  fn front_to_notify(&self) -> Option<Task> {
+    self
+      .waiters
+      .front()
+      .filter(|waiter| waiter.permits <= self.available_permits)
+      .map(|waiter| waiter.task.clone())
+  }
 }
 
+///
+/// A semaphore for Futures, which wakes waiters in FIFO order.
+///
+/// Each acquisition may request more than one permit, which allows the semaphore to bound a
+/// resource (such as an estimate of the memory used by running processes) rather than only a count
+/// of concurrent Futures.
+///
 #[derive(Clone)]
 pub struct AsyncSemaphore {
   inner: Arc<Mutex<Inner>>,
@@ -50,10 +94,42 @@ impl AsyncSemaphore {
       inner: Arc::new(Mutex::new(Inner {
         waiters: VecDeque::new(),
         available_permits: permits,
+        total_permits: permits,
+        next_waiter_id: 0,
       })),
     }
   }
 
+  ///
+  /// The number of callers which are currently waiting to acquire a permit.
+  ///
+  pub fn queue_depth(&self) -> usize {
+    self.inner.lock().waiters.len()
+  }
+
+  ///
+  /// Returns a Future for a single Permit, which is released when dropped.
+  ///
+  pub fn acquire(&self) -> PermitFuture {
+    self.acquire_weighted(1)
+  }
+
+  ///
+  /// Returns a Future for a Permit holding the given number of permits, which are released when it
+  /// is dropped.
+  ///
+  /// Requests for more permits than the semaphore was created with are clamped to its total, so
+  /// that they are able to run (alone) rather than waiting forever.
+  ///
+  pub fn acquire_weighted(&self, permits: usize) -> PermitFuture {
+    let permits = permits.min(self.inner.lock().total_permits);
+    PermitFuture {
+      inner: self.inner.clone(),
+      permits,
+      waiter_id: None,
+    }
+  }
+
   ///
   /// Runs the given Future-creating function (and the Future it returns) under the semaphore.
   ///
@@ -62,11 +138,24 @@ impl AsyncSemaphore {
     F: FnOnce() -> B + Send + 'static,
     B: Future<Item = T, Error = E> + Send + 'static,
   {
-    let permit = PermitFuture {
-      inner: Some(self.inner.clone()),
-    };
+    self.with_acquired_weighted(1, f)
+  }
+
+  ///
+  /// As with `with_acquired`, but holds the given number of permits while the Future runs.
+  ///
+  pub fn with_acquired_weighted<F, B, T, E>(
+    &self,
+    permits: usize,
+    f: F,
+  ) -> Box<dyn Future<Item = T, Error = E> + Send>
+  where
+    F: FnOnce() -> B + Send + 'static,
+    B: Future<Item = T, Error = E> + Send + 'static,
+  {
     Box::new(
-      permit
+      self
+        .acquire_weighted(permits)
         .map_err(|()| panic!("Acquisition is infalliable."))
         .and_then(|permit| {
           f().map(move |t| {
@@ -76,29 +165,78 @@ impl AsyncSemaphore {
         }),
     )
   }
+
+  ///
+  /// As with `with_acquired`, but fails with `AcquireError::Timeout` (without calling the given
+  /// function) if a permit cannot be acquired within the given timeout.
+  ///
+  pub fn with_acquired_timeout<F, B, T, E>(
+    &self,
+    timeout: Duration,
+    timer_handle: &TimerHandle,
+    f: F,
+  ) -> Box<dyn Future<Item = T, Error = AcquireError<E>> + Send>
+  where
+    F: FnOnce() -> B + Send + 'static,
+    B: Future<Item = T, Error = E> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+  {
+    let delay = Delay::new_handle(Instant::now() + timeout, timer_handle.clone());
+    Box::new(
+      self
+        .acquire()
+        .select2(delay)
+        .then(
+          move |res| -> Box<dyn Future<Item = Permit, Error = AcquireError<E>> + Send> {
+            match res {
+              Ok(Either::A((permit, _))) => Box::new(future::ok(permit)),
+              // Dropping the PermitFuture gives up its place in the queue.
+              Ok(Either::B(((), _))) => Box::new(future::err(AcquireError::Timeout(timeout))),
+              // If the timer has failed, fall back to waiting without a timeout.
+              Err(Either::B((_, permit_future))) => Box::new(
+                permit_future
+                  .map_err(|()| -> AcquireError<E> { panic!("Acquisition is infalliable.") }),
+              ),
+              Err(Either::A(((), _))) => panic!("Acquisition is infalliable."),
+            }
+          },
+        )
+        .and_then(|permit| {
+          f()
+            .map(move |t| {
+              drop(permit);
+              t
+            })
+            .map_err(AcquireError::Failed)
+        }),
+    )
+  }
 }
 
 pub struct Permit {
   inner: Arc<Mutex<Inner>>,
+  permits: usize,
 }
 
 impl Drop for Permit {
   fn drop(&mut self) {
     let task = {
       let mut inner = self.inner.lock();
-      inner.available_permits += 1;
-      if let Some(task) = inner.waiters.pop_front() {
-        task
-      } else {
-        return;
-      }
+      inner.available_permits += self.permits;
+      inner.front_to_notify()
     };
-    task.notify();
+    if let Some(task) = task {
+      task.notify();
+    }
   }
 }
 
 pub struct PermitFuture {
-  inner: Option<Arc<Mutex<Inner>>>,
+  inner: Arc<Mutex<Inner>>,
+  permits: usize,
+  // Set once this PermitFuture has been queued to wait for a permit.
+  waiter_id: Option<usize>,
 }
 
 impl Future for PermitFuture {
@@ -106,22 +244,64 @@ impl Future for PermitFuture {
   type Error = ();
 
   fn poll(&mut self) -> Poll<Permit, ()> {
-    let inner = self.inner.take().expect("cannot poll PermitFuture twice");
-    let acquired = {
-      let mut inner = inner.lock();
-      if inner.available_permits == 0 {
-        inner.waiters.push_back(task::current());
-        false
-      } else {
-        inner.available_permits -= 1;
-        true
-      }
+    let mut inner = self.inner.lock();
+    let is_front = match self.waiter_id {
+      None => inner.waiters.is_empty(),
+      Some(id) => inner.waiters.front().map(|waiter| waiter.id) == Some(id),
     };
-    if acquired {
-      Ok(Async::Ready(Permit { inner }))
-    } else {
-      self.inner = Some(inner);
-      Ok(Async::NotReady)
+    if is_front && inner.available_permits >= self.permits {
+      inner.available_permits -= self.permits;
+      if self.waiter_id.take().is_some() {
+        inner.waiters.pop_front();
+      }
+      // If more permits were released than were needed, the next waiter may be able to acquire as
+      // well.
+      let task = inner.front_to_notify();
+      drop(inner);
+      if let Some(task) = task {
+        task.notify();
+      }
+      return Ok(Async::Ready(Permit {
+        inner: self.inner.clone(),
+        permits: self.permits,
+      }));
+    }
+
+    match self.waiter_id {
+      Some(id) => {
+        // The task polling this PermitFuture might have changed since it was queued.
+        if let Some(waiter) = inner.waiters.iter_mut().find(|waiter| waiter.id == id) {
+          waiter.task = task::current();
+        }
+      }
+      None => {
+        let id = inner.next_waiter_id;
+        inner.next_waiter_id += 1;
+        inner.waiters.push_back(Waiter {
+          id,
+          permits: self.permits,
+          task: task::current(),
+        });
+        self.waiter_id = Some(id);
+      }
+    }
+    Ok(Async::NotReady)
+  }
+}
+
+impl Drop for PermitFuture {
+  fn drop(&mut self) {
+    // A PermitFuture which is dropped while waiting gives up its place in the queue, and passes on
+    // any wakeup that it might have received to the next waiter.
+    if let Some(id) = self.waiter_id.take() {
+      let task = {
+        let mut inner = self.inner.lock();
+        inner.waiters.retain(|waiter| waiter.id != id);
+        inner.front_to_notify()
+      };
+      if let Some(task) = task {
+        task.notify();
+      }
     }
   }
 }
@@ -129,11 +309,13 @@ impl Future for PermitFuture {
 #[cfg(test)]
 mod tests {
 
-  use super::AsyncSemaphore;
+  use super::{AcquireError, AsyncSemaphore};
   use futures::{future, Future};
-  use std::sync::mpsc;
+  use futures_timer::TimerHandle;
+  use parking_lot::Mutex;
+  use std::sync::{mpsc, Arc};
   use std::thread;
-  use std::time::Duration;
+  use std::time::{Duration, Instant};
 
   #[test]
   fn acquire_and_release() {
@@ -194,4 +376,108 @@ mod tests {
       .recv_timeout(Duration::from_secs(5))
       .expect("thread2 didn't acquire.");
   }
+
+  #[test]
+  fn acquisitions_are_fifo() {
+    let sema = AsyncSemaphore::new(1);
+    let permit = sema.acquire().wait().unwrap();
+    let acquired = Arc::new(Mutex::new(Vec::new()));
+
+    let threads = (0..4)
+      .map(|i| {
+        let handle = sema.clone();
+        let acquired = acquired.clone();
+        let thread = thread::spawn(move || {
+          handle
+            .with_acquired(move || {
+              acquired.lock().push(i);
+              future::ok::<_, ()>(())
+            })
+            .wait()
+            .unwrap();
+        });
+        // Wait for the thread to be queued before starting the next one.
+        wait_for_queue_depth(&sema, i + 1);
+        thread
+      })
+      .collect::<Vec<_>>();
+
+    drop(permit);
+    for thread in threads {
+      thread.join().unwrap();
+    }
+    assert_eq!(vec![0, 1, 2, 3], *acquired.lock());
+    assert_eq!(0, sema.queue_depth());
+  }
+
+  #[test]
+  fn acquisition_timeout() {
+    let sema = AsyncSemaphore::new(1);
+    let timer_handle = TimerHandle::default();
+    let permit = sema.acquire().wait().unwrap();
+
+    let timeout = Duration::from_millis(10);
+    let result = sema
+      .with_acquired_timeout(timeout, &timer_handle, || future::ok::<_, ()>(()))
+      .wait();
+    assert_eq!(Err(AcquireError::Timeout(timeout)), result);
+    // The timed out waiter should have given up its place in the queue.
+    assert_eq!(0, sema.queue_depth());
+
+    drop(permit);
+    let result = sema
+      .with_acquired_timeout(Duration::from_secs(5), &timer_handle, || {
+        future::err::<(), _>("failed")
+      })
+      .wait();
+    assert_eq!(Err(AcquireError::Failed("failed")), result);
+    sema
+      .acquire()
+      .wait()
+      .expect("Permit should have been released.");
+  }
+
+  #[test]
+  fn weighted_acquisitions() {
+    let sema = AsyncSemaphore::new(4);
+    let large = sema.acquire_weighted(3).wait().unwrap();
+    let small = sema.acquire().wait().unwrap();
+
+    // Two permits cannot be acquired until the large permit is released...
+    let handle = sema.clone();
+    let (tx, rx) = mpsc::channel();
+    let thread = thread::spawn(move || {
+      handle
+        .with_acquired_weighted(2, move || {
+          tx.send(()).unwrap();
+          future::ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    });
+    wait_for_queue_depth(&sema, 1);
+    drop(small);
+    rx.recv_timeout(Duration::from_millis(100))
+      .expect_err("Should not have acquired while three permits were held.");
+
+    drop(large);
+    rx.recv_timeout(Duration::from_secs(5))
+      .expect("Should have acquired after the large permit was released.");
+    thread.join().unwrap();
+
+    // ...and requests for more than the total number of permits are clamped.
+    sema.acquire_weighted(10).wait().unwrap();
+  }
+
+  fn wait_for_queue_depth(sema: &AsyncSemaphore, depth: usize) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while sema.queue_depth() < depth {
+      assert!(
+        Instant::now() < deadline,
+        "Queue depth did not reach {}",
+        depth
+      );
+      thread::sleep(Duration::from_millis(1));
+    }
+  }
 }
@@ -35,6 +35,7 @@ use fs;
 use fuse;
 
 use futures_timer;
+use grpcio;
 
 use libc;
 
@@ -42,11 +43,14 @@ use serverset;
 
 use time;
 
+use bytes::Bytes;
+use futures::future::{self, Future};
 use hashing::{Digest, Fingerprint};
 use log::{debug, error, warn};
 use parking_lot::Mutex;
+use protobuf::{self, Message};
 use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{CString, OsStr, OsString};
 use std::path::Path;
 use std::sync::Arc;
@@ -98,6 +102,7 @@ type Inode = u64;
 const ROOT: Inode = 1;
 const DIGEST_ROOT: Inode = 2;
 const DIRECTORY_ROOT: Inode = 3;
+const ACTIONS_ROOT: Inode = 4;
 
 #[derive(Clone, Copy, Debug)]
 enum EntryType {
@@ -110,6 +115,9 @@ struct InodeDetails {
   digest: Digest,
   entry_type: EntryType,
   is_executable: bool,
+  // For files, the Directory that the file was first found in (if any), whose other files are
+  // prefetched when the file is read.
+  parent: Option<Digest>,
 }
 
 #[derive(Debug)]
@@ -124,24 +132,104 @@ enum Node {
   File(bazel_protos::remote_execution::FileNode),
 }
 
+///
+/// A client for the remote action cache, used to find the outputs of Actions.
+///
+pub struct ActionCache {
+  client: bazel_protos::remote_execution_grpc::ActionCacheClient,
+  instance_name: Option<String>,
+  authorization_header: Option<String>,
+}
+
+impl ActionCache {
+  pub fn new(
+    address: &str,
+    instance_name: Option<String>,
+    root_ca_certs: &Option<Vec<u8>>,
+    proxies: &fs::ProxyConfig,
+    oauth_bearer_token: Option<String>,
+  ) -> ActionCache {
+    let env = Arc::new(grpcio::Environment::new(1));
+    let builder = proxies.configure_channel(
+      grpcio::ChannelBuilder::new(env),
+      address,
+      root_ca_certs.is_some(),
+    );
+    let channel = if let Some(ref root_ca_certs) = *root_ca_certs {
+      let creds = grpcio::ChannelCredentialsBuilder::new()
+        .root_cert(root_ca_certs.clone())
+        .build();
+      builder.secure_connect(address, creds)
+    } else {
+      builder.connect(address)
+    };
+    ActionCache {
+      client: bazel_protos::remote_execution_grpc::ActionCacheClient::new(channel),
+      instance_name: instance_name,
+      authorization_header: oauth_bearer_token.map(|t| format!("Bearer {}", t)),
+    }
+  }
+
+  fn get_action_result(
+    &self,
+    action_digest: Digest,
+  ) -> Result<Option<bazel_protos::remote_execution::ActionResult>, String> {
+    let mut request = bazel_protos::remote_execution::GetActionResultRequest::new();
+    if let Some(ref instance_name) = self.instance_name {
+      request.set_instance_name(instance_name.clone());
+    }
+    request.set_action_digest((&action_digest).into());
+    let mut call_option = grpcio::CallOption::default();
+    if let Some(ref authorization_header) = self.authorization_header {
+      let mut builder = grpcio::MetadataBuilder::with_capacity(1);
+      builder
+        .add_str("authorization", &authorization_header)
+        .unwrap();
+      call_option = call_option.headers(builder.build());
+    }
+    match self.client.get_action_result_opt(&request, call_option) {
+      Ok(action_result) => Ok(Some(action_result)),
+      Err(grpcio::Error::RpcFailure(ref status))
+        if status.status == grpcio::RpcStatusCode::NotFound =>
+      {
+        Ok(None)
+      }
+      Err(err) => Err(format!(
+        "Error getting action result for {:?}: {:?}",
+        action_digest, err
+      )),
+    }
+  }
+}
+
 struct BuildResultFS {
   runtime: tokio::runtime::Runtime,
   store: fs::Store,
+  action_cache: Option<ActionCache>,
   inode_digest_cache: HashMap<Inode, InodeDetails>,
   digest_inode_cache: HashMap<Digest, (Inode, Inode)>,
   directory_inode_cache: HashMap<Digest, Inode>,
+  action_directory_cache: HashMap<Digest, Digest>,
+  prefetched_directories: HashSet<Digest>,
   next_inode: Inode,
 }
 
 impl BuildResultFS {
-  pub fn new(runtime: tokio::runtime::Runtime, store: fs::Store) -> BuildResultFS {
+  pub fn new(
+    runtime: tokio::runtime::Runtime,
+    store: fs::Store,
+    action_cache: Option<ActionCache>,
+  ) -> BuildResultFS {
     BuildResultFS {
       runtime: runtime,
       store: store,
+      action_cache: action_cache,
       inode_digest_cache: HashMap::new(),
       digest_inode_cache: HashMap::new(),
       directory_inode_cache: HashMap::new(),
-      next_inode: 4,
+      action_directory_cache: HashMap::new(),
+      prefetched_directories: HashSet::new(),
+      next_inode: 5,
     }
   }
 }
@@ -165,58 +253,132 @@ impl BuildResultFS {
     None
   }
 
+  ///
+  /// Returns the inode for a file which is not known to exist, which requires loading it (and thus
+  /// fetching it from the remote Store if it is not available locally).
+  ///
   pub fn inode_for_file(
     &mut self,
     digest: Digest,
     is_executable: bool,
   ) -> Result<Option<Inode>, String> {
-    match self.digest_inode_cache.entry(digest) {
-      Occupied(entry) => {
-        let (executable_inode, non_executable_inode) = *entry.get();
-        Ok(Some(if is_executable {
+    if let Some(inode) = self.cached_file_inode(digest, is_executable) {
+      return Ok(Some(inode));
+    }
+    match self
+      .runtime
+      .block_on(self.store.load_file_bytes_with(digest, |_| ()))
+    {
+      Ok(Some(())) => Ok(Some(self.allocate_file_inodes(digest, is_executable, None))),
+      Ok(None) => Ok(None),
+      Err(err) => Err(err),
+    }
+  }
+
+  ///
+  /// Returns the inode for a file in the Directory with the given digest. The Directory vouches
+  /// for the existence of the file, so its content is not loaded until it is read: this allows
+  /// huge remote trees to be listed without fetching all of their files.
+  ///
+  pub fn inode_for_directory_file(
+    &mut self,
+    parent: Digest,
+    digest: Digest,
+    is_executable: bool,
+  ) -> Inode {
+    self
+      .cached_file_inode(digest, is_executable)
+      .unwrap_or_else(|| self.allocate_file_inodes(digest, is_executable, Some(parent)))
+  }
+
+  fn cached_file_inode(&self, digest: Digest, is_executable: bool) -> Option<Inode> {
+    self
+      .digest_inode_cache
+      .get(&digest)
+      .map(|&(executable_inode, non_executable_inode)| {
+        if is_executable {
           executable_inode
         } else {
           non_executable_inode
-        }))
-      }
-      Vacant(entry) => match self
-        .runtime
-        .block_on(self.store.load_file_bytes_with(digest, |_| ()))
-      {
-        Ok(Some(())) => {
-          let executable_inode = self.next_inode;
-          self.next_inode += 1;
-          let non_executable_inode = self.next_inode;
-          self.next_inode += 1;
-          entry.insert((executable_inode, non_executable_inode));
-          self.inode_digest_cache.insert(
-            executable_inode,
-            InodeDetails {
-              digest: digest,
-              entry_type: EntryType::File,
-              is_executable: true,
-            },
-          );
-          self.inode_digest_cache.insert(
-            non_executable_inode,
-            InodeDetails {
-              digest: digest,
-              entry_type: EntryType::File,
-              is_executable: false,
-            },
-          );
-          Ok(Some(if is_executable {
-            executable_inode
-          } else {
-            non_executable_inode
-          }))
         }
-        Ok(None) => Ok(None),
-        Err(err) => Err(err),
+      })
+  }
+
+  fn allocate_file_inodes(
+    &mut self,
+    digest: Digest,
+    is_executable: bool,
+    parent: Option<Digest>,
+  ) -> Inode {
+    let executable_inode = self.next_inode;
+    self.next_inode += 1;
+    let non_executable_inode = self.next_inode;
+    self.next_inode += 1;
+    self
+      .digest_inode_cache
+      .insert(digest, (executable_inode, non_executable_inode));
+    self.inode_digest_cache.insert(
+      executable_inode,
+      InodeDetails {
+        digest: digest,
+        entry_type: EntryType::File,
+        is_executable: true,
+        parent: parent,
+      },
+    );
+    self.inode_digest_cache.insert(
+      non_executable_inode,
+      InodeDetails {
+        digest: digest,
+        entry_type: EntryType::File,
+        is_executable: false,
+        parent: parent,
       },
+    );
+    if is_executable {
+      executable_inode
+    } else {
+      non_executable_inode
     }
   }
 
+  ///
+  /// Starts loading (and thus fetching, if necessary) all of the files in the Directory with the
+  /// given digest in the background, because reading one file in a Directory makes reading its
+  /// siblings likely. Each Directory is only prefetched once.
+  ///
+  fn prefetch_directory_files(&mut self, digest: Digest) {
+    if !self.prefetched_directories.insert(digest) {
+      return;
+    }
+    let store = self.store.clone();
+    let prefetch = self
+      .store
+      .load_directory(digest)
+      .and_then(move |maybe_directory| {
+        let file_digests = maybe_directory
+          .map(|directory| {
+            directory
+              .get_files()
+              .iter()
+              .map(|file| file.get_digest().into())
+              .collect::<Result<Vec<Digest>, String>>()
+          })
+          .unwrap_or_else(|| Ok(vec![]));
+        future::done(file_digests).and_then(move |file_digests| {
+          future::join_all(
+            file_digests
+              .into_iter()
+              .map(|file_digest| store.load_file_bytes_with(file_digest, |_| ()))
+              .collect::<Vec<_>>(),
+          )
+        })
+      })
+      .map(|_| ())
+      .map_err(move |err| warn!("Error prefetching files of directory {:?}: {}", digest, err));
+    self.runtime.spawn(prefetch);
+  }
+
   pub fn inode_for_directory(&mut self, digest: Digest) -> Result<Option<Inode>, String> {
     match self.directory_inode_cache.entry(digest) {
       Occupied(entry) => Ok(Some(*entry.get())),
@@ -233,6 +395,7 @@ impl BuildResultFS {
               digest: digest,
               entry_type: EntryType::Directory,
               is_executable: true,
+              parent: None,
             },
           );
           Ok(Some(inode))
@@ -243,6 +406,95 @@ impl BuildResultFS {
     }
   }
 
+  ///
+  /// Returns the digest of a Directory which describes the Action with the given digest. It
+  /// contains:
+  ///  action:     The Action, in protobuf text format.
+  ///  command:    The Command, in protobuf text format.
+  ///  input_root: The input root of the Action.
+  ///  output:     The outputs of the Action, if the action cache has a result for it.
+  ///
+  pub fn action_directory(&mut self, action_digest: Digest) -> Result<Option<Digest>, String> {
+    if let Some(digest) = self.action_directory_cache.get(&action_digest) {
+      return Ok(Some(*digest));
+    }
+    let action: bazel_protos::remote_execution::Action = match self.load_proto(action_digest)? {
+      Some(action) => action,
+      None => return Ok(None),
+    };
+    let command_digest_result: Result<Digest, String> = action.get_command_digest().into();
+    let command_digest = command_digest_result?;
+    let command: bazel_protos::remote_execution::Command =
+      self.load_proto(command_digest)?.ok_or_else(|| {
+        format!(
+          "Command {:?} of action {:?} does not exist",
+          command_digest, action_digest
+        )
+      })?;
+    let input_root_digest_result: Result<Digest, String> = action.get_input_root_digest().into();
+    let input_root_digest = input_root_digest_result?;
+    let output_digest = match self.action_cache {
+      Some(ref action_cache) => match action_cache.get_action_result(action_digest)? {
+        Some(action_result) => Some(self.runtime.block_on(
+          process_execution::remote::extract_output_files(self.store.clone(), &action_result),
+        )?),
+        None => None,
+      },
+      None => None,
+    };
+
+    // Entries are added in sorted order, so that the Directory is canonical.
+    let mut directory = bazel_protos::remote_execution::Directory::new();
+    directory
+      .mut_files()
+      .push(self.store_text_file("action", &action)?);
+    directory
+      .mut_files()
+      .push(self.store_text_file("command", &command)?);
+    directory
+      .mut_directories()
+      .push(directory_node("input_root", input_root_digest));
+    if let Some(output_digest) = output_digest {
+      directory
+        .mut_directories()
+        .push(directory_node("output", output_digest));
+    }
+    let digest = self
+      .runtime
+      .block_on(self.store.record_directory(&directory, false))?;
+    self.action_directory_cache.insert(action_digest, digest);
+    Ok(Some(digest))
+  }
+
+  fn load_proto<M: Message>(&mut self, digest: Digest) -> Result<Option<M>, String> {
+    self
+      .runtime
+      .block_on(self.store.load_file_bytes_with(digest, |bytes| bytes))?
+      .map(|bytes| {
+        protobuf::parse_from_bytes::<M>(&bytes)
+          .map_err(|e| format!("Error parsing proto with digest {:?}: {:?}", digest, e))
+      })
+      .transpose()
+  }
+
+  ///
+  /// Stores the protobuf text format rendering of the given message, and returns a FileNode for it.
+  ///
+  fn store_text_file<M: Message>(
+    &mut self,
+    name: &str,
+    message: &M,
+  ) -> Result<bazel_protos::remote_execution::FileNode, String> {
+    let bytes = Bytes::from(protobuf::text_format::print_to_string(message));
+    let digest = self
+      .runtime
+      .block_on(self.store.store_file_bytes(bytes, false))?;
+    let mut file_node = bazel_protos::remote_execution::FileNode::new();
+    file_node.set_name(name.to_owned());
+    file_node.set_digest((&digest).into());
+    Ok(file_node)
+  }
+
   pub fn file_attr_for(&mut self, inode: Inode) -> Option<fuse::FileAttr> {
     self.inode_digest_cache.get(&inode).map(|f| {
       attr_for(
@@ -288,8 +540,13 @@ impl BuildResultFS {
           kind: fuse::FileType::Directory,
           name: OsString::from("directory"),
         },
+        ReaddirEntry {
+          inode: ACTIONS_ROOT,
+          kind: fuse::FileType::Directory,
+          name: OsString::from("actions"),
+        },
       ]),
-      // readdir on /digest or /directory will return an empty set.
+      // readdir on /digest, /directory or /actions will return an empty set.
       // readdir on /directory/abc123... will properly list the contents of that Directory.
       //
       // We skip directory listing for the roots because they will just be very long lists of
@@ -302,7 +559,7 @@ impl BuildResultFS {
       // All three of these end up with the same problem that readdir doesn't show things which, if
       // you were to getattr/open would actually exist. So we choose the cheapest, and most
       // consistent one: readdir is always empty.
-      DIGEST_ROOT | DIRECTORY_ROOT => Ok(vec![]),
+      DIGEST_ROOT | DIRECTORY_ROOT | ACTIONS_ROOT => Ok(vec![]),
       inode => match self.inode_digest_cache.get(&inode) {
         Some(&InodeDetails {
           digest,
@@ -351,7 +608,11 @@ impl BuildResultFS {
                 })?;
                 let maybe_child_inode = match filetype {
                   fuse::FileType::Directory => self.inode_for_directory(child_digest),
-                  fuse::FileType::RegularFile => self.inode_for_file(child_digest, is_executable),
+                  fuse::FileType::RegularFile => Ok(Some(self.inode_for_directory_file(
+                    digest,
+                    child_digest,
+                    is_executable,
+                  ))),
                   _ => unreachable!(),
                 };
                 match maybe_child_inode {
@@ -391,6 +652,7 @@ impl BuildResultFS {
 //  1: /
 //  2: /digest
 //  3: /directory
+//  4: /actions
 //  ... created on demand and cached for the lifetime of the program.
 impl fuse::Filesystem for BuildResultFS {
   // Used to answer stat calls
@@ -404,6 +666,7 @@ impl fuse::Filesystem for BuildResultFS {
     let r = match (parent, name.to_str()) {
       (ROOT, Some("digest")) => Ok(dir_attr_for(DIGEST_ROOT)),
       (ROOT, Some("directory")) => Ok(dir_attr_for(DIRECTORY_ROOT)),
+      (ROOT, Some("actions")) => Ok(dir_attr_for(ACTIONS_ROOT)),
       (DIGEST_ROOT, Some(digest_str)) => match digest_from_filepath(digest_str) {
         Ok(digest) => self
           .inode_for_file(digest, true)
@@ -428,6 +691,20 @@ impl fuse::Filesystem for BuildResultFS {
           Err(libc::ENOENT)
         }
       },
+      (ACTIONS_ROOT, Some(digest_str)) => match digest_from_filepath(digest_str) {
+        Ok(digest) => match self.action_directory(digest) {
+          Ok(Some(directory_digest)) => self.dir_attr_for(directory_digest),
+          Ok(None) => Err(libc::ENOENT),
+          Err(err) => {
+            error!("Error loading action {}: {}", digest_str, err);
+            Err(libc::EINVAL)
+          }
+        },
+        Err(err) => {
+          warn!("Invalid digest for action in actions root: {}", err);
+          Err(libc::ENOENT)
+        }
+      },
       (parent, Some(filename)) => {
         let maybe_cache_entry = self
           .inode_digest_cache
@@ -445,9 +722,10 @@ impl fuse::Filesystem for BuildResultFS {
                 libc::EINVAL
               })?
               .and_then(|directory| self.node_for_digest(&directory, filename))
+              .map(|node| (parent_digest, node))
               .ok_or(libc::ENOENT)
           })
-          .and_then(|node| match node {
+          .and_then(|(parent_digest, node)| match node {
             Node::Directory(directory_node) => {
               let digest_result: Result<Digest, String> = directory_node.get_digest().into();
               let digest = digest_result.map_err(|err| {
@@ -462,17 +740,9 @@ impl fuse::Filesystem for BuildResultFS {
                 error!("Error parsing digest: {:?}", err);
                 libc::ENOENT
               })?;
-              self
-                .inode_for_file(digest, file_node.get_is_executable())
-                .map_err(|err| {
-                  error!("Error loading file by digest {}: {}", filename, err);
-                  libc::EINVAL
-                })
-                .and_then(|maybe_inode| {
-                  maybe_inode
-                    .and_then(|inode| self.file_attr_for(inode))
-                    .ok_or(libc::ENOENT)
-                })
+              let inode =
+                self.inode_for_directory_file(parent_digest, digest, file_node.get_is_executable());
+              self.file_attr_for(inode).ok_or(libc::ENOENT)
             }
           })
       }
@@ -489,6 +759,7 @@ impl fuse::Filesystem for BuildResultFS {
       ROOT => reply.attr(&TTL, &dir_attr_for(ROOT)),
       DIGEST_ROOT => reply.attr(&TTL, &dir_attr_for(DIGEST_ROOT)),
       DIRECTORY_ROOT => reply.attr(&TTL, &dir_attr_for(DIRECTORY_ROOT)),
+      ACTIONS_ROOT => reply.attr(&TTL, &dir_attr_for(ACTIONS_ROOT)),
       _ => match self.inode_digest_cache.get(&inode) {
         Some(&InodeDetails {
           entry_type: EntryType::File,
@@ -516,12 +787,16 @@ impl fuse::Filesystem for BuildResultFS {
     size: u32,
     reply: fuse::ReplyData,
   ) {
-    match self.inode_digest_cache.get(&inode) {
-      Some(&InodeDetails {
+    match self.inode_digest_cache.get(&inode).cloned() {
+      Some(InodeDetails {
         digest,
         entry_type: EntryType::File,
+        parent,
         ..
       }) => {
+        if let Some(parent) = parent {
+          self.prefetch_directory_files(parent);
+        }
         let reply = Arc::new(Mutex::new(Some(reply)));
         let reply2 = reply.clone();
         // TODO: Read from a cache of Futures driven from a CPU pool, so we can merge in-flight
@@ -596,9 +871,17 @@ impl fuse::Filesystem for BuildResultFS {
   }
 }
 
+fn directory_node(name: &str, digest: Digest) -> bazel_protos::remote_execution::DirectoryNode {
+  let mut directory_node = bazel_protos::remote_execution::DirectoryNode::new();
+  directory_node.set_name(name.to_owned());
+  directory_node.set_digest((&digest).into());
+  directory_node
+}
+
 pub fn mount<'a, P: AsRef<Path>>(
   mount_path: P,
   store: fs::Store,
+  action_cache: Option<ActionCache>,
   runtime: tokio::runtime::Runtime,
 ) -> std::io::Result<fuse::BackgroundSession<'a>> {
   // TODO: Work out how to disable caching in the filesystem
@@ -609,7 +892,13 @@ pub fn mount<'a, P: AsRef<Path>>(
 
   debug!("About to spawn_mount with options {:?}", options);
 
-  let fs = unsafe { fuse::spawn_mount(BuildResultFS::new(runtime, store), &mount_path, &options) };
+  let fs = unsafe {
+    fuse::spawn_mount(
+      BuildResultFS::new(runtime, store, action_cache),
+      &mount_path,
+      &options,
+    )
+  };
   // fuse::spawn_mount doesn't always fully initialise the filesystem before returning.
   // Bluntly sleep for a bit here. If this poses a problem, we should maybe start doing some polling
   // stats or something until the filesystem seems to be correct.
@@ -686,12 +975,26 @@ fn main() {
     None
   };
 
+  let proxies = fs::ProxyConfig::from_env().expect("Invalid proxy environment variables");
+
+  let action_cache = args.value_of("server-address").map(|address| {
+    ActionCache::new(
+      address,
+      args.value_of("remote-instance-name").map(str::to_owned),
+      &root_ca_certs,
+      &proxies,
+      oauth_bearer_token.clone(),
+    )
+  });
+
   let store = match args.value_of("server-address") {
     Some(address) => fs::Store::with_remote(
       &store_path,
       &[address.to_owned()],
+      &[],
       args.value_of("remote-instance-name").map(str::to_owned),
       &root_ca_certs,
+      &proxies,
       oauth_bearer_token,
       1,
       4 * 1024 * 1024,
@@ -711,7 +1014,7 @@ fn main() {
   .expect("Error making store");
 
   let runtime = tokio::runtime::Runtime::new().expect("Making runtime");
-  let _fs = mount(mount_path, store, runtime).expect("Error mounting");
+  let _fs = mount(mount_path, store, action_cache, runtime).expect("Error mounting");
   loop {
     std::thread::sleep(std::time::Duration::from_secs(1));
   }
@@ -739,8 +1042,15 @@ mod test {
   use testutil;
 
   use super::mount;
+  use bazel_protos;
+  use bytes::Bytes;
   use fs;
+  use futures_timer::TimerHandle;
   use hashing;
+  use mock::StubCAS;
+  use protobuf::Message;
+  use serverset::BackoffConfig;
+  use std::time::{Duration, Instant};
   use testutil::{
     data::{TestData, TestDirectory},
     file,
@@ -754,7 +1064,7 @@ mod test {
 
     let runtime = tokio::runtime::Runtime::new().unwrap();
 
-    let _fs = mount(mount_dir.path(), store, runtime).expect("Mounting");
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
     assert!(!&mount_dir
       .path()
       .join("digest")
@@ -775,7 +1085,7 @@ mod test {
       .block_on(store.store_file_bytes(test_bytes.bytes(), false))
       .expect("Storing bytes");
 
-    let _fs = mount(mount_dir.path(), store, runtime).expect("Mounting");
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
     let file_path = mount_dir
       .path()
       .join("digest")
@@ -801,7 +1111,7 @@ mod test {
       .block_on(store.record_directory(&test_directory.directory(), false))
       .expect("Storing directory");
 
-    let _fs = mount(mount_dir.path(), store, runtime).expect("Mounting");
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
     let virtual_dir = mount_dir
       .path()
       .join("directory")
@@ -826,7 +1136,7 @@ mod test {
       .block_on(store.record_directory(&test_directory.directory(), false))
       .expect("Storing directory");
 
-    let _fs = mount(mount_dir.path(), store, runtime).expect("Mounting");
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
     let roland = mount_dir
       .path()
       .join("directory")
@@ -861,7 +1171,7 @@ mod test {
       .block_on(store.record_directory(&recursive_directory.directory(), false))
       .expect("Storing directory");
 
-    let _fs = mount(mount_dir.path(), store, runtime).expect("Mounting");
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
     let virtual_dir = mount_dir
       .path()
       .join("directory")
@@ -895,7 +1205,7 @@ mod test {
       .block_on(store.record_directory(&recursive_directory.directory(), false))
       .expect("Storing directory");
 
-    let _fs = mount(mount_dir.path(), store, runtime).expect("Mounting");
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
     let virtual_dir = mount_dir
       .path()
       .join("directory")
@@ -926,7 +1236,7 @@ mod test {
       .block_on(store.record_directory(&directory.directory(), false))
       .expect("Storing directory");
 
-    let _fs = mount(mount_dir.path(), store, runtime).expect("Mounting");
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
     let virtual_dir = mount_dir
       .path()
       .join("directory")
@@ -936,6 +1246,141 @@ mod test {
     assert!(!file::is_executable(&virtual_dir.join("food")));
   }
 
+  #[test]
+  fn list_remote_directory_without_fetching_files() {
+    let (store_dir, mount_dir) = make_dirs();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let treat_bytes = TestData::catnip();
+    let recursive_directory = TestDirectory::recursive();
+    let cas = StubCAS::builder()
+      .file(&TestData::roland())
+      .file(&treat_bytes)
+      .directory(&TestDirectory::containing_roland())
+      .directory(&recursive_directory)
+      .build();
+    let store = new_remote_store(store_dir.path(), cas.address());
+
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
+    let virtual_dir = mount_dir
+      .path()
+      .join("directory")
+      .join(digest_to_filepath(&recursive_directory.digest()));
+    assert_eq!(vec!["cats", "treats"], file::list_dir(&virtual_dir));
+    let read_requests_after_listing = cas.read_request_count();
+
+    // The file is only fetched when it is read.
+    assert_eq!(
+      treat_bytes.bytes(),
+      file::contents(&virtual_dir.join("treats"))
+    );
+    assert!(cas.read_request_count() > read_requests_after_listing);
+  }
+
+  #[test]
+  fn reading_remote_file_prefetches_siblings() {
+    let (store_dir, mount_dir) = make_dirs();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let roland_bytes = TestData::roland();
+    let treat_bytes = TestData::catnip();
+    let directory = TestDirectory::containing_roland_and_treats();
+    let cas = StubCAS::builder()
+      .file(&roland_bytes)
+      .file(&treat_bytes)
+      .directory(&directory)
+      .build();
+    let store = new_remote_store(store_dir.path(), cas.address());
+
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
+    let virtual_dir = mount_dir
+      .path()
+      .join("directory")
+      .join(digest_to_filepath(&directory.digest()));
+    assert_eq!(
+      roland_bytes.bytes(),
+      file::contents(&virtual_dir.join("roland"))
+    );
+
+    // One read for the Directory, and one for each of its files.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while cas.read_request_count() < 3 && Instant::now() < deadline {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    let read_requests_after_prefetch = cas.read_request_count();
+    assert_eq!(
+      treat_bytes.bytes(),
+      file::contents(&virtual_dir.join("treats"))
+    );
+    assert_eq!(read_requests_after_prefetch, cas.read_request_count());
+  }
+
+  #[test]
+  fn browse_action() {
+    let (store_dir, mount_dir) = make_dirs();
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let store = fs::Store::local_only(store_dir.path()).expect("Error creating local store");
+
+    let test_bytes = TestData::roland();
+    let test_directory = TestDirectory::containing_roland();
+    let mut command = bazel_protos::remote_execution::Command::new();
+    command.mut_arguments().push("/bin/cat".to_owned());
+    command.mut_arguments().push("roland".to_owned());
+
+    runtime
+      .block_on(store.store_file_bytes(test_bytes.bytes(), false))
+      .expect("Storing bytes");
+    runtime
+      .block_on(store.record_directory(&test_directory.directory(), false))
+      .expect("Storing directory");
+    let command_digest = runtime
+      .block_on(store.store_file_bytes(Bytes::from(command.write_to_bytes().unwrap()), false))
+      .expect("Storing command");
+    let mut action = bazel_protos::remote_execution::Action::new();
+    action.set_command_digest((&command_digest).into());
+    action.set_input_root_digest((&test_directory.digest()).into());
+    let action_digest = runtime
+      .block_on(store.store_file_bytes(Bytes::from(action.write_to_bytes().unwrap()), false))
+      .expect("Storing action");
+
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
+    let virtual_dir = mount_dir
+      .path()
+      .join("actions")
+      .join(digest_to_filepath(&action_digest));
+    // Without an action cache, the outputs of the action are unknown.
+    assert_eq!(
+      vec!["action", "command", "input_root"],
+      file::list_dir(&virtual_dir)
+    );
+    assert_eq!(
+      test_bytes.bytes(),
+      file::contents(&virtual_dir.join("input_root").join("roland"))
+    );
+    let command_text = file::contents(&virtual_dir.join("command"));
+    assert!(String::from_utf8_lossy(&command_text).contains("\"/bin/cat\""));
+  }
+
+  fn new_remote_store<P: AsRef<std::path::Path>>(dir: P, cas_address: String) -> fs::Store {
+    fs::Store::with_remote(
+      dir,
+      &[cas_address],
+      &[],
+      None,
+      &None,
+      &fs::ProxyConfig::default(),
+      None,
+      1,
+      10 * 1024 * 1024,
+      Duration::from_secs(1),
+      BackoffConfig::new(Duration::from_millis(10), 1.0, Duration::from_millis(10)).unwrap(),
+      1,
+      TimerHandle::default(),
+    )
+    .expect("Error creating remote store")
+  }
+
   pub fn digest_to_filepath(digest: &hashing::Digest) -> String {
     format!("{}-{}", digest.0, digest.1)
   }
@@ -973,7 +1418,7 @@ mod syscall_tests {
       .block_on(store.store_file_bytes(test_bytes.bytes(), false))
       .expect("Storing bytes");
 
-    let _fs = mount(mount_dir.path(), store, runtime).expect("Mounting");
+    let _fs = mount(mount_dir.path(), store, None, runtime).expect("Mounting");
 
     let path = mount_dir
       .path()
@@ -26,6 +26,7 @@
 // Arc<Mutex> can be more clear than needing to grok Orderings:
 #![allow(clippy::mutex_atomic)]
 
+use bazel_protos;
 use clap;
 use env_logger;
 use fs;
@@ -39,14 +40,14 @@ use serde_json;
 use boxfuture::{try_future, BoxFuture, Boxable};
 use bytes::Bytes;
 use clap::{value_t, App, Arg, SubCommand};
-use fs::{GlobMatching, Snapshot, Store, StoreFileByDigest, UploadSummary};
+use fs::{GlobMatching, ProxyConfig, Snapshot, Store, StoreFileByDigest, UploadSummary};
 use futures::future::Future;
 use hashing::{Digest, Fingerprint};
 use parking_lot::Mutex;
 use protobuf::Message;
 use rand::seq::SliceRandom;
 use serde_derive::Serialize;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::sync::Arc;
@@ -163,6 +164,34 @@ to this directory.",
               .arg(Arg::with_name("size_bytes").required(true).takes_value(
                 true,
               )),
+          )
+          .subcommand(
+            SubCommand::with_name("cat-tree")
+              .about(
+                "Output the bytes of a serialized REAPI Tree proto containing the Directory \
+addressed by fingerprint and all of its child Directories.",
+              )
+              .arg(
+                Arg::with_name("output-format")
+                  .long("output-format")
+                  .takes_value(true)
+                  .default_value("binary")
+                  .possible_values(&["binary", "text"]),
+              )
+              .arg(Arg::with_name("fingerprint").required(true).takes_value(
+                true,
+              ))
+              .arg(Arg::with_name("size_bytes").required(true).takes_value(
+                true,
+              )),
+          )
+          .subcommand(
+            SubCommand::with_name("save-tree")
+              .about(
+                "Ingest the Directories of a serialized REAPI Tree proto read from stdin, verifying \
+that they are canonical. Outputs a fingerprint of the root Directory proto and the size of the \
+serialized proto in bytes, separated by a space.",
+              ),
           ),
       )
       .subcommand(
@@ -177,6 +206,24 @@ to this directory.",
             true,
           )),
       )
+        .subcommand(
+          SubCommand::with_name("provenance")
+              .about("Output the process execution provenance records (as written by pants' --process-execution-provenance-file) which mention the given fingerprint as their action, input or output digest, or all records if no fingerprint is given.")
+              .arg(Arg::with_name("file").required(true).takes_value(true))
+              .arg(Arg::with_name("fingerprint").required(false).takes_value(true))
+        )
+        .subcommand(
+          SubCommand::with_name("store")
+              .subcommand(
+                SubCommand::with_name("summary")
+                    .about("Output statistics about the blobs in the on-disk store: their count, total size, and histograms of their sizes and of when their leases expired, in total and per shard.")
+                    .arg(
+                      Arg::with_name("json")
+                          .long("json")
+                          .help("Output the summary as JSON.")
+                    )
+              )
+        )
         .subcommand(
           SubCommand::with_name("gc")
               .about("Garbage collect the on-disk store. Note that after running this command, any processes with an open store (e.g. a pantsd) may need to re-initialize their store.")
@@ -253,6 +300,14 @@ to this directory.",
 }
 
 fn execute(top_match: &clap::ArgMatches<'_>) -> Result<(), ExitError> {
+  // Provenance records are not stored in the Store, so there is no need to open it.
+  if let ("provenance", Some(args)) = top_match.subcommand() {
+    return print_provenance(
+      Path::new(args.value_of("file").unwrap()),
+      args.value_of("fingerprint"),
+    );
+  }
+
   let store_dir = top_match
     .value_of("local-store-path")
     .map(PathBuf::from)
@@ -290,10 +345,12 @@ fn execute(top_match: &clap::ArgMatches<'_>) -> Result<(), ExitError> {
           Store::with_remote(
             &store_dir,
             &cas_addresses,
+            &[],
             top_match
               .value_of("remote-instance-name")
               .map(str::to_owned),
             &root_ca_certs,
+            &ProxyConfig::from_env()?,
             oauth_bearer_token,
             value_t!(top_match.value_of("thread-count"), usize).expect("Invalid thread count"),
             chunk_size,
@@ -493,6 +550,44 @@ fn execute(top_match: &clap::ArgMatches<'_>) -> Result<(), ExitError> {
           )),
         }
       }
+      ("cat-tree", Some(args)) => {
+        let fingerprint = Fingerprint::from_hex_string(args.value_of("fingerprint").unwrap())?;
+        let size_bytes = args
+          .value_of("size_bytes")
+          .unwrap()
+          .parse::<usize>()
+          .expect("size_bytes must be a non-negative number");
+        let digest = Digest(fingerprint, size_bytes);
+        let tree = runtime.block_on(store.load_tree(digest))?;
+        let tree_bytes = match args.value_of("output-format").unwrap() {
+          "binary" => tree.map(|t| t.write_to_bytes().unwrap()),
+          "text" => tree.map(|t| format!("{:?}\n", t).as_bytes().to_vec()),
+          format => {
+            return Err(format!("Unexpected value of --output-format arg: {}", format).into())
+          }
+        };
+        match tree_bytes {
+          Some(bytes) => {
+            io::stdout().write_all(&bytes).unwrap();
+            Ok(())
+          }
+          None => Err(ExitError(
+            format!("Directory with digest {:?} not found", digest),
+            ExitCode::NotFound,
+          )),
+        }
+      }
+      ("save-tree", Some(_)) => {
+        let mut tree_bytes = Vec::new();
+        io::stdin()
+          .read_to_end(&mut tree_bytes)
+          .map_err(|e| format!("Error reading Tree proto from stdin: {}", e))?;
+        let tree = protobuf::parse_from_bytes::<bazel_protos::remote_execution::Tree>(&tree_bytes)
+          .map_err(|e| format!("Error parsing Tree proto: {:?}", e))?;
+        let digest = runtime.block_on(store.record_tree(&tree, true))?;
+        println!("{} {}", digest.0, digest.1);
+        Ok(())
+      }
       (_, _) => unimplemented!(),
     },
     ("cat", Some(args)) => {
@@ -529,6 +624,18 @@ fn execute(top_match: &clap::ArgMatches<'_>) -> Result<(), ExitError> {
         )),
       }
     }
+    ("store", Some(sub_match)) => match sub_match.subcommand() {
+      ("summary", Some(args)) => {
+        let summary = store.summary()?;
+        if args.is_present("json") {
+          println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+        } else {
+          print_summary(&summary);
+        }
+        Ok(())
+      }
+      (_, _) => unimplemented!(),
+    },
     ("gc", Some(args)) => {
       let target_size_bytes = value_t!(args.value_of("target-size-bytes"), usize)
         .expect("--target-size-bytes must be passed as a non-negative integer");
@@ -540,6 +647,45 @@ fn execute(top_match: &clap::ArgMatches<'_>) -> Result<(), ExitError> {
   }
 }
 
+fn print_summary(summary: &fs::StoreSummary) {
+  let print_buckets = |buckets: &[fs::Bucket]| {
+    for bucket in buckets {
+      println!("  {:>14}: {}", bucket.label, bucket.count);
+    }
+  };
+  println!("{} blobs, {} bytes", summary.blob_count, summary.total_bytes);
+  println!("Sizes:");
+  print_buckets(&summary.sizes);
+  println!("Leases:");
+  print_buckets(&summary.lease_ages);
+  println!("Shards:");
+  for shard in &summary.shards {
+    println!(
+      "  {:>9} {}: {} blobs, {} bytes",
+      shard.entry_type, shard.shard, shard.blob_count, shard.total_bytes
+    );
+  }
+}
+
+fn print_provenance(path: &Path, fingerprint: Option<&str>) -> Result<(), ExitError> {
+  let content = std::fs::read_to_string(path)
+    .map_err(|e| format!("Error reading provenance file {:?}: {}", path, e))?;
+  for line in content.lines().filter(|line| !line.trim().is_empty()) {
+    let entry: serde_json::Value = serde_json::from_str(line)
+      .map_err(|e| format!("Invalid provenance record {:?}: {}", line, e))?;
+    let matches = match fingerprint {
+      Some(fingerprint) => ["action_digest", "input_digest", "output_digest"]
+        .iter()
+        .any(|field| entry[field]["fingerprint"] == fingerprint),
+      None => true,
+    };
+    if matches {
+      println!("{}", serde_json::to_string_pretty(&entry).unwrap());
+    }
+  }
+  Ok(())
+}
+
 fn expand_files(store: Store, digest: Digest) -> Result<Option<Vec<(String, Digest)>>, String> {
   let files = Arc::new(Mutex::new(Vec::new()));
   expand_files_helper(store, digest, String::new(), files.clone())
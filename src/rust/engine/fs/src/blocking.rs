@@ -0,0 +1,136 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use async_semaphore::AsyncSemaphore;
+use boxfuture::{BoxFuture, Boxable};
+use futures_cpupool::{self, CpuPool};
+
+// The number of operations per thread which may be queued on a BlockingPool before callers must
+// wait to queue more.
+const QUEUED_OPERATIONS_PER_THREAD: usize = 4;
+
+///
+/// A bounded pool of threads on which to run blocking operations (such as LMDB transactions), so
+/// that they do not occupy the threads of the executor which drives the rest of the engine.
+///
+/// Operations wait (asynchronously) for a permit before being queued on the pool, so that a burst
+/// of operations applies backpressure to its callers rather than growing an unbounded queue.
+///
+#[derive(Clone)]
+pub struct BlockingPool {
+  pool: CpuPool,
+  permits: AsyncSemaphore,
+}
+
+impl BlockingPool {
+  pub fn new(name: &str, threads: usize) -> BlockingPool {
+    let threads = threads.max(1);
+    BlockingPool {
+      pool: futures_cpupool::Builder::new()
+        .name_prefix(format!("{}-", name))
+        .pool_size(threads)
+        .create(),
+      permits: AsyncSemaphore::new(threads * QUEUED_OPERATIONS_PER_THREAD),
+    }
+  }
+
+  ///
+  /// Runs the given blocking function on the pool, once there is room to queue it.
+  ///
+  pub fn run<F, T>(&self, f: F) -> BoxFuture<T, String>
+  where
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+  {
+    let pool = self.pool.clone();
+    self
+      .permits
+      .with_acquired(move || pool.spawn_fn(f))
+      .to_boxed()
+  }
+}
+
+///
+/// The pools on which blocking work is run, split by the resource which the work is bound by: IO
+/// (such as LMDB transactions) and CPU (such as hashing and parsing protos). The pools are sized
+/// independently, so that a burst of one kind of work does not starve the other.
+///
+#[derive(Clone)]
+pub struct Pools {
+  pub(crate) io: BlockingPool,
+  pub(crate) cpu: BlockingPool,
+}
+
+impl Pools {
+  pub fn new(io_threads: usize, cpu_threads: usize) -> Pools {
+    Pools {
+      io: BlockingPool::new("io", io_threads),
+      cpu: BlockingPool::new("cpu", cpu_threads),
+    }
+  }
+}
+
+impl Default for Pools {
+  fn default() -> Pools {
+    Pools::new(num_cpus::get(), num_cpus::get())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{mpsc, Arc, Mutex};
+  use std::thread;
+  use std::time::Duration;
+
+  use futures::{future, Future};
+
+  use super::{BlockingPool, Pools};
+
+  #[test]
+  fn runs_functions() {
+    let pool = BlockingPool::new("test", 2);
+    assert_eq!(pool.run(|| Ok(42)).wait(), Ok(42));
+    assert_eq!(
+      pool.run(|| Err::<(), _>("Failed".to_owned())).wait(),
+      Err("Failed".to_owned())
+    );
+  }
+
+  #[test]
+  fn bounds_concurrency() {
+    let pool = BlockingPool::new("test", 2);
+    // The number of operations which are currently running, and the most which ever ran at once.
+    let running = Arc::new(Mutex::new((0, 0)));
+    let operations = (0..16)
+      .map(|_| {
+        let running = running.clone();
+        pool.run(move || {
+          {
+            let mut running = running.lock().unwrap();
+            running.0 += 1;
+            running.1 = running.1.max(running.0);
+          }
+          thread::sleep(Duration::from_millis(10));
+          running.lock().unwrap().0 -= 1;
+          Ok(())
+        })
+      })
+      .collect::<Vec<_>>();
+    future::join_all(operations).wait().unwrap();
+    assert_eq!(*running.lock().unwrap(), (0, 2));
+  }
+
+  #[test]
+  fn pools_are_independent() {
+    let pools = Pools::new(1, 1);
+    let (sender, receiver) = mpsc::channel();
+    // Occupies the only IO thread until the CPU pool has run an operation.
+    let io = pools
+      .io
+      .run(move || receiver.recv().map_err(|e| format!("{}", e)));
+    let cpu = pools
+      .cpu
+      .run(move || sender.send(42).map_err(|e| format!("{}", e)));
+    assert_eq!(cpu.join(io).wait(), Ok(((), 42)));
+  }
+}
@@ -14,8 +14,8 @@ use log::warn;
 use parking_lot::Mutex;
 
 use crate::{
-  Dir, GitignoreStyleExcludes, GlobExpansionConjunction, Link, PathGlob, PathGlobs, PathStat, Stat,
-  VFS,
+  Dir, GitignoreStyleExcludes, GlobExpansionConjunction, Link, PathGlob, PathGlobs, PathStat,
+  SpecialFileBehavior, Stat, VFS,
 };
 
 pub trait GlobMatching<E: Display + Send + Sync + 'static>: VFS<E> {
@@ -52,6 +52,7 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
     symbolic_path: PathBuf,
     wildcard: Pattern,
     exclude: &Arc<GitignoreStyleExcludes>,
+    special_file_behavior: SpecialFileBehavior,
   ) -> BoxFuture<Vec<PathStat>, E> {
     // List the directory.
     let context = self.clone();
@@ -99,6 +100,19 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
                     f.clone(),
                   )))
                   .to_boxed(),
+                  Stat::Special(s) => {
+                    let msg = format!(
+                      "{:?} is a {}, which cannot be captured.",
+                      stat_symbolic_path, s.kind
+                    );
+                    match special_file_behavior {
+                      SpecialFileBehavior::Error => future::err(Self::mk_error(&msg)).to_boxed(),
+                      SpecialFileBehavior::Warn => {
+                        warn!("{} Skipping it.", msg);
+                        future::ok(None).to_boxed()
+                      }
+                    }
+                  }
                 }
               }
             })
@@ -118,6 +132,7 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
       exclude,
       strict_match_behavior,
       conjunction,
+      special_file_behavior,
     } = path_globs;
 
     if include.is_empty() {
@@ -132,7 +147,12 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
       let source = Arc::new(pgie.input);
       for path_glob in pgie.globs {
         sources.push(source.clone());
-        roots.push(self.expand_single(result.clone(), exclude.clone(), path_glob));
+        roots.push(self.expand_single(
+          result.clone(),
+          exclude.clone(),
+          special_file_behavior,
+          path_glob,
+        ));
       }
     }
 
@@ -205,6 +225,7 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
     &self,
     result: Arc<Mutex<Vec<PathStat>>>,
     exclude: Arc<GitignoreStyleExcludes>,
+    special_file_behavior: SpecialFileBehavior,
     path_glob: PathGlob,
   ) -> BoxFuture<bool, E> {
     match path_glob {
@@ -215,6 +236,7 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
       } => self.expand_wildcard(
         result.clone(),
         exclude.clone(),
+        special_file_behavior,
         canonical_dir,
         symbolic_path,
         wildcard,
@@ -227,6 +249,7 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
       } => self.expand_dir_wildcard(
         result.clone(),
         exclude.clone(),
+        special_file_behavior,
         canonical_dir,
         symbolic_path,
         wildcard,
@@ -239,13 +262,20 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
     &self,
     result: Arc<Mutex<Vec<PathStat>>>,
     exclude: Arc<GitignoreStyleExcludes>,
+    special_file_behavior: SpecialFileBehavior,
     canonical_dir: Dir,
     symbolic_path: PathBuf,
     wildcard: Pattern,
   ) -> BoxFuture<bool, E> {
     // Filter directory listing to append PathStats, with no continuation.
     self
-      .directory_listing(canonical_dir, symbolic_path, wildcard, &exclude)
+      .directory_listing(
+        canonical_dir,
+        symbolic_path,
+        wildcard,
+        &exclude,
+        special_file_behavior,
+      )
       .map(move |path_stats| {
         let mut result = result.lock();
         let matched = !path_stats.is_empty();
@@ -259,6 +289,7 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
     &self,
     result: Arc<Mutex<Vec<PathStat>>>,
     exclude: Arc<GitignoreStyleExcludes>,
+    special_file_behavior: SpecialFileBehavior,
     canonical_dir: Dir,
     symbolic_path: PathBuf,
     wildcard: Pattern,
@@ -267,7 +298,13 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
     // Filter directory listing and recurse for matched Dirs.
     let context = self.clone();
     self
-      .directory_listing(canonical_dir, symbolic_path, wildcard, &exclude)
+      .directory_listing(
+        canonical_dir,
+        symbolic_path,
+        wildcard,
+        &exclude,
+        special_file_behavior,
+      )
       .and_then(move |path_stats| {
         path_stats
           .into_iter()
@@ -283,7 +320,9 @@ trait GlobMatchingImplementation<E: Display + Send + Sync + 'static>: VFS<E> {
         let child_globs = path_globs
           .into_iter()
           .flat_map(Vec::into_iter)
-          .map(|pg| context.expand_single(result.clone(), exclude.clone(), pg))
+          .map(|pg| {
+            context.expand_single(result.clone(), exclude.clone(), special_file_behavior, pg)
+          })
           .collect::<Vec<_>>();
         future::join_all(child_globs)
           .map(|child_matches| child_matches.into_iter().any(|m| m))
@@ -26,21 +26,34 @@
 // Arc<Mutex> can be more clear than needing to grok Orderings:
 #![allow(clippy::mutex_atomic)]
 
+mod blocking;
+pub use crate::blocking::Pools;
 mod glob_matching;
 pub use crate::glob_matching::GlobMatching;
+mod normalization;
+pub use crate::normalization::UnicodeNormalization;
+mod proxy;
+pub use crate::proxy::ProxyConfig;
 mod snapshot;
 pub use crate::snapshot::{
-  OneOffStoreFileByDigest, Snapshot, StoreFileByDigest, EMPTY_DIGEST, EMPTY_FINGERPRINT,
+  OneOffStoreFileByDigest, RemoteOnlyStoreFileByDigest, Snapshot, SnapshotSizeLimits,
+  StoreFileByDigest, EMPTY_DIGEST, EMPTY_FINGERPRINT,
 };
 mod store;
-pub use crate::store::{ShrinkBehavior, Store, UploadSummary, DEFAULT_LOCAL_STORE_GC_TARGET_BYTES};
+pub use crate::store::{
+  Bucket, ConflictPolicy, RemoteStoreTimeouts, ShardSummary, ShrinkBehavior, Store, StoreSummary,
+  UploadScope, UploadSummary, WorkspaceManifest, DEFAULT_LOCAL_STORE_GC_TARGET_BYTES,
+};
+mod store_lock;
+pub use crate::store_lock::LOCAL_STORE_VERSION;
+mod transfer;
+pub use crate::transfer::{TransferLimits, TransferStats};
 
 pub use serverset::BackoffConfig;
 
 use std::cmp::min;
 use std::ffi::OsStr;
 use std::io;
-use std::os::unix::fs::PermissionsExt;
 use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
 use std::{fmt, fs};
@@ -53,11 +66,24 @@ use futures::Stream;
 use glob::{MatchOptions, Pattern};
 use lazy_static::lazy_static;
 
+///
+/// The error for an operation which needs the network, when the engine is running in offline mode.
+/// Network operations fail immediately with this error rather than waiting for a connection (or
+/// a timeout) which would never succeed.
+///
+pub fn offline_error(operation: &str) -> String {
+  format!(
+    "Cannot {} in offline mode. Run without --offline to allow network access.",
+    operation
+  )
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Stat {
   Link(Link),
   Dir(Dir),
   File(File),
+  Special(Special),
 }
 
 impl Stat {
@@ -66,6 +92,7 @@ impl Stat {
       &Stat::Dir(Dir(ref p)) => p.as_path(),
       &Stat::File(File { path: ref p, .. }) => p.as_path(),
       &Stat::Link(Link(ref p)) => p.as_path(),
+      &Stat::Special(Special { path: ref p, .. }) => p.as_path(),
     }
   }
 
@@ -93,6 +120,36 @@ pub struct File {
   pub is_executable: bool,
 }
 
+///
+/// A file which is neither a regular file, a directory nor a symlink, and which therefore cannot
+/// be captured.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Special {
+  pub path: PathBuf,
+  pub kind: SpecialKind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SpecialKind {
+  Fifo,
+  Socket,
+  BlockDevice,
+  CharDevice,
+}
+
+impl fmt::Display for SpecialKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      SpecialKind::Fifo => "FIFO",
+      SpecialKind::Socket => "socket",
+      SpecialKind::BlockDevice => "block device",
+      SpecialKind::CharDevice => "character device",
+    };
+    write!(f, "{}", name)
+  }
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum PathStat {
   Dir {
@@ -441,6 +498,27 @@ impl StrictGlobMatching {
   }
 }
 
+///
+/// How glob expansion treats Special files (FIFOs, sockets and device nodes) which match a glob.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpecialFileBehavior {
+  // Skip the file, with a warning naming it.
+  Warn,
+  // Fail the expansion with an error naming the file.
+  Error,
+}
+
+impl SpecialFileBehavior {
+  pub fn create(behavior: &str) -> Result<Self, String> {
+    match behavior {
+      "warn" => Ok(SpecialFileBehavior::Warn),
+      "error" => Ok(SpecialFileBehavior::Error),
+      _ => Err(format!("Unrecognized special file behavior: {}.", behavior)),
+    }
+  }
+}
+
 #[derive(Debug)]
 pub enum GlobExpansionConjunction {
   AllMatch,
@@ -463,6 +541,7 @@ pub struct PathGlobs {
   exclude: Arc<GitignoreStyleExcludes>,
   strict_match_behavior: StrictGlobMatching,
   conjunction: GlobExpansionConjunction,
+  special_file_behavior: SpecialFileBehavior,
 }
 
 impl PathGlobs {
@@ -488,9 +567,21 @@ impl PathGlobs {
       exclude: gitignore_excludes,
       strict_match_behavior,
       conjunction,
+      special_file_behavior: SpecialFileBehavior::Warn,
     })
   }
 
+  ///
+  /// Returns these PathGlobs with the given treatment of matched Special files, which are skipped
+  /// with a warning by default.
+  ///
+  pub fn with_special_file_behavior(self, special_file_behavior: SpecialFileBehavior) -> PathGlobs {
+    PathGlobs {
+      special_file_behavior,
+      ..self
+    }
+  }
+
   pub fn from_globs(include: Vec<PathGlob>) -> Result<PathGlobs, String> {
     let include = include
       .into_iter()
@@ -557,6 +648,7 @@ pub struct GlobWithSource {
 pub struct PosixFS {
   root: Dir,
   ignore: Arc<GitignoreStyleExcludes>,
+  case_insensitive: bool,
 }
 
 impl PosixFS {
@@ -584,12 +676,22 @@ impl PosixFS {
         ignore_patterns, e
       )
     })?;
+    let case_insensitive = is_case_insensitive(&canonical_root.0);
     Ok(PosixFS {
       root: canonical_root,
       ignore: ignore,
+      case_insensitive,
     })
   }
 
+  ///
+  /// True if the root of this PosixFS is on a case-insensitive filesystem (as macOS uses by
+  /// default), on which two paths which differ only in case refer to the same file.
+  ///
+  pub fn is_case_insensitive(&self) -> bool {
+    self.case_insensitive
+  }
+
   pub fn scandir(
     &self,
     dir_relative_to_root: Dir,
@@ -628,6 +730,16 @@ impl PosixFS {
       })
   }
 
+  ///
+  /// The metadata of the given file, without reading its content.
+  ///
+  pub fn file_metadata(
+    &self,
+    file: &File,
+  ) -> impl Future<Item = std::fs::Metadata, Error = io::Error> {
+    tokio_fs::metadata(self.root.0.join(&file.path))
+  }
+
   pub fn read_link(&self, link: &Link) -> impl Future<Item = PathBuf, Error = io::Error> {
     let link_parent = link.0.parent().map(Path::to_owned);
     let link_abs = self.root.0.join(link.0.as_path()).to_owned();
@@ -681,13 +793,18 @@ impl PosixFS {
     if file_type.is_symlink() {
       Ok(Stat::Link(Link(path_for_stat)))
     } else if file_type.is_file() {
-      let is_executable = metadata.permissions().mode() & 0o100 == 0o100;
+      let is_executable = is_executable(&path_for_stat, metadata);
       Ok(Stat::File(File {
         path: path_for_stat,
         is_executable: is_executable,
       }))
     } else if file_type.is_dir() {
       Ok(Stat::Dir(Dir(path_for_stat)))
+    } else if let Some(kind) = special_kind(file_type) {
+      Ok(Stat::Special(Special {
+        path: path_for_stat,
+        kind,
+      }))
     } else {
       Err(io::Error::new(
         io::ErrorKind::InvalidData,
@@ -706,6 +823,76 @@ impl PosixFS {
   }
 }
 
+///
+/// Detects whether the given canonical path is on a case-insensitive filesystem, by checking
+/// whether the nearest path component with a cased name may also be reached with its case swapped.
+///
+fn is_case_insensitive(canonical_path: &Path) -> bool {
+  for path in canonical_path.ancestors() {
+    let name = match path.file_name().and_then(OsStr::to_str) {
+      Some(name) => name,
+      None => continue,
+    };
+    let swapped: String = name
+      .chars()
+      .flat_map(|c| {
+        if c.is_lowercase() {
+          c.to_uppercase().collect::<Vec<_>>()
+        } else {
+          c.to_lowercase().collect::<Vec<_>>()
+        }
+      })
+      .collect();
+    if swapped == name {
+      continue;
+    }
+    return match (
+      fs::metadata(path),
+      fs::metadata(path.with_file_name(swapped)),
+    ) {
+      (Ok(original), Ok(swapped)) => is_same_file(&original, &swapped),
+      _ => false,
+    };
+  }
+  false
+}
+
+#[cfg(unix)]
+fn is_same_file(original: &fs::Metadata, other: &fs::Metadata) -> bool {
+  use std::os::unix::fs::MetadataExt;
+  original.dev() == other.dev() && original.ino() == other.ino()
+}
+
+// The volume and index which identify a file on Windows are not exposed by stable Rust, but two
+// paths which differ only by case can only name distinct files in a case-sensitive directory, which
+// Windows does not create by default.
+#[cfg(windows)]
+fn is_same_file(_original: &fs::Metadata, _other: &fs::Metadata) -> bool {
+  true
+}
+
+#[cfg(unix)]
+fn special_kind(file_type: fs::FileType) -> Option<SpecialKind> {
+  use std::os::unix::fs::FileTypeExt;
+  if file_type.is_fifo() {
+    Some(SpecialKind::Fifo)
+  } else if file_type.is_socket() {
+    Some(SpecialKind::Socket)
+  } else if file_type.is_block_device() {
+    Some(SpecialKind::BlockDevice)
+  } else if file_type.is_char_device() {
+    Some(SpecialKind::CharDevice)
+  } else {
+    None
+  }
+}
+
+// Windows has no FIFOs, sockets or device files which may be found in a filesystem tree.
+#[cfg(windows)]
+fn special_kind(_file_type: fs::FileType) -> Option<SpecialKind> {
+  None
+}
+
 impl VFS<io::Error> for Arc<PosixFS> {
   fn read_link(&self, link: &Link) -> BoxFuture<PathBuf, io::Error> {
     PosixFS::read_link(self, link).to_boxed()
@@ -754,6 +941,8 @@ impl PathStatGetter<io::Error> for Arc<PosixFS> {
                 Some(Stat::File(file)) => {
                   future::ok(Some(PathStat::file(file.path.clone(), file))).to_boxed()
                 }
+                // Special files cannot be captured, so are treated as though they don't exist.
+                Some(Stat::Special(_)) => future::ok(None).to_boxed(),
                 None => future::ok(None).to_boxed(),
               }
             })
@@ -798,6 +987,28 @@ impl fmt::Debug for FileContent {
   }
 }
 
+#[cfg(unix)]
+fn is_executable(_path: &Path, metadata: &fs::Metadata) -> bool {
+  use std::os::unix::fs::PermissionsExt;
+  metadata.permissions().mode() & 0o100 == 0o100
+}
+
+// Windows has no executable bit, so it is emulated using the extensions which Windows itself
+// will execute.
+#[cfg(windows)]
+fn is_executable(path: &Path, _metadata: &fs::Metadata) -> bool {
+  const EXECUTABLE_EXTENSIONS: &[&str] = &["bat", "cmd", "com", "exe"];
+  path
+    .extension()
+    .and_then(OsStr::to_str)
+    .map(|extension| {
+      EXECUTABLE_EXTENSIONS
+        .iter()
+        .any(|executable| executable.eq_ignore_ascii_case(extension))
+    })
+    .unwrap_or(false)
+}
+
 // Like std::fs::create_dir_all, except handles concurrent calls among multiple
 // threads or processes. Originally lifted from rustc.
 pub fn safe_create_dir_all_ioerror(path: &Path) -> Result<(), io::Error> {
@@ -805,6 +1016,8 @@ pub fn safe_create_dir_all_ioerror(path: &Path) -> Result<(), io::Error> {
     Ok(()) => return Ok(()),
     Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(()),
     Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+    // Windows reports PermissionDenied (rather than AlreadyExists) for the root of a drive.
+    Err(_) if path.is_dir() => return Ok(()),
     Err(e) => return Err(e),
   }
   match path.parent() {
@@ -830,7 +1043,8 @@ mod posixfs_test {
 
   use super::{
     Dir, DirectoryListing, File, GlobExpansionConjunction, GlobMatching, Link, PathGlobs, PathStat,
-    PathStatGetter, PosixFS, Stat, StrictGlobMatching, VFS,
+    PathStatGetter, PosixFS, Special, SpecialFileBehavior, SpecialKind, Stat, StrictGlobMatching,
+    VFS,
   };
   use boxfuture::{BoxFuture, Boxable};
   use futures::future::{self, Future};
@@ -840,6 +1054,7 @@ mod posixfs_test {
   use std::sync::Arc;
   use testutil::make_file;
 
+  #[cfg(unix)]
   #[test]
   fn is_executable_false() {
     let dir = tempfile::TempDir::new().unwrap();
@@ -847,6 +1062,7 @@ mod posixfs_test {
     assert_only_file_is_executable(dir.path(), false);
   }
 
+  #[cfg(unix)]
   #[test]
   fn is_executable_true() {
     let dir = tempfile::TempDir::new().unwrap();
@@ -854,6 +1070,26 @@ mod posixfs_test {
     assert_only_file_is_executable(dir.path(), true);
   }
 
+  #[cfg(windows)]
+  #[test]
+  fn is_executable_by_extension() {
+    let dir = tempfile::TempDir::new().unwrap();
+    make_file(&dir.path().join("marmosets.txt"), &[], 0o600);
+    make_file(&dir.path().join("photograph_marmosets.EXE"), &[], 0o600);
+    let posix_fs = new_posixfs(&dir.path());
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    for (path, is_executable) in &[("marmosets.txt", false), ("photograph_marmosets.EXE", true)] {
+      let path = PathBuf::from(path);
+      assert_eq!(
+        runtime.block_on(posix_fs.stat(path.clone())).unwrap(),
+        super::Stat::File(File {
+          path: path,
+          is_executable: *is_executable,
+        })
+      );
+    }
+  }
+
   #[test]
   fn read_file() {
     let dir = tempfile::TempDir::new().unwrap();
@@ -888,6 +1124,7 @@ mod posixfs_test {
       .expect_err("Expected error");
   }
 
+  #[cfg(unix)]
   #[test]
   fn stat_executable_file() {
     let dir = tempfile::TempDir::new().unwrap();
@@ -933,6 +1170,7 @@ mod posixfs_test {
     )
   }
 
+  #[cfg(unix)]
   #[test]
   fn stat_symlink() {
     let dir = tempfile::TempDir::new().unwrap();
@@ -949,12 +1187,69 @@ mod posixfs_test {
     )
   }
 
+  #[cfg(unix)]
   #[test]
-  fn stat_other() {
+  fn stat_special() {
     let mut runtime = tokio::runtime::Runtime::new().unwrap();
-    runtime
-      .block_on(new_posixfs("/dev").stat(PathBuf::from("null")))
-      .expect_err("Want error");
+    assert_eq!(
+      runtime
+        .block_on(new_posixfs("/dev").stat(PathBuf::from("null")))
+        .unwrap(),
+      super::Stat::Special(Special {
+        path: PathBuf::from("null"),
+        kind: SpecialKind::CharDevice,
+      })
+    );
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn expand_special_files() {
+    use std::os::unix::net::UnixListener;
+
+    let dir = tempfile::TempDir::new().unwrap();
+    make_file(&dir.path().join("roland"), &[], 0o600);
+    let _listener = UnixListener::bind(dir.path().join("catflap")).unwrap();
+    let posix_fs = Arc::new(new_posixfs(&dir.path()));
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let path_globs = || {
+      PathGlobs::create(
+        &["*".into()],
+        &[],
+        StrictGlobMatching::Ignore,
+        GlobExpansionConjunction::AnyMatch,
+      )
+      .unwrap()
+    };
+
+    assert_eq!(
+      runtime.block_on(posix_fs.expand(path_globs())).unwrap(),
+      vec![PathStat::file(
+        PathBuf::from("roland"),
+        File {
+          path: PathBuf::from("roland"),
+          is_executable: false,
+        },
+      )],
+    );
+
+    let error = runtime
+      .block_on(
+        posix_fs.expand(path_globs().with_special_file_behavior(SpecialFileBehavior::Error)),
+      )
+      .expect_err("Want error")
+      .to_string();
+    assert_eq!(error, "\"catflap\" is a socket, which cannot be captured.");
+  }
+
+  // Unlike on macOS, temporary directories on Linux are case-sensitive.
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn case_sensitive_tempdir() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let lowercase = dir.path().join("roland");
+    std::fs::create_dir(&lowercase).unwrap();
+    assert!(!new_posixfs(&lowercase).is_case_insensitive());
   }
 
   #[test]
@@ -980,6 +1275,7 @@ mod posixfs_test {
     );
   }
 
+  #[cfg(unix)]
   #[test]
   fn scandir() {
     let dir = tempfile::TempDir::new().unwrap();
@@ -1043,6 +1339,7 @@ mod posixfs_test {
       .expect_err("Want error");
   }
 
+  #[cfg(unix)]
   #[test]
   fn path_stats_for_paths() {
     let dir = tempfile::TempDir::new().unwrap();
@@ -1155,6 +1452,7 @@ mod posixfs_test {
     );
   }
 
+  #[cfg(unix)]
   fn assert_only_file_is_executable(path: &Path, want_is_executable: bool) {
     let fs = new_posixfs(path);
     let mut runtime = tokio::runtime::Runtime::new().unwrap();
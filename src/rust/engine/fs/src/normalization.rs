@@ -0,0 +1,175 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use unicode_normalization::UnicodeNormalization as _;
+
+use crate::PathStat;
+
+///
+/// The unicode normalization form applied to the names of files and directories when they are
+/// captured into (or materialized from) Directory protos.
+///
+/// The same logical name may be stored in different forms by different filesystems (HFS+ on macOS
+/// decomposes names to NFD, while most Linux filesystems store names as they were given, which is
+/// usually NFC), and a Directory proto's digest depends on the exact bytes of its names. Normalizing
+/// names to one form allows digests to match across platforms.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnicodeNormalization {
+  // Record names exactly as they are stored by the filesystem.
+  Preserve,
+  // Normalization Form C (canonical composition).
+  Nfc,
+  // Normalization Form D (canonical decomposition).
+  Nfd,
+}
+
+impl Default for UnicodeNormalization {
+  fn default() -> UnicodeNormalization {
+    UnicodeNormalization::Preserve
+  }
+}
+
+impl UnicodeNormalization {
+  pub fn create(policy: &str) -> Result<UnicodeNormalization, String> {
+    match policy {
+      "preserve" => Ok(UnicodeNormalization::Preserve),
+      "nfc" => Ok(UnicodeNormalization::Nfc),
+      "nfd" => Ok(UnicodeNormalization::Nfd),
+      _ => Err(format!("Unrecognized unicode normalization: {}.", policy)),
+    }
+  }
+
+  pub fn normalize_name(self, name: &str) -> String {
+    match self {
+      UnicodeNormalization::Preserve => name.to_owned(),
+      UnicodeNormalization::Nfc => name.nfc().collect(),
+      UnicodeNormalization::Nfd => name.nfd().collect(),
+    }
+  }
+
+  ///
+  /// Normalizes the given path. Paths which are not valid unicode are returned unchanged.
+  ///
+  pub fn normalize_path(self, path: &Path) -> PathBuf {
+    match (self, path.to_str()) {
+      (UnicodeNormalization::Preserve, _) | (_, None) => path.to_owned(),
+      (_, Some(path_str)) => PathBuf::from(self.normalize_name(path_str)),
+    }
+  }
+
+  ///
+  /// Normalizes the symbolic paths of the given PathStats, leaving the underlying Stats (which are
+  /// used to read them from the filesystem) unchanged.
+  ///
+  /// Fails with an error naming both paths if two distinct paths would be normalized to the same
+  /// path, since they could not both be recorded.
+  ///
+  pub fn normalize_path_stats(self, path_stats: Vec<PathStat>) -> Result<Vec<PathStat>, String> {
+    if self == UnicodeNormalization::Preserve {
+      return Ok(path_stats);
+    }
+    let mut originals: HashMap<PathBuf, PathBuf> = HashMap::new();
+    path_stats
+      .into_iter()
+      .map(|path_stat| {
+        let original = path_stat.path().to_owned();
+        let normalized = self.normalize_path(&original);
+        if let Some(existing) = originals.get(&normalized) {
+          if *existing != original {
+            return Err(format!(
+              "Paths {:?} and {:?} are both {:?} once normalized to {:?}, so cannot both be \
+               captured. Rename one of them.",
+              existing, original, normalized, self
+            ));
+          }
+        }
+        originals.insert(normalized.clone(), original);
+        Ok(match path_stat {
+          PathStat::Dir { stat, .. } => PathStat::dir(normalized, stat),
+          PathStat::File { stat, .. } => PathStat::file(normalized, stat),
+        })
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::{Path, PathBuf};
+
+  use super::UnicodeNormalization;
+  use crate::{File, PathStat};
+
+  // "café", with the é as one composed character (NFC), and as an e and a combining acute accent
+  // (NFD).
+  const CAFE_NFC: &str = "caf\u{e9}";
+  const CAFE_NFD: &str = "cafe\u{301}";
+
+  fn file(path: &str) -> PathStat {
+    PathStat::file(
+      PathBuf::from(path),
+      File {
+        path: PathBuf::from(path),
+        is_executable: false,
+      },
+    )
+  }
+
+  #[test]
+  fn normalize_path() {
+    let nfd_path = Path::new("menus").join(CAFE_NFD);
+    let nfc_path = Path::new("menus").join(CAFE_NFC);
+    assert_eq!(
+      UnicodeNormalization::Nfc.normalize_path(&nfd_path),
+      nfc_path
+    );
+    assert_eq!(
+      UnicodeNormalization::Nfd.normalize_path(&nfc_path),
+      nfd_path
+    );
+    assert_eq!(
+      UnicodeNormalization::Preserve.normalize_path(&nfd_path),
+      nfd_path
+    );
+  }
+
+  #[test]
+  fn normalize_path_stats_preserves_stats() {
+    let normalized = UnicodeNormalization::Nfc
+      .normalize_path_stats(vec![file(CAFE_NFD)])
+      .unwrap();
+    assert_eq!(
+      normalized,
+      vec![PathStat::file(
+        PathBuf::from(CAFE_NFC),
+        File {
+          path: PathBuf::from(CAFE_NFD),
+          is_executable: false,
+        },
+      )]
+    );
+  }
+
+  #[test]
+  fn normalize_path_stats_collision() {
+    let error = UnicodeNormalization::Nfc
+      .normalize_path_stats(vec![file(CAFE_NFC), file(CAFE_NFD)])
+      .unwrap_err();
+    assert!(
+      error.contains("once normalized to Nfc"),
+      "Unexpected error: {}",
+      error
+    );
+    assert_eq!(
+      UnicodeNormalization::Preserve
+        .normalize_path_stats(vec![file(CAFE_NFC), file(CAFE_NFD)])
+        .unwrap()
+        .len(),
+      2
+    );
+  }
+}
@@ -0,0 +1,273 @@
+use grpcio;
+use std::ffi::CString;
+
+///
+/// The HTTP proxies which network clients connect via, which are configured by the conventional
+/// `http_proxy`, `https_proxy` and `no_proxy` environment variables (in either case), or by
+/// explicit options which override them.
+///
+/// Connections to https URLs and to gRPC servers are tunneled through their proxy with HTTP
+/// CONNECT, so TLS is still negotiated end-to-end with the server.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ProxyConfig {
+  http_proxy: Option<String>,
+  https_proxy: Option<String>,
+  // Hosts which are connected to directly, each of which also matches its subdomains. A `*` matches
+  // every host.
+  no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+  pub fn new(
+    http_proxy: Option<String>,
+    https_proxy: Option<String>,
+    no_proxy: Vec<String>,
+  ) -> Result<ProxyConfig, String> {
+    Ok(ProxyConfig {
+      http_proxy: normalize_proxy("http_proxy", http_proxy)?,
+      https_proxy: normalize_proxy("https_proxy", https_proxy)?,
+      no_proxy: no_proxy
+        .iter()
+        .map(|host| host.trim().to_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect(),
+    })
+  }
+
+  ///
+  /// Reads the proxies from the environment, preferring the lowercase name of each variable.
+  ///
+  pub fn from_env() -> Result<ProxyConfig, String> {
+    ProxyConfig::from_vars(|name| std::env::var(name).ok())
+  }
+
+  fn from_vars<F: Fn(&str) -> Option<String>>(var: F) -> Result<ProxyConfig, String> {
+    let env_var = |name: &str| {
+      var(name)
+        .filter(|value| !value.is_empty())
+        .or_else(|| var(&name.to_uppercase()))
+        .filter(|value| !value.is_empty())
+    };
+    ProxyConfig::new(
+      env_var("http_proxy"),
+      env_var("https_proxy"),
+      env_var("no_proxy")
+        .map(|hosts| hosts.split(',').map(str::to_owned).collect())
+        .unwrap_or_default(),
+    )
+  }
+
+  ///
+  /// Replaces each part of this config for which an explicit value is given.
+  ///
+  pub fn with_overrides(
+    self,
+    http_proxy: Option<String>,
+    https_proxy: Option<String>,
+    no_proxy: Option<Vec<String>>,
+  ) -> Result<ProxyConfig, String> {
+    ProxyConfig::new(
+      http_proxy.or(self.http_proxy),
+      https_proxy.or(self.https_proxy),
+      no_proxy.unwrap_or(self.no_proxy),
+    )
+  }
+
+  ///
+  /// The URL of the proxy (if any) via which to connect to the given host, for either a secure or an
+  /// insecure connection.
+  ///
+  pub fn proxy_for(&self, secure: bool, host: &str) -> Option<&str> {
+    let proxy = if secure {
+      self.https_proxy.as_ref()
+    } else {
+      self.http_proxy.as_ref()
+    };
+    proxy.filter(|_| !self.bypasses(host)).map(String::as_str)
+  }
+
+  ///
+  /// The URLs of all of the configured proxies.
+  ///
+  pub fn urls(&self) -> impl Iterator<Item = &str> {
+    self
+      .http_proxy
+      .iter()
+      .chain(self.https_proxy.iter())
+      .map(String::as_str)
+  }
+
+  fn bypasses(&self, host: &str) -> bool {
+    let host = host.to_lowercase();
+    self.no_proxy.iter().any(|entry| {
+      if entry == "*" {
+        return true;
+      }
+      let domain = strip_port(entry.trim_start_matches('*').trim_start_matches('.'));
+      host == domain || host.ends_with(&format!(".{}", domain))
+    })
+  }
+
+  ///
+  /// Configures a gRPC channel to the given `host:port` address to connect via its proxy, if it has
+  /// one. Otherwise, the channel connects directly: gRPC is prevented from consulting the
+  /// environment itself, which would ignore explicitly configured options.
+  ///
+  pub fn configure_channel(
+    &self,
+    builder: grpcio::ChannelBuilder,
+    address: &str,
+    secure: bool,
+  ) -> grpcio::ChannelBuilder {
+    match self.proxy_for(secure, strip_port(address)) {
+      Some(proxy) => builder.raw_cfg_string(
+        CString::new("grpc.http_proxy").unwrap(),
+        CString::new(proxy).unwrap(),
+      ),
+      None => builder.raw_cfg_int(CString::new("grpc.enable_http_proxy").unwrap(), 0),
+    }
+  }
+}
+
+///
+/// Validates a proxy URL, which is assumed to be an `http://` URL if it does not have a scheme.
+///
+fn normalize_proxy(name: &str, proxy: Option<String>) -> Result<Option<String>, String> {
+  let proxy = match proxy.as_ref().map(|proxy| proxy.trim()) {
+    Some(proxy) if !proxy.is_empty() => proxy,
+    _ => return Ok(None),
+  };
+  if proxy.contains('\0') {
+    return Err(format!(
+      "Invalid {} {:?}: contains a NUL byte.",
+      name, proxy
+    ));
+  }
+  match proxy.find("://") {
+    None => Ok(Some(format!("http://{}", proxy))),
+    Some(i) if proxy[..i].eq_ignore_ascii_case("http") => Ok(Some(proxy.to_owned())),
+    Some(i) => Err(format!(
+      "Invalid {} {:?}: the {} scheme is not supported; proxies must be http:// URLs, which are \
+       tunneled through for https connections.",
+      name,
+      proxy,
+      &proxy[..i]
+    )),
+  }
+}
+
+///
+/// Strips the port (if any) from a `host:port` address, including a bracketed IPv6 address.
+///
+fn strip_port(address: &str) -> &str {
+  if address.starts_with('[') {
+    return address[1..].split(']').next().unwrap_or("");
+  }
+  match address.rfind(':') {
+    // More than one colon indicates an unbracketed IPv6 address, which has no port.
+    Some(i) if !address[..i].contains(':') => &address[..i],
+    _ => address,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ProxyConfig;
+  use std::collections::HashMap;
+
+  fn from_vars(vars: &[(&str, &str)]) -> Result<ProxyConfig, String> {
+    let vars = vars
+      .iter()
+      .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+      .collect::<HashMap<_, _>>();
+    ProxyConfig::from_vars(|name| vars.get(name).cloned())
+  }
+
+  #[test]
+  fn proxies_from_env_prefer_lowercase() {
+    let proxies = from_vars(&[
+      ("http_proxy", "proxy.corp:3128"),
+      ("HTTP_PROXY", "other.corp:3128"),
+      ("HTTPS_PROXY", "http://secure.corp:8080"),
+    ])
+    .unwrap();
+    assert_eq!(
+      proxies.proxy_for(false, "example.com"),
+      Some("http://proxy.corp:3128")
+    );
+    assert_eq!(
+      proxies.proxy_for(true, "example.com"),
+      Some("http://secure.corp:8080")
+    );
+  }
+
+  #[test]
+  fn no_proxy_matches_hosts_and_their_subdomains() {
+    let proxies = from_vars(&[
+      ("https_proxy", "http://proxy.corp:3128"),
+      ("no_proxy", "localhost, .internal.corp,cas.example.com:443"),
+    ])
+    .unwrap();
+    for host in &[
+      "localhost",
+      "internal.corp",
+      "cas.internal.corp",
+      "CAS.example.com",
+    ] {
+      assert_eq!(proxies.proxy_for(true, host), None, "{}", host);
+    }
+    for host in &["example.com", "notinternal.corp", "localhost.example.com"] {
+      assert_eq!(
+        proxies.proxy_for(true, host),
+        Some("http://proxy.corp:3128"),
+        "{}",
+        host
+      );
+    }
+  }
+
+  #[test]
+  fn no_proxy_wildcard_matches_everything() {
+    let proxies = from_vars(&[("http_proxy", "proxy.corp:3128"), ("NO_PROXY", "*")]).unwrap();
+    assert_eq!(proxies.proxy_for(false, "example.com"), None);
+  }
+
+  #[test]
+  fn overrides_replace_env() {
+    let proxies = from_vars(&[
+      ("http_proxy", "proxy.corp:3128"),
+      ("https_proxy", "proxy.corp:3128"),
+      ("no_proxy", "example.com"),
+    ])
+    .unwrap()
+    .with_overrides(None, Some("override.corp:8080".to_owned()), Some(vec![]))
+    .unwrap();
+    assert_eq!(
+      proxies.proxy_for(false, "example.com"),
+      Some("http://proxy.corp:3128")
+    );
+    assert_eq!(
+      proxies.proxy_for(true, "example.com"),
+      Some("http://override.corp:8080")
+    );
+  }
+
+  #[test]
+  fn unsupported_proxy_scheme_is_error() {
+    let error = from_vars(&[("https_proxy", "socks5://proxy.corp:1080")]).expect_err("Want error");
+    assert!(
+      error.contains("socks5"),
+      "Bad error message, got: {}",
+      error
+    );
+  }
+
+  #[test]
+  fn strip_port() {
+    assert_eq!(super::strip_port("cas.example.com:443"), "cas.example.com");
+    assert_eq!(super::strip_port("cas.example.com"), "cas.example.com");
+    assert_eq!(super::strip_port("[::1]:443"), "::1");
+    assert_eq!(super::strip_port("::1"), "::1");
+  }
+}
@@ -5,17 +5,21 @@ use crate::glob_matching::GlobMatching;
 use crate::{Dir, File, PathGlobs, PathStat, PosixFS, Store};
 use bazel_protos;
 use boxfuture::{try_future, BoxFuture, Boxable};
+use bytes::Bytes;
 use futures::future::{self, join_all};
 use futures::Future;
 use hashing::{Digest, Fingerprint};
 use indexmap::{self, IndexMap};
 use itertools::Itertools;
 use protobuf;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
 use std::fmt;
+use std::io::Cursor;
 use std::iter::Iterator;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
+use tar_api::{ArchiveEntry, ArchiveFormat};
 
 pub const EMPTY_FINGERPRINT: Fingerprint = Fingerprint([
   0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f, 0xb9, 0x24,
@@ -43,8 +47,11 @@ impl Snapshot {
   >(
     store: Store,
     file_digester: &S,
-    mut path_stats: Vec<PathStat>,
+    path_stats: Vec<PathStat>,
   ) -> BoxFuture<Snapshot, String> {
+    let mut path_stats = try_future!(store
+      .unicode_normalization()
+      .normalize_path_stats(path_stats));
     path_stats.sort_by(|a, b| a.path().cmp(b.path()));
 
     // The helper assumes that if a Path has multiple children, it must be a directory.
@@ -64,6 +71,46 @@ impl Snapshot {
       .to_boxed()
   }
 
+  ///
+  /// Checks that no two of the given PathStats (or their parent directories) have paths which
+  /// differ only in case.
+  ///
+  /// On a case-insensitive filesystem such paths refer to the same file, but would be recorded as
+  /// distinct entries in a Snapshot, and would then collide when materialized. So captures from a
+  /// case-insensitive filesystem fail with an error naming both paths rather than depending on
+  /// which of them happened to be matched.
+  ///
+  pub fn check_case_collisions(path_stats: &[PathStat]) -> Result<(), String> {
+    let mut seen: HashMap<String, &Path> = HashMap::new();
+    for path_stat in path_stats {
+      for path in path_stat.path().ancestors() {
+        if path.as_os_str().is_empty() {
+          continue;
+        }
+        let folded = path.to_string_lossy().to_lowercase();
+        match seen.get(&folded) {
+          Some(existing) if *existing != path => {
+            let (first, second) = if *existing < path {
+              (*existing, path)
+            } else {
+              (path, *existing)
+            };
+            return Err(format!(
+              "Paths {:?} and {:?} differ only in case, so refer to the same file on this \
+               case-insensitive filesystem. Change the globs so that only one of them is matched.",
+              first, second
+            ));
+          }
+          Some(_) => break,
+          None => {
+            seen.insert(folded, path);
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
   pub fn from_digest(store: Store, digest: Digest) -> BoxFuture<Snapshot, String> {
     store
       .walk(digest, |_, path_so_far, _, directory| {
@@ -418,6 +465,254 @@ impl Snapshot {
       .and_then(move |dir| store2.record_directory(&dir, true))
   }
 
+  ///
+  /// Given a Digest representing a Directory, returns the Digest of a Directory which contains it
+  /// beneath the given relative prefix. An empty prefix returns the input Digest.
+  ///
+  pub fn add_prefix(store: Store, digest: Digest, prefix: PathBuf) -> BoxFuture<Digest, String> {
+    let components = try_future!(prefix
+      .components()
+      .map(|component| match component {
+        Component::Normal(name) => osstring_as_utf8(name.to_owned()),
+        _ => Err(format!(
+          "Cannot add prefix {}: prefixes must be relative paths without `.` or `..` components",
+          prefix.display()
+        )),
+      })
+      .collect::<Result<Vec<_>, _>>());
+    // Wrap the Directory in one parent per component, starting from the innermost.
+    future::loop_fn(
+      (digest, components),
+      move |(digest, mut components)| match components.pop() {
+        Some(name) => {
+          let mut dir_node = bazel_protos::remote_execution::DirectoryNode::new();
+          dir_node.set_name(name);
+          dir_node.set_digest((&digest).into());
+          let mut out_dir = bazel_protos::remote_execution::Directory::new();
+          out_dir.set_directories(protobuf::RepeatedField::from_vec(vec![dir_node]));
+          store
+            .record_directory(&out_dir, true)
+            .map(move |digest| future::Loop::Continue((digest, components)))
+            .to_boxed()
+        }
+        None => future::ok(future::Loop::Break(digest)).to_boxed(),
+      },
+    )
+    .to_boxed()
+  }
+
+  ///
+  /// Given a Digest representing a Directory, returns a Snapshot containing only the files in it
+  /// which match the given PathGlobs (and the directories which contain them). File contents are
+  /// not re-read: the subset is assembled from the Digests already recorded in the Directory.
+  ///
+  pub fn get_snapshot_subset(
+    store: Store,
+    digest: Digest,
+    path_globs: PathGlobs,
+  ) -> BoxFuture<Snapshot, String> {
+    let store2 = store.clone();
+    store
+      .walk(digest, |_, path_so_far, _, directory| {
+        let files = directory
+          .get_files()
+          .iter()
+          .map(|file_node| {
+            let digest: Result<Digest, String> = file_node.get_digest().into();
+            digest.map(|digest| {
+              let file = File {
+                path: path_so_far.join(file_node.get_name()),
+                is_executable: file_node.is_executable,
+              };
+              (file, digest)
+            })
+          })
+          .collect::<Result<Vec<_>, String>>();
+        future::result(files).to_boxed()
+      })
+      .and_then(move |files_per_directory| {
+        let mut path_stats = Vec::new();
+        let mut digests = HashMap::new();
+        for (file, digest) in Iterator::flatten(files_per_directory.into_iter().map(Vec::into_iter))
+        {
+          if path_globs.matches(&[file.path.clone()])? {
+            path_stats.push(PathStat::file(file.path.clone(), file.clone()));
+            digests.insert(file.path, digest);
+          }
+        }
+        Ok((path_stats, digests))
+      })
+      .and_then(move |(path_stats, digests)| {
+        Snapshot::digest_from_path_stats(
+          store2.clone(),
+          &RecordedFileDigests(Arc::new(digests)),
+          &path_stats,
+        )
+        .and_then(move |digest| Snapshot::from_digest(store2, digest))
+      })
+      .to_boxed()
+  }
+
+  ///
+  /// Given a Digest representing a Directory which contains exactly one archive file, returns the
+  /// Digest of a Directory containing the extracted contents of that archive. The format of the
+  /// archive is determined by the extension of its file name.
+  ///
+  pub fn extract_archive(store: Store, digest: Digest) -> BoxFuture<Digest, String> {
+    let store2 = store.clone();
+    let store3 = store.clone();
+    let store4 = store.clone();
+    Self::get_directory_or_err(store, digest)
+      .and_then(move |directory| {
+        if directory.get_files().len() != 1 || !directory.get_directories().is_empty() {
+          return Err(format!(
+            "Expected a directory containing exactly one archive file, but got {} file(s) and {} \
+             director(ies) in {:?}",
+            directory.get_files().len(),
+            directory.get_directories().len(),
+            digest
+          ));
+        }
+        let file_node = &directory.get_files()[0];
+        let format = ArchiveFormat::from_file_name(file_node.get_name()).ok_or_else(|| {
+          format!(
+            "Cannot extract {}: unrecognized archive file extension.",
+            file_node.get_name()
+          )
+        })?;
+        let file_digest: Result<Digest, String> = file_node.get_digest().into();
+        Ok((file_node.get_name().to_owned(), format, file_digest?))
+      })
+      .and_then(move |(file_name, format, file_digest)| {
+        store2
+          .load_file_bytes_with(file_digest, move |bytes| {
+            // Each file is stored as soon as it has been extracted, so that only its Digest is held
+            // in memory. Later entries for the same path replace earlier ones, as they would on
+            // disk.
+            let mut entries = BTreeMap::new();
+            tar_api::extract_archive(format, Cursor::new(bytes), |entry| {
+              match entry {
+                ArchiveEntry::Dir(path) => {
+                  entries.insert(path.clone(), (PathStat::dir(path.clone(), Dir(path)), None));
+                }
+                ArchiveEntry::File {
+                  path,
+                  is_executable,
+                  content,
+                } => {
+                  let digest = store3.store_file_bytes_blocking(Bytes::from(content), true)?;
+                  let path_stat = PathStat::file(
+                    path.clone(),
+                    File {
+                      path: path.clone(),
+                      is_executable,
+                    },
+                  );
+                  entries.insert(path, (path_stat, Some(digest)));
+                }
+              }
+              Ok(())
+            })
+            .map(|()| entries)
+          })
+          .and_then(move |maybe_entries| {
+            maybe_entries
+              .ok_or_else(|| format!("Archive {} ({:?}) was not known", file_name, file_digest))?
+              .map_err(|err| format!("Error extracting archive {}: {}", file_name, err))
+          })
+      })
+      .and_then(move |entries| {
+        let mut path_stats = Vec::new();
+        let mut digests = HashMap::new();
+        for (path, (path_stat, digest)) in entries {
+          path_stats.push(path_stat);
+          if let Some(digest) = digest {
+            digests.insert(path, digest);
+          }
+        }
+        Snapshot::digest_from_path_stats(
+          store4,
+          &RecordedFileDigests(Arc::new(digests)),
+          &path_stats,
+        )
+      })
+      .to_boxed()
+  }
+
+  ///
+  /// Given a Digest representing a Directory, returns the Digest of a Directory containing a
+  /// single file with the given name: an archive of the input Directory's contents. The format of
+  /// the archive is determined by the extension of the file name.
+  ///
+  pub fn create_archive(
+    store: Store,
+    digest: Digest,
+    archive_name: String,
+  ) -> BoxFuture<Digest, String> {
+    let format = try_future!(
+      ArchiveFormat::from_file_name(&archive_name).ok_or_else(|| format!(
+        "Cannot create {}: unrecognized archive file extension.",
+        archive_name
+      ))
+    );
+    let archive_path = PathBuf::from(&archive_name);
+    if archive_path.components().count() != 1 {
+      return future::err(format!(
+        "Cannot create {}: archive names may not contain path separators.",
+        archive_name
+      ))
+      .to_boxed();
+    }
+    let store2 = store.clone();
+    store
+      .walk(digest, |store, path_so_far, _, directory| {
+        let dirs = directory.get_directories().iter().map(|dir_node| {
+          future::ok(ArchiveEntry::Dir(path_so_far.join(dir_node.get_name()))).to_boxed()
+        });
+        let files = directory.get_files().iter().map(|file_node| {
+          let path = path_so_far.join(file_node.get_name());
+          let is_executable = file_node.is_executable;
+          store
+            .load_file_bytes_with(try_future!(file_node.get_digest().into()), |b| b)
+            .and_then(move |maybe_bytes| {
+              maybe_bytes
+                .ok_or_else(|| format!("Couldn't find file contents for {:?}", path))
+                .map(|content| ArchiveEntry::File {
+                  path,
+                  is_executable,
+                  content: content.to_vec(),
+                })
+            })
+            .to_boxed()
+        });
+        join_all(dirs.chain(files).collect::<Vec<_>>()).to_boxed()
+      })
+      .and_then(move |entries_per_directory| {
+        let entries = Iterator::flatten(entries_per_directory.into_iter().map(Vec::into_iter))
+          .collect::<Vec<_>>();
+        tar_api::create_archive(format, entries)
+          .map_err(|err| format!("Error creating archive {}: {}", archive_name, err))
+      })
+      .and_then(move |archive| {
+        store2
+          .store_file_bytes(Bytes::from(archive), true)
+          .and_then(move |archive_digest| {
+            let file = File {
+              path: archive_path.clone(),
+              is_executable: false,
+            };
+            let mut digests = HashMap::new();
+            digests.insert(archive_path.clone(), archive_digest);
+            Snapshot::digest_from_path_stats(
+              store2,
+              &RecordedFileDigests(Arc::new(digests)),
+              &[PathStat::file(archive_path, file)],
+            )
+          })
+      })
+      .to_boxed()
+  }
+
   fn directories_and_files(directories: &[String], files: &[String]) -> String {
     format!(
       "{}{}{}",
@@ -468,11 +763,16 @@ impl Snapshot {
   /// fall back to actually walking the filesystem if we don't have it (either due to garbage
   /// collection or Digest-oblivious legacy caching).
   ///
+  /// If `remote_only` is set, file contents are stored directly in the remote rather than in the
+  /// local store, and only the Directory protos are recorded locally.
+  ///
   pub fn capture_snapshot_from_arbitrary_root<P: AsRef<Path> + Send + 'static>(
     store: Store,
     root_path: P,
     path_globs: PathGlobs,
     digest_hint: Option<Digest>,
+    remote_only: bool,
+    size_limits: SnapshotSizeLimits,
   ) -> BoxFuture<Snapshot, String> {
     // Attempt to use the digest hint to load a Snapshot without expanding the globs; otherwise,
     // expand the globs to capture a Snapshot.
@@ -481,16 +781,34 @@ impl Snapshot {
       .and_then(move |digest| Snapshot::from_digest(store, digest))
       .or_else(|_| {
         let posix_fs = Arc::new(try_future!(PosixFS::new(root_path, &[])));
+        let posix_fs2 = posix_fs.clone();
 
         posix_fs
           .expand(path_globs)
           .map_err(|err| format!("Error expanding globs: {:?}", err))
-          .and_then(|path_stats| {
-            Snapshot::from_path_stats(
-              store2.clone(),
-              &OneOffStoreFileByDigest::new(store2, posix_fs),
-              path_stats,
-            )
+          .and_then(move |path_stats| {
+            if posix_fs2.is_case_insensitive() {
+              try_future!(Snapshot::check_case_collisions(&path_stats));
+            }
+            size_limits
+              .check(&posix_fs2, &path_stats)
+              .map(|()| path_stats)
+              .to_boxed()
+          })
+          .and_then(move |path_stats| {
+            if remote_only {
+              Snapshot::from_path_stats(
+                store2.clone(),
+                &RemoteOnlyStoreFileByDigest::new(store2, posix_fs),
+                path_stats,
+              )
+            } else {
+              Snapshot::from_path_stats(
+                store2.clone(),
+                &OneOffStoreFileByDigest::new(store2, posix_fs),
+                path_stats,
+              )
+            }
           })
           .to_boxed()
       })
@@ -498,6 +816,89 @@ impl Snapshot {
   }
 }
 
+///
+/// Limits on the sizes of the files captured into a Snapshot, which guard against accidentally
+/// capturing (and storing) enormous files. A limit of None is unlimited.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SnapshotSizeLimits {
+  // The maximum size of any one captured file.
+  pub max_file_bytes: Option<u64>,
+  // The maximum total size of the files captured for one Snapshot.
+  pub max_total_bytes: Option<u64>,
+}
+
+// The number of files to list when the total size of a Snapshot exceeds its limit.
+const LARGEST_FILE_COUNT: usize = 5;
+
+impl SnapshotSizeLimits {
+  ///
+  /// Returns these limits, replaced by any limits which are explicitly set in `overrides`.
+  ///
+  pub fn overridden_by(self, overrides: SnapshotSizeLimits) -> SnapshotSizeLimits {
+    SnapshotSizeLimits {
+      max_file_bytes: overrides.max_file_bytes.or(self.max_file_bytes),
+      max_total_bytes: overrides.max_total_bytes.or(self.max_total_bytes),
+    }
+  }
+
+  ///
+  /// Checks the sizes of the files among the given PathStats against these limits, without
+  /// reading their content, so that a capture which exceeds them fails before anything is stored.
+  ///
+  pub fn check(&self, posix_fs: &PosixFS, path_stats: &[PathStat]) -> BoxFuture<(), String> {
+    if self.max_file_bytes.is_none() && self.max_total_bytes.is_none() {
+      return future::ok(()).to_boxed();
+    }
+    let limits = *self;
+    let sizes = path_stats
+      .iter()
+      .filter_map(|path_stat| match path_stat {
+        PathStat::File { stat, .. } => {
+          let path = stat.path.clone();
+          Some(posix_fs.file_metadata(stat).then(move |metadata| {
+            let size = metadata
+              .map_err(|e| format!("Failed to stat {:?}: {}", path, e))?
+              .len();
+            match limits.max_file_bytes {
+              Some(max) if size > max => Err(format!(
+                "{:?} is {} bytes, which exceeds the limit of {} bytes per captured file. Raise \
+                 the limit for this capture with PathGlobs(max_file_bytes=...), or for all \
+                 captures with --snapshot-max-file-bytes.",
+                path, size, max
+              )),
+              _ => Ok((path, size)),
+            }
+          }))
+        }
+        PathStat::Dir { .. } => None,
+      })
+      .collect::<Vec<_>>();
+    join_all(sizes)
+      .and_then(move |mut sizes| {
+        let total: u64 = sizes.iter().map(|(_, size)| size).sum();
+        match limits.max_total_bytes {
+          Some(max) if total > max => {
+            // Sort by descending size, and then by path for stability.
+            sizes.sort_by(|(p1, s1), (p2, s2)| s2.cmp(s1).then_with(|| p1.cmp(p2)));
+            let mut error = format!(
+              "The captured files total {} bytes, which exceeds the limit of {} bytes per \
+               capture. Raise the limit for this capture with PathGlobs(max_total_bytes=...), or \
+               for all captures with --snapshot-max-total-bytes. The largest files were:",
+              total, max
+            );
+            for (path, size) in sizes.into_iter().take(LARGEST_FILE_COUNT) {
+              error.push_str(&format!("\n  {:>12} {}", size, path.display()));
+            }
+            Err(error)
+          }
+          _ => Ok(()),
+        }
+      })
+      .to_boxed()
+  }
+}
+
 impl fmt::Debug for Snapshot {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     write!(
@@ -544,6 +945,26 @@ pub trait StoreFileByDigest<Error> {
   fn store_by_digest(&self, file: File) -> BoxFuture<Digest, Error>;
 }
 
+///
+/// A StoreFileByDigest for files which are already present in a Store, and whose Digests are
+/// already known.
+///
+#[derive(Clone)]
+struct RecordedFileDigests(Arc<HashMap<PathBuf, Digest>>);
+
+impl StoreFileByDigest<String> for RecordedFileDigests {
+  fn store_by_digest(&self, file: File) -> BoxFuture<Digest, String> {
+    future::result(
+      self
+        .0
+        .get(&file.path)
+        .cloned()
+        .ok_or_else(|| format!("No recorded Digest for {:?}", file.path)),
+    )
+    .to_boxed()
+  }
+}
+
 ///
 /// A StoreFileByDigest which reads with a PosixFS and writes to a Store, with no caching.
 ///
@@ -571,19 +992,48 @@ impl StoreFileByDigest<String> for OneOffStoreFileByDigest {
   }
 }
 
+///
+/// A StoreFileByDigest which reads with a PosixFS and writes directly to the remote of a Store,
+/// without storing the file locally.
+///
+#[derive(Clone)]
+pub struct RemoteOnlyStoreFileByDigest {
+  store: Store,
+  posix_fs: Arc<PosixFS>,
+}
+
+impl RemoteOnlyStoreFileByDigest {
+  pub fn new(store: Store, posix_fs: Arc<PosixFS>) -> RemoteOnlyStoreFileByDigest {
+    RemoteOnlyStoreFileByDigest { store, posix_fs }
+  }
+}
+
+impl StoreFileByDigest<String> for RemoteOnlyStoreFileByDigest {
+  fn store_by_digest(&self, file: File) -> BoxFuture<Digest, String> {
+    let store = self.store.clone();
+    self
+      .posix_fs
+      .read_file(&file)
+      .map_err(move |err| format!("Error reading file {:?}: {:?}", file, err))
+      .and_then(move |content| store.store_file_bytes_remote_only(content.content))
+      .to_boxed()
+  }
+}
+
 #[cfg(test)]
 mod tests {
+  use bytes::Bytes;
   use futures::future::Future;
   use hashing::{Digest, Fingerprint};
   use tempfile;
-  use testutil::data::TestDirectory;
+  use testutil::data::{TestData, TestDirectory};
   use testutil::make_file;
 
   use super::super::{
     Dir, File, GlobExpansionConjunction, GlobMatching, Path, PathGlobs, PathStat, PosixFS,
-    Snapshot, Store, StrictGlobMatching,
+    Snapshot, Store, StrictGlobMatching, UnicodeNormalization,
   };
-  use super::OneOffStoreFileByDigest;
+  use super::{OneOffStoreFileByDigest, SnapshotSizeLimits};
 
   use std;
   use std::path::PathBuf;
@@ -643,6 +1093,33 @@ mod tests {
     );
   }
 
+  #[test]
+  fn snapshot_unicode_normalization() {
+    let (store, dir, posix_fs, digester, mut runtime) = setup();
+    let store = store.with_unicode_normalization(UnicodeNormalization::Nfc);
+
+    // "café", decomposed (as HFS+ stores it), and composed (as it is usually typed).
+    make_file(&dir.path().join("cafe\u{301}"), STR.as_bytes(), 0o600);
+    let nfd_snapshot = runtime
+      .block_on(Snapshot::from_path_stats(
+        store.clone(),
+        &digester,
+        expand_all_sorted(posix_fs.clone(), &mut runtime),
+      ))
+      .unwrap();
+    std::fs::rename(dir.path().join("cafe\u{301}"), dir.path().join("caf\u{e9}")).unwrap();
+    let nfc_snapshot = runtime
+      .block_on(Snapshot::from_path_stats(
+        store,
+        &digester,
+        expand_all_sorted(posix_fs, &mut runtime),
+      ))
+      .unwrap();
+
+    assert_eq!(nfd_snapshot.digest, nfc_snapshot.digest);
+    assert_eq!(nfd_snapshot.path_stats[0].path(), Path::new("caf\u{e9}"));
+  }
+
   #[test]
   fn snapshot_recursive_directories() {
     let (store, dir, posix_fs, digester, mut runtime) = setup();
@@ -1069,6 +1546,194 @@ mod tests {
     assert_eq!(result, Err(format!("Cannot strip prefix cats/ugly from root directory {:?} - subdirectory cats didn't contain a directory named ugly but did contain file named: roland", dir.digest())));
   }
 
+  #[test]
+  fn add_empty_prefix() {
+    let (store, _, _, _, mut runtime) = setup();
+
+    let dir = TestDirectory::containing_roland();
+    let result = runtime.block_on(super::Snapshot::add_prefix(
+      store,
+      dir.digest(),
+      PathBuf::from(""),
+    ));
+    assert_eq!(result, Ok(dir.digest()));
+  }
+
+  #[test]
+  fn add_non_empty_prefix() {
+    let (store, _, _, _, mut runtime) = setup();
+
+    let dir = TestDirectory::containing_roland();
+    runtime
+      .block_on(store.record_directory(&dir.directory(), false))
+      .expect("Error storing directory");
+
+    let result = runtime.block_on(super::Snapshot::add_prefix(
+      store.clone(),
+      dir.digest(),
+      PathBuf::from("cats"),
+    ));
+    assert_eq!(result, Ok(TestDirectory::nested().digest()));
+
+    // Adding and then stripping a prefix should round trip.
+    let stripped = runtime.block_on(super::Snapshot::strip_prefix(
+      store,
+      result.unwrap(),
+      PathBuf::from("cats"),
+    ));
+    assert_eq!(stripped, Ok(dir.digest()));
+  }
+
+  #[test]
+  fn add_prefix_with_parent_component() {
+    let (store, _, _, _, mut runtime) = setup();
+
+    let result = runtime.block_on(super::Snapshot::add_prefix(
+      store,
+      TestDirectory::containing_roland().digest(),
+      PathBuf::from("cats/../dogs"),
+    ));
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn snapshot_subset() {
+    let (store, _, _, _, mut runtime) = setup();
+
+    let dir = TestDirectory::recursive();
+    runtime
+      .block_on(store.record_directory(&dir.directory(), false))
+      .expect("Error storing directory");
+    runtime
+      .block_on(store.record_directory(&TestDirectory::containing_roland().directory(), false))
+      .expect("Error storing directory");
+
+    let mut subset = |glob: &str| {
+      let path_globs = PathGlobs::create(
+        &[glob.to_owned()],
+        &[],
+        StrictGlobMatching::Ignore,
+        GlobExpansionConjunction::AllMatch,
+      )
+      .unwrap();
+      runtime
+        .block_on(super::Snapshot::get_snapshot_subset(
+          store.clone(),
+          dir.digest(),
+          path_globs,
+        ))
+        .unwrap()
+        .digest
+    };
+
+    assert_eq!(
+      subset("treats"),
+      TestDirectory::containing_treats().digest()
+    );
+    assert_eq!(subset("cats/*"), TestDirectory::nested().digest());
+    assert_eq!(subset("**/*"), dir.digest());
+    assert_eq!(subset("dogs/*"), TestDirectory::empty().digest());
+  }
+
+  #[test]
+  fn extract_tar_archive() {
+    let (store, _, _, _, mut runtime) = setup();
+
+    let roland = TestData::roland();
+    let mut header = tar::Header::new_gnu();
+    header.set_size(roland.bytes().len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    let mut builder = tar::Builder::new(Vec::new());
+    builder
+      .append_data(&mut header, "./cats/roland", &roland.bytes()[..])
+      .unwrap();
+    let tar_bytes = Bytes::from(builder.into_inner().unwrap());
+
+    let tar_digest = runtime
+      .block_on(store.store_file_bytes(tar_bytes, false))
+      .expect("Error storing archive");
+    let mut directory = bazel_protos::remote_execution::Directory::new();
+    directory.mut_files().push({
+      let mut file = bazel_protos::remote_execution::FileNode::new();
+      file.set_name("cats.tar".to_owned());
+      file.set_digest((&tar_digest).into());
+      file
+    });
+    let dir_digest = runtime
+      .block_on(store.record_directory(&directory, false))
+      .expect("Error storing directory");
+
+    assert_eq!(
+      runtime.block_on(super::Snapshot::extract_archive(store, dir_digest)),
+      Ok(TestDirectory::nested().digest())
+    );
+  }
+
+  #[test]
+  fn extract_unrecognized_archive() {
+    let (store, _, _, _, mut runtime) = setup();
+
+    runtime
+      .block_on(store.store_file_bytes(TestData::roland().bytes(), false))
+      .expect("Error storing file");
+    let dir = TestDirectory::containing_roland();
+    runtime
+      .block_on(store.record_directory(&dir.directory(), false))
+      .expect("Error storing directory");
+
+    let err = runtime
+      .block_on(super::Snapshot::extract_archive(store, dir.digest()))
+      .expect_err("Want error");
+    assert!(
+      err.contains("unrecognized archive file extension"),
+      "{}",
+      err
+    );
+  }
+
+  #[test]
+  fn create_and_extract_archive() {
+    let (store, _, _, _, mut runtime) = setup();
+
+    runtime
+      .block_on(store.store_file_bytes(TestData::roland().bytes(), false))
+      .expect("Error storing file");
+    runtime
+      .block_on(store.record_directory(&TestDirectory::containing_roland().directory(), false))
+      .expect("Error storing directory");
+    let dir = TestDirectory::nested();
+    runtime
+      .block_on(store.record_directory(&dir.directory(), false))
+      .expect("Error storing directory");
+
+    for archive_name in &["cats.zip", "cats.tar.gz"] {
+      let archive_digest = runtime
+        .block_on(super::Snapshot::create_archive(
+          store.clone(),
+          dir.digest(),
+          archive_name.to_string(),
+        ))
+        .expect("Error creating archive");
+      // Creation is deterministic.
+      assert_eq!(
+        runtime.block_on(super::Snapshot::create_archive(
+          store.clone(),
+          dir.digest(),
+          archive_name.to_string(),
+        )),
+        Ok(archive_digest)
+      );
+      assert_eq!(
+        runtime.block_on(super::Snapshot::extract_archive(
+          store.clone(),
+          archive_digest
+        )),
+        Ok(dir.digest())
+      );
+    }
+  }
+
   fn make_dir_stat(root: &Path, relpath: &Path) -> PathStat {
     std::fs::create_dir(root.join(relpath)).unwrap();
     PathStat::dir(relpath.to_owned(), Dir(relpath.to_owned()))
@@ -1089,6 +1754,117 @@ mod tests {
     )
   }
 
+  #[test]
+  fn size_limits() {
+    let (_, dir, posix_fs, _, mut runtime) = setup();
+    std::fs::create_dir(dir.path().join("cats")).unwrap();
+    make_file(&dir.path().join("cats/roland"), &[0; 100], 0o600);
+    make_file(&dir.path().join("treats"), &[0; 10], 0o600);
+    let path_stats = expand_all_sorted(posix_fs.clone(), &mut runtime);
+
+    let check = |limits: SnapshotSizeLimits, runtime: &mut tokio::runtime::Runtime| {
+      runtime.block_on(limits.check(&posix_fs, &path_stats))
+    };
+
+    assert_eq!(check(SnapshotSizeLimits::default(), &mut runtime), Ok(()));
+    assert_eq!(
+      check(
+        SnapshotSizeLimits {
+          max_file_bytes: Some(100),
+          max_total_bytes: Some(110),
+        },
+        &mut runtime
+      ),
+      Ok(())
+    );
+
+    let file_error = check(
+      SnapshotSizeLimits {
+        max_file_bytes: Some(99),
+        max_total_bytes: None,
+      },
+      &mut runtime,
+    )
+    .unwrap_err();
+    assert!(
+      file_error.starts_with("\"cats/roland\" is 100 bytes, which exceeds the limit of 99 bytes"),
+      "Unexpected error: {}",
+      file_error
+    );
+
+    let total_error = check(
+      SnapshotSizeLimits {
+        max_file_bytes: None,
+        max_total_bytes: Some(109),
+      },
+      &mut runtime,
+    )
+    .unwrap_err();
+    assert!(
+      total_error.starts_with("The captured files total 110 bytes"),
+      "Unexpected error: {}",
+      total_error
+    );
+    assert!(
+      total_error
+        .ends_with("The largest files were:\n           100 cats/roland\n            10 treats"),
+      "Unexpected error: {}",
+      total_error
+    );
+  }
+
+  #[test]
+  fn case_collisions() {
+    let file = |path: &str| {
+      PathStat::file(
+        PathBuf::from(path),
+        File {
+          path: PathBuf::from(path),
+          is_executable: false,
+        },
+      )
+    };
+
+    assert_eq!(
+      Snapshot::check_case_collisions(&[file("cats/roland"), file("cats/Roland.txt")]),
+      Ok(())
+    );
+
+    let file_error =
+      Snapshot::check_case_collisions(&[file("cats/Roland"), file("cats/roland")]).unwrap_err();
+    assert!(
+      file_error.starts_with("Paths \"cats/Roland\" and \"cats/roland\" differ only in case"),
+      "Unexpected error: {}",
+      file_error
+    );
+
+    let dir_error =
+      Snapshot::check_case_collisions(&[file("cats/roland"), file("Cats/susannah")]).unwrap_err();
+    assert!(
+      dir_error.starts_with("Paths \"Cats\" and \"cats\" differ only in case"),
+      "Unexpected error: {}",
+      dir_error
+    );
+  }
+
+  #[test]
+  fn size_limit_overrides() {
+    let defaults = SnapshotSizeLimits {
+      max_file_bytes: Some(1),
+      max_total_bytes: Some(2),
+    };
+    assert_eq!(
+      defaults.overridden_by(SnapshotSizeLimits {
+        max_file_bytes: Some(10),
+        max_total_bytes: None,
+      }),
+      SnapshotSizeLimits {
+        max_file_bytes: Some(10),
+        max_total_bytes: Some(2),
+      }
+    );
+  }
+
   fn expand_all_sorted(
     posix_fs: Arc<PosixFS>,
     runtime: &mut tokio::runtime::Runtime,
@@ -1,32 +1,57 @@
-use crate::{BackoffConfig, FileContent};
+use crate::transfer::{TransferLimits, TransferStats};
+use crate::{BackoffConfig, FileContent, Pools, ProxyConfig, UnicodeNormalization};
 
 use bazel_protos;
 use boxfuture::{try_future, BoxFuture, Boxable};
 use bytes::Bytes;
+use digest::{Digest as DigestTrait, FixedOutput};
 use dirs;
-use futures::{future, Future};
-use hashing::Digest;
-use protobuf::Message;
+use futures::future::Shared;
+use futures::{future, Future, IntoFuture};
+use hashing::{Digest, Fingerprint};
+use log::{info, warn};
+use protobuf::{Message, RepeatedField};
 use serde_derive::Serialize;
-use std::collections::HashMap;
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs::OpenOptions;
-use std::io::Write;
-use std::os::unix::fs::OpenOptionsExt;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio_executor::{DefaultExecutor, Executor};
 
 use parking_lot::Mutex;
 
+pub use self::remote::RemoteStoreTimeouts;
+
 // This is the maximum size any particular local LMDB store file is allowed to grow to.
 // It doesn't reflect space allocated on disk, or RAM allocated (it may be reflected in VIRT but
 // not RSS). There is no practical upper bound on this number, so we set it ridiculously high.
+#[cfg(unix)]
 const MAX_LOCAL_STORE_SIZE_BYTES: usize = 1024 * 1024 * 1024 * 1024 / 10;
 
+// On Windows, LMDB grows each store file to its maximum size up front, so the maximum must be small
+// enough that all of the shards will fit on disk.
+#[cfg(windows)]
+const MAX_LOCAL_STORE_SIZE_BYTES: usize = 512 * 1024 * 1024;
+
 // This is the target number of bytes which should be present in all combined LMDB store files
 // after garbage collection. We almost certainly want to make this configurable.
 pub const DEFAULT_LOCAL_STORE_GC_TARGET_BYTES: usize = 4 * 1024 * 1024 * 1024;
 
+#[cfg(unix)]
+fn set_executable_mode(options: &mut OpenOptions, is_executable: bool) {
+  use std::os::unix::fs::OpenOptionsExt;
+  options.mode(if is_executable { 0o755 } else { 0o644 });
+}
+
+// Windows has no executable bit: instead, executability is determined by a file's extension.
+#[cfg(windows)]
+fn set_executable_mode(_options: &mut OpenOptions, _is_executable: bool) {}
+
 // Summary of the files and directories uploaded with an operation
 // ingested_file_{count, bytes}: Number and combined size of processed files
 // uploaded_file_{count, bytes}: Number and combined size of files uploaded to the remote
@@ -40,6 +65,147 @@ pub struct UploadSummary {
   pub upload_wall_time: Duration,
 }
 
+// The minimum interval between progress reports while materializing a directory.
+const MATERIALIZE_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+///
+/// Tracks the number of bytes written while materializing a directory, and logs the progress at
+/// most once per interval. Materializations which complete within the first interval are silent.
+///
+struct MaterializeProgress {
+  description: String,
+  total_bytes: usize,
+  written_bytes: AtomicUsize,
+  interval: Duration,
+  start: Instant,
+  // The time of the last report, if there has been one.
+  last_report: Mutex<Option<Instant>>,
+}
+
+impl MaterializeProgress {
+  fn new(description: String, total_bytes: usize, interval: Duration) -> MaterializeProgress {
+    MaterializeProgress {
+      description,
+      total_bytes,
+      written_bytes: AtomicUsize::new(0),
+      interval,
+      start: Instant::now(),
+      last_report: Mutex::new(None),
+    }
+  }
+
+  fn wrote(&self, bytes: usize) {
+    let written_bytes = self.written_bytes.fetch_add(bytes, Ordering::SeqCst) + bytes;
+    let now = Instant::now();
+    let mut last_report = self.last_report.lock();
+    let since = last_report.unwrap_or(self.start);
+    if now.duration_since(since) >= self.interval {
+      *last_report = Some(now);
+      info!("{}", self.message(written_bytes));
+    }
+  }
+
+  fn finished(&self) {
+    if self.last_report.lock().is_some() {
+      let elapsed = self.start.elapsed();
+      info!(
+        "Materialized {} ({} bytes) in {}.{:03}s",
+        self.description,
+        self.total_bytes,
+        elapsed.as_secs(),
+        elapsed.subsec_millis()
+      );
+    }
+  }
+
+  fn message(&self, written_bytes: usize) -> String {
+    let percent = if self.total_bytes == 0 {
+      100
+    } else {
+      written_bytes * 100 / self.total_bytes
+    };
+    format!(
+      "Materializing {}: {} of {} bytes written ({}%)",
+      self.description, written_bytes, self.total_bytes, percent
+    )
+  }
+}
+
+///
+/// How to handle a path which already exists when writing a Directory to the workspace.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConflictPolicy {
+  /// Replace the existing file.
+  Overwrite,
+  /// Fail before writing anything.
+  Error,
+  /// Move the existing file or directory aside to a path with a `.bak` suffix (replacing any
+  /// previous backup).
+  Backup,
+}
+
+impl ConflictPolicy {
+  pub fn new(name: &str) -> Result<ConflictPolicy, String> {
+    match name {
+      "overwrite" => Ok(ConflictPolicy::Overwrite),
+      "error" => Ok(ConflictPolicy::Error),
+      "backup" => Ok(ConflictPolicy::Backup),
+      _ => Err(format!(
+        "Unknown conflict policy `{}`: expected one of `overwrite`, `error` or `backup`.",
+        name
+      )),
+    }
+  }
+}
+
+///
+/// The files written by Store::write_to_workspace, and the backups made of the paths they
+/// replaced. All paths are sorted, and include the destination.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct WorkspaceManifest {
+  pub written: Vec<PathBuf>,
+  pub backed_up: Vec<PathBuf>,
+}
+
+///
+/// Makes room to write a file at the given path according to the given ConflictPolicy, returning
+/// the path of a backup if one was made.
+///
+fn clear_workspace_path(path: &Path, policy: ConflictPolicy) -> Result<Option<PathBuf>, String> {
+  let metadata = match path.symlink_metadata() {
+    Ok(metadata) => metadata,
+    Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(format!("Error inspecting {:?}: {:?}", path, e)),
+  };
+  match policy {
+    ConflictPolicy::Error => Err(format!("Cannot write to {:?}: it already exists", path)),
+    ConflictPolicy::Overwrite if metadata.is_dir() => {
+      Err(format!("Cannot overwrite {:?}: it is a directory", path))
+    }
+    ConflictPolicy::Overwrite => std::fs::remove_file(path)
+      .map(|()| None)
+      .map_err(|e| format!("Error removing {:?}: {:?}", path, e)),
+    ConflictPolicy::Backup => {
+      let mut backup = path.as_os_str().to_owned();
+      backup.push(".bak");
+      let backup = PathBuf::from(backup);
+      let removed_previous_backup = if backup.is_dir() {
+        std::fs::remove_dir_all(&backup)
+      } else if backup.symlink_metadata().is_ok() {
+        std::fs::remove_file(&backup)
+      } else {
+        Ok(())
+      };
+      removed_previous_backup
+        .and_then(|()| std::fs::rename(path, &backup))
+        .map(|()| Some(backup.clone()))
+        .map_err(|e| format!("Error backing up {:?} to {:?}: {:?}", path, backup, e))
+    }
+  }
+}
+
 ///
 /// A content-addressed store of file contents, and Directories.
 ///
@@ -50,12 +216,48 @@ pub struct UploadSummary {
 /// https://github.com/googleapis/googleapis/blob/master/google/devtools/remoteexecution/v1test/)
 ///
 /// It can also write back to a remote gRPC server, but will only do so when explicitly instructed
-/// to do so.
+/// to do so, and never if the remote has been marked read-only.
+///
+/// The remote may optionally have two tiers: a nearby cache (which is preferred for reads, and is
+/// written to synchronously), and a central CAS (which is read from when the nearby cache misses,
+/// and is written back to asynchronously).
 ///
 #[derive(Clone)]
 pub struct Store {
   local: local::ByteStore,
   remote: Option<remote::ByteStore>,
+  central_remote: Option<remote::ByteStore>,
+  remote_is_read_only: bool,
+  unicode_normalization: UnicodeNormalization,
+  uploads: UploadScope,
+}
+
+type UploadBatch = Shared<BoxFuture<Vec<Digest>, String>>;
+
+///
+/// The batches of digests which have been uploaded to the remote (or which are being uploaded)
+/// within a scope, such as a Session. Shared between clones of a Store (unless they are given
+/// another scope with `Store::with_upload_scope`), so that when concurrent callers upload
+/// overlapping digests (such as the inputs of many processes which share a large input tree), each
+/// digest is checked for and uploaded at most once.
+///
+/// Because the remote may evict blobs, a scope should not outlive the run which it is used for: a
+/// new scope checks for each digest again.
+///
+#[derive(Clone, Default)]
+pub struct UploadScope(Arc<Mutex<Uploads>>);
+
+impl fmt::Debug for UploadScope {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "UploadScope({} batches)", self.0.lock().batches.len())
+  }
+}
+
+#[derive(Default)]
+struct Uploads {
+  next_batch_id: usize,
+  batches: HashMap<usize, UploadBatch>,
+  batch_ids: HashMap<Digest, usize>,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -88,6 +290,10 @@ impl Store {
     Ok(Store {
       local: local::ByteStore::new(path)?,
       remote: None,
+      central_remote: None,
+      remote_is_read_only: false,
+      unicode_normalization: UnicodeNormalization::default(),
+      uploads: UploadScope::default(),
     })
   }
 
@@ -95,11 +301,16 @@ impl Store {
   /// Make a store which uses local storage, and if it is missing a value which it tries to load,
   /// will attempt to back-fill its local storage from a remote CAS.
   ///
+  /// If central_cas_addresses is non-empty, cas_addresses are treated as a nearby cache of the
+  /// central CAS: see the Store docs.
+  ///
   pub fn with_remote<P: AsRef<Path>>(
     path: P,
     cas_addresses: &[String],
+    central_cas_addresses: &[String],
     instance_name: Option<String>,
     root_ca_certs: &Option<Vec<u8>>,
+    proxies: &ProxyConfig,
     oauth_bearer_token: Option<String>,
     thread_count: usize,
     chunk_size_bytes: usize,
@@ -108,24 +319,179 @@ impl Store {
     rpc_retries: usize,
     futures_timer_thread: futures_timer::TimerHandle,
   ) -> Result<Store, String> {
-    Ok(Store {
-      local: local::ByteStore::new(path)?,
-      remote: Some(remote::ByteStore::new(
-        cas_addresses,
-        instance_name,
+    let new_remote = |addresses: &[String]| {
+      remote::ByteStore::new(
+        addresses,
+        instance_name.clone(),
         root_ca_certs,
-        oauth_bearer_token,
+        proxies,
+        oauth_bearer_token.clone(),
         thread_count,
         chunk_size_bytes,
         upload_timeout,
         backoff_config,
         rpc_retries,
-        futures_timer_thread,
-      )?),
+        futures_timer_thread.clone(),
+      )
+    };
+    let remote = new_remote(cas_addresses)?;
+    // Both tiers share a TransferLimiter, so that their transfers are limited (and reported)
+    // together.
+    let central_remote = if central_cas_addresses.is_empty() {
+      None
+    } else {
+      Some(
+        new_remote(central_cas_addresses)?.with_transfer_limiter(remote.transfer_limiter().clone()),
+      )
+    };
+    Ok(Store {
+      local: local::ByteStore::new(path)?,
+      remote: Some(remote),
+      central_remote,
+      remote_is_read_only: false,
+      unicode_normalization: UnicodeNormalization::default(),
+      uploads: UploadScope::default(),
+    })
+  }
+
+  ///
+  /// Allows up to the given number of chunks of each upload to the remote CAS to be in flight at
+  /// once. Has no effect on a Store without a remote.
+  ///
+  pub fn with_upload_window_chunks(self, upload_window_chunks: usize) -> Store {
+    Store {
+      remote: self
+        .remote
+        .map(|remote| remote.with_upload_window_chunks(upload_window_chunks)),
+      central_remote: self
+        .central_remote
+        .map(|remote| remote.with_upload_window_chunks(upload_window_chunks)),
+      ..self
+    }
+  }
+
+  ///
+  /// Records the digests which this Store uploads in the given scope, so that they are only
+  /// deduplicated against the uploads of other Stores which share it.
+  ///
+  pub fn with_upload_scope(self, uploads: UploadScope) -> Store {
+    Store { uploads, ..self }
+  }
+
+  ///
+  /// Applies the given limits to uploads to and downloads from the remote CAS. Has no effect on a
+  /// Store without a remote.
+  ///
+  pub fn with_transfer_limits(self, limits: TransferLimits) -> Store {
+    let transfer_limiter = if let Some(ref remote) = self.remote {
+      remote.transfer_limiter().with_limits(limits)
+    } else {
+      return self;
+    };
+    Store {
+      remote: self
+        .remote
+        .map(|remote| remote.with_transfer_limiter(transfer_limiter.clone())),
+      central_remote: self
+        .central_remote
+        .map(|remote| remote.with_transfer_limiter(transfer_limiter)),
+      ..self
+    }
+  }
+
+  ///
+  /// Applies the given deadlines to RPCs made to the remote CAS. Has no effect on a Store without a
+  /// remote.
+  ///
+  pub fn with_remote_timeouts(self, timeouts: RemoteStoreTimeouts) -> Store {
+    Store {
+      remote: self.remote.map(|remote| remote.with_timeouts(timeouts)),
+      central_remote: self
+        .central_remote
+        .map(|remote| remote.with_timeouts(timeouts)),
+      ..self
+    }
+  }
+
+  ///
+  /// Stats for the current and past transfers between this Store and its remote CAS, if it has
+  /// one.
+  ///
+  pub fn transfer_stats(&self) -> Option<TransferStats> {
+    self
+      .remote
+      .as_ref()
+      .map(|remote| remote.transfer_limiter().stats())
+  }
+
+  ///
+  /// Marks the remote CAS of this Store as a read-only upstream: values missing locally are still
+  /// fetched from it (and persisted locally), but nothing will be uploaded to it. This makes the
+  /// local store a read-through cache of a central CAS.
+  ///
+  pub fn into_read_only_remote(self) -> Store {
+    Store {
+      remote_is_read_only: true,
+      ..self
+    }
+  }
+
+  ///
+  /// Makes every operation of this Store which would reach its remote CAS fail immediately with an
+  /// offline mode error. Operations which can be satisfied locally are unaffected. Has no effect on
+  /// a Store without a remote.
+  ///
+  pub fn into_offline(self) -> Store {
+    Store {
+      remote: self.remote.map(remote::ByteStore::into_offline),
+      central_remote: self.central_remote.map(remote::ByteStore::into_offline),
+      ..self
+    }
+  }
+
+  ///
+  /// Normalizes the names of files and directories which are captured into Snapshots with this
+  /// Store (and which are materialized from it) to the given unicode normalization form.
+  ///
+  pub fn with_unicode_normalization(self, unicode_normalization: UnicodeNormalization) -> Store {
+    Store {
+      unicode_normalization,
+      ..self
+    }
+  }
+
+  pub fn unicode_normalization(&self) -> UnicodeNormalization {
+    self.unicode_normalization
+  }
+
+  ///
+  /// Runs the blocking work of the local store (LMDB transactions, and hashing and parsing of the
+  /// values which are stored in it) on the given pools, rather than on pools sized by default.
+  ///
+  pub fn with_pools(self, pools: Pools) -> Store {
+    Store {
+      local: self.local.with_pools(pools),
+      ..self
+    }
+  }
+
+  ///
+  /// Keeps the leases of the local store in the given (per-repo) directory, rather than alongside
+  /// its content, so that its content may be shared between the stores of many repos on one
+  /// machine. Garbage collection of the shared content respects the leases of all of them.
+  ///
+  /// The leases which are already stored alongside the content are adopted the first time that a
+  /// metadata directory is used, so a store may be split without losing what it has leased.
+  ///
+  pub fn with_local_metadata_dir<P: AsRef<Path>>(self, metadata_dir: P) -> Result<Store, String> {
+    Ok(Store {
+      local: self.local.with_metadata_dir(metadata_dir.as_ref())?,
+      ..self
     })
   }
 
   // This default is also hard-coded into the Python options code in global_options.py
+  #[cfg(unix)]
   pub fn default_path() -> PathBuf {
     match dirs::home_dir() {
       Some(home_dir) => home_dir.join(".cache").join("pants").join("lmdb_store"),
@@ -133,6 +499,14 @@ impl Store {
     }
   }
 
+  #[cfg(windows)]
+  pub fn default_path() -> PathBuf {
+    match dirs::cache_dir() {
+      Some(cache_dir) => cache_dir.join("pants").join("lmdb_store"),
+      None => panic!("Could not find cache dir"),
+    }
+  }
+
   ///
   /// Store a file locally.
   ///
@@ -143,6 +517,51 @@ impl Store {
       .to_boxed()
   }
 
+  ///
+  /// Store a file locally, blocking the calling thread until it has been written.
+  ///
+  /// This is only for use by functions which are already running on one of the Store's pools (such
+  /// as the function passed to `load_file_bytes_with`), and which would otherwise need to buffer
+  /// the files that they produce.
+  ///
+  pub fn store_file_bytes_blocking(
+    &self,
+    bytes: Bytes,
+    initial_lease: bool,
+  ) -> Result<Digest, String> {
+    self
+      .local
+      .store_bytes_blocking(EntryType::File, bytes, initial_lease)
+  }
+
+  ///
+  /// Store a file directly in the remote, without storing it locally.
+  ///
+  /// This is useful for large files which will never be read locally, and which would otherwise
+  /// double the disk usage of the local store. If a central remote is configured, the file is then
+  /// asynchronously written back to it.
+  ///
+  pub fn store_file_bytes_remote_only(&self, bytes: Bytes) -> BoxFuture<Digest, String> {
+    let remote = if let Some(ref remote) = self.remote {
+      remote
+    } else {
+      return future::err("Cannot store a file remotely without a remote".to_owned()).to_boxed();
+    };
+    if self.remote_is_read_only {
+      return future::err("Cannot upload blobs to a read-only remote".to_owned()).to_boxed();
+    }
+    let central_remote = self.central_remote.clone();
+    remote
+      .store_bytes(bytes.clone())
+      .map(move |digest| {
+        if let Some(central_remote) = central_remote {
+          Self::spawn_write_back("central", central_remote.store_bytes(bytes).map(|_| ()));
+        }
+        digest
+      })
+      .to_boxed()
+  }
+
   ///
   /// Loads the bytes of the file with the passed fingerprint from the local store and back-fill
   /// from remote when necessary and possible (i.e. when remote is configured), and returns the
@@ -215,7 +634,8 @@ impl Store {
         Ok(directory)
       },
       // Eagerly verify that CAS-returned Directories are canonical, so that we don't write them
-      // into our local store.
+      // into our local store: a non-canonical Directory from a buggy server would otherwise
+      // poison the digests of any Directories which are computed from it.
       move |bytes: Bytes| {
         let mut directory = bazel_protos::remote_execution::Directory::new();
         directory.merge_from_bytes(&bytes).map_err(|e| {
@@ -224,7 +644,14 @@ impl Store {
             digest, e
           )
         })?;
-        bazel_protos::verify_directory_canonical(&directory)?;
+        bazel_protos::verify_directory_canonical(&directory).map_err(|e| {
+          let msg = format!(
+            "CAS returned non-canonical Directory proto for {:?}: {}",
+            digest, e
+          );
+          warn!("{}", msg);
+          msg
+        })?;
         Ok(directory)
       },
     )
@@ -247,16 +674,16 @@ impl Store {
     f_remote: FRemote,
   ) -> BoxFuture<Option<T>, String> {
     let local = self.local.clone();
-    let maybe_remote = self.remote.clone();
+    let store = self.clone();
     self
       .local
       .load_bytes_with(entry_type, digest, f_local)
       .and_then(
-        move |maybe_local_value| match (maybe_local_value, maybe_remote) {
+        move |maybe_local_value| match (maybe_local_value, store.remote.clone()) {
           (Some(value_result), _) => future::done(value_result.map(Some)).to_boxed(),
           (None, None) => future::ok(None).to_boxed(),
-          (None, Some(remote)) => remote
-            .load_bytes_with(entry_type, digest, move |bytes: Bytes| bytes)
+          (None, Some(remote)) => store
+            .load_remote_bytes(remote, entry_type, digest)
             .and_then(move |maybe_bytes: Option<Bytes>| match maybe_bytes {
               Some(bytes) => future::done(f_remote(bytes.clone()))
                 .and_then(move |value| {
@@ -282,10 +709,75 @@ impl Store {
       .to_boxed()
   }
 
+  ///
+  /// Loads bytes from the (nearby) remote, falling back to the central remote if one is configured.
+  /// Bytes which are found only in the central remote are asynchronously written back to the nearby
+  /// remote.
+  ///
+  fn load_remote_bytes(
+    &self,
+    remote: remote::ByteStore,
+    entry_type: EntryType,
+    digest: Digest,
+  ) -> BoxFuture<Option<Bytes>, String> {
+    let central_remote = if let Some(ref central_remote) = self.central_remote {
+      central_remote.clone()
+    } else {
+      return remote.load_bytes_with(entry_type, digest, |bytes: Bytes| bytes);
+    };
+    let write_back = !self.remote_is_read_only;
+    remote
+      .load_bytes_with(entry_type, digest, |bytes: Bytes| bytes)
+      .or_else(move |err| {
+        warn!(
+          "Failed to load {:?} from the nearby CAS, falling back to the central CAS: {}",
+          digest, err
+        );
+        Ok(None)
+      })
+      .and_then(move |maybe_bytes| match maybe_bytes {
+        Some(bytes) => future::ok(Some(bytes)).to_boxed(),
+        None => central_remote
+          .load_bytes_with(entry_type, digest, |bytes: Bytes| bytes)
+          .map(move |maybe_bytes| {
+            if let Some(ref bytes) = maybe_bytes {
+              if write_back {
+                Self::spawn_write_back("nearby", remote.store_bytes(bytes.clone()).map(|_| ()));
+              }
+            }
+            maybe_bytes
+          })
+          .to_boxed(),
+      })
+      .to_boxed()
+  }
+
+  ///
+  /// Spawns a write to a remote onto the current executor, without waiting for it to complete.
+  /// Failures are logged, because the write is only an optimisation for future reads.
+  ///
+  fn spawn_write_back<F: Future<Item = (), Error = String> + Send + 'static>(
+    remote_name: &'static str,
+    write: F,
+  ) {
+    let write = write.map_err(move |err| {
+      warn!("Failed to write back to the {} CAS: {}", remote_name, err);
+    });
+    if let Err(err) = DefaultExecutor::current().spawn(Box::new(write)) {
+      warn!(
+        "Failed to spawn write back to the {} CAS: {:?}",
+        remote_name, err
+      );
+    }
+  }
+
   ///
   /// Ensures that the remote ByteStore has a copy of each passed Fingerprint, including any files
   /// contained in any Directories in the list.
   ///
+  /// If a central remote is configured, the same digests are then asynchronously written back to
+  /// it: the returned future does not wait for that to complete.
+  ///
   /// Returns a structure with the summary of operations.
   ///
   pub fn ensure_remote_has_recursive(
@@ -299,6 +791,9 @@ impl Store {
     } else {
       return future::err("Cannot ensure remote has blobs without a remote".to_owned()).to_boxed();
     };
+    if self.remote_is_read_only {
+      return future::err("Cannot upload blobs to a read-only remote".to_owned()).to_boxed();
+    }
 
     let mut expanding_futures = Vec::new();
 
@@ -324,7 +819,8 @@ impl Store {
 
     let local = self.local.clone();
     let remote = remote.clone();
-    let remote2 = remote.clone();
+    let central_remote = self.central_remote.clone();
+    let uploads = self.uploads.clone();
     future::join_all(expanding_futures)
       .map(move |futures| {
         for mut digests in futures {
@@ -332,36 +828,20 @@ impl Store {
             expanded_digests.insert(digest, entry_type);
           }
         }
-        expanded_digests
+        Arc::new(expanded_digests)
       })
       .and_then(move |ingested_digests| {
-        if Store::upload_is_faster_than_checking_whether_to_upload(&ingested_digests) {
-          return future::ok((ingested_digests.keys().cloned().collect(), ingested_digests))
-            .to_boxed();
-        }
-        let request = remote.find_missing_blobs_request(ingested_digests.keys());
-        let f = remote.list_missing_digests(request);
-        f.map(move |digests_to_upload| (digests_to_upload, ingested_digests))
-          .to_boxed()
-      })
-      .and_then(move |(digests_to_upload, ingested_digests)| {
-        future::join_all(
-          digests_to_upload
-            .into_iter()
-            .map(|digest| {
-              let entry_type = ingested_digests[&digest];
-              let remote = remote2.clone();
-              local
-                .load_bytes_with(entry_type, digest, move |bytes| remote.store_bytes(bytes))
-                .and_then(move |maybe_future| match maybe_future {
-                  Some(future) => Ok(future),
-                  None => Err(format!("Failed to upload digest {:?}: Not found", digest)),
-                })
-            })
-            .collect::<Vec<_>>(),
+        Self::upload_missing_deduplicated(uploads, local.clone(), remote, &ingested_digests).map(
+          move |uploaded_digests| {
+            if let Some(central_remote) = central_remote {
+              Self::spawn_write_back(
+                "central",
+                Self::upload_missing(local, central_remote, ingested_digests.clone()).map(|_| ()),
+              );
+            }
+            (uploaded_digests, ingested_digests)
+          },
         )
-        .and_then(future::join_all)
-        .map(|uploaded_digests| (uploaded_digests, ingested_digests))
       })
       .map(move |(uploaded_digests, ingested_digests)| {
         let ingested_file_sizes = ingested_digests.iter().map(|(digest, _)| digest.1);
@@ -378,6 +858,188 @@ impl Store {
       .to_boxed()
   }
 
+  ///
+  /// Uploads exactly the given locally stored blobs to the remote, without first checking whether
+  /// the remote already has them, and without expanding Directories to upload their contents.
+  ///
+  /// This is useful when a remote has reported that specific blobs are missing: it may have
+  /// garbage collected them since it was last asked whether it had them, so asking again is not
+  /// trustworthy.
+  ///
+  pub fn upload_to_remote(&self, digests: Vec<Digest>) -> BoxFuture<UploadSummary, String> {
+    let start_time = Instant::now();
+
+    let remote = if let Some(ref remote) = self.remote {
+      remote.clone()
+    } else {
+      return future::err("Cannot upload blobs without a remote".to_owned()).to_boxed();
+    };
+    if self.remote_is_read_only {
+      return future::err("Cannot upload blobs to a read-only remote".to_owned()).to_boxed();
+    }
+
+    let mut entry_types = HashMap::new();
+    for digest in digests {
+      match self.local.entry_type(&digest.0) {
+        Ok(Some(entry_type)) => {
+          entry_types.insert(digest, entry_type);
+        }
+        Ok(None) => {
+          return future::err(format!("Failed to upload digest {:?}: Not found", digest))
+            .to_boxed();
+        }
+        Err(err) => {
+          return future::err(format!("Failed to upload digest {:?}: {:?}", digest, err))
+            .to_boxed();
+        }
+      };
+    }
+    let digests_to_upload = entry_types.keys().cloned().collect();
+
+    Self::upload(
+      self.local.clone(),
+      remote,
+      digests_to_upload,
+      Arc::new(entry_types),
+    )
+    .map(move |uploaded_digests| {
+      let uploaded_file_count = uploaded_digests.len();
+      let uploaded_file_bytes = uploaded_digests.iter().map(|digest| digest.1).sum();
+      UploadSummary {
+        ingested_file_count: uploaded_file_count,
+        ingested_file_bytes: uploaded_file_bytes,
+        uploaded_file_count,
+        uploaded_file_bytes,
+        upload_wall_time: start_time.elapsed(),
+      }
+    })
+    .to_boxed()
+  }
+
+  ///
+  /// Returns those of the given digests which the remote does not have.
+  ///
+  pub fn list_missing_remote_digests(
+    &self,
+    digests: Vec<Digest>,
+  ) -> BoxFuture<HashSet<Digest>, String> {
+    let remote = if let Some(ref remote) = self.remote {
+      remote.clone()
+    } else {
+      return future::err("Cannot check for blobs without a remote".to_owned()).to_boxed();
+    };
+    let request = remote.find_missing_blobs_request(digests.iter());
+    remote.list_missing_digests(request).to_boxed()
+  }
+
+  ///
+  /// As `upload_missing`, but waits for (rather than repeating) the uploads of any digests which
+  /// are already being (or have been) uploaded, and returns only the digests which this call
+  /// uploaded.
+  ///
+  fn upload_missing_deduplicated(
+    uploads: UploadScope,
+    local: local::ByteStore,
+    remote: remote::ByteStore,
+    ingested_digests: &HashMap<Digest, EntryType>,
+  ) -> BoxFuture<Vec<Digest>, String> {
+    let (batch_id, batch, other_batches) = {
+      let mut uploads = uploads.0.lock();
+      let mut other_batch_ids = HashSet::new();
+      let mut digests_to_upload = HashMap::new();
+      for (digest, entry_type) in ingested_digests {
+        if let Some(batch_id) = uploads.batch_ids.get(digest) {
+          other_batch_ids.insert(*batch_id);
+        } else {
+          digests_to_upload.insert(*digest, *entry_type);
+        }
+      }
+      let other_batches = other_batch_ids
+        .into_iter()
+        .map(|batch_id| uploads.batches[&batch_id].clone())
+        .collect::<Vec<_>>();
+
+      let batch_id = uploads.next_batch_id;
+      uploads.next_batch_id += 1;
+      for digest in digests_to_upload.keys() {
+        uploads.batch_ids.insert(*digest, batch_id);
+      }
+      let batch = Self::upload_missing(local, remote, Arc::new(digests_to_upload)).shared();
+      uploads.batches.insert(batch_id, batch.clone());
+      (batch_id, batch, other_batches)
+    };
+
+    batch
+      .map(|uploaded_digests| (*uploaded_digests).clone())
+      .map_err(|err| (*err).clone())
+      .then(move |res| {
+        if res.is_err() {
+          // Forget the failed batch, so that its digests will be uploaded again if requested.
+          let mut uploads = uploads.0.lock();
+          uploads.batches.remove(&batch_id);
+          uploads.batch_ids.retain(|_, id| *id != batch_id);
+        }
+        res
+      })
+      .join(
+        future::join_all(other_batches)
+          .map_err(|err| format!("Concurrent upload failed: {}", *err)),
+      )
+      .map(|(uploaded_digests, _)| uploaded_digests)
+      .to_boxed()
+  }
+
+  ///
+  /// Uploads any of the given locally stored digests which the given remote does not already have,
+  /// returning the digests which were uploaded.
+  ///
+  fn upload_missing(
+    local: local::ByteStore,
+    remote: remote::ByteStore,
+    ingested_digests: Arc<HashMap<Digest, EntryType>>,
+  ) -> BoxFuture<Vec<Digest>, String> {
+    let digests_to_upload: BoxFuture<HashSet<Digest>, String> =
+      if Store::upload_is_faster_than_checking_whether_to_upload(&ingested_digests) {
+        future::ok(ingested_digests.keys().cloned().collect()).to_boxed()
+      } else {
+        let request = remote.find_missing_blobs_request(ingested_digests.keys());
+        remote.list_missing_digests(request).to_boxed()
+      };
+    digests_to_upload
+      .and_then(move |digests_to_upload| {
+        Self::upload(local, remote, digests_to_upload, ingested_digests)
+      })
+      .to_boxed()
+  }
+
+  ///
+  /// Uploads the given locally stored digests (whose entry types are given) to the given remote.
+  ///
+  fn upload(
+    local: local::ByteStore,
+    remote: remote::ByteStore,
+    digests_to_upload: HashSet<Digest>,
+    entry_types: Arc<HashMap<Digest, EntryType>>,
+  ) -> BoxFuture<Vec<Digest>, String> {
+    future::join_all(
+      digests_to_upload
+        .into_iter()
+        .map(|digest| {
+          let entry_type = entry_types[&digest];
+          let remote = remote.clone();
+          local
+            .load_bytes_with(entry_type, digest, move |bytes| remote.store_bytes(bytes))
+            .and_then(move |maybe_future| match maybe_future {
+              Some(future) => Ok(future),
+              None => Err(format!("Failed to upload digest {:?}: Not found", digest)),
+            })
+        })
+        .collect::<Vec<_>>(),
+    )
+    .and_then(future::join_all)
+    .to_boxed()
+  }
+
   ///
   /// Download a directory from Remote ByteStore recursively to the local one. Called only with the
   /// Digest of a Directory.
@@ -440,6 +1102,22 @@ impl Store {
     }
   }
 
+  ///
+  /// Summarizes the blobs in the local store, to help with choosing garbage collection targets,
+  /// and with spotting unexpected growth.
+  ///
+  pub fn summary(&self) -> Result<StoreSummary, String> {
+    self.local.summary()
+  }
+
+  ///
+  /// Returns the path and size of the (memory mapped) data file of each LMDB map of the local
+  /// store. Unlike `summary`, this does not read the content of the store.
+  ///
+  pub fn lmdb_sizes(&self) -> Result<Vec<(PathBuf, u64)>, String> {
+    self.local.lmdb_sizes()
+  }
+
   ///
   /// To check if it might be faster to upload the digests recursively
   /// vs checking if the files are present first.
@@ -476,6 +1154,110 @@ impl Store {
       .to_boxed()
   }
 
+  ///
+  /// Loads the Directory with the given Digest and all of its transitive child Directories as a
+  /// REAPI Tree. Children are deduplicated and sorted by Digest, so that the Tree for a particular
+  /// Directory is deterministic.
+  ///
+  pub fn load_tree(
+    &self,
+    digest: Digest,
+  ) -> BoxFuture<Option<bazel_protos::remote_execution::Tree>, String> {
+    let store = self.clone();
+    self
+      .load_directory(digest)
+      .and_then(move |maybe_root| match maybe_root {
+        Some(root) => store
+          .walk(digest, |_, _, digest, directory| {
+            future::ok((digest, directory.clone())).to_boxed()
+          })
+          .map(move |mut directories| {
+            directories.retain(|&(child_digest, _)| child_digest != digest);
+            directories.sort_by_key(|&(child_digest, _)| (child_digest.0, child_digest.1));
+            directories.dedup_by_key(|&mut (child_digest, _)| child_digest);
+            let mut tree = bazel_protos::remote_execution::Tree::new();
+            tree.set_root(root);
+            tree.set_children(RepeatedField::from_vec(
+              directories
+                .into_iter()
+                .map(|(_, directory)| directory)
+                .collect(),
+            ));
+            Some(tree)
+          })
+          .to_boxed(),
+        None => future::ok(None).to_boxed(),
+      })
+      .to_boxed()
+  }
+
+  ///
+  /// Saves the root and children of a REAPI Tree locally, and returns the Digest of the root.
+  ///
+  /// Trees are frequently produced by third-party servers, so every Directory is verified to be
+  /// canonical, and every child Directory that is referenced must be present in the Tree. A Tree
+  /// which violates either requirement is rejected, because storing it would result in Digests
+  /// which don't match those computed by other clients.
+  ///
+  pub fn record_tree(
+    &self,
+    tree: &bazel_protos::remote_execution::Tree,
+    initial_lease: bool,
+  ) -> BoxFuture<Digest, String> {
+    let root = try_future!(Self::serialize_tree_directory(tree.get_root()));
+    let children = try_future!(tree
+      .get_children()
+      .iter()
+      .map(Self::serialize_tree_directory)
+      .collect::<Result<Vec<_>, _>>());
+    let child_digests = children
+      .iter()
+      .map(|&(digest, _)| digest)
+      .collect::<HashSet<_>>();
+    for directory in tree
+      .get_children()
+      .iter()
+      .chain(std::iter::once(tree.get_root()))
+    {
+      for directory_node in directory.get_directories() {
+        let digest: Digest = try_future!(directory_node.get_digest().into());
+        if !child_digests.contains(&digest) {
+          return future::err(format!(
+            "Tree is missing child Directory {:?} of {:?}",
+            digest, directory
+          ))
+          .to_boxed();
+        }
+      }
+    }
+
+    let local = self.local.clone();
+    let root_digest = root.0;
+    future::join_all(
+      children
+        .into_iter()
+        .chain(std::iter::once(root))
+        .map(move |(_, bytes)| local.store_bytes(EntryType::Directory, bytes, initial_lease))
+        .collect::<Vec<_>>(),
+    )
+    .map(move |_| root_digest)
+    .to_boxed()
+  }
+
+  fn serialize_tree_directory(
+    directory: &bazel_protos::remote_execution::Directory,
+  ) -> Result<(Digest, Bytes), String> {
+    bazel_protos::verify_directory_canonical(directory)
+      .map_err(|e| format!("Tree contained a non-canonical Directory: {}", e))?;
+    let bytes = directory
+      .write_to_bytes()
+      .map_err(|e| format!("Error serializing directory proto {:?}: {:?}", directory, e))?;
+    let mut hasher = Sha256::default();
+    hasher.input(&bytes);
+    let fingerprint = Fingerprint::from_bytes_unsafe(hasher.fixed_result().as_slice());
+    Ok((Digest(fingerprint, bytes.len()), Bytes::from(bytes)))
+  }
+
   ///
   /// Lays out the directory and all of its contents (files and directories) on disk so that a
   /// process which uses the directory structure can run.
@@ -484,6 +1266,50 @@ impl Store {
     &self,
     destination: PathBuf,
     digest: Digest,
+  ) -> BoxFuture<(), String> {
+    self.materialize_directory_helper(destination, digest, None)
+  }
+
+  ///
+  /// As materialize_directory, but periodically logs the number of bytes which have been written
+  /// (out of the total size of the directory's files), so that materializing a large directory
+  /// does not appear to hang.
+  ///
+  pub fn materialize_directory_with_progress(
+    &self,
+    destination: PathBuf,
+    digest: Digest,
+  ) -> BoxFuture<(), String> {
+    let store = self.clone();
+    self
+      .walk(digest, |_, _, _, directory| {
+        future::ok(
+          directory
+            .get_files()
+            .iter()
+            .map(|file_node| file_node.get_digest().get_size_bytes() as usize)
+            .sum::<usize>(),
+        )
+        .to_boxed()
+      })
+      .and_then(move |sizes| {
+        let progress = Arc::new(MaterializeProgress::new(
+          format!("{}", destination.display()),
+          sizes.into_iter().sum(),
+          MATERIALIZE_PROGRESS_INTERVAL,
+        ));
+        store
+          .materialize_directory_helper(destination, digest, Some(progress.clone()))
+          .map(move |()| progress.finished())
+      })
+      .to_boxed()
+  }
+
+  fn materialize_directory_helper(
+    &self,
+    destination: PathBuf,
+    digest: Digest,
+    progress: Option<Arc<MaterializeProgress>>,
   ) -> BoxFuture<(), String> {
     try_future!(super::safe_create_dir_all(&destination));
     let store = self.clone();
@@ -498,9 +1324,21 @@ impl Store {
           .iter()
           .map(|file_node| {
             let store = store.clone();
-            let path = destination.join(file_node.get_name());
-            let digest = try_future!(file_node.get_digest().into());
-            store.materialize_file(path, digest, file_node.is_executable)
+            let path = destination.join(
+              store
+                .unicode_normalization
+                .normalize_name(file_node.get_name()),
+            );
+            let digest: Digest = try_future!(file_node.get_digest().into());
+            let progress = progress.clone();
+            store
+              .materialize_file(path, digest, file_node.is_executable)
+              .map(move |()| {
+                if let Some(progress) = progress {
+                  progress.wrote(digest.1);
+                }
+              })
+              .to_boxed()
           })
           .collect::<Vec<_>>();
         let directory_futures = directory
@@ -508,9 +1346,13 @@ impl Store {
           .iter()
           .map(|directory_node| {
             let store = store.clone();
-            let path = destination.join(directory_node.get_name());
+            let path = destination.join(
+              store
+                .unicode_normalization
+                .normalize_name(directory_node.get_name()),
+            );
             let digest = try_future!(directory_node.get_digest().into());
-            store.materialize_directory(path, digest)
+            store.materialize_directory_helper(path, digest, progress.clone())
           })
           .collect::<Vec<_>>();
         future::join_all(file_futures)
@@ -528,10 +1370,10 @@ impl Store {
   ) -> BoxFuture<(), String> {
     self
       .load_file_bytes_with(digest, move |bytes| {
-        OpenOptions::new()
-          .create(true)
-          .write(true)
-          .mode(if is_executable { 0o755 } else { 0o644 })
+        let mut options = OpenOptions::new();
+        options.create(true).write(true);
+        set_executable_mode(&mut options, is_executable);
+        options
           .open(&destination)
           .and_then(|mut f| {
             f.write_all(&bytes)?;
@@ -550,6 +1392,88 @@ impl Store {
       .to_boxed()
   }
 
+  ///
+  /// Writes the contents of a Directory to a (possibly non-empty) directory in the workspace,
+  /// creating any missing parent directories, and handling files which already exist according to
+  /// the given ConflictPolicy.
+  ///
+  /// Unlike materialize_directory, which expects to write to a fresh directory, existing files are
+  /// never partially overwritten: they are removed or moved aside before being written.
+  ///
+  pub fn write_to_workspace(
+    &self,
+    destination: PathBuf,
+    digest: Digest,
+    policy: ConflictPolicy,
+  ) -> BoxFuture<WorkspaceManifest, String> {
+    let store = self.clone();
+    self
+      .walk(digest, |_, path_so_far, _, directory| {
+        directory
+          .get_files()
+          .iter()
+          .map(|file_node| {
+            let digest: Result<Digest, String> = file_node.get_digest().into();
+            digest.map(|digest| {
+              (
+                path_so_far.join(file_node.get_name()),
+                digest,
+                file_node.is_executable,
+              )
+            })
+          })
+          .collect::<Result<Vec<_>, _>>()
+          .map(|files| (path_so_far.clone(), files))
+          .into_future()
+          .to_boxed()
+      })
+      .and_then(move |files_per_directory| {
+        let mut directories = Vec::with_capacity(files_per_directory.len());
+        let mut files = Vec::new();
+        for (directory, files_in_directory) in files_per_directory {
+          directories.push(destination.join(directory));
+          files.extend(
+            files_in_directory
+              .into_iter()
+              .map(|(path, digest, is_executable)| (destination.join(path), digest, is_executable)),
+          );
+        }
+        directories.sort();
+        files.sort_by(|l, r| l.0.cmp(&r.0));
+
+        if policy == ConflictPolicy::Error {
+          let conflicts = files
+            .iter()
+            .filter(|(path, _, _)| path.symlink_metadata().is_ok())
+            .map(|(path, _, _)| path.clone())
+            .collect::<Vec<_>>();
+          if !conflicts.is_empty() {
+            return future::err(format!(
+              "Cannot write to the workspace: the following paths already exist: {:?}",
+              conflicts
+            ))
+            .to_boxed();
+          }
+        }
+
+        // Create every directory, including any which are empty.
+        for directory in directories {
+          try_future!(super::safe_create_dir_all(&directory));
+        }
+        let mut manifest = WorkspaceManifest::default();
+        let mut writes = Vec::with_capacity(files.len());
+        for (path, digest, is_executable) in files {
+          if let Some(backup) = try_future!(clear_workspace_path(&path, policy)) {
+            manifest.backed_up.push(backup);
+          }
+          writes.push(store.materialize_file(path.clone(), digest, is_executable));
+          manifest.written.push(path);
+        }
+        future::join_all(writes).map(|_| manifest).to_boxed()
+      })
+      .to_boxed()
+  }
+
   // Returns files sorted by their path.
   pub fn contents_for_directory(&self, digest: Digest) -> BoxFuture<Vec<FileContent>, String> {
     self
@@ -669,6 +1593,158 @@ impl Store {
   }
 }
 
+// The upper bounds (inclusive) of the buckets of the blob size histograms of a StoreSummary.
+const SIZE_BUCKETS: &[(usize, &str)] = &[
+  (1024, "<=1KiB"),
+  (16 * 1024, "<=16KiB"),
+  (256 * 1024, "<=256KiB"),
+  (4 * 1024 * 1024, "<=4MiB"),
+  (64 * 1024 * 1024, "<=64MiB"),
+];
+const SIZE_OVERFLOW_BUCKET: &str = ">64MiB";
+
+// The upper bounds (exclusive) of the buckets of the expired lease histograms of a StoreSummary.
+const LEASE_AGE_BUCKETS: &[(u64, &str)] = &[
+  (24 * 60 * 60, "expired <1d"),
+  (7 * 24 * 60 * 60, "expired <7d"),
+  (30 * 24 * 60 * 60, "expired <30d"),
+];
+const LEASE_AGE_OVERFLOW_BUCKET: &str = "expired >=30d";
+const LEASED_BUCKET: &str = "leased";
+const UNLEASED_BUCKET: &str = "never leased";
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Bucket {
+  pub label: &'static str,
+  pub count: usize,
+}
+
+fn buckets(labels: Vec<&'static str>) -> Vec<Bucket> {
+  labels
+    .into_iter()
+    .map(|label| Bucket { label, count: 0 })
+    .collect()
+}
+
+fn increment(buckets: &mut [Bucket], label: &str) {
+  if let Some(bucket) = buckets.iter_mut().find(|bucket| bucket.label == label) {
+    bucket.count += 1;
+  }
+}
+
+fn add_buckets(buckets: &mut [Bucket], other: &[Bucket]) {
+  for (bucket, other) in buckets.iter_mut().zip(other) {
+    bucket.count += other.count;
+  }
+}
+
+///
+/// Statistics about the blobs in one shard of the local store: their count and total size, and
+/// histograms of their sizes and of how long ago their leases expired.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ShardSummary {
+  pub entry_type: &'static str,
+  // The (hex) fingerprint prefix of the blobs in the shard.
+  pub shard: String,
+  pub blob_count: usize,
+  pub total_bytes: usize,
+  pub sizes: Vec<Bucket>,
+  pub lease_ages: Vec<Bucket>,
+}
+
+impl ShardSummary {
+  fn new(entry_type: EntryType, fingerprint_prefix: u8) -> ShardSummary {
+    ShardSummary {
+      entry_type: match entry_type {
+        EntryType::File => "file",
+        EntryType::Directory => "directory",
+      },
+      shard: format!("{:x}", fingerprint_prefix >> 4),
+      blob_count: 0,
+      total_bytes: 0,
+      sizes: size_buckets(),
+      lease_ages: lease_age_buckets(),
+    }
+  }
+
+  ///
+  /// Records a blob of the given size, which has either never been leased (None), is still leased
+  /// (Some(None)), or has a lease which expired the given duration ago.
+  ///
+  fn record(&mut self, size_bytes: usize, lease_age: Option<Option<Duration>>) {
+    self.blob_count += 1;
+    self.total_bytes += size_bytes;
+    let size_label = SIZE_BUCKETS
+      .iter()
+      .find(|(upper_bound, _)| size_bytes <= *upper_bound)
+      .map(|(_, label)| *label)
+      .unwrap_or(SIZE_OVERFLOW_BUCKET);
+    increment(&mut self.sizes, size_label);
+    let lease_label = match lease_age {
+      None => UNLEASED_BUCKET,
+      Some(None) => LEASED_BUCKET,
+      Some(Some(expired_ago)) => LEASE_AGE_BUCKETS
+        .iter()
+        .find(|(upper_bound, _)| expired_ago.as_secs() < *upper_bound)
+        .map(|(_, label)| *label)
+        .unwrap_or(LEASE_AGE_OVERFLOW_BUCKET),
+    };
+    increment(&mut self.lease_ages, lease_label);
+  }
+}
+
+fn size_buckets() -> Vec<Bucket> {
+  let mut labels = SIZE_BUCKETS
+    .iter()
+    .map(|(_, label)| *label)
+    .collect::<Vec<_>>();
+  labels.push(SIZE_OVERFLOW_BUCKET);
+  buckets(labels)
+}
+
+fn lease_age_buckets() -> Vec<Bucket> {
+  let mut labels = vec![LEASED_BUCKET];
+  labels.extend(LEASE_AGE_BUCKETS.iter().map(|(_, label)| *label));
+  labels.push(LEASE_AGE_OVERFLOW_BUCKET);
+  labels.push(UNLEASED_BUCKET);
+  buckets(labels)
+}
+
+///
+/// Statistics about the blobs in the local store, in total and per shard. See Store::summary.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct StoreSummary {
+  pub blob_count: usize,
+  pub total_bytes: usize,
+  pub sizes: Vec<Bucket>,
+  pub lease_ages: Vec<Bucket>,
+  pub shards: Vec<ShardSummary>,
+}
+
+impl Default for StoreSummary {
+  fn default() -> StoreSummary {
+    StoreSummary {
+      blob_count: 0,
+      total_bytes: 0,
+      sizes: size_buckets(),
+      lease_ages: lease_age_buckets(),
+      shards: vec![],
+    }
+  }
+}
+
+impl StoreSummary {
+  fn add(&mut self, shard: ShardSummary) {
+    self.blob_count += shard.blob_count;
+    self.total_bytes += shard.total_bytes;
+    add_buckets(&mut self.sizes, &shard.sizes);
+    add_buckets(&mut self.lease_ages, &shard.lease_ages);
+    self.shards.push(shard);
+  }
+}
+
 // Only public for testing.
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Ord, PartialOrd)]
 pub enum EntryType {
@@ -677,57 +1753,301 @@ pub enum EntryType {
 }
 
 mod local {
-  use super::{EntryType, ShrinkBehavior};
+  use super::{EntryType, ShardSummary, ShrinkBehavior, StoreSummary};
 
   use boxfuture::{BoxFuture, Boxable};
   use bytes::Bytes;
   use digest::{Digest as DigestTrait, FixedOutput};
   use futures::future::{self, Future};
   use hashing::{Digest, Fingerprint};
+  use lazy_static::lazy_static;
   use lmdb::Error::{KeyExist, NotFound};
   use lmdb::{
     self, Cursor, Database, DatabaseFlags, Environment, EnvironmentCopyFlags, EnvironmentFlags,
     RwTransaction, Transaction, WriteFlags,
   };
-  use log::{error, trace};
+  use log::{error, trace, warn};
+  use parking_lot::Mutex;
   use sha2::Sha256;
   use std;
   use std::collections::{BinaryHeap, HashMap};
   use std::fmt;
+  use std::io;
   use std::path::{Path, PathBuf};
-  use std::sync::Arc;
+  use std::sync::{Arc, Weak};
   use std::time;
   use tempfile::TempDir;
 
-  use super::super::EMPTY_DIGEST;
-  use super::MAX_LOCAL_STORE_SIZE_BYTES;
+  use super::super::EMPTY_DIGEST;
+  use super::MAX_LOCAL_STORE_SIZE_BYTES;
+  use crate::blocking::Pools;
+  use crate::store_lock::{write_atomically, StoreLock};
+
+  #[derive(Clone)]
+  pub struct ByteStore {
+    inner: Arc<InnerStore>,
+    leases: Arc<Leases>,
+    // LMDB transactions block, and hashing is expensive, so neither runs on the caller's thread.
+    pools: Pools,
+  }
+
+  struct InnerStore {
+    // Store directories separately from files because:
+    //  1. They may have different lifetimes.
+    //  2. It's nice to know whether we should be able to parse something as a proto.
+    file_dbs: Result<Arc<ShardedLmdb>, String>,
+    directory_dbs: Result<Arc<ShardedLmdb>, String>,
+    // The root of the content, which links to the metadata directories of the stores which share
+    // it (see Leases).
+    root: PathBuf,
+    // Held for as long as the store is open, so that other processes do not compact it.
+    lock: StoreLock,
+  }
+
+  ///
+  /// The databases which hold the leases of a ByteStore. By default, these are the lease databases
+  /// which are stored alongside its content. But the content may instead be shared between the
+  /// stores of many repos (to avoid storing the same blobs once per checkout), with the leases of
+  /// each kept in a per-repo metadata directory, so that what each repo keeps alive remains
+  /// separate.
+  ///
+  /// The root of the content links to each metadata directory which uses it, and each metadata
+  /// directory links back to the root of its content. Garbage collection respects the leases of
+  /// every linked metadata directory (and those stored alongside the content), and prunes links
+  /// which are no longer linked back.
+  ///
+  struct Leases {
+    file_dbs: Result<Arc<ShardedLmdb>, String>,
+    directory_dbs: Result<Arc<ShardedLmdb>, String>,
+    // Held on the metadata directory (if the leases are kept in one) for as long as it is open.
+    metadata_lock: Option<StoreLock>,
+  }
+
+  impl Leases {
+    fn dbs(&self, entry_type: EntryType) -> Result<Arc<ShardedLmdb>, String> {
+      match entry_type {
+        EntryType::File => self.file_dbs.clone(),
+        EntryType::Directory => self.directory_dbs.clone(),
+      }
+    }
+
+    fn open_metadata_dir(content: &InnerStore, metadata_root: &Path) -> Result<Leases, String> {
+      let (metadata_root, metadata_lock) = StoreLock::open_versioned(metadata_root)?;
+      let content_root = canonicalize(&content.root)?;
+      let metadata_root = canonicalize(&metadata_root)?;
+      let file_dbs = open_metadata_dbs(metadata_root.join("files"))?;
+      let directory_dbs = open_metadata_dbs(metadata_root.join("directories"))?;
+
+      if read_link(&metadata_root.join(CONTENT_LINK_FILE_NAME))?.as_ref() != Some(&content_root) {
+        // The metadata directory is new, or was used with different content. Adopt the leases
+        // which are stored alongside the content, which include any that this repo took while its
+        // store was kept in a single directory. They are copied rather than moved, because other
+        // repos may still keep their stores in that single directory.
+        copy_leases(&content.file_dbs.clone()?, &file_dbs)?;
+        copy_leases(&content.directory_dbs.clone()?, &directory_dbs)?;
+        write_link(&metadata_root.join(CONTENT_LINK_FILE_NAME), &content_root)?;
+      }
+      write_link(
+        &metadata_link_path(&content_root, &metadata_root),
+        &metadata_root,
+      )?;
+
+      Ok(Leases {
+        file_dbs: Ok(file_dbs),
+        directory_dbs: Ok(directory_dbs),
+        metadata_lock: Some(metadata_lock),
+      })
+    }
+  }
+
+  // Within a metadata directory: the file which links back to the root of its content.
+  const CONTENT_LINK_FILE_NAME: &str = "CONTENT";
+  // Within the root of the content: the directory of links to the metadata directories using it.
+  const METADATA_LINKS_DIR_NAME: &str = "metadata_links";
+
+  lazy_static! {
+    // The lease databases of the metadata directories which are open in this process. An LMDB
+    // environment must not be opened more than once by a process, but a metadata directory is
+    // used both by its own store, and by any other store which shares its content.
+    static ref OPEN_METADATA_DBS: Mutex<HashMap<PathBuf, Weak<ShardedLmdb>>> =
+      Mutex::new(HashMap::new());
+  }
+
+  fn open_metadata_dbs(root: PathBuf) -> Result<Arc<ShardedLmdb>, String> {
+    let mut open_metadata_dbs = OPEN_METADATA_DBS.lock();
+    if let Some(dbs) = open_metadata_dbs.get(&root).and_then(Weak::upgrade) {
+      return Ok(dbs);
+    }
+    let dbs = Arc::new(ShardedLmdb::new(root.clone())?);
+    open_metadata_dbs.insert(root, Arc::downgrade(&dbs));
+    Ok(dbs)
+  }
+
+  fn canonicalize(path: &Path) -> Result<PathBuf, String> {
+    std::fs::canonicalize(path).map_err(|e| format!("Failed to canonicalize {:?}: {}", path, e))
+  }
+
+  fn metadata_link_path(content_root: &Path, metadata_root: &Path) -> PathBuf {
+    let mut hasher = Sha256::default();
+    hasher.input(metadata_root.to_string_lossy().as_bytes());
+    content_root
+      .join(METADATA_LINKS_DIR_NAME)
+      .join(Fingerprint::from_bytes_unsafe(hasher.fixed_result().as_slice()).to_hex())
+  }
+
+  fn read_link(path: &Path) -> Result<Option<PathBuf>, String> {
+    match std::fs::read_to_string(path) {
+      Ok(target) => Ok(Some(PathBuf::from(target))),
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(format!("Error reading store link {:?}: {}", path, e)),
+    }
+  }
+
+  fn write_link(path: &Path, target: &Path) -> Result<(), String> {
+    if read_link(path)?.as_ref().map(PathBuf::as_path) == Some(target) {
+      return Ok(());
+    }
+    super::super::safe_create_dir_all(path.parent().unwrap()).and_then(|()| {
+      write_atomically(path, &target.to_string_lossy())
+        .map_err(|e| format!("Error writing store link {:?}: {}", path, e))
+    })
+  }
 
-  #[derive(Clone)]
-  pub struct ByteStore {
-    inner: Arc<InnerStore>,
+  ///
+  /// The metadata directories which are linked to from the given root of the content, and which
+  /// link back to it. Links which do not are pruned.
+  ///
+  fn linked_metadata_roots(content_root: &Path) -> Result<Vec<PathBuf>, String> {
+    let links_dir = content_root.join(METADATA_LINKS_DIR_NAME);
+    let entries = match std::fs::read_dir(&links_dir) {
+      Ok(entries) => entries,
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+      Err(e) => return Err(format!("Error listing store links {:?}: {}", links_dir, e)),
+    };
+    let content_root = canonicalize(content_root)?;
+    let mut metadata_roots = vec![];
+    for entry in entries {
+      let link_path = entry
+        .map_err(|e| format!("Error listing store links {:?}: {}", links_dir, e))?
+        .path();
+      let metadata_root = match read_link(&link_path)? {
+        Some(metadata_root) => metadata_root,
+        None => continue,
+      };
+      if read_link(&metadata_root.join(CONTENT_LINK_FILE_NAME))?.as_ref() == Some(&content_root) {
+        metadata_roots.push(metadata_root);
+      } else {
+        trace!(
+          "Pruning stale store link {:?} to {:?}",
+          link_path,
+          metadata_root
+        );
+        std::fs::remove_file(&link_path)
+          .or_else(|e| {
+            if e.kind() == io::ErrorKind::NotFound {
+              Ok(())
+            } else {
+              Err(e)
+            }
+          })
+          .map_err(|e| format!("Error pruning store link {:?}: {}", link_path, e))?;
+      }
+    }
+    Ok(metadata_roots)
   }
 
-  struct InnerStore {
-    // Store directories separately from files because:
-    //  1. They may have different lifetimes.
-    //  2. It's nice to know whether we should be able to parse something as a proto.
-    file_dbs: Result<Arc<ShardedLmdb>, String>,
-    directory_dbs: Result<Arc<ShardedLmdb>, String>,
+  ///
+  /// Copies the leases in the given databases into other databases, keeping the later of any two
+  /// leases of the same key.
+  ///
+  fn copy_leases(from: &ShardedLmdb, to: &ShardedLmdb) -> Result<(), String> {
+    for (prefix, from_env, _, from_lease_database) in from.shards() {
+      let (to_env, _, to_lease_database) = to.shard(prefix);
+      let from_txn = from_env
+        .begin_ro_txn()
+        .map_err(|err| format!("Error beginning transaction to copy leases: {}", err))?;
+      let mut cursor = from_txn
+        .open_ro_cursor(from_lease_database)
+        .map_err(|err| format!("Failed to open lmdb read cursor: {}", err))?;
+      let mut to_txn = to_env
+        .begin_rw_txn()
+        .map_err(|err| format!("Error beginning transaction to copy leases: {}", err))?;
+      for (key, until) in cursor.iter() {
+        if lease_until(&to_txn, to_lease_database, key)
+          < lease_until(&from_txn, from_lease_database, key)
+        {
+          to_txn
+            .put(to_lease_database, &key, &until, WriteFlags::empty())
+            .map_err(|err| format!("Error copying lease: {}", err))?;
+        }
+      }
+      to_txn
+        .commit()
+        .map_err(|err| format!("Error committing copied leases: {}", err))?;
+    }
+    Ok(())
   }
 
   impl ByteStore {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<ByteStore, String> {
-      let root = path.as_ref();
-      let files_root = root.join("files");
-      let directories_root = root.join("directories");
+      let (root, lock) = StoreLock::open_versioned(path.as_ref())?;
+      let file_dbs = ShardedLmdb::new(root.join("files")).map(Arc::new);
+      let directory_dbs = ShardedLmdb::new(root.join("directories")).map(Arc::new);
       Ok(ByteStore {
+        leases: Arc::new(Leases {
+          file_dbs: file_dbs.clone(),
+          directory_dbs: directory_dbs.clone(),
+          metadata_lock: None,
+        }),
         inner: Arc::new(InnerStore {
-          file_dbs: ShardedLmdb::new(files_root.clone()).map(Arc::new),
-          directory_dbs: ShardedLmdb::new(directories_root.clone()).map(Arc::new),
+          file_dbs,
+          directory_dbs,
+          root,
+          lock,
         }),
+        pools: Pools::default(),
+      })
+    }
+
+    ///
+    /// Keeps the leases of this store in the given (per-repo) metadata directory, rather than
+    /// alongside its content, so that the content may be shared with the stores of other repos.
+    /// See Leases.
+    ///
+    pub fn with_metadata_dir(self, metadata_root: &Path) -> Result<ByteStore, String> {
+      let leases = Leases::open_metadata_dir(&self.inner, metadata_root)?;
+      Ok(ByteStore {
+        leases: Arc::new(leases),
+        ..self
       })
     }
 
+    ///
+    /// The lease databases of every store which shares this store's content: those alongside the
+    /// content, and those of each linked metadata directory.
+    ///
+    fn all_lease_dbs(&self, entry_type: EntryType) -> Result<Vec<Arc<ShardedLmdb>>, String> {
+      let mut all_lease_dbs = vec![match entry_type {
+        EntryType::File => self.inner.file_dbs.clone()?,
+        EntryType::Directory => self.inner.directory_dbs.clone()?,
+      }];
+      let dir_name = match entry_type {
+        EntryType::File => "files",
+        EntryType::Directory => "directories",
+      };
+      for metadata_root in linked_metadata_roots(&self.inner.root)? {
+        all_lease_dbs.push(open_metadata_dbs(metadata_root.join(dir_name))?);
+      }
+      Ok(all_lease_dbs)
+    }
+
+    ///
+    /// Runs the blocking work of this store on the given pools.
+    ///
+    pub fn with_pools(self, pools: Pools) -> ByteStore {
+      ByteStore { pools, ..self }
+    }
+
     // Note: This performs IO on the calling thread. Hopefully the IO is small enough not to matter.
     pub fn entry_type(&self, fingerprint: &Fingerprint) -> Result<Option<EntryType>, String> {
       if *fingerprint == EMPTY_DIGEST.0 {
@@ -774,10 +2094,13 @@ mod local {
     ) -> Result<(), String> {
       let until = Self::default_lease_until_secs_since_epoch();
       for digest in digests {
-        let (env, _, lease_database) = self.inner.file_dbs.clone()?.get(&digest.0);
+        let (env, _, lease_database) = self.leases.file_dbs.clone()?.get(&digest.0);
         env
           .begin_rw_txn()
-          .and_then(|mut txn| self.lease(lease_database, &digest.0, until, &mut txn))
+          .and_then(|mut txn| {
+            self.lease(lease_database, &digest.0, until, &mut txn)?;
+            txn.commit()
+          })
           .map_err(|err| format!("Error leasing digest {:?}: {}", digest, err))?;
       }
       Ok(())
@@ -863,10 +2186,38 @@ mod local {
             })
             .map_err(|err| format!("Error garbage collecting: {}", err))?;
         }
+        if self.leases.metadata_lock.is_some() {
+          let (env, _, lease_database) = self
+            .leases
+            .dbs(aged_fingerprint.entry_type)?
+            .get(&aged_fingerprint.fingerprint);
+          env
+            .begin_rw_txn()
+            .and_then(|mut txn| {
+              txn
+                .del(lease_database, &aged_fingerprint.fingerprint.as_ref(), None)
+                .or_else(|err| match err {
+                  NotFound => Ok(()),
+                  err => Err(err),
+                })?;
+              txn.commit()
+            })
+            .map_err(|err| format!("Error garbage collecting: {}", err))?;
+        }
       }
 
       if shrink_behavior == ShrinkBehavior::Compact {
-        self.inner.file_dbs.clone()?.compact()?;
+        // Compaction replaces the store's files, so it is only safe while no other process has
+        // the store open. Otherwise, the space freed above will be reused by future writes.
+        let file_dbs = self.inner.file_dbs.clone()?;
+        if self
+          .inner
+          .lock
+          .with_exclusive(|| file_dbs.compact())?
+          .is_none()
+        {
+          warn!("Not compacting the local store, because it is in use by another process.");
+        }
       }
 
       Ok(used_bytes)
@@ -883,12 +2234,27 @@ mod local {
         EntryType::Directory => self.inner.directory_dbs.clone(),
       };
 
-      for &(ref env, ref database, ref lease_database) in &database?.all_lmdbs() {
+      // A blob is leased until the latest of its leases by any of the stores which share it.
+      let all_lease_dbs = self.all_lease_dbs(entry_type)?;
+      for (prefix, env, database, _) in database?.shards() {
         let txn = env
           .begin_ro_txn()
           .map_err(|err| format!("Error beginning transaction to garbage collect: {}", err))?;
+        let lease_shards = all_lease_dbs
+          .iter()
+          .map(|lease_dbs| lease_dbs.shard(prefix))
+          .collect::<Vec<_>>();
+        let lease_txns = lease_shards
+          .iter()
+          .map(|(lease_env, _, lease_database)| {
+            lease_env
+              .begin_ro_txn()
+              .map(|lease_txn| (lease_txn, *lease_database))
+          })
+          .collect::<Result<Vec<_>, _>>()
+          .map_err(|err| format!("Error beginning transaction to garbage collect: {}", err))?;
         let mut cursor = txn
-          .open_ro_cursor(*database)
+          .open_ro_cursor(database)
           .map_err(|err| format!("Failed to open lmdb read cursor: {}", err))?;
         for (key, bytes) in cursor.iter() {
           *used_bytes += bytes.len();
@@ -897,17 +2263,11 @@ mod local {
           // collection is rare enough that we can get away with this, rather than do two passes
           // here (either to populate leases into pre-populated AgedFingerprints, or to read sizes
           // when we delete from lmdb to track how much we've freed).
-          let lease_until_unix_timestamp = txn
-            .get(*lease_database, &key)
-            .map(|b| {
-              let mut array = [0_u8; 8];
-              array.copy_from_slice(b);
-              u64::from_le_bytes(array)
-            })
-            .unwrap_or_else(|e| match e {
-              NotFound => 0,
-              e => panic!("Error reading lease, probable lmdb corruption: {:?}", e),
-            });
+          let lease_until_unix_timestamp = lease_txns
+            .iter()
+            .filter_map(|(lease_txn, lease_database)| lease_until(lease_txn, *lease_database, key))
+            .max()
+            .unwrap_or(0);
 
           let leased_until =
             time::UNIX_EPOCH + time::Duration::from_secs(lease_until_unix_timestamp);
@@ -929,61 +2289,133 @@ mod local {
       Ok(())
     }
 
+    pub fn lmdb_sizes(&self) -> Result<Vec<(PathBuf, u64)>, String> {
+      let mut sizes = self.inner.file_dbs.clone()?.data_file_sizes()?;
+      sizes.extend(self.inner.directory_dbs.clone()?.data_file_sizes()?);
+      Ok(sizes)
+    }
+
+    ///
+    /// Summarizes the content of each shard of the store, by reading every entry.
+    ///
+    pub fn summary(&self) -> Result<StoreSummary, String> {
+      let now = time::SystemTime::now();
+      let mut summary = StoreSummary::default();
+      let all_dbs = vec![
+        (EntryType::File, self.inner.file_dbs.clone()),
+        (EntryType::Directory, self.inner.directory_dbs.clone()),
+      ];
+      for (entry_type, dbs) in all_dbs {
+        let lease_dbs = self.leases.dbs(entry_type)?;
+        for (prefix, env, database, _) in dbs?.shards() {
+          let mut shard = ShardSummary::new(entry_type, prefix);
+          let (lease_env, _, lease_database) = lease_dbs.shard(prefix);
+          let txn = env
+            .begin_ro_txn()
+            .map_err(|err| format!("Error beginning transaction to summarize: {}", err))?;
+          let lease_txn = lease_env
+            .begin_ro_txn()
+            .map_err(|err| format!("Error beginning transaction to summarize: {}", err))?;
+          let mut cursor = txn
+            .open_ro_cursor(database)
+            .map_err(|err| format!("Failed to open lmdb read cursor: {}", err))?;
+          for (key, bytes) in cursor.iter() {
+            let lease_age = lease_until(&lease_txn, lease_database, key).map(|lease_until| {
+              now
+                .duration_since(time::UNIX_EPOCH + time::Duration::from_secs(lease_until))
+                // An error indicates that the lease has not yet expired.
+                .ok()
+            });
+            shard.record(bytes.len(), lease_age);
+          }
+          summary.add(shard);
+        }
+      }
+      Ok(summary)
+    }
+
     pub fn store_bytes(
       &self,
       entry_type: EntryType,
       bytes: Bytes,
       initial_lease: bool,
     ) -> BoxFuture<Digest, String> {
+      let bytestore = self.clone();
+      let io_pool = self.pools.io.clone();
+      self
+        .pools
+        .cpu
+        .run(move || Ok((Self::fingerprint(&bytes), bytes)))
+        .and_then(move |(fingerprint, bytes)| {
+          io_pool.run(move || bytestore.put(entry_type, fingerprint, &bytes, initial_lease))
+        })
+        .to_boxed()
+    }
+
+    ///
+    /// Like `store_bytes`, but hashes and writes the bytes on the calling thread.
+    ///
+    pub fn store_bytes_blocking(
+      &self,
+      entry_type: EntryType,
+      bytes: Bytes,
+      initial_lease: bool,
+    ) -> Result<Digest, String> {
+      self.put(entry_type, Self::fingerprint(&bytes), &bytes, initial_lease)
+    }
+
+    fn fingerprint(bytes: &[u8]) -> Fingerprint {
+      let mut hasher = Sha256::default();
+      hasher.input(bytes);
+      Fingerprint::from_bytes_unsafe(hasher.fixed_result().as_slice())
+    }
+
+    fn put(
+      &self,
+      entry_type: EntryType,
+      fingerprint: Fingerprint,
+      bytes: &Bytes,
+      initial_lease: bool,
+    ) -> Result<Digest, String> {
       let dbs = match entry_type {
         EntryType::Directory => self.inner.directory_dbs.clone(),
         EntryType::File => self.inner.file_dbs.clone(),
       };
+      let digest = Digest(fingerprint, bytes.len());
+
+      let (env, content_database, _) = dbs?.get(&fingerprint);
+      let put_res = env.begin_rw_txn().and_then(|mut txn| {
+        txn.put(
+          content_database,
+          &fingerprint,
+          bytes,
+          WriteFlags::NO_OVERWRITE,
+        )?;
+        txn.commit()
+      });
+      match put_res {
+        Ok(()) | Err(KeyExist) => {}
+        Err(err) => return Err(format!("Error storing digest {:?}: {}", digest, err)),
+      }
 
-      let bytestore = self.clone();
-      futures::future::poll_fn(move || {
-        tokio_threadpool::blocking(|| {
-          let fingerprint = {
-            let mut hasher = Sha256::default();
-            hasher.input(&bytes);
-            Fingerprint::from_bytes_unsafe(hasher.fixed_result().as_slice())
-          };
-          let digest = Digest(fingerprint, bytes.len());
-
-          let (env, content_database, lease_database) = dbs.clone()?.get(&fingerprint);
-          let put_res = env.begin_rw_txn().and_then(|mut txn| {
-            txn.put(
-              content_database,
+      if initial_lease {
+        // Leased even if the content already existed, since it may have been stored by
+        // another store which shares it.
+        let (env, _, lease_database) = self.leases.dbs(entry_type)?.get(&fingerprint);
+        env
+          .begin_rw_txn()
+          .and_then(|mut txn| {
+            self.lease(
+              lease_database,
               &fingerprint,
-              &bytes,
-              WriteFlags::NO_OVERWRITE,
+              Self::default_lease_until_secs_since_epoch(),
+              &mut txn,
             )?;
-            if initial_lease {
-              bytestore.lease(
-                lease_database,
-                &fingerprint,
-                Self::default_lease_until_secs_since_epoch(),
-                &mut txn,
-              )?;
-            }
             txn.commit()
-          });
-
-          match put_res {
-            Ok(()) => Ok(digest),
-            Err(KeyExist) => Ok(digest),
-            Err(err) => Err(format!("Error storing digest {:?}: {}", digest, err)),
-          }
-        })
-      })
-      .then(|blocking_result| match blocking_result {
-        Ok(v) => v,
-        Err(blocking_err) => Err(format!(
-          "Unable to run blocking task to store_bytes in local ByteStore on tokio runtime: {}",
-          blocking_err
-        )),
-      })
-      .to_boxed()
+          })
+          .map_err(|err| format!("Error leasing digest {:?}: {}", digest, err))?;
+      }
+      Ok(digest)
     }
 
     pub fn load_bytes_with<T: Send + 'static, F: Fn(Bytes) -> T + Send + Sync + 'static>(
@@ -1004,30 +2436,63 @@ mod local {
         EntryType::File => self.inner.file_dbs.clone(),
       };
 
-      futures::future::poll_fn(move || tokio_threadpool::blocking( || {
-          let (env, db, _) = dbs.clone()?.get(&digest.0);
-          let ro_txn = env
-            .begin_ro_txn()
-            .map_err(|err| format!("Failed to begin read transaction: {}", err));
-          ro_txn.and_then(|txn| match txn.get(db, &digest.0) {
-            Ok(bytes) => {
-              if bytes.len() == digest.1 {
-                Ok(Some(f(Bytes::from(bytes))))
-              } else {
-                error!("Got hash collision reading from store - digest {:?} was requested, but retrieved bytes with that fingerprint had length {}. Congratulations, you may have broken sha256! Underlying bytes: {:?}", digest, bytes.len(), bytes);
-                Ok(None)
-              }
+      // The bytes are copied out of the transaction on the IO pool, and then (since `f` usually
+      // parses or hashes them) consumed on the CPU pool.
+      let cpu_pool = self.pools.cpu.clone();
+      self.pools.io.run(move || {
+        let (env, db, _) = dbs.clone()?.get(&digest.0);
+        let ro_txn = env
+          .begin_ro_txn()
+          .map_err(|err| format!("Failed to begin read transaction: {}", err));
+        ro_txn.and_then(|txn| match txn.get(db, &digest.0) {
+          Ok(bytes) => {
+            if bytes.len() == digest.1 {
+              Ok(Some(Bytes::from(bytes)))
+            } else {
+              error!("Got hash collision reading from store - digest {:?} was requested, but retrieved bytes with that fingerprint had length {}. Congratulations, you may have broken sha256! Underlying bytes: {:?}", digest, bytes.len(), bytes);
+              Ok(None)
             }
-            Err(NotFound) => Ok(None),
-            Err(err) => Err(format!("Error loading digest {:?}: {}", digest, err,)),
-          })
-        })).then(|blocking_result| {
-        match blocking_result {
-          Ok(v) => v,
-          Err(blocking_err) => Err(format!("Unable to run blocking task to load_bytes in local ByteStore on tokio runtime: {}", blocking_err)),
-        }
-      }).to_boxed()
+          }
+          Err(NotFound) => Ok(None),
+          Err(err) => Err(format!("Error loading digest {:?}: {}", digest, err,)),
+        })
+      })
+      .and_then(move |maybe_bytes| match maybe_bytes {
+        Some(bytes) => cpu_pool.run(move || Ok(Some(f(bytes)))),
+        None => future::ok(None).to_boxed(),
+      })
+      .to_boxed()
+    }
+  }
+
+  const META_DATABASE_NAME: &str = "meta";
+  // The LOCAL_STORE_VERSION which the databases of an environment have been migrated to (see
+  // `migrate_lmdbs`). Environments which have never been migrated have no recorded version.
+  const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+  // Upgrades the content and lease databases of an environment within a write transaction.
+  pub type SchemaMigration = fn(&mut RwTransaction, Database, Database) -> Result<(), lmdb::Error>;
+
+  ///
+  /// Migrates the databases within each LMDB environment of the store at the given root to the
+  /// given LOCAL_STORE_VERSION, for use by a migration in `store_lock::MIGRATIONS` which changes
+  /// only their layout (adding a table, for example).
+  ///
+  /// Each environment is migrated in a single write transaction which also records the version in
+  /// its meta database, so a migration which is interrupted resumes with the environments which it
+  /// had not yet migrated, rather than re-applying itself to the others.
+  ///
+  // Unused until such a migration is added to MIGRATIONS.
+  #[allow(dead_code)]
+  pub fn migrate_lmdbs(
+    root: &Path,
+    version: u32,
+    migration: SchemaMigration,
+  ) -> Result<(), String> {
+    for dbs_root in &[root.join("files"), root.join("directories")] {
+      ShardedLmdb::new(dbs_root.clone())?.migrate(version, migration)?;
     }
+    Ok(())
   }
 
   // Each LMDB directory can have at most one concurrent writer.
@@ -1066,6 +2531,13 @@ mod local {
             )
           })?;
 
+        trace!("Making ShardedLmdb meta database for {:?}", dir);
+        // Created up front (rather than by a migration) so that every environment has the same
+        // databases.
+        env
+          .create_db(Some(META_DATABASE_NAME), DatabaseFlags::empty())
+          .map_err(|e| format!("Error creating/opening meta database at {:?}: {}", dir, e))?;
+
         lmdbs.insert(
           fingerprint_prefix,
           (Arc::new(env), content_database, lease_database),
@@ -1123,13 +2595,73 @@ mod local {
         // not try to perform multiple write transactions concurrently. Fortunately, this
         // property holds for us.
         .set_flags(EnvironmentFlags::NO_SYNC | EnvironmentFlags::NO_TLS)
-        // 2 DBs; one for file contents, one for leases.
-        .set_max_dbs(2)
+        // 3 DBs; one for file contents, one for leases, and one for metadata (such as the schema
+        // version).
+        .set_max_dbs(3)
         .set_map_size(MAX_LOCAL_STORE_SIZE_BYTES)
         .open(dir)
         .map_err(|e| format!("Error making env for store at {:?}: {}", dir, e))
     }
 
+    ///
+    /// Applies the given migration to each environment which has not already been migrated to the
+    /// given version.
+    ///
+    fn migrate(&self, version: u32, migration: SchemaMigration) -> Result<(), String> {
+      for (env, content_database, lease_database) in self.all_lmdbs() {
+        let meta_database = env
+          .open_db(Some(META_DATABASE_NAME))
+          .map_err(|e| format!("Error opening meta database at {:?}: {}", self.root_path, e))?;
+        let mut txn = env
+          .begin_rw_txn()
+          .map_err(|e| format!("Failed to begin write transaction: {}", e))?;
+        let migrated = ShardedLmdb::schema_version(&txn, meta_database, &self.root_path)?
+          .map_or(false, |migrated| migrated >= version);
+        if migrated {
+          continue;
+        }
+        migration(&mut txn, content_database, lease_database).map_err(|e| {
+          format!(
+            "Error migrating the store at {:?} to version {}: {}",
+            self.root_path, version, e
+          )
+        })?;
+        txn
+          .put(
+            meta_database,
+            &SCHEMA_VERSION_KEY,
+            &version.to_be_bytes(),
+            WriteFlags::empty(),
+          )
+          .and_then(|()| txn.commit())
+          .map_err(|e| format!("Error recording the version of {:?}: {}", self.root_path, e))?;
+      }
+      Ok(())
+    }
+
+    fn schema_version<T: Transaction>(
+      txn: &T,
+      meta_database: Database,
+      dir: &Path,
+    ) -> Result<Option<u32>, String> {
+      match txn.get(meta_database, &SCHEMA_VERSION_KEY) {
+        Ok(bytes) if bytes.len() == 4 => {
+          let mut version = [0; 4];
+          version.copy_from_slice(bytes);
+          Ok(Some(u32::from_be_bytes(version)))
+        }
+        Ok(bytes) => Err(format!(
+          "Invalid schema version in the store at {:?}: {:?}",
+          dir, bytes
+        )),
+        Err(NotFound) => Ok(None),
+        Err(e) => Err(format!(
+          "Error reading the schema version of the store at {:?}: {}",
+          dir, e
+        )),
+      }
+    }
+
     // First Database is content, second is leases.
     pub fn get(&self, fingerprint: &Fingerprint) -> (Arc<Environment>, Database, Database) {
       self.lmdbs[&(fingerprint.0[0] & 0xF0)].clone()
@@ -1139,6 +2671,49 @@ mod local {
       self.lmdbs.values().cloned().collect()
     }
 
+    // The shard with the given fingerprint prefix.
+    pub fn shard(&self, fingerprint_prefix: u8) -> (Arc<Environment>, Database, Database) {
+      self.lmdbs[&fingerprint_prefix].clone()
+    }
+
+    // As all_lmdbs, but including the fingerprint prefix of each shard, and sorted by it.
+    pub fn shards(&self) -> Vec<(u8, Arc<Environment>, Database, Database)> {
+      let mut shards = self
+        .lmdbs
+        .iter()
+        .map(|(prefix, (env, content, leases))| (*prefix, env.clone(), *content, *leases))
+        .collect::<Vec<_>>();
+      shards.sort_by_key(|shard| shard.0);
+      shards
+    }
+
+    // The size of the data file of each shard, sorted by fingerprint prefix. LMDB memory maps the
+    // whole file, so this bounds the memory used to access the shard.
+    pub fn data_file_sizes(&self) -> Result<Vec<(PathBuf, u64)>, String> {
+      self
+        .shards()
+        .into_iter()
+        .map(|(fingerprint_prefix, ..)| {
+          let path = self
+            .root_path
+            .join(format!("{:x}", fingerprint_prefix >> 4))
+            .join("data.mdb");
+          std::fs::metadata(&path)
+            .map(|metadata| (path.clone(), metadata.len()))
+            .map_err(|e| format!("Failed to stat {:?}: {}", path, e))
+        })
+        .collect()
+    }
+
+    // Files which are memory mapped (as the store's files are, while it is open) cannot be removed
+    // on Windows, so compaction (which replaces them) is not supported there: space freed by garbage
+    // collection is instead reused by future writes.
+    #[cfg(windows)]
+    pub fn compact(&self) -> Result<(), String> {
+      Ok(())
+    }
+
+    #[cfg(unix)]
     pub fn compact(&self) -> Result<(), String> {
       for (env, old_dir, _) in ShardedLmdb::envs(&self.root_path)? {
         let new_dir = TempDir::new_in(old_dir.parent().unwrap()).expect("TODO");
@@ -1170,6 +2745,22 @@ mod local {
     }
   }
 
+  ///
+  /// Returns the time (in seconds since the epoch) until which the given key is leased, or None if
+  /// it has never been leased.
+  ///
+  fn lease_until<T: Transaction>(txn: &T, lease_database: Database, key: &[u8]) -> Option<u64> {
+    match txn.get(lease_database, &key) {
+      Ok(b) => {
+        let mut array = [0_u8; 8];
+        array.copy_from_slice(b);
+        Some(u64::from_le_bytes(array))
+      }
+      Err(NotFound) => None,
+      Err(e) => panic!("Error reading lease, probable lmdb corruption: {:?}", e),
+    }
+  }
+
   #[derive(Eq, PartialEq, Ord, PartialOrd)]
   struct AgedFingerprint {
     // expired_seconds_ago must be the first field for the Ord implementation.
@@ -1182,9 +2773,14 @@ mod local {
   #[cfg(test)]
   pub mod tests {
     use super::super::tests::block_on;
-    use super::{ByteStore, EntryType, ShrinkBehavior};
+    use super::{
+      migrate_lmdbs, ByteStore, EntryType, ShardedLmdb, ShrinkBehavior, META_DATABASE_NAME,
+      SCHEMA_VERSION_KEY,
+    };
+    use crate::Bucket;
     use bytes::{BufMut, Bytes, BytesMut};
     use hashing::{Digest, Fingerprint};
+    use lmdb::{Transaction, WriteFlags};
     use std::path::Path;
     use tempfile::TempDir;
     use testutil::data::{TestData, TestDirectory};
@@ -1201,6 +2797,94 @@ mod local {
       );
     }
 
+    #[test]
+    fn lmdb_sizes() {
+      let dir = TempDir::new().unwrap();
+      let store = new_store(dir.path());
+      block_on(store.store_bytes(EntryType::File, TestData::roland().bytes(), false)).unwrap();
+
+      let sizes = store.lmdb_sizes().unwrap();
+      // One data file for each of the 16 shards of files and of directories.
+      assert_eq!(sizes.len(), 32);
+      for (path, size) in sizes {
+        assert!(path.starts_with(dir.path()));
+        assert!(path.ends_with("data.mdb"));
+        assert!(size > 0);
+      }
+    }
+
+    #[test]
+    fn summary() {
+      let dir = TempDir::new().unwrap();
+      let store = new_store(dir.path());
+      let roland = TestData::roland();
+      let testdir = TestDirectory::containing_roland();
+      block_on(store.store_bytes(EntryType::File, roland.bytes(), true)).unwrap();
+      block_on(store.store_bytes(EntryType::Directory, testdir.bytes(), false)).unwrap();
+
+      let summary = store.summary().unwrap();
+      assert_eq!(summary.blob_count, 2);
+      assert_eq!(summary.total_bytes, roland.len() + testdir.bytes().len());
+      assert_eq!(summary.shards.len(), 32);
+      let count = |buckets: &[Bucket], label: &str| {
+        buckets
+          .iter()
+          .find(|bucket| bucket.label == label)
+          .unwrap()
+          .count
+      };
+      assert_eq!(count(&summary.sizes, "<=1KiB"), 2);
+      assert_eq!(count(&summary.lease_ages, "leased"), 1);
+      assert_eq!(count(&summary.lease_ages, "never leased"), 1);
+
+      let file_shard = summary
+        .shards
+        .iter()
+        .find(|shard| shard.entry_type == "file" && shard.blob_count > 0)
+        .unwrap();
+      assert_eq!(file_shard.shard, &roland.fingerprint().to_hex()[0..1]);
+      assert_eq!(file_shard.total_bytes, roland.len());
+    }
+
+    // Records each migration in the content database which it is applied to.
+    fn record_migration(
+      txn: &mut lmdb::RwTransaction,
+      content_database: lmdb::Database,
+      _lease_database: lmdb::Database,
+    ) -> Result<(), lmdb::Error> {
+      let count = txn
+        .get(content_database, b"migrations")
+        .map(|bytes| bytes[0])
+        .unwrap_or(0);
+      txn.put(
+        content_database,
+        b"migrations",
+        &[count + 1],
+        WriteFlags::empty(),
+      )
+    }
+
+    #[test]
+    fn migrate_lmdbs_once_per_version() {
+      let dir = TempDir::new().unwrap();
+      migrate_lmdbs(dir.path(), 2, record_migration).unwrap();
+      migrate_lmdbs(dir.path(), 2, record_migration).unwrap();
+      migrate_lmdbs(dir.path(), 3, record_migration).unwrap();
+
+      for dbs_root in &[dir.path().join("files"), dir.path().join("directories")] {
+        let lmdbs = ShardedLmdb::new(dbs_root.clone()).unwrap();
+        for (env, content_database, _) in lmdbs.all_lmdbs() {
+          let meta_database = env.open_db(Some(META_DATABASE_NAME)).unwrap();
+          let txn = env.begin_ro_txn().unwrap();
+          assert_eq!(txn.get(content_database, b"migrations"), Ok(&[2][..]));
+          assert_eq!(
+            txn.get(meta_database, &SCHEMA_VERSION_KEY),
+            Ok(&3_u32.to_be_bytes()[..])
+          );
+        }
+      }
+    }
+
     #[test]
     fn save_file_is_idempotent() {
       let dir = TempDir::new().unwrap();
@@ -1509,6 +3193,86 @@ mod local {
       // Whether the unleased file is present is undefined.
     }
 
+    #[test]
+    fn garbage_collect_shared_content_respects_leases_of_each_metadata_dir() {
+      let content_dir = TempDir::new().unwrap();
+      let metadata_dir_a = TempDir::new().unwrap();
+      let metadata_dir_b = TempDir::new().unwrap();
+      let store = new_store(content_dir.path());
+      let store_a = store
+        .clone()
+        .with_metadata_dir(metadata_dir_a.path())
+        .unwrap();
+      let store_b = store
+        .clone()
+        .with_metadata_dir(metadata_dir_b.path())
+        .unwrap();
+
+      let roland = TestData::roland();
+      let fourty_chars = TestData::fourty_chars();
+      block_on(store_a.store_bytes(EntryType::File, roland.bytes(), true)).expect("Error storing");
+      block_on(store_b.store_bytes(EntryType::File, fourty_chars.bytes(), true))
+        .expect("Error storing");
+      let unleased = prime_store_with_file_bytes(&store_a, TestData::catnip().bytes());
+
+      // Each store only sees its own leases...
+      let count = |store: &ByteStore, label: &str| {
+        store
+          .summary()
+          .unwrap()
+          .lease_ages
+          .iter()
+          .find(|bucket| bucket.label == label)
+          .unwrap()
+          .count
+      };
+      assert_eq!(count(&store_a, "leased"), 1);
+      assert_eq!(count(&store_b, "leased"), 1);
+
+      // ...but garbage collection by either respects the leases of both.
+      assert_eq!(
+        store_a.shrink(0, ShrinkBehavior::Fast),
+        Ok(roland.len() + fourty_chars.len())
+      );
+      assert_eq!(load_file_bytes(&store_b, unleased), Ok(None));
+      assert_eq!(
+        load_file_bytes(&store_a, fourty_chars.digest()),
+        Ok(Some(fourty_chars.bytes()))
+      );
+
+      // Once a metadata directory is removed, its leases are no longer respected.
+      drop(store_b);
+      drop(metadata_dir_b);
+      assert_eq!(store_a.shrink(0, ShrinkBehavior::Fast), Ok(roland.len()));
+      assert_eq!(load_file_bytes(&store_a, fourty_chars.digest()), Ok(None));
+      assert_eq!(
+        load_file_bytes(&store_a, roland.digest()),
+        Ok(Some(roland.bytes()))
+      );
+    }
+
+    #[test]
+    fn metadata_dir_adopts_leases_stored_alongside_content() {
+      let content_dir = TempDir::new().unwrap();
+      let metadata_dir = TempDir::new().unwrap();
+      let store = new_store(content_dir.path());
+      let roland = TestData::roland();
+      block_on(store.store_bytes(EntryType::File, roland.bytes(), true)).expect("Error storing");
+
+      let split_store = store.with_metadata_dir(metadata_dir.path()).unwrap();
+      let summary = split_store.summary().unwrap();
+      assert_eq!(summary.blob_count, 1);
+      assert_eq!(
+        summary
+          .lease_ages
+          .iter()
+          .find(|bucket| bucket.label == "leased")
+          .unwrap()
+          .count,
+        1
+      );
+    }
+
     #[test]
     fn garbage_collect_and_compact() {
       let dir = TempDir::new().unwrap();
@@ -1537,12 +3301,15 @@ mod local {
         .shrink(1024 * 1024, ShrinkBehavior::Compact)
         .expect("Error shrinking");
 
-      let size = get_directory_size(dir.path());
-      assert!(
-        size < 2 * 1024 * 1024,
-        "Expect size to be less than 2MB but was {}",
-        size
-      );
+      // Compaction is not supported on Windows.
+      if cfg!(unix) {
+        let size = get_directory_size(dir.path());
+        assert!(
+          size < 2 * 1024 * 1024,
+          "Expect size to be less than 2MB but was {}",
+          size
+        );
+      }
     }
 
     #[test]
@@ -1654,7 +3421,9 @@ mod local {
 }
 
 mod remote {
-  use super::{BackoffConfig, EntryType};
+  use super::{BackoffConfig, EntryType, ProxyConfig};
+  use crate::offline_error;
+  use crate::transfer::{TransferDirection, TransferLimiter, TransferLimits};
 
   use bazel_protos;
   use boxfuture::{BoxFuture, Boxable};
@@ -1663,23 +3432,53 @@ mod remote {
   use futures::{self, future, Future, IntoFuture, Sink, Stream};
   use grpcio;
   use hashing::{Digest, Fingerprint};
+  use metrics::{self, Metric, ObservationMetric};
   use serverset::{Retry, Serverset};
   use sha2::Sha256;
-  use std::cmp::min;
+  use std::cmp::{max, min};
   use std::collections::HashSet;
   use std::sync::Arc;
   use std::time::Duration;
   use uuid;
 
+  ///
+  /// Deadlines for the RPCs made to a remote CAS. Each deadline applies to a single attempt of a
+  /// single RPC: for example, a read of one large blob is not bounded by the deadline for a
+  /// FindMissingBlobs request.
+  ///
+  #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+  pub struct RemoteStoreTimeouts {
+    pub find_missing_blobs: Duration,
+    // Covers a ByteStream read of a complete blob.
+    pub read: Duration,
+    // Covers a ByteStream write of a complete blob.
+    pub write: Duration,
+  }
+
+  impl Default for RemoteStoreTimeouts {
+    fn default() -> RemoteStoreTimeouts {
+      RemoteStoreTimeouts {
+        find_missing_blobs: Duration::from_secs(30),
+        read: Duration::from_secs(60),
+        write: Duration::from_secs(60),
+      }
+    }
+  }
+
   #[derive(Clone)]
   pub struct ByteStore {
     instance_name: Option<String>,
     chunk_size_bytes: usize,
-    upload_timeout: Duration,
+    timeouts: RemoteStoreTimeouts,
     rpc_attempts: usize,
     env: Arc<grpcio::Environment>,
     serverset: Serverset<grpcio::Channel>,
     authorization_header: Option<String>,
+    transfer_limiter: TransferLimiter,
+    // The number of chunks of an upload which may be buffered before they are flushed.
+    upload_window_chunks: usize,
+    // If true, every RPC fails immediately rather than being attempted.
+    offline: bool,
   }
 
   impl ByteStore {
@@ -1687,6 +3486,7 @@ mod remote {
       cas_addresses: &[String],
       instance_name: Option<String>,
       root_ca_certs: &Option<Vec<u8>>,
+      proxies: &ProxyConfig,
       oauth_bearer_token: Option<String>,
       thread_count: usize,
       chunk_size_bytes: usize,
@@ -1700,7 +3500,11 @@ mod remote {
       let channels = cas_addresses
         .iter()
         .map(|cas_address| {
-          let builder = grpcio::ChannelBuilder::new(env.clone());
+          let builder = proxies.configure_channel(
+            grpcio::ChannelBuilder::new(env.clone()),
+            cas_address,
+            root_ca_certs.is_some(),
+          );
           if let Some(ref root_ca_certs) = root_ca_certs {
             let creds = grpcio::ChannelCredentialsBuilder::new()
               .root_cert(root_ca_certs.clone())
@@ -1712,19 +3516,65 @@ mod remote {
         })
         .collect();
 
+      let transfer_limiter =
+        TransferLimiter::new(TransferLimits::default(), futures_timer_thread.clone());
       let serverset = Serverset::new(channels, backoff_config, futures_timer_thread)?;
 
       Ok(ByteStore {
         instance_name,
         chunk_size_bytes,
-        upload_timeout,
+        timeouts: RemoteStoreTimeouts {
+          write: upload_timeout,
+          ..RemoteStoreTimeouts::default()
+        },
         rpc_attempts: rpc_retries + 1,
         env,
         serverset,
         authorization_header: oauth_bearer_token.map(|t| format!("Bearer {}", t)),
+        transfer_limiter,
+        upload_window_chunks: 1,
+        offline: false,
       })
     }
 
+    ///
+    /// Allows up to the given number of chunks of each upload to be buffered before they are
+    /// flushed, rather than waiting for each chunk to be written before sending the next one. A
+    /// larger window improves throughput on links with a high bandwidth-delay product.
+    ///
+    pub fn with_upload_window_chunks(self, upload_window_chunks: usize) -> ByteStore {
+      ByteStore {
+        upload_window_chunks: max(upload_window_chunks, 1),
+        ..self
+      }
+    }
+
+    pub fn with_timeouts(self, timeouts: RemoteStoreTimeouts) -> ByteStore {
+      ByteStore { timeouts, ..self }
+    }
+
+    pub fn into_offline(self) -> ByteStore {
+      ByteStore {
+        offline: true,
+        ..self
+      }
+    }
+
+    pub fn transfer_limiter(&self) -> &TransferLimiter {
+      &self.transfer_limiter
+    }
+
+    ///
+    /// Replaces the TransferLimiter which uploads and downloads are run through, which may be
+    /// shared with other ByteStores.
+    ///
+    pub fn with_transfer_limiter(self, transfer_limiter: TransferLimiter) -> ByteStore {
+      ByteStore {
+        transfer_limiter,
+        ..self
+      }
+    }
+
     fn with_byte_stream_client<
       Value: Send + 'static,
       Fut: Future<Item = Value, Error = String>,
@@ -1782,6 +3632,18 @@ mod remote {
     }
 
     pub fn store_bytes(&self, bytes: Bytes) -> BoxFuture<Digest, String> {
+      if self.offline {
+        return future::err(offline_error("upload to the remote store")).to_boxed();
+      }
+      let store = self.clone();
+      self
+        .transfer_limiter
+        .transfer(TransferDirection::Upload, bytes.len(), move || {
+          store.store_bytes_unlimited(bytes)
+        })
+    }
+
+    fn store_bytes_unlimited(&self, bytes: Bytes) -> BoxFuture<Digest, String> {
       let mut hasher = Sha256::default();
       hasher.input(&bytes);
       let fingerprint = Fingerprint::from_bytes_unsafe(hasher.fixed_result().as_slice());
@@ -1798,7 +3660,7 @@ mod remote {
       self
         .with_byte_stream_client(move |client| {
           match client
-            .write_opt(store.call_option().timeout(store.upload_timeout))
+            .write_opt(store.call_option().timeout(store.timeouts.write))
             .map(|v| (v, client))
           {
             Err(err) => future::err(format!(
@@ -1808,6 +3670,8 @@ mod remote {
             .to_boxed(),
             Ok(((sender, receiver), _client)) => {
               let chunk_size_bytes = store.chunk_size_bytes;
+              let upload_window_chunks = store.upload_window_chunks;
+              let transfer_limiter = store.transfer_limiter.clone();
               let resource_name = resource_name.clone();
               let bytes = bytes.clone();
               let stream = futures::stream::unfold::<
@@ -1823,10 +3687,15 @@ mod remote {
                   req.set_resource_name(resource_name.clone());
                   req.set_write_offset(offset as i64);
                   let next_offset = min(offset + chunk_size_bytes, bytes.len());
-                  req.set_finish_write(next_offset == bytes.len());
+                  let finish_write = next_offset == bytes.len();
+                  req.set_finish_write(finish_write);
                   req.set_data(bytes.slice(offset, next_offset));
+                  // Buffer all but the last chunk of each window (and of the upload), so that the
+                  // window is flushed together.
+                  let chunk_index = offset / chunk_size_bytes;
+                  let buffer_hint = !finish_write && (chunk_index + 1) % upload_window_chunks != 0;
                   Some(future::ok((
-                    (req, grpcio::WriteFlags::default()),
+                    (req, grpcio::WriteFlags::default().buffer_hint(buffer_hint)),
                     (next_offset, true),
                   )))
                 }
@@ -1865,6 +3734,13 @@ mod remote {
                 })
                 .and_then(move |received| {
                   if received.get_committed_size() == len as i64 {
+                    metrics::increment_counter(Metric::RemoteStoreBlobsUploaded, 1);
+                    metrics::increment_counter(Metric::RemoteStoreBytesUploaded, len as u64);
+                    transfer_limiter.record_transferred(TransferDirection::Upload, len);
+                    metrics::record_observation(
+                      ObservationMetric::RemoteStoreUploadSizeBytes,
+                      len as u64,
+                    );
                     Ok(digest)
                   } else {
                     Err(format!(
@@ -1883,6 +3759,30 @@ mod remote {
     }
 
     pub fn load_bytes_with<T: Send + 'static, F: Fn(Bytes) -> T + Send + Sync + Clone + 'static>(
+      &self,
+      entry_type: EntryType,
+      digest: Digest,
+      f: F,
+    ) -> BoxFuture<Option<T>, String> {
+      if self.offline {
+        return future::err(offline_error(&format!(
+          "load {:?} from the remote store",
+          digest
+        )))
+        .to_boxed();
+      }
+      let store = self.clone();
+      self
+        .transfer_limiter
+        .transfer(TransferDirection::Download, digest.1, move || {
+          store.load_bytes_with_unlimited(entry_type, digest, f)
+        })
+    }
+
+    fn load_bytes_with_unlimited<
+      T: Send + 'static,
+      F: Fn(Bytes) -> T + Send + Sync + Clone + 'static,
+    >(
       &self,
       _entry_type: EntryType,
       digest: Digest,
@@ -1906,12 +3806,13 @@ mod remote {
                 req.set_read_limit(0);
                 req
               },
-              store.call_option(),
+              store.call_option().timeout(store.timeouts.read),
             )
             .map(|stream| (stream, client))
           {
             Ok((stream, client)) => {
               let f = f.clone();
+              let transfer_limiter = store.transfer_limiter.clone();
               // We shouldn't have to pass around the client here, it's a workaround for
               // https://github.com/pingcap/grpc-rs/issues/123
               future::ok(client)
@@ -1921,7 +3822,14 @@ mod remote {
                     future::ok::<_, grpcio::Error>(bytes)
                   }),
                 )
-                .map(|(_client, bytes)| Some(bytes.freeze()))
+                .map(move |(_client, bytes)| {
+                  let len = bytes.len() as u64;
+                  metrics::increment_counter(Metric::RemoteStoreBlobsDownloaded, 1);
+                  metrics::increment_counter(Metric::RemoteStoreBytesDownloaded, len);
+                  transfer_limiter.record_transferred(TransferDirection::Download, bytes.len());
+                  metrics::record_observation(ObservationMetric::RemoteStoreDownloadSizeBytes, len);
+                  Some(bytes.freeze())
+                })
                 .or_else(|e| match e {
                   grpcio::Error::RpcFailure(grpcio::RpcStatus {
                     status: grpcio::RpcStatusCode::NotFound,
@@ -1953,10 +3861,20 @@ mod remote {
       &self,
       request: bazel_protos::remote_execution::FindMissingBlobsRequest,
     ) -> impl Future<Item = HashSet<Digest>, Error = String> {
+      if self.offline {
+        return future::Either::A(future::err(offline_error(
+          "list the missing digests of the remote store",
+        )));
+      }
       let store = self.clone();
-      self.with_cas_client(move |client| {
+      future::Either::B(self.with_cas_client(move |client| {
         client
-          .find_missing_blobs_opt(&request, store.call_option())
+          .find_missing_blobs_opt(
+            &request,
+            store
+              .call_option()
+              .timeout(store.timeouts.find_missing_blobs),
+          )
           .map_err(|err| {
             format!(
               "Error from server in response to find_missing_blobs_request: {:?}",
@@ -1970,7 +3888,7 @@ mod remote {
               .map(|digest| digest.into())
               .collect()
           })
-      })
+      }))
     }
 
     pub(super) fn find_missing_blobs_request<'a, Digests: Iterator<Item = &'a Digest>>(
@@ -1991,11 +3909,11 @@ mod remote {
   #[cfg(test)]
   mod tests {
     use super::super::EntryType;
-    use super::ByteStore;
+    use super::{ByteStore, ProxyConfig, RemoteStoreTimeouts};
     use bytes::Bytes;
     use futures_timer::TimerHandle;
     use hashing::Digest;
-    use mock::StubCAS;
+    use mock::{Faults, StubCAS};
     use serverset::BackoffConfig;
     use std::collections::HashSet;
     use std::time::Duration;
@@ -2016,6 +3934,35 @@ mod remote {
       );
     }
 
+    #[test]
+    fn read_timeout() {
+      let testdata = TestData::roland();
+      let cas = StubCAS::builder()
+        .file(&testdata)
+        .read_delay(Duration::from_millis(500))
+        .build();
+
+      let store = new_byte_store(&cas).with_timeouts(RemoteStoreTimeouts {
+        read: Duration::from_millis(50),
+        ..RemoteStoreTimeouts::default()
+      });
+      let error = load_file_bytes(&store, testdata.digest()).expect_err("Want error");
+      assert!(
+        error.contains("DeadlineExceeded"),
+        "Bad error message, got: {}",
+        error
+      );
+
+      let store = new_byte_store(&cas).with_timeouts(RemoteStoreTimeouts {
+        read: Duration::from_secs(5),
+        ..RemoteStoreTimeouts::default()
+      });
+      assert_eq!(
+        load_file_bytes(&store, testdata.digest()),
+        Ok(Some(testdata.bytes()))
+      );
+    }
+
     #[test]
     fn missing_file() {
       let cas = StubCAS::empty();
@@ -2144,6 +4091,7 @@ mod remote {
         &[cas.address()],
         None,
         &None,
+        &ProxyConfig::default(),
         None,
         1,
         10 * 1024,
@@ -2180,6 +4128,40 @@ mod remote {
       }
     }
 
+    #[test]
+    fn write_file_multiple_chunks_with_window() {
+      let cas = StubCAS::empty();
+
+      let store = ByteStore::new(
+        &[cas.address()],
+        None,
+        &None,
+        &ProxyConfig::default(),
+        None,
+        1,
+        10 * 1024,
+        Duration::from_secs(5),
+        BackoffConfig::new(Duration::from_millis(10), 1.0, Duration::from_millis(10)).unwrap(),
+        1,
+        TimerHandle::default(),
+      )
+      .unwrap()
+      .with_upload_window_chunks(8);
+
+      let all_the_henries = big_file_bytes();
+      assert_eq!(
+        block_on(store.store_bytes(all_the_henries.clone())),
+        Ok(big_file_digest())
+      );
+
+      // Buffered chunks are still sent as individual messages.
+      assert_eq!(
+        cas.blobs.lock().get(&big_file_fingerprint()),
+        Some(&all_the_henries)
+      );
+      assert_eq!(cas.write_message_sizes.lock().len(), 98);
+    }
+
     #[test]
     fn write_empty_file() {
       let empty_file = TestData::empty();
@@ -2220,6 +4202,7 @@ mod remote {
         &[String::from("doesnotexist.example")],
         None,
         &None,
+        &ProxyConfig::default(),
         None,
         1,
         10 * 1024 * 1024,
@@ -2282,6 +4265,122 @@ mod remote {
       );
     }
 
+    #[test]
+    fn list_missing_digests_latency_timeout() {
+      let cas = StubCAS::builder()
+        .faults(Faults {
+          latency: Some(Duration::from_millis(500)),
+          ..Faults::default()
+        })
+        .build();
+
+      let store = new_byte_store(&cas).with_timeouts(RemoteStoreTimeouts {
+        find_missing_blobs: Duration::from_millis(50),
+        ..RemoteStoreTimeouts::default()
+      });
+      let error = block_on(store.list_missing_digests(
+        store.find_missing_blobs_request(vec![TestData::roland().digest()].iter()),
+      ))
+      .expect_err("Want error");
+      assert!(
+        error.contains("DeadlineExceeded"),
+        "Bad error message, got: {}",
+        error
+      );
+    }
+
+    #[test]
+    fn offline_fails_without_making_requests() {
+      let testdata = TestData::roland();
+      let cas = StubCAS::builder().file(&testdata).build();
+      let store = new_byte_store(&cas).into_offline();
+
+      let errors = vec![
+        block_on(store.store_bytes(testdata.bytes())).expect_err("Want error"),
+        block_on(store.load_bytes_with(EntryType::File, testdata.digest(), |b| b))
+          .expect_err("Want error"),
+        block_on(
+          store
+            .list_missing_digests(store.find_missing_blobs_request(vec![testdata.digest()].iter())),
+        )
+        .expect_err("Want error"),
+      ];
+      for error in errors {
+        assert!(
+          error.contains("offline mode"),
+          "Bad error message, got: {}",
+          error
+        );
+      }
+      assert_eq!(cas.request_count(), 0);
+    }
+
+    #[test]
+    fn load_retries_injected_errors() {
+      let testdata = TestData::roland();
+      let cas = StubCAS::builder()
+        .file(&testdata)
+        .faults(Faults {
+          error_rate: 0.5,
+          ..Faults::default()
+        })
+        .build();
+
+      // The first request fails, and the retry succeeds.
+      assert_eq!(
+        load_file_bytes(&new_byte_store(&cas), testdata.digest()),
+        Ok(Some(testdata.bytes()))
+      );
+      assert_eq!(cas.request_count(), 2);
+    }
+
+    #[test]
+    fn write_partially_committed_is_error() {
+      let testdata = TestData::roland();
+      let cas = StubCAS::builder()
+        .faults(Faults {
+          partial_write_bytes: Some(1),
+          ..Faults::default()
+        })
+        .build();
+
+      let error =
+        block_on(new_byte_store(&cas).store_bytes(testdata.bytes())).expect_err("Want error");
+      assert!(
+        error.contains("want commited size"),
+        "Bad error message, got: {}",
+        error
+      );
+      assert_eq!(cas.blobs.lock().get(&testdata.fingerprint()), None);
+    }
+
+    #[test]
+    fn write_evicted_between_calls_is_missing() {
+      let testdata = TestData::roland();
+      let cas = StubCAS::builder()
+        .faults(Faults {
+          gc_between_calls: true,
+          ..Faults::default()
+        })
+        .build();
+
+      let store = new_byte_store(&cas);
+      assert_eq!(
+        block_on(store.store_bytes(testdata.bytes())),
+        Ok(testdata.digest())
+      );
+
+      let mut digest_set = HashSet::new();
+      digest_set.insert(testdata.digest());
+      assert_eq!(
+        block_on(
+          store
+            .list_missing_digests(store.find_missing_blobs_request(vec![testdata.digest()].iter()))
+        ),
+        Ok(digest_set)
+      );
+    }
+
     #[test]
     fn reads_from_multiple_cas_servers() {
       let roland = TestData::roland();
@@ -2294,6 +4393,7 @@ mod remote {
         &[cas1.address(), cas2.address()],
         None,
         &None,
+        &ProxyConfig::default(),
         None,
         1,
         10 * 1024 * 1024,
@@ -2323,6 +4423,7 @@ mod remote {
         &[cas.address()],
         None,
         &None,
+        &ProxyConfig::default(),
         None,
         1,
         10 * 1024 * 1024,
@@ -2357,7 +4458,12 @@ mod remote {
 
 #[cfg(test)]
 mod tests {
-  use super::{local, EntryType, FileContent, Store, UploadSummary};
+  use super::{
+    local, ConflictPolicy, EntryType, FileContent, MaterializeProgress, Store, UploadScope,
+    UploadSummary, WorkspaceManifest,
+  };
+  use crate::transfer::{TransferLimits, TransferStats};
+  use crate::ProxyConfig;
 
   use bazel_protos;
   use bytes::Bytes;
@@ -2373,8 +4479,8 @@ mod tests {
   use std::collections::HashMap;
   use std::fs::File;
   use std::io::Read;
-  use std::os::unix::fs::PermissionsExt;
   use std::path::{Path, PathBuf};
+  use std::sync::atomic::Ordering;
   use std::time::Duration;
   use tempfile::TempDir;
   use testutil::data::{TestData, TestDirectory};
@@ -2445,8 +4551,36 @@ mod tests {
     Store::with_remote(
       dir,
       &[cas_address],
+      &[],
+      None,
+      &None,
+      &ProxyConfig::default(),
+      None,
+      1,
+      10 * 1024 * 1024,
+      Duration::from_secs(1),
+      BackoffConfig::new(Duration::from_millis(10), 1.0, Duration::from_millis(10)).unwrap(),
+      1,
+      TimerHandle::default(),
+    )
+    .unwrap()
+  }
+
+  ///
+  /// Create a new store with a nearby remote CAS, which caches a central remote CAS.
+  ///
+  fn new_tiered_store<P: AsRef<Path>>(
+    dir: P,
+    cas_address: String,
+    central_cas_address: String,
+  ) -> Store {
+    Store::with_remote(
+      dir,
+      &[cas_address],
+      &[central_cas_address],
       None,
       &None,
+      &ProxyConfig::default(),
       None,
       1,
       10 * 1024 * 1024,
@@ -2719,6 +4853,52 @@ mod tests {
     );
   }
 
+  #[test]
+  fn parent_path_remote_directory_is_error() {
+    let mut directory = bazel_protos::remote_execution::Directory::new();
+    directory.mut_files().push({
+      let mut file = bazel_protos::remote_execution::FileNode::new();
+      file.set_name("..".to_string());
+      file.set_digest((&TestData::roland().digest()).into());
+      file
+    });
+    let directory_bytes = Bytes::from(directory.write_to_bytes().expect("Error serializing proto"));
+    assert_remote_directory_bytes_rejected(directory_bytes, "non-canonical");
+  }
+
+  #[test]
+  fn invalid_unicode_remote_directory_is_error() {
+    // A Directory containing a single FileNode whose name is the invalid UTF-8 byte 0xff.
+    let directory_bytes = Bytes::from(vec![0x0a, 0x03, 0x0a, 0x01, 0xff]);
+    assert_remote_directory_bytes_rejected(directory_bytes, "was not valid");
+  }
+
+  fn assert_remote_directory_bytes_rejected(directory_bytes: Bytes, expected_error: &str) {
+    let fingerprint = {
+      let mut hasher = Sha256::default();
+      hasher.input(&directory_bytes);
+      Fingerprint::from_bytes_unsafe(hasher.fixed_result().as_slice())
+    };
+    let directory_digest = Digest(fingerprint, directory_bytes.len());
+
+    let dir = TempDir::new().unwrap();
+
+    let cas = StubCAS::builder()
+      .unverified_content(fingerprint, directory_bytes)
+      .build();
+    let error = block_on(new_store(dir.path(), cas.address()).load_directory(directory_digest))
+      .expect_err("Want error");
+    assert!(error.contains(expected_error), "Bad error: {}", error);
+
+    assert_eq!(
+      local::tests::load_directory_proto_bytes(
+        &local::tests::new_store(dir.path()),
+        directory_digest,
+      ),
+      Ok(None)
+    );
+  }
+
   #[test]
   fn wrong_remote_file_bytes_is_error() {
     let dir = TempDir::new().unwrap();
@@ -2797,62 +4977,156 @@ mod tests {
   }
 
   #[test]
-  fn expand_recursive_directory() {
+  fn expand_recursive_directory() {
+    let dir = TempDir::new().unwrap();
+
+    let roland = TestData::roland();
+    let catnip = TestData::catnip();
+    let testdir = TestDirectory::containing_roland();
+    let recursive_testdir = TestDirectory::recursive();
+
+    block_on(new_local_store(dir.path()).record_directory(&recursive_testdir.directory(), false))
+      .expect("Error storing directory locally");
+    block_on(new_local_store(dir.path()).record_directory(&testdir.directory(), false))
+      .expect("Error storing directory locally");
+
+    let expanded =
+      block_on(new_local_store(dir.path()).expand_directory(recursive_testdir.digest()))
+        .expect("Error expanding directory");
+    let want: HashMap<Digest, EntryType> = vec![
+      (recursive_testdir.digest(), EntryType::Directory),
+      (testdir.digest(), EntryType::Directory),
+      (roland.digest(), EntryType::File),
+      (catnip.digest(), EntryType::File),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(expanded, want);
+  }
+
+  #[test]
+  fn expand_missing_directory() {
+    let dir = TempDir::new().unwrap();
+    let digest = TestDirectory::containing_roland().digest();
+    let error =
+      block_on(new_local_store(dir.path()).expand_directory(digest)).expect_err("Want error");
+    assert!(
+      error.contains(&format!("{:?}", digest)),
+      "Bad error message: {}",
+      error
+    );
+  }
+
+  #[test]
+  fn expand_directory_missing_subdir() {
+    let dir = TempDir::new().unwrap();
+
+    let recursive_testdir = TestDirectory::recursive();
+
+    block_on(new_local_store(dir.path()).record_directory(&recursive_testdir.directory(), false))
+      .expect("Error storing directory locally");
+
+    let error = block_on(new_local_store(dir.path()).expand_directory(recursive_testdir.digest()))
+      .expect_err("Want error");
+    assert!(
+      error.contains(&format!(
+        "{}",
+        TestDirectory::containing_roland().fingerprint()
+      )),
+      "Bad error message: {}",
+      error
+    );
+  }
+
+  #[test]
+  fn load_tree_of_recursive_directory() {
+    let dir = TempDir::new().unwrap();
+
+    let testdir = TestDirectory::containing_roland();
+    let recursive_testdir = TestDirectory::recursive();
+
+    let store = new_local_store(dir.path());
+    block_on(store.record_directory(&recursive_testdir.directory(), false))
+      .expect("Error storing directory locally");
+    block_on(store.record_directory(&testdir.directory(), false))
+      .expect("Error storing directory locally");
+
+    let tree = block_on(store.load_tree(recursive_testdir.digest()))
+      .expect("Error loading tree")
+      .expect("Tree was missing");
+    assert_eq!(&recursive_testdir.directory(), tree.get_root());
+    assert_eq!(vec![testdir.directory()], tree.get_children().to_vec());
+  }
+
+  #[test]
+  fn load_tree_missing_directory() {
+    let dir = TempDir::new().unwrap();
+
+    let tree = block_on(new_local_store(dir.path()).load_tree(TestDirectory::recursive().digest()))
+      .expect("Error loading tree");
+    assert_eq!(None, tree);
+  }
+
+  #[test]
+  fn record_tree_roundtrips() {
     let dir = TempDir::new().unwrap();
+    let other_dir = TempDir::new().unwrap();
 
-    let roland = TestData::roland();
-    let catnip = TestData::catnip();
     let testdir = TestDirectory::containing_roland();
     let recursive_testdir = TestDirectory::recursive();
 
-    block_on(new_local_store(dir.path()).record_directory(&recursive_testdir.directory(), false))
+    let store = new_local_store(dir.path());
+    block_on(store.record_directory(&recursive_testdir.directory(), false))
       .expect("Error storing directory locally");
-    block_on(new_local_store(dir.path()).record_directory(&testdir.directory(), false))
+    block_on(store.record_directory(&testdir.directory(), false))
       .expect("Error storing directory locally");
+    let tree = block_on(store.load_tree(recursive_testdir.digest()))
+      .expect("Error loading tree")
+      .expect("Tree was missing");
 
-    let expanded =
-      block_on(new_local_store(dir.path()).expand_directory(recursive_testdir.digest()))
-        .expect("Error expanding directory");
-    let want: HashMap<Digest, EntryType> = vec![
-      (recursive_testdir.digest(), EntryType::Directory),
-      (testdir.digest(), EntryType::Directory),
-      (roland.digest(), EntryType::File),
-      (catnip.digest(), EntryType::File),
-    ]
-    .into_iter()
-    .collect();
-    assert_eq!(expanded, want);
+    let other_store = new_local_store(other_dir.path());
+    let digest = block_on(other_store.record_tree(&tree, false)).expect("Error recording tree");
+    assert_eq!(recursive_testdir.digest(), digest);
+    assert_eq!(
+      Some(testdir.directory()),
+      block_on(other_store.load_directory(testdir.digest())).expect("Error loading directory")
+    );
   }
 
   #[test]
-  fn expand_missing_directory() {
+  fn record_tree_missing_child() {
     let dir = TempDir::new().unwrap();
-    let digest = TestDirectory::containing_roland().digest();
+
+    let mut tree = bazel_protos::remote_execution::Tree::new();
+    tree.set_root(TestDirectory::recursive().directory());
+
     let error =
-      block_on(new_local_store(dir.path()).expand_directory(digest)).expect_err("Want error");
+      block_on(new_local_store(dir.path()).record_tree(&tree, false)).expect_err("Want error");
     assert!(
-      error.contains(&format!("{:?}", digest)),
+      error.contains("missing child Directory"),
       "Bad error message: {}",
       error
     );
   }
 
   #[test]
-  fn expand_directory_missing_subdir() {
+  fn record_tree_non_canonical() {
     let dir = TempDir::new().unwrap();
 
-    let recursive_testdir = TestDirectory::recursive();
-
-    block_on(new_local_store(dir.path()).record_directory(&recursive_testdir.directory(), false))
-      .expect("Error storing directory locally");
+    let mut root = bazel_protos::remote_execution::Directory::new();
+    for name in &["simba", "roland"] {
+      let mut file = bazel_protos::remote_execution::FileNode::new();
+      file.set_name((*name).to_owned());
+      file.set_digest((&TestData::roland().digest()).into());
+      root.mut_files().push(file);
+    }
+    let mut tree = bazel_protos::remote_execution::Tree::new();
+    tree.set_root(root);
 
-    let error = block_on(new_local_store(dir.path()).expand_directory(recursive_testdir.digest()))
-      .expect_err("Want error");
+    let error =
+      block_on(new_local_store(dir.path()).record_tree(&tree, false)).expect_err("Want error");
     assert!(
-      error.contains(&format!(
-        "{}",
-        TestDirectory::containing_roland().fingerprint()
-      )),
+      error.contains("non-canonical"),
       "Bad error message: {}",
       error
     );
@@ -2881,6 +5155,113 @@ mod tests {
     );
   }
 
+  #[test]
+  fn read_only_remote_backfills_but_does_not_upload() {
+    let dir = TempDir::new().unwrap();
+    let cas = new_cas(1024);
+    let store = new_store(dir.path(), cas.address()).into_read_only_remote();
+
+    let testdata = TestData::roland();
+    assert_eq!(
+      load_file_bytes(&store, testdata.digest()),
+      Ok(Some(testdata.bytes())),
+      "Read from CAS"
+    );
+    assert_eq!(
+      local::tests::load_file_bytes(&local::tests::new_store(dir.path()), testdata.digest()),
+      Ok(Some(testdata.bytes())),
+      "Read from local cache"
+    );
+
+    let catnip = TestData::catnip();
+    block_on(store.store_file_bytes(catnip.bytes(), false)).expect("Error storing file locally");
+    assert_eq!(
+      block_on(store.ensure_remote_has_recursive(vec![catnip.digest()])),
+      Err("Cannot upload blobs to a read-only remote".to_owned())
+    );
+    assert_eq!(cas.blobs.lock().get(&catnip.fingerprint()), None);
+  }
+
+  #[test]
+  fn records_transfer_stats() {
+    let dir = TempDir::new().unwrap();
+    let cas = new_cas(1024);
+    let store = new_store(dir.path(), cas.address()).with_transfer_limits(TransferLimits {
+      max_concurrent_transfers: Some(1),
+      max_bytes_per_second: Some(1024 * 1024),
+    });
+
+    let testdata = TestData::roland();
+    assert_eq!(
+      load_file_bytes(&store, testdata.digest()),
+      Ok(Some(testdata.bytes()))
+    );
+    assert_eq!(
+      load_file_bytes(&store, TestData::catnip().digest()),
+      Ok(None)
+    );
+
+    assert_eq!(
+      store.transfer_stats(),
+      Some(TransferStats {
+        bytes_downloaded: testdata.len() as u64,
+        ..TransferStats::default()
+      })
+    );
+    assert_eq!(new_local_store(dir.path()).transfer_stats(), None);
+  }
+
+  #[test]
+  fn load_file_falls_back_to_central_cas_and_writes_back() {
+    let dir = TempDir::new().unwrap();
+    let nearby_cas = StubCAS::empty();
+    let central_cas = new_cas(1024);
+    let store = new_tiered_store(dir.path(), nearby_cas.address(), central_cas.address());
+
+    let testdata = TestData::roland();
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    assert_eq!(
+      runtime.block_on(store.load_file_bytes_with(testdata.digest(), |bytes| bytes)),
+      Ok(Some(testdata.bytes()))
+    );
+    // Wait for the asynchronous write back to the nearby CAS.
+    runtime.shutdown_on_idle().wait().unwrap();
+
+    assert_eq!(nearby_cas.read_request_count(), 1);
+    assert_eq!(central_cas.read_request_count(), 1);
+    assert_eq!(
+      nearby_cas.blobs.lock().get(&testdata.fingerprint()),
+      Some(&testdata.bytes())
+    );
+  }
+
+  #[test]
+  fn uploads_files_to_central_cas_asynchronously() {
+    let dir = TempDir::new().unwrap();
+    let nearby_cas = StubCAS::empty();
+    let central_cas = StubCAS::empty();
+    let store = new_tiered_store(dir.path(), nearby_cas.address(), central_cas.address());
+
+    let testdata = TestData::roland();
+    block_on(store.store_file_bytes(testdata.bytes(), false)).expect("Error storing file locally");
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime
+      .block_on(store.ensure_remote_has_recursive(vec![testdata.digest()]))
+      .expect("Error uploading file");
+    assert_eq!(
+      nearby_cas.blobs.lock().get(&testdata.fingerprint()),
+      Some(&testdata.bytes())
+    );
+    // Wait for the asynchronous write back to the central CAS.
+    runtime.shutdown_on_idle().wait().unwrap();
+
+    assert_eq!(
+      central_cas.blobs.lock().get(&testdata.fingerprint()),
+      Some(&testdata.bytes())
+    );
+  }
+
   #[test]
   fn uploads_directories_recursively() {
     let dir = TempDir::new().unwrap();
@@ -3047,6 +5428,38 @@ mod tests {
     );
   }
 
+  #[test]
+  fn store_file_bytes_remote_only() {
+    let dir = TempDir::new().unwrap();
+    let cas = StubCAS::empty();
+
+    let testdata = TestData::roland();
+
+    let digest =
+      block_on(new_store(dir.path(), cas.address()).store_file_bytes_remote_only(testdata.bytes()))
+        .expect("Error storing file remotely");
+    assert_eq!(digest, testdata.digest());
+    assert_eq!(
+      cas.blobs.lock().get(&testdata.fingerprint()),
+      Some(&testdata.bytes())
+    );
+    assert_eq!(
+      load_file_bytes(&new_local_store(dir.path()), testdata.digest()),
+      Ok(None)
+    );
+  }
+
+  #[test]
+  fn store_file_bytes_remote_only_requires_remote() {
+    let dir = TempDir::new().unwrap();
+
+    let error = block_on(
+      new_local_store(dir.path()).store_file_bytes_remote_only(TestData::roland().bytes()),
+    )
+    .expect_err("Want error");
+    assert_eq!(error, "Cannot store a file remotely without a remote");
+  }
+
   #[test]
   fn upload_missing_file_in_directory() {
     let dir = TempDir::new().unwrap();
@@ -3074,6 +5487,90 @@ mod tests {
     );
   }
 
+  #[test]
+  fn upload_to_remote_uploads_exactly_the_given_blobs() {
+    let dir = TempDir::new().unwrap();
+    let cas = StubCAS::empty();
+
+    let testdata = TestData::roland();
+    let testdir = TestDirectory::containing_roland();
+
+    let local_store = new_local_store(dir.path());
+    block_on(local_store.store_file_bytes(testdata.bytes(), false))
+      .expect("Error storing file locally");
+    block_on(local_store.record_directory(&testdir.directory(), false))
+      .expect("Error storing directory locally");
+
+    // Only the Directory is uploaded: not the file which it contains.
+    let summary =
+      block_on(new_store(dir.path(), cas.address()).upload_to_remote(vec![testdir.digest()]))
+        .expect("Error uploading directory");
+    assert_eq!(summary.uploaded_file_count, 1);
+    assert_eq!(summary.uploaded_file_bytes, testdir.digest().1);
+    assert_eq!(
+      cas.blobs.lock().get(&testdir.fingerprint()),
+      Some(&testdir.bytes())
+    );
+    assert_eq!(cas.blobs.lock().get(&testdata.fingerprint()), None);
+  }
+
+  #[test]
+  fn concurrent_uploads_of_the_same_digests_are_deduplicated() {
+    let dir = TempDir::new().unwrap();
+    let cas = StubCAS::empty();
+
+    let testdata = TestData::roland();
+
+    block_on(new_local_store(dir.path()).store_file_bytes(testdata.bytes(), false))
+      .expect("Error storing file locally");
+
+    let store = new_store(dir.path(), cas.address());
+    let (first, second) = block_on(
+      store
+        .ensure_remote_has_recursive(vec![testdata.digest()])
+        .join(store.ensure_remote_has_recursive(vec![testdata.digest()])),
+    )
+    .expect("Error uploading file");
+    assert_eq!(cas.write_message_sizes.lock().len(), 1);
+    assert_eq!(first.uploaded_file_count + second.uploaded_file_count, 1);
+
+    // Digests which have already been uploaded are not checked for or uploaded again...
+    let summary = block_on(store.ensure_remote_has_recursive(vec![testdata.digest()]))
+      .expect("Error uploading file");
+    assert_eq!(summary.ingested_file_count, 1);
+    assert_eq!(summary.uploaded_file_count, 0);
+    assert_eq!(cas.write_message_sizes.lock().len(), 1);
+
+    // ...except within another scope.
+    let store = store.with_upload_scope(UploadScope::default());
+    cas.blobs.lock().clear();
+    block_on(store.ensure_remote_has_recursive(vec![testdata.digest()]))
+      .expect("Error uploading file");
+    assert_eq!(cas.write_message_sizes.lock().len(), 2);
+    assert_eq!(
+      cas.blobs.lock().get(&testdata.fingerprint()),
+      Some(&testdata.bytes())
+    );
+  }
+
+  #[test]
+  fn list_missing_remote_digests() {
+    let dir = TempDir::new().unwrap();
+    let cas = StubCAS::builder().file(&TestData::roland()).build();
+
+    let missing = block_on(
+      new_store(dir.path(), cas.address()).list_missing_remote_digests(vec![
+        TestData::roland().digest(),
+        TestData::catnip().digest(),
+      ]),
+    )
+    .expect("Error listing missing digests");
+    assert_eq!(
+      missing,
+      vec![TestData::catnip().digest()].into_iter().collect()
+    );
+  }
+
   #[test]
   fn uploading_digest_with_wrong_size_is_error() {
     let dir = TempDir::new().unwrap();
@@ -3114,8 +5611,10 @@ mod tests {
     let store_with_remote = Store::with_remote(
       dir.path(),
       &[cas.address()],
+      &[],
       Some("dark-tower".to_owned()),
       &None,
+      &ProxyConfig::default(),
       None,
       1,
       10 * 1024 * 1024,
@@ -3141,8 +5640,10 @@ mod tests {
     let store_with_remote = Store::with_remote(
       dir.path(),
       &[cas.address()],
+      &[],
       Some("dark-tower".to_owned()),
       &None,
+      &ProxyConfig::default(),
       None,
       1,
       10 * 1024 * 1024,
@@ -3179,8 +5680,10 @@ mod tests {
     let store_with_remote = Store::with_remote(
       dir.path(),
       &[cas.address()],
+      &[],
       None,
       &None,
+      &ProxyConfig::default(),
       Some("Armory.Key".to_owned()),
       1,
       10 * 1024 * 1024,
@@ -3206,8 +5709,10 @@ mod tests {
     let store_with_remote = Store::with_remote(
       dir.path(),
       &[cas.address()],
+      &[],
       None,
       &None,
+      &ProxyConfig::default(),
       Some("Armory.Key".to_owned()),
       1,
       10 * 1024 * 1024,
@@ -3235,6 +5740,7 @@ mod tests {
       .expect_err("Want unknown digest error");
   }
 
+  #[cfg(unix)]
   #[test]
   fn materialize_file() {
     let materialize_dir = TempDir::new().unwrap();
@@ -3251,6 +5757,7 @@ mod tests {
     assert!(!is_executable(&file));
   }
 
+  #[cfg(unix)]
   #[test]
   fn materialize_file_executable() {
     let materialize_dir = TempDir::new().unwrap();
@@ -3319,6 +5826,155 @@ mod tests {
     );
   }
 
+  #[test]
+  fn materialize_directory_with_progress() {
+    let materialize_dir = TempDir::new().unwrap();
+
+    let roland = TestData::roland();
+    let catnip = TestData::catnip();
+    let testdir = TestDirectory::containing_roland();
+    let recursive_testdir = TestDirectory::recursive();
+
+    let store_dir = TempDir::new().unwrap();
+    let store = new_local_store(store_dir.path());
+    block_on(store.record_directory(&recursive_testdir.directory(), false))
+      .expect("Error saving recursive Directory");
+    block_on(store.record_directory(&testdir.directory(), false)).expect("Error saving Directory");
+    block_on(store.store_file_bytes(roland.bytes(), false)).expect("Error saving file bytes");
+    block_on(store.store_file_bytes(catnip.bytes(), false))
+      .expect("Error saving catnip file bytes");
+
+    block_on(store.materialize_directory_with_progress(
+      materialize_dir.path().to_owned(),
+      recursive_testdir.digest(),
+    ))
+    .expect("Error materializing");
+
+    assert_eq!(
+      file_contents(&materialize_dir.path().join("treats")),
+      catnip.bytes()
+    );
+    assert_eq!(
+      file_contents(&materialize_dir.path().join("cats").join("roland")),
+      roland.bytes()
+    );
+  }
+
+  fn store_with_recursive_directory(store_dir: &Path) -> Store {
+    let store = new_local_store(store_dir);
+    block_on(store.record_directory(&TestDirectory::recursive().directory(), false))
+      .expect("Error saving recursive Directory");
+    block_on(store.record_directory(&TestDirectory::containing_roland().directory(), false))
+      .expect("Error saving Directory");
+    block_on(store.store_file_bytes(TestData::roland().bytes(), false))
+      .expect("Error saving file bytes");
+    block_on(store.store_file_bytes(TestData::catnip().bytes(), false))
+      .expect("Error saving catnip file bytes");
+    store
+  }
+
+  #[test]
+  fn write_to_workspace() {
+    let workspace = TempDir::new().unwrap();
+    let destination = workspace.path().join("dist").join("cats");
+    let store_dir = TempDir::new().unwrap();
+    let store = store_with_recursive_directory(store_dir.path());
+
+    let manifest = block_on(store.write_to_workspace(
+      destination.clone(),
+      TestDirectory::recursive().digest(),
+      ConflictPolicy::Error,
+    ))
+    .expect("Error writing to workspace");
+
+    assert_eq!(
+      manifest,
+      WorkspaceManifest {
+        written: vec![
+          destination.join("cats").join("roland"),
+          destination.join("treats")
+        ],
+        backed_up: vec![],
+      }
+    );
+    assert_eq!(
+      file_contents(&destination.join("treats")),
+      TestData::catnip().bytes()
+    );
+    assert_eq!(
+      file_contents(&destination.join("cats").join("roland")),
+      TestData::roland().bytes()
+    );
+  }
+
+  #[test]
+  fn write_to_workspace_conflicts() {
+    let workspace = TempDir::new().unwrap();
+    let destination = workspace.path().to_owned();
+    let store_dir = TempDir::new().unwrap();
+    let store = store_with_recursive_directory(store_dir.path());
+    let treats = destination.join("treats");
+    let write = |policy| {
+      block_on(store.write_to_workspace(
+        destination.clone(),
+        TestDirectory::recursive().digest(),
+        policy,
+      ))
+    };
+
+    // A longer pre-existing file, to confirm that it is not partially overwritten.
+    let stale = "stale treats which are longer than catnip";
+    std::fs::write(&treats, stale).unwrap();
+
+    let err = write(ConflictPolicy::Error).expect_err("Want conflict error");
+    assert!(err.contains("already exist"), "Unexpected error: {}", err);
+    assert_eq!(file_contents(&treats), Bytes::from(stale));
+    assert!(!destination.join("cats").exists());
+
+    let manifest = write(ConflictPolicy::Backup).expect("Error writing with backups");
+    let backup = destination.join("treats.bak");
+    assert_eq!(manifest.backed_up, vec![backup.clone()]);
+    assert_eq!(file_contents(&backup), Bytes::from(stale));
+    assert_eq!(file_contents(&treats), TestData::catnip().bytes());
+
+    let manifest = write(ConflictPolicy::Overwrite).expect("Error overwriting");
+    assert_eq!(manifest.backed_up, Vec::<PathBuf>::new());
+    assert_eq!(manifest.written.len(), 2);
+    assert_eq!(file_contents(&treats), TestData::catnip().bytes());
+  }
+
+  #[test]
+  fn conflict_policy() {
+    assert_eq!(ConflictPolicy::new("backup"), Ok(ConflictPolicy::Backup));
+    assert!(ConflictPolicy::new("clobber").is_err());
+  }
+
+  #[test]
+  fn materialize_progress() {
+    let progress = MaterializeProgress::new("dist".to_owned(), 200, Duration::from_secs(0));
+    assert_eq!(
+      progress.message(0),
+      "Materializing dist: 0 of 200 bytes written (0%)"
+    );
+    progress.wrote(50);
+    progress.wrote(100);
+    assert_eq!(progress.written_bytes.load(Ordering::SeqCst), 150);
+    assert!(progress.last_report.lock().is_some());
+    assert_eq!(
+      progress.message(150),
+      "Materializing dist: 150 of 200 bytes written (75%)"
+    );
+
+    let empty = MaterializeProgress::new("empty".to_owned(), 0, Duration::from_secs(3600));
+    empty.wrote(0);
+    assert!(empty.last_report.lock().is_none());
+    assert_eq!(
+      empty.message(0),
+      "Materializing empty: 0 of 0 bytes written (100%)"
+    );
+  }
+
+  #[cfg(unix)]
   #[test]
   fn materialize_directory_executable() {
     let materialize_dir = TempDir::new().unwrap();
@@ -3449,7 +6105,9 @@ mod tests {
     Bytes::from(contents)
   }
 
+  #[cfg(unix)]
   fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
     std::fs::metadata(path)
       .expect("Getting metadata")
       .permissions()
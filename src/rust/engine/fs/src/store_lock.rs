@@ -0,0 +1,241 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+use log::warn;
+
+///
+/// The version of the on-disk format of the local store (including the layout of the databases
+/// within its LMDB environments), which is stamped into a VERSION file in its root. Bump this
+/// whenever the layout or encoding of the store changes incompatibly, and (if possible) add a
+/// migration from the previous version to MIGRATIONS.
+///
+/// Stores created before versions were stamped have no VERSION file, and are version 1.
+///
+pub const LOCAL_STORE_VERSION: u32 = 1;
+
+const VERSION_FILE_NAME: &str = "VERSION";
+const LOCK_FILE_NAME: &str = "LOCK";
+
+type Migration = fn(&Path) -> Result<(), String>;
+
+// Migrations which upgrade a store in place, keyed by the version that they upgrade from (to the
+// next version). A store with a version that cannot be migrated (including a newer version) is
+// isolated instead. Migrations which change only the layout of the databases within each LMDB
+// environment should use `store::local::migrate_lmdbs`.
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+///
+/// An advisory lock on the root of a local store, which allows multiple processes (for example,
+/// pants runs in different repositories which share a cache directory) to safely use one store.
+///
+/// Every process which has the store open holds a shared lock for as long as it does. LMDB
+/// already coordinates concurrent reads and writes between processes, so the lock need only be
+/// upgraded to an exclusive lock for operations (such as compaction, or migration) which replace
+/// the store's files out from under other processes.
+///
+pub struct StoreLock {
+  path: PathBuf,
+  file: File,
+}
+
+impl StoreLock {
+  ///
+  /// Opens and locks the store at the given root, migrating it if it has an older (migratable)
+  /// version. If the store at the root has an incompatible version (or could not be migrated
+  /// because it is in use) a versioned sibling directory is used instead, so that processes which
+  /// expect different versions do not corrupt one another's stores.
+  ///
+  /// Returns the root which should actually be used, and the (shared) lock on it.
+  ///
+  pub fn open_versioned(root: &Path) -> Result<(PathBuf, StoreLock), String> {
+    let lock = StoreLock::open(root)?;
+    let mut version = read_version(root)?.unwrap_or(1);
+    while version < LOCAL_STORE_VERSION {
+      let migration = match MIGRATIONS.iter().find(|(from, _)| *from == version) {
+        Some((_, migration)) => migration,
+        None => break,
+      };
+      let migrated = lock.with_exclusive(|| {
+        migration(root)?;
+        write_version(root, version + 1)
+      })?;
+      if migrated.is_none() {
+        break;
+      }
+      version += 1;
+    }
+
+    if version == LOCAL_STORE_VERSION {
+      write_version(root, version)?;
+      return Ok((root.to_owned(), lock));
+    }
+
+    let isolated_root = isolated_root(root);
+    warn!(
+      "The local store at {:?} has version {}, which is incompatible with version {}: using {:?} \
+       instead.",
+      root, version, LOCAL_STORE_VERSION, isolated_root
+    );
+    let lock = StoreLock::open(&isolated_root)?;
+    match read_version(&isolated_root)? {
+      Some(version) if version != LOCAL_STORE_VERSION => Err(format!(
+        "The local store at {:?} has version {}, which is incompatible with version {}.",
+        isolated_root, version, LOCAL_STORE_VERSION
+      )),
+      _ => {
+        write_version(&isolated_root, LOCAL_STORE_VERSION)?;
+        Ok((isolated_root, lock))
+      }
+    }
+  }
+
+  fn open(root: &Path) -> Result<StoreLock, String> {
+    super::safe_create_dir_all(root)?;
+    let path = root.join(LOCK_FILE_NAME);
+    let file = OpenOptions::new()
+      .create(true)
+      .read(true)
+      .write(true)
+      .open(&path)
+      .map_err(|e| format!("Error opening store lock {:?}: {}", path, e))?;
+    file
+      .lock_shared()
+      .map_err(|e| format!("Error locking store lock {:?}: {}", path, e))?;
+    Ok(StoreLock { path, file })
+  }
+
+  ///
+  /// Runs the given function while holding an exclusive lock on the store, if no other process
+  /// has it open. Returns None (without running the function) if one does.
+  ///
+  pub fn with_exclusive<T, F: FnOnce() -> Result<T, String>>(
+    &self,
+    f: F,
+  ) -> Result<Option<T>, String> {
+    match self.file.try_lock_exclusive() {
+      Ok(()) => {}
+      Err(ref e) if e.kind() == fs2::lock_contended_error().kind() => return Ok(None),
+      Err(e) => {
+        return Err(format!(
+          "Error exclusively locking store lock {:?}: {}",
+          self.path, e
+        ));
+      }
+    }
+    let result = f();
+    // Downgrade back to a shared lock, rather than unlocking, so that the lock is never released
+    // while the store is open.
+    self
+      .file
+      .lock_shared()
+      .map_err(|e| format!("Error locking store lock {:?}: {}", self.path, e))?;
+    result.map(Some)
+  }
+}
+
+fn read_version(root: &Path) -> Result<Option<u32>, String> {
+  let path = root.join(VERSION_FILE_NAME);
+  match std::fs::read_to_string(&path) {
+    Ok(content) => content
+      .trim()
+      .parse()
+      .map(Some)
+      .map_err(|e| format!("Invalid store version in {:?}: {}", path, e)),
+    Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(e) => Err(format!("Error reading store version {:?}: {}", path, e)),
+  }
+}
+
+fn write_version(root: &Path, version: u32) -> Result<(), String> {
+  if read_version(root)? == Some(version) {
+    return Ok(());
+  }
+  let path = root.join(VERSION_FILE_NAME);
+  write_atomically(&path, &format!("{}\n", version))
+    .map_err(|e| format!("Error writing store version {:?}: {}", path, e))
+}
+
+///
+/// Writes a file via a temporary file which is renamed into place, so that concurrent readers
+/// (including those in other processes) never see it partially written.
+///
+pub(crate) fn write_atomically(path: &Path, content: &str) -> io::Result<()> {
+  let mut tmp_name = path
+    .file_name()
+    .map(|name| name.to_owned())
+    .unwrap_or_default();
+  tmp_name.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+  let tmp_path = path.with_file_name(tmp_name);
+  std::fs::write(&tmp_path, content).and_then(|()| std::fs::rename(&tmp_path, path))
+}
+
+fn isolated_root(root: &Path) -> PathBuf {
+  let mut name = root
+    .file_name()
+    .map(|name| name.to_owned())
+    .unwrap_or_default();
+  name.push(format!("-v{}", LOCAL_STORE_VERSION));
+  root.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use tempfile::TempDir;
+
+  use super::{isolated_root, read_version, StoreLock, LOCAL_STORE_VERSION};
+
+  #[test]
+  fn stamps_new_store() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("lmdb_store");
+    let (actual_root, _lock) = StoreLock::open_versioned(&root).unwrap();
+    assert_eq!(actual_root, root);
+    assert_eq!(read_version(&root), Ok(Some(LOCAL_STORE_VERSION)));
+  }
+
+  #[test]
+  fn isolates_incompatible_store() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("lmdb_store");
+    fs::create_dir(&root).unwrap();
+    fs::write(
+      root.join("VERSION"),
+      format!("{}\n", LOCAL_STORE_VERSION + 1),
+    )
+    .unwrap();
+
+    let (actual_root, _lock) = StoreLock::open_versioned(&root).unwrap();
+    assert_eq!(actual_root, isolated_root(&root));
+    assert_eq!(
+      actual_root,
+      dir
+        .path()
+        .join(format!("lmdb_store-v{}", LOCAL_STORE_VERSION))
+    );
+    assert_eq!(read_version(&actual_root), Ok(Some(LOCAL_STORE_VERSION)));
+    // The incompatible store is left untouched.
+    assert_eq!(read_version(&root), Ok(Some(LOCAL_STORE_VERSION + 1)));
+  }
+
+  #[test]
+  fn exclusive_only_when_not_shared() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("lmdb_store");
+    let (_, lock) = StoreLock::open_versioned(&root).unwrap();
+    assert_eq!(lock.with_exclusive(|| Ok(42)), Ok(Some(42)));
+
+    // A second lock (as taken by another process) prevents exclusive operations, until dropped.
+    let (_, other_lock) = StoreLock::open_versioned(&root).unwrap();
+    assert_eq!(lock.with_exclusive(|| Ok(42)), Ok(None));
+    assert_eq!(other_lock.with_exclusive(|| Ok(42)), Ok(None));
+    drop(other_lock);
+    assert_eq!(lock.with_exclusive(|| Ok(42)), Ok(Some(42)));
+  }
+}
@@ -0,0 +1,309 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_semaphore::{AsyncSemaphore, Permit};
+use boxfuture::{BoxFuture, Boxable};
+use futures::{future, Future};
+use futures_timer::{Delay, TimerHandle};
+use parking_lot::Mutex;
+use serde_derive::Serialize;
+
+///
+/// Limits on the transfers between a Store and its remote CAS. A limit of None is unlimited.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TransferLimits {
+  // The maximum number of uploads and downloads which may be in flight at once.
+  pub max_concurrent_transfers: Option<usize>,
+  // The maximum combined rate of uploads and downloads, in bytes per second.
+  pub max_bytes_per_second: Option<u64>,
+}
+
+///
+/// A snapshot of the transfers between a Store and its remote CAS.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct TransferStats {
+  // Transfers which are waiting for one of the max_concurrent_transfers.
+  pub queued_transfers: usize,
+  pub in_flight_uploads: usize,
+  pub in_flight_downloads: usize,
+  pub bytes_uploaded: u64,
+  pub bytes_downloaded: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransferDirection {
+  Upload,
+  Download,
+}
+
+///
+/// Applies TransferLimits to the transfers which are run through it, and records TransferStats for
+/// them.
+///
+/// Clones share their limits and stats, so that a single TransferLimiter may bound all of the
+/// remote stores which share a network link.
+///
+#[derive(Clone)]
+pub struct TransferLimiter {
+  semaphore: Option<AsyncSemaphore>,
+  max_bytes_per_second: Option<u64>,
+  // The earliest time at which the rate limit allows another transfer to begin.
+  next_transfer_at: Arc<Mutex<Instant>>,
+  stats: Arc<Mutex<TransferStats>>,
+  timer_handle: TimerHandle,
+}
+
+impl TransferLimiter {
+  pub fn new(limits: TransferLimits, timer_handle: TimerHandle) -> TransferLimiter {
+    TransferLimiter {
+      semaphore: limits
+        .max_concurrent_transfers
+        .filter(|max| *max > 0)
+        .map(AsyncSemaphore::new),
+      max_bytes_per_second: limits.max_bytes_per_second.filter(|max| *max > 0),
+      next_transfer_at: Arc::new(Mutex::new(Instant::now())),
+      stats: Arc::new(Mutex::new(TransferStats::default())),
+      timer_handle,
+    }
+  }
+
+  ///
+  /// A TransferLimiter with the given limits which shares this one's timer, but not its stats.
+  ///
+  pub fn with_limits(&self, limits: TransferLimits) -> TransferLimiter {
+    TransferLimiter::new(limits, self.timer_handle.clone())
+  }
+
+  pub fn stats(&self) -> TransferStats {
+    *self.stats.lock()
+  }
+
+  ///
+  /// Records that a transfer (which was run via `transfer`) successfully moved len bytes.
+  ///
+  pub fn record_transferred(&self, direction: TransferDirection, len: usize) {
+    let mut stats = self.stats.lock();
+    match direction {
+      TransferDirection::Upload => stats.bytes_uploaded += len as u64,
+      TransferDirection::Download => stats.bytes_downloaded += len as u64,
+    }
+  }
+
+  ///
+  /// Runs the given Future-creating function (and the Future it returns) to transfer (up to) len
+  /// bytes, once the limits allow it.
+  ///
+  pub fn transfer<F, B, T>(
+    &self,
+    direction: TransferDirection,
+    len: usize,
+    f: F,
+  ) -> BoxFuture<T, String>
+  where
+    F: FnOnce() -> B + Send + 'static,
+    B: Future<Item = T, Error = String> + Send + 'static,
+    T: Send + 'static,
+  {
+    let queued = StatsGuard::new(self.stats.clone(), queued_transfers);
+    let permit: BoxFuture<Option<Permit>, String> = match self.semaphore {
+      Some(ref semaphore) => semaphore
+        .acquire()
+        .map(Some)
+        .map_err(|()| "Failed to acquire a transfer permit".to_owned())
+        .to_boxed(),
+      None => future::ok(None).to_boxed(),
+    };
+    let limiter = self.clone();
+    permit
+      .and_then(move |permit| {
+        std::mem::drop(queued);
+        let in_flight = StatsGuard::new(
+          limiter.stats.clone(),
+          match direction {
+            TransferDirection::Upload => in_flight_uploads,
+            TransferDirection::Download => in_flight_downloads,
+          },
+        );
+        limiter
+          .rate_limit(len)
+          .and_then(|()| f())
+          .then(move |result| {
+            // The permit is held until the transfer has completed.
+            std::mem::drop((permit, in_flight));
+            result
+          })
+      })
+      .to_boxed()
+  }
+
+  ///
+  /// Reserves the time needed to transfer len bytes at the rate limit, and returns a Future which
+  /// completes when the reservation begins.
+  ///
+  fn rate_limit(&self, len: usize) -> BoxFuture<(), String> {
+    let max_bytes_per_second = if let Some(max_bytes_per_second) = self.max_bytes_per_second {
+      max_bytes_per_second
+    } else {
+      return future::ok(()).to_boxed();
+    };
+    let now = Instant::now();
+    let start = {
+      let mut next_transfer_at = self.next_transfer_at.lock();
+      let start = if *next_transfer_at > now {
+        *next_transfer_at
+      } else {
+        now
+      };
+      *next_transfer_at =
+        start + Duration::from_nanos(len as u64 * 1_000_000_000 / max_bytes_per_second);
+      start
+    };
+    if start <= now {
+      return future::ok(()).to_boxed();
+    }
+    Delay::new_handle(start, self.timer_handle.clone())
+      .map_err(|e| format!("Transfer rate limit timer failed: {}", e))
+      .to_boxed()
+  }
+}
+
+fn queued_transfers(stats: &mut TransferStats) -> &mut usize {
+  &mut stats.queued_transfers
+}
+
+fn in_flight_uploads(stats: &mut TransferStats) -> &mut usize {
+  &mut stats.in_flight_uploads
+}
+
+fn in_flight_downloads(stats: &mut TransferStats) -> &mut usize {
+  &mut stats.in_flight_downloads
+}
+
+///
+/// Increments a count in the stats for as long as it is held, so that counts remain accurate even
+/// if a transfer is cancelled.
+///
+struct StatsGuard {
+  stats: Arc<Mutex<TransferStats>>,
+  count: fn(&mut TransferStats) -> &mut usize,
+}
+
+impl StatsGuard {
+  fn new(
+    stats: Arc<Mutex<TransferStats>>,
+    count: fn(&mut TransferStats) -> &mut usize,
+  ) -> StatsGuard {
+    *count(&mut stats.lock()) += 1;
+    StatsGuard { stats, count }
+  }
+}
+
+impl Drop for StatsGuard {
+  fn drop(&mut self) {
+    *(self.count)(&mut self.stats.lock()) -= 1;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+
+  use futures::sync::oneshot;
+  use futures::{future, Future};
+  use futures_timer::TimerHandle;
+
+  use super::{TransferDirection, TransferLimiter, TransferLimits, TransferStats};
+
+  #[test]
+  fn records_stats() {
+    let limiter = TransferLimiter::new(TransferLimits::default(), TimerHandle::default());
+    let limiter2 = limiter.clone();
+    limiter
+      .transfer(TransferDirection::Upload, 10, move || {
+        assert_eq!(limiter2.stats().in_flight_uploads, 1);
+        limiter2.record_transferred(TransferDirection::Upload, 10);
+        future::ok(())
+      })
+      .wait()
+      .unwrap();
+    let limiter2 = limiter.clone();
+    limiter
+      .transfer(TransferDirection::Download, 20, move || {
+        assert_eq!(limiter2.stats().in_flight_downloads, 1);
+        limiter2.record_transferred(TransferDirection::Download, 20);
+        future::ok(())
+      })
+      .wait()
+      .unwrap();
+    limiter
+      .transfer(TransferDirection::Download, 40, || {
+        future::err::<(), _>("Failed".to_owned())
+      })
+      .wait()
+      .unwrap_err();
+
+    assert_eq!(
+      limiter.stats(),
+      TransferStats {
+        queued_transfers: 0,
+        in_flight_uploads: 0,
+        in_flight_downloads: 0,
+        bytes_uploaded: 10,
+        bytes_downloaded: 20,
+      }
+    );
+  }
+
+  #[test]
+  fn limits_concurrent_transfers() {
+    let limiter = TransferLimiter::new(
+      TransferLimits {
+        max_concurrent_transfers: Some(1),
+        max_bytes_per_second: None,
+      },
+      TimerHandle::default(),
+    );
+    let (sender, receiver) = oneshot::channel::<()>();
+    let mut first = limiter.transfer(TransferDirection::Upload, 1, move || {
+      receiver.map_err(|_| "Cancelled".to_owned())
+    });
+    let mut second = limiter.transfer(TransferDirection::Upload, 1, || future::ok(()));
+
+    let limiter2 = limiter.clone();
+    future::lazy(move || {
+      assert!(first.poll().unwrap().is_not_ready());
+      assert!(second.poll().unwrap().is_not_ready());
+      assert_eq!(limiter2.stats().in_flight_uploads, 1);
+      assert_eq!(limiter2.stats().queued_transfers, 1);
+      sender.send(()).unwrap();
+      first.join(second)
+    })
+    .wait()
+    .unwrap();
+
+    assert_eq!(limiter.stats(), TransferStats::default());
+  }
+
+  #[test]
+  fn limits_transfer_rate() {
+    let limiter = TransferLimiter::new(
+      TransferLimits {
+        max_concurrent_transfers: None,
+        max_bytes_per_second: Some(1000),
+      },
+      TimerHandle::default(),
+    );
+    let start = Instant::now();
+    // The first transfer starts immediately, and the second waits for the first's 100 bytes.
+    let transfers = (0..2)
+      .map(|_| limiter.transfer(TransferDirection::Download, 100, || future::ok(())))
+      .collect::<Vec<_>>();
+    future::join_all(transfers).wait().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+  }
+}
@@ -0,0 +1,81 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::sync::Arc;
+
+use futures::future::{self, Future};
+use futures::sync::oneshot;
+use parking_lot::Mutex;
+
+use boxfuture::{BoxFuture, Boxable};
+
+///
+/// A handle which cancels all of the work that was started with it: once it has been cancelled,
+/// running Nodes are interrupted (by dropping their Futures, which kills any processes that they
+/// are waiting for), and Nodes which have not started do not run.
+///
+/// Cancelled Nodes fail as `invalidated`, which (unlike other failures) is never persisted in the
+/// Graph, so they will run again when they are next requested.
+///
+#[derive(Clone, Default)]
+pub struct Cancellation {
+  inner: Arc<Mutex<CancellationState>>,
+}
+
+#[derive(Default)]
+struct CancellationState {
+  cancelled: bool,
+  waiters: Vec<oneshot::Sender<()>>,
+  // The number of waiters at which waiters which have completed are next pruned.
+  prune_at: usize,
+}
+
+impl Cancellation {
+  pub fn cancel(&self) {
+    let mut state = self.inner.lock();
+    state.cancelled = true;
+    for waiter in state.waiters.drain(..) {
+      let _ = waiter.send(());
+    }
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.inner.lock().cancelled
+  }
+
+  ///
+  /// Runs the given Future until it completes, or until this Cancellation is cancelled, in which
+  /// case the Future is dropped and the given `cancelled` value is returned instead.
+  ///
+  pub fn run<F>(&self, work: F, cancelled: F::Item) -> BoxFuture<F::Item, F::Error>
+  where
+    F: Future + Send + 'static,
+    F::Item: Send + 'static,
+    F::Error: Send + 'static,
+  {
+    let (send, recv) = oneshot::channel();
+    {
+      let mut state = self.inner.lock();
+      if state.cancelled {
+        return future::ok(cancelled).to_boxed();
+      }
+      // Waiters are only removed when they are cancelled, so prune those whose work has completed
+      // (and dropped its receiver) as they accumulate.
+      if state.waiters.len() >= state.prune_at {
+        state.waiters.retain(|waiter| !waiter.is_canceled());
+        state.prune_at = std::cmp::max(64, state.waiters.len() * 2);
+      }
+      state.waiters.push(send);
+    }
+    let on_cancel = recv
+      .map(move |()| cancelled)
+      // The sender is only dropped without sending if this Cancellation was dropped, in which case
+      // it can never be cancelled.
+      .or_else(|_| future::empty());
+    work
+      .select(on_cancel)
+      .map(|(item, _)| item)
+      .map_err(|(err, _)| err)
+      .to_boxed()
+  }
+}
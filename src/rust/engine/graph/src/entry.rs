@@ -6,7 +6,8 @@ use crate::node::{EntryId, Node, NodeContext, NodeError};
 
 use futures::future::{self, Future};
 use futures::sync::oneshot;
-use log::{self, trace};
+use log::{self, trace, warn};
+use metrics::{self, Metric};
 use parking_lot::Mutex;
 
 use boxfuture::{BoxFuture, Boxable};
@@ -98,6 +99,37 @@ impl<N: Node> AsRef<Result<N::Item, N::Error>> for EntryResult<N> {
   }
 }
 
+///
+/// The reason that a Node which had previously completed was run again.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RerunCause {
+  // The values of some of the Node's dependencies changed.
+  InputsChanged,
+  // The Node was cleared because files which it depended on changed on disk.
+  FilesChanged,
+  // The previous attempt to run the Node failed transiently, and it was retried.
+  PreviousFailure,
+}
+
+impl RerunCause {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      RerunCause::InputsChanged => "inputs_changed",
+      RerunCause::FilesChanged => "files_changed",
+      RerunCause::PreviousFailure => "previous_failure",
+    }
+  }
+
+  fn metric(self) -> Metric {
+    match self {
+      RerunCause::InputsChanged => Metric::NodeRerunsInputsChanged,
+      RerunCause::FilesChanged => Metric::NodeRerunsFilesChanged,
+      RerunCause::PreviousFailure => Metric::NodeRerunsPreviousFailure,
+    }
+  }
+}
+
 #[allow(clippy::type_complexity)]
 #[derive(Debug)]
 pub(crate) enum EntryState<N: Node> {
@@ -124,6 +156,8 @@ pub(crate) enum EntryState<N: Node> {
     waiters: Vec<oneshot::Sender<Result<(N::Item, Generation), N::Error>>>,
     previous_result: Option<EntryResult<N>>,
     dirty: bool,
+    // If this is not the first run of the Node, the reason that it is running again.
+    rerun_cause: Option<RerunCause>,
   },
   // A node that has completed, and then possibly been marked dirty. Because marking a node
   // dirty does not eagerly re-execute any logic, it will stay this way until a caller moves it
@@ -133,6 +167,8 @@ pub(crate) enum EntryState<N: Node> {
     generation: Generation,
     result: EntryResult<N>,
     dep_generations: Vec<Generation>,
+    // If the result was produced by a re-run of the Node, the reason that it re-ran.
+    rerun_cause: Option<RerunCause>,
   },
 }
 
@@ -210,6 +246,37 @@ impl<N: Node> Entry<N> {
     }
   }
 
+  ///
+  /// Runs the given Node, re-attempting it for as long as it fails with errors which it declares
+  /// to be transient. Returns its final result, and whether it was retried.
+  ///
+  fn run_with_retries(
+    node: N,
+    context: N::Context,
+    attempt: usize,
+  ) -> BoxFuture<(Result<N::Item, N::Error>, bool), ()> {
+    let node2 = node.clone();
+    let context2 = context.clone();
+    node
+      .run(context)
+      .then(move |res| match res {
+        // An invalidated Node will be re-run by the Graph itself.
+        Err(ref err)
+          if *err != N::Error::invalidated() && attempt < node2.transient_retries(err) =>
+        {
+          warn!(
+            "{} failed (attempt {}), and will be retried: {:?}",
+            node2,
+            attempt + 1,
+            err
+          );
+          Self::run_with_retries(node2, context2, attempt + 1)
+        }
+        res => future::ok((res, attempt > 0)).to_boxed(),
+      })
+      .to_boxed()
+  }
+
   ///
   /// Spawn the execution of the node on an Executor, which will cause it to execute outside of
   /// the Graph lock and call back into the graph lock to set the final value.
@@ -222,6 +289,7 @@ impl<N: Node> Entry<N> {
     generation: Generation,
     previous_dep_generations: Option<Vec<Generation>>,
     previous_result: Option<EntryResult<N>>,
+    rerun_cause: Option<RerunCause>,
   ) -> EntryState<N>
   where
     C: NodeContext<Node = N>,
@@ -266,18 +334,25 @@ impl<N: Node> Entry<N> {
               // previous_result or generation.
               context
                 .graph()
-                .complete(&context, entry_id, run_token, None);
+                .complete(&context, entry_id, run_token, None, false);
               future::ok(()).to_boxed()
             } else {
               // The Node needs to (re-)run!
               let context2 = context.clone();
-              node
-                .run(context)
-                .then(move |res| {
+              let run = match context.cancellation().cloned() {
+                // If the Context has been cancelled, don't start the Node, and if it is cancelled
+                // while the Node is running, stop running it.
+                Some(cancellation) => cancellation.run(
+                  future::lazy(move || Self::run_with_retries(node, context, 0)),
+                  (Err(N::Error::invalidated()), false),
+                ),
+                None => Self::run_with_retries(node, context, 0),
+              };
+              run
+                .map(move |(res, retried)| {
                   context2
                     .graph()
-                    .complete(&context2, entry_id, run_token, Some(res));
-                  Ok(())
+                    .complete(&context2, entry_id, run_token, Some(res), retried);
                 })
                 .to_boxed()
             }
@@ -291,6 +366,7 @@ impl<N: Node> Entry<N> {
           generation,
           previous_result,
           dirty: false,
+          rerun_cause,
         }
       }
       &EntryKey::Cyclic(_) => EntryState::Completed {
@@ -298,6 +374,7 @@ impl<N: Node> Entry<N> {
         dep_generations: Vec::new(),
         run_token,
         generation,
+        rerun_cause: None,
       },
     }
   }
@@ -339,7 +416,7 @@ impl<N: Node> Entry<N> {
           ref result,
           generation,
           ..
-        } if self.node.content().cacheable() && !result.is_dirty() => {
+        } if !result.is_dirty() => {
           return future::result(result.as_ref().clone())
             .map(move |res| (res, generation))
             .to_boxed();
@@ -355,55 +432,49 @@ impl<N: Node> Entry<N> {
           run_token,
           generation,
           previous_result,
-        } => Self::run(
-          context,
-          &self.node,
-          entry_id,
-          run_token,
-          generation,
-          None,
-          previous_result,
-        ),
+        } => {
+          // A NotStarted Node with a previous result was cleared due to changed files.
+          let rerun_cause = previous_result.as_ref().map(|_| RerunCause::FilesChanged);
+          Self::run(
+            context,
+            &self.node,
+            entry_id,
+            run_token,
+            generation,
+            None,
+            previous_result,
+            rerun_cause,
+          )
+        }
         EntryState::Completed {
           run_token,
           generation,
-          mut result,
+          result,
           dep_generations,
+          ..
         } => {
           trace!(
-            "Re-starting node {:?}. It was: previous_result={:?}, cacheable={}",
+            "Re-starting node {:?}. It was: previous_result={:?}",
             self.node,
             result,
-            self.node.content().cacheable()
           );
           assert!(
-            result.is_dirty() || !self.node.content().cacheable(),
+            result.is_dirty(),
             "A clean Node should not reach this point: {:?}",
             result
           );
-          result.dirty();
           // The Node has already completed but is now marked dirty. This indicates that we are the
           // first caller to request it since it was marked dirty. We attempt to clean it (which will
           // cause it to re-run if the dep_generations mismatch).
-          // Note that if the node is uncacheable, we avoid storing a previous result, which will
-          // transitively invalidate every node that depends on us. This works because, in practice,
-          // the only uncacheable nodes are Select nodes and @console_rule Task nodes. See #6146 and #6598
           Self::run(
             context,
             &self.node,
             entry_id,
             run_token,
             generation,
-            if self.node.content().cacheable() {
-              Some(dep_generations)
-            } else {
-              None
-            },
-            if self.node.content().cacheable() {
-              Some(result)
-            } else {
-              None
-            },
+            Some(dep_generations),
+            Some(result),
+            Some(RerunCause::InputsChanged),
           )
         }
         EntryState::Running { .. } => {
@@ -437,6 +508,7 @@ impl<N: Node> Entry<N> {
     result_run_token: RunToken,
     dep_generations: Vec<Generation>,
     result: Option<Result<N::Item, N::Error>>,
+    retried: bool,
     _graph: &mut super::InnerGraph<N>,
   ) where
     C: NodeContext<Node = N>,
@@ -462,6 +534,7 @@ impl<N: Node> Entry<N> {
         generation,
         mut previous_result,
         dirty,
+        rerun_cause,
         ..
       } => {
         if result == Some(Err(N::Error::invalidated())) {
@@ -498,8 +571,19 @@ impl<N: Node> Entry<N> {
             generation,
             None,
             previous_result,
+            Some(RerunCause::InputsChanged),
           )
         } else {
+          // A Node which was found to be clean did not actually re-run, while a retried Node re-ran
+          // (at least) because it failed.
+          let rerun_cause = match result {
+            None => None,
+            Some(_) if retried => Some(RerunCause::PreviousFailure),
+            Some(_) => rerun_cause,
+          };
+          if let Some(rerun_cause) = rerun_cause {
+            metrics::increment_counter(rerun_cause.metric(), 1);
+          }
           // If the new result does not match the previous result, the generation increments.
           let (generation, next_result) = if let Some(result) = result {
             if Some(&result) == previous_result.as_ref().map(EntryResult::as_ref) {
@@ -533,6 +617,7 @@ impl<N: Node> Entry<N> {
             dep_generations,
             run_token,
             generation,
+            rerun_cause,
           }
         }
       }
@@ -554,6 +639,16 @@ impl<N: Node> Entry<N> {
     }
   }
 
+  ///
+  /// If this Node has completed by re-running, returns the reason that it re-ran.
+  ///
+  pub fn rerun_cause(&self) -> Option<RerunCause> {
+    match *self.state.lock() {
+      EntryState::Completed { rerun_cause, .. } => rerun_cause,
+      _ => None,
+    }
+  }
+
   ///
   /// Get the current RunToken of this entry.
   ///
@@ -678,6 +773,19 @@ impl<N: Node> Entry<N> {
     }
   }
 
+  ///
+  /// A name for the current state of this Entry, for visualization.
+  ///
+  pub fn state_name(&self) -> &'static str {
+    match *self.state.lock() {
+      EntryState::NotStarted { .. } => "not_started",
+      EntryState::Running { dirty: true, .. } => "dirtied",
+      EntryState::Running { .. } => "running",
+      EntryState::Completed { ref result, .. } if result.is_dirty() => "dirtied",
+      EntryState::Completed { .. } => "completed",
+    }
+  }
+
   pub(crate) fn format(&self) -> String {
     let state = match self.peek() {
       Some(Ok(ref nr)) => format!("{:?}", nr),
@@ -30,17 +30,20 @@ use hashing;
 
 use petgraph;
 
+mod cancellation;
 mod entry;
 mod node;
 
-pub use crate::entry::Entry;
+pub use crate::cancellation::Cancellation;
+pub use crate::entry::{Entry, RerunCause};
 use crate::entry::{EntryKey, Generation, RunToken};
 
 use std::collections::binary_heap::BinaryHeap;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs::{File, OpenOptions};
 use std::hash::BuildHasherDefault;
 use std::io::{self, BufWriter, Write};
+use std::mem;
 use std::path::Path;
 use std::time::{Duration, Instant};
 
@@ -48,18 +51,20 @@ use fnv::FnvHasher;
 
 use futures::future::{self, Future};
 use indexmap::IndexSet;
-use log::{info, trace, warn};
+use log::{info, trace, warn, Level};
 use parking_lot::Mutex;
 use petgraph::graph::DiGraph;
-use petgraph::visit::EdgeRef;
+use petgraph::stable_graph::StableDiGraph;
 use petgraph::Direction;
+use serde_json::{self, json};
 
 pub use crate::node::{EntryId, Node, NodeContext, NodeError, NodeTracer, NodeVisualizer};
 use boxfuture::{BoxFuture, Boxable};
 
 type FNV = BuildHasherDefault<FnvHasher>;
 
-type PGraph<N> = DiGraph<Entry<N>, f32, u32>;
+// A StableGraph, so that the EntryIds of Nodes are not changed by the removal of other Nodes.
+type PGraph<N> = StableDiGraph<Entry<N>, f32, u32>;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct InvalidationResult {
@@ -83,7 +88,8 @@ impl<N: Node> InnerGraph<N> {
     self.nodes.get(node)
   }
 
-  // TODO: Now that we never delete Entries, we should consider making this infalliable.
+  // Entries are removed when they can no longer be requested (see `remove_if`), but the EntryIds of
+  // Nodes which were running when they were removed may still be held by their Contexts.
   fn entry_for_id(&self, id: EntryId) -> Option<&Entry<N>> {
     self.pg.node_weight(id)
   }
@@ -130,7 +136,7 @@ impl<N: Node> InnerGraph<N> {
     if !self.detect_cycle(src_id, dst_id) {
       return None;
     }
-    Self::shortest_path(&self.pg, dst_id, src_id).map(|path| {
+    Self::shortest_path(&self.weighted_copy(false), dst_id, src_id).map(|path| {
       path
         .into_iter()
         .map(|index| self.entry_for_id(index).unwrap().clone())
@@ -171,7 +177,11 @@ impl<N: Node> InnerGraph<N> {
   /// Uses Bellman-Ford, which is pretty expensive O(VE) as it has to traverse the whole graph and
   /// keeping a lot of state on the way.
   ///
-  fn shortest_path(graph: &PGraph<N>, src: EntryId, dst: EntryId) -> Option<Vec<EntryId>> {
+  fn shortest_path(
+    graph: &DiGraph<(), f32, u32>,
+    src: EntryId,
+    dst: EntryId,
+  ) -> Option<Vec<EntryId>> {
     let (_path_weights, paths) = petgraph::algo::bellman_ford(graph, src)
       .expect("There should not be any negative edge weights");
 
@@ -200,6 +210,72 @@ impl<N: Node> InnerGraph<N> {
     }
   }
 
+  ///
+  /// Copies the structure of the Graph (optionally with its edges reversed) into a Graph without
+  /// Entries, in which each edge has an equal weight, so that it may be Bellman-Forded over. The
+  /// EntryIds of the Graph are the indexes of the copy: the indexes of removed Nodes are left as
+  /// unconnected placeholders.
+  ///
+  fn weighted_copy(&self, reversed: bool) -> DiGraph<(), f32, u32> {
+    let node_bound = self
+      .pg
+      .node_indices()
+      .map(|id| id.index() + 1)
+      .max()
+      .unwrap_or(0);
+    let mut copy = DiGraph::with_capacity(node_bound, self.pg.edge_count());
+    for _ in 0..node_bound {
+      copy.add_node(());
+    }
+    for src_id in self.pg.node_indices() {
+      for dst_id in self.pg.neighbors_directed(src_id, Direction::Outgoing) {
+        if reversed {
+          copy.add_edge(dst_id, src_id, 1.0);
+        } else {
+          copy.add_edge(src_id, dst_id, 1.0);
+        }
+      }
+    }
+    copy
+  }
+
+  ///
+  /// Removes the Nodes matching the predicate which are not running, after clearing them and
+  /// dirtying their transitive dependents (which will re-run rather than observing the removal).
+  ///
+  /// A running Node is not removed, because its Context still holds its EntryId.
+  ///
+  fn remove_if<P: Fn(&N) -> bool>(&mut self, predicate: P) -> usize {
+    let now = Instant::now();
+    let removed: HashSet<EntryKey<N>, FNV> = self
+      .nodes
+      .iter()
+      .filter(|&(entry_key, &entry_id)| {
+        predicate(entry_key.content())
+          && self
+            .entry_for_id(entry_id)
+            .map(|entry| entry.current_running_duration(now).is_none())
+            .unwrap_or(true)
+      })
+      .map(|(entry_key, _)| entry_key.clone())
+      .collect();
+    if removed.is_empty() {
+      return 0;
+    }
+
+    self.invalidate_from_roots(|node| {
+      removed.contains(&EntryKey::Valid(node.clone()))
+        || removed.contains(&EntryKey::Cyclic(node.clone()))
+    });
+    for entry_key in &removed {
+      if let Some(entry_id) = self.nodes.remove(entry_key) {
+        // Also removes the edges to and from the Node.
+        self.pg.remove_node(entry_id);
+      }
+    }
+    removed.len()
+  }
+
   fn clear(&mut self) {
     for eid in self.nodes.values() {
       if let Some(entry) = self.pg.node_weight_mut(*eid) {
@@ -264,12 +340,46 @@ impl<N: Node> InnerGraph<N> {
     invalidation_result
   }
 
+  ///
+  /// Walks breadth first from the given roots (at depth zero) to at most max_depth, and returns the
+  /// walked entries which the visualizer includes.
+  ///
+  fn visualized_entries<V: NodeVisualizer<N>>(
+    &self,
+    visualizer: &V,
+    roots: &[N],
+    max_depth: Option<usize>,
+  ) -> IndexSet<EntryId, FNV> {
+    let mut queue: VecDeque<(EntryId, usize)> = roots
+      .iter()
+      .filter_map(|n| self.entry_id(&EntryKey::Valid(n.clone())))
+      .map(|id| (*id, 0))
+      .collect();
+    let mut walked: HashSet<EntryId, FNV> = HashSet::default();
+    let mut included = IndexSet::default();
+    while let Some((id, depth)) = queue.pop_front() {
+      if !walked.insert(id) {
+        continue;
+      }
+      if visualizer.include(self.unsafe_entry_for_id(id)) {
+        included.insert(id);
+      }
+      if max_depth.map(|max_depth| depth < max_depth).unwrap_or(true) {
+        queue.extend(self.pg.neighbors(id).map(|dep_id| (dep_id, depth + 1)));
+      }
+    }
+    included
+  }
+
   fn visualize<V: NodeVisualizer<N>>(
     &self,
     mut visualizer: V,
     roots: &[N],
+    max_depth: Option<usize>,
     path: &Path,
   ) -> io::Result<()> {
+    let entry_ids = self.visualized_entries(&visualizer, roots, max_depth);
+
     let file = File::create(path)?;
     let mut f = BufWriter::new(file);
 
@@ -283,13 +393,7 @@ impl<N: Node> InnerGraph<N> {
 
     let mut format_color = |entry: &Entry<N>| visualizer.color(entry);
 
-    let root_entries = roots
-      .iter()
-      .filter_map(|n| self.entry_id(&EntryKey::Valid(n.clone())))
-      .cloned()
-      .collect();
-
-    for eid in self.walk(root_entries, Direction::Outgoing) {
+    for &eid in &entry_ids {
       let entry = self.unsafe_entry_for_id(eid);
       let node_str = entry.format();
 
@@ -300,7 +404,11 @@ impl<N: Node> InnerGraph<N> {
         format_color(entry)
       ))?;
 
-      for dep_id in self.pg.neighbors(eid) {
+      for dep_id in self
+        .pg
+        .neighbors(eid)
+        .filter(|dep_id| entry_ids.contains(dep_id))
+      {
         let dep_entry = self.unsafe_entry_for_id(dep_id);
 
         // Write an entry per edge.
@@ -313,6 +421,48 @@ impl<N: Node> InnerGraph<N> {
     Ok(())
   }
 
+  ///
+  /// Like `visualize`, but renders JSON containing the state of each Node, which is useful for
+  /// capturing what a (possibly hung) run is doing.
+  ///
+  fn visualize_json<V: NodeVisualizer<N>>(
+    &self,
+    visualizer: V,
+    roots: &[N],
+    max_depth: Option<usize>,
+    path: &Path,
+  ) -> io::Result<()> {
+    let entry_ids = self.visualized_entries(&visualizer, roots, max_depth);
+    let now = Instant::now();
+    let nodes = entry_ids
+      .iter()
+      .map(|&eid| {
+        let entry = self.unsafe_entry_for_id(eid);
+        let running_millis = entry
+          .current_running_duration(now)
+          .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_millis()));
+        let dependencies = self
+          .pg
+          .neighbors(eid)
+          .filter(|dep_id| entry_ids.contains(dep_id))
+          .map(|dep_id| dep_id.index())
+          .collect::<Vec<_>>();
+        json!({
+          "id": eid.index(),
+          "node": entry.node().to_string(),
+          "state": entry.state_name(),
+          "running_millis": running_millis,
+          "rerun_cause": entry.rerun_cause().map(RerunCause::as_str),
+          "dependencies": dependencies,
+        })
+      })
+      .collect::<Vec<_>>();
+
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &json!({ "nodes": nodes }))?;
+    Ok(())
+  }
+
   fn trace<T: NodeTracer<N>>(&self, roots: &[N], file_path: &Path) -> Result<(), String> {
     let root_ids: IndexSet<EntryId, FNV> = roots
       .iter()
@@ -347,17 +497,9 @@ impl<N: Node> InnerGraph<N> {
       bottom_nodes
     };
 
-    // Invert the graph into a evenly-weighted dependent graph by cloning it and stripping out
-    // the Nodes (to avoid cloning them), adding equal edge weights, and then reversing it.
-    // Because we do not remove any Nodes or edges, all EntryIds remain stable.
-    let dependent_graph = {
-      let mut dg = self
-        .pg
-        .filter_map(|_, _| Some(()), |_, _| Some(1.0))
-        .clone();
-      dg.reverse();
-      dg
-    };
+    // Invert the graph into a evenly-weighted dependent graph without the Nodes (to avoid cloning
+    // them), in which all EntryIds remain stable.
+    let dependent_graph = self.weighted_copy(true);
 
     // Render the shortest path through the dependent graph to any root for each bottom_node.
     for bottom_node in bottom_nodes {
@@ -440,16 +582,27 @@ impl<N: Node> InnerGraph<N> {
   ///
   /// Computes the K longest running entries in a Graph-aware fashion.
   ///
-  fn heavy_hitters(&self, roots: &[N], k: usize) -> HashMap<String, Duration> {
+  /// Only entries with a Level at most as verbose as the given Level are reported: a running leaf
+  /// which is more verbose is represented by its nearest running dependee which is not (if any).
+  ///
+  fn heavy_hitters(&self, roots: &[N], k: usize, max_level: Level) -> HashMap<N, Duration> {
     let now = Instant::now();
-    let queue_entry = |id| {
-      self
-        .entry_for_id(id)
-        .and_then(|entry| entry.current_running_duration(now))
-        .map(|d| (d, id))
+    // Each queued entry is paired with the nearest entry (itself, or a dependee) which may be
+    // reported on its behalf.
+    let queue_entry = |id, reported: Option<(Duration, EntryId)>| {
+      self.entry_for_id(id).and_then(|entry| {
+        entry.current_running_duration(now).map(|d| {
+          if entry.node().level() <= max_level {
+            (d, id, Some((d, id)))
+          } else {
+            (d, id, reported)
+          }
+        })
+      })
     };
 
-    let mut queue: BinaryHeap<(Duration, EntryId)> = BinaryHeap::with_capacity(k);
+    let mut queue: BinaryHeap<(Duration, EntryId, Option<(Duration, EntryId)>)> =
+      BinaryHeap::with_capacity(k);
     let mut visited: HashSet<EntryId, FNV> = HashSet::default();
     let mut res = HashMap::new();
 
@@ -458,10 +611,10 @@ impl<N: Node> InnerGraph<N> {
       roots
         .iter()
         .filter_map(|nk| self.entry_id(&EntryKey::Valid(nk.clone())))
-        .filter_map(|eid| queue_entry(*eid)),
+        .filter_map(|eid| queue_entry(*eid, None)),
     );
 
-    while let Some((duration, id)) = queue.pop() {
+    while let Some((_, id, reported)) = queue.pop() {
       if !visited.insert(id) {
         continue;
       }
@@ -470,12 +623,17 @@ impl<N: Node> InnerGraph<N> {
       let mut deps = self
         .pg
         .neighbors_directed(id, Direction::Outgoing)
-        .filter_map(&queue_entry)
+        .filter_map(|dep_id| queue_entry(dep_id, reported))
         .peekable();
 
       if deps.peek().is_none() {
-        // If the entry has no running deps, it is a leaf. Emit it.
-        res.insert(format!("{}", self.unsafe_entry_for_id(id).node()), duration);
+        // If the entry has no running deps, it is a leaf. Emit it (or its representative).
+        if let Some((duration, reported_id)) = reported {
+          res.insert(
+            self.unsafe_entry_for_id(reported_id).node().clone(),
+            duration,
+          );
+        }
         if res.len() >= k {
           break;
         }
@@ -488,6 +646,29 @@ impl<N: Node> InnerGraph<N> {
     res
   }
 
+  ///
+  /// Returns the number of entries of each type (as named by the given function), and the
+  /// approximate bytes held by them: the shallow sizes of the entries and of their dependency
+  /// edges, but not any heap allocations owned by the Nodes or their results.
+  ///
+  fn memory_usage<F: Fn(&N) -> &'static str>(
+    &self,
+    node_type: F,
+  ) -> BTreeMap<&'static str, (usize, usize)> {
+    let entry_bytes = mem::size_of::<Entry<N>>() + mem::size_of::<(EntryKey<N>, EntryId)>();
+    let edge_bytes = mem::size_of::<petgraph::graph::Edge<f32, u32>>();
+    let mut usage = BTreeMap::new();
+    for id in self.pg.node_indices() {
+      let edge_count = self.pg.neighbors_directed(id, Direction::Outgoing).count();
+      let type_usage = usage
+        .entry(node_type(self.unsafe_entry_for_id(id).node()))
+        .or_insert((0, 0));
+      type_usage.0 += 1;
+      type_usage.1 += entry_bytes + edge_count * edge_bytes;
+    }
+    usage
+  }
+
   fn reachable_digest_count(&self, roots: &[N]) -> usize {
     let root_ids = roots
       .iter()
@@ -532,13 +713,23 @@ impl<N: Node> Graph<N> {
     let inner = InnerGraph {
       draining: false,
       nodes: HashMap::default(),
-      pg: DiGraph::new(),
+      pg: StableDiGraph::with_capacity(0, 0),
     };
     Graph {
       inner: Mutex::new(inner),
     }
   }
 
+  ///
+  /// Calls the given function for each Node in the Graph, while holding the Graph's lock.
+  ///
+  pub fn visit_nodes<F: FnMut(&N)>(&self, mut f: F) {
+    let inner = self.inner.lock();
+    for id in inner.pg.node_indices() {
+      f(inner.unsafe_entry_for_id(id).node());
+    }
+  }
+
   pub fn len(&self) -> usize {
     let inner = self.inner.lock();
     inner.nodes.len()
@@ -555,7 +746,8 @@ impl<N: Node> Graph<N> {
     let maybe_entry_and_id = {
       // Get or create the destination, and then insert the dep and return its state.
       let mut inner = self.inner.lock();
-      if inner.draining {
+      // If the src has been removed, it will not be able to complete anyway.
+      if inner.draining || inner.entry_for_id(src_id).is_none() {
         None
       } else {
         let dst_id = {
@@ -687,6 +879,9 @@ impl<N: Node> Graph<N> {
     C: NodeContext<Node = N>,
   {
     let mut inner = self.inner.lock();
+    if inner.entry_for_id(entry_id).is_none() {
+      return future::err(N::Error::invalidated()).to_boxed();
+    }
     let dep_ids = inner
       .pg
       .neighbors_directed(entry_id, Direction::Outgoing)
@@ -714,20 +909,18 @@ impl<N: Node> Graph<N> {
   ///
   fn clear_deps(&self, entry_id: EntryId, run_token: RunToken) {
     let mut inner = self.inner.lock();
-    // If the RunToken mismatches, return.
-    if let Some(entry) = inner.entry_for_id(entry_id) {
-      if entry.run_token() != run_token {
-        return;
-      }
+    // If the Entry has been removed, or the RunToken mismatches, return.
+    match inner.entry_for_id(entry_id) {
+      Some(entry) if entry.run_token() == run_token => {}
+      _ => return,
     }
 
     // Otherwise, clear the deps.
-    // NB: Because `remove_edge` changes EdgeIndex values, we remove edges one at a time.
     while let Some(dep_edge) = inner
       .pg
-      .edges_directed(entry_id, Direction::Outgoing)
+      .neighbors_directed(entry_id, Direction::Outgoing)
       .next()
-      .map(|edge| edge.id())
+      .and_then(|dep_id| inner.pg.find_edge(entry_id, dep_id))
     {
       inner.pg.remove_edge(dep_edge);
     }
@@ -771,11 +964,16 @@ impl<N: Node> Graph<N> {
     entry_id: EntryId,
     run_token: RunToken,
     result: Option<Result<N::Item, N::Error>>,
+    retried: bool,
   ) where
     C: NodeContext<Node = N>,
   {
     let (entry, entry_id, dep_generations) = {
       let inner = self.inner.lock();
+      // If the Entry has been removed, its result is discarded.
+      if inner.entry_for_id(entry_id).is_none() {
+        return;
+      }
       // Get the Generations of all dependencies of the Node. We can trust that these have not changed
       // since we began executing, as long as we are not currently marked dirty (see the method doc).
       let dep_generations = inner
@@ -798,6 +996,7 @@ impl<N: Node> Graph<N> {
         run_token,
         dep_generations,
         result,
+        retried,
         &mut inner,
       );
     }
@@ -816,6 +1015,15 @@ impl<N: Node> Graph<N> {
     inner.invalidate_from_roots(predicate)
   }
 
+  ///
+  /// Removes the Nodes matching the predicate (for example, those which are keyed by a salt that
+  /// will never be requested again), except for any which are running. Returns the number removed.
+  ///
+  pub fn remove_if<P: Fn(&N) -> bool>(&self, predicate: P) -> usize {
+    let mut inner = self.inner.lock();
+    inner.remove_if(predicate)
+  }
+
   pub fn trace<T: NodeTracer<N>>(&self, roots: &[N], path: &Path) -> Result<(), String> {
     let inner = self.inner.lock();
     inner.trace::<T>(roots, path)
@@ -825,15 +1033,46 @@ impl<N: Node> Graph<N> {
     &self,
     visualizer: V,
     roots: &[N],
+    max_depth: Option<usize>,
+    path: &Path,
+  ) -> io::Result<()> {
+    let inner = self.inner.lock();
+    inner.visualize(visualizer, roots, max_depth, path)
+  }
+
+  pub fn visualize_json<V: NodeVisualizer<N>>(
+    &self,
+    visualizer: V,
+    roots: &[N],
+    max_depth: Option<usize>,
     path: &Path,
   ) -> io::Result<()> {
     let inner = self.inner.lock();
-    inner.visualize(visualizer, roots, path)
+    inner.visualize_json(visualizer, roots, max_depth, path)
   }
 
-  pub fn heavy_hitters(&self, roots: &[N], k: usize) -> HashMap<String, Duration> {
+  pub fn heavy_hitters(&self, roots: &[N], k: usize, max_level: Level) -> HashMap<N, Duration> {
     let inner = self.inner.lock();
-    inner.heavy_hitters(roots, k)
+    inner.heavy_hitters(roots, k, max_level)
+  }
+
+  ///
+  /// If the given Node has completed by re-running, returns the reason that it re-ran.
+  ///
+  pub fn rerun_cause(&self, node: &N) -> Option<RerunCause> {
+    let inner = self.inner.lock();
+    inner
+      .entry_id(&EntryKey::Valid(node.clone()))
+      .and_then(|id| inner.entry_for_id(*id))
+      .and_then(Entry::rerun_cause)
+  }
+
+  pub fn memory_usage<F: Fn(&N) -> &'static str>(
+    &self,
+    node_type: F,
+  ) -> BTreeMap<&'static str, (usize, usize)> {
+    let inner = self.inner.lock();
+    inner.memory_usage(node_type)
   }
 
   pub fn reachable_digest_count(&self, roots: &[N]) -> usize {
@@ -918,16 +1157,20 @@ mod tests {
   use std::collections::{HashMap, HashSet};
   use std::sync::{mpsc, Arc};
   use std::thread;
-  use std::time::Duration;
+  use std::time::{Duration, Instant};
 
   use boxfuture::{BoxFuture, Boxable};
   use futures::future::{self, Future};
   use hashing::Digest;
+  use log::Level;
   use parking_lot::Mutex;
 
   use self::rand::Rng;
 
-  use super::{EntryId, Graph, InvalidationResult, Node, NodeContext, NodeError};
+  use super::{
+    Cancellation, Entry, EntryId, Graph, InvalidationResult, Node, NodeContext, NodeError,
+    NodeVisualizer, RerunCause,
+  };
 
   #[test]
   fn create() {
@@ -968,6 +1211,88 @@ mod tests {
     assert_eq!(context.runs(), vec![TNode(2), TNode(1), TNode(0), TNode(1)]);
   }
 
+  #[test]
+  fn retry_transient_failures() {
+    let graph = Arc::new(Graph::new());
+    let failures = vec![(TNode(1), 2)].into_iter().collect();
+    let context = TContext::new_with_failures(0, failures, graph.clone());
+
+    // The middle Node fails twice, and is retried within the same run.
+    assert_eq!(
+      graph.create(TNode(2), &context).wait(),
+      Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+    );
+    assert_eq!(
+      context.runs(),
+      vec![TNode(2), TNode(1), TNode(1), TNode(1), TNode(0)]
+    );
+  }
+
+  #[test]
+  fn retry_transient_failures_exhausted() {
+    let graph = Arc::new(Graph::new());
+    let failures = vec![(TNode(1), 3)].into_iter().collect();
+    let context = TContext::new_with_failures(0, failures, graph.clone());
+
+    // The middle Node fails more times than it may be retried, so the failure propagates.
+    assert_eq!(
+      graph.create(TNode(2), &context).wait(),
+      Err(TError::Transient)
+    );
+    assert_eq!(context.runs(), vec![TNode(2), TNode(1), TNode(1), TNode(1)]);
+  }
+
+  #[test]
+  fn remove_if_dirties_dependents() {
+    let graph = Arc::new(Graph::new());
+    let context = TContext::new(0, graph.clone());
+
+    // Create three nodes.
+    assert_eq!(
+      graph.create(TNode(2), &context).wait(),
+      Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+    );
+    assert_eq!(graph.len(), 3);
+
+    // Remove the middle Node, which dirties the upper node.
+    assert_eq!(graph.remove_if(|&TNode(n)| n == 1), 1);
+    assert_eq!(graph.len(), 2);
+
+    // Confirm that the upper Node re-runs (because one of its dependencies is gone), and that the
+    // removed Node is re-created.
+    assert_eq!(
+      graph.create(TNode(2), &context).wait(),
+      Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+    );
+    assert_eq!(
+      context.runs(),
+      vec![TNode(2), TNode(1), TNode(0), TNode(2), TNode(1)]
+    );
+    assert_eq!(graph.len(), 3);
+  }
+
+  #[test]
+  fn remove_if_bounds_salted_nodes() {
+    let graph = Arc::new(Graph::new());
+
+    // Each "run" requests a distinct Node (as if salted by its run), which depends on a shared Node.
+    for run in 0..5 {
+      let salted = TNode(100 + run);
+      let context = TContext::new_with_dependencies(
+        run,
+        vec![(salted.clone(), Some(TNode(0)))].into_iter().collect(),
+        graph.clone(),
+      );
+      assert_eq!(
+        graph.create(salted, &context).wait(),
+        Ok(vec![T(0, 0), T(100 + run, run)])
+      );
+      assert_eq!(graph.len(), 2);
+      assert_eq!(graph.remove_if(|&TNode(n)| n >= 100), 1);
+      assert_eq!(graph.len(), 1);
+    }
+  }
+
   #[test]
   fn invalidate_and_rerun() {
     let graph = Arc::new(Graph::new());
@@ -999,6 +1324,42 @@ mod tests {
     assert_eq!(context1.runs(), vec![TNode(1), TNode(2)]);
   }
 
+  #[test]
+  fn rerun_causes() {
+    let graph = Arc::new(Graph::new());
+    let failures = vec![(TNode(0), 1)].into_iter().collect();
+    let context0 = TContext::new_with_failures(0, failures, graph.clone());
+
+    // The lowest Node fails once and is retried, while the others run for the first time.
+    assert_eq!(
+      graph.create(TNode(2), &context0).wait(),
+      Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+    );
+    assert_eq!(
+      graph.rerun_cause(&TNode(0)),
+      Some(RerunCause::PreviousFailure)
+    );
+    assert_eq!(graph.rerun_cause(&TNode(1)), None);
+    assert_eq!(graph.rerun_cause(&TNode(2)), None);
+
+    // Clear the middle Node, and re-run with a new context so that the upper node's inputs change.
+    graph.invalidate_from_roots(|&TNode(n)| n == 1);
+    let context1 = TContext::new(1, graph.clone());
+    assert_eq!(
+      graph.create(TNode(2), &context1).wait(),
+      Ok(vec![T(0, 0), T(1, 1), T(2, 1)])
+    );
+    assert_eq!(
+      graph.rerun_cause(&TNode(0)),
+      Some(RerunCause::PreviousFailure)
+    );
+    assert_eq!(graph.rerun_cause(&TNode(1)), Some(RerunCause::FilesChanged));
+    assert_eq!(
+      graph.rerun_cause(&TNode(2)),
+      Some(RerunCause::InputsChanged)
+    );
+  }
+
   #[test]
   fn invalidate_with_changed_dependencies() {
     let graph = Arc::new(Graph::new());
@@ -1151,6 +1512,98 @@ mod tests {
     );
   }
 
+  #[test]
+  fn cancel() {
+    // Confirms that cancelling a context interrupts the Nodes which are running in it, and that the
+    // interruption is not persisted.
+    let graph = Arc::new(Graph::new());
+
+    let delay_before_cancel = Duration::from_millis(100);
+    let delay_in_task = delay_before_cancel * 10;
+
+    // Create a context that will sleep long enough at TNode(1) to be cancelled while TNode(2) is
+    // waiting for it.
+    let context = {
+      let mut delays = HashMap::new();
+      delays.insert(TNode(1), delay_in_task);
+      let mut context = TContext::new_with_delays(0, delays, graph.clone());
+      context.cancellation = Some(Cancellation::default());
+      context
+    };
+
+    let cancellation = context.cancellation.clone().unwrap();
+    let _join = thread::spawn(move || {
+      thread::sleep(delay_before_cancel);
+      cancellation.cancel();
+    });
+
+    let start = Instant::now();
+    assert_eq!(
+      graph.create(TNode(2), &context).wait(),
+      Err(TError::Invalidated),
+    );
+    assert!(start.elapsed() < delay_in_task);
+
+    // Once TNode(1) has finished sleeping, its request for TNode(0) should not run it.
+    thread::sleep(delay_in_task + delay_before_cancel);
+    assert_eq!(vec![TNode(2), TNode(1)], context.runs());
+
+    // Request the Node again in a context which has not been cancelled.
+    let context = TContext::new(0, graph.clone());
+    assert_eq!(
+      graph.create(TNode(2), &context).wait(),
+      Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+    );
+  }
+
+  #[test]
+  fn heavy_hitters_filtered_by_level() {
+    let graph = Arc::new(Graph::new());
+
+    // TNode(2) is a running leaf while it sleeps, and is at the Debug level.
+    let context = {
+      let mut delays = HashMap::new();
+      delays.insert(TNode(2), Duration::from_millis(500));
+      TContext::new_with_delays(0, delays, graph.clone())
+    };
+    let graph2 = graph.clone();
+    let join = thread::spawn(move || graph2.create(TNode(3), &context).wait());
+    thread::sleep(Duration::from_millis(100));
+
+    let names = |level| {
+      graph
+        .heavy_hitters(&[TNode(3)], 1, level)
+        .into_iter()
+        .map(|(node, _)| format!("{}", node))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(names(Level::Debug), vec!["TNode(2)".to_owned()]);
+    // At the Info level, the leaf is represented by its nearest Info-level dependee.
+    assert_eq!(names(Level::Info), vec!["TNode(3)".to_owned()]);
+
+    assert!(join.join().unwrap().is_ok());
+  }
+
+  #[test]
+  fn memory_usage_by_node_type() {
+    let graph = Arc::new(Graph::new());
+    let context = TContext::new(0, graph.clone());
+    assert_eq!(
+      graph.create(TNode(2), &context).wait(),
+      Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+    );
+
+    let usage = graph.memory_usage(|n| if n.0 == 0 { "leaf" } else { "inner" });
+    assert_eq!(
+      usage.keys().cloned().collect::<Vec<_>>(),
+      vec!["inner", "leaf"]
+    );
+    assert_eq!(usage["inner"].0, 2);
+    assert_eq!(usage["leaf"].0, 1);
+    // The inner entries hold dependency edges, which the leaf does not.
+    assert!(usage["inner"].1 > 2 * usage["leaf"].1);
+  }
+
   #[test]
   fn cyclic_failure() {
     // Confirms that an attempt to create a cycle fails.
@@ -1201,6 +1654,53 @@ mod tests {
     assert_eq!(res, Ok(vec![T(1, 1), T(2, 1)]));
   }
 
+  #[test]
+  fn visualize_json_to_depth() {
+    let graph = Arc::new(Graph::new());
+    let context = TContext::new(0, graph.clone());
+    assert_eq!(
+      graph.create(TNode(2), &context).wait(),
+      Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+    );
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("graph.json");
+    graph
+      .visualize_json(TVisualizer, &[TNode(2)], Some(1), &path)
+      .unwrap();
+
+    let content: serde_json::Value =
+      serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let nodes = content["nodes"].as_array().unwrap();
+    assert_eq!(
+      nodes
+        .iter()
+        .map(|node| (
+          node["node"].as_str().unwrap(),
+          node["state"].as_str().unwrap()
+        ))
+        .collect::<Vec<_>>(),
+      vec![("TNode(2)", "completed"), ("TNode(1)", "completed")]
+    );
+    assert_eq!(
+      nodes[0]["dependencies"],
+      serde_json::json!([nodes[1]["id"]])
+    );
+    assert_eq!(nodes[1]["dependencies"], serde_json::json!([]));
+  }
+
+  struct TVisualizer;
+
+  impl NodeVisualizer<TNode> for TVisualizer {
+    fn color_scheme(&self) -> &str {
+      "set312"
+    }
+
+    fn color(&mut self, _entry: &Entry<TNode>) -> String {
+      "1".to_owned()
+    }
+  }
+
   ///
   /// A token containing the id of a Node and the id of a Context, respectively. Has a short name
   /// to minimize the verbosity of tests.
@@ -1221,6 +1721,9 @@ mod tests {
 
     fn run(self, context: TContext) -> BoxFuture<Vec<T>, TError> {
       context.ran(self.clone());
+      if context.maybe_fail(&self) {
+        return future::err(TError::Transient).to_boxed();
+      }
       let token = T(self.0, context.id());
       if let Some(dep) = context.dependency_of(&self) {
         context.maybe_delay(&self);
@@ -1240,8 +1743,20 @@ mod tests {
       None
     }
 
-    fn cacheable(&self) -> bool {
-      true
+    fn transient_retries(&self, error: &TError) -> usize {
+      match error {
+        TError::Transient => 2,
+        _ => 0,
+      }
+    }
+
+    fn level(&self) -> Level {
+      // Even nodes are more verbose than odd nodes.
+      if self.0 % 2 == 0 {
+        Level::Debug
+      } else {
+        Level::Info
+      }
     }
   }
 
@@ -1310,9 +1825,12 @@ mod tests {
     // dependencies.
     edges: Arc<HashMap<TNode, Option<TNode>>>,
     delays: HashMap<TNode, Duration>,
+    // The number of times each TNode should fail (transiently) before succeeding.
+    failures: Arc<Mutex<HashMap<TNode, usize>>>,
     graph: Arc<Graph<TNode>>,
     runs: Arc<Mutex<Vec<TNode>>>,
     entry_id: Option<EntryId>,
+    cancellation: Option<Cancellation>,
   }
   impl NodeContext for TContext {
     type Node = TNode;
@@ -1321,9 +1839,11 @@ mod tests {
         id: self.id,
         edges: self.edges.clone(),
         delays: self.delays.clone(),
+        failures: self.failures.clone(),
         graph: self.graph.clone(),
         runs: self.runs.clone(),
         entry_id: Some(entry_id),
+        cancellation: self.cancellation.clone(),
       }
     }
 
@@ -1340,6 +1860,10 @@ mod tests {
         future.wait().unwrap();
       });
     }
+
+    fn cancellation(&self) -> Option<&Cancellation> {
+      self.cancellation.as_ref()
+    }
   }
 
   impl TContext {
@@ -1348,9 +1872,11 @@ mod tests {
         id,
         edges: Arc::default(),
         delays: HashMap::default(),
+        failures: Arc::default(),
         graph,
         runs: Arc::new(Mutex::new(Vec::new())),
         entry_id: None,
+        cancellation: None,
       }
     }
 
@@ -1363,9 +1889,11 @@ mod tests {
         id,
         edges: Arc::new(edges),
         delays: HashMap::default(),
+        failures: Arc::default(),
         graph,
         runs: Arc::new(Mutex::new(Vec::new())),
         entry_id: None,
+        cancellation: None,
       }
     }
 
@@ -1378,9 +1906,28 @@ mod tests {
         id,
         edges: Arc::default(),
         delays,
+        failures: Arc::default(),
         graph,
         runs: Arc::new(Mutex::new(Vec::new())),
         entry_id: None,
+        cancellation: None,
+      }
+    }
+
+    fn new_with_failures(
+      id: usize,
+      failures: HashMap<TNode, usize>,
+      graph: Arc<Graph<TNode>>,
+    ) -> TContext {
+      TContext {
+        id,
+        edges: Arc::default(),
+        delays: HashMap::default(),
+        failures: Arc::new(Mutex::new(failures)),
+        graph,
+        runs: Arc::new(Mutex::new(Vec::new())),
+        entry_id: None,
+        cancellation: None,
       }
     }
 
@@ -1415,6 +1962,20 @@ mod tests {
       }
     }
 
+    ///
+    /// Returns true (and decrements its remaining failures) if the given TNode should fail.
+    ///
+    fn maybe_fail(&self, node: &TNode) -> bool {
+      let mut failures = self.failures.lock();
+      match failures.get_mut(node) {
+        Some(remaining) if *remaining > 0 => {
+          *remaining -= 1;
+          true
+        }
+        _ => false,
+      }
+    }
+
     fn runs(&self) -> Vec<TNode> {
       self.runs.lock().clone()
     }
@@ -1424,6 +1985,7 @@ mod tests {
   enum TError {
     Cyclic,
     Invalidated,
+    Transient,
   }
   impl NodeError for TError {
     fn invalidated() -> Self {
@@ -6,12 +6,13 @@ use std::hash::Hash;
 
 use boxfuture::BoxFuture;
 use hashing::Digest;
+use log::Level;
 
 use futures::future::Future;
 use petgraph::stable_graph;
 
 use crate::entry::Entry;
-use crate::Graph;
+use crate::{Cancellation, Graph};
 
 // 2^32 Nodes ought to be more than enough for anyone!
 pub type EntryId = stable_graph::NodeIndex<u32>;
@@ -19,7 +20,8 @@ pub type EntryId = stable_graph::NodeIndex<u32>;
 ///
 /// Defines executing a cacheable/memoizable step within the given NodeContext.
 ///
-/// Note that it is assumed that Nodes are very cheap to clone.
+/// Note that it is assumed that Nodes are very cheap to clone. Nodes whose results should not be
+/// memoized indefinitely should include some salt in their identity to control their lifetime.
 ///
 pub trait Node: Clone + Debug + Display + Eq + Hash + Send + 'static {
   type Context: NodeContext<Node = Self>;
@@ -35,9 +37,17 @@ pub trait Node: Clone + Debug + Display + Eq + Hash + Send + 'static {
   fn digest(result: Self::Item) -> Option<Digest>;
 
   ///
-  /// If the node result is cacheable, return true.
+  /// The number of times this Node should be re-attempted if it fails with the given (transient)
+  /// error. Retries happen within the same run of the Node, so unlike a failure which is
+  /// completed and then invalidated, they do not cause its dependees to be dirtied and recomputed.
   ///
-  fn cacheable(&self) -> bool;
+  fn transient_retries(&self, error: &Self::Error) -> usize;
+
+  ///
+  /// The verbosity of this Node, which determines whether it is rendered by consumers (such as
+  /// the UI) which are filtered to a particular level.
+  ///
+  fn level(&self) -> Level;
 }
 
 pub trait NodeError: Clone + Debug + Eq + Send {
@@ -66,6 +76,14 @@ pub trait NodeVisualizer<N: Node> {
   /// Returns a GraphViz color name/id within Self::color_scheme for the given Entry.
   ///
   fn color(&mut self, entry: &Entry<N>) -> String;
+
+  ///
+  /// Returns true if the given Entry should be rendered. Entries which are not rendered are still
+  /// walked, so that their dependencies may be rendered.
+  ///
+  fn include(&self, _entry: &Entry<N>) -> bool {
+    true
+  }
 }
 
 ///
@@ -118,4 +136,12 @@ pub trait NodeContext: Clone + Send + 'static {
   fn spawn<F>(&self, future: F)
   where
     F: Future<Item = (), Error = ()> + Send + 'static;
+
+  ///
+  /// Returns the Cancellation of the work which this Context is running Nodes for, if it may be
+  /// cancelled. Nodes which are run by a cancelled Context fail as invalidated.
+  ///
+  fn cancellation(&self) -> Option<&Cancellation> {
+    None
+  }
 }
@@ -0,0 +1,242 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+lazy_static! {
+  static ref TEES: Mutex<Tees> = Mutex::new(Tees::default());
+}
+
+#[derive(Default)]
+struct Tees {
+  next_id: usize,
+  // The live tees, keyed by the id of the TeeGuard which owns each.
+  files: BTreeMap<usize, Arc<Mutex<File>>>,
+}
+
+///
+/// A tee of console output into a file, which is owned by whatever is scoping the tee (generally,
+/// a Session), and which stops when dropped.
+///
+/// Output which is written on behalf of the owner should be written via `TeeGuard::writer`, so
+/// that it reaches only this tee. Output which cannot be attributed to an owner (such as log lines
+/// written to stderr by a ConsoleWriter) reaches every live tee.
+///
+pub struct TeeGuard {
+  id: usize,
+  file: Arc<Mutex<File>>,
+}
+
+impl TeeGuard {
+  ///
+  /// A writer which copies to only this tee, with ANSI escape sequences stripped.
+  ///
+  pub fn writer(&self) -> TeeWriter {
+    TeeWriter(self.file.clone())
+  }
+
+  ///
+  /// Copies the given console output to only this tee.
+  ///
+  pub fn write(&self, buf: &[u8]) {
+    // A failure to write to the tee should never prevent output from reaching the console.
+    let _ = self.writer().write_all(buf);
+  }
+}
+
+impl Drop for TeeGuard {
+  fn drop(&mut self) {
+    TEES.lock().files.remove(&self.id);
+    let _ = self.file.lock().flush();
+  }
+}
+
+///
+/// Starts copying everything written to the console via a ConsoleWriter into the file at the
+/// given path, with ANSI escape sequences stripped. Any other live tees are unaffected.
+///
+pub fn start_tee(path: &Path) -> Result<TeeGuard, String> {
+  if let Some(parent) = path.parent() {
+    create_dir_all(parent)
+      .map_err(|e| format!("Error creating directory for console tee {:?}: {}", path, e))?;
+  }
+  let file =
+    File::create(path).map_err(|e| format!("Error creating console tee {:?}: {}", path, e))?;
+  let file = Arc::new(Mutex::new(file));
+  let mut tees = TEES.lock();
+  let id = tees.next_id;
+  tees.next_id += 1;
+  tees.files.insert(id, file.clone());
+  Ok(TeeGuard { id, file })
+}
+
+fn tee_all(buf: &[u8]) {
+  let stripped = strip_ansi(buf);
+  for file in TEES.lock().files.values() {
+    // A failure to write to the tee should never prevent output from reaching the console.
+    let _ = file.lock().write_all(&stripped);
+  }
+}
+
+///
+/// Writes to a single tee, with ANSI escape sequences stripped.
+///
+#[derive(Clone)]
+pub struct TeeWriter(Arc<Mutex<File>>);
+
+impl Write for TeeWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.lock().write_all(&strip_ansi(buf))?;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.0.lock().flush()
+  }
+}
+
+///
+/// Wraps a console stream (stdout or stderr) so that anything written to it is also written to
+/// every live tee.
+///
+pub struct ConsoleWriter<W: Write> {
+  inner: W,
+}
+
+impl<W: Write> ConsoleWriter<W> {
+  pub fn new(inner: W) -> ConsoleWriter<W> {
+    ConsoleWriter { inner }
+  }
+}
+
+impl<W: Write> Write for ConsoleWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let written = self.inner.write(buf)?;
+    tee_all(&buf[..written]);
+    Ok(written)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    for file in TEES.lock().files.values() {
+      let _ = file.lock().flush();
+    }
+    self.inner.flush()
+  }
+}
+
+///
+/// Removes ANSI escape sequences (colors, cursor movement, etc) from the given bytes.
+///
+/// Handles CSI sequences (`ESC [ ... final`), OSC sequences (`ESC ] ... BEL` or `ESC ] ... ESC \`),
+/// and two byte escapes. Sequences which are split across calls are not recognised.
+///
+pub fn strip_ansi(buf: &[u8]) -> Vec<u8> {
+  const ESC: u8 = 0x1b;
+  const BEL: u8 = 0x07;
+
+  let mut result = Vec::with_capacity(buf.len());
+  let mut i = 0;
+  while i < buf.len() {
+    if buf[i] != ESC {
+      result.push(buf[i]);
+      i += 1;
+      continue;
+    }
+    match buf.get(i + 1) {
+      Some(b'[') => {
+        // Parameter and intermediate bytes, followed by a single final byte in 0x40..=0x7e.
+        i += 2;
+        while i < buf.len() {
+          let byte = buf[i];
+          i += 1;
+          if byte >= 0x40 && byte <= 0x7e {
+            break;
+          }
+        }
+      }
+      Some(b']') => {
+        i += 2;
+        while i < buf.len() {
+          if buf[i] == BEL {
+            i += 1;
+            break;
+          } else if buf[i] == ESC && buf.get(i + 1) == Some(&b'\\') {
+            i += 2;
+            break;
+          }
+          i += 1;
+        }
+      }
+      Some(_) => i += 2,
+      None => i += 1,
+    }
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{start_tee, strip_ansi, ConsoleWriter};
+  use std::io::Write;
+  use tempfile::TempDir;
+
+  #[test]
+  fn tees_are_independent() {
+    let dir = TempDir::new().unwrap();
+    let first_path = dir.path().join("first.log");
+    let second_path = dir.path().join("second.log");
+    let first = start_tee(&first_path).unwrap();
+    let second = start_tee(&second_path).unwrap();
+
+    first.write(b"\x1b[32mfirst\x1b[0m\n");
+    second.writer().write_all(b"second\n").unwrap();
+    ConsoleWriter::new(Vec::new()).write_all(b"both\n").unwrap();
+    drop(first);
+    ConsoleWriter::new(Vec::new())
+      .write_all(b"only second\n")
+      .unwrap();
+    drop(second);
+
+    assert_eq!(
+      std::fs::read_to_string(&first_path).unwrap(),
+      "first\nboth\n"
+    );
+    assert_eq!(
+      std::fs::read_to_string(&second_path).unwrap(),
+      "second\nboth\nonly second\n"
+    );
+  }
+
+  #[test]
+  fn strip_plain() {
+    assert_eq!(strip_ansi(b"hello\nworld"), b"hello\nworld".to_vec());
+  }
+
+  #[test]
+  fn strip_colors() {
+    assert_eq!(
+      strip_ansi(b"\x1b[31mred\x1b[0m and \x1b[1;32mbold green\x1b[m"),
+      b"red and bold green".to_vec()
+    );
+  }
+
+  #[test]
+  fn strip_cursor_movement_and_osc() {
+    assert_eq!(
+      strip_ansi(b"\x1b[2K\x1b[1Aline\x1b]0;title\x07\x1b]8;;url\x1b\\!"),
+      b"line!".to_vec()
+    );
+  }
+
+  #[test]
+  fn strip_truncated_sequence() {
+    assert_eq!(strip_ansi(b"done\x1b"), b"done".to_vec());
+    assert_eq!(strip_ansi(b"done\x1b[3"), b"done".to_vec());
+  }
+}
@@ -46,6 +46,7 @@ macro_rules! debug_log {
     };
 }
 
+pub mod console;
 pub mod logger;
 
 pub use logger::{get_destination, set_destination, Destination};
@@ -68,6 +69,25 @@ enum PythonLogLevel {
   Critical = 50,
 }
 
+impl PythonLogLevel {
+  ///
+  /// Python allows records to be logged at arbitrary integer levels (including custom levels like
+  /// pants' TRACE), so rather than requiring an exact match we round the level of an individual
+  /// record down to the nearest level that we know how to translate.
+  ///
+  fn for_record(python_level: u64) -> PythonLogLevel {
+    match python_level {
+      l if l >= PythonLogLevel::Critical as u64 => PythonLogLevel::Critical,
+      l if l >= PythonLogLevel::Error_ as u64 => PythonLogLevel::Error_,
+      l if l >= PythonLogLevel::Warn as u64 => PythonLogLevel::Warn,
+      l if l >= PythonLogLevel::Info as u64 => PythonLogLevel::Info,
+      l if l >= PythonLogLevel::Debug as u64 => PythonLogLevel::Debug,
+      // NB: A record is never actually emitted at NotSet, so anything below Debug is Trace.
+      _ => PythonLogLevel::Trace,
+    }
+  }
+}
+
 impl From<log::Level> for PythonLogLevel {
   fn from(level: log::Level) -> Self {
     match level {
@@ -115,3 +135,29 @@ impl From<PythonLogLevel> for log::Level {
 mod pants_packages {
   include!(concat!(env!("OUT_DIR"), "/packages.rs"));
 }
+
+#[cfg(test)]
+mod tests {
+  use super::PythonLogLevel;
+
+  #[test]
+  fn record_levels_round_down() {
+    assert_eq!(PythonLogLevel::for_record(0), PythonLogLevel::Trace);
+    assert_eq!(PythonLogLevel::for_record(5), PythonLogLevel::Trace);
+    assert_eq!(PythonLogLevel::for_record(10), PythonLogLevel::Debug);
+    assert_eq!(PythonLogLevel::for_record(15), PythonLogLevel::Debug);
+    assert_eq!(PythonLogLevel::for_record(25), PythonLogLevel::Info);
+    assert_eq!(PythonLogLevel::for_record(30), PythonLogLevel::Warn);
+    assert_eq!(PythonLogLevel::for_record(45), PythonLogLevel::Error_);
+    assert_eq!(PythonLogLevel::for_record(50), PythonLogLevel::Critical);
+    assert_eq!(PythonLogLevel::for_record(1000), PythonLogLevel::Critical);
+  }
+
+  #[test]
+  fn record_levels_translate() {
+    let level: log::Level = PythonLogLevel::for_record(5).into();
+    assert_eq!(level, log::Level::Trace);
+    let level: log::Level = PythonLogLevel::for_record(50).into();
+    assert_eq!(level, log::Level::Error);
+  }
+}
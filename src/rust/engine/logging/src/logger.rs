@@ -1,11 +1,12 @@
 // Copyright 2018 Pants project contributors (see CONTRIBUTORS.md).
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use crate::console::ConsoleWriter;
 use crate::PythonLogLevel;
 use futures::task_local;
 use lazy_static::lazy_static;
-use log::{log, set_logger, set_max_level, LevelFilter, Log, Metadata, Record};
-use parking_lot::Mutex;
+use log::{set_logger, set_max_level, LevelFilter, Log, Metadata, Record};
+use parking_lot::{Mutex, RwLock};
 use simplelog::Config;
 use simplelog::WriteLogger;
 use std::convert::TryInto;
@@ -19,10 +20,17 @@ lazy_static! {
   pub static ref LOGGER: Logger = Logger::new();
 }
 
+///
+/// Logs a record at the given python level, to the python logger with the given name. Returns
+/// false if the record could not be forwarded.
+///
+pub type PythonForwarder = Box<dyn Fn(u64, &str, &str) -> bool + Send + Sync>;
+
 pub struct Logger {
   pantsd_log: Mutex<MaybeWriteLogger<File>>,
-  stderr_log: Mutex<MaybeWriteLogger<Stderr>>,
+  stderr_log: Mutex<MaybeWriteLogger<ConsoleWriter<Stderr>>>,
   show_rust_3rdparty_logs: AtomicBool,
+  python_forwarder: RwLock<Option<PythonForwarder>>,
 }
 
 impl Logger {
@@ -31,6 +39,7 @@ impl Logger {
       pantsd_log: Mutex::new(MaybeWriteLogger::empty()),
       stderr_log: Mutex::new(MaybeWriteLogger::empty()),
       show_rust_3rdparty_logs: AtomicBool::new(true),
+      python_forwarder: RwLock::new(None),
     }
   }
 
@@ -59,7 +68,7 @@ impl Logger {
     python_level.try_into().map(|level: PythonLogLevel| {
       self.maybe_increase_global_verbosity(level.into());
       *self.stderr_log.lock() = MaybeWriteLogger::new(
-        stderr(),
+        ConsoleWriter::new(stderr()),
         level.into(),
         self.show_rust_3rdparty_logs.load(Ordering::SeqCst),
       )
@@ -100,15 +109,51 @@ impl Logger {
     })
   }
 
-  pub fn log_from_python(
-    &self,
-    message: &str,
-    python_level: u64,
-    target: &str,
-  ) -> Result<(), String> {
-    python_level.try_into().map(|level: PythonLogLevel| {
-      log!(target: target, level.into(), "{}", message);
-    })
+  ///
+  /// Forwards records which are emitted in rust to the python `logging` module, under the name of
+  /// their target (with `::` replaced by `.`). Python then filters and formats them alongside its
+  /// own records, and hands them back to `log_from_python`.
+  ///
+  pub fn set_python_forwarder(&self, forwarder: PythonForwarder) {
+    *self.python_forwarder.write() = Some(forwarder);
+  }
+
+  ///
+  /// Logs a record which was emitted by the python `logging` module (including records which were
+  /// forwarded to it from rust), using the python logger name as the target. Records are written
+  /// directly to the sub-logger for the current destination, so that records from python and from
+  /// the engine interleave consistently in each destination.
+  ///
+  pub fn log_from_python(&self, message: &str, python_level: u64, target: &str) {
+    let level: log::Level = PythonLogLevel::for_record(python_level).into();
+    if level <= log::max_level() {
+      self.write(
+        &Record::builder()
+          .args(format_args!("{}", message))
+          .level(level)
+          .target(target)
+          .build(),
+      );
+    }
+  }
+
+  fn forward_to_python(&self, record: &Record) -> bool {
+    match *self.python_forwarder.read_recursive() {
+      Some(ref forwarder) => forwarder(
+        PythonLogLevel::from(record.level()) as u64,
+        &record.target().replace("::", "."),
+        &record.args().to_string(),
+      ),
+      None => false,
+    }
+  }
+
+  fn write(&self, record: &Record) {
+    let destination = get_destination();
+    match destination {
+      Destination::Stderr => self.stderr_log.lock().log(record),
+      Destination::Pantsd => self.pantsd_log.lock().log(record),
+    }
   }
 }
 
@@ -121,10 +166,13 @@ impl Log for Logger {
   }
 
   fn log(&self, record: &Record) {
-    let destination = get_destination();
-    match destination {
-      Destination::Stderr => self.stderr_log.lock().log(record),
-      Destination::Pantsd => self.pantsd_log.lock().log(record),
+    // Third party records are filtered before they are forwarded, because python does not know
+    // which module they were emitted by.
+    if !self.show_rust_3rdparty_logs.load(Ordering::SeqCst) && !is_pants_record(record) {
+      return;
+    }
+    if !self.forward_to_python(record) {
+      self.write(record);
     }
   }
 
@@ -182,20 +230,7 @@ impl<W: Write + Send + 'static> Log for MaybeWriteLogger<W> {
     if !self.enabled(record.metadata()) {
       return;
     }
-    let mut should_log = self.show_rust_3rdparty_logs;
-    if !self.show_rust_3rdparty_logs {
-      if let Some(ref module_path) = record.module_path() {
-        for pants_package in super::pants_packages::PANTS_PACKAGE_NAMES {
-          if &module_path.split("::").next().unwrap() == pants_package {
-            should_log = true;
-            break;
-          }
-        }
-      } else {
-        should_log = true;
-      }
-    }
-    if !should_log {
+    if !self.show_rust_3rdparty_logs && !is_pants_record(record) {
       return;
     }
     if let Some(ref logger) = self.inner {
@@ -210,6 +245,18 @@ impl<W: Write + Send + 'static> Log for MaybeWriteLogger<W> {
   }
 }
 
+///
+/// Whether the record was emitted by a pants crate, or (because it has no module path) by python.
+///
+fn is_pants_record(record: &Record) -> bool {
+  match record.module_path() {
+    Some(module_path) => super::pants_packages::PANTS_PACKAGE_NAMES
+      .iter()
+      .any(|pants_package| module_path.split("::").next() == Some(*pants_package)),
+    None => true,
+  }
+}
+
 ///
 /// Thread- or task-local context for where the Logger should send log statements.
 ///
@@ -0,0 +1,560 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+#![deny(warnings)]
+// Enable all clippy lints except for many of the pedantic ones. It's a shame this needs to be copied and pasted across crates, but there doesn't appear to be a way to include inner attributes from a common source.
+#![deny(
+  clippy::all,
+  clippy::default_trait_access,
+  clippy::expl_impl_clone_on_copy,
+  clippy::if_not_else,
+  clippy::needless_continue,
+  clippy::single_match_else,
+  clippy::unseparated_literal_suffix,
+  clippy::used_underscore_binding
+)]
+// It is often more clear to show that nothing is being moved.
+#![allow(clippy::match_ref_pats)]
+// Subjective style.
+#![allow(
+  clippy::len_without_is_empty,
+  clippy::redundant_field_names,
+  clippy::too_many_arguments
+)]
+// Default isn't as big a deal as people seem to think it is.
+#![allow(clippy::new_without_default, clippy::new_ret_no_self)]
+// Arc<Mutex> can be more clear than needing to grok Orderings:
+#![allow(clippy::mutex_atomic)]
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+use lazy_static::lazy_static;
+use log::{warn, Level};
+use parking_lot::Mutex;
+
+lazy_static! {
+  static ref METRICS: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+// The number of the slowest workunits which are retained.
+const SLOWEST_WORKUNITS: usize = 10;
+
+// The number of workunits with warnings which are retained (in addition to the slowest).
+const WORKUNITS_WITH_WARNINGS: usize = 100;
+
+///
+/// A monotonically increasing count of some event in the engine.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Metric {
+  LocalExecutionRequests,
+  RemoteExecutionRequests,
+  RemoteExecutionCacheHits,
+  RemoteExecutionCacheMisses,
+  RemoteExecutionStaleCacheHits,
+  RemoteStoreBlobsUploaded,
+  RemoteStoreBytesUploaded,
+  RemoteStoreBlobsDownloaded,
+  RemoteStoreBytesDownloaded,
+  NodeRerunsInputsChanged,
+  NodeRerunsFilesChanged,
+  NodeRerunsPreviousFailure,
+  HermeticityWarnings,
+}
+
+impl Metric {
+  pub fn all() -> &'static [Metric] {
+    &[
+      Metric::LocalExecutionRequests,
+      Metric::RemoteExecutionRequests,
+      Metric::RemoteExecutionCacheHits,
+      Metric::RemoteExecutionCacheMisses,
+      Metric::RemoteExecutionStaleCacheHits,
+      Metric::RemoteStoreBlobsUploaded,
+      Metric::RemoteStoreBytesUploaded,
+      Metric::RemoteStoreBlobsDownloaded,
+      Metric::RemoteStoreBytesDownloaded,
+      Metric::NodeRerunsInputsChanged,
+      Metric::NodeRerunsFilesChanged,
+      Metric::NodeRerunsPreviousFailure,
+      Metric::HermeticityWarnings,
+    ]
+  }
+
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Metric::LocalExecutionRequests => "local_execution_requests",
+      Metric::RemoteExecutionRequests => "remote_execution_requests",
+      Metric::RemoteExecutionCacheHits => "remote_execution_cache_hits",
+      Metric::RemoteExecutionCacheMisses => "remote_execution_cache_misses",
+      Metric::RemoteExecutionStaleCacheHits => "remote_execution_stale_cache_hits",
+      Metric::RemoteStoreBlobsUploaded => "remote_store_blobs_uploaded",
+      Metric::RemoteStoreBytesUploaded => "remote_store_bytes_uploaded",
+      Metric::RemoteStoreBlobsDownloaded => "remote_store_blobs_downloaded",
+      Metric::RemoteStoreBytesDownloaded => "remote_store_bytes_downloaded",
+      Metric::NodeRerunsInputsChanged => "node_reruns_inputs_changed",
+      Metric::NodeRerunsFilesChanged => "node_reruns_files_changed",
+      Metric::NodeRerunsPreviousFailure => "node_reruns_previous_failure",
+      Metric::HermeticityWarnings => "hermeticity_warnings",
+    }
+  }
+}
+
+///
+/// A distribution of observed values, recorded into an HDR histogram. Durations are recorded in
+/// microseconds.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ObservationMetric {
+  LocalExecutionTimeMicros,
+  RemoteExecutionTimeMicros,
+  RemoteQueueTimeMicros,
+  RemoteWorkerExecutionTimeMicros,
+  RemoteStoreUploadSizeBytes,
+  RemoteStoreDownloadSizeBytes,
+}
+
+impl ObservationMetric {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      ObservationMetric::LocalExecutionTimeMicros => "local_execution_time_micros",
+      ObservationMetric::RemoteExecutionTimeMicros => "remote_execution_time_micros",
+      ObservationMetric::RemoteQueueTimeMicros => "remote_queue_time_micros",
+      ObservationMetric::RemoteWorkerExecutionTimeMicros => "remote_worker_execution_time_micros",
+      ObservationMetric::RemoteStoreUploadSizeBytes => "remote_store_upload_size_bytes",
+      ObservationMetric::RemoteStoreDownloadSizeBytes => "remote_store_download_size_bytes",
+    }
+  }
+}
+
+///
+/// A point-in-time summary of the values recorded for an ObservationMetric.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistogramSummary {
+  pub count: u64,
+  pub min: u64,
+  pub max: u64,
+  pub mean: f64,
+  pub p50: u64,
+  pub p90: u64,
+  pub p99: u64,
+}
+
+impl HistogramSummary {
+  fn of(histogram: &Histogram<u64>) -> HistogramSummary {
+    HistogramSummary {
+      count: histogram.len(),
+      min: histogram.min(),
+      max: histogram.max(),
+      mean: histogram.mean(),
+      p50: histogram.value_at_quantile(0.5),
+      p90: histogram.value_at_quantile(0.9),
+      p99: histogram.value_at_quantile(0.99),
+    }
+  }
+}
+
+///
+/// A named unit of work (such as the execution of a process), its verbosity, how long it took, and
+/// any warnings about it.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Workunit {
+  pub name: String,
+  pub level: Level,
+  pub duration: Duration,
+  pub warnings: Vec<String>,
+}
+
+///
+/// A structured copy of all metrics recorded while a Scope has been live.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+  pub counters: BTreeMap<Metric, u64>,
+  pub observations: BTreeMap<ObservationMetric, HistogramSummary>,
+  // The slowest workunits, slowest first.
+  pub slowest_workunits: Vec<Workunit>,
+  // The (first) workunits which had warnings, in the order they were recorded.
+  pub workunits_with_warnings: Vec<Workunit>,
+}
+
+struct Metrics {
+  counters: BTreeMap<Metric, u64>,
+  observations: BTreeMap<ObservationMetric, Histogram<u64>>,
+  slowest_workunits: Vec<Workunit>,
+  workunits_with_warnings: Vec<Workunit>,
+}
+
+impl Metrics {
+  fn new() -> Metrics {
+    Metrics {
+      counters: BTreeMap::new(),
+      observations: BTreeMap::new(),
+      slowest_workunits: Vec::new(),
+      workunits_with_warnings: Vec::new(),
+    }
+  }
+
+  fn increment_counter(&mut self, metric: Metric, delta: u64) {
+    *self.counters.entry(metric).or_insert(0) += delta;
+  }
+
+  fn record_observation(&mut self, metric: ObservationMetric, value: u64) {
+    let histogram = self.observations.entry(metric).or_insert_with(|| {
+      // Three significant figures is the usual precision for latency histograms, and
+      // auto-resizing means we never need to decide on a maximum trackable value up front.
+      Histogram::new(3).expect("Three significant figures is a valid histogram precision.")
+    });
+    if let Err(err) = histogram.record(value) {
+      warn!(
+        "Failed to record observation {} for {}: {:?}",
+        value,
+        metric.as_str(),
+        err
+      );
+    }
+  }
+
+  fn record_workunit(&mut self, workunit: &Workunit) {
+    if !workunit.warnings.is_empty() && self.workunits_with_warnings.len() < WORKUNITS_WITH_WARNINGS
+    {
+      self.workunits_with_warnings.push(workunit.clone());
+    }
+    let workunits = &mut self.slowest_workunits;
+    if workunits.len() >= SLOWEST_WORKUNITS
+      && workunits
+        .last()
+        .map(|slowest| slowest.duration >= workunit.duration)
+        .unwrap_or(false)
+    {
+      return;
+    }
+    let position = workunits
+      .iter()
+      .position(|existing| existing.duration < workunit.duration)
+      .unwrap_or_else(|| workunits.len());
+    workunits.insert(position, workunit.clone());
+    workunits.truncate(SLOWEST_WORKUNITS);
+  }
+
+  fn snapshot(&self) -> Snapshot {
+    let mut counters: BTreeMap<Metric, u64> = Metric::all().iter().map(|m| (*m, 0)).collect();
+    counters.extend(self.counters.iter().map(|(m, v)| (*m, *v)));
+    Snapshot {
+      counters: counters,
+      observations: self
+        .observations
+        .iter()
+        .map(|(m, h)| (*m, HistogramSummary::of(h)))
+        .collect(),
+      slowest_workunits: self.slowest_workunits.clone(),
+      workunits_with_warnings: self.workunits_with_warnings.clone(),
+    }
+  }
+
+  fn workunits(&self) -> impl Iterator<Item = &Workunit> + Clone {
+    self
+      .slowest_workunits
+      .iter()
+      .chain(self.workunits_with_warnings.iter())
+  }
+}
+
+///
+/// The Metrics of each live Scope, into all of which every metric is recorded.
+///
+#[derive(Default)]
+struct Registry {
+  next_scope_id: u64,
+  scopes: BTreeMap<u64, Metrics>,
+}
+
+///
+/// A view of the metrics which are recorded while it is live. Each Session holds a Scope, so that
+/// concurrent Sessions each observe everything recorded during their lifetime, rather than
+/// clearing the metrics recorded for one another.
+///
+/// Metrics are recorded into every live Scope, because the work which records them (process
+/// executions, remote store transfers, etc) is shared between Sessions.
+///
+pub struct Scope(u64);
+
+impl Scope {
+  pub fn new() -> Scope {
+    let mut registry = METRICS.lock();
+    let id = registry.next_scope_id;
+    registry.next_scope_id += 1;
+    registry.scopes.insert(id, Metrics::new());
+    Scope(id)
+  }
+
+  ///
+  /// Returns a copy of all metrics recorded since this Scope was created. Counters which have
+  /// never been incremented are reported as zero.
+  ///
+  pub fn snapshot(&self) -> Snapshot {
+    METRICS
+      .lock()
+      .scopes
+      .get(&self.0)
+      .map(Metrics::snapshot)
+      .unwrap_or_default()
+  }
+}
+
+impl Drop for Scope {
+  fn drop(&mut self) {
+    METRICS.lock().scopes.remove(&self.0);
+  }
+}
+
+///
+/// Increments the given counter by `delta`.
+///
+pub fn increment_counter(metric: Metric, delta: u64) {
+  for metrics in METRICS.lock().scopes.values_mut() {
+    metrics.increment_counter(metric, delta);
+  }
+}
+
+///
+/// Records a single observed value for the given metric.
+///
+pub fn record_observation(metric: ObservationMetric, value: u64) {
+  for metrics in METRICS.lock().scopes.values_mut() {
+    metrics.record_observation(metric, value);
+  }
+}
+
+///
+/// Records the given Duration (in microseconds) for the given metric.
+///
+pub fn record_duration(metric: ObservationMetric, duration: Duration) {
+  let micros = duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros());
+  record_observation(metric, micros);
+}
+
+///
+/// Records that the named workunit took the given Duration. Only the slowest workunits are
+/// retained.
+///
+pub fn record_workunit(name: &str, level: Level, duration: Duration) {
+  record_workunit_with_warnings(name, level, duration, vec![])
+}
+
+///
+/// Records that the named workunit took the given Duration, and had the given warnings. The
+/// slowest workunits are retained, as are (up to a limit) all workunits which had warnings.
+///
+pub fn record_workunit_with_warnings(
+  name: &str,
+  level: Level,
+  duration: Duration,
+  warnings: Vec<String>,
+) {
+  let mut registry = METRICS.lock();
+  if registry.scopes.is_empty() {
+    return;
+  }
+  let workunit = Workunit {
+    name: name.to_owned(),
+    level,
+    duration,
+    warnings,
+  };
+  for metrics in registry.scopes.values_mut() {
+    metrics.record_workunit(&workunit);
+  }
+}
+
+///
+/// Returns the number of workunits retained by all live Scopes, and the approximate bytes held by
+/// them.
+///
+pub fn workunits_memory_usage() -> (usize, usize) {
+  let registry = METRICS.lock();
+  let workunits = registry.scopes.values().flat_map(Metrics::workunits);
+  let bytes = workunits
+    .clone()
+    .map(|workunit| {
+      mem::size_of::<Workunit>()
+        + workunit.name.capacity()
+        + workunit
+          .warnings
+          .iter()
+          .map(|warning| mem::size_of::<String>() + warning.capacity())
+          .sum::<usize>()
+    })
+    .sum();
+  (workunits.count(), bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    increment_counter, record_duration, record_observation, record_workunit,
+    record_workunit_with_warnings, workunits_memory_usage, Metric, ObservationMetric, Scope,
+    SLOWEST_WORKUNITS,
+  };
+  use log::Level;
+  use parking_lot::Mutex;
+  use std::time::Duration;
+
+  lazy_static::lazy_static! {
+    // Metrics are recorded into every live Scope, so tests must not run concurrently.
+    static ref SERIAL: Mutex<()> = Mutex::new(());
+  }
+
+  #[test]
+  fn counters_default_to_zero_and_accumulate() {
+    let _lock = SERIAL.lock();
+    let scope = Scope::new();
+    assert_eq!(
+      scope
+        .snapshot()
+        .counters
+        .get(&Metric::RemoteExecutionCacheHits),
+      Some(&0)
+    );
+    increment_counter(Metric::RemoteExecutionCacheHits, 1);
+    increment_counter(Metric::RemoteExecutionCacheHits, 2);
+    assert_eq!(
+      scope
+        .snapshot()
+        .counters
+        .get(&Metric::RemoteExecutionCacheHits),
+      Some(&3)
+    );
+  }
+
+  #[test]
+  fn observations_are_summarized() {
+    let _lock = SERIAL.lock();
+    let scope = Scope::new();
+    for value in 1..=100 {
+      record_observation(ObservationMetric::RemoteStoreUploadSizeBytes, value);
+    }
+    record_duration(
+      ObservationMetric::LocalExecutionTimeMicros,
+      Duration::from_millis(1),
+    );
+
+    let snapshot = scope.snapshot();
+    let uploads = snapshot
+      .observations
+      .get(&ObservationMetric::RemoteStoreUploadSizeBytes)
+      .unwrap();
+    assert_eq!(uploads.count, 100);
+    assert_eq!(uploads.min, 1);
+    assert_eq!(uploads.max, 100);
+    assert_eq!(uploads.p50, 50);
+    assert_eq!(uploads.p99, 99);
+
+    let local = snapshot
+      .observations
+      .get(&ObservationMetric::LocalExecutionTimeMicros)
+      .unwrap();
+    assert_eq!(local.count, 1);
+    assert_eq!(local.max, 1000);
+  }
+
+  #[test]
+  fn only_the_slowest_workunits_are_retained() {
+    let _lock = SERIAL.lock();
+    let scope = Scope::new();
+    for millis in 0..(SLOWEST_WORKUNITS as u64 * 2) {
+      record_workunit(
+        &format!("{}", millis),
+        Level::Info,
+        Duration::from_millis(millis),
+      );
+    }
+
+    let names = scope
+      .snapshot()
+      .slowest_workunits
+      .into_iter()
+      .map(|workunit| workunit.name)
+      .collect::<Vec<_>>();
+    let expected = (SLOWEST_WORKUNITS as u64..(SLOWEST_WORKUNITS as u64 * 2))
+      .rev()
+      .map(|millis| format!("{}", millis))
+      .collect::<Vec<_>>();
+    assert_eq!(names, expected);
+    assert_eq!(workunits_memory_usage().0, SLOWEST_WORKUNITS);
+  }
+
+  #[test]
+  fn workunits_with_warnings_are_retained() {
+    let _lock = SERIAL.lock();
+    let scope = Scope::new();
+    record_workunit_with_warnings(
+      "leaky",
+      Level::Info,
+      Duration::from_millis(0),
+      vec!["Leaked a path.".to_owned()],
+    );
+    for millis in 1..=(SLOWEST_WORKUNITS as u64) {
+      record_workunit(
+        &format!("{}", millis),
+        Level::Info,
+        Duration::from_millis(millis),
+      );
+    }
+
+    let snapshot = scope.snapshot();
+    assert!(snapshot
+      .slowest_workunits
+      .iter()
+      .all(|workunit| workunit.name != "leaky"));
+    let names = snapshot
+      .workunits_with_warnings
+      .into_iter()
+      .map(|workunit| (workunit.name, workunit.warnings))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      names,
+      vec![("leaky".to_owned(), vec!["Leaked a path.".to_owned()])]
+    );
+  }
+
+  #[test]
+  fn scopes_observe_metrics_recorded_while_they_are_live() {
+    let _lock = SERIAL.lock();
+    let first = Scope::new();
+    increment_counter(Metric::LocalExecutionRequests, 5);
+    record_workunit("slow", Level::Info, Duration::from_secs(1));
+
+    // A later Scope does not clear the metrics of an earlier one.
+    let second = Scope::new();
+    increment_counter(Metric::LocalExecutionRequests, 1);
+    record_observation(ObservationMetric::RemoteQueueTimeMicros, 10);
+
+    let first_snapshot = first.snapshot();
+    assert_eq!(
+      first_snapshot.counters.get(&Metric::LocalExecutionRequests),
+      Some(&6)
+    );
+    assert_eq!(first_snapshot.slowest_workunits.len(), 1);
+    assert_eq!(first_snapshot.observations.len(), 1);
+
+    let second_snapshot = second.snapshot();
+    assert_eq!(
+      second_snapshot
+        .counters
+        .get(&Metric::LocalExecutionRequests),
+      Some(&1)
+    );
+    assert!(second_snapshot.slowest_workunits.is_empty());
+    assert_eq!(second_snapshot.observations.len(), 1);
+
+    // Dropped Scopes release their workunits.
+    drop(first);
+    assert_eq!(workunits_memory_usage().0, 0);
+  }
+}
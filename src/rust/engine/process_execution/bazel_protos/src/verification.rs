@@ -1,8 +1,19 @@
 use crate::remote_execution;
+use hashing;
 use protobuf;
 
 use std::collections::HashSet;
 
+///
+/// Verifies that the given Directory is in the canonical form required by the remote execution
+/// API, so that it will have the same Digest regardless of which client or server produced it:
+///  * children are sorted by name, and names are unique across files and directories,
+///  * names are single path components, and are not empty, `.` or `..`,
+///  * all Digests are well formed,
+///  * and there are no unknown fields.
+///
+/// Names are guaranteed to be valid unicode by parsing, which fails for invalid strings.
+///
 pub fn verify_directory_canonical(directory: &remote_execution::Directory) -> Result<(), String> {
   verify_no_unknown_fields(directory)?;
   verify_nodes(directory.get_files(), |n| n.get_name(), |n| n.get_digest())?;
@@ -45,12 +56,23 @@ where
   for node in nodes {
     verify_no_unknown_fields(node)?;
     verify_no_unknown_fields(get_digest(node))?;
-    if get_name(node).contains('/') {
-      return Err(format!(
-        "All children must have one path segment, but found {}",
-        get_name(node)
-      ));
+    match get_name(node) {
+      "" | "." | ".." => {
+        return Err(format!(
+          "Children must have non-empty names which are not `.` or `..`, but found {:?}",
+          get_name(node)
+        ));
+      }
+      name if name.contains('/') => {
+        return Err(format!(
+          "All children must have one path segment, but found {}",
+          name
+        ));
+      }
+      _ => {}
     }
+    verify_digest(get_digest(node))
+      .map_err(|e| format!("Child {} had an invalid digest: {}", get_name(node), e))?;
     if let Some(p) = prev {
       if get_name(node) <= get_name(p) {
         return Err(format!(
@@ -65,6 +87,14 @@ where
   Ok(())
 }
 
+fn verify_digest(digest: &remote_execution::Digest) -> Result<(), String> {
+  if digest.get_size_bytes() < 0 {
+    return Err(format!("Negative size: {}", digest.get_size_bytes()));
+  }
+  let result: Result<hashing::Digest, String> = digest.into();
+  result.map(|_| ())
+}
+
 fn verify_no_unknown_fields(message: &dyn protobuf::Message) -> Result<(), String> {
   if message.get_unknown_fields().fields.is_some() {
     return Err(format!(
@@ -343,6 +373,8 @@ mod canonical_directory_tests {
       });
       dir
     });
+
+    verify_directory_canonical(&directory).expect_err("Want error");
   }
 
   #[test]
@@ -370,5 +402,54 @@ mod canonical_directory_tests {
       });
       file
     });
+
+    verify_directory_canonical(&directory).expect_err("Want error");
+  }
+
+  #[test]
+  fn dot_paths() {
+    for name in &["", ".", ".."] {
+      let mut directory = Directory::new();
+      directory.mut_directories().push({
+        let mut dir = DirectoryNode::new();
+        dir.set_name((*name).to_owned());
+        dir.set_digest({
+          let mut digest = Digest::new();
+          digest.set_size_bytes(DIRECTORY_SIZE);
+          digest.set_hash(DIRECTORY_HASH.to_owned());
+          digest
+        });
+        dir
+      });
+
+      let error = verify_directory_canonical(&directory).expect_err("Want error");
+      assert!(error.contains("non-empty names"), "Bad error: {}", error);
+    }
+  }
+
+  #[test]
+  fn invalid_digest() {
+    let mut directory = Directory::new();
+    directory.mut_files().push({
+      let mut file = FileNode::new();
+      file.set_name("roland".to_owned());
+      file.set_digest({
+        let mut digest = Digest::new();
+        digest.set_size_bytes(FILE_SIZE);
+        digest.set_hash("not-a-hash".to_owned());
+        digest
+      });
+      file
+    });
+
+    let error = verify_directory_canonical(&directory).expect_err("Want error");
+    assert!(error.contains("invalid digest"), "Bad error: {}", error);
+
+    directory.mut_files()[0]
+      .mut_digest()
+      .set_hash(HASH.to_owned());
+    directory.mut_files()[0].mut_digest().set_size_bytes(-1);
+    let error = verify_directory_canonical(&directory).expect_err("Want error");
+    assert!(error.contains("Negative size"), "Bad error: {}", error);
   }
 }
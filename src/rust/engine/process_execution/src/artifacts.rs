@@ -0,0 +1,416 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use boxfuture::{BoxFuture, Boxable};
+use fs::Store;
+use futures::{future, Future};
+use hashing::Digest;
+use log::warn;
+use serde_derive::Serialize;
+use serde_json;
+
+use super::{
+  CommandRunner, Context, ExecuteProcessRequest, ExecuteProcessRequestMetadata,
+  FallibleExecuteProcessResult,
+};
+
+///
+/// The process execution which produced a file.
+///
+#[derive(Debug)]
+struct Producer {
+  // The digest of the (remote execution API) Action for the request, as recorded by provenance.
+  action_digest: Option<Digest>,
+  workunit_id: usize,
+  description: String,
+}
+
+///
+/// A record of one file which was materialized in the workspace, and the process which produced it.
+///
+#[derive(Debug, Serialize)]
+pub struct ArtifactEntry {
+  pub path: PathBuf,
+  pub digest: Digest,
+  pub action_digest: Option<Digest>,
+  pub workunit_id: usize,
+  pub description: String,
+}
+
+///
+/// An index from the digests of the files output by processes to the processes which produced
+/// them, which is used to append a JSON ArtifactEntry line to a file for each produced file that
+/// is materialized in the workspace.
+///
+/// Producers are remembered for the lifetime of the index (because process results may be
+/// memoized between runs), but the file is truncated by the first materialization of each run,
+/// unless another run which has materialized files is still in progress (in which case their
+/// entries are interleaved).
+///
+pub struct ArtifactIndex {
+  path: PathBuf,
+  file: Mutex<File>,
+  producers: Mutex<HashMap<Digest, Arc<Producer>>>,
+  next_workunit_id: AtomicUsize,
+  // The ids of the runs which have materialized files, and which have not yet ended.
+  live_runs: Mutex<HashSet<u32>>,
+}
+
+impl ArtifactIndex {
+  pub fn new(path: &Path) -> Result<ArtifactIndex, String> {
+    let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .map_err(|e| format!("Failed to open artifact index {:?}: {}", path, e))?;
+    let index = ArtifactIndex {
+      path: path.to_owned(),
+      file: Mutex::new(file),
+      producers: Mutex::new(HashMap::new()),
+      next_workunit_id: AtomicUsize::new(0),
+      live_runs: Mutex::new(HashSet::new()),
+    };
+    index.truncate(&mut index.file.lock().unwrap())?;
+    Ok(index)
+  }
+
+  fn truncate(&self, file: &mut File) -> Result<(), String> {
+    file
+      .set_len(0)
+      .map_err(|e| format!("Failed to truncate artifact index {:?}: {}", self.path, e))
+  }
+
+  ///
+  /// Records that the run with the given id has ended, so that the next run to materialize files
+  /// truncates the index (if no other run is in progress).
+  ///
+  pub fn end_run(&self, run_id: u32) {
+    self.live_runs.lock().unwrap().remove(&run_id);
+  }
+
+  ///
+  /// Records that the given output files were produced by the given request, executed with the
+  /// given metadata.
+  ///
+  fn record_files(
+    &self,
+    req: &ExecuteProcessRequest,
+    metadata: &ExecuteProcessRequestMetadata,
+    files: Vec<(PathBuf, Digest)>,
+  ) {
+    let action_digest = super::remote::make_execute_request(req, metadata)
+      .and_then(|(_, _, execute_request)| execute_request.get_action_digest().into())
+      .map_err(|e| warn!("Failed to compute action digest for artifact index: {}", e))
+      .ok();
+    let producer = Arc::new(Producer {
+      action_digest,
+      workunit_id: self.next_workunit_id.fetch_add(1, Ordering::SeqCst),
+      description: req.description.clone(),
+    });
+    let mut producers = self.producers.lock().unwrap();
+    for (_, digest) in files {
+      producers.insert(digest, producer.clone());
+    }
+  }
+
+  ///
+  /// Appends an entry for each file below the given Directory digest which was produced by a
+  /// process, and which has been materialized below the given destination by the run with the
+  /// given id.
+  ///
+  pub fn record_materialized(
+    index: Arc<ArtifactIndex>,
+    store: &Store,
+    run_id: u32,
+    destination: PathBuf,
+    digest: Digest,
+  ) -> BoxFuture<(), String> {
+    file_digests(store, digest)
+      .and_then(move |files| {
+        let entries = {
+          let producers = index.producers.lock().unwrap();
+          files
+            .into_iter()
+            .filter_map(|(path, digest)| {
+              producers.get(&digest).map(|producer| ArtifactEntry {
+                path: destination.join(path),
+                digest,
+                action_digest: producer.action_digest,
+                workunit_id: producer.workunit_id,
+                description: producer.description.clone(),
+              })
+            })
+            .collect::<Vec<_>>()
+        };
+        let mut content = String::new();
+        for entry in entries {
+          content.push_str(
+            &serde_json::to_string(&entry)
+              .map_err(|e| format!("Failed to serialize artifact entry: {}", e))?,
+          );
+          content.push('\n');
+        }
+        // A single write per materialization, so that concurrent entries are not interleaved.
+        let mut file = index.file.lock().unwrap();
+        let is_only_live_run = {
+          let mut live_runs = index.live_runs.lock().unwrap();
+          live_runs.insert(run_id) && live_runs.len() == 1
+        };
+        if is_only_live_run {
+          index.truncate(&mut file)?;
+        }
+        file
+          .write_all(content.as_bytes())
+          .map_err(|e| format!("Failed to write artifact index {:?}: {}", index.path, e))
+      })
+      .to_boxed()
+  }
+}
+
+///
+/// Lists the paths and digests of the files below the given Directory digest, sorted by path.
+///
+fn file_digests(store: &Store, digest: Digest) -> BoxFuture<Vec<(PathBuf, Digest)>, String> {
+  store
+    .walk(digest, |_, path_so_far, _, directory| {
+      let files: Result<Vec<_>, String> = directory
+        .get_files()
+        .iter()
+        .map(|file_node| {
+          let digest: Result<Digest, String> = file_node.get_digest().into();
+          digest.map(|digest| (path_so_far.join(file_node.get_name()), digest))
+        })
+        .collect();
+      future::result(files).to_boxed()
+    })
+    .map(|files_per_directory| {
+      let mut files = files_per_directory
+        .into_iter()
+        .flat_map(Vec::into_iter)
+        .collect::<Vec<_>>();
+      files.sort_by(|l, r| l.0.cmp(&r.0));
+      files
+    })
+    .to_boxed()
+}
+
+///
+/// A CommandRunner wrapper which records the files output by each successful process in an
+/// ArtifactIndex.
+///
+pub struct ArtifactIndexingCommandRunner {
+  inner: Box<dyn CommandRunner>,
+  store: Store,
+  index: Arc<ArtifactIndex>,
+}
+
+impl ArtifactIndexingCommandRunner {
+  pub fn new(
+    inner: Box<dyn CommandRunner>,
+    store: Store,
+    index: Arc<ArtifactIndex>,
+  ) -> ArtifactIndexingCommandRunner {
+    ArtifactIndexingCommandRunner {
+      inner,
+      store,
+      index,
+    }
+  }
+}
+
+impl CommandRunner for ArtifactIndexingCommandRunner {
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    let store = self.store.clone();
+    let index = self.index.clone();
+    let req2 = req.clone();
+    let metadata = context.metadata(&ExecuteProcessRequestMetadata::default());
+    self
+      .inner
+      .run(req, context)
+      .and_then(move |result| {
+        if result.exit_code != 0 {
+          return future::ok(result).to_boxed();
+        }
+        // Failing to index outputs should not fail the process.
+        let output_directory = result.output_directory;
+        file_digests(&store, output_directory)
+          .then(move |files| {
+            match files {
+              Ok(files) => index.record_files(&req2, &metadata, files),
+              Err(e) => warn!("Failed to index outputs of {}: {}", req2.description, e),
+            }
+            Ok(result)
+          })
+          .to_boxed()
+      })
+      .to_boxed()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::{Path, PathBuf};
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  use boxfuture::{BoxFuture, Boxable};
+  use futures::{future, Future};
+  use serde_json::{self, Value};
+  use tempfile::TempDir;
+  use testutil::data::{TestData, TestDirectory};
+  use testutil::{as_bytes, owned_string_vec};
+
+  use super::{ArtifactIndex, ArtifactIndexingCommandRunner};
+  use crate::{
+    CommandRunner, Context, ExecuteProcessRequest, ExecutionStats, FallibleExecuteProcessResult,
+  };
+
+  struct RolandCommandRunner;
+
+  impl CommandRunner for RolandCommandRunner {
+    fn run(
+      &self,
+      _req: ExecuteProcessRequest,
+      _context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      future::ok(FallibleExecuteProcessResult {
+        stdout: as_bytes(""),
+        stderr: as_bytes(""),
+        exit_code: 0,
+        output_directory: TestDirectory::containing_roland().digest(),
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
+      })
+      .to_boxed()
+    }
+  }
+
+  fn request() -> ExecuteProcessRequest {
+    ExecuteProcessRequest {
+      argv: owned_string_vec(&["write", "roland"]),
+      timeout: Duration::from_millis(1000),
+      description: "write roland".to_owned(),
+      ..ExecuteProcessRequest::default()
+    }
+  }
+
+  fn entries(path: &Path) -> Vec<Value> {
+    std::fs::read_to_string(path)
+      .unwrap()
+      .lines()
+      .map(|line| serde_json::from_str::<Value>(line).unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn records_producers_of_materialized_files() {
+    let store_dir = TempDir::new().unwrap();
+    let index_dir = TempDir::new().unwrap();
+    let store = fs::Store::local_only(store_dir.path()).unwrap();
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime
+      .block_on(store.store_file_bytes(TestData::roland().bytes(), false))
+      .unwrap();
+    runtime
+      .block_on(store.record_directory(&TestDirectory::containing_roland().directory(), false))
+      .unwrap();
+    runtime
+      .block_on(store.record_directory(
+        &TestDirectory::containing_roland_and_treats().directory(),
+        false,
+      ))
+      .unwrap();
+
+    let path = index_dir.path().join("artifacts.jsonl");
+    let index = Arc::new(ArtifactIndex::new(&path).unwrap());
+    let runner = ArtifactIndexingCommandRunner::new(
+      Box::new(RolandCommandRunner),
+      store.clone(),
+      index.clone(),
+    );
+    runtime
+      .block_on(runner.run(request(), Context::default()))
+      .unwrap();
+
+    // Only roland was produced by a process, so treats is not indexed.
+    runtime
+      .block_on(ArtifactIndex::record_materialized(
+        index.clone(),
+        &store,
+        0,
+        PathBuf::from("dist"),
+        TestDirectory::containing_roland_and_treats().digest(),
+      ))
+      .unwrap();
+
+    let entries = entries(&path);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["path"], "dist/roland");
+    assert_eq!(entries[0]["description"], "write roland");
+    assert_eq!(entries[0]["workunit_id"], 0);
+    assert_eq!(
+      entries[0]["digest"]["fingerprint"],
+      TestData::roland().fingerprint().to_hex()
+    );
+    assert!(entries[0]["action_digest"]["fingerprint"].is_string());
+  }
+
+  #[test]
+  fn each_run_replaces_the_entries_of_previous_runs() {
+    let store_dir = TempDir::new().unwrap();
+    let index_dir = TempDir::new().unwrap();
+    let store = fs::Store::local_only(store_dir.path()).unwrap();
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime
+      .block_on(store.store_file_bytes(TestData::roland().bytes(), false))
+      .unwrap();
+    runtime
+      .block_on(store.record_directory(&TestDirectory::containing_roland().directory(), false))
+      .unwrap();
+
+    let path = index_dir.path().join("artifacts.jsonl");
+    let index = Arc::new(ArtifactIndex::new(&path).unwrap());
+    let runner = ArtifactIndexingCommandRunner::new(
+      Box::new(RolandCommandRunner),
+      store.clone(),
+      index.clone(),
+    );
+    runtime
+      .block_on(runner.run(request(), Context::default()))
+      .unwrap();
+    let mut materialize = |run_id: u32, destination: &str| {
+      runtime
+        .block_on(ArtifactIndex::record_materialized(
+          index.clone(),
+          &store,
+          run_id,
+          PathBuf::from(destination),
+          TestDirectory::containing_roland().digest(),
+        ))
+        .unwrap();
+    };
+
+    // Concurrent runs append to the index...
+    materialize(0, "first");
+    materialize(1, "second");
+    materialize(0, "third");
+    assert_eq!(entries(&path).len(), 3);
+
+    // ...and once they have ended, the next run replaces their entries.
+    index.end_run(0);
+    index.end_run(1);
+    materialize(2, "fourth");
+    let entries = entries(&path);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["path"], "fourth/roland");
+  }
+}
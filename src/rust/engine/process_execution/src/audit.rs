@@ -0,0 +1,357 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use boxfuture::{BoxFuture, Boxable};
+use futures::Future;
+use glob::{MatchOptions, Pattern};
+use log::warn;
+use serde_derive::Serialize;
+use serde_json;
+
+use super::{CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult};
+
+// The value recorded in place of the values of redacted environment variables.
+const REDACTED: &str = "<redacted>";
+
+///
+/// Configuration for an audit log of executed processes.
+///
+#[derive(Clone, Debug)]
+pub struct AuditLogConfig {
+  pub path: PathBuf,
+  // Case-insensitive glob patterns (such as `*TOKEN*`) for the names of environment variables
+  // whose values should not be recorded.
+  pub redact_env_patterns: Vec<String>,
+}
+
+///
+/// A record of one process which was about to be executed.
+///
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+  // Identifies the process, so that its AuditCompletion can be matched with this entry.
+  pub id: String,
+  pub started_at_secs: u64,
+  // The description of the process, which is provided by the rule which requested it.
+  pub description: String,
+  pub argv: Vec<String>,
+  pub env: BTreeMap<String, String>,
+  pub input_digest: hashing::Digest,
+}
+
+///
+/// A record of the completion of a process which was previously recorded by an AuditEntry.
+///
+#[derive(Debug, Serialize)]
+pub struct AuditCompletion {
+  pub id: String,
+  // The runner which produced the result, and its exit code, if the process completed.
+  pub runner: Option<String>,
+  pub exit_code: Option<i32>,
+}
+
+///
+/// A CommandRunner wrapper which appends a JSON AuditEntry line to an audit log for each process
+/// before it is executed, so that processes which never complete are recorded too, and then an
+/// AuditCompletion line once it has completed.
+///
+/// The log is only ever appended to, and is created readable only by its owner.
+///
+pub struct AuditLogCommandRunner {
+  inner: Box<dyn CommandRunner>,
+  redact_env_patterns: Vec<Pattern>,
+  file: Arc<Mutex<File>>,
+}
+
+impl AuditLogCommandRunner {
+  pub fn new(
+    inner: Box<dyn CommandRunner>,
+    config: &AuditLogConfig,
+  ) -> Result<AuditLogCommandRunner, String> {
+    let redact_env_patterns = config
+      .redact_env_patterns
+      .iter()
+      .map(|pattern| {
+        Pattern::new(pattern)
+          .map_err(|e| format!("Invalid env redaction pattern {:?}: {}", pattern, e))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    set_private_mode(&mut options);
+    let file = options
+      .open(&config.path)
+      .map_err(|e| format!("Failed to open audit log {:?}: {}", config.path, e))?;
+    Ok(AuditLogCommandRunner {
+      inner,
+      redact_env_patterns,
+      file: Arc::new(Mutex::new(file)),
+    })
+  }
+
+  fn entry(&self, req: &ExecuteProcessRequest) -> AuditEntry {
+    let match_options = MatchOptions {
+      case_sensitive: false,
+      ..MatchOptions::new()
+    };
+    // Secret env is always redacted, in addition to any env matching the configured patterns.
+    let env = req
+      .redacted_env()
+      .iter()
+      .map(|(key, value)| {
+        let redact = self
+          .redact_env_patterns
+          .iter()
+          .any(|pattern| pattern.matches_with(key, &match_options));
+        let value = if redact { REDACTED } else { value };
+        (key.clone(), value.to_owned())
+      })
+      .collect();
+    AuditEntry {
+      id: uuid::Uuid::new_v4().to_string(),
+      started_at_secs: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0),
+      description: req.description.clone(),
+      argv: req.argv.clone(),
+      env,
+      input_digest: req.input_files,
+    }
+  }
+
+  fn record<T: serde::Serialize>(file: &Mutex<File>, entry: &T) {
+    let mut line = match serde_json::to_string(entry) {
+      Ok(line) => line,
+      Err(e) => {
+        warn!("Failed to serialize audit entry: {}", e);
+        return;
+      }
+    };
+    line.push('\n');
+    // A single write per entry, so that concurrent entries are not interleaved.
+    let mut file = file.lock().unwrap();
+    if let Err(e) = file.write_all(line.as_bytes()) {
+      warn!("Failed to write audit entry: {}", e);
+    }
+  }
+}
+
+impl CommandRunner for AuditLogCommandRunner {
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    let entry = self.entry(&req);
+    Self::record(&self.file, &entry);
+    let id = entry.id;
+    let file = self.file.clone();
+    self
+      .inner
+      .run(req, context)
+      .then(move |result| {
+        let completion = match result {
+          Ok(ref result) => AuditCompletion {
+            id,
+            runner: Some(result.runner.clone()),
+            exit_code: Some(result.exit_code),
+          },
+          Err(_) => AuditCompletion {
+            id,
+            runner: None,
+            exit_code: None,
+          },
+        };
+        Self::record(&file, &completion);
+        result
+      })
+      .to_boxed()
+  }
+}
+
+// The log may record secrets which are not redacted, so it is only readable by its owner.
+#[cfg(unix)]
+fn set_private_mode(options: &mut OpenOptions) {
+  use std::os::unix::fs::OpenOptionsExt;
+  options.mode(0o600);
+}
+
+// Windows has no mode bits: instead, a new file inherits the access control list of its directory.
+#[cfg(windows)]
+fn set_private_mode(_options: &mut OpenOptions) {}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+  use std::time::Duration;
+
+  use boxfuture::{BoxFuture, Boxable};
+  use futures::{future, Future};
+  use serde_json::{self, Value};
+  use tempfile::TempDir;
+  use testutil::{as_bytes, owned_string_vec};
+
+  use super::{AuditLogCommandRunner, AuditLogConfig};
+  use crate::{CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult};
+
+  struct FailingCommandRunner;
+
+  impl CommandRunner for FailingCommandRunner {
+    fn run(
+      &self,
+      _req: ExecuteProcessRequest,
+      _context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      future::err("Failed to execute!".to_owned()).to_boxed()
+    }
+  }
+
+  struct NoopCommandRunner;
+
+  impl CommandRunner for NoopCommandRunner {
+    fn run(
+      &self,
+      _req: ExecuteProcessRequest,
+      _context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      future::ok(FallibleExecuteProcessResult {
+        stdout: as_bytes(""),
+        stderr: as_bytes(""),
+        exit_code: 0,
+        output_directory: fs::EMPTY_DIGEST,
+        execution_attempts: vec![],
+        runner: "noop".to_owned(),
+        timed_out: None,
+      })
+      .to_boxed()
+    }
+  }
+
+  fn request(argv: &[&str]) -> ExecuteProcessRequest {
+    let mut env = BTreeMap::new();
+    env.insert("PATH".to_owned(), "/bin".to_owned());
+    env.insert("github_token".to_owned(), "hunter2".to_owned());
+    ExecuteProcessRequest {
+      argv: owned_string_vec(argv),
+      env,
+      timeout: Duration::from_millis(1000),
+      description: "Run curl".to_owned(),
+      ..ExecuteProcessRequest::default()
+    }
+  }
+
+  fn config(dir: &TempDir) -> AuditLogConfig {
+    AuditLogConfig {
+      path: dir.path().join("audit.jsonl"),
+      redact_env_patterns: vec!["*TOKEN*".to_owned()],
+    }
+  }
+
+  #[test]
+  fn records_redacted_entries() {
+    let dir = TempDir::new().unwrap();
+    let config = config(&dir);
+    let runner = AuditLogCommandRunner::new(Box::new(NoopCommandRunner), &config).unwrap();
+
+    runner
+      .run(
+        request(&["curl", "https://example.com"]),
+        Context::default(),
+      )
+      .wait()
+      .unwrap();
+
+    let content = std::fs::read_to_string(&config.path).unwrap();
+    assert!(!content.contains("hunter2"));
+    let entries = content
+      .lines()
+      .map(|line| serde_json::from_str::<Value>(line).unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 2);
+    let entry = &entries[0];
+    assert_eq!(entry["description"], "Run curl");
+    assert_eq!(
+      entry["argv"],
+      serde_json::json!(["curl", "https://example.com"])
+    );
+    assert_eq!(
+      entry["env"],
+      serde_json::json!({"PATH": "/bin", "github_token": "<redacted>"})
+    );
+    let completion = &entries[1];
+    assert_eq!(completion["id"], entry["id"]);
+    assert_eq!(completion["runner"], "noop");
+    assert_eq!(completion["exit_code"], 0);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn is_only_readable_by_owner() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = TempDir::new().unwrap();
+    let config = config(&dir);
+    AuditLogCommandRunner::new(Box::new(NoopCommandRunner), &config).unwrap();
+
+    let mode = std::fs::metadata(&config.path)
+      .unwrap()
+      .permissions()
+      .mode();
+    assert_eq!(mode & 0o777, 0o600);
+  }
+
+  #[test]
+  fn records_before_execution() {
+    let dir = TempDir::new().unwrap();
+    let config = config(&dir);
+    let runner = AuditLogCommandRunner::new(Box::new(FailingCommandRunner), &config).unwrap();
+
+    runner
+      .run(request(&["false"]), Context::default())
+      .wait()
+      .unwrap_err();
+    runner
+      .run(request(&["false"]), Context::default())
+      .wait()
+      .unwrap_err();
+
+    let content = std::fs::read_to_string(&config.path).unwrap();
+    let entries = content
+      .lines()
+      .map(|line| serde_json::from_str::<Value>(line).unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 4);
+    assert_eq!(
+      entries
+        .iter()
+        .filter(|entry| entry["argv"] == serde_json::json!(["false"]))
+        .count(),
+      2
+    );
+    assert!(entries
+      .iter()
+      .filter(|entry| entry.get("argv").is_none())
+      .all(|completion| completion["runner"] == Value::Null));
+  }
+
+  #[test]
+  fn invalid_pattern() {
+    let dir = TempDir::new().unwrap();
+    let config = AuditLogConfig {
+      redact_env_patterns: vec!["[".to_owned()],
+      ..config(&dir)
+    };
+    let error = AuditLogCommandRunner::new(Box::new(NoopCommandRunner), &config)
+      .err()
+      .unwrap();
+    assert!(
+      error.starts_with("Invalid env redaction pattern \"[\""),
+      "Unexpected error: {}",
+      error
+    );
+  }
+}
@@ -0,0 +1,198 @@
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use log::warn;
+use tempfile::TempDir;
+
+// The longest that a waiter sleeps between checks of whether the process has exited: a process
+// whose outputs are inherited by its own children may exit without closing them.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+///
+/// The outputs of a BackgroundProcess so far, and whether it has exited.
+///
+#[derive(Clone, Debug)]
+pub struct BackgroundProgress {
+  pub stdout: Bytes,
+  pub stderr: Bytes,
+  pub exit_code: Option<i32>,
+  pub elapsed: Duration,
+}
+
+#[derive(Default)]
+struct Outputs {
+  stdout: Vec<u8>,
+  stderr: Vec<u8>,
+}
+
+///
+/// A process which runs in the background (such as a dev server) while its stdout and stderr are
+/// captured as they are written, so that a caller may wait for it to become ready and then continue
+/// while it runs.
+///
+/// The process is killed when the BackgroundProcess is dropped.
+///
+pub struct BackgroundProcess {
+  child: Mutex<Child>,
+  outputs: Arc<(Mutex<Outputs>, Condvar)>,
+  started: Instant,
+  // The sandbox of the process, which is kept until the process is dropped (if it is to be cleaned
+  // up at all).
+  _sandbox: Option<TempDir>,
+}
+
+impl BackgroundProcess {
+  pub fn spawn(
+    command: &mut Command,
+    sandbox: Option<TempDir>,
+  ) -> Result<BackgroundProcess, String> {
+    let mut child = command
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .map_err(|e| format!("Error launching background process: {:?}", e))?;
+    let outputs = Arc::new((Mutex::new(Outputs::default()), Condvar::new()));
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    Self::capture(stdout, outputs.clone(), |outputs| &mut outputs.stdout);
+    Self::capture(stderr, outputs.clone(), |outputs| &mut outputs.stderr);
+    Ok(BackgroundProcess {
+      child: Mutex::new(child),
+      outputs,
+      started: Instant::now(),
+      _sandbox: sandbox,
+    })
+  }
+
+  ///
+  /// Spawns a thread which appends everything read from the given output to the selected buffer,
+  /// and wakes any waiters, until the output is closed.
+  ///
+  fn capture<R: Read + Send + 'static>(
+    mut output: R,
+    outputs: Arc<(Mutex<Outputs>, Condvar)>,
+    select: fn(&mut Outputs) -> &mut Vec<u8>,
+  ) {
+    thread::spawn(move || {
+      let mut buf = [0; 8192];
+      loop {
+        match output.read(&mut buf) {
+          Ok(0) => break,
+          Ok(n) => select(&mut outputs.0.lock().unwrap()).extend_from_slice(&buf[..n]),
+          Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+          Err(e) => {
+            warn!("Error reading output of background process: {}", e);
+            break;
+          }
+        }
+        outputs.1.notify_all();
+      }
+      outputs.1.notify_all();
+    });
+  }
+
+  ///
+  /// The exit code of the process, or None if it is still running.
+  ///
+  pub fn exit_code(&self) -> Option<i32> {
+    match self.child.lock().unwrap().try_wait() {
+      Ok(Some(status)) => Some(super::local::exit_code(status).unwrap_or(-1)),
+      Ok(None) => None,
+      Err(e) => {
+        warn!("Error checking status of background process: {}", e);
+        Some(-1)
+      }
+    }
+  }
+
+  ///
+  /// Blocks until the process has written more than `stdout_seen` bytes of stdout, until it has
+  /// exited, or (as a heartbeat) until the timeout has elapsed, and then returns its progress.
+  ///
+  pub fn wait_for_progress(&self, stdout_seen: usize, timeout: Duration) -> BackgroundProgress {
+    let deadline = Instant::now() + timeout;
+    let (ref lock, ref condvar) = *self.outputs;
+    let mut outputs = lock.lock().unwrap();
+    let mut exit_code = self.exit_code();
+    while outputs.stdout.len() <= stdout_seen && exit_code.is_none() {
+      let now = Instant::now();
+      if now >= deadline {
+        break;
+      }
+      let wait = std::cmp::min(deadline - now, EXIT_POLL_INTERVAL);
+      outputs = condvar.wait_timeout(outputs, wait).unwrap().0;
+      exit_code = self.exit_code();
+    }
+    BackgroundProgress {
+      stdout: Bytes::from(&outputs.stdout[..]),
+      stderr: Bytes::from(&outputs.stderr[..]),
+      exit_code,
+      elapsed: self.started.elapsed(),
+    }
+  }
+
+  ///
+  /// Kills the process (if it has not already exited), and returns its exit code.
+  ///
+  pub fn kill(&self) -> Result<i32, String> {
+    let mut child = self.child.lock().unwrap();
+    if let Ok(None) = child.try_wait() {
+      child
+        .kill()
+        .map_err(|e| format!("Error killing background process: {}", e))?;
+    }
+    child
+      .wait()
+      .map_err(|e| format!("Error waiting for background process: {}", e))
+      .map(|status| super::local::exit_code(status).unwrap_or(-1))
+  }
+}
+
+impl Drop for BackgroundProcess {
+  fn drop(&mut self) {
+    if let Err(e) = self.kill() {
+      warn!("{}", e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::process::Command;
+  use std::time::Duration;
+
+  use super::BackgroundProcess;
+
+  fn bash(script: &str) -> BackgroundProcess {
+    BackgroundProcess::spawn(Command::new("/bin/bash").arg("-c").arg(script), None).unwrap()
+  }
+
+  #[test]
+  fn waits_for_stdout() {
+    let process = bash("echo -n ready; sleep 60");
+    let progress = process.wait_for_progress(0, Duration::from_secs(10));
+    assert_eq!(progress.stdout, "ready".as_bytes());
+    assert_eq!(progress.exit_code, None);
+    assert!(progress.elapsed < Duration::from_secs(10));
+
+    // With no further output, the wait ends with a heartbeat after the timeout.
+    let progress = process.wait_for_progress(5, Duration::from_millis(200));
+    assert_eq!(progress.stdout, "ready".as_bytes());
+    assert_eq!(progress.exit_code, None);
+
+    assert!(process.kill().unwrap() < 0);
+  }
+
+  #[test]
+  fn waits_for_exit() {
+    let process = bash("echo -n oops >&2; exit 3");
+    let progress = process.wait_for_progress(0, Duration::from_secs(10));
+    assert_eq!(progress.exit_code, Some(3));
+    assert_eq!(process.kill().unwrap(), 3);
+  }
+}
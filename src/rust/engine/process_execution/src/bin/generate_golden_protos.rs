@@ -0,0 +1,70 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+#![deny(warnings)]
+// Enable all clippy lints except for many of the pedantic ones. It's a shame this needs to be copied and pasted across crates, but there doesn't appear to be a way to include inner attributes from a common source.
+#![deny(
+  clippy::all,
+  clippy::default_trait_access,
+  clippy::expl_impl_clone_on_copy,
+  clippy::if_not_else,
+  clippy::needless_continue,
+  clippy::single_match_else,
+  clippy::unseparated_literal_suffix,
+  clippy::used_underscore_binding
+)]
+// It is often more clear to show that nothing is being moved.
+#![allow(clippy::match_ref_pats)]
+// Subjective style.
+#![allow(
+  clippy::len_without_is_empty,
+  clippy::redundant_field_names,
+  clippy::too_many_arguments
+)]
+// Default isn't as big a deal as people seem to think it is.
+#![allow(clippy::new_without_default, clippy::new_ret_no_self)]
+// Arc<Mutex> can be more clear than needing to grok Orderings:
+#![allow(clippy::mutex_atomic)]
+
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+use process_execution::golden;
+
+///
+/// Regenerates the golden files of the protos which are constructed for representative requests,
+/// and removes any golden files which are no longer generated.
+///
+fn main() {
+  if let Err(e) = generate(&golden::golden_dir()) {
+    eprintln!("{}", e);
+    exit(1);
+  }
+}
+
+fn generate(golden_dir: &Path) -> Result<(), String> {
+  let golden_files = golden::render_all()?;
+  fs::create_dir_all(golden_dir)
+    .map_err(|e| format!("Failed to create {}: {}", golden_dir.display(), e))?;
+
+  let entries = fs::read_dir(golden_dir)
+    .map_err(|e| format!("Failed to list {}: {}", golden_dir.display(), e))?;
+  for entry in entries {
+    let path = entry
+      .map_err(|e| format!("Failed to list {}: {}", golden_dir.display(), e))?
+      .path();
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    if !golden_files.contains_key(&name) {
+      fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+      println!("Removed {}", path.display());
+    }
+  }
+
+  for (name, content) in golden_files {
+    let path = golden_dir.join(name);
+    fs::write(&path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    println!("Wrote {}", path.display());
+  }
+  Ok(())
+}
@@ -0,0 +1,404 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use boxfuture::{BoxFuture, Boxable};
+use fs::Store;
+use futures::{future, Future};
+use glob::Pattern;
+use hashing::Digest;
+use log::warn;
+use serde_derive::Serialize;
+use serde_json;
+
+use super::verify::{compare_outputs, diff, digest_of, VerifiedOutcome};
+use super::{CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult};
+
+///
+/// Configuration for checking that processes produce the same outputs each time they run.
+///
+#[derive(Clone, Debug)]
+pub struct DeterminismCheckConfig {
+  pub report_path: PathBuf,
+  // Glob patterns for the descriptions of the processes to check. If empty, all are checked.
+  pub description_patterns: Vec<String>,
+  // Whether to run the second execution of each process in a sandbox below a different directory,
+  // to detect processes whose outputs depend on the absolute path at which they ran.
+  pub vary_sandbox: bool,
+}
+
+///
+/// A file which differed between the outputs of two runs of a process. A digest of None indicates
+/// that the file was not output by that run.
+///
+#[derive(Debug, Serialize)]
+pub struct OutputDifference {
+  pub path: PathBuf,
+  pub first_digest: Option<Digest>,
+  pub second_digest: Option<Digest>,
+  // The lines which were removed ("-") or added ("+") by the second run, if both versions of the
+  // file were small enough UTF8 to diff.
+  pub diff: Option<String>,
+}
+
+///
+/// A record of a process which produced different results when it was run twice.
+///
+#[derive(Debug, Serialize)]
+pub struct DeterminismMismatch {
+  pub description: String,
+  pub argv: Vec<String>,
+  pub input_digest: Digest,
+  pub first: VerifiedOutcome,
+  pub second: VerifiedOutcome,
+  pub differing_files: Vec<OutputDifference>,
+}
+
+///
+/// A CommandRunner which runs each selected request twice (the second time with the `second`
+/// runner, which may run it in a sandbox at a different path), compares their exit codes and output
+/// digests, and appends a JSON DeterminismMismatch line to a report file for each request whose
+/// results differed. A process which passes can be safely shared via a remote cache.
+///
+/// The first result is always the one returned. Side-effecting requests are never run twice, and
+/// so are not checked.
+///
+pub struct DeterminismCheckingCommandRunner {
+  first: Box<dyn CommandRunner>,
+  second: Arc<dyn CommandRunner>,
+  description_patterns: Vec<Pattern>,
+  store: Store,
+  report: Arc<Mutex<File>>,
+}
+
+impl DeterminismCheckingCommandRunner {
+  pub fn new(
+    first: Box<dyn CommandRunner>,
+    second: Box<dyn CommandRunner>,
+    store: Store,
+    config: &DeterminismCheckConfig,
+  ) -> Result<DeterminismCheckingCommandRunner, String> {
+    let description_patterns = config
+      .description_patterns
+      .iter()
+      .map(|pattern| {
+        Pattern::new(pattern)
+          .map_err(|e| format!("Invalid description pattern {:?}: {}", pattern, e))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let report = Self::open_report(&config.report_path)?;
+    Ok(DeterminismCheckingCommandRunner {
+      first,
+      second: second.into(),
+      description_patterns,
+      store,
+      report: Arc::new(Mutex::new(report)),
+    })
+  }
+
+  fn open_report(report_path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(report_path)
+      .map_err(|e| format!("Failed to open determinism report {:?}: {}", report_path, e))
+  }
+
+  fn is_selected(&self, req: &ExecuteProcessRequest) -> bool {
+    !req.side_effecting
+      && (self.description_patterns.is_empty()
+        || self
+          .description_patterns
+          .iter()
+          .any(|pattern| pattern.matches(&req.description)))
+  }
+
+  fn record(report: &Mutex<File>, mismatch: &DeterminismMismatch) {
+    let mut line = match serde_json::to_string(mismatch) {
+      Ok(line) => line,
+      Err(e) => {
+        warn!("Failed to serialize determinism mismatch: {}", e);
+        return;
+      }
+    };
+    line.push('\n');
+    // A single write per mismatch, so that concurrent mismatches are not interleaved.
+    let mut report = report.lock().unwrap();
+    if let Err(e) = report.write_all(line.as_bytes()) {
+      warn!("Failed to write determinism mismatch: {}", e);
+    }
+  }
+
+  fn differing_files(
+    store: &Store,
+    first_output: Option<Digest>,
+    second_output: Option<Digest>,
+  ) -> BoxFuture<Vec<OutputDifference>, String> {
+    compare_outputs(store, first_output, second_output)
+      .map(|differences| {
+        differences
+          .into_iter()
+          .map(|(path, first_content, second_content)| OutputDifference {
+            diff: match (&first_content, &second_content) {
+              (Some(first), Some(second)) => diff(first, second),
+              _ => None,
+            },
+            first_digest: first_content.as_ref().map(|content| digest_of(content)),
+            second_digest: second_content.as_ref().map(|content| digest_of(content)),
+            path,
+          })
+          .collect()
+      })
+      .to_boxed()
+  }
+}
+
+impl CommandRunner for DeterminismCheckingCommandRunner {
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    if !self.is_selected(&req) {
+      return self.first.run(req, context);
+    }
+    let description = req.description.clone();
+    let argv = req.argv.clone();
+    let input_digest = req.input_files;
+    let store = self.store.clone();
+    let report = self.report.clone();
+    let second = self.second.clone();
+    let second_req = req.clone();
+    let second_context = context.clone();
+    // The runs are sequential, so that they do not contend for the same resources.
+    self
+      .first
+      .run(req, context)
+      .then(move |first_result| {
+        second
+          .run(second_req, second_context)
+          .then(move |second_result| Ok::<_, String>((first_result, second_result)))
+      })
+      .and_then(move |(first_result, second_result)| {
+        let first = VerifiedOutcome::new(&first_result);
+        let second = VerifiedOutcome::new(&second_result);
+        if first.exit_code == second.exit_code
+          && first.output_digest == second.output_digest
+          && first.error.is_some() == second.error.is_some()
+        {
+          return future::result(first_result).to_boxed();
+        }
+        Self::differing_files(&store, first.output_digest, second.output_digest)
+          .then(move |differing_files| {
+            let differing_files = differing_files.unwrap_or_else(|e| {
+              warn!("Failed to compare outputs of {}: {}", description, e);
+              vec![]
+            });
+            Self::record(
+              &report,
+              &DeterminismMismatch {
+                description,
+                argv,
+                input_digest,
+                first,
+                second,
+                differing_files,
+              },
+            );
+            first_result
+          })
+          .to_boxed()
+      })
+      .to_boxed()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::read_to_string;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  use boxfuture::{BoxFuture, Boxable};
+  use fs::Store;
+  use futures::{future, Future};
+  use serde_json::{self, Value};
+  use tempfile::TempDir;
+  use testutil::data::{TestData, TestDirectory};
+  use testutil::{as_bytes, owned_string_vec};
+
+  use super::{DeterminismCheckConfig, DeterminismCheckingCommandRunner};
+  use crate::{
+    CommandRunner, Context, ExecuteProcessRequest, ExecutionStats, FallibleExecuteProcessResult,
+  };
+
+  ///
+  /// Outputs the given directories in turn, one per run.
+  ///
+  struct AlternatingCommandRunner {
+    outputs: Vec<TestDirectory>,
+    runs: AtomicUsize,
+  }
+
+  impl CommandRunner for AlternatingCommandRunner {
+    fn run(
+      &self,
+      _req: ExecuteProcessRequest,
+      _context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      let run = self.runs.fetch_add(1, Ordering::SeqCst);
+      future::ok(FallibleExecuteProcessResult {
+        stdout: as_bytes(""),
+        stderr: as_bytes(""),
+        exit_code: 0,
+        output_directory: self.outputs[run % self.outputs.len()].digest(),
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: format!("run {}", run),
+        timed_out: None,
+      })
+      .to_boxed()
+    }
+  }
+
+  struct SharedCommandRunner(Arc<AlternatingCommandRunner>);
+
+  impl CommandRunner for SharedCommandRunner {
+    fn run(
+      &self,
+      req: ExecuteProcessRequest,
+      context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      self.0.run(req, context)
+    }
+  }
+
+  fn request(description: &str) -> ExecuteProcessRequest {
+    ExecuteProcessRequest {
+      argv: owned_string_vec(&["cat", "roland"]),
+      input_files: TestDirectory::containing_roland().digest(),
+      timeout: Duration::from_millis(1000),
+      description: description.to_owned(),
+      ..ExecuteProcessRequest::default()
+    }
+  }
+
+  fn new_store(dir: &TempDir) -> Store {
+    let store = Store::local_only(dir.path().join("store")).unwrap();
+    for data in &[TestData::roland(), TestData::catnip()] {
+      store.store_file_bytes(data.bytes(), false).wait().unwrap();
+    }
+    for directory in &[
+      TestDirectory::containing_roland(),
+      TestDirectory::containing_wrong_roland(),
+    ] {
+      store
+        .record_directory(&directory.directory(), false)
+        .wait()
+        .unwrap();
+    }
+    store
+  }
+
+  fn runner(
+    dir: &TempDir,
+    description_patterns: Vec<String>,
+    outputs: Vec<TestDirectory>,
+  ) -> DeterminismCheckingCommandRunner {
+    // Both runs share one underlying runner, so that its outputs alternate between them.
+    let underlying = Arc::new(AlternatingCommandRunner {
+      outputs,
+      runs: AtomicUsize::new(0),
+    });
+    DeterminismCheckingCommandRunner::new(
+      Box::new(SharedCommandRunner(underlying.clone())),
+      Box::new(SharedCommandRunner(underlying)),
+      new_store(dir),
+      &DeterminismCheckConfig {
+        report_path: dir.path().join("determinism.jsonl"),
+        description_patterns,
+        vary_sandbox: false,
+      },
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn deterministic_results_are_not_reported() {
+    let dir = TempDir::new().unwrap();
+    let runner = runner(&dir, vec![], vec![TestDirectory::containing_roland()]);
+
+    let result = runner
+      .run(request("cat roland"), Context::default())
+      .wait()
+      .unwrap();
+    assert_eq!(result.runner, "run 0");
+    assert_eq!(
+      read_to_string(dir.path().join("determinism.jsonl")).unwrap(),
+      ""
+    );
+  }
+
+  #[test]
+  fn nondeterministic_results_are_reported() {
+    let dir = TempDir::new().unwrap();
+    let runner = runner(
+      &dir,
+      vec![],
+      vec![
+        TestDirectory::containing_roland(),
+        TestDirectory::containing_wrong_roland(),
+      ],
+    );
+
+    let result = runner
+      .run(request("cat roland"), Context::default())
+      .wait()
+      .unwrap();
+    assert_eq!(result.runner, "run 0");
+    let report = read_to_string(dir.path().join("determinism.jsonl")).unwrap();
+    let lines = report.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1);
+    let mismatch: Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(mismatch["description"], "cat roland");
+    let differing_files = mismatch["differing_files"].as_array().unwrap();
+    assert_eq!(differing_files.len(), 1);
+    assert_eq!(differing_files[0]["path"], "roland");
+    assert!(differing_files[0]["diff"].is_string());
+  }
+
+  #[test]
+  fn unselected_and_side_effecting_requests_run_once() {
+    let dir = TempDir::new().unwrap();
+    let runner = runner(
+      &dir,
+      vec!["javac *".to_owned()],
+      vec![
+        TestDirectory::containing_roland(),
+        TestDirectory::containing_wrong_roland(),
+      ],
+    );
+
+    runner
+      .run(request("cat roland"), Context::default())
+      .wait()
+      .unwrap();
+    let mut side_effecting = request("javac roland");
+    side_effecting.side_effecting = true;
+    runner
+      .run(side_effecting, Context::default())
+      .wait()
+      .unwrap();
+    // Neither request was run a second time.
+    let result = runner
+      .run(request("javac roland"), Context::default())
+      .wait()
+      .unwrap();
+    assert_eq!(result.runner, "run 2");
+    assert_eq!(
+      read_to_string(dir.path().join("determinism.jsonl")).unwrap(),
+      ""
+    );
+  }
+}
@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bytes::Bytes;
+use fs::Store;
+use futures::future::Future;
+use hashing::{Digest, Fingerprint};
+use hex;
+use protobuf::Message;
+use tempfile::TempDir;
+
+use crate::remote::{digest, make_execute_request};
+use crate::{ExecuteProcessRequest, ExecuteProcessRequestMetadata, FallibleExecuteProcessResult};
+
+///
+/// The command which regenerates the golden files.
+///
+pub const GENERATE_COMMAND: &str = "cargo run -p process_execution --bin generate_golden_protos";
+
+///
+/// A representative request, and the remote execution options which it is executed with.
+///
+struct RequestCase {
+  name: &'static str,
+  request: ExecuteProcessRequest,
+  metadata: ExecuteProcessRequestMetadata,
+}
+
+fn request(argv: &[&str]) -> ExecuteProcessRequest {
+  ExecuteProcessRequest {
+    argv: argv.iter().map(|arg| (*arg).to_owned()).collect(),
+    timeout: Duration::from_secs(60),
+    description: argv.join(" "),
+    ..ExecuteProcessRequest::default()
+  }
+}
+
+fn strings(strs: &[(&str, &str)]) -> BTreeMap<String, String> {
+  strs
+    .iter()
+    .map(|(key, value)| ((*key).to_owned(), (*value).to_owned()))
+    .collect()
+}
+
+fn paths(strs: &[&str]) -> BTreeSet<PathBuf> {
+  strs.iter().map(PathBuf::from).collect()
+}
+
+fn request_cases() -> Vec<RequestCase> {
+  let echo = request(&["/bin/echo", "-n", "foo"]);
+  vec![
+    RequestCase {
+      name: "minimal",
+      request: echo.clone(),
+      metadata: ExecuteProcessRequestMetadata::default(),
+    },
+    RequestCase {
+      name: "env_and_outputs",
+      request: ExecuteProcessRequest {
+        env: strings(&[("LANG", "C"), ("JAVA_HOME", "/usr/lib/jvm/default")]),
+        // A Directory containing a single file named `roland`.
+        input_files: Digest(
+          Fingerprint::from_hex_string(
+            "63949aa823baf765eff07b946050d76ec0033144c785a94d3ebd82baa931cd16",
+          )
+          .unwrap(),
+          80,
+        ),
+        output_files: paths(&["classes/Roland.class", "compile.log"]),
+        output_directories: paths(&["classes/META-INF"]),
+        ..request(&["/usr/bin/javac", "-d", "classes", "Roland.java"])
+      },
+      metadata: ExecuteProcessRequestMetadata::default(),
+    },
+    RequestCase {
+      name: "cache_key_gen_version",
+      request: echo.clone(),
+      metadata: ExecuteProcessRequestMetadata {
+        cache_key_gen_version: Some("2019-06-01".to_owned()),
+        ..ExecuteProcessRequestMetadata::default()
+      },
+    },
+    RequestCase {
+      name: "jdk_and_platform_properties",
+      request: ExecuteProcessRequest {
+        jdk_home: Some(PathBuf::from("/usr/lib/jvm/java-8")),
+        platform_properties: strings(&[("container-image", "docker://ubuntu")]),
+        ..request(&["/usr/bin/java", "-version"])
+      },
+      metadata: ExecuteProcessRequestMetadata {
+        platform_properties: strings(&[("OSFamily", "Linux")]),
+        ..ExecuteProcessRequestMetadata::default()
+      },
+    },
+    RequestCase {
+      name: "side_effecting",
+      request: ExecuteProcessRequest {
+        side_effecting: true,
+        ..echo
+      },
+      metadata: ExecuteProcessRequestMetadata::default(),
+    },
+  ]
+}
+
+///
+/// The directory which contains the golden files.
+///
+pub fn golden_dir() -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("golden")
+}
+
+///
+/// Renders the content of each golden file, keyed by its name: the digest and the serialized bytes
+/// of each proto which is constructed for a representative case. Since the digests of these protos
+/// are the cache keys of processes, any change to them invalidates every cached process result.
+///
+pub fn render_all() -> Result<BTreeMap<String, String>, String> {
+  let mut golden_files = BTreeMap::new();
+  for case in request_cases() {
+    let (action, command, _) = make_execute_request(&case.request, &case.metadata)?;
+    let mut content = header();
+    render_message("Command", &command, &mut content)?;
+    render_message("Action", &action, &mut content)?;
+    golden_files.insert(format!("{}.txt", case.name), content);
+  }
+  golden_files.insert(
+    "empty_output_directories.txt".to_owned(),
+    render_empty_output_directories()?,
+  );
+  Ok(golden_files)
+}
+
+///
+/// Renders the Directories which are constructed for the empty output directories of a result.
+///
+fn render_empty_output_directories() -> Result<String, String> {
+  let store_dir =
+    TempDir::new().map_err(|e| format!("Failed to create a temporary directory: {}", e))?;
+  let store = Store::local_only(store_dir.path())?;
+  let result = FallibleExecuteProcessResult {
+    stdout: Bytes::new(),
+    stderr: Bytes::new(),
+    exit_code: 0,
+    output_directory: fs::EMPTY_DIGEST,
+    execution_attempts: vec![],
+    runner: "local".to_owned(),
+    timed_out: None,
+  }
+  .with_empty_directories(store.clone(), &paths(&["dist/classes", "logs"]))
+  .wait()?;
+
+  let mut content = header();
+  let mut rendered = HashSet::new();
+  let mut to_render = vec![result.output_directory];
+  while let Some(directory_digest) = to_render.pop() {
+    if !rendered.insert(directory_digest) {
+      continue;
+    }
+    let directory = store
+      .load_directory(directory_digest)
+      .wait()?
+      .ok_or_else(|| format!("Directory {:?} was not stored.", directory_digest))?;
+    render_message("Directory", &directory, &mut content)?;
+    // Render children after their parent, in name order.
+    for child in directory.get_directories().iter().rev() {
+      let child_digest: Result<Digest, String> = child.get_digest().into();
+      to_render.push(child_digest?);
+    }
+  }
+  Ok(content)
+}
+
+fn header() -> String {
+  format!("# Generated by `{}`: do not edit.\n", GENERATE_COMMAND)
+}
+
+fn render_message(kind: &str, message: &dyn Message, content: &mut String) -> Result<(), String> {
+  let bytes = message
+    .write_to_bytes()
+    .map_err(|e| format!("Error serializing {}: {:?}", kind, e))?;
+  let digest = digest(message)?;
+  content.push_str(&format!("\n{} {}-{}\n", kind, digest.0, digest.1));
+  for line in bytes.chunks(32) {
+    content.push_str(&hex::encode(line));
+    content.push('\n');
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeSet;
+
+  use super::{golden_dir, render_all, GENERATE_COMMAND};
+
+  #[test]
+  fn protos_match_golden_files() {
+    let golden_files = render_all().unwrap();
+    for (name, content) in &golden_files {
+      let path = golden_dir().join(name);
+      let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Failed to read golden file {}: {}", path.display(), e));
+      assert!(
+        content == &expected,
+        "The protos for {} no longer match its golden file, so the cache keys of the processes \
+         which they describe have changed. If that is intended, run `{}` to regenerate the \
+         golden files.\nExpected:\n{}\nActual:\n{}",
+        name,
+        GENERATE_COMMAND,
+        expected,
+        content
+      );
+    }
+
+    let on_disk = std::fs::read_dir(golden_dir())
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+      .collect::<BTreeSet<_>>();
+    assert_eq!(
+      on_disk,
+      golden_files.keys().cloned().collect::<BTreeSet<_>>(),
+      "Stale golden files: run `{}` to regenerate them.",
+      GENERATE_COMMAND
+    );
+  }
+}
@@ -0,0 +1,191 @@
+use std::path::Path;
+
+use boxfuture::{BoxFuture, Boxable};
+use bytes::Bytes;
+use fs::Store;
+use futures::{future, Future};
+
+use super::FallibleExecuteProcessResult;
+
+///
+/// Scans the captured outputs of processes for absolute paths which differ between machines (or
+/// between runs): the sandbox in which a process ran, the buildroot, and the home directory of the
+/// user. A process whose outputs contain one of them is not hermetic, and its outputs will poison
+/// any cache which they are shared through.
+///
+/// NB: Only the sandboxes of local execution (which are created below the work_dir) are detected.
+///
+#[derive(Clone, Debug)]
+pub struct HermeticityLint {
+  // Pairs of a description and an absolute path, from most to least specific, since (for example)
+  // the sandbox is often below the buildroot, which is often below the home directory.
+  paths: Vec<(&'static str, Bytes)>,
+}
+
+impl HermeticityLint {
+  pub fn new(work_dir: &Path, build_root: &Path, home: Option<&Path>) -> HermeticityLint {
+    let mut paths = vec![("sandbox", work_dir), ("buildroot", build_root)];
+    if let Some(home) = home {
+      paths.push(("$HOME", home));
+    }
+    paths.sort_by_key(|(_, path)| std::cmp::Reverse(path.as_os_str().len()));
+    HermeticityLint {
+      paths: paths
+        .into_iter()
+        .map(|(name, path)| (name, Bytes::from(path.to_string_lossy().as_bytes())))
+        // A root (or empty) path would match nearly everything.
+        .filter(|(_, path)| path.len() > 1)
+        .collect(),
+    }
+  }
+
+  ///
+  /// Returns a warning for each of the outputs (stdout, stderr, and output files) of the given
+  /// successful result which contains one of the paths. Failed results are not linted, because
+  /// error messages commonly (and harmlessly) contain absolute paths.
+  ///
+  pub fn lint(
+    &self,
+    store: &Store,
+    result: &FallibleExecuteProcessResult,
+  ) -> BoxFuture<Vec<String>, String> {
+    if result.exit_code != 0 {
+      return future::ok(vec![]).to_boxed();
+    }
+    let lint = self.clone();
+    let mut warnings = vec![];
+    for (name, content) in &[("stdout", &result.stdout), ("stderr", &result.stderr)] {
+      warnings.extend(lint.check(name, content));
+    }
+    store
+      .contents_for_directory(result.output_directory)
+      .map(move |files| {
+        for file in files {
+          let name = format!("output file {}", file.path.display());
+          warnings.extend(lint.check(&name, &file.content));
+        }
+        warnings
+      })
+      .to_boxed()
+  }
+
+  ///
+  /// Returns a warning if the given content contains any of the paths, naming the most specific.
+  ///
+  fn check(&self, name: &str, content: &[u8]) -> Option<String> {
+    self
+      .paths
+      .iter()
+      .find(|(_, path)| contains(content, path))
+      .map(|(path_name, path)| {
+        format!(
+          "The {} contains the {} path {}.",
+          name,
+          path_name,
+          String::from_utf8_lossy(path)
+        )
+      })
+  }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+  haystack
+    .windows(needle.len())
+    .any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use bazel_protos;
+  use bytes::Bytes;
+  use fs::Store;
+  use futures::Future;
+  use tempfile::TempDir;
+  use testutil::as_bytes;
+
+  use super::HermeticityLint;
+  use crate::FallibleExecuteProcessResult;
+
+  fn lint() -> HermeticityLint {
+    HermeticityLint::new(
+      Path::new("/home/roland/repo/.pants.d/tmp"),
+      Path::new("/home/roland/repo"),
+      Some(Path::new("/home/roland")),
+    )
+  }
+
+  fn result(
+    exit_code: i32,
+    stdout: &str,
+    output_directory: hashing::Digest,
+  ) -> FallibleExecuteProcessResult {
+    FallibleExecuteProcessResult {
+      stdout: as_bytes(stdout),
+      stderr: as_bytes(""),
+      exit_code,
+      output_directory,
+      execution_attempts: vec![],
+      runner: "local".to_owned(),
+      timed_out: None,
+    }
+  }
+
+  #[test]
+  fn reports_the_most_specific_path() {
+    let dir = TempDir::new().unwrap();
+    let store = Store::local_only(dir.path()).unwrap();
+    let warnings = lint()
+      .lint(
+        &store,
+        &result(
+          0,
+          "Compiled /home/roland/repo/.pants.d/tmp/process-execution-1/Roland.java",
+          fs::EMPTY_DIGEST,
+        ),
+      )
+      .wait()
+      .unwrap();
+    assert_eq!(
+      warnings,
+      vec!["The stdout contains the sandbox path /home/roland/repo/.pants.d/tmp.".to_owned()]
+    );
+  }
+
+  #[test]
+  fn reports_output_files() {
+    let dir = TempDir::new().unwrap();
+    let store = Store::local_only(dir.path()).unwrap();
+    let file_digest = store
+      .store_file_bytes(Bytes::from("cache_dir=/home/roland/.cache\n"), false)
+      .wait()
+      .unwrap();
+    let mut file_node = bazel_protos::remote_execution::FileNode::new();
+    file_node.set_name("config".to_owned());
+    file_node.set_digest((&file_digest).into());
+    let mut directory = bazel_protos::remote_execution::Directory::new();
+    directory.mut_files().push(file_node);
+    let directory_digest = store.record_directory(&directory, false).wait().unwrap();
+
+    let warnings = lint()
+      .lint(&store, &result(0, "", directory_digest))
+      .wait()
+      .unwrap();
+    assert_eq!(
+      warnings,
+      vec!["The output file config contains the $HOME path /home/roland.".to_owned()]
+    );
+  }
+
+  #[test]
+  fn ignores_hermetic_and_failed_results() {
+    let dir = TempDir::new().unwrap();
+    let store = Store::local_only(dir.path()).unwrap();
+    let lint = lint();
+    let hermetic = result(0, "Compiled Roland.java", fs::EMPTY_DIGEST);
+    assert!(lint.lint(&store, &hermetic).wait().unwrap().is_empty());
+    let failed = result(1, "No such file: /home/roland/repo/src", fs::EMPTY_DIGEST);
+    assert!(lint.lint(&store, &failed).wait().unwrap().is_empty());
+  }
+}
@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use digest::{Digest as DigestTrait, FixedOutput};
+use hashing::Fingerprint;
+use log::debug;
+use serde_derive::Serialize;
+use sha2::Sha256;
+
+///
+/// The environment variable which holds the fingerprint of the JDK that a request selected by
+/// version spec, so that the cache key of the request changes whenever the selected JDK does.
+///
+pub const JDK_FINGERPRINT_ENV_VAR: &str = "PANTS_JDK_FINGERPRINT";
+
+///
+/// A request for a JDK of a (possibly partial) version, and optionally from a particular vendor:
+/// for example `11`, `1.8` or `zulu-17`.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JdkSpec {
+  pub vendor: Option<String>,
+  pub version: Vec<u64>,
+}
+
+impl JdkSpec {
+  pub fn new(spec: &str) -> Result<JdkSpec, String> {
+    let (vendor, version) = match spec.rfind('-') {
+      Some(i) => (Some(spec[..i].to_lowercase()), &spec[i + 1..]),
+      None => (None, spec),
+    };
+    let components = version_components(version);
+    if components.is_empty() || vendor.as_ref().map_or(false, String::is_empty) {
+      return Err(format!(
+        "Invalid JDK version spec `{}`: expected a version (for example `11`), optionally \
+         prefixed by a vendor (for example `zulu-17`).",
+        spec
+      ));
+    }
+    Ok(JdkSpec {
+      vendor,
+      version: components,
+    })
+  }
+
+  fn matches(&self, jdk: &Jdk) -> bool {
+    let version = version_components(&jdk.version);
+    if !version.starts_with(&self.version) {
+      return false;
+    }
+    match self.vendor {
+      Some(ref vendor) => jdk.vendor.to_lowercase().contains(vendor.as_str()),
+      None => true,
+    }
+  }
+}
+
+///
+/// A JDK which was discovered on this machine.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Jdk {
+  pub home: PathBuf,
+  // The JAVA_VERSION from the `release` file of the JDK.
+  pub version: String,
+  // The IMPLEMENTOR (and IMPLEMENTOR_VERSION) from the `release` file of the JDK, and the name of
+  // its home directory, which is where most distributions record their vendor.
+  pub vendor: String,
+  // A hash of the real path of the home of the JDK and of its `release` file, which changes if a
+  // different JDK is installed in the same place.
+  pub fingerprint: String,
+}
+
+impl Jdk {
+  ///
+  /// Returns the JDK at the given home, if it contains a `bin/java` and a `release` file which
+  /// declares its version.
+  ///
+  fn load(home: &Path) -> Option<Jdk> {
+    if !home.join("bin").join("java").is_file() {
+      return None;
+    }
+    let real_home = fs::canonicalize(home).ok()?;
+    let release = fs::read_to_string(real_home.join("release")).ok()?;
+    let properties = parse_release(&release);
+    let version = properties.get("JAVA_VERSION")?.clone();
+
+    let mut vendor = vec![];
+    for key in &["IMPLEMENTOR", "IMPLEMENTOR_VERSION"] {
+      if let Some(value) = properties.get(*key) {
+        vendor.push(value.clone());
+      }
+    }
+    // The home of a macOS JDK is nested below the directory which names it.
+    let name = home.ancestors().filter_map(Path::file_name).find(|name| {
+      name
+        .to_str()
+        .map_or(true, |n| n != "Home" && n != "Contents")
+    });
+    if let Some(name) = name {
+      vendor.push(name.to_string_lossy().into_owned());
+    }
+
+    let mut hasher = Sha256::default();
+    hasher.input(real_home.to_string_lossy().as_bytes());
+    hasher.input(&[0]);
+    hasher.input(release.as_bytes());
+    Some(Jdk {
+      home: home.to_path_buf(),
+      version,
+      vendor: vendor.join(" "),
+      fingerprint: Fingerprint::from_bytes_unsafe(&hasher.fixed_result()).to_hex(),
+    })
+  }
+}
+
+///
+/// The JDKs which were discovered in a set of search paths, from which a JDK is selected for each
+/// request which specifies a JDK version spec rather than a `jdk_home`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct JdkRegistry {
+  jdks: Vec<Jdk>,
+}
+
+impl JdkRegistry {
+  ///
+  /// Discovers JDKs in the given search paths, each of which may either be the home of a JDK, or a
+  /// directory containing JDK homes (such as `/usr/lib/jvm`, or the macOS
+  /// `/Library/Java/JavaVirtualMachines`, whose JDKs have their homes at `Contents/Home`). Paths
+  /// which do not exist are ignored.
+  ///
+  pub fn discover(search_paths: &[PathBuf]) -> JdkRegistry {
+    let mut jdks = vec![];
+    for search_path in search_paths {
+      if let Some(jdk) = Jdk::load(search_path) {
+        jdks.push(jdk);
+        continue;
+      }
+      let entries = match fs::read_dir(search_path) {
+        Ok(entries) => entries,
+        Err(e) => {
+          debug!("Skipping JDK search path {:?}: {}", search_path, e);
+          continue;
+        }
+      };
+      let mut candidates = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+      candidates.sort();
+      for candidate in candidates {
+        let mac_home = candidate.join("Contents").join("Home");
+        if let Some(jdk) = Jdk::load(&candidate).or_else(|| Jdk::load(&mac_home)) {
+          jdks.push(jdk);
+        }
+      }
+    }
+    debug!("Discovered JDKs: {:?}", jdks);
+    JdkRegistry { jdks }
+  }
+
+  pub fn jdks(&self) -> &[Jdk] {
+    &self.jdks
+  }
+
+  ///
+  /// Selects the newest JDK which matches the given version spec. Between JDKs of the same version,
+  /// the one which was discovered first (in the earliest search path) is selected.
+  ///
+  pub fn select(&self, spec: &str) -> Result<Jdk, String> {
+    let parsed = JdkSpec::new(spec)?;
+    let mut selected: Option<&Jdk> = None;
+    for jdk in self.jdks.iter().filter(|jdk| parsed.matches(jdk)) {
+      let newer = selected.map_or(true, |current| {
+        version_components(&jdk.version) > version_components(&current.version)
+      });
+      if newer {
+        selected = Some(jdk);
+      }
+    }
+    selected.cloned().ok_or_else(|| {
+      format!(
+        "No JDK matching `{}` was found. Discovered JDKs: [{}]",
+        spec,
+        self
+          .jdks
+          .iter()
+          .map(|jdk| format!("{} ({})", jdk.version, jdk.home.display()))
+          .collect::<Vec<_>>()
+          .join(", ")
+      )
+    })
+  }
+}
+
+///
+/// Parses the `KEY="value"` lines of the `release` file of a JDK.
+///
+fn parse_release(content: &str) -> BTreeMap<String, String> {
+  content
+    .lines()
+    .filter_map(|line| {
+      let mut parts = line.splitn(2, '=');
+      let key = parts.next()?.trim();
+      let value = parts.next()?.trim().trim_matches('"');
+      Some((key.to_owned(), value.to_owned()))
+    })
+    .collect()
+}
+
+///
+/// The numeric components of a Java version, with the legacy `1.` prefix of versions before 9
+/// removed, so that `8`, `1.8` and `1.8.0_202` all begin with `[8]`.
+///
+fn version_components(version: &str) -> Vec<u64> {
+  let mut components = vec![];
+  for part in version.split(|c: char| c == '.' || c == '_' || c == '+' || c == '-') {
+    match part.parse::<u64>() {
+      Ok(component) => components.push(component),
+      Err(_) => break,
+    }
+  }
+  if components.len() > 1 && components[0] == 1 {
+    components.remove(0);
+  }
+  components
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+  use std::path::Path;
+
+  use tempfile::TempDir;
+
+  use super::{JdkRegistry, JdkSpec};
+
+  fn make_jdk(home: &Path, release: &str) {
+    fs::create_dir_all(home.join("bin")).unwrap();
+    fs::write(home.join("bin").join("java"), "").unwrap();
+    fs::write(home.join("release"), release).unwrap();
+  }
+
+  fn registry(dir: &TempDir) -> JdkRegistry {
+    make_jdk(
+      &dir.path().join("java-8-openjdk"),
+      "JAVA_VERSION=\"1.8.0_202\"\nIMPLEMENTOR=\"Oracle Corporation\"\n",
+    );
+    make_jdk(
+      &dir.path().join("zulu-11"),
+      "JAVA_VERSION=\"11.0.2\"\nIMPLEMENTOR=\"Azul Systems, Inc.\"\n",
+    );
+    make_jdk(
+      &dir.path().join("zulu-17.jdk").join("Contents").join("Home"),
+      "JAVA_VERSION=\"17.0.1\"\nIMPLEMENTOR=\"Azul Systems, Inc.\"\n\
+       IMPLEMENTOR_VERSION=\"Zulu17.30+15-CA\"\n",
+    );
+    make_jdk(
+      &dir.path().join("openjdk-11"),
+      "JAVA_VERSION=\"11.0.10\"\nIMPLEMENTOR=\"AdoptOpenJDK\"\n",
+    );
+    // Not a JDK, because it has no release file.
+    fs::create_dir_all(dir.path().join("jre").join("bin")).unwrap();
+    JdkRegistry::discover(&[dir.path().to_path_buf(), dir.path().join("missing")])
+  }
+
+  #[test]
+  fn discovers_jdks() {
+    let dir = TempDir::new().unwrap();
+    let versions = registry(&dir)
+      .jdks()
+      .iter()
+      .map(|jdk| jdk.version.clone())
+      .collect::<Vec<_>>();
+    assert_eq!(versions, vec!["1.8.0_202", "11.0.10", "11.0.2", "17.0.1"]);
+  }
+
+  #[test]
+  fn selects_newest_matching_version() {
+    let dir = TempDir::new().unwrap();
+    let registry = registry(&dir);
+    assert_eq!(registry.select("11").unwrap().version, "11.0.10");
+    assert_eq!(registry.select("8").unwrap().version, "1.8.0_202");
+    assert_eq!(registry.select("1.8").unwrap().version, "1.8.0_202");
+    assert_eq!(registry.select("11.0.2").unwrap().version, "11.0.2");
+  }
+
+  #[test]
+  fn selects_by_vendor() {
+    let dir = TempDir::new().unwrap();
+    let registry = registry(&dir);
+    assert_eq!(
+      registry.select("zulu-11").unwrap().home,
+      dir.path().join("zulu-11")
+    );
+    assert_eq!(
+      registry.select("zulu-17").unwrap().home,
+      dir.path().join("zulu-17.jdk").join("Contents").join("Home")
+    );
+    assert!(registry.select("openjdk-17").is_err());
+  }
+
+  #[test]
+  fn fingerprint_changes_with_release() {
+    let dir = TempDir::new().unwrap();
+    let before = registry(&dir).select("17").unwrap().fingerprint;
+    fs::write(
+      dir
+        .path()
+        .join("zulu-17.jdk")
+        .join("Contents")
+        .join("Home")
+        .join("release"),
+      "JAVA_VERSION=\"17.0.2\"\n",
+    )
+    .unwrap();
+    let after = JdkRegistry::discover(&[dir.path().to_path_buf()])
+      .select("17")
+      .unwrap()
+      .fingerprint;
+    assert_ne!(before, after);
+  }
+
+  #[test]
+  fn parses_specs() {
+    assert_eq!(
+      JdkSpec::new("zulu-17"),
+      Ok(JdkSpec {
+        vendor: Some("zulu".to_owned()),
+        version: vec![17],
+      })
+    );
+    assert_eq!(
+      JdkSpec::new("1.8"),
+      Ok(JdkSpec {
+        vendor: None,
+        version: vec![8],
+      })
+    );
+    assert!(JdkSpec::new("zulu").is_err());
+    assert!(JdkSpec::new("-11").is_err());
+  }
+}
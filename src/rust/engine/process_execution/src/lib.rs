@@ -26,9 +26,11 @@
 // Arc<Mutex> can be more clear than needing to grok Orderings:
 #![allow(clippy::mutex_atomic)]
 
-use boxfuture::BoxFuture;
-use bytes::Bytes;
+use boxfuture::{try_future, BoxFuture, Boxable};
+use bytes::{Bytes, BytesMut};
+use futures::{future, Future};
 use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::ops::AddAssign;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -36,13 +38,64 @@ use std::time::Duration;
 
 use async_semaphore::AsyncSemaphore;
 
+pub mod artifacts;
+pub mod audit;
+pub mod background;
+pub mod determinism;
+pub mod golden;
+pub mod hermeticity;
+pub mod jdk;
+pub mod limits;
 pub mod local;
+pub mod provenance;
 pub mod remote;
+pub mod service;
+pub mod strategy;
+pub mod unused_inputs;
+pub mod verify;
+
+// The value which is logged in place of the value of a secret environment variable.
+const REDACTED_SECRET: &str = "<secret>";
+
+///
+/// The environment variable which holds the idempotency token of a side-effecting process.
+///
+pub const IDEMPOTENCY_TOKEN_ENV_VAR: &str = "PANTS_IDEMPOTENCY_TOKEN";
+
+///
+/// The number of times a request which fails due to an infrastructure error (rather than completing
+/// with a failed result) is re-attempted before failing.
+///
+pub const TRANSIENT_RETRIES: usize = 2;
+
+// The versions of the cache keys of the subsystems which affect the results of processes. Bump one
+// when the behavior of its subsystem changes in a way which the inputs of the affected processes
+// do not capture, to invalidate exactly the cached results which it affected. A version of 0 is
+// not mixed into cache keys at all, so that introducing a version does not invalidate anything.
+//
+// How processes are executed: affects every process.
+pub const PROCESS_EXECUTION_CACHE_KEY_VERSION: u32 = 0;
+// How the outputs of processes are captured as Snapshots: affects processes with outputs.
+pub const SNAPSHOT_FORMAT_CACHE_KEY_VERSION: u32 = 0;
+// How JVM processes (which may be run in nailguns) are launched: affects processes with a JDK.
+pub const NAILGUN_CACHE_KEY_VERSION: u32 = 0;
+
+///
+/// Creates the salt of the Session with the given id, which is mixed into the cache keys of its
+/// requests with secret environment variables, so that those keys are neither reusable between
+/// Sessions nor comparable with keys computed for guessed values of the secrets.
+///
+/// The id makes the salt unique among the Sessions of this process, and the random value makes it
+/// unique among (and unpredictable to) other processes.
+///
+pub fn secret_env_salt(session_id: u32) -> String {
+  format!("{}-{}", session_id, uuid::Uuid::new_v4())
+}
 
 ///
 /// A process to be executed.
 ///
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Eq, Hash, PartialEq)]
 pub struct ExecuteProcessRequest {
   ///
   /// The arguments to execute.
@@ -80,6 +133,400 @@ pub struct ExecuteProcessRequest {
   /// see https://github.com/pantsbuild/pants/issues/6416.
   ///
   pub jdk_home: Option<PathBuf>,
+
+  ///
+  /// If present, a limit on the size of the stdout and stderr captured for the process. Otherwise,
+  /// any default limit configured for the CommandRunner applies.
+  ///
+  pub output_limit: Option<OutputLimit>,
+
+  ///
+  /// Scheduling hints (for example, a preferred or excluded pool of workers) which are sent as
+  /// platform properties when the process is executed remotely. Only keys which the remote
+  /// CommandRunner has been configured to allow may be set. Local execution ignores them.
+  ///
+  pub platform_properties: BTreeMap<String, String>,
+
+  ///
+  /// If present, the digest of a file whose content is written to the stdin of the process, for
+  /// tools (formatters in filter mode, for example) which read their input from it. Otherwise the
+  /// process has no stdin. Not supported by remote execution, which has no notion of stdin.
+  ///
+  pub stdin_digest: Option<hashing::Digest>,
+
+  ///
+  /// The names of the entries of `env` whose values are secret (credentials, for example). Their
+  /// values are redacted from logs, and a request with any secret env is never cached remotely.
+  ///
+  pub secret_env: BTreeSet<String>,
+
+  ///
+  /// If true, any of the output_directories which the process did not create are included in the
+  /// output_directory of its result as empty directories, for tools which require them to exist
+  /// when the result is materialized. Otherwise, they are omitted.
+  ///
+  pub preserve_empty_output_directories: bool,
+
+  ///
+  /// If true, the process has effects beyond its outputs (publishing an artifact, or deploying a
+  /// service, for example), so it must run exactly when it is requested: its result is never
+  /// served from or written to a cache, and it is never run more than once (to verify it, for
+  /// example). It is run with an IDEMPOTENCY_TOKEN_ENV_VAR, which the process may pass to the
+  /// services it affects so that they can recognise a retry of the same execution.
+  ///
+  pub side_effecting: bool,
+
+  ///
+  /// If present, an estimate of the memory (in MB) which the process uses, which counts against any
+  /// memory budget of the BoundedCommandRunner that it runs under. A process without an estimate
+  /// is bounded only by the count of concurrent processes.
+  ///
+  pub memory_estimate_mb: Option<usize>,
+}
+
+impl ExecuteProcessRequest {
+  ///
+  /// The output_directories which should be included in the result of the request even if the
+  /// process does not create them.
+  ///
+  pub fn empty_output_directories(&self) -> BTreeSet<PathBuf> {
+    if self.preserve_empty_output_directories {
+      self.output_directories.clone()
+    } else {
+      BTreeSet::new()
+    }
+  }
+
+  ///
+  /// The env of the request, with the values of secret entries redacted, for use in logs.
+  ///
+  pub fn redacted_env(&self) -> BTreeMap<String, String> {
+    self
+      .env
+      .iter()
+      .map(|(name, value)| {
+        if self.secret_env.contains(name) {
+          (name.clone(), REDACTED_SECRET.to_owned())
+        } else {
+          (name.clone(), value.clone())
+        }
+      })
+      .collect()
+  }
+
+  ///
+  /// The number of times the request may be re-attempted if it fails due to an infrastructure
+  /// error. A side-effecting request is never re-attempted: its failed attempt may already have
+  /// had its effects, and a new attempt would be run with a new IDEMPOTENCY_TOKEN_ENV_VAR.
+  ///
+  pub fn transient_retries(&self) -> usize {
+    if self.side_effecting {
+      0
+    } else {
+      TRANSIENT_RETRIES
+    }
+  }
+}
+
+///
+/// A request with no argv, inputs or outputs, and the same default timeout as requests constructed
+/// by rules, for callers (and tests) which set only the fields they care about with struct update
+/// syntax.
+///
+impl Default for ExecuteProcessRequest {
+  fn default() -> ExecuteProcessRequest {
+    ExecuteProcessRequest {
+      argv: vec![],
+      env: BTreeMap::new(),
+      input_files: fs::EMPTY_DIGEST,
+      output_files: BTreeSet::new(),
+      output_directories: BTreeSet::new(),
+      timeout: Duration::from_secs(15 * 60),
+      description: String::new(),
+      jdk_home: None,
+      output_limit: None,
+      platform_properties: BTreeMap::new(),
+      stdin_digest: None,
+      secret_env: BTreeSet::new(),
+      preserve_empty_output_directories: false,
+      side_effecting: false,
+      memory_estimate_mb: None,
+    }
+  }
+}
+
+impl fmt::Debug for ExecuteProcessRequest {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("ExecuteProcessRequest")
+      .field("argv", &self.argv)
+      .field("env", &self.redacted_env())
+      .field("input_files", &self.input_files)
+      .field("output_files", &self.output_files)
+      .field("output_directories", &self.output_directories)
+      .field("timeout", &self.timeout)
+      .field("description", &self.description)
+      .field("jdk_home", &self.jdk_home)
+      .field("output_limit", &self.output_limit)
+      .field("platform_properties", &self.platform_properties)
+      .field("stdin_digest", &self.stdin_digest)
+      .field("secret_env", &self.secret_env)
+      .field(
+        "preserve_empty_output_directories",
+        &self.preserve_empty_output_directories,
+      )
+      .field("side_effecting", &self.side_effecting)
+      .field("memory_estimate_mb", &self.memory_estimate_mb)
+      .finish()
+  }
+}
+
+///
+/// The per-subsystem cache key versions which are mixed into the cache keys of processes.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CacheKeyVersions {
+  pub process_execution: u32,
+  pub snapshot_format: u32,
+  pub nailgun: u32,
+}
+
+impl CacheKeyVersions {
+  ///
+  /// The (non-zero) versions which apply to the given request, keyed by the name of their
+  /// subsystem.
+  ///
+  pub fn for_request(&self, req: &ExecuteProcessRequest) -> BTreeMap<&'static str, u32> {
+    let has_outputs = !req.output_files.is_empty() || !req.output_directories.is_empty();
+    vec![
+      ("PROCESS_EXECUTION", self.process_execution, true),
+      ("SNAPSHOT_FORMAT", self.snapshot_format, has_outputs),
+      ("NAILGUN", self.nailgun, req.jdk_home.is_some()),
+    ]
+    .into_iter()
+    .filter(|&(_, version, applies)| applies && version != 0)
+    .map(|(subsystem, version, _)| (subsystem, version))
+    .collect()
+  }
+}
+
+impl Default for CacheKeyVersions {
+  fn default() -> CacheKeyVersions {
+    CacheKeyVersions {
+      process_execution: PROCESS_EXECUTION_CACHE_KEY_VERSION,
+      snapshot_format: SNAPSHOT_FORMAT_CACHE_KEY_VERSION,
+      nailgun: NAILGUN_CACHE_KEY_VERSION,
+    }
+  }
+}
+
+///
+/// The configuration which, together with an ExecuteProcessRequest, determines the cache key (and
+/// the protos) of its remote execution.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ExecuteProcessRequestMetadata {
+  pub instance_name: Option<String>,
+  // An arbitrary string which is configured to invalidate every cached result at once.
+  pub cache_key_gen_version: Option<String>,
+  pub platform_properties: BTreeMap<String, String>,
+  pub cache_key_versions: CacheKeyVersions,
+  // The salt of the Session which the request is executed for, which is mixed into the cache key
+  // of a request with secret env (see `secret_env_salt`). If unset, such a request is salted with
+  // a random value instead, so that its cache key is never reusable.
+  pub secret_env_salt: Option<String>,
+}
+
+///
+/// A process to be executed in the foreground, with exclusive use of the console (the real stdin,
+/// stdout and stderr of the engine). Its outputs are not captured, and it has no timeout.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InteractiveProcessRequest {
+  pub argv: Vec<String>,
+  pub env: BTreeMap<String, String>,
+  pub input_files: hashing::Digest,
+  pub description: String,
+}
+
+///
+/// What to do with the stdout or stderr of a process which exceeds its OutputLimit.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum OutputOverflowPolicy {
+  // Discard the beginning of the output, keeping the last `max_bytes`.
+  TruncateHead,
+  // Discard the end of the output, keeping the first `max_bytes`.
+  TruncateTail,
+  // Fail the process execution.
+  Fail,
+  // Store the complete output in the Store, and replace it with a message containing its Digest.
+  Spill,
+}
+
+impl OutputOverflowPolicy {
+  pub fn new(name: &str) -> Result<OutputOverflowPolicy, String> {
+    match name {
+      "truncate_head" => Ok(OutputOverflowPolicy::TruncateHead),
+      "truncate_tail" => Ok(OutputOverflowPolicy::TruncateTail),
+      "fail" => Ok(OutputOverflowPolicy::Fail),
+      "spill" => Ok(OutputOverflowPolicy::Spill),
+      _ => Err(format!(
+        "Unknown output overflow policy `{}`: expected one of `truncate_head`, `truncate_tail`, \
+         `fail` or `spill`.",
+        name
+      )),
+    }
+  }
+}
+
+///
+/// A limit on the size of each of the stdout and stderr of a process.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct OutputLimit {
+  pub max_bytes: usize,
+  pub policy: OutputOverflowPolicy,
+}
+
+///
+/// Accumulates one output stream of a process, retaining no more of it than its OutputLimit
+/// requires.
+///
+pub struct OutputCapture {
+  name: &'static str,
+  limit: Option<OutputLimit>,
+  bytes: BytesMut,
+  total_len: usize,
+}
+
+impl OutputCapture {
+  pub fn new(name: &'static str, limit: Option<OutputLimit>) -> OutputCapture {
+    OutputCapture {
+      name,
+      limit,
+      bytes: BytesMut::new(),
+      total_len: 0,
+    }
+  }
+
+  ///
+  /// Adds a chunk of output, failing if the limit has been exceeded and its policy is to fail.
+  ///
+  pub fn extend(&mut self, chunk: &[u8]) -> Result<(), String> {
+    self.total_len += chunk.len();
+    let limit = match self.limit {
+      Some(limit) => limit,
+      None => {
+        self.bytes.extend_from_slice(chunk);
+        return Ok(());
+      }
+    };
+    match limit.policy {
+      OutputOverflowPolicy::TruncateHead => {
+        self.bytes.extend_from_slice(chunk);
+        // Trim in batches, to avoid copying on every chunk.
+        if self.bytes.len() > 2 * limit.max_bytes {
+          let excess = self.bytes.len() - limit.max_bytes;
+          self.bytes.advance(excess);
+        }
+      }
+      OutputOverflowPolicy::TruncateTail => {
+        let remaining = limit.max_bytes.saturating_sub(self.bytes.len());
+        self
+          .bytes
+          .extend_from_slice(&chunk[..remaining.min(chunk.len())]);
+      }
+      OutputOverflowPolicy::Fail => {
+        if self.total_len > limit.max_bytes {
+          return Err(self.exceeded_message(limit));
+        }
+        self.bytes.extend_from_slice(chunk);
+      }
+      OutputOverflowPolicy::Spill => self.bytes.extend_from_slice(chunk),
+    }
+    Ok(())
+  }
+
+  ///
+  /// Completes the capture, applying the limit (if it was exceeded) to the retained output.
+  ///
+  pub fn finish(mut self, store: &fs::Store) -> BoxFuture<Bytes, String> {
+    let limit = match self.limit {
+      Some(limit) if self.total_len > limit.max_bytes => limit,
+      _ => return future::ok(self.bytes.freeze()).to_boxed(),
+    };
+    let omitted = self.total_len - limit.max_bytes;
+    match limit.policy {
+      OutputOverflowPolicy::TruncateHead => {
+        let excess = self.bytes.len() - limit.max_bytes;
+        let tail = self.bytes.split_off(excess);
+        let mut bytes = BytesMut::from(
+          format!(
+            "[{} truncated: the first {} of {} bytes were omitted]\n",
+            self.name, omitted, self.total_len
+          )
+          .into_bytes(),
+        );
+        bytes.extend_from_slice(&tail);
+        future::ok(bytes.freeze()).to_boxed()
+      }
+      OutputOverflowPolicy::TruncateTail => {
+        let message = format!(
+          "\n[{} truncated: the last {} of {} bytes were omitted]\n",
+          self.name, omitted, self.total_len
+        );
+        self.bytes.extend_from_slice(message.as_bytes());
+        future::ok(self.bytes.freeze()).to_boxed()
+      }
+      OutputOverflowPolicy::Fail => future::err(self.exceeded_message(limit)).to_boxed(),
+      OutputOverflowPolicy::Spill => {
+        let name = self.name;
+        let total_len = self.total_len;
+        store
+          .store_file_bytes(self.bytes.freeze(), true)
+          .map(move |digest| {
+            Bytes::from(format!(
+              "[{} of {} bytes exceeded the limit of {} bytes, and was stored as {} {}]\n",
+              name, total_len, limit.max_bytes, digest.0, digest.1
+            ))
+          })
+          .to_boxed()
+      }
+    }
+  }
+
+  fn exceeded_message(&self, limit: OutputLimit) -> String {
+    format!(
+      "Process {} exceeded the limit of {} bytes.",
+      self.name, limit.max_bytes
+    )
+  }
+}
+
+///
+/// Applies the output limit of the given request to an already complete result.
+///
+pub fn limit_output(
+  store: &fs::Store,
+  output_limit: Option<OutputLimit>,
+  result: FallibleExecuteProcessResult,
+) -> BoxFuture<FallibleExecuteProcessResult, String> {
+  if output_limit.is_none() {
+    return future::ok(result).to_boxed();
+  }
+  let mut stdout = OutputCapture::new("stdout", output_limit);
+  let mut stderr = OutputCapture::new("stderr", output_limit);
+  try_future!(stdout.extend(&result.stdout));
+  try_future!(stderr.extend(&result.stderr));
+  stdout
+    .finish(store)
+    .join(stderr.finish(store))
+    .map(move |(stdout, stderr)| FallibleExecuteProcessResult {
+      stdout,
+      stderr,
+      ..result
+    })
+    .to_boxed()
 }
 
 ///
@@ -96,6 +543,104 @@ pub struct FallibleExecuteProcessResult {
   pub output_directory: hashing::Digest,
 
   pub execution_attempts: Vec<ExecutionStats>,
+
+  // A description of the runner which produced this result: "local", or "remote(<address>)".
+  pub runner: String,
+
+  // Set if the process exceeded its timeout, in which case its exit_code is TIMED_OUT_EXIT_CODE,
+  // and its stdout and stderr are whatever was captured before then.
+  pub timed_out: Option<ProcessTimedOut>,
+}
+
+///
+/// The exit_code of a process which exceeded its timeout.
+///
+pub const TIMED_OUT_EXIT_CODE: i32 = -1;
+
+///
+/// Describes a process which exceeded its timeout, and so was killed (when executed locally) or
+/// abandoned (when executed remotely).
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ProcessTimedOut {
+  pub timeout: Duration,
+  pub elapsed: Duration,
+}
+
+impl FallibleExecuteProcessResult {
+  ///
+  /// True if the final attempt to execute the process was served from a remote ActionCache, rather
+  /// than executed.
+  ///
+  pub fn was_cache_hit(&self) -> bool {
+    self
+      .execution_attempts
+      .last()
+      .map(|attempt| attempt.was_cache_hit)
+      .unwrap_or(false)
+  }
+
+  ///
+  /// The number of attempts which were made to execute the process, including the final one.
+  ///
+  pub fn attempt_count(&self) -> usize {
+    self.execution_attempts.len()
+  }
+
+  ///
+  /// Adds the given directories to the output_directory of this result as empty directories.
+  /// Because the directories are merged, those which the process created (whether empty or not)
+  /// are unaffected.
+  ///
+  pub fn with_empty_directories(
+    self,
+    store: fs::Store,
+    directories: &BTreeSet<PathBuf>,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    if directories.is_empty() {
+      return future::ok(self).to_boxed();
+    }
+    let empty_directory_digests = directories
+      .iter()
+      .map(|path| {
+        let mut digest = store
+          .record_directory(&bazel_protos::remote_execution::Directory::new(), true)
+          .to_boxed();
+        for component in path.components().rev() {
+          let name = try_future!(component
+            .as_os_str()
+            .to_str()
+            .map(str::to_owned)
+            .ok_or_else(|| format!("Output directory {:?} was not valid unicode.", path)));
+          let store = store.clone();
+          digest = digest
+            .and_then(move |digest| {
+              let mut directory = bazel_protos::remote_execution::Directory::new();
+              directory.mut_directories().push({
+                let mut node = bazel_protos::remote_execution::DirectoryNode::new();
+                node.set_name(name);
+                node.set_digest((&digest).into());
+                node
+              });
+              store.record_directory(&directory, true)
+            })
+            .to_boxed();
+        }
+        digest
+      })
+      .collect::<Vec<_>>();
+    future::join_all(empty_directory_digests)
+      .and_then(move |mut digests| {
+        digests.push(self.output_directory);
+        fs::Snapshot::merge_directories(store, digests).map(move |output_directory| {
+          FallibleExecuteProcessResult {
+            output_directory,
+            ..self
+          }
+        })
+      })
+      .to_boxed()
+  }
 }
 
 #[cfg(test)]
@@ -106,16 +651,25 @@ impl FallibleExecuteProcessResult {
   }
 }
 
+///
+/// Statistics about one attempt to execute a process. The remote_* timings are reported by the
+/// remote execution server (in the ExecutedActionMetadata of its ActionResult), and so are only
+/// present for remote executions whose server reports them.
+///
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub struct ExecutionStats {
-  uploaded_bytes: usize,
-  uploaded_file_count: usize,
-  upload: Duration,
-  remote_queue: Option<Duration>,
-  remote_input_fetch: Option<Duration>,
-  remote_execution: Option<Duration>,
-  remote_output_store: Option<Duration>,
-  was_cache_hit: bool,
+  pub uploaded_bytes: usize,
+  pub uploaded_file_count: usize,
+  pub upload: Duration,
+  // Time between the action being queued and a worker starting on it.
+  pub remote_queue: Option<Duration>,
+  // Time between a worker starting on the action and beginning to execute it, which includes
+  // fetching inputs.
+  pub remote_worker_setup: Option<Duration>,
+  pub remote_input_fetch: Option<Duration>,
+  pub remote_execution: Option<Duration>,
+  pub remote_output_store: Option<Duration>,
+  pub was_cache_hit: bool,
 }
 
 impl AddAssign<fs::UploadSummary> for ExecutionStats {
@@ -126,29 +680,436 @@ impl AddAssign<fs::UploadSummary> for ExecutionStats {
   }
 }
 
+///
+/// The context in which a process is executed on behalf of a Session, which is passed along with
+/// its request through each CommandRunner. Unlike the request, it does not affect the identity of
+/// the process, or where its result is cached.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+  // Where the process is executed, which may override the configured runners.
+  pub execution_strategy: strategy::ExecutionStrategy,
+  // The counts of the processes which have been executed for the Session, which are checked
+  // against its limits.
+  pub process_counts: Arc<limits::ProcessCounts>,
+  // The digests which have been uploaded to the remote for the Session, which are not checked for
+  // again within it.
+  pub uploads: fs::UploadScope,
+  // The salt of the Session (see `secret_env_salt`), if the process is executed for one.
+  pub secret_env_salt: Option<String>,
+}
+
+impl Context {
+  ///
+  /// The metadata which the given metadata of a runner has for requests executed in this Context.
+  ///
+  pub fn metadata(
+    &self,
+    metadata: &ExecuteProcessRequestMetadata,
+  ) -> ExecuteProcessRequestMetadata {
+    ExecuteProcessRequestMetadata {
+      secret_env_salt: self.secret_env_salt.clone(),
+      ..metadata.clone()
+    }
+  }
+}
+
 pub trait CommandRunner: Send + Sync {
-  fn run(&self, req: ExecuteProcessRequest) -> BoxFuture<FallibleExecuteProcessResult, String>;
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String>;
 }
 
 ///
-/// A CommandRunner wrapper that limits the number of concurrent requests.
+/// A CommandRunner wrapper that limits the number of concurrent requests, and optionally the total
+/// memory which they are estimated to use.
 ///
 #[derive(Clone)]
 pub struct BoundedCommandRunner {
   inner: Arc<(Box<dyn CommandRunner>, AsyncSemaphore)>,
+  // Permits for each MB of the memory budget (if any), of which each request holds as many as its
+  // memory_estimate_mb while it waits for and then holds a slot.
+  memory_budget: Option<AsyncSemaphore>,
 }
 
 impl BoundedCommandRunner {
   pub fn new(inner: Box<dyn CommandRunner>, bound: usize) -> BoundedCommandRunner {
     BoundedCommandRunner {
       inner: Arc::new((inner, AsyncSemaphore::new(bound))),
+      memory_budget: None,
     }
   }
+
+  ///
+  /// Additionally bounds the total memory_estimate_mb of the requests which run concurrently to the
+  /// given budget. A request whose estimate exceeds the whole budget runs alone.
+  ///
+  pub fn with_memory_budget_mb(self, budget_mb: usize) -> BoundedCommandRunner {
+    BoundedCommandRunner {
+      memory_budget: Some(AsyncSemaphore::new(budget_mb)),
+      ..self
+    }
+  }
+
+  ///
+  /// The number of requests which are currently waiting for a slot to run in.
+  ///
+  pub fn queue_depth(&self) -> usize {
+    self.inner.1.queue_depth()
+  }
+}
+
+///
+/// A CommandRunner wrapper that applies a default OutputLimit to requests which do not specify
+/// their own.
+///
+pub struct DefaultOutputLimitCommandRunner {
+  inner: Box<dyn CommandRunner>,
+  output_limit: OutputLimit,
+}
+
+impl DefaultOutputLimitCommandRunner {
+  pub fn new(
+    inner: Box<dyn CommandRunner>,
+    output_limit: OutputLimit,
+  ) -> DefaultOutputLimitCommandRunner {
+    DefaultOutputLimitCommandRunner {
+      inner,
+      output_limit,
+    }
+  }
+}
+
+impl CommandRunner for DefaultOutputLimitCommandRunner {
+  fn run(
+    &self,
+    mut req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    if req.output_limit.is_none() {
+      req.output_limit = Some(self.output_limit);
+    }
+    self.inner.run(req, context)
+  }
 }
 
 impl CommandRunner for BoundedCommandRunner {
-  fn run(&self, req: ExecuteProcessRequest) -> BoxFuture<FallibleExecuteProcessResult, String> {
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
     let inner = self.inner.clone();
-    self.inner.1.with_acquired(move || inner.0.run(req))
+    let memory_estimate_mb = req.memory_estimate_mb;
+    let run = move || {
+      let slots = inner.1.clone();
+      slots.with_acquired(move || inner.0.run(req, context))
+    };
+    match (&self.memory_budget, memory_estimate_mb) {
+      (Some(memory_budget), Some(memory_estimate_mb)) => {
+        memory_budget.with_acquired_weighted(memory_estimate_mb, run)
+      }
+      _ => run(),
+    }
+  }
+}
+
+///
+/// Environment variables which affect the output of many tools, and which are set to fixed values
+/// for every process (unless its request sets them) to reduce differences between machines.
+///
+pub const REPRODUCIBLE_ENV: &[(&str, &str)] = &[
+  // 1980-01-01, the earliest timestamp which can be represented in a zip file.
+  ("SOURCE_DATE_EPOCH", "315532800"),
+  ("TZ", "UTC"),
+  ("LANG", "C.UTF-8"),
+  ("PYTHONHASHSEED", "0"),
+];
+
+///
+/// A CommandRunner wrapper that adds the REPRODUCIBLE_ENV to requests which do not already set
+/// those variables.
+///
+pub struct ReproducibleEnvCommandRunner {
+  inner: Box<dyn CommandRunner>,
+}
+
+impl ReproducibleEnvCommandRunner {
+  pub fn new(inner: Box<dyn CommandRunner>) -> ReproducibleEnvCommandRunner {
+    ReproducibleEnvCommandRunner { inner }
+  }
+}
+
+impl CommandRunner for ReproducibleEnvCommandRunner {
+  fn run(
+    &self,
+    mut req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    add_reproducible_env(&mut req);
+    self.inner.run(req, context)
+  }
+}
+
+///
+/// A CommandRunner wrapper that attaches a unique IDEMPOTENCY_TOKEN_ENV_VAR to each side-effecting
+/// request which does not already set one. Wrapped CommandRunners which retry the request (or
+/// which execute it remotely) pass the same token to each attempt.
+///
+pub struct IdempotencyTokenCommandRunner {
+  inner: Box<dyn CommandRunner>,
+}
+
+impl IdempotencyTokenCommandRunner {
+  pub fn new(inner: Box<dyn CommandRunner>) -> IdempotencyTokenCommandRunner {
+    IdempotencyTokenCommandRunner { inner }
+  }
+}
+
+impl CommandRunner for IdempotencyTokenCommandRunner {
+  fn run(
+    &self,
+    mut req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    if req.side_effecting {
+      req
+        .env
+        .entry(IDEMPOTENCY_TOKEN_ENV_VAR.to_owned())
+        .or_insert_with(|| uuid::Uuid::new_v4().to_string());
+    }
+    self.inner.run(req, context)
+  }
+}
+
+///
+/// Adds the REPRODUCIBLE_ENV variables which the given request does not already set.
+///
+pub fn add_reproducible_env(req: &mut ExecuteProcessRequest) {
+  for (name, value) in REPRODUCIBLE_ENV {
+    req
+      .env
+      .entry((*name).to_owned())
+      .or_insert_with(|| (*value).to_owned());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+  use std::sync::{Arc, Mutex};
+  use std::time::Duration;
+
+  use boxfuture::{BoxFuture, Boxable};
+  use futures::{future, Future};
+  use futures_timer::Delay;
+  use testutil::as_bytes;
+
+  use super::{
+    BoundedCommandRunner, CommandRunner, Context, ExecuteProcessRequest,
+    FallibleExecuteProcessResult, IdempotencyTokenCommandRunner, ReproducibleEnvCommandRunner,
+    IDEMPOTENCY_TOKEN_ENV_VAR, TRANSIENT_RETRIES,
+  };
+
+  ///
+  /// Outputs the environment of the request as `name=value` lines.
+  ///
+  struct EnvCommandRunner;
+
+  impl CommandRunner for EnvCommandRunner {
+    fn run(
+      &self,
+      req: ExecuteProcessRequest,
+      _context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      let env = req
+        .env
+        .iter()
+        .map(|(name, value)| format!("{}={}\n", name, value))
+        .collect::<String>();
+      future::ok(FallibleExecuteProcessResult {
+        stdout: as_bytes(&env),
+        stderr: as_bytes(""),
+        exit_code: 0,
+        output_directory: fs::EMPTY_DIGEST,
+        execution_attempts: vec![],
+        runner: "env".to_owned(),
+        timed_out: None,
+      })
+      .to_boxed()
+    }
+  }
+
+  ///
+  /// Runs each request for a short delay, and records the maximum number which run concurrently.
+  ///
+  #[derive(Clone, Default)]
+  struct ConcurrencyCommandRunner {
+    // The number of running requests, and the maximum that it has reached.
+    running: Arc<Mutex<(usize, usize)>>,
+  }
+
+  impl CommandRunner for ConcurrencyCommandRunner {
+    fn run(
+      &self,
+      _req: ExecuteProcessRequest,
+      _context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      {
+        let mut running = self.running.lock().unwrap();
+        running.0 += 1;
+        running.1 = running.1.max(running.0);
+      }
+      let running = self.running.clone();
+      Delay::new(Duration::from_millis(50))
+        .map_err(|e| format!("Timer failed: {}", e))
+        .map(move |()| {
+          running.lock().unwrap().0 -= 1;
+          FallibleExecuteProcessResult {
+            stdout: as_bytes(""),
+            stderr: as_bytes(""),
+            exit_code: 0,
+            output_directory: fs::EMPTY_DIGEST,
+            execution_attempts: vec![],
+            runner: "concurrency".to_owned(),
+            timed_out: None,
+          }
+        })
+        .to_boxed()
+    }
+  }
+
+  ///
+  /// The maximum number of three requests with the given estimate which run concurrently under a
+  /// bound of three slots and a memory budget of 100MB.
+  ///
+  fn max_concurrency(memory_estimate_mb: Option<usize>) -> usize {
+    let runner = ConcurrencyCommandRunner::default();
+    let bounded = BoundedCommandRunner::new(Box::new(runner.clone()), 3).with_memory_budget_mb(100);
+    let requests = (0..3)
+      .map(|_| {
+        bounded.run(
+          ExecuteProcessRequest {
+            memory_estimate_mb,
+            ..ExecuteProcessRequest::default()
+          },
+          Context::default(),
+        )
+      })
+      .collect::<Vec<_>>();
+    future::join_all(requests).wait().unwrap();
+    let running = runner.running.lock().unwrap();
+    running.1
+  }
+
+  #[test]
+  fn memory_budget_bounds_requests_with_estimates() {
+    assert_eq!(max_concurrency(None), 3);
+    assert_eq!(max_concurrency(Some(40)), 2);
+    // An estimate which exceeds the whole budget runs alone, rather than never running.
+    assert_eq!(max_concurrency(Some(1000)), 1);
+  }
+
+  #[test]
+  fn reproducible_env_is_added_unless_overridden() {
+    let mut env = BTreeMap::new();
+    env.insert("TZ".to_owned(), "America/Los_Angeles".to_owned());
+    env.insert("USER".to_owned(), "roland".to_owned());
+    let req = ExecuteProcessRequest {
+      argv: vec!["env".to_owned()],
+      env,
+      timeout: Duration::from_millis(1000),
+      description: "env".to_owned(),
+      ..ExecuteProcessRequest::default()
+    };
+
+    let result = ReproducibleEnvCommandRunner::new(Box::new(EnvCommandRunner))
+      .run(req, Context::default())
+      .wait()
+      .unwrap();
+
+    assert_eq!(
+      result.stdout,
+      as_bytes(
+        "LANG=C.UTF-8\nPYTHONHASHSEED=0\nSOURCE_DATE_EPOCH=315532800\nTZ=America/Los_Angeles\nUSER=roland\n"
+      )
+    );
+  }
+
+  #[test]
+  fn debug_redacts_secret_env() {
+    let mut env = BTreeMap::new();
+    env.insert("TOKEN".to_owned(), "hunter2".to_owned());
+    env.insert("USER".to_owned(), "roland".to_owned());
+    let req = ExecuteProcessRequest {
+      argv: vec!["env".to_owned()],
+      env,
+      timeout: Duration::from_millis(1000),
+      description: "env".to_owned(),
+      secret_env: vec!["TOKEN".to_owned()].into_iter().collect(),
+      ..ExecuteProcessRequest::default()
+    };
+
+    let debug = format!("{:?}", req);
+    assert!(!debug.contains("hunter2"), "Secret was logged: {}", debug);
+    assert!(debug.contains("\"TOKEN\": \"<secret>\""));
+    assert!(debug.contains("\"USER\": \"roland\""));
+  }
+
+  fn env_request(side_effecting: bool) -> ExecuteProcessRequest {
+    ExecuteProcessRequest {
+      argv: vec!["env".to_owned()],
+      timeout: Duration::from_millis(1000),
+      description: "env".to_owned(),
+      side_effecting,
+      ..ExecuteProcessRequest::default()
+    }
+  }
+
+  #[test]
+  fn idempotency_token_is_unique_per_side_effecting_request() {
+    let runner = IdempotencyTokenCommandRunner::new(Box::new(EnvCommandRunner));
+    let run = |req| {
+      String::from_utf8(
+        runner
+          .run(req, Context::default())
+          .wait()
+          .unwrap()
+          .stdout
+          .to_vec(),
+      )
+      .unwrap()
+    };
+
+    let first = run(env_request(true));
+    let second = run(env_request(true));
+    assert!(first.starts_with(&format!("{}=", IDEMPOTENCY_TOKEN_ENV_VAR)));
+    assert_ne!(first, second);
+
+    assert_eq!(run(env_request(false)), "");
+  }
+
+  #[test]
+  fn side_effecting_requests_are_not_retried() {
+    assert_eq!(env_request(false).transient_retries(), TRANSIENT_RETRIES);
+    assert_eq!(env_request(true).transient_retries(), 0);
+  }
+
+  #[test]
+  fn idempotency_token_is_preserved_if_set() {
+    let mut req = env_request(true);
+    req
+      .env
+      .insert(IDEMPOTENCY_TOKEN_ENV_VAR.to_owned(), "retry".to_owned());
+    let result = IdempotencyTokenCommandRunner::new(Box::new(EnvCommandRunner))
+      .run(req, Context::default())
+      .wait()
+      .unwrap();
+    assert_eq!(
+      result.stdout,
+      as_bytes(&format!("{}=retry\n", IDEMPOTENCY_TOKEN_ENV_VAR))
+    );
   }
 }
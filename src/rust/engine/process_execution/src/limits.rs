@@ -0,0 +1,284 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use boxfuture::{BoxFuture, Boxable};
+use futures::future;
+
+use super::{CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult};
+
+// The window over which the per-minute limit is applied.
+const MINUTE: Duration = Duration::from_secs(60);
+
+// The number of distinct processes to list when a limit is exceeded.
+const TOP_OFFENDER_COUNT: usize = 5;
+
+///
+/// Limits on the number of processes which may be executed, as a safety valve against rules which
+/// (accidentally) spawn unbounded numbers of processes. A limit of None is unlimited.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProcessCountLimits {
+  // The maximum number of processes which may be executed in one Session.
+  pub per_session: Option<usize>,
+  // The maximum number of processes which may be started by one Session in any one minute.
+  pub per_minute: Option<usize>,
+}
+
+///
+/// Counts of the processes executed for a Session, which are checked against ProcessCountLimits.
+/// Each Session creates its own, which is passed to the ProcessCountLimitCommandRunner in the
+/// Context of each of its requests.
+///
+#[derive(Debug, Default)]
+pub struct ProcessCounts {
+  limits: ProcessCountLimits,
+  state: Mutex<ProcessCountsState>,
+}
+
+#[derive(Debug, Default)]
+struct ProcessCountsState {
+  total: usize,
+  started_at: VecDeque<Instant>,
+  by_description: HashMap<String, usize>,
+  // Once a limit has been exceeded, every further execution fails with the same error.
+  exceeded: Option<String>,
+}
+
+impl ProcessCounts {
+  pub fn new(limits: ProcessCountLimits) -> ProcessCounts {
+    ProcessCounts {
+      limits,
+      state: Mutex::new(ProcessCountsState::default()),
+    }
+  }
+
+  ///
+  /// The number of processes which have been executed for the Session.
+  ///
+  pub fn total(&self) -> usize {
+    self.state.lock().unwrap().total
+  }
+
+  ///
+  /// Records the start of an execution of the given request, or returns an error describing the
+  /// most frequently executed processes if doing so would exceed a limit.
+  ///
+  fn record(&self, req: &ExecuteProcessRequest) -> Result<(), String> {
+    let mut state = self.state.lock().unwrap();
+    if let Some(ref error) = state.exceeded {
+      return Err(error.clone());
+    }
+
+    let now = Instant::now();
+    while state
+      .started_at
+      .front()
+      .map(|started_at| now.duration_since(*started_at) >= MINUTE)
+      .unwrap_or(false)
+    {
+      state.started_at.pop_front();
+    }
+
+    let exceeded = match (self.limits.per_session, self.limits.per_minute) {
+      (Some(max), _) if state.total >= max => Some(format!(
+        "{} processes have been executed in this run, which is the limit (see \
+         --process-execution-max-per-session).",
+        max
+      )),
+      (_, Some(max)) if state.started_at.len() >= max => Some(format!(
+        "{} processes have been executed in the last minute, which is the limit (see \
+         --process-execution-max-per-minute).",
+        max
+      )),
+      _ => None,
+    };
+    if let Some(message) = exceeded {
+      let error = format!("{}\n{}", message, state.top_offenders());
+      state.exceeded = Some(error.clone());
+      return Err(error);
+    }
+
+    state.total += 1;
+    state.started_at.push_back(now);
+    *state
+      .by_description
+      .entry(req.description.clone())
+      .or_insert(0) += 1;
+    Ok(())
+  }
+}
+
+impl ProcessCountsState {
+  fn top_offenders(&self) -> String {
+    let mut counts = self.by_description.iter().collect::<Vec<_>>();
+    // Sort by descending count, and then by description for stability.
+    counts.sort_by(|(d1, c1), (d2, c2)| c2.cmp(c1).then_with(|| d1.cmp(d2)));
+    let mut summary = "The most frequently executed processes were:".to_owned();
+    for (description, count) in counts.into_iter().take(TOP_OFFENDER_COUNT) {
+      summary.push_str(&format!("\n  {:>6} x {}", count, description));
+    }
+    summary
+  }
+}
+
+///
+/// A CommandRunner wrapper which fails executions beyond the ProcessCountLimits of the Session that
+/// requested them, as recorded in the ProcessCounts of their Context.
+///
+pub struct ProcessCountLimitCommandRunner {
+  inner: Box<dyn CommandRunner>,
+}
+
+impl ProcessCountLimitCommandRunner {
+  pub fn new(inner: Box<dyn CommandRunner>) -> ProcessCountLimitCommandRunner {
+    ProcessCountLimitCommandRunner { inner }
+  }
+}
+
+impl CommandRunner for ProcessCountLimitCommandRunner {
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    match context.process_counts.record(&req) {
+      Ok(()) => self.inner.run(req, context),
+      Err(e) => future::err(e).to_boxed(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  use boxfuture::{BoxFuture, Boxable};
+  use futures::{future, Future};
+  use testutil::as_bytes;
+
+  use super::{ProcessCountLimitCommandRunner, ProcessCountLimits, ProcessCounts};
+  use crate::{CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult};
+
+  struct NoopCommandRunner;
+
+  impl CommandRunner for NoopCommandRunner {
+    fn run(
+      &self,
+      _req: ExecuteProcessRequest,
+      _context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      future::ok(FallibleExecuteProcessResult {
+        stdout: as_bytes(""),
+        stderr: as_bytes(""),
+        exit_code: 0,
+        output_directory: fs::EMPTY_DIGEST,
+        execution_attempts: vec![],
+        runner: "noop".to_owned(),
+        timed_out: None,
+      })
+      .to_boxed()
+    }
+  }
+
+  fn request(description: &str) -> ExecuteProcessRequest {
+    ExecuteProcessRequest {
+      argv: vec!["true".to_owned()],
+      timeout: Duration::from_millis(1000),
+      description: description.to_owned(),
+      ..ExecuteProcessRequest::default()
+    }
+  }
+
+  fn runner() -> ProcessCountLimitCommandRunner {
+    ProcessCountLimitCommandRunner::new(Box::new(NoopCommandRunner))
+  }
+
+  fn session_context(limits: ProcessCountLimits) -> Context {
+    Context {
+      process_counts: Arc::new(ProcessCounts::new(limits)),
+      ..Context::default()
+    }
+  }
+
+  #[test]
+  fn unlimited() {
+    let runner = runner();
+    let context = session_context(ProcessCountLimits::default());
+    for _ in 0..100 {
+      runner.run(request("echo"), context.clone()).wait().unwrap();
+    }
+  }
+
+  #[test]
+  fn per_session_limit_lists_top_offenders() {
+    let runner = runner();
+    let limits = ProcessCountLimits {
+      per_session: Some(3),
+      per_minute: None,
+    };
+    let context = session_context(limits);
+    runner
+      .run(request("javac"), context.clone())
+      .wait()
+      .unwrap();
+    runner
+      .run(request("scalac"), context.clone())
+      .wait()
+      .unwrap();
+    runner
+      .run(request("scalac"), context.clone())
+      .wait()
+      .unwrap();
+
+    let expected_error = [
+      "3 processes have been executed in this run, which is the limit (see \
+       --process-execution-max-per-session).",
+      "The most frequently executed processes were:",
+      "       2 x scalac",
+      "       1 x javac",
+    ]
+    .join("\n");
+    assert_eq!(
+      runner.run(request("javac"), context.clone()).wait(),
+      Err(expected_error.clone())
+    );
+    assert_eq!(
+      runner.run(request("javac"), context.clone()).wait(),
+      Err(expected_error)
+    );
+
+    // Another Session counts independently.
+    runner
+      .run(request("javac"), session_context(limits))
+      .wait()
+      .unwrap();
+  }
+
+  #[test]
+  fn per_minute_limit() {
+    let runner = runner();
+    let context = session_context(ProcessCountLimits {
+      per_session: None,
+      per_minute: Some(2),
+    });
+    runner
+      .run(request("javac"), context.clone())
+      .wait()
+      .unwrap();
+    runner
+      .run(request("javac"), context.clone())
+      .wait()
+      .unwrap();
+    let error = runner
+      .run(request("javac"), context.clone())
+      .wait()
+      .unwrap_err();
+    assert!(
+      error.starts_with("2 processes have been executed in the last minute"),
+      "Unexpected error: {}",
+      error
+    );
+  }
+}
@@ -4,22 +4,29 @@ use tempfile;
 use boxfuture::{try_future, BoxFuture, Boxable};
 use fs::{self, GlobExpansionConjunction, GlobMatching, PathGlobs, Snapshot, StrictGlobMatching};
 use futures::{future, Future, Stream};
-use log::info;
+use futures_timer::Delay;
+use log::{debug, info};
+use metrics::{self, Metric, ObservationMetric};
 use std::collections::{BTreeSet, HashSet};
 use std::ffi::OsStr;
 use std::fs::create_dir_all;
-use std::ops::Neg;
-use std::os::unix::{fs::symlink, process::ExitStatusExt};
+use std::io;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::sync::Arc;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
 
 use tokio_codec::{BytesCodec, FramedRead};
 use tokio_process::CommandExt;
 
-use super::{ExecuteProcessRequest, FallibleExecuteProcessResult};
+use super::background::BackgroundProcess;
+use super::{
+  Context, ExecuteProcessRequest, ExecutionStats, FallibleExecuteProcessResult,
+  InteractiveProcessRequest, OutputCapture, OutputLimit, ProcessTimedOut, TIMED_OUT_EXIT_CODE,
+};
 
-use bytes::{Bytes, BytesMut};
+use bytes::Bytes;
 
 pub struct CommandRunner {
   store: fs::Store,
@@ -36,6 +43,103 @@ impl CommandRunner {
     }
   }
 
+  ///
+  /// Runs an interactive process in the foreground, in a sandbox containing its input files, and
+  /// returns its exit code. The caller is responsible for ensuring that nothing else is using the
+  /// console (such as the UI) while the process runs.
+  ///
+  /// The process is waited for synchronously, blocking whichever thread polls the returned Future.
+  ///
+  pub fn run_interactive(&self, req: InteractiveProcessRequest) -> BoxFuture<i32, String> {
+    if req.argv.is_empty() {
+      return future::err("An interactive process must have a non-empty argv.".to_owned())
+        .to_boxed();
+    }
+    let execution_id = Uuid::new_v4().to_string();
+    debug!(
+      "Starting interactive execution {} of {}",
+      execution_id, req.description
+    );
+    let workdir = try_future!(tempfile::Builder::new()
+      .prefix(&format!("interactive-process-{}-", execution_id))
+      .tempdir_in(&self.work_dir)
+      .map_err(|err| format!(
+        "Error making tempdir for interactive process execution: {:?}",
+        err
+      )));
+    let workdir_path = workdir.path().to_owned();
+    let cleanup_local_dirs = self.cleanup_local_dirs;
+    let argv = req.argv;
+    let env = req.env;
+    self
+      .store
+      .materialize_directory(workdir_path.clone(), req.input_files)
+      .and_then(move |()| {
+        StreamedHermeticCommand::new(&argv[0])
+          .args(&argv[1..])
+          .current_dir(&workdir_path)
+          .envs(env)
+          .run_in_foreground()
+      })
+      .then(move |result| {
+        if !cleanup_local_dirs {
+          // Consume the `TempDir` without deleting it, so that the sandbox can be inspected.
+          workdir.into_path();
+        }
+        result
+      })
+      .to_boxed()
+  }
+
+  ///
+  /// Starts a process in the background, in a sandbox containing its input files, and returns a
+  /// handle with which to wait for its outputs. Unlike an interactive process, it does not use the
+  /// console, and may run while the engine continues.
+  ///
+  pub fn start_background(
+    &self,
+    req: InteractiveProcessRequest,
+  ) -> BoxFuture<BackgroundProcess, String> {
+    if req.argv.is_empty() {
+      return future::err("A background process must have a non-empty argv.".to_owned()).to_boxed();
+    }
+    let execution_id = Uuid::new_v4().to_string();
+    debug!(
+      "Starting background execution {} of {}",
+      execution_id, req.description
+    );
+    let workdir = try_future!(tempfile::Builder::new()
+      .prefix(&format!("background-process-{}-", execution_id))
+      .tempdir_in(&self.work_dir)
+      .map_err(|err| format!(
+        "Error making tempdir for background process execution: {:?}",
+        err
+      )));
+    let workdir_path = workdir.path().to_owned();
+    let cleanup_local_dirs = self.cleanup_local_dirs;
+    let argv = req.argv;
+    let env = req.env;
+    self
+      .store
+      .materialize_directory(workdir_path.clone(), req.input_files)
+      .and_then(move |()| {
+        // Unless it is to be cleaned up, consume the `TempDir` without deleting it, so that the
+        // sandbox can be inspected.
+        let sandbox = if cleanup_local_dirs {
+          Some(workdir)
+        } else {
+          workdir.into_path();
+          None
+        };
+        StreamedHermeticCommand::new(&argv[0])
+          .args(&argv[1..])
+          .current_dir(&workdir_path)
+          .envs(env)
+          .start_in_background(sandbox)
+      })
+      .to_boxed()
+  }
+
   fn construct_output_snapshot(
     store: fs::Store,
     posix_fs: Arc<fs::PosixFS>,
@@ -93,7 +197,7 @@ enum ChildOutput {
 }
 
 ///
-/// A streaming command that accepts no input stream and does not consult the `PATH`.
+/// A streaming command that accepts an optional input stream and does not consult the `PATH`.
 ///
 impl StreamedHermeticCommand {
   fn new<S: AsRef<OsStr>>(program: S) -> StreamedHermeticCommand {
@@ -130,31 +234,71 @@ impl StreamedHermeticCommand {
     self
   }
 
-  fn stream(&mut self) -> Result<impl Stream<Item = ChildOutput, Error = String> + Send, String> {
+  ///
+  /// Runs the command with the stdin, stdout and stderr of this process, blocking until it exits,
+  /// and returns its exit code.
+  ///
+  fn run_in_foreground(&mut self) -> Result<i32, String> {
+    let exit_status = self
+      .inner
+      .stdin(Stdio::inherit())
+      .stdout(Stdio::inherit())
+      .stderr(Stdio::inherit())
+      .status()
+      .map_err(|e| format!("Error running process: {:?}", e))?;
+    exit_code(exit_status)
+      .ok_or_else(|| "Child process should exit via returned code or signal.".to_owned())
+  }
+
+  fn start_in_background(
+    &mut self,
+    sandbox: Option<tempfile::TempDir>,
+  ) -> Result<BackgroundProcess, String> {
+    BackgroundProcess::spawn(&mut self.inner, sandbox)
+  }
+
+  fn stream(
+    &mut self,
+    stdin: Option<Bytes>,
+  ) -> Result<impl Stream<Item = ChildOutput, Error = String> + Send, String> {
     self
       .inner
-      .stdin(Stdio::null())
+      .stdin(if stdin.is_some() {
+        Stdio::piped()
+      } else {
+        Stdio::null()
+      })
       .stdout(Stdio::piped())
       .stderr(Stdio::piped())
       .spawn_async()
       .map_err(|e| format!("Error launching process: {:?}", e))
       .and_then(|mut child| {
+        // Stdin is written while the outputs are consumed (so that a process which interleaves
+        // reading and writing cannot deadlock), and is then closed. A process may exit without
+        // reading all of its stdin, so failures to write it are ignored.
+        let write_stdin = match (child.stdin().take(), stdin) {
+          (Some(handle), Some(bytes)) => future::Either::A(
+            tokio_io::io::write_all(handle, bytes).then(|_| Ok::<(), io::Error>(())),
+          ),
+          _ => future::Either::B(future::ok(())),
+        };
+        let stdin_stream = write_stdin
+          .into_stream()
+          .filter_map(|()| None::<ChildOutput>);
         let stdout_stream = FramedRead::new(child.stdout().take().unwrap(), BytesCodec::new())
           .map(|bytes| ChildOutput::Stdout(bytes.into()));
         let stderr_stream = FramedRead::new(child.stderr().take().unwrap(), BytesCodec::new())
           .map(|bytes| ChildOutput::Stderr(bytes.into()));
         let exit_stream = child.into_stream().map(|exit_status| {
           ChildOutput::Exit(
-            exit_status
-              .code()
-              .or_else(|| exit_status.signal().map(Neg::neg))
-              .expect("Child process should exit via returned code or signal."),
+            exit_code(exit_status).expect("Child process should exit via returned code or signal."),
           )
         });
 
         Ok(
           stdout_stream
             .select(stderr_stream)
+            .select(stdin_stream)
             .select(exit_stream)
             .map_err(|e| format!("Failed to consume process outputs: {:?}", e)),
         )
@@ -163,39 +307,115 @@ impl StreamedHermeticCommand {
 }
 
 ///
-/// The fully collected outputs of a completed child process.
+/// The exit code of a process, or the negated number of the signal which killed it.
+///
+#[cfg(unix)]
+pub(crate) fn exit_code(exit_status: ExitStatus) -> Option<i32> {
+  use std::os::unix::process::ExitStatusExt;
+  exit_status
+    .code()
+    .or_else(|| exit_status.signal().map(|signal| -signal))
+}
+
+///
+/// The exit code of a process: Windows has no signals, so every process exits with a code.
+///
+#[cfg(windows)]
+pub(crate) fn exit_code(exit_status: ExitStatus) -> Option<i32> {
+  exit_status.code()
+}
+
+#[cfg(unix)]
+fn symlink_dir<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> std::io::Result<()> {
+  std::os::unix::fs::symlink(src, dst)
+}
+
+#[cfg(windows)]
+fn symlink_dir<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> std::io::Result<()> {
+  std::os::windows::fs::symlink_dir(src, dst)
+}
+
+///
+/// The fully collected outputs of a completed (or timed out) child process.
 ///
 struct ChildResults {
   stdout: Bytes,
   stderr: Bytes,
   exit_code: i32,
+  timed_out: Option<ProcessTimedOut>,
 }
 
 impl ChildResults {
-  fn collect_from<E>(
-    stream: impl Stream<Item = ChildOutput, Error = E> + Send,
-  ) -> impl Future<Item = ChildResults, Error = E> {
-    let init = (
-      BytesMut::with_capacity(8192),
-      BytesMut::with_capacity(8192),
+  ///
+  /// Collects the outputs of the child, retaining only as much of them as the given OutputLimit
+  /// requires. Fails as soon as an output exceeds the limit if its policy is to fail.
+  ///
+  /// If the child has not exited before the timeout, it is killed (by dropping the stream), and the
+  /// outputs which were collected before then are returned.
+  ///
+  fn collect_from(
+    stream: impl Stream<Item = ChildOutput, Error = String> + Send,
+    store: fs::Store,
+    output_limit: Option<OutputLimit>,
+    timeout: Duration,
+  ) -> impl Future<Item = ChildResults, Error = String> {
+    let start = Instant::now();
+    let collected = Arc::new(Mutex::new(Some((
+      OutputCapture::new("stdout", output_limit),
+      OutputCapture::new("stderr", output_limit),
       0,
-    );
-    stream
-      .fold(
-        init,
-        |(mut stdout, mut stderr, mut exit_code), child_output| {
-          match child_output {
-            ChildOutput::Stdout(bytes) => stdout.extend_from_slice(&bytes),
-            ChildOutput::Stderr(bytes) => stderr.extend_from_slice(&bytes),
-            ChildOutput::Exit(code) => exit_code = code,
-          };
-          Ok((stdout, stderr, exit_code)) as Result<_, E>
-        },
-      )
-      .map(|(stdout, stderr, exit_code)| ChildResults {
-        stdout: stdout.into(),
-        stderr: stderr.into(),
-        exit_code,
+    ))));
+    let collected2 = collected.clone();
+    let collect = stream.for_each(move |child_output| {
+      let mut collected = collected2.lock().unwrap();
+      let (stdout, stderr, exit_code) = collected
+        .as_mut()
+        .ok_or_else(|| "Process outputs were already collected.".to_owned())?;
+      match child_output {
+        ChildOutput::Stdout(bytes) => stdout.extend(&bytes)?,
+        ChildOutput::Stderr(bytes) => stderr.extend(&bytes)?,
+        ChildOutput::Exit(code) => *exit_code = code,
+      };
+      Ok(())
+    });
+    let deadline =
+      Delay::new(timeout).map_err(|e| format!("Process timeout timer failed: {:?}", e));
+    collect
+      .select2(deadline)
+      .then(move |res| {
+        let timed_out = match res {
+          Ok(future::Either::A(_)) => None,
+          Ok(future::Either::B((_, collect))) => {
+            // Kill the child.
+            std::mem::drop(collect);
+            Some(ProcessTimedOut {
+              timeout,
+              elapsed: start.elapsed(),
+            })
+          }
+          Err(future::Either::A((e, _))) | Err(future::Either::B((e, _))) => return Err(e),
+        };
+        let (stdout, stderr, exit_code) = collected
+          .lock()
+          .unwrap()
+          .take()
+          .ok_or_else(|| "Process outputs were already collected.".to_owned())?;
+        Ok((stdout, stderr, exit_code, timed_out))
+      })
+      .and_then(move |(stdout, stderr, exit_code, timed_out)| {
+        stdout
+          .finish(&store)
+          .join(stderr.finish(&store))
+          .map(move |(stdout, stderr)| ChildResults {
+            stdout,
+            stderr,
+            exit_code: if timed_out.is_some() {
+              TIMED_OUT_EXIT_CODE
+            } else {
+              exit_code
+            },
+            timed_out,
+          })
       })
   }
 }
@@ -204,9 +424,23 @@ impl super::CommandRunner for CommandRunner {
   ///
   /// Runs a command on this machine in the passed working directory.
   ///
-  fn run(&self, req: ExecuteProcessRequest) -> BoxFuture<FallibleExecuteProcessResult, String> {
+  /// Each execution is assigned a unique id, which is logged and included in the name of its
+  /// sandbox directory.
+  ///
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    _context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    metrics::increment_counter(Metric::LocalExecutionRequests, 1);
+    let start_time = Instant::now();
+    let execution_id = Uuid::new_v4().to_string();
+    debug!(
+      "Starting local execution {} of {}",
+      execution_id, req.description
+    );
     let workdir = try_future!(tempfile::Builder::new()
-      .prefix("process-execution")
+      .prefix(&format!("process-execution-{}-", execution_id))
       .tempdir_in(&self.work_dir)
       .map_err(|err| format!(
         "Error making tempdir for local process execution: {:?}",
@@ -216,7 +450,9 @@ impl super::CommandRunner for CommandRunner {
     let workdir_path2 = workdir_path.clone();
     let workdir_path3 = workdir_path.clone();
     let store = self.store.clone();
+    let store4 = self.store.clone();
 
+    let empty_output_dir_paths = req.empty_output_directories();
     let env = req.env;
     let output_file_paths = req.output_files;
     let output_file_paths2 = output_file_paths.clone();
@@ -226,12 +462,17 @@ impl super::CommandRunner for CommandRunner {
     let argv = req.argv;
     let req_description = req.description;
     let maybe_jdk_home = req.jdk_home;
+    let output_limit = req.output_limit;
+    let timeout = req.timeout;
+    let stdin_digest = req.stdin_digest;
+    let store2 = self.store.clone();
+    let store3 = self.store.clone();
     self
       .store
       .materialize_directory(workdir_path.clone(), req.input_files)
       .and_then(move |()| {
         maybe_jdk_home.map_or(Ok(()), |jdk_home| {
-          symlink(jdk_home, workdir_path3.clone().join(".jdk"))
+          symlink_dir(jdk_home, workdir_path3.clone().join(".jdk"))
             .map_err(|err| format!("Error making symlink for local execution: {:?}", err))
         })?;
         // The bazel remote execution API specifies that the parent directories for output files and
@@ -258,19 +499,30 @@ impl super::CommandRunner for CommandRunner {
         }
         Ok(())
       })
-      .and_then(move |()| {
+      .and_then(move |()| match stdin_digest {
+        Some(digest) => store3
+          .load_file_bytes_with(digest, |bytes| bytes)
+          .and_then(move |maybe_bytes| {
+            maybe_bytes
+              .map(Some)
+              .ok_or_else(|| format!("Stdin digest {:?} was not found in the store.", digest))
+          })
+          .to_boxed(),
+        None => future::ok(None).to_boxed(),
+      })
+      .and_then(move |stdin| {
         StreamedHermeticCommand::new(&argv[0])
           .args(&argv[1..])
           .current_dir(&workdir_path)
           .envs(env)
-          .stream()
+          .stream(stdin)
       })
       // NB: We fully buffer up the `Stream` above into final `ChildResults` below and so could
       // instead be using `CommandExt::output_async` above to avoid the `ChildResults::collect_from`
       // code. The idea going forward though is we eventually want to pass incremental results on
       // down the line for streaming process results to console logs, etc. as tracked by:
       //   https://github.com/pantsbuild/pants/issues/6089
-      .and_then(ChildResults::collect_from)
+      .and_then(move |stream| ChildResults::collect_from(stream, store2, output_limit, timeout))
       .and_then(move |child_results| {
         let output_snapshot = if output_file_paths.is_empty() && output_dir_paths.is_empty() {
           future::ok(fs::Snapshot::empty()).to_boxed()
@@ -301,19 +553,26 @@ impl super::CommandRunner for CommandRunner {
             stderr: child_results.stderr,
             exit_code: child_results.exit_code,
             output_directory: snapshot.digest,
-            execution_attempts: vec![],
+            execution_attempts: vec![ExecutionStats::default()],
+            runner: "local".to_owned(),
+            timed_out: child_results.timed_out,
           })
           .to_boxed()
       })
+      .and_then(move |result| result.with_empty_directories(store4, &empty_output_dir_paths))
       .then(move |result| {
+        metrics::record_duration(
+          ObservationMetric::LocalExecutionTimeMicros,
+          start_time.elapsed(),
+        );
         // Force workdir not to get dropped until after we've ingested the outputs
         if !cleanup_local_dirs {
           // This consumes the `TempDir` without deleting directory on the filesystem, meaning
           // that the temporary directory will no longer be automatically deleted when dropped.
           let preserved_path = workdir.into_path();
           info!(
-            "preserved local process execution dir `{:?}` for {:?}",
-            preserved_path, req_description
+            "preserved local process execution dir `{:?}` for {:?} (execution {})",
+            preserved_path, req_description, execution_id
           );
         } // Else, workdir gets dropped here
         result
@@ -322,16 +581,21 @@ impl super::CommandRunner for CommandRunner {
   }
 }
 
-#[cfg(test)]
+// These tests run unix binaries (bash, echo, etc) as their processes.
+#[cfg(all(test, unix))]
 mod tests {
   use tempfile;
   use testutil;
 
   use super::super::CommandRunner as CommandRunnerTrait;
   use super::{ExecuteProcessRequest, FallibleExecuteProcessResult};
+  use crate::{
+    Context, ExecutionStats, InteractiveProcessRequest, OutputLimit, OutputOverflowPolicy,
+    TIMED_OUT_EXIT_CODE,
+  };
   use fs;
   use std;
-  use std::collections::{BTreeMap, BTreeSet};
+  use std::collections::BTreeMap;
   use std::env;
   use std::os::unix::fs::PermissionsExt;
   use std::path::{Path, PathBuf};
@@ -345,13 +609,9 @@ mod tests {
   fn stdout() {
     let result = run_command_locally(ExecuteProcessRequest {
       argv: owned_string_vec(&["/bin/echo", "-n", "foo"]),
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "echo foo".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -361,23 +621,44 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
 
+  #[test]
+  #[cfg(unix)]
+  fn timeout() {
+    let result = run_command_locally(ExecuteProcessRequest {
+      argv: vec![
+        find_bash(),
+        "-c".to_owned(),
+        "/bin/echo -n foo; /bin/sleep 10".to_owned(),
+      ],
+      timeout: Duration::from_millis(500),
+      description: "sleepy".to_string(),
+      ..ExecuteProcessRequest::default()
+    })
+    .unwrap();
+
+    assert_eq!(result.stdout, as_bytes("foo"));
+    assert_eq!(result.exit_code, TIMED_OUT_EXIT_CODE);
+    let timed_out = result.timed_out.expect("Process should have timed out.");
+    assert_eq!(timed_out.timeout, Duration::from_millis(500));
+    assert!(timed_out.elapsed >= timed_out.timeout);
+    assert!(timed_out.elapsed < Duration::from_secs(10));
+  }
+
   #[test]
   #[cfg(unix)]
   fn stdout_and_stderr_and_exit_code() {
     let result = run_command_locally(ExecuteProcessRequest {
       argv: owned_string_vec(&["/bin/bash", "-c", "echo -n foo ; echo >&2 -n bar ; exit 1"]),
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "echo foo and fail".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -387,7 +668,9 @@ mod tests {
         stderr: as_bytes("bar"),
         exit_code: 1,
         output_directory: fs::EMPTY_DIGEST,
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
@@ -398,13 +681,9 @@ mod tests {
     // Launch a process that kills itself with a signal.
     let result = run_command_locally(ExecuteProcessRequest {
       argv: owned_string_vec(&["/bin/bash", "-c", "kill $$"]),
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "kill self".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -414,7 +693,9 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: -15,
         output_directory: fs::EMPTY_DIGEST,
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
@@ -429,12 +710,9 @@ mod tests {
     let result = run_command_locally(ExecuteProcessRequest {
       argv: owned_string_vec(&["/usr/bin/env"]),
       env: env.clone(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "run env".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     let stdout = String::from_utf8(result.unwrap().stdout.to_vec()).unwrap();
@@ -465,12 +743,9 @@ mod tests {
       ExecuteProcessRequest {
         argv: owned_string_vec(&["/usr/bin/env"]),
         env: env,
-        input_files: fs::EMPTY_DIGEST,
-        output_files: BTreeSet::new(),
-        output_directories: BTreeSet::new(),
         timeout: Duration::from_millis(1000),
         description: "run env".to_string(),
-        jdk_home: None,
+        ..ExecuteProcessRequest::default()
       }
     }
 
@@ -484,13 +759,9 @@ mod tests {
   fn binary_not_found() {
     run_command_locally(ExecuteProcessRequest {
       argv: owned_string_vec(&["echo", "-n", "foo"]),
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "echo foo".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     })
     .expect_err("Want Err");
   }
@@ -503,13 +774,9 @@ mod tests {
         "-c",
         "exit 0",
       ]),
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "bash".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
     assert_eq!(
       result.unwrap(),
@@ -518,7 +785,9 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
@@ -531,13 +800,10 @@ mod tests {
         "-c".to_owned(),
         format!("echo -n {} > {}", TestData::roland().string(), "roland"),
       ],
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
       output_files: vec![PathBuf::from("roland")].into_iter().collect(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "bash".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -547,7 +813,9 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 0,
         output_directory: TestDirectory::containing_roland().digest(),
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
@@ -565,13 +833,11 @@ mod tests {
           TestData::catnip().string()
         ),
       ],
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
       output_files: vec![PathBuf::from("treats")].into_iter().collect(),
       output_directories: vec![PathBuf::from("cats")].into_iter().collect(),
       timeout: Duration::from_millis(1000),
       description: "bash".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -581,7 +847,9 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 0,
         output_directory: TestDirectory::recursive().digest(),
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
@@ -598,15 +866,12 @@ mod tests {
           TestData::catnip().string()
         ),
       ],
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
       output_files: vec![PathBuf::from("cats/roland"), PathBuf::from("treats")]
         .into_iter()
         .collect(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "treats-roland".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -616,7 +881,9 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 0,
         output_directory: TestDirectory::recursive().digest(),
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
@@ -633,13 +900,10 @@ mod tests {
           "roland"
         ),
       ],
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
       output_files: vec![PathBuf::from("roland")].into_iter().collect(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "echo foo".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -649,7 +913,9 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 1,
         output_directory: TestDirectory::containing_roland().digest(),
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
@@ -662,15 +928,12 @@ mod tests {
         "-c".to_owned(),
         format!("echo -n {} > {}", TestData::roland().string(), "roland"),
       ],
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
       output_files: vec![PathBuf::from("roland"), PathBuf::from("susannah")]
         .into_iter()
         .collect(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "echo-roland".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -680,7 +943,9 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 0,
         output_directory: TestDirectory::containing_roland().digest(),
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
@@ -693,13 +958,11 @@ mod tests {
         "-c".to_owned(),
         format!("echo -n {} > cats/roland", TestData::roland().string()),
       ],
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
       output_files: vec![PathBuf::from("cats/roland")].into_iter().collect(),
       output_directories: vec![PathBuf::from("cats")].into_iter().collect(),
       timeout: Duration::from_millis(1000),
       description: "bash".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -709,7 +972,9 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 0,
         output_directory: TestDirectory::nested().digest(),
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
@@ -723,13 +988,10 @@ mod tests {
 
     let result = run_command_locally(ExecuteProcessRequest {
       argv: vec!["/bin/cat".to_owned(), ".jdk/roland".to_owned()],
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "cat roland".to_string(),
       jdk_home: Some(preserved_work_tmpdir.path().to_path_buf()),
+      ..ExecuteProcessRequest::default()
     });
     assert_eq!(
       result,
@@ -738,7 +1000,9 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       })
     )
   }
@@ -755,13 +1019,10 @@ mod tests {
           "-c".to_owned(),
           format!("echo -n {} > {}", TestData::roland().string(), "roland"),
         ],
-        env: BTreeMap::new(),
-        input_files: fs::EMPTY_DIGEST,
         output_files: vec![PathBuf::from("roland")].into_iter().collect(),
-        output_directories: BTreeSet::new(),
         timeout: Duration::from_millis(1000),
         description: "bash".to_string(),
-        jdk_home: None,
+        ..ExecuteProcessRequest::default()
       },
       preserved_work_root.clone(),
       false,
@@ -774,7 +1035,10 @@ mod tests {
     let subdirs = testutil::file::list_dir(&preserved_work_root);
     assert_eq!(subdirs.len(), 1);
 
-    // Then look for a file like e.g. `/tmp/abc1234/process-execution7zt4pH/roland`
+    // The sub-dir is named for the execution id, followed by a random suffix.
+    assert!(subdirs[0].starts_with("process-execution-"));
+
+    // Then look for a file like e.g. `/tmp/abc1234/process-execution-<uuid>-7zt4pH/roland`
     let rolands_path = preserved_work_root.join(&subdirs[0]).join("roland");
     assert!(rolands_path.exists());
   }
@@ -790,13 +1054,9 @@ mod tests {
     run_command_locally_in_dir(
       ExecuteProcessRequest {
         argv: vec!["doesnotexist".to_owned()],
-        env: BTreeMap::new(),
-        input_files: fs::EMPTY_DIGEST,
-        output_files: BTreeSet::new(),
-        output_directories: BTreeSet::new(),
         timeout: Duration::from_millis(1000),
         description: "failing execution".to_string(),
-        jdk_home: None,
+        ..ExecuteProcessRequest::default()
       },
       preserved_work_root.clone(),
       false,
@@ -822,13 +1082,11 @@ mod tests {
           TestData::roland().string()
         ),
       ],
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
       output_files: vec![PathBuf::from("cats/roland")].into_iter().collect(),
       output_directories: vec![PathBuf::from("birds/falcons")].into_iter().collect(),
       timeout: Duration::from_millis(1000),
       description: "create nonoverlapping directories and file".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -838,7 +1096,9 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 0,
         output_directory: TestDirectory::nested_dir_and_file().digest(),
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
@@ -851,13 +1111,10 @@ mod tests {
         "-c".to_owned(),
         "/bin/mkdir falcons".to_string(),
       ],
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
       output_directories: vec![PathBuf::from("falcons")].into_iter().collect(),
       timeout: Duration::from_millis(1000),
       description: "bash".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     });
 
     assert_eq!(
@@ -867,11 +1124,242 @@ mod tests {
         stderr: as_bytes(""),
         exit_code: 0,
         output_directory: TestDirectory::containing_falcons_dir().digest(),
-        execution_attempts: vec![],
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
       }
     )
   }
 
+  #[test]
+  fn output_missing_dir() {
+    let request = |preserve_empty_output_directories| ExecuteProcessRequest {
+      argv: vec![find_bash(), "-c".to_owned(), "true".to_string()],
+      output_directories: vec![PathBuf::from("falcons")].into_iter().collect(),
+      timeout: Duration::from_millis(1000),
+      description: "bash".to_string(),
+      preserve_empty_output_directories,
+      ..ExecuteProcessRequest::default()
+    };
+
+    // By default, an output directory which the process did not create is omitted.
+    assert_eq!(
+      run_command_locally(request(false))
+        .unwrap()
+        .output_directory,
+      fs::EMPTY_DIGEST
+    );
+    // But it may be preserved as an empty directory.
+    assert_eq!(
+      run_command_locally(request(true)).unwrap().output_directory,
+      TestDirectory::containing_falcons_dir().digest()
+    );
+  }
+
+  #[test]
+  fn output_missing_nested_dir_merged() {
+    let result = run_command_locally(ExecuteProcessRequest {
+      argv: vec![
+        find_bash(),
+        "-c".to_owned(),
+        format!(
+          "/bin/mkdir cats && echo -n {} > cats/roland",
+          TestData::roland().string()
+        ),
+      ],
+      output_directories: vec![PathBuf::from("cats"), PathBuf::from("birds/falcons")]
+        .into_iter()
+        .collect(),
+      timeout: Duration::from_millis(1000),
+      description: "bash".to_string(),
+      preserve_empty_output_directories: true,
+      ..ExecuteProcessRequest::default()
+    });
+
+    // The directory which was created keeps its content, and the missing one is added.
+    assert_eq!(
+      result.unwrap().output_directory,
+      TestDirectory::nested_dir_and_file().digest()
+    );
+  }
+
+  #[test]
+  fn output_limit_truncate_head() {
+    let result = run_command_locally(limited_echo_request(OutputOverflowPolicy::TruncateHead));
+
+    let result = result.unwrap();
+    assert_eq!(
+      result.stdout,
+      as_bytes("[stdout truncated: the first 6 of 10 bytes were omitted]\nghij")
+    );
+    assert_eq!(result.stderr, as_bytes("ab"));
+  }
+
+  #[test]
+  fn output_limit_truncate_tail() {
+    let result = run_command_locally(limited_echo_request(OutputOverflowPolicy::TruncateTail));
+
+    assert_eq!(
+      result.unwrap().stdout,
+      as_bytes("abcd\n[stdout truncated: the last 6 of 10 bytes were omitted]\n")
+    );
+  }
+
+  #[test]
+  fn output_limit_fail() {
+    let result = run_command_locally(limited_echo_request(OutputOverflowPolicy::Fail));
+
+    assert_eq!(
+      result,
+      Err("Process stdout exceeded the limit of 4 bytes.".to_owned())
+    );
+  }
+
+  #[test]
+  fn output_limit_spill() {
+    let result = run_command_locally(limited_echo_request(OutputOverflowPolicy::Spill));
+
+    let stdout = String::from_utf8(result.unwrap().stdout.to_vec()).unwrap();
+    assert_eq!(
+      stdout,
+      format!(
+        "[stdout of 10 bytes exceeded the limit of 4 bytes, and was stored as {} 10]\n",
+        TestData::new("abcdefghij").fingerprint()
+      )
+    );
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn interactive_process_runs_with_its_inputs() {
+    let store_dir = TempDir::new().unwrap();
+    let work_dir = TempDir::new().unwrap();
+    let store = fs::Store::local_only(store_dir.path()).unwrap();
+    let runner = super::CommandRunner::new(store.clone(), work_dir.path().to_owned(), true);
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let roland = TestData::roland();
+    runtime
+      .block_on(store.store_file_bytes(roland.bytes(), false))
+      .unwrap();
+    runtime
+      .block_on(store.record_directory(&TestDirectory::containing_roland().directory(), false))
+      .unwrap();
+
+    let request = |script: &str| InteractiveProcessRequest {
+      argv: owned_string_vec(&["/bin/bash", "-c", script]),
+      env: BTreeMap::new(),
+      input_files: TestDirectory::containing_roland().digest(),
+      description: "interactive".to_owned(),
+    };
+    let exit_code = runtime
+      .block_on(runner.run_interactive(request(&format!(
+        "test \"$(< roland)\" = \"{}\" && exit 7",
+        roland.string()
+      ))))
+      .unwrap();
+    assert_eq!(exit_code, 7);
+
+    let exit_code = runtime
+      .block_on(runner.run_interactive(request("exit 1")))
+      .unwrap();
+    assert_eq!(exit_code, 1);
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn background_process_runs_with_its_inputs() {
+    let store_dir = TempDir::new().unwrap();
+    let work_dir = TempDir::new().unwrap();
+    let store = fs::Store::local_only(store_dir.path()).unwrap();
+    let runner = super::CommandRunner::new(store.clone(), work_dir.path().to_owned(), true);
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let roland = TestData::roland();
+    runtime
+      .block_on(store.store_file_bytes(roland.bytes(), false))
+      .unwrap();
+    runtime
+      .block_on(store.record_directory(&TestDirectory::containing_roland().directory(), false))
+      .unwrap();
+
+    let process = runtime
+      .block_on(runner.start_background(InteractiveProcessRequest {
+        argv: owned_string_vec(&["/bin/bash", "-c", "cat roland; sleep 60"]),
+        env: BTreeMap::new(),
+        input_files: TestDirectory::containing_roland().digest(),
+        description: "background".to_owned(),
+      }))
+      .unwrap();
+    let progress = process.wait_for_progress(0, Duration::from_secs(10));
+    assert_eq!(progress.stdout, roland.bytes());
+    assert_eq!(progress.exit_code, None);
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn stdin() {
+    let store_dir = TempDir::new().unwrap();
+    let work_dir = TempDir::new().unwrap();
+    let store = fs::Store::local_only(store_dir.path()).unwrap();
+    let runner = super::CommandRunner::new(store.clone(), work_dir.path().to_owned(), true);
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let stdin = TestData::roland();
+    let stdin_digest = runtime
+      .block_on(store.store_file_bytes(stdin.bytes(), false))
+      .unwrap();
+    let result = runtime.block_on(runner.run(
+      ExecuteProcessRequest {
+        argv: owned_string_vec(&["/bin/cat"]),
+        timeout: Duration::from_millis(1000),
+        description: "cat stdin".to_string(),
+        stdin_digest: Some(stdin_digest),
+        ..ExecuteProcessRequest::default()
+      },
+      Context::default(),
+    ));
+
+    assert_eq!(result.unwrap().stdout, stdin.bytes());
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn stdin_missing() {
+    let result = run_command_locally(ExecuteProcessRequest {
+      argv: owned_string_vec(&["/bin/cat"]),
+      timeout: Duration::from_millis(1000),
+      description: "cat stdin".to_string(),
+      stdin_digest: Some(TestData::roland().digest()),
+      ..ExecuteProcessRequest::default()
+    });
+
+    assert_eq!(
+      result,
+      Err(format!(
+        "Stdin digest {:?} was not found in the store.",
+        TestData::roland().digest()
+      ))
+    );
+  }
+
+  fn limited_echo_request(policy: OutputOverflowPolicy) -> ExecuteProcessRequest {
+    ExecuteProcessRequest {
+      argv: vec![
+        find_bash(),
+        "-c".to_owned(),
+        "echo -n abcdefghij; echo -n ab >&2".to_owned(),
+      ],
+      timeout: Duration::from_millis(1000),
+      description: "echo alphabet".to_string(),
+      output_limit: Some(OutputLimit {
+        max_bytes: 4,
+        policy,
+      }),
+      ..ExecuteProcessRequest::default()
+    }
+  }
+
   fn run_command_locally(
     req: ExecuteProcessRequest,
   ) -> Result<FallibleExecuteProcessResult, String> {
@@ -900,7 +1388,7 @@ mod tests {
     };
     tokio::runtime::Runtime::new()
       .unwrap()
-      .block_on(runner.run(req))
+      .block_on(runner.run(req, Context::default()))
   }
 
   fn find_bash() -> String {
@@ -0,0 +1,262 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use boxfuture::{BoxFuture, Boxable};
+use digest::{Digest as DigestTrait, FixedOutput};
+use futures::Future;
+use hashing::{Digest, Fingerprint};
+use log::warn;
+use serde_derive::Serialize;
+use serde_json;
+use sha2::Sha256;
+
+use super::{
+  CommandRunner, Context, ExecuteProcessRequest, ExecuteProcessRequestMetadata,
+  FallibleExecuteProcessResult,
+};
+
+///
+/// A record of one execution of a process, which may be compared with records from other runs or
+/// machines to determine why a process re-ran, or why its results differed.
+///
+#[derive(Debug, Serialize)]
+pub struct ProvenanceEntry {
+  // The digest of the (remote execution API) Action for the request, computed without any
+  // runner-specific configuration, so that it is comparable between runners and machines.
+  pub action_digest: Option<Digest>,
+  pub input_digest: Digest,
+  pub argv: Vec<String>,
+  // The environment may contain secrets, so only its fingerprint is recorded.
+  pub env_fingerprint: Fingerprint,
+  pub description: String,
+  // The runner which produced the result, if the process completed. This may differ between
+  // processes in a run (for example, if a Session overrides the ExecutionStrategy).
+  pub runner: Option<String>,
+  pub attempt_count: Option<usize>,
+  pub output_digest: Option<Digest>,
+  pub exit_code: Option<i32>,
+  pub error: Option<String>,
+  pub duration_millis: u64,
+  pub started_at_secs: u64,
+}
+
+impl ProvenanceEntry {
+  fn new(req: &ExecuteProcessRequest, context: &Context) -> ProvenanceEntry {
+    // Outside of a Session, secret env is salted randomly (as it is for remote execution).
+    let salt = context
+      .secret_env_salt
+      .clone()
+      .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let metadata = ExecuteProcessRequestMetadata {
+      secret_env_salt: Some(salt.clone()),
+      ..ExecuteProcessRequestMetadata::default()
+    };
+    let action_digest = super::remote::make_execute_request(req, &metadata)
+      .and_then(|(_, _, execute_request)| execute_request.get_action_digest().into())
+      .map_err(|e| warn!("Failed to compute action digest for provenance: {}", e))
+      .ok();
+
+    let mut hasher = Sha256::default();
+    for (key, value) in &req.env {
+      hasher.input(key.as_bytes());
+      hasher.input(&[0]);
+      if req.secret_env.contains(key) {
+        // Salted, so that the fingerprint cannot be used to confirm a guess of the secret.
+        hasher.input(salt.as_bytes());
+        hasher.input(&[0]);
+      }
+      hasher.input(value.as_bytes());
+      hasher.input(&[0]);
+    }
+
+    ProvenanceEntry {
+      action_digest,
+      input_digest: req.input_files,
+      argv: req.argv.clone(),
+      env_fingerprint: Fingerprint::from_bytes_unsafe(&hasher.fixed_result()),
+      description: req.description.clone(),
+      runner: None,
+      attempt_count: None,
+      output_digest: None,
+      exit_code: None,
+      error: None,
+      duration_millis: 0,
+      started_at_secs: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0),
+    }
+  }
+}
+
+///
+/// A CommandRunner wrapper which appends a JSON ProvenanceEntry line to a file for each process
+/// that it executes.
+///
+pub struct ProvenanceRecordingCommandRunner {
+  inner: Box<dyn CommandRunner>,
+  file: Arc<Mutex<File>>,
+}
+
+impl ProvenanceRecordingCommandRunner {
+  pub fn new(
+    inner: Box<dyn CommandRunner>,
+    path: &Path,
+  ) -> Result<ProvenanceRecordingCommandRunner, String> {
+    let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .map_err(|e| format!("Failed to open provenance file {:?}: {}", path, e))?;
+    Ok(ProvenanceRecordingCommandRunner {
+      inner,
+      file: Arc::new(Mutex::new(file)),
+    })
+  }
+
+  fn record(file: &Mutex<File>, entry: &ProvenanceEntry) {
+    let mut line = match serde_json::to_string(entry) {
+      Ok(line) => line,
+      Err(e) => {
+        warn!("Failed to serialize provenance entry: {}", e);
+        return;
+      }
+    };
+    line.push('\n');
+    // A single write per entry, so that concurrent entries are not interleaved.
+    let mut file = file.lock().unwrap();
+    if let Err(e) = file.write_all(line.as_bytes()) {
+      warn!("Failed to write provenance entry: {}", e);
+    }
+  }
+}
+
+impl CommandRunner for ProvenanceRecordingCommandRunner {
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    let mut entry = ProvenanceEntry::new(&req, &context);
+    let file = self.file.clone();
+    let start = Instant::now();
+    self
+      .inner
+      .run(req, context)
+      .then(move |result| {
+        let elapsed = start.elapsed();
+        entry.duration_millis = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+        match result {
+          Ok(ref result) => {
+            entry.runner = Some(result.runner.clone());
+            entry.attempt_count = Some(result.attempt_count());
+            entry.output_digest = Some(result.output_directory);
+            entry.exit_code = Some(result.exit_code);
+          }
+          Err(ref e) => entry.error = Some(e.clone()),
+        }
+        Self::record(&file, &entry);
+        result
+      })
+      .to_boxed()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+  use std::fs;
+  use std::time::Duration;
+
+  use boxfuture::{BoxFuture, Boxable};
+  use futures::{future, Future};
+  use serde_json::{self, Value};
+  use tempfile::TempDir;
+  use testutil::data::TestDirectory;
+  use testutil::{as_bytes, owned_string_vec};
+
+  use super::ProvenanceRecordingCommandRunner;
+  use crate::{
+    CommandRunner, Context, ExecuteProcessRequest, ExecutionStats, FallibleExecuteProcessResult,
+  };
+
+  struct EchoCommandRunner;
+
+  impl CommandRunner for EchoCommandRunner {
+    fn run(
+      &self,
+      req: ExecuteProcessRequest,
+      _context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      if req.argv.is_empty() {
+        return future::err("No argv!".to_owned()).to_boxed();
+      }
+      future::ok(FallibleExecuteProcessResult {
+        stdout: as_bytes(&req.argv.join(" ")),
+        stderr: as_bytes(""),
+        exit_code: 0,
+        output_directory: TestDirectory::containing_roland().digest(),
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: "local".to_owned(),
+        timed_out: None,
+      })
+      .to_boxed()
+    }
+  }
+
+  fn request(argv: &[&str]) -> ExecuteProcessRequest {
+    let mut env = BTreeMap::new();
+    env.insert("SECRET".to_owned(), "hunter2".to_owned());
+    ExecuteProcessRequest {
+      argv: owned_string_vec(argv),
+      env,
+      input_files: TestDirectory::containing_roland().digest(),
+      timeout: Duration::from_millis(1000),
+      description: "echo".to_owned(),
+      ..ExecuteProcessRequest::default()
+    }
+  }
+
+  #[test]
+  fn records_successes_and_failures() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("provenance.jsonl");
+    let runner = ProvenanceRecordingCommandRunner::new(Box::new(EchoCommandRunner), &path).unwrap();
+
+    runner
+      .run(request(&["echo", "hi"]), Context::default())
+      .wait()
+      .unwrap();
+    runner
+      .run(request(&[]), Context::default())
+      .wait()
+      .unwrap_err();
+
+    let content = fs::read_to_string(&path).unwrap();
+    let entries = content
+      .lines()
+      .map(|line| serde_json::from_str::<Value>(line).unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 2);
+
+    let success = &entries[0];
+    assert_eq!(success["argv"], serde_json::json!(["echo", "hi"]));
+    assert_eq!(success["runner"], "local");
+    assert_eq!(success["attempt_count"], 1);
+    assert_eq!(success["exit_code"], 0);
+    assert_eq!(
+      success["output_digest"]["fingerprint"],
+      TestDirectory::containing_roland().fingerprint().to_hex()
+    );
+    assert!(success["action_digest"]["fingerprint"].is_string());
+    assert!(!content.contains("hunter2"));
+
+    let failure = &entries[1];
+    assert_eq!(failure["runner"], Value::Null);
+    assert_eq!(failure["error"], "No argv!");
+    assert_eq!(failure["output_digest"], Value::Null);
+  }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::mem::drop;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -8,17 +8,23 @@ use bazel_protos;
 use boxfuture::{try_future, BoxFuture, Boxable};
 use bytes::Bytes;
 use digest::{Digest as DigestTrait, FixedOutput};
-use fs::{self, File, PathStat, Store};
+use fs::{self, File, PathStat, ProxyConfig, Store};
 use futures::{future, Future, Stream};
 use futures_timer::Delay;
 use grpcio;
 use hashing::{Digest, Fingerprint};
 use log::{debug, trace, warn};
+use metrics::{self, Metric, ObservationMetric};
 use protobuf::{self, Message, ProtobufEnum};
+use serde_derive::Serialize;
 use sha2::Sha256;
 use time;
+use uuid::Uuid;
 
-use super::{ExecuteProcessRequest, ExecutionStats, FallibleExecuteProcessResult};
+use super::{
+  Context, ExecuteProcessRequest, ExecuteProcessRequestMetadata, ExecutionStats,
+  FallibleExecuteProcessResult, ProcessTimedOut, TIMED_OUT_EXIT_CODE,
+};
 use std;
 use std::cmp::min;
 use std::collections::btree_map::BTreeMap;
@@ -27,6 +33,15 @@ use std::collections::btree_map::BTreeMap;
 // This may be not specified in an ExecuteProcessRequest, and may be populated only by the
 // CommandRunner.
 const CACHE_KEY_GEN_VERSION_ENV_VAR_NAME: &str = "PANTS_CACHE_KEY_GEN_VERSION";
+// Similarly reserved: the version of the cache keys of each subsystem is set in an environment
+// variable named `PANTS_<SUBSYSTEM>_CACHE_KEY_VERSION`.
+const CACHE_KEY_VERSION_ENV_VAR_PREFIX: &str = "PANTS_";
+const CACHE_KEY_VERSION_ENV_VAR_SUFFIX: &str = "_CACHE_KEY_VERSION";
+const SECRET_ENV_SALT_ENV_VAR_NAME: &str = "PANTS_SECRET_ENV_SALT";
+
+// The header which carries a serialized RequestMetadata proto, as defined by the Remote Execution
+// API.
+const REQUEST_METADATA_HEADER_NAME: &str = "build.bazel.remote.execution.v2.requestmetadata-bin";
 
 #[derive(Debug)]
 enum OperationOrStatus {
@@ -34,16 +49,48 @@ enum OperationOrStatus {
   Status(bazel_protos::status::Status),
 }
 
+///
+/// Deadlines for the RPCs made to a remote execution server. Each deadline applies to a single
+/// RPC, rather than to the execution as a whole (which is bounded by the timeout of its request).
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExecutionTimeouts {
+  // Covers waiting for a response to an Execute or GetOperation request. A server which has not
+  // responded within it is treated as unresponsive.
+  pub execute_stream: Duration,
+  pub action_cache: Duration,
+}
+
+impl Default for ExecutionTimeouts {
+  fn default() -> ExecutionTimeouts {
+    ExecutionTimeouts {
+      execute_stream: Duration::from_secs(120),
+      action_cache: Duration::from_secs(10),
+    }
+  }
+}
+
 #[derive(Clone)]
 pub struct CommandRunner {
-  cache_key_gen_version: Option<String>,
-  instance_name: Option<String>,
+  // Identifies this runner in the results that it produces.
+  name: String,
+  metadata: ExecuteProcessRequestMetadata,
   authorization_header: Option<String>,
-  platform_properties: BTreeMap<String, String>,
+  allowed_platform_property_keys: BTreeSet<String>,
+  skip_cache_lookup: bool,
+  // Whether results may be written to the ActionCache. Only trusted writers (such as CI for the
+  // main branch) should be allowed to, so that untrusted machines cannot poison the cache.
+  action_cache_writes: bool,
+  // Whether the outputs of cached results are checked for in the CAS before the result is used.
+  verify_cache_hits: bool,
+  // If true, requests fail immediately rather than being sent to the server.
+  offline: bool,
+  timeouts: ExecutionTimeouts,
   channel: grpcio::Channel,
   env: Arc<grpcio::Environment>,
   execution_client: Arc<bazel_protos::remote_execution_grpc::ExecutionClient>,
   operations_client: Arc<bazel_protos::operations_grpc::OperationsClient>,
+  action_cache_client: Arc<bazel_protos::remote_execution_grpc::ActionCacheClient>,
   store: Store,
   futures_timer_thread: resettable::Resettable<futures_timer::HelperThread>,
 }
@@ -54,6 +101,8 @@ enum ExecutionError {
   Fatal(String),
   // Digests are Files and Directories which have been reported to be missing. May be incomplete.
   MissingDigests(Vec<Digest>),
+  // Digests are outputs of a cached result which are missing from the CAS.
+  MissingCachedOutputs(Vec<Digest>),
   // String is the operation name which can be used to poll the GetOperation gRPC API.
   NotFinished(String),
 }
@@ -75,10 +124,14 @@ impl CommandRunner {
   fn oneshot_execute(
     &self,
     execute_request: &Arc<bazel_protos::remote_execution::ExecuteRequest>,
+    execution_id: &str,
   ) -> BoxFuture<OperationOrStatus, String> {
     let stream = try_future!(self
       .execution_client
-      .execute_opt(&execute_request, self.call_option())
+      .execute_opt(
+        &execute_request,
+        try_future!(self.call_option(execution_id)).timeout(self.timeouts.execute_stream)
+      )
       .map_err(rpcerror_to_string));
     stream
       .take(1)
@@ -106,9 +159,9 @@ impl CommandRunner {
   }
 }
 
-impl super::CommandRunner for CommandRunner {
+impl CommandRunner {
   ///
-  /// Runs a command via a gRPC service implementing the Bazel Remote Execution API
+  /// Executes a command via a gRPC service implementing the Bazel Remote Execution API
   /// (https://docs.google.com/document/d/1AaGk7fOPByEvpAbqeXIyE8HX_A3_axxNnvroblTZ_6s/edit).
   ///
   /// If the CommandRunner has a Store, files will be uploaded to the remote CAS as needed.
@@ -124,38 +177,65 @@ impl super::CommandRunner for CommandRunner {
   /// Loops until the server gives a response, either successful or error. Does not have any
   /// timeout: polls in a tight loop.
   ///
+  /// Each execution is assigned a unique id, which is logged and sent to the server as the
+  /// `action_id` of the RequestMetadata for its requests, so that it may be correlated with
+  /// server-side logs.
+  ///
   /// TODO: Request jdk_home be created if set.
   ///
-  fn run(&self, req: ExecuteProcessRequest) -> BoxFuture<FallibleExecuteProcessResult, String> {
+  fn execute(&self, req: ExecuteProcessRequest) -> BoxFuture<FallibleExecuteProcessResult, String> {
     let operations_client = self.operations_client.clone();
 
     let store = self.store.clone();
-    let execute_request_result = make_execute_request(
-      &req,
-      &self.instance_name,
-      &self.cache_key_gen_version,
-      self.platform_properties.clone(),
-    );
+    if let Err(e) = validate_platform_properties(&req, &self.allowed_platform_property_keys) {
+      return future::err(e).to_boxed();
+    }
+    let execute_request_result = make_execute_request(&req, &self.metadata);
+    // Without permission to write to the ActionCache, the Action is marked uncacheable, so that the
+    // server will not cache its result.
+    let execute_request_result = if self.action_cache_writes {
+      execute_request_result
+    } else {
+      execute_request_result.and_then(|(mut action, command, mut execute_request)| {
+        make_uncacheable(&mut action, &mut execute_request)?;
+        Ok((action, command, execute_request))
+      })
+    };
 
+    let has_secret_env = !req.secret_env.is_empty();
     let ExecuteProcessRequest {
       description,
       timeout,
       input_files,
+      output_limit,
       ..
     } = req;
 
     let description2 = description.clone();
+    let execution_id = Uuid::new_v4().to_string();
+    let execution_id2 = execution_id.clone();
+    let execution_id3 = execution_id.clone();
 
     match execute_request_result {
-      Ok((action, command, execute_request)) => {
+      Ok((action, command, mut execute_request)) => {
+        metrics::increment_counter(Metric::RemoteExecutionRequests, 1);
+        // NB: The cache key is unaffected, so that a fresh result will still be written to the
+        // ActionCache.
+        let skip_cache_lookup = execute_request.get_skip_cache_lookup() || self.skip_cache_lookup;
+        execute_request.set_skip_cache_lookup(skip_cache_lookup);
+        debug!(
+          "Starting remote execution {} of {}",
+          execution_id, description
+        );
+        let total_start_time = Instant::now();
         let command_runner = self.clone();
         let command_runner2 = self.clone();
         let command_runner3 = self.clone();
         let execute_request = Arc::new(execute_request);
-        let execute_request2 = execute_request.clone();
         let futures_timer_thread = self.futures_timer_thread.clone();
 
         let store2 = store.clone();
+        let store3 = store.clone();
         let mut history = ExecutionHistory::default();
 
         self
@@ -166,24 +246,33 @@ impl super::CommandRunner for CommandRunner {
           })
           .and_then(move |summary| {
             history.current_attempt += summary;
-            trace!(
-              "Executing remotely request: {:?} (command: {:?})",
-              execute_request,
-              command
-            );
+            if has_secret_env {
+              trace!(
+                "Executing remotely request {}: {:?} (command redacted: it has secret env)",
+                execution_id2,
+                execute_request
+              );
+            } else {
+              trace!(
+                "Executing remotely request {}: {:?} (command: {:?})",
+                execution_id2,
+                execute_request,
+                command
+              );
+            }
             command_runner
-              .oneshot_execute(&execute_request)
-              .join(future::ok(history))
+              .oneshot_execute(&execute_request, &execution_id2)
+              .join(future::ok((history, execute_request)))
           })
-          .and_then(move |(operation, history)| {
+          .and_then(move |(operation, (history, execute_request))| {
             let start_time = Instant::now();
 
             future::loop_fn(
-              (history, operation, 0),
-              move |(mut history, operation, iter_num)| {
+              (history, execute_request, operation, 0),
+              move |(mut history, execute_request, operation, iter_num)| {
                 let description = description.clone();
+                let execution_id = execution_id.clone();
 
-                let execute_request2 = execute_request2.clone();
                 let store = store.clone();
                 let operations_client = operations_client.clone();
                 let command_runner2 = command_runner2.clone();
@@ -199,8 +288,23 @@ impl super::CommandRunner for CommandRunner {
                         current_attempt,
                       } = history;
 
+                      // Each attempt which reported missing digests has been recorded. A server
+                      // which continues to report them (despite our having uploaded them) is
+                      // unlikely to stop.
+                      if attempts.len() >= CommandRunner::MAX_MISSING_DIGEST_RETRIES {
+                        return future::err(format!(
+                          "Remote execution {} reported missing digests after they had been \
+                           uploaded {} times: {:?}",
+                          execution_id,
+                          attempts.len(),
+                          missing_digests,
+                        ))
+                        .to_boxed();
+                      }
+
                       trace!(
-                        "Server reported missing digests ({:?}); trying to upload: {:?}",
+                        "Server reported missing digests for {} ({:?}); trying to upload: {:?}",
+                        execution_id,
                         current_attempt,
                         missing_digests,
                       );
@@ -211,18 +315,58 @@ impl super::CommandRunner for CommandRunner {
                         current_attempt: ExecutionStats::default(),
                       };
 
-                      let execute_request = execute_request2.clone();
+                      // NB: The server may have garbage collected the digests since it last
+                      // reported having them, so we upload exactly the digests that it reports
+                      // missing, rather than asking it again which it is missing.
                       store
-                        .ensure_remote_has_recursive(missing_digests)
+                        .upload_to_remote(missing_digests)
                         .and_then(move |summary| {
                           let mut history = history;
                           history.current_attempt += summary;
                           command_runner2
-                            .oneshot_execute(&execute_request)
-                            .join(future::ok(history))
+                            .oneshot_execute(&execute_request, &execution_id)
+                            .join(future::ok((history, execute_request)))
                         })
                         // Reset `iter_num` on `MissingDigests`
-                        .map(|(operation, history)| future::Loop::Continue((history, operation, 0)))
+                        .map(|(operation, (history, execute_request))| {
+                          future::Loop::Continue((history, execute_request, operation, 0))
+                        })
+                        .to_boxed()
+                    }
+                    ExecutionError::MissingCachedOutputs(missing_digests) => {
+                      // The server should not have served a cached result when asked to skip the
+                      // cache, so there is nothing more to try.
+                      if execute_request.get_skip_cache_lookup() {
+                        return future::err(format!(
+                          "Remote execution {} returned a cached result with missing outputs, \
+                           despite skipping the cache: {:?}",
+                          execution_id, missing_digests,
+                        ))
+                        .to_boxed();
+                      }
+                      debug!(
+                        "Cached result for {} has missing outputs ({:?}); executing it instead",
+                        execution_id, missing_digests,
+                      );
+
+                      let ExecutionHistory {
+                        mut attempts,
+                        current_attempt,
+                      } = history;
+                      attempts.push(current_attempt);
+                      let history = ExecutionHistory {
+                        attempts,
+                        current_attempt: ExecutionStats::default(),
+                      };
+
+                      let mut execute_request = (*execute_request).clone();
+                      execute_request.set_skip_cache_lookup(true);
+                      let execute_request = Arc::new(execute_request);
+                      command_runner2
+                        .oneshot_execute(&execute_request, &execution_id)
+                        .map(move |operation| {
+                          future::Loop::Continue((history, execute_request, operation, 0))
+                        })
                         .to_boxed()
                     }
                     ExecutionError::NotFinished(operation_name) => {
@@ -239,10 +383,24 @@ impl super::CommandRunner for CommandRunner {
                       let elapsed = start_time.elapsed();
 
                       if elapsed > timeout {
-                        future::err(format!(
-                          "Exceeded time out of {:?} with {:?} for operation {}, {}",
-                          timeout, elapsed, operation_name, description
-                        ))
+                        debug!(
+                          "Exceeded time out of {:?} with {:?} for operation {} (execution {}), {}",
+                          timeout, elapsed, operation_name, execution_id, description
+                        );
+                        let ExecutionHistory {
+                          mut attempts,
+                          current_attempt,
+                        } = history;
+                        attempts.push(current_attempt);
+                        future::ok(future::Loop::Break(FallibleExecuteProcessResult {
+                          stdout: Bytes::new(),
+                          stderr: Bytes::new(),
+                          exit_code: TIMED_OUT_EXIT_CODE,
+                          output_directory: fs::EMPTY_DIGEST,
+                          execution_attempts: attempts,
+                          runner: command_runner2.name.clone(),
+                          timed_out: Some(ProcessTimedOut { timeout, elapsed }),
+                        }))
                         .to_boxed()
                       } else {
                         // maybe the delay here should be the min of remaining time and the backoff period
@@ -257,9 +415,11 @@ impl super::CommandRunner for CommandRunner {
                           )
                         })
                         .and_then(move |_| {
+                          let call_option = try_future!(command_runner3.call_option(&execution_id))
+                            .timeout(command_runner3.timeouts.execute_stream);
                           future::done(
                             operations_client
-                              .get_operation_opt(&operation_request, command_runner3.call_option())
+                              .get_operation_opt(&operation_request, call_option)
                               .or_else(move |err| {
                                 rpcerror_recover_cancelled(operation_request.take_name(), err)
                               })
@@ -267,7 +427,12 @@ impl super::CommandRunner for CommandRunner {
                               .map_err(rpcerror_to_string),
                           )
                           .map(move |operation| {
-                            future::Loop::Continue((history, operation, iter_num + 1))
+                            future::Loop::Continue((
+                              history,
+                              execute_request,
+                              operation,
+                              iter_num + 1,
+                            ))
                           })
                           .to_boxed()
                         })
@@ -280,43 +445,239 @@ impl super::CommandRunner for CommandRunner {
             )
           })
           .map(move |resp| {
+            metrics::record_duration(
+              ObservationMetric::RemoteExecutionTimeMicros,
+              total_start_time.elapsed(),
+            );
             let mut attempts = String::new();
             for (i, attempt) in resp.execution_attempts.iter().enumerate() {
               attempts += &format!("\nAttempt {}: {:?}", i, attempt);
             }
             debug!(
-              "Finished remote exceution of {} after {} attempts: Stats: {}",
+              "Finished remote execution {} of {} after {} attempts (cache hit: {}): Stats: {}",
+              execution_id3,
               description2,
               resp.execution_attempts.len(),
+              resp.was_cache_hit(),
               attempts
             );
             resp
           })
+          // The complete outputs have already been fetched, but the limit still protects consumers
+          // of the result.
+          .and_then(move |resp| super::limit_output(&store3, output_limit, resp))
           .to_boxed()
       }
       Err(err) => future::err(err).to_boxed(),
     }
   }
+
+  ///
+  /// Looks up the result of the given (cacheable) request in the ActionCache, without executing
+  /// it. Returns None if there is no usable cached result.
+  ///
+  fn lookup_action_result(
+    &self,
+    execute_request: &bazel_protos::remote_execution::ExecuteRequest,
+  ) -> BoxFuture<Option<FallibleExecuteProcessResult>, String> {
+    let mut request = bazel_protos::remote_execution::GetActionResultRequest::new();
+    request.set_instance_name(execute_request.get_instance_name().to_owned());
+    request.set_action_digest(execute_request.get_action_digest().clone());
+    let execution_id = Uuid::new_v4().to_string();
+    let call_option =
+      try_future!(self.call_option(&execution_id)).timeout(self.timeouts.action_cache);
+
+    let command_runner = self.clone();
+    future::done(
+      self
+        .action_cache_client
+        .get_action_result_opt(&request, call_option),
+    )
+    .then(move |result| match result {
+      Ok(action_result) => {
+        // Present the cached result as a completed Operation, so that it is handled exactly as
+        // a cache hit during execution would be.
+        let mut execute_response = bazel_protos::remote_execution::ExecuteResponse::new();
+        execute_response.set_cached_result(true);
+        execute_response.set_result(action_result);
+        let mut response = protobuf::well_known_types::Any::new();
+        response.set_type_url(format!(
+          "type.googleapis.com/{}",
+          execute_response.descriptor().full_name()
+        ));
+        response.set_value(try_future!(execute_response
+          .write_to_bytes()
+          .map_err(|e| format!("Error serializing ExecuteResponse: {:?}", e))));
+        let mut operation = bazel_protos::operations::Operation::new();
+        operation.set_name(execution_id.clone());
+        operation.set_done(true);
+        operation.set_response(response);
+
+        command_runner
+          .extract_execute_response(
+            OperationOrStatus::Operation(operation),
+            &mut ExecutionHistory::default(),
+          )
+          .then(move |result| match result {
+            Ok(result) => Ok(Some(result)),
+            Err(e) => {
+              // For example, the outputs of the cached result may have been evicted from the CAS.
+              warn!(
+                "Ignoring unusable cached result for execution {}: {:?}",
+                execution_id, e
+              );
+              Ok(None)
+            }
+          })
+          .to_boxed()
+      }
+      Err(grpcio::Error::RpcFailure(ref status))
+        if status.status == grpcio::RpcStatusCode::NotFound =>
+      {
+        future::ok(None).to_boxed()
+      }
+      Err(e) => {
+        warn!(
+          "Failed to look up cached result for execution {}: {}",
+          execution_id,
+          rpcerror_to_string(e)
+        );
+        future::ok(None).to_boxed()
+      }
+    })
+    .to_boxed()
+  }
+}
+
+impl super::CommandRunner for CommandRunner {
+  ///
+  /// Runs a command remotely. If the runner may not write to the ActionCache, a cached result is
+  /// looked up directly, and the command is only executed (uncacheably) if there is none.
+  ///
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    if self.offline {
+      return future::err(fs::offline_error(&format!(
+        "execute {:?} remotely",
+        req.description
+      )))
+      .to_boxed();
+    }
+    // Remote execution omits output directories which the process did not create, so they are
+    // added (when requested) after the fact, which also applies to cached results.
+    let store = self.store.clone();
+    let empty_output_directories = req.empty_output_directories();
+    // Inputs are uploaded within the scope of the Session which requested the process, and secret
+    // env is salted with its salt.
+    let command_runner = CommandRunner {
+      metadata: context.metadata(&self.metadata),
+      store: self.store.clone().with_upload_scope(context.uploads),
+      ..self.clone()
+    };
+    command_runner
+      .lookup_or_execute(req)
+      .and_then(move |result| result.with_empty_directories(store, &empty_output_directories))
+      .to_boxed()
+  }
 }
 
 impl CommandRunner {
   const BACKOFF_INCR_WAIT_MILLIS: u64 = 500;
   const BACKOFF_MAX_WAIT_MILLIS: u64 = 5000;
+  // The number of times the missing digests reported by the server are uploaded before giving up.
+  const MAX_MISSING_DIGEST_RETRIES: usize = 3;
+
+  ///
+  /// Looks up the result of the given request in the ActionCache without executing it, and returns
+  /// None if there is no usable cached result (or if the request may not be cached).
+  ///
+  pub fn lookup(
+    &self,
+    req: &ExecuteProcessRequest,
+  ) -> BoxFuture<Option<FallibleExecuteProcessResult>, String> {
+    if !req.secret_env.is_empty() || req.side_effecting {
+      return future::ok(None).to_boxed();
+    }
+    if self.offline {
+      return future::err(fs::offline_error(&format!(
+        "look up the cached result of {:?}",
+        req.description
+      )))
+      .to_boxed();
+    }
+    let execute_request = match make_execute_request(req, &self.metadata) {
+      Ok((_, _, execute_request)) => execute_request,
+      Err(e) => return future::err(e).to_boxed(),
+    };
+    let store = self.store.clone();
+    let empty_output_directories = req.empty_output_directories();
+    self
+      .lookup_action_result(&execute_request)
+      .and_then(move |result| match result {
+        Some(result) => result
+          .with_empty_directories(store, &empty_output_directories)
+          .map(Some)
+          .to_boxed(),
+        None => future::ok(None).to_boxed(),
+      })
+      .to_boxed()
+  }
+
+  ///
+  /// Looks up a cached result for the request if the runner may not write to the ActionCache,
+  /// and otherwise (or if there is none) executes it.
+  ///
+  fn lookup_or_execute(
+    &self,
+    req: ExecuteProcessRequest,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    if self.action_cache_writes
+      || self.skip_cache_lookup
+      || !req.secret_env.is_empty()
+      || req.side_effecting
+    {
+      return self.execute(req);
+    }
+    let execute_request = match make_execute_request(&req, &self.metadata) {
+      Ok((_, _, execute_request)) => execute_request,
+      Err(e) => return future::err(e).to_boxed(),
+    };
+    let command_runner = self.clone();
+    self
+      .lookup_action_result(&execute_request)
+      .and_then(move |result| match result {
+        Some(result) => future::ok(result).to_boxed(),
+        None => command_runner.execute(req),
+      })
+      .to_boxed()
+  }
 
   pub fn new(
     address: &str,
     cache_key_gen_version: Option<String>,
     instance_name: Option<String>,
     root_ca_certs: Option<Vec<u8>>,
+    proxies: &ProxyConfig,
     oauth_bearer_token: Option<String>,
     platform_properties: BTreeMap<String, String>,
+    allowed_platform_property_keys: BTreeSet<String>,
+    skip_cache_lookup: bool,
+    action_cache_writes: bool,
+    verify_cache_hits: bool,
     thread_count: usize,
     store: Store,
     futures_timer_thread: resettable::Resettable<futures_timer::HelperThread>,
   ) -> CommandRunner {
     let env = Arc::new(grpcio::Environment::new(thread_count));
     let channel = {
-      let builder = grpcio::ChannelBuilder::new(env.clone());
+      let builder = proxies.configure_channel(
+        grpcio::ChannelBuilder::new(env.clone()),
+        address,
+        root_ca_certs.is_some(),
+      );
       if let Some(root_ca_certs) = root_ca_certs {
         let creds = grpcio::ChannelCredentialsBuilder::new()
           .root_cert(root_ca_certs)
@@ -332,31 +693,78 @@ impl CommandRunner {
     let operations_client = Arc::new(bazel_protos::operations_grpc::OperationsClient::new(
       channel.clone(),
     ));
+    let action_cache_client = Arc::new(
+      bazel_protos::remote_execution_grpc::ActionCacheClient::new(channel.clone()),
+    );
 
     CommandRunner {
-      cache_key_gen_version,
-      instance_name,
+      name: format!("remote({})", address),
+      metadata: ExecuteProcessRequestMetadata {
+        instance_name,
+        cache_key_gen_version,
+        platform_properties,
+        ..ExecuteProcessRequestMetadata::default()
+      },
       authorization_header: oauth_bearer_token.map(|t| format!("Bearer {}", t)),
-      platform_properties,
+      allowed_platform_property_keys,
+      skip_cache_lookup,
+      action_cache_writes,
+      verify_cache_hits,
+      offline: false,
+      timeouts: ExecutionTimeouts::default(),
       channel,
       env,
       execution_client,
       operations_client,
+      action_cache_client,
       store,
       futures_timer_thread,
     }
   }
 
-  fn call_option(&self) -> grpcio::CallOption {
-    let mut call_option = grpcio::CallOption::default();
+  ///
+  /// Applies the given deadlines to RPCs made to the remote execution server.
+  ///
+  pub fn with_timeouts(self, timeouts: ExecutionTimeouts) -> CommandRunner {
+    CommandRunner { timeouts, ..self }
+  }
+
+  ///
+  /// Makes every request to this runner (including ActionCache lookups) fail immediately with an
+  /// offline mode error, rather than being sent to the server.
+  ///
+  pub fn into_offline(self) -> CommandRunner {
+    CommandRunner {
+      offline: true,
+      store: self.store.into_offline(),
+      ..self
+    }
+  }
+
+  ///
+  /// Creates a CallOption for requests made on behalf of the given execution, which attaches a
+  /// RequestMetadata (as described by the Remote Execution API) identifying it.
+  ///
+  fn call_option(&self, execution_id: &str) -> Result<grpcio::CallOption, String> {
+    let mut request_metadata = bazel_protos::remote_execution::RequestMetadata::new();
+    request_metadata
+      .mut_tool_details()
+      .set_tool_name("pants".to_owned());
+    request_metadata.set_action_id(execution_id.to_owned());
+    let request_metadata_bytes = request_metadata
+      .write_to_bytes()
+      .map_err(|e| format!("Error serializing RequestMetadata: {:?}", e))?;
+
+    let mut builder = grpcio::MetadataBuilder::with_capacity(2);
+    builder
+      .add_bytes(REQUEST_METADATA_HEADER_NAME, &request_metadata_bytes)
+      .unwrap();
     if let Some(ref authorization_header) = self.authorization_header {
-      let mut builder = grpcio::MetadataBuilder::with_capacity(1);
       builder
         .add_str("authorization", &authorization_header)
         .unwrap();
-      call_option = call_option.headers(builder.build());
     }
-    call_option
+    Ok(grpcio::CallOption::default().headers(builder.build()))
   }
 
   fn store_proto_locally<P: protobuf::Message>(
@@ -403,53 +811,50 @@ impl CommandRunner {
         trace!("Got (nested) execute response: {:?}", execute_response);
 
         if execute_response.get_result().has_execution_metadata() {
-          let metadata = execute_response.get_result().get_execution_metadata();
-          let enqueued = timespec_from(metadata.get_queued_timestamp());
-          let worker_start = timespec_from(metadata.get_worker_start_timestamp());
-          let input_fetch_start = timespec_from(metadata.get_input_fetch_start_timestamp());
-          let input_fetch_completed = timespec_from(metadata.get_input_fetch_completed_timestamp());
-          let execution_start = timespec_from(metadata.get_execution_start_timestamp());
-          let execution_completed = timespec_from(metadata.get_execution_completed_timestamp());
-          let output_upload_start = timespec_from(metadata.get_output_upload_start_timestamp());
-          let output_upload_completed =
-            timespec_from(metadata.get_output_upload_completed_timestamp());
-
-          match (worker_start - enqueued).to_std() {
-            Ok(duration) => attempts.current_attempt.remote_queue = Some(duration),
-            Err(err) => warn!("Got negative remote queue time: {}", err),
-          }
-          match (input_fetch_completed - input_fetch_start).to_std() {
-            Ok(duration) => attempts.current_attempt.remote_input_fetch = Some(duration),
-            Err(err) => warn!("Got negative remote input fetch time: {}", err),
-          }
-          match (execution_completed - execution_start).to_std() {
-            Ok(duration) => attempts.current_attempt.remote_execution = Some(duration),
-            Err(err) => warn!("Got negative remote execution time: {}", err),
-          }
-          match (output_upload_completed - output_upload_start).to_std() {
-            Ok(duration) => attempts.current_attempt.remote_output_store = Some(duration),
-            Err(err) => warn!("Got negative remote output store time: {}", err),
-          }
-          attempts.current_attempt.was_cache_hit = execute_response.cached_result;
+          record_execution_metadata(
+            execute_response.get_result().get_execution_metadata(),
+            &mut attempts.current_attempt,
+          );
         }
-
-        let mut execution_attempts = std::mem::replace(&mut attempts.attempts, vec![]);
-        execution_attempts.push(attempts.current_attempt);
+        attempts.current_attempt.was_cache_hit = execute_response.cached_result;
 
         let status = execute_response.take_status();
         if grpcio::RpcStatusCode::from(status.get_code()) == grpcio::RpcStatusCode::Ok {
-          return self
-            .extract_stdout(&execute_response)
-            .join(self.extract_stderr(&execute_response))
-            .join(self.extract_output_files(&execute_response))
-            .and_then(move |((stdout, stderr), output_directory)| {
-              Ok(FallibleExecuteProcessResult {
-                stdout: stdout,
-                stderr: stderr,
-                exit_code: execute_response.get_result().get_exit_code(),
-                output_directory: output_directory,
-                execution_attempts: execution_attempts,
-              })
+          // NB: The history is copied rather than consumed, since the outputs of a cached result
+          // may turn out to be missing, in which case the caller records the attempt and retries.
+          let mut execution_attempts = attempts.attempts.clone();
+          execution_attempts.push(attempts.current_attempt);
+          metrics::increment_counter(
+            if execute_response.cached_result {
+              Metric::RemoteExecutionCacheHits
+            } else {
+              Metric::RemoteExecutionCacheMisses
+            },
+            1,
+          );
+          let verified = if execute_response.cached_result && self.verify_cache_hits {
+            self.verify_cached_outputs(execute_response.get_result())
+          } else {
+            future::ok(()).to_boxed()
+          };
+          let command_runner = self.clone();
+          return verified
+            .and_then(move |()| {
+              command_runner
+                .extract_stdout(&execute_response)
+                .join(command_runner.extract_stderr(&execute_response))
+                .join(command_runner.extract_output_files(&execute_response))
+                .and_then(move |((stdout, stderr), output_directory)| {
+                  Ok(FallibleExecuteProcessResult {
+                    stdout: stdout,
+                    stderr: stderr,
+                    exit_code: execute_response.get_result().get_exit_code(),
+                    output_directory: output_directory,
+                    execution_attempts: execution_attempts,
+                    runner: command_runner.name.clone(),
+                    timed_out: None,
+                  })
+                })
             })
             .to_boxed();
         }
@@ -540,6 +945,56 @@ impl CommandRunner {
     .to_boxed()
   }
 
+  ///
+  /// Checks that the CAS has all of the outputs referenced by a cached ActionResult: the remote may
+  /// have garbage collected some of them since the result was cached, in which case the result is
+  /// not usable.
+  ///
+  /// NB: The contents of output directories are not checked, since that would require fetching
+  /// their Trees.
+  ///
+  fn verify_cached_outputs(
+    &self,
+    action_result: &bazel_protos::remote_execution::ActionResult,
+  ) -> BoxFuture<(), ExecutionError> {
+    let mut digests: Vec<Result<Digest, String>> = vec![];
+    if action_result.has_stdout_digest() {
+      digests.push(action_result.get_stdout_digest().into());
+    }
+    if action_result.has_stderr_digest() {
+      digests.push(action_result.get_stderr_digest().into());
+    }
+    for output_file in action_result.get_output_files() {
+      digests.push(output_file.get_digest().into());
+    }
+    for output_directory in action_result.get_output_directories() {
+      digests.push(output_directory.get_tree_digest().into());
+    }
+    let digests = try_future!(digests
+      .into_iter()
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|err| ExecutionError::Fatal(format!("Error extracting output digests: {}", err))));
+    if digests.is_empty() {
+      return future::ok(()).to_boxed();
+    }
+
+    self
+      .store
+      .list_missing_remote_digests(digests)
+      .map_err(|err| ExecutionError::Fatal(format!("Error verifying cached outputs: {}", err)))
+      .and_then(|missing_digests| {
+        if missing_digests.is_empty() {
+          Ok(())
+        } else {
+          metrics::increment_counter(Metric::RemoteExecutionStaleCacheHits, 1);
+          Err(ExecutionError::MissingCachedOutputs(
+            missing_digests.into_iter().collect(),
+          ))
+        }
+      })
+      .to_boxed()
+  }
+
   fn extract_stdout(
     &self,
     execute_response: &bazel_protos::remote_execution::ExecuteResponse,
@@ -626,118 +1081,118 @@ impl CommandRunner {
     &self,
     execute_response: &bazel_protos::remote_execution::ExecuteResponse,
   ) -> BoxFuture<Digest, ExecutionError> {
-    // Get Digests of output Directories.
-    // Then we'll make a Directory for the output files, and merge them.
-    let mut directory_digests =
-      Vec::with_capacity(execute_response.get_result().get_output_directories().len() + 1);
-    // TODO: Maybe take rather than clone
-    let output_directories = execute_response
-      .get_result()
-      .get_output_directories()
-      .to_owned();
-    for dir in output_directories {
-      let digest_result: Result<Digest, String> = dir.get_tree_digest().into();
-      let mut digest = future::done(digest_result).to_boxed();
-      for component in dir.get_path().rsplit('/') {
-        let component = component.to_owned();
-        let store = self.store.clone();
-        digest = digest
-          .and_then(move |digest| {
-            let mut directory = bazel_protos::remote_execution::Directory::new();
-            directory.mut_directories().push({
-              let mut node = bazel_protos::remote_execution::DirectoryNode::new();
-              node.set_name(component);
-              node.set_digest((&digest).into());
-              node
-            });
-            store.record_directory(&directory, true)
-          })
-          .to_boxed();
-      }
-      directory_digests.push(digest.map_err(|err| {
-        ExecutionError::Fatal(format!("Error saving remote output directory: {}", err))
-      }));
+    extract_output_files(self.store.clone(), execute_response.get_result())
+      .map_err(ExecutionError::Fatal)
+      .to_boxed()
+  }
+}
+
+///
+/// Stores a Directory containing the output files and directories of the given ActionResult, and
+/// returns its Digest. The contents of the outputs are not fetched.
+///
+pub fn extract_output_files(
+  store: Store,
+  action_result: &bazel_protos::remote_execution::ActionResult,
+) -> BoxFuture<Digest, String> {
+  // Get Digests of output Directories.
+  // Then we'll make a Directory for the output files, and merge them.
+  let mut directory_digests = Vec::with_capacity(action_result.get_output_directories().len() + 1);
+  // TODO: Maybe take rather than clone
+  let output_directories = action_result.get_output_directories().to_owned();
+  for dir in output_directories {
+    let digest_result: Result<Digest, String> = dir.get_tree_digest().into();
+    let mut digest = future::done(digest_result).to_boxed();
+    for component in dir.get_path().rsplit('/') {
+      let component = component.to_owned();
+      let store = store.clone();
+      digest = digest
+        .and_then(move |digest| {
+          let mut directory = bazel_protos::remote_execution::Directory::new();
+          directory.mut_directories().push({
+            let mut node = bazel_protos::remote_execution::DirectoryNode::new();
+            node.set_name(component);
+            node.set_digest((&digest).into());
+            node
+          });
+          store.record_directory(&directory, true)
+        })
+        .to_boxed();
     }
+    directory_digests
+      .push(digest.map_err(|err| format!("Error saving remote output directory: {}", err)));
+  }
 
-    // Make a directory for the files
-    let mut path_map = HashMap::new();
-    let path_stats_result: Result<Vec<PathStat>, String> = execute_response
-      .get_result()
-      .get_output_files()
-      .iter()
-      .map(|output_file| {
-        let output_file_path_buf = PathBuf::from(output_file.get_path());
-        let digest: Result<Digest, String> = output_file.get_digest().into();
-        path_map.insert(output_file_path_buf.clone(), digest?);
-        Ok(PathStat::file(
-          output_file_path_buf.clone(),
-          File {
-            path: output_file_path_buf,
-            is_executable: output_file.get_is_executable(),
-          },
-        ))
-      })
-      .collect();
+  // Make a directory for the files
+  let mut path_map = HashMap::new();
+  let path_stats_result: Result<Vec<PathStat>, String> = action_result
+    .get_output_files()
+    .iter()
+    .map(|output_file| {
+      let output_file_path_buf = PathBuf::from(output_file.get_path());
+      let digest: Result<Digest, String> = output_file.get_digest().into();
+      path_map.insert(output_file_path_buf.clone(), digest?);
+      Ok(PathStat::file(
+        output_file_path_buf.clone(),
+        File {
+          path: output_file_path_buf,
+          is_executable: output_file.get_is_executable(),
+        },
+      ))
+    })
+    .collect();
 
-    let path_stats = try_future!(path_stats_result.map_err(ExecutionError::Fatal));
+  let path_stats = try_future!(path_stats_result);
 
-    #[derive(Clone)]
-    struct StoreOneOffRemoteDigest {
-      map_of_paths_to_digests: HashMap<PathBuf, Digest>,
-    }
+  #[derive(Clone)]
+  struct StoreOneOffRemoteDigest {
+    map_of_paths_to_digests: HashMap<PathBuf, Digest>,
+  }
 
-    impl StoreOneOffRemoteDigest {
-      fn new(map: HashMap<PathBuf, Digest>) -> StoreOneOffRemoteDigest {
-        StoreOneOffRemoteDigest {
-          map_of_paths_to_digests: map,
-        }
+  impl StoreOneOffRemoteDigest {
+    fn new(map: HashMap<PathBuf, Digest>) -> StoreOneOffRemoteDigest {
+      StoreOneOffRemoteDigest {
+        map_of_paths_to_digests: map,
       }
     }
+  }
 
-    impl fs::StoreFileByDigest<String> for StoreOneOffRemoteDigest {
-      fn store_by_digest(&self, file: File) -> BoxFuture<Digest, String> {
-        match self.map_of_paths_to_digests.get(&file.path) {
-          Some(digest) => future::ok(*digest),
-          None => future::err(format!(
-            "Didn't know digest for path in remote execution response: {:?}",
-            file.path
-          )),
-        }
-        .to_boxed()
+  impl fs::StoreFileByDigest<String> for StoreOneOffRemoteDigest {
+    fn store_by_digest(&self, file: File) -> BoxFuture<Digest, String> {
+      match self.map_of_paths_to_digests.get(&file.path) {
+        Some(digest) => future::ok(*digest),
+        None => future::err(format!(
+          "Didn't know digest for path in remote execution response: {:?}",
+          file.path
+        )),
       }
+      .to_boxed()
     }
+  }
 
-    let store = self.store.clone();
-    fs::Snapshot::digest_from_path_stats(
-      self.store.clone(),
-      &StoreOneOffRemoteDigest::new(path_map),
-      &path_stats,
+  fs::Snapshot::digest_from_path_stats(
+    store.clone(),
+    &StoreOneOffRemoteDigest::new(path_map),
+    &path_stats,
+  )
+  .map_err(move |error| {
+    format!(
+      "Error when storing the output file directory info in the remote CAS: {:?}",
+      error
     )
-    .map_err(move |error| {
-      ExecutionError::Fatal(format!(
-        "Error when storing the output file directory info in the remote CAS: {:?}",
-        error
-      ))
-    })
-    .join(future::join_all(directory_digests))
-    .and_then(|(files_digest, mut directory_digests)| {
-      directory_digests.push(files_digest);
-      fs::Snapshot::merge_directories(store, directory_digests).map_err(|err| {
-        ExecutionError::Fatal(format!(
-          "Error when merging output files and directories: {}",
-          err
-        ))
-      })
-    })
-    .to_boxed()
-  }
+  })
+  .join(future::join_all(directory_digests))
+  .and_then(|(files_digest, mut directory_digests)| {
+    directory_digests.push(files_digest);
+    fs::Snapshot::merge_directories(store, directory_digests)
+      .map_err(|err| format!("Error when merging output files and directories: {}", err))
+  })
+  .to_boxed()
 }
 
-fn make_execute_request(
+pub(crate) fn make_execute_request(
   req: &ExecuteProcessRequest,
-  instance_name: &Option<String>,
-  cache_key_gen_version: &Option<String>,
-  mut platform_properties: BTreeMap<String, String>,
+  metadata: &ExecuteProcessRequestMetadata,
 ) -> Result<
   (
     bazel_protos::remote_execution::Action,
@@ -746,13 +1201,23 @@ fn make_execute_request(
   ),
   String,
 > {
+  if req.stdin_digest.is_some() {
+    return Err(format!(
+      "Cannot execute {:?} remotely: remote execution does not support stdin.",
+      req.description
+    ));
+  }
   let mut command = bazel_protos::remote_execution::Command::new();
   command.set_arguments(protobuf::RepeatedField::from_vec(req.argv.clone()));
   for (ref name, ref value) in &req.env {
-    if name.as_str() == CACHE_KEY_GEN_VERSION_ENV_VAR_NAME {
+    if name.as_str() == CACHE_KEY_GEN_VERSION_ENV_VAR_NAME
+      || name.as_str() == SECRET_ENV_SALT_ENV_VAR_NAME
+      || (name.starts_with(CACHE_KEY_VERSION_ENV_VAR_PREFIX)
+        && name.ends_with(CACHE_KEY_VERSION_ENV_VAR_SUFFIX))
+    {
       return Err(format!(
         "Cannot set env var with name {} as that is reserved for internal use by pants",
-        CACHE_KEY_GEN_VERSION_ENV_VAR_NAME
+        name
       ));
     }
     let mut env = bazel_protos::remote_execution::Command_EnvironmentVariable::new();
@@ -760,12 +1225,34 @@ fn make_execute_request(
     env.set_value(value.to_string());
     command.mut_environment_variables().push(env);
   }
-  if let Some(cache_key_gen_version) = cache_key_gen_version {
+  if let Some(ref cache_key_gen_version) = metadata.cache_key_gen_version {
     let mut env = bazel_protos::remote_execution::Command_EnvironmentVariable::new();
     env.set_name(CACHE_KEY_GEN_VERSION_ENV_VAR_NAME.to_string());
     env.set_value(cache_key_gen_version.to_string());
     command.mut_environment_variables().push(env);
   }
+  for (subsystem, version) in metadata.cache_key_versions.for_request(req) {
+    let mut env = bazel_protos::remote_execution::Command_EnvironmentVariable::new();
+    env.set_name(format!(
+      "{}{}{}",
+      CACHE_KEY_VERSION_ENV_VAR_PREFIX, subsystem, CACHE_KEY_VERSION_ENV_VAR_SUFFIX
+    ));
+    env.set_value(version.to_string());
+    command.mut_environment_variables().push(env);
+  }
+  if !req.secret_env.is_empty() {
+    // The values of secret env vars are necessarily part of the Command, so the Command (and thus
+    // the Action) digest is salted per-Session to prevent it from being correlated between
+    // Sessions.
+    let salt = metadata
+      .secret_env_salt
+      .clone()
+      .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let mut env = bazel_protos::remote_execution::Command_EnvironmentVariable::new();
+    env.set_name(SECRET_ENV_SALT_ENV_VAR_NAME.to_string());
+    env.set_value(salt);
+    command.mut_environment_variables().push(env);
+  }
   let mut output_files = req
     .output_files
     .iter()
@@ -790,6 +1277,7 @@ fn make_execute_request(
   output_directories.sort();
   command.set_output_directories(protobuf::RepeatedField::from_vec(output_directories));
 
+  let mut platform_properties = metadata.platform_properties.clone();
   if req.jdk_home.is_some() {
     // Ideally, the JDK would be brought along as part of the input directory, but we don't
     // currently have support for that. Scoot supports this property, and will symlink .jdk to a
@@ -799,6 +1287,13 @@ fn make_execute_request(
     // well-known path in the docker container you specify in which to run.
     platform_properties.insert("JDK_SYMLINK".to_owned(), ".jdk".to_owned());
   }
+  // Hints set on the request itself take precedence over the configured properties.
+  platform_properties.extend(
+    req
+      .platform_properties
+      .iter()
+      .map(|(name, value)| (name.clone(), value.clone())),
+  );
 
   for (name, value) in platform_properties {
     command.mut_platform().mut_properties().push({
@@ -814,14 +1309,99 @@ fn make_execute_request(
   action.set_input_root_digest((&req.input_files).into());
 
   let mut execute_request = bazel_protos::remote_execution::ExecuteRequest::new();
-  if let Some(instance_name) = instance_name {
+  if let Some(ref instance_name) = metadata.instance_name {
     execute_request.set_instance_name(instance_name.clone());
   }
+  if !req.secret_env.is_empty() || req.side_effecting {
+    // A result which was computed using secrets must never be shared via the ActionCache, and a
+    // side-effecting process must actually run each time it is requested.
+    action.set_do_not_cache(true);
+    execute_request.set_skip_cache_lookup(true);
+  }
   execute_request.set_action_digest((&digest(&action)?).into());
 
   Ok((action, command, execute_request))
 }
 
+///
+/// Marks the given Action as uncacheable (so that the server will not write its result to the
+/// ActionCache), and updates the ExecuteRequest with its new digest.
+///
+fn make_uncacheable(
+  action: &mut bazel_protos::remote_execution::Action,
+  execute_request: &mut bazel_protos::remote_execution::ExecuteRequest,
+) -> Result<(), String> {
+  action.set_do_not_cache(true);
+  execute_request.set_action_digest((&digest(action)?).into());
+  Ok(())
+}
+
+///
+/// Validates that a request only sets the platform properties (i.e. scheduling hints) which have
+/// been explicitly allowed, so that a typo does not silently route it to the wrong workers.
+///
+pub fn validate_platform_properties(
+  req: &ExecuteProcessRequest,
+  allowed_keys: &BTreeSet<String>,
+) -> Result<(), String> {
+  let disallowed = req
+    .platform_properties
+    .keys()
+    .filter(|key| !allowed_keys.contains(*key))
+    .cloned()
+    .collect::<Vec<_>>();
+  if disallowed.is_empty() {
+    Ok(())
+  } else {
+    Err(format!(
+      "Process '{}' set platform properties {:?} which are not allowed: allowed keys are {:?}. \
+       See --remote-execution-allowed-platform-property-keys.",
+      req.description, disallowed, allowed_keys
+    ))
+  }
+}
+
+///
+/// The Action and Command which would be sent to a remote execution server to execute a request,
+/// both as (hex encoded) serialized bytes, and in protobuf text format.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DryRun {
+  pub action_digest: Digest,
+  pub command_digest: Digest,
+  pub input_root_digest: Digest,
+  pub serialized_action: String,
+  pub serialized_command: String,
+  pub action: String,
+  pub command: String,
+}
+
+///
+/// Constructs the protos which would be sent to execute the given request remotely, without
+/// executing it.
+///
+pub fn dry_run(
+  req: &ExecuteProcessRequest,
+  metadata: &ExecuteProcessRequestMetadata,
+) -> Result<DryRun, String> {
+  let (action, command, _) = make_execute_request(req, metadata)?;
+  let serialize = |message: &dyn Message| {
+    message
+      .write_to_bytes()
+      .map(|bytes| hex::encode(&bytes))
+      .map_err(|e| format!("{:?}", e))
+  };
+  Ok(DryRun {
+    action_digest: digest(&action)?,
+    command_digest: digest(&command)?,
+    input_root_digest: req.input_files,
+    serialized_action: serialize(&action)?,
+    serialized_command: serialize(&command)?,
+    action: protobuf::text_format::print_to_string(&action),
+    command: protobuf::text_format::print_to_string(&command),
+  })
+}
+
 fn format_error(error: &bazel_protos::status::Status) -> String {
   let error_code_enum = bazel_protos::code::Code::from_i32(error.get_code());
   let error_code = match error_code_enum {
@@ -886,7 +1466,7 @@ fn rpcerror_to_string(error: grpcio::Error) -> String {
   }
 }
 
-fn digest(message: &dyn Message) -> Result<Digest, String> {
+pub(crate) fn digest(message: &dyn Message) -> Result<Digest, String> {
   let bytes = message.write_to_bytes().map_err(|e| format!("{:?}", e))?;
 
   let mut hasher = Sha256::default();
@@ -898,6 +1478,50 @@ fn digest(message: &dyn Message) -> Result<Digest, String> {
   ))
 }
 
+///
+/// Records the timings reported by the server for an execution, and the corresponding metrics.
+///
+fn record_execution_metadata(
+  metadata: &bazel_protos::remote_execution::ExecutedActionMetadata,
+  stats: &mut ExecutionStats,
+) {
+  let enqueued = timespec_from(metadata.get_queued_timestamp());
+  let worker_start = timespec_from(metadata.get_worker_start_timestamp());
+  let input_fetch_start = timespec_from(metadata.get_input_fetch_start_timestamp());
+  let input_fetch_completed = timespec_from(metadata.get_input_fetch_completed_timestamp());
+  let execution_start = timespec_from(metadata.get_execution_start_timestamp());
+  let execution_completed = timespec_from(metadata.get_execution_completed_timestamp());
+  let output_upload_start = timespec_from(metadata.get_output_upload_start_timestamp());
+  let output_upload_completed = timespec_from(metadata.get_output_upload_completed_timestamp());
+
+  match (worker_start - enqueued).to_std() {
+    Ok(duration) => {
+      metrics::record_duration(ObservationMetric::RemoteQueueTimeMicros, duration);
+      stats.remote_queue = Some(duration)
+    }
+    Err(err) => warn!("Got negative remote queue time: {}", err),
+  }
+  match (execution_start - worker_start).to_std() {
+    Ok(duration) => stats.remote_worker_setup = Some(duration),
+    Err(err) => warn!("Got negative remote worker setup time: {}", err),
+  }
+  match (input_fetch_completed - input_fetch_start).to_std() {
+    Ok(duration) => stats.remote_input_fetch = Some(duration),
+    Err(err) => warn!("Got negative remote input fetch time: {}", err),
+  }
+  match (execution_completed - execution_start).to_std() {
+    Ok(duration) => {
+      metrics::record_duration(ObservationMetric::RemoteWorkerExecutionTimeMicros, duration);
+      stats.remote_execution = Some(duration)
+    }
+    Err(err) => warn!("Got negative remote execution time: {}", err),
+  }
+  match (output_upload_completed - output_upload_start).to_std() {
+    Ok(duration) => stats.remote_output_store = Some(duration),
+    Err(err) => warn!("Got negative remote output store time: {}", err),
+  }
+}
+
 fn timespec_from(timestamp: &protobuf::well_known_types::Timestamp) -> time::Timespec {
   time::Timespec::new(timestamp.seconds, timestamp.nanos)
 }
@@ -916,10 +1540,11 @@ mod tests {
   use testutil::data::{TestData, TestDirectory};
   use testutil::{as_bytes, owned_string_vec};
 
-  use super::super::CommandRunner as CommandRunnerTrait;
+  use super::super::{CacheKeyVersions, CommandRunner as CommandRunnerTrait, Context};
   use super::{
-    CommandRunner, ExecuteProcessRequest, ExecutionError, ExecutionHistory,
-    FallibleExecuteProcessResult,
+    record_execution_metadata, CommandRunner, ExecuteProcessRequest, ExecuteProcessRequestMetadata,
+    ExecutionError, ExecutionHistory, ExecutionStats, ExecutionTimeouts,
+    FallibleExecuteProcessResult, TIMED_OUT_EXIT_CODE,
   };
   use mock::execution_server::MockOperation;
   use std::collections::{BTreeMap, BTreeSet};
@@ -960,7 +1585,7 @@ mod tests {
         .collect(),
       timeout: Duration::from_millis(1000),
       description: "some description".to_owned(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     };
 
     let mut want_command = bazel_protos::remote_execution::Command::new();
@@ -1008,7 +1633,7 @@ mod tests {
     );
 
     assert_eq!(
-      super::make_execute_request(&req, &None, &None, BTreeMap::new()),
+      super::make_execute_request(&req, &ExecuteProcessRequestMetadata::default()),
       Ok((want_action, want_command, want_execute_request))
     );
   }
@@ -1033,7 +1658,7 @@ mod tests {
         .collect(),
       timeout: Duration::from_millis(1000),
       description: "some description".to_owned(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     };
 
     let mut want_command = bazel_protos::remote_execution::Command::new();
@@ -1082,7 +1707,13 @@ mod tests {
     );
 
     assert_eq!(
-      super::make_execute_request(&req, &Some("dark-tower".to_owned()), &None, BTreeMap::new()),
+      super::make_execute_request(
+        &req,
+        &ExecuteProcessRequestMetadata {
+          instance_name: Some("dark-tower".to_owned()),
+          ..ExecuteProcessRequestMetadata::default()
+        }
+      ),
       Ok((want_action, want_command, want_execute_request))
     );
   }
@@ -1107,7 +1738,7 @@ mod tests {
         .collect(),
       timeout: Duration::from_millis(1000),
       description: "some description".to_owned(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     };
 
     let mut want_command = bazel_protos::remote_execution::Command::new();
@@ -1161,7 +1792,13 @@ mod tests {
     );
 
     assert_eq!(
-      super::make_execute_request(&req, &None, &Some("meep".to_owned()), BTreeMap::new()),
+      super::make_execute_request(
+        &req,
+        &ExecuteProcessRequestMetadata {
+          cache_key_gen_version: Some("meep".to_owned()),
+          ..ExecuteProcessRequestMetadata::default()
+        }
+      ),
       Ok((want_action, want_command, want_execute_request))
     );
   }
@@ -1171,13 +1808,11 @@ mod tests {
     let input_directory = TestDirectory::containing_roland();
     let req = ExecuteProcessRequest {
       argv: owned_string_vec(&["/bin/echo", "yo"]),
-      env: BTreeMap::new(),
       input_files: input_directory.digest(),
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "some description".to_owned(),
       jdk_home: Some(PathBuf::from("/tmp")),
+      ..ExecuteProcessRequest::default()
     };
 
     let mut want_command = bazel_protos::remote_execution::Command::new();
@@ -1216,23 +1851,48 @@ mod tests {
     );
 
     assert_eq!(
-      super::make_execute_request(&req, &None, &None, BTreeMap::new()),
+      super::make_execute_request(&req, &ExecuteProcessRequestMetadata::default()),
       Ok((want_action, want_command, want_execute_request))
     );
   }
 
+  #[test]
+  fn dry_run() {
+    let input_directory = TestDirectory::containing_roland();
+    let req = ExecuteProcessRequest {
+      argv: owned_string_vec(&["/bin/echo", "yo"]),
+      input_files: input_directory.digest(),
+      timeout: Duration::from_millis(1000),
+      description: "some description".to_owned(),
+      ..ExecuteProcessRequest::default()
+    };
+
+    let (action, command, execute_request) =
+      super::make_execute_request(&req, &ExecuteProcessRequestMetadata::default()).unwrap();
+    let dry_run = super::dry_run(&req, &ExecuteProcessRequestMetadata::default()).unwrap();
+
+    let action_digest: Result<Digest, String> = execute_request.get_action_digest().into();
+    assert_eq!(Ok(dry_run.action_digest), action_digest);
+    let command_digest: Result<Digest, String> = action.get_command_digest().into();
+    assert_eq!(Ok(dry_run.command_digest), command_digest);
+    assert_eq!(dry_run.input_root_digest, input_directory.digest());
+    assert_eq!(
+      dry_run.serialized_command,
+      hex::encode(&command.write_to_bytes().unwrap())
+    );
+    assert!(dry_run.command.contains("arguments: \"/bin/echo\""));
+  }
+
   #[test]
   fn make_execute_request_with_jdk_and_extra_platform_properties() {
     let input_directory = TestDirectory::containing_roland();
     let req = ExecuteProcessRequest {
       argv: owned_string_vec(&["/bin/echo", "yo"]),
-      env: BTreeMap::new(),
       input_files: input_directory.digest(),
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "some description".to_owned(),
       jdk_home: Some(PathBuf::from("/tmp")),
+      ..ExecuteProcessRequest::default()
     };
 
     let mut want_command = bazel_protos::remote_execution::Command::new();
@@ -1283,19 +1943,252 @@ mod tests {
     );
 
     assert_eq!(
-      super::make_execute_request(
-        &req,
-        &None,
-        &None,
-        vec![
-          ("FIRST".to_owned(), "foo".to_owned()),
-          ("last".to_owned(), "bar".to_owned())
-        ]
-        .into_iter()
-        .collect()
-      ),
-      Ok((want_action, want_command, want_execute_request))
+      super::make_execute_request(
+        &req,
+        &ExecuteProcessRequestMetadata {
+          platform_properties: vec![
+            ("FIRST".to_owned(), "foo".to_owned()),
+            ("last".to_owned(), "bar".to_owned())
+          ]
+          .into_iter()
+          .collect(),
+          ..ExecuteProcessRequestMetadata::default()
+        }
+      ),
+      Ok((want_action, want_command, want_execute_request))
+    );
+  }
+
+  #[test]
+  fn make_execute_request_cache_key_versions_only_affect_relevant_requests() {
+    let action_digest = |req: &ExecuteProcessRequest, cache_key_versions: CacheKeyVersions| {
+      let metadata = ExecuteProcessRequestMetadata {
+        cache_key_versions,
+        ..ExecuteProcessRequestMetadata::default()
+      };
+      super::make_execute_request(req, &metadata)
+        .unwrap()
+        .2
+        .get_action_digest()
+        .clone()
+    };
+    let plain = echo_foo_request();
+    let mut with_outputs = echo_foo_request();
+    with_outputs.output_files.insert(PathBuf::from("foo"));
+    let mut with_jdk = echo_foo_request();
+    with_jdk.jdk_home = Some(PathBuf::from("/tmp"));
+
+    let unversioned = CacheKeyVersions {
+      process_execution: 0,
+      snapshot_format: 0,
+      nailgun: 0,
+    };
+    // A version of 0 is not mixed in, so introducing a version does not change any digests.
+    assert_eq!(
+      action_digest(&plain, unversioned),
+      action_digest(&plain, CacheKeyVersions::default())
+    );
+
+    let affected = |bumped: CacheKeyVersions| {
+      vec![&plain, &with_outputs, &with_jdk]
+        .into_iter()
+        .map(|req| action_digest(req, unversioned) != action_digest(req, bumped))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      affected(CacheKeyVersions {
+        process_execution: 1,
+        ..unversioned
+      }),
+      vec![true, true, true]
+    );
+    assert_eq!(
+      affected(CacheKeyVersions {
+        snapshot_format: 1,
+        ..unversioned
+      }),
+      vec![false, true, false]
+    );
+    assert_eq!(
+      affected(CacheKeyVersions {
+        nailgun: 1,
+        ..unversioned
+      }),
+      vec![false, false, true]
+    );
+  }
+
+  #[test]
+  fn make_execute_request_with_reserved_cache_key_version_env_is_error() {
+    let mut req = echo_foo_request();
+    req
+      .env
+      .insert("PANTS_NAILGUN_CACHE_KEY_VERSION".to_owned(), "7".to_owned());
+    let err = super::make_execute_request(&req, &ExecuteProcessRequestMetadata::default())
+      .expect_err("Want Err");
+    assert!(err.contains("reserved"), "Unexpected error: {}", err);
+  }
+
+  #[test]
+  fn make_execute_request_with_stdin_is_error() {
+    let mut req = echo_foo_request();
+    req.stdin_digest = Some(TestData::roland().digest());
+
+    assert_eq!(
+      super::make_execute_request(&req, &ExecuteProcessRequestMetadata::default()),
+      Err(
+        "Cannot execute \"echo a foo\" remotely: remote execution does not support stdin."
+          .to_owned()
+      )
+    );
+  }
+
+  #[test]
+  fn make_execute_request_with_secret_env_is_uncacheable() {
+    let mut req = echo_foo_request();
+    req.env.insert("TOKEN".to_owned(), "hunter2".to_owned());
+    let (public_action, public_command, public_execute_request) =
+      super::make_execute_request(&req, &ExecuteProcessRequestMetadata::default()).unwrap();
+    assert!(!public_action.get_do_not_cache());
+    assert!(!public_execute_request.get_skip_cache_lookup());
+    assert_eq!(public_command.get_environment_variables().len(), 1);
+
+    req.secret_env.insert("TOKEN".to_owned());
+    let (action, command, execute_request) =
+      super::make_execute_request(&req, &ExecuteProcessRequestMetadata::default()).unwrap();
+    assert!(action.get_do_not_cache());
+    assert!(execute_request.get_skip_cache_lookup());
+    let env_names = command
+      .get_environment_variables()
+      .iter()
+      .map(|env| env.get_name())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      env_names,
+      vec!["TOKEN", super::SECRET_ENV_SALT_ENV_VAR_NAME]
+    );
+    assert_ne!(
+      execute_request.get_action_digest(),
+      public_execute_request.get_action_digest()
+    );
+  }
+
+  #[test]
+  fn make_execute_request_with_secret_env_is_salted_per_session() {
+    let mut req = echo_foo_request();
+    req.env.insert("TOKEN".to_owned(), "hunter2".to_owned());
+    req.secret_env.insert("TOKEN".to_owned());
+    let action_digest = |session_id: u32| {
+      let context = Context {
+        secret_env_salt: Some(super::super::secret_env_salt(session_id)),
+        ..Context::default()
+      };
+      let metadata = context.metadata(&ExecuteProcessRequestMetadata::default());
+      let (_, _, execute_request) = super::make_execute_request(&req, &metadata).unwrap();
+      (metadata, execute_request.get_action_digest().clone())
+    };
+
+    let (metadata, digest) = action_digest(1);
+    let (_, _, execute_request) = super::make_execute_request(&req, &metadata).unwrap();
+    assert_eq!(execute_request.get_action_digest(), &digest);
+    assert_ne!(action_digest(1).1, digest);
+    assert_ne!(action_digest(2).1, digest);
+  }
+
+  #[test]
+  fn make_execute_request_side_effecting_is_uncacheable() {
+    let mut req = echo_foo_request();
+    req.side_effecting = true;
+    let (action, _, execute_request) =
+      super::make_execute_request(&req, &ExecuteProcessRequestMetadata::default()).unwrap();
+    assert!(action.get_do_not_cache());
+    assert!(execute_request.get_skip_cache_lookup());
+  }
+
+  #[test]
+  fn make_execute_request_with_request_platform_properties() {
+    let mut req = echo_foo_request();
+    req.platform_properties = vec![
+      ("pool".to_owned(), "gpu".to_owned()),
+      ("zone".to_owned(), "west".to_owned()),
+    ]
+    .into_iter()
+    .collect();
+
+    let (_, command, _) = super::make_execute_request(
+      &req,
+      &ExecuteProcessRequestMetadata {
+        platform_properties: vec![
+          ("pool".to_owned(), "default".to_owned()),
+          ("os".to_owned(), "linux".to_owned()),
+        ]
+        .into_iter()
+        .collect(),
+        ..ExecuteProcessRequestMetadata::default()
+      },
+    )
+    .unwrap();
+
+    let properties = command
+      .get_platform()
+      .get_properties()
+      .iter()
+      .map(|property| (property.get_name(), property.get_value()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      properties,
+      vec![("os", "linux"), ("pool", "gpu"), ("zone", "west")]
+    );
+  }
+
+  #[test]
+  fn validate_platform_properties() {
+    let allowed_keys = vec!["pool".to_owned()].into_iter().collect();
+
+    let mut req = echo_foo_request();
+    assert_eq!(
+      super::validate_platform_properties(&req, &allowed_keys),
+      Ok(())
+    );
+
+    req
+      .platform_properties
+      .insert("pool".to_owned(), "big-memory".to_owned());
+    assert_eq!(
+      super::validate_platform_properties(&req, &allowed_keys),
+      Ok(())
     );
+
+    req
+      .platform_properties
+      .insert("pol".to_owned(), "gpu".to_owned());
+    let err = super::validate_platform_properties(&req, &allowed_keys).unwrap_err();
+    assert!(err.contains("[\"pol\"]"), "Unexpected error: {}", err);
+  }
+
+  #[test]
+  fn disallowed_platform_properties_gives_error() {
+    let mut execute_request = echo_foo_request();
+    execute_request
+      .platform_properties
+      .insert("pool".to_owned(), "gpu".to_owned());
+
+    // The request should be rejected before the server is contacted.
+    let mock_server = {
+      mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
+        "unused".to_string(),
+        super::make_execute_request(
+          &echo_foo_request(),
+          &ExecuteProcessRequestMetadata::default(),
+        )
+        .unwrap()
+        .2,
+        vec![],
+      ))
+    };
+
+    let error = run_command_remote(mock_server.address(), execute_request).expect_err("Want Err");
+    assert!(error.contains("not allowed"), "Unexpected error: {}", error);
   }
 
   #[test]
@@ -1308,17 +2201,11 @@ mod tests {
         super::make_execute_request(
           &ExecuteProcessRequest {
             argv: owned_string_vec(&["/bin/echo", "-n", "bar"]),
-            env: BTreeMap::new(),
-            input_files: fs::EMPTY_DIGEST,
-            output_files: BTreeSet::new(),
-            output_directories: BTreeSet::new(),
             timeout: Duration::from_millis(1000),
             description: "wrong command".to_string(),
-            jdk_home: None,
+            ..ExecuteProcessRequest::default()
           },
-          &None,
-          &None,
-          BTreeMap::new(),
+          &ExecuteProcessRequestMetadata::default(),
         )
         .unwrap()
         .2,
@@ -1342,7 +2229,7 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
           .unwrap()
           .2,
         vec![
@@ -1367,10 +2254,246 @@ mod tests {
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
         execution_attempts: vec![],
+        runner: format!("remote({})", mock_server.address()),
+        timed_out: None,
       }
     );
   }
 
+  #[test]
+  fn skip_cache_lookup() {
+    let execute_request = echo_foo_request();
+
+    let mock_server = {
+      let op_name = "gimme-foo".to_string();
+      let mut expected_request =
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
+          .unwrap()
+          .2;
+      expected_request.set_skip_cache_lookup(true);
+
+      mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
+        op_name.clone(),
+        expected_request,
+        vec![make_successful_operation(
+          &op_name,
+          StdoutType::Raw("foo".to_owned()),
+          StderrType::Raw("".to_owned()),
+          0,
+        )],
+      ))
+    };
+
+    let cas = mock::StubCAS::builder().build();
+    let mut command_runner = create_command_runner(mock_server.address(), &cas);
+    command_runner.skip_cache_lookup = true;
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let result = runtime
+      .block_on(command_runner.run(execute_request, Context::default()))
+      .unwrap();
+
+    assert_eq!(result.stdout, as_bytes("foo"));
+    assert!(!result.was_cache_hit());
+  }
+
+  #[test]
+  fn read_only_action_cache_executes_uncacheably() {
+    let execute_request = echo_foo_request();
+
+    let mock_server = {
+      let op_name = "gimme-foo".to_string();
+      let (mut action, _, mut expected_request) =
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
+          .unwrap();
+      super::make_uncacheable(&mut action, &mut expected_request).unwrap();
+
+      mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
+        op_name.clone(),
+        expected_request,
+        vec![make_successful_operation(
+          &op_name,
+          StdoutType::Raw("foo".to_owned()),
+          StderrType::Raw("".to_owned()),
+          0,
+        )],
+      ))
+    };
+
+    // The mock server has no ActionCache, so the lookup fails, and the process is executed.
+    let cas = mock::StubCAS::builder().build();
+    let mut command_runner = create_command_runner(mock_server.address(), &cas);
+    command_runner.action_cache_writes = false;
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let result = runtime
+      .block_on(command_runner.run(execute_request, Context::default()))
+      .unwrap();
+
+    assert_eq!(result.stdout, as_bytes("foo"));
+    assert!(!result.was_cache_hit());
+  }
+
+  fn cached_action_result(
+    action_cache: &mock::StubActionCache,
+    execute_request: &ExecuteProcessRequest,
+    stdout: StdoutType,
+  ) {
+    let action_digest: Result<Digest, String> =
+      super::make_execute_request(execute_request, &ExecuteProcessRequestMetadata::default())
+        .unwrap()
+        .2
+        .get_action_digest()
+        .into();
+    let mut action_result = bazel_protos::remote_execution::ActionResult::new();
+    match stdout {
+      StdoutType::Raw(stdout_raw) => action_result.set_stdout_raw(Bytes::from(stdout_raw)),
+      StdoutType::Digest(stdout_digest) => action_result.set_stdout_digest((&stdout_digest).into()),
+    }
+    action_cache.insert(action_digest.unwrap(), action_result);
+  }
+
+  #[test]
+  fn lookup_finds_cached_result() {
+    let execute_request = echo_foo_request();
+    let action_cache = mock::StubActionCache::empty();
+    cached_action_result(
+      &action_cache,
+      &execute_request,
+      StdoutType::Raw("foo".to_owned()),
+    );
+
+    let cas = mock::StubCAS::empty();
+    let command_runner = create_command_runner(action_cache.address(), &cas);
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let result = runtime
+      .block_on(command_runner.lookup(&execute_request))
+      .unwrap()
+      .expect("Want a cached result");
+
+    assert_eq!(result.stdout, as_bytes("foo"));
+    assert!(result.was_cache_hit());
+  }
+
+  #[test]
+  fn lookup_ignores_failing_action_cache() {
+    let execute_request = echo_foo_request();
+    let action_cache = mock::StubActionCache::with_faults(mock::Faults {
+      error_rate: 1.0,
+      ..mock::Faults::default()
+    });
+    cached_action_result(
+      &action_cache,
+      &execute_request,
+      StdoutType::Raw("foo".to_owned()),
+    );
+
+    let cas = mock::StubCAS::empty();
+    let command_runner = create_command_runner(action_cache.address(), &cas);
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    assert_eq!(
+      runtime.block_on(command_runner.lookup(&execute_request)),
+      Ok(None)
+    );
+    assert_eq!(action_cache.request_count(), 1);
+  }
+
+  #[test]
+  fn lookup_ignores_cached_result_with_evicted_outputs() {
+    let execute_request = echo_foo_request();
+    let action_cache = mock::StubActionCache::empty();
+    cached_action_result(
+      &action_cache,
+      &execute_request,
+      StdoutType::Digest(TestData::roland().digest()),
+    );
+
+    // The stdout of the cached result is garbage collected from the CAS before it is looked up.
+    let cas = mock::StubCAS::builder().file(&TestData::roland()).build();
+    cas.gc();
+    let command_runner = create_command_runner(action_cache.address(), &cas);
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    assert_eq!(
+      runtime.block_on(command_runner.lookup(&execute_request)),
+      Ok(None)
+    );
+  }
+
+  #[test]
+  fn offline_fails_without_making_requests() {
+    let execute_request = echo_foo_request();
+    let action_cache = mock::StubActionCache::empty();
+    cached_action_result(
+      &action_cache,
+      &execute_request,
+      StdoutType::Raw("foo".to_owned()),
+    );
+
+    let cas = mock::StubCAS::empty();
+    let command_runner = create_command_runner(action_cache.address(), &cas).into_offline();
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let lookup_error = runtime
+      .block_on(command_runner.lookup(&execute_request))
+      .expect_err("Want error");
+    let run_error = runtime
+      .block_on(command_runner.run(execute_request, Context::default()))
+      .expect_err("Want error");
+
+    for error in &[lookup_error, run_error] {
+      assert!(
+        error.contains("offline mode"),
+        "Bad error message, got: {}",
+        error
+      );
+    }
+    assert_eq!(action_cache.request_count(), 0);
+    assert_eq!(cas.request_count(), 0);
+  }
+
+  #[test]
+  fn verified_cache_hit_with_missing_outputs_is_rejected() {
+    let cached_operation = |stdout_digest: Digest| {
+      let mut operation = make_successful_operation(
+        "cached",
+        StdoutType::Digest(stdout_digest),
+        StderrType::Raw("".to_owned()),
+        0,
+      )
+      .op
+      .unwrap()
+      .unwrap();
+      let mut execute_response = bazel_protos::remote_execution::ExecuteResponse::new();
+      execute_response
+        .merge_from_bytes(operation.get_response().get_value())
+        .unwrap();
+      execute_response.set_cached_result(true);
+      operation.set_response(make_any_proto(&execute_response));
+      operation
+    };
+
+    let cas = mock::StubCAS::builder().file(&TestData::roland()).build();
+    let mut command_runner = create_command_runner("".to_owned(), &cas);
+    command_runner.verify_cache_hits = true;
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let result = runtime
+      .block_on(command_runner.extract_execute_response(
+        super::OperationOrStatus::Operation(cached_operation(TestData::roland().digest())),
+        &mut ExecutionHistory::default(),
+      ))
+      .unwrap();
+    assert_eq!(result.stdout, TestData::roland().bytes());
+    assert!(result.was_cache_hit());
+
+    assert_eq!(
+      runtime.block_on(command_runner.extract_execute_response(
+        super::OperationOrStatus::Operation(cached_operation(TestData::catnip().digest())),
+        &mut ExecutionHistory::default(),
+      )),
+      Err(ExecutionError::MissingCachedOutputs(vec![
+        TestData::catnip().digest()
+      ]))
+    );
+  }
+
   #[test]
   fn extract_response_with_digest_stdout() {
     let op_name = "gimme-foo".to_string();
@@ -1396,6 +2519,8 @@ mod tests {
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
         execution_attempts: vec![],
+        runner: "remote()".to_owned(),
+        timed_out: None,
       }
     );
   }
@@ -1425,6 +2550,8 @@ mod tests {
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
         execution_attempts: vec![],
+        runner: "remote()".to_owned(),
+        timed_out: None,
       }
     );
   }
@@ -1441,9 +2568,12 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&echo_roland_request(), &None, &None, BTreeMap::new())
-          .unwrap()
-          .2,
+        super::make_execute_request(
+          &echo_roland_request(),
+          &ExecuteProcessRequestMetadata::default(),
+        )
+        .unwrap()
+        .2,
         vec![make_successful_operation(
           &op_name.clone(),
           StdoutType::Raw(test_stdout.string()),
@@ -1461,8 +2591,10 @@ mod tests {
     let store = fs::Store::with_remote(
       &store_dir_path,
       &[cas.address()],
+      &[],
       None,
       &None,
+      &fs::ProxyConfig::default(),
       None,
       1,
       10 * 1024 * 1024,
@@ -1478,14 +2610,19 @@ mod tests {
       None,
       None,
       None,
+      &fs::ProxyConfig::default(),
       None,
       BTreeMap::new(),
+      BTreeSet::new(),
+      false,
+      true,
+      false,
       1,
       store,
       timer_thread,
     );
     let result = runtime
-      .block_on(cmd_runner.run(echo_roland_request()))
+      .block_on(cmd_runner.run(echo_roland_request(), Context::default()))
       .unwrap();
     assert_eq!(
       result.without_execution_attempts(),
@@ -1495,6 +2632,8 @@ mod tests {
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
         execution_attempts: vec![],
+        runner: format!("remote({})", mock_server.address()),
+        timed_out: None,
       }
     );
 
@@ -1524,7 +2663,7 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
           .unwrap()
           .2,
         Vec::from_iter(
@@ -1550,6 +2689,8 @@ mod tests {
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
         execution_attempts: vec![],
+        runner: format!("remote({})", mock_server.address()),
+        timed_out: None,
       }
     );
   }
@@ -1561,13 +2702,9 @@ mod tests {
 
     let execute_request = ExecuteProcessRequest {
       argv: owned_string_vec(&["/bin/echo", "-n", "foo"]),
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: request_timeout,
       description: "echo-a-foo".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     };
 
     let mock_server = {
@@ -1575,7 +2712,7 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
           .unwrap()
           .2,
         vec![
@@ -1585,10 +2722,46 @@ mod tests {
       ))
     };
 
-    let error_msg = run_command_remote(mock_server.address(), execute_request)
-      .expect_err("Timeout did not cause failure.");
-    assert_contains(&error_msg, "Exceeded time out");
-    assert_contains(&error_msg, "echo-a-foo");
+    let result = run_command_remote(mock_server.address(), execute_request).unwrap();
+    assert_eq!(result.exit_code, TIMED_OUT_EXIT_CODE);
+    assert_eq!(result.stdout, Bytes::new());
+    let timed_out = result
+      .timed_out
+      .expect("Timeout did not cause a timed out result.");
+    assert_eq!(timed_out.timeout, request_timeout);
+    assert!(timed_out.elapsed > request_timeout);
+  }
+
+  #[test]
+  fn execute_stream_deadline() {
+    let execute_request = echo_foo_request();
+
+    let mock_server = {
+      let op_name = "gimme-foo".to_string();
+
+      mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
+        op_name.clone(),
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
+          .unwrap()
+          .2,
+        vec![make_delayed_incomplete_operation(
+          &op_name,
+          Duration::from_secs(2),
+        )],
+      ))
+    };
+
+    let cas = mock::StubCAS::builder().build();
+    let command_runner =
+      create_command_runner(mock_server.address(), &cas).with_timeouts(ExecutionTimeouts {
+        execute_stream: Duration::from_millis(100),
+        ..ExecutionTimeouts::default()
+      });
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let error = runtime
+      .block_on(command_runner.run(execute_request, Context::default()))
+      .expect_err("Want error");
+    assert_contains(&error, "DeadlineExceeded");
   }
 
   #[test]
@@ -1600,7 +2773,7 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
           .unwrap()
           .2,
         vec![
@@ -1626,6 +2799,8 @@ mod tests {
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
         execution_attempts: vec![],
+        runner: format!("remote({})", mock_server.address()),
+        timed_out: None,
       }
     );
   }
@@ -1639,7 +2814,7 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
           .unwrap()
           .2,
         vec![
@@ -1677,7 +2852,7 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
           .unwrap()
           .2,
         vec![MockOperation::new({
@@ -1709,7 +2884,7 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
           .unwrap()
           .2,
         vec![
@@ -1744,7 +2919,7 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
           .unwrap()
           .2,
         vec![MockOperation::new({
@@ -1770,7 +2945,7 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+        super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
           .unwrap()
           .2,
         vec![
@@ -1801,9 +2976,12 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&cat_roland_request(), &None, &None, BTreeMap::new())
-          .unwrap()
-          .2,
+        super::make_execute_request(
+          &cat_roland_request(),
+          &ExecuteProcessRequestMetadata::default(),
+        )
+        .unwrap()
+        .2,
         vec![
           make_incomplete_operation(&op_name),
           make_precondition_failure_operation(vec![missing_preconditionfailure_violation(
@@ -1827,8 +3005,10 @@ mod tests {
     let store = fs::Store::with_remote(
       store_dir,
       &[cas.address()],
+      &[],
       None,
       &None,
+      &fs::ProxyConfig::default(),
       None,
       1,
       10 * 1024 * 1024,
@@ -1849,15 +3029,20 @@ mod tests {
       None,
       None,
       None,
+      &fs::ProxyConfig::default(),
       None,
       BTreeMap::new(),
+      BTreeSet::new(),
+      false,
+      true,
+      false,
       1,
       store,
       timer_thread,
     );
 
     let result = runtime
-      .block_on(command_runner.run(cat_roland_request()))
+      .block_on(command_runner.run(cat_roland_request(), Context::default()))
       .unwrap();
     assert_eq!(
       result.without_execution_attempts(),
@@ -1867,6 +3052,8 @@ mod tests {
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
         execution_attempts: vec![],
+        runner: format!("remote({})", mock_server.address()),
+        timed_out: None,
       }
     );
     {
@@ -1875,6 +3062,92 @@ mod tests {
     }
   }
 
+  #[test]
+  fn execute_missing_file_retries_are_bounded() {
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let roland = TestData::roland();
+
+    // The server reports the file missing more times than it will be uploaded.
+    let mock_server = {
+      let op_name = "cat".to_owned();
+
+      mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
+        op_name.clone(),
+        super::make_execute_request(
+          &cat_roland_request(),
+          &ExecuteProcessRequestMetadata::default(),
+        )
+        .unwrap()
+        .2,
+        (0..=CommandRunner::MAX_MISSING_DIGEST_RETRIES)
+          .map(|_| {
+            make_precondition_failure_operation(vec![missing_preconditionfailure_violation(
+              &roland.digest(),
+            )])
+          })
+          .collect(),
+      ))
+    };
+
+    let store_dir = TempDir::new().unwrap();
+    let cas = mock::StubCAS::builder()
+      .directory(&TestDirectory::containing_roland())
+      .build();
+    let timer_thread = timer_thread();
+    let store = fs::Store::with_remote(
+      store_dir,
+      &[cas.address()],
+      &[],
+      None,
+      &None,
+      &fs::ProxyConfig::default(),
+      None,
+      1,
+      10 * 1024 * 1024,
+      Duration::from_secs(1),
+      fs::BackoffConfig::new(Duration::from_millis(10), 1.0, Duration::from_millis(10)).unwrap(),
+      1,
+      timer_thread.with(|t| t.handle()),
+    )
+    .expect("Failed to make store");
+    runtime
+      .block_on(store.store_file_bytes(roland.bytes(), false))
+      .expect("Saving file bytes to store");
+    let command_runner = CommandRunner::new(
+      &mock_server.address(),
+      None,
+      None,
+      None,
+      &fs::ProxyConfig::default(),
+      None,
+      BTreeMap::new(),
+      BTreeSet::new(),
+      false,
+      true,
+      false,
+      1,
+      store,
+      timer_thread,
+    );
+
+    let error = runtime
+      .block_on(command_runner.run(cat_roland_request(), Context::default()))
+      .expect_err("Want error");
+    assert_contains(
+      &error,
+      &format!(
+        "reported missing digests after they had been uploaded {} times",
+        CommandRunner::MAX_MISSING_DIGEST_RETRIES
+      ),
+    );
+    // Each time the file was reported missing, it was uploaded.
+    assert_eq!(
+      cas.blobs.lock().get(&roland.fingerprint()),
+      Some(&roland.bytes())
+    );
+  }
+
   //#[test] // TODO: Unignore this test when the server can actually fail with status protos.
   // See https://github.com/pantsbuild/pants/issues/6597
   #[allow(dead_code)]
@@ -1898,9 +3171,12 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&cat_roland_request(), &None, &None, BTreeMap::new())
-          .unwrap()
-          .2,
+        super::make_execute_request(
+          &cat_roland_request(),
+          &ExecuteProcessRequestMetadata::default(),
+        )
+        .unwrap()
+        .2,
         vec![
           //make_incomplete_operation(&op_name),
           MockOperation {
@@ -1925,8 +3201,10 @@ mod tests {
     let store = fs::Store::with_remote(
       store_dir,
       &[cas.address()],
+      &[],
       None,
       &None,
+      &fs::ProxyConfig::default(),
       None,
       1,
       10 * 1024 * 1024,
@@ -1946,13 +3224,18 @@ mod tests {
       None,
       None,
       None,
+      &fs::ProxyConfig::default(),
       None,
       BTreeMap::new(),
+      BTreeSet::new(),
+      false,
+      true,
+      false,
       1,
       store,
       timer_thread,
     )
-    .run(cat_roland_request())
+    .run(cat_roland_request(), Context::default())
     .wait();
     assert_eq!(
       result,
@@ -1962,6 +3245,8 @@ mod tests {
         exit_code: 0,
         output_directory: fs::EMPTY_DIGEST,
         execution_attempts: vec![],
+        runner: format!("remote({})", mock_server.address()),
+        timed_out: None,
       })
     );
     {
@@ -1979,9 +3264,12 @@ mod tests {
 
       mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
         op_name.clone(),
-        super::make_execute_request(&cat_roland_request(), &None, &None, BTreeMap::new())
-          .unwrap()
-          .2,
+        super::make_execute_request(
+          &cat_roland_request(),
+          &ExecuteProcessRequestMetadata::default(),
+        )
+        .unwrap()
+        .2,
         // We won't get as far as trying to run the operation, so don't expect any requests whose
         // responses we would need to stub.
         vec![],
@@ -1997,8 +3285,10 @@ mod tests {
     let store = fs::Store::with_remote(
       store_dir,
       &[cas.address()],
+      &[],
       None,
       &None,
+      &fs::ProxyConfig::default(),
       None,
       1,
       10 * 1024 * 1024,
@@ -2015,15 +3305,20 @@ mod tests {
       None,
       None,
       None,
+      &fs::ProxyConfig::default(),
       None,
       BTreeMap::new(),
+      BTreeSet::new(),
+      false,
+      true,
+      false,
       1,
       store,
       timer_thread,
     );
 
     let error = runtime
-      .block_on(runner.run(cat_roland_request()))
+      .block_on(runner.run(cat_roland_request(), Context::default()))
       .expect_err("Want error");
     assert_contains(&error, &format!("{}", missing_digest.0));
   }
@@ -2058,6 +3353,8 @@ mod tests {
       exit_code: 17,
       output_directory: TestDirectory::nested().digest(),
       execution_attempts: vec![],
+      runner: "remote()".to_owned(),
+      timed_out: None,
     };
 
     let mut output_file = bazel_protos::remote_execution::OutputFile::new();
@@ -2239,7 +3536,7 @@ mod tests {
         let op_name = "gimme-foo".to_string();
         mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
           op_name.clone(),
-          super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+          super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
             .unwrap()
             .2,
           vec![
@@ -2277,7 +3574,7 @@ mod tests {
         let op_name = "gimme-foo".to_string();
         mock::execution_server::TestServer::new(mock::execution_server::MockExecution::new(
           op_name.clone(),
-          super::make_execute_request(&execute_request, &None, &None, BTreeMap::new())
+          super::make_execute_request(&execute_request, &ExecuteProcessRequestMetadata::default())
             .unwrap()
             .2,
           vec![
@@ -2427,6 +3724,39 @@ mod tests {
     )
   }
 
+  #[test]
+  fn record_execution_metadata_timings() {
+    let timestamp = |seconds| {
+      let mut timestamp = protobuf::well_known_types::Timestamp::new();
+      timestamp.set_seconds(seconds);
+      timestamp
+    };
+    let mut metadata = bazel_protos::remote_execution::ExecutedActionMetadata::new();
+    metadata.set_queued_timestamp(timestamp(100));
+    metadata.set_worker_start_timestamp(timestamp(103));
+    metadata.set_input_fetch_start_timestamp(timestamp(104));
+    metadata.set_input_fetch_completed_timestamp(timestamp(106));
+    metadata.set_execution_start_timestamp(timestamp(107));
+    metadata.set_execution_completed_timestamp(timestamp(117));
+    metadata.set_output_upload_start_timestamp(timestamp(118));
+    metadata.set_output_upload_completed_timestamp(timestamp(119));
+
+    let mut stats = ExecutionStats::default();
+    record_execution_metadata(&metadata, &mut stats);
+
+    assert_eq!(
+      stats,
+      ExecutionStats {
+        remote_queue: Some(Duration::from_secs(3)),
+        remote_worker_setup: Some(Duration::from_secs(4)),
+        remote_input_fetch: Some(Duration::from_secs(2)),
+        remote_execution: Some(Duration::from_secs(10)),
+        remote_output_store: Some(Duration::from_secs(1)),
+        ..ExecutionStats::default()
+      }
+    );
+  }
+
   #[test]
   fn extract_output_files_from_response_directories_and_files() {
     // /catnip
@@ -2480,13 +3810,9 @@ mod tests {
   fn echo_foo_request() -> ExecuteProcessRequest {
     ExecuteProcessRequest {
       argv: owned_string_vec(&["/bin/echo", "-n", "foo"]),
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(5000),
       description: "echo a foo".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     }
   }
 
@@ -2598,7 +3924,7 @@ mod tests {
       .build();
     let command_runner = create_command_runner(address, &cas);
     let mut runtime = tokio::runtime::Runtime::new().unwrap();
-    runtime.block_on(command_runner.run(request))
+    runtime.block_on(command_runner.run(request, Context::default()))
   }
 
   fn create_command_runner(address: String, cas: &mock::StubCAS) -> CommandRunner {
@@ -2607,8 +3933,10 @@ mod tests {
     let store = fs::Store::with_remote(
       store_dir,
       &[cas.address()],
+      &[],
       None,
       &None,
+      &fs::ProxyConfig::default(),
       None,
       1,
       10 * 1024 * 1024,
@@ -2624,8 +3952,13 @@ mod tests {
       None,
       None,
       None,
+      &fs::ProxyConfig::default(),
       None,
       BTreeMap::new(),
+      BTreeSet::new(),
+      false,
+      true,
+      false,
       1,
       store,
       timer_thread,
@@ -2699,26 +4032,19 @@ mod tests {
   fn cat_roland_request() -> ExecuteProcessRequest {
     ExecuteProcessRequest {
       argv: owned_string_vec(&["/bin/cat", "roland"]),
-      env: BTreeMap::new(),
       input_files: TestDirectory::containing_roland().digest(),
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "cat a roland".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     }
   }
 
   fn echo_roland_request() -> ExecuteProcessRequest {
     ExecuteProcessRequest {
       argv: owned_string_vec(&["/bin/echo", "meoooow"]),
-      env: BTreeMap::new(),
-      input_files: fs::EMPTY_DIGEST,
-      output_files: BTreeSet::new(),
-      output_directories: BTreeSet::new(),
       timeout: Duration::from_millis(1000),
       description: "unleash a roaring meow".to_string(),
-      jdk_home: None,
+      ..ExecuteProcessRequest::default()
     }
   }
 }
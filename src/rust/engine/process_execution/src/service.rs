@@ -0,0 +1,248 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use super::background::BackgroundProcess;
+use super::InteractiveProcessRequest;
+
+///
+/// A request for a named, long-running process (such as a database or an emulator) which is shared
+/// by every requester within a Session, rather than started by each of them.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServiceRequest {
+  pub name: String,
+  pub process: InteractiveProcessRequest,
+  ///
+  /// If present, the service is healthy once this has been written to its stdout. Otherwise, it is
+  /// healthy as soon as it has started.
+  ///
+  pub ready_stdout: Option<String>,
+  pub startup_timeout: Duration,
+}
+
+struct Service {
+  request: ServiceRequest,
+  process: Arc<BackgroundProcess>,
+}
+
+///
+/// The services which have been started within a Session. Each is started once (when it is first
+/// requested), is restarted if it has exited when it is next requested, and is killed (in the
+/// reverse of the order in which the services were started) when the registry is dropped.
+///
+#[derive(Default)]
+pub struct ServiceRegistry {
+  // NB: Held while a service starts, so that concurrent requests for it share one process.
+  services: Mutex<Vec<Service>>,
+}
+
+impl ServiceRegistry {
+  ///
+  /// Returns the running service for the given request, using `start` to start it (and then waiting
+  /// until it is healthy) if it has not been started, or if it has exited.
+  ///
+  pub fn ensure<F>(
+    &self,
+    request: ServiceRequest,
+    start: F,
+  ) -> Result<Arc<BackgroundProcess>, String>
+  where
+    F: FnOnce(InteractiveProcessRequest) -> Result<BackgroundProcess, String>,
+  {
+    let mut services = self.services.lock().unwrap();
+    if let Some(i) = services.iter().position(|s| s.request.name == request.name) {
+      if services[i].request != request {
+        return Err(format!(
+          "Service {} was already started by a different request.",
+          request.name
+        ));
+      }
+      match services[i].process.exit_code() {
+        None => return Ok(services[i].process.clone()),
+        Some(exit_code) => {
+          warn!(
+            "Service {} exited with code {}, and will be restarted.",
+            request.name, exit_code
+          );
+          services.remove(i);
+        }
+      }
+    }
+
+    debug!("Starting service {}", request.name);
+    let process = Arc::new(start(request.process.clone())?);
+    wait_until_healthy(&request, &process)?;
+    services.push(Service {
+      request,
+      process: process.clone(),
+    });
+    Ok(process)
+  }
+
+  ///
+  /// Kills every service, in the reverse of the order in which they were started (since a later
+  /// service may depend on an earlier one).
+  ///
+  pub fn teardown(&self) {
+    let mut services = self.services.lock().unwrap();
+    while let Some(service) = services.pop() {
+      match service.process.kill() {
+        Ok(exit_code) => debug!(
+          "Stopped service {} (exit code {})",
+          service.request.name, exit_code
+        ),
+        Err(e) => warn!("Failed to stop service {}: {}", service.request.name, e),
+      }
+    }
+  }
+}
+
+impl Drop for ServiceRegistry {
+  fn drop(&mut self) {
+    self.teardown();
+  }
+}
+
+///
+/// Waits until the service has written its `ready_stdout`, failing if it exits or if its
+/// `startup_timeout` elapses first.
+///
+fn wait_until_healthy(request: &ServiceRequest, process: &BackgroundProcess) -> Result<(), String> {
+  let deadline = Instant::now() + request.startup_timeout;
+  let mut stdout_seen = 0;
+  loop {
+    let now = Instant::now();
+    let remaining = if now < deadline {
+      deadline - now
+    } else {
+      Duration::from_millis(0)
+    };
+    let progress = process.wait_for_progress(stdout_seen, remaining);
+    let ready = match request.ready_stdout {
+      Some(ref ready_stdout) => String::from_utf8_lossy(&progress.stdout).contains(ready_stdout),
+      None => true,
+    };
+    if let Some(exit_code) = progress.exit_code {
+      return Err(format!(
+        "Service {} exited with code {} before it was healthy. stderr:\n{}",
+        request.name,
+        exit_code,
+        String::from_utf8_lossy(&progress.stderr)
+      ));
+    }
+    if ready {
+      return Ok(());
+    }
+    if remaining == Duration::from_millis(0) {
+      // Kill the unhealthy service, rather than leaving it to run until the end of the Session.
+      let _ = process.kill();
+      return Err(format!(
+        "Service {} was not healthy after {:?}.",
+        request.name, request.startup_timeout
+      ));
+    }
+    stdout_seen = progress.stdout.len();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+  use std::process::Command;
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  use testutil::owned_string_vec;
+
+  use super::{ServiceRegistry, ServiceRequest};
+  use crate::background::BackgroundProcess;
+  use crate::InteractiveProcessRequest;
+
+  fn request(name: &str, script: &str, ready_stdout: Option<&str>) -> ServiceRequest {
+    ServiceRequest {
+      name: name.to_owned(),
+      process: InteractiveProcessRequest {
+        argv: owned_string_vec(&["/bin/bash", "-c", script]),
+        env: BTreeMap::new(),
+        input_files: fs::EMPTY_DIGEST,
+        description: name.to_owned(),
+      },
+      ready_stdout: ready_stdout.map(str::to_owned),
+      startup_timeout: Duration::from_secs(10),
+    }
+  }
+
+  fn start(process: InteractiveProcessRequest) -> Result<BackgroundProcess, String> {
+    BackgroundProcess::spawn(
+      Command::new(&process.argv[0]).args(&process.argv[1..]),
+      None,
+    )
+  }
+
+  #[test]
+  fn shares_a_started_service() {
+    let registry = ServiceRegistry::default();
+    let db = request(
+      "db",
+      "sleep 0.1; echo listening; sleep 60",
+      Some("listening"),
+    );
+    let first = registry.ensure(db.clone(), start).unwrap();
+    assert_eq!(first.exit_code(), None);
+    let second = registry
+      .ensure(db, |_| Err("Should not be restarted.".to_owned()))
+      .unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+
+    registry.teardown();
+    assert!(first.exit_code().is_some());
+  }
+
+  #[test]
+  fn restarts_an_exited_service() {
+    let registry = ServiceRegistry::default();
+    let db = request("db", "sleep 60", None);
+    let first = registry.ensure(db.clone(), start).unwrap();
+    first.kill().unwrap();
+    let second = registry.ensure(db, start).unwrap();
+    assert!(!Arc::ptr_eq(&first, &second));
+    assert_eq!(second.exit_code(), None);
+  }
+
+  #[test]
+  fn rejects_a_conflicting_request() {
+    let registry = ServiceRegistry::default();
+    registry
+      .ensure(request("db", "sleep 60", None), start)
+      .unwrap();
+    let err = registry
+      .ensure(request("db", "sleep 30", None), start)
+      .err()
+      .unwrap();
+    assert!(err.contains("already started by a different request"));
+  }
+
+  #[test]
+  fn fails_if_the_service_exits_before_it_is_healthy() {
+    let registry = ServiceRegistry::default();
+    let err = registry
+      .ensure(
+        request("db", "echo oops >&2; exit 3", Some("listening")),
+        start,
+      )
+      .err()
+      .unwrap();
+    assert!(err.contains("exited with code 3 before it was healthy"));
+  }
+
+  #[test]
+  fn fails_if_the_service_is_not_healthy_in_time() {
+    let registry = ServiceRegistry::default();
+    let mut db = request("db", "sleep 60", Some("listening"));
+    db.startup_timeout = Duration::from_millis(200);
+    let err = registry.ensure(db, start).err().unwrap();
+    assert!(err.contains("was not healthy after"));
+  }
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use boxfuture::{BoxFuture, Boxable};
+use futures::{future, Future};
+
+use super::{remote, CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult};
+
+///
+/// Where processes are executed, overriding the configured runners, so that (for example) a
+/// failure can be quickly bisected to the local or remote environment.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ExecutionStrategy {
+  // Execute processes with the configured runners.
+  Default,
+  // Execute every process locally, even if remote execution is configured.
+  LocalOnly,
+  // Execute every process remotely, bypassing any local or verifying runners.
+  RemoteOnly,
+  // Use results from the remote cache, but execute every process which misses it locally.
+  RemoteCacheOnly,
+}
+
+impl ExecutionStrategy {
+  pub fn new(name: &str) -> Result<ExecutionStrategy, String> {
+    match name {
+      "default" => Ok(ExecutionStrategy::Default),
+      "local_only" => Ok(ExecutionStrategy::LocalOnly),
+      "remote_only" => Ok(ExecutionStrategy::RemoteOnly),
+      "remote_cache_only" => Ok(ExecutionStrategy::RemoteCacheOnly),
+      other => Err(format!("Unknown execution strategy: {:?}", other)),
+    }
+  }
+}
+
+impl Default for ExecutionStrategy {
+  fn default() -> ExecutionStrategy {
+    ExecutionStrategy::Default
+  }
+}
+
+///
+/// A CommandRunner which executes each request with the configured (default) runner, unless the
+/// ExecutionStrategy of the Context that it is executed in overrides it.
+///
+pub struct StrategyCommandRunner {
+  default: Box<dyn CommandRunner>,
+  local: Arc<dyn CommandRunner>,
+  remote: Option<remote::CommandRunner>,
+}
+
+impl StrategyCommandRunner {
+  pub fn new(
+    default: Box<dyn CommandRunner>,
+    local: Box<dyn CommandRunner>,
+    remote: Option<remote::CommandRunner>,
+  ) -> StrategyCommandRunner {
+    StrategyCommandRunner {
+      default,
+      local: local.into(),
+      remote,
+    }
+  }
+
+  fn remote(&self, strategy: ExecutionStrategy) -> Result<&remote::CommandRunner, String> {
+    self.remote.as_ref().ok_or_else(|| {
+      format!(
+        "The {:?} execution strategy requires a remote execution server.",
+        strategy
+      )
+    })
+  }
+}
+
+impl CommandRunner for StrategyCommandRunner {
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    let strategy = context.execution_strategy;
+    match strategy {
+      ExecutionStrategy::Default => self.default.run(req, context),
+      ExecutionStrategy::LocalOnly => self.local.run(req, context),
+      ExecutionStrategy::RemoteOnly => match self.remote(strategy) {
+        Ok(remote) => remote.run(req, context),
+        Err(e) => future::err(e).to_boxed(),
+      },
+      ExecutionStrategy::RemoteCacheOnly => match self.remote(strategy) {
+        Ok(remote) => {
+          let local = self.local.clone();
+          remote
+            .lookup(&req)
+            .and_then(move |cached| match cached {
+              Some(result) => future::ok(result).to_boxed(),
+              None => local.run(req, context),
+            })
+            .to_boxed()
+        }
+        Err(e) => future::err(e).to_boxed(),
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use boxfuture::{BoxFuture, Boxable};
+  use futures::{future, Future};
+  use testutil::{as_bytes, owned_string_vec};
+
+  use super::{ExecutionStrategy, StrategyCommandRunner};
+  use crate::{CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult};
+
+  struct NamedCommandRunner(&'static str);
+
+  impl CommandRunner for NamedCommandRunner {
+    fn run(
+      &self,
+      _req: ExecuteProcessRequest,
+      _context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      future::ok(FallibleExecuteProcessResult {
+        stdout: as_bytes(""),
+        stderr: as_bytes(""),
+        exit_code: 0,
+        output_directory: fs::EMPTY_DIGEST,
+        execution_attempts: vec![],
+        runner: self.0.to_owned(),
+        timed_out: None,
+      })
+      .to_boxed()
+    }
+  }
+
+  fn request() -> ExecuteProcessRequest {
+    ExecuteProcessRequest {
+      argv: owned_string_vec(&["true"]),
+      timeout: Duration::from_millis(1000),
+      description: "true".to_owned(),
+      ..ExecuteProcessRequest::default()
+    }
+  }
+
+  #[test]
+  fn strategy_is_read_from_the_context_of_each_request() {
+    let runner = StrategyCommandRunner::new(
+      Box::new(NamedCommandRunner("default")),
+      Box::new(NamedCommandRunner("local")),
+      None,
+    );
+    let runner_name = |execution_strategy| {
+      runner
+        .run(
+          request(),
+          Context {
+            execution_strategy,
+            ..Context::default()
+          },
+        )
+        .wait()
+        .map(|result| result.runner)
+    };
+
+    assert_eq!(
+      runner_name(ExecutionStrategy::Default),
+      Ok("default".to_owned())
+    );
+    assert_eq!(
+      runner_name(ExecutionStrategy::LocalOnly),
+      Ok("local".to_owned())
+    );
+    assert_eq!(
+      runner_name(ExecutionStrategy::RemoteOnly),
+      Err("The RemoteOnly execution strategy requires a remote execution server.".to_owned())
+    );
+    // Concurrent Sessions with different strategies share a runner without affecting one another.
+    assert_eq!(
+      runner_name(ExecutionStrategy::Default),
+      Ok("default".to_owned())
+    );
+  }
+
+  #[test]
+  fn parses_strategies() {
+    assert_eq!(
+      ExecutionStrategy::new("remote_cache_only"),
+      Ok(ExecutionStrategy::RemoteCacheOnly)
+    );
+    assert!(ExecutionStrategy::new("remote_first").is_err());
+  }
+}
@@ -0,0 +1,294 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use boxfuture::{BoxFuture, Boxable};
+use fs::Store;
+use futures::{future, Future};
+use hashing::Digest;
+use log::warn;
+use serde_derive::Serialize;
+use serde_json;
+
+use super::{CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult};
+
+// The (output) file to which strace writes its trace of a re-run process.
+const TRACE_FILE_NAME: &str = ".pants-unused-inputs.strace";
+
+///
+/// A record of the declared input files of a process which it never accessed.
+///
+#[derive(Debug, Serialize)]
+pub struct UnusedInputsEntry {
+  pub description: String,
+  pub argv: Vec<String>,
+  pub input_digest: Digest,
+  pub input_file_count: usize,
+  pub unused_inputs: Vec<PathBuf>,
+}
+
+///
+/// A CommandRunner wrapper which, after a process succeeds, re-runs it locally under strace to
+/// track which of its declared input files it accesses, and appends a JSON UnusedInputsEntry line
+/// to a report file for each process with input files that it never accessed.
+///
+/// The result of the (untraced) inner runner is always the one returned. Only Linux is supported.
+///
+pub struct UnusedInputsReportingCommandRunner {
+  inner: Box<dyn CommandRunner>,
+  local: Box<dyn CommandRunner>,
+  strace: PathBuf,
+  store: Store,
+  report: Arc<Mutex<File>>,
+}
+
+impl UnusedInputsReportingCommandRunner {
+  pub fn new(
+    inner: Box<dyn CommandRunner>,
+    local: Box<dyn CommandRunner>,
+    store: Store,
+    report_path: &Path,
+  ) -> Result<UnusedInputsReportingCommandRunner, String> {
+    if !cfg!(target_os = "linux") {
+      return Err("Reporting unused inputs is only supported on Linux.".to_owned());
+    }
+    let strace = env::var_os("PATH")
+      .and_then(|paths| {
+        env::split_paths(&paths)
+          .map(|path| path.join("strace"))
+          .find(|path| path.is_file())
+      })
+      .ok_or_else(|| "Reporting unused inputs requires strace to be on the PATH.".to_owned())?;
+    let report = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(report_path)
+      .map_err(|e| {
+        format!(
+          "Failed to open unused inputs report {:?}: {}",
+          report_path, e
+        )
+      })?;
+    Ok(UnusedInputsReportingCommandRunner {
+      inner,
+      local,
+      strace,
+      store,
+      report: Arc::new(Mutex::new(report)),
+    })
+  }
+
+  fn traced_request(&self, mut req: ExecuteProcessRequest) -> ExecuteProcessRequest {
+    let mut argv = vec![
+      self.strace.to_string_lossy().into_owned(),
+      "-f".to_owned(),
+      "-qq".to_owned(),
+      "-e".to_owned(),
+      "trace=file".to_owned(),
+      "-o".to_owned(),
+      TRACE_FILE_NAME.to_owned(),
+    ];
+    argv.extend(req.argv);
+    req.argv = argv;
+    req.output_files.insert(PathBuf::from(TRACE_FILE_NAME));
+    req.description = format!("Tracing inputs of {}", req.description);
+    req
+  }
+
+  ///
+  /// Re-runs the given request under strace, and returns its input files which were not accessed.
+  ///
+  fn unused_inputs(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<UnusedInputsEntry, String> {
+    let store = self.store.clone();
+    let store2 = self.store.clone();
+    let description = req.description.clone();
+    let argv = req.argv.clone();
+    let input_digest = req.input_files;
+    self
+      .local
+      .run(self.traced_request(req), context)
+      .and_then(move |result| {
+        if result.exit_code != 0 {
+          return future::err(format!(
+            "Traced process exited with code {}",
+            result.exit_code
+          ))
+          .to_boxed();
+        }
+        store.contents_for_directory(result.output_directory)
+      })
+      .and_then(move |outputs| {
+        let trace = outputs
+          .into_iter()
+          .find(|file_content| file_content.path == Path::new(TRACE_FILE_NAME))
+          .ok_or_else(|| "Traced process did not produce a trace".to_owned())?;
+        Ok(accessed_paths(&String::from_utf8_lossy(&trace.content)))
+      })
+      .and_then(move |accessed| {
+        store2
+          .contents_for_directory(input_digest)
+          .map(move |inputs| {
+            let input_file_count = inputs.len();
+            let unused_inputs = inputs
+              .into_iter()
+              .map(|file_content| file_content.path)
+              .filter(|path| !was_accessed(path, &accessed))
+              .collect();
+            UnusedInputsEntry {
+              description,
+              argv,
+              input_digest,
+              input_file_count,
+              unused_inputs,
+            }
+          })
+      })
+      .to_boxed()
+  }
+
+  fn record(report: &Mutex<File>, entry: &UnusedInputsEntry) {
+    let mut line = match serde_json::to_string(entry) {
+      Ok(line) => line,
+      Err(e) => {
+        warn!("Failed to serialize unused inputs entry: {}", e);
+        return;
+      }
+    };
+    line.push('\n');
+    // A single write per entry, so that concurrent entries are not interleaved.
+    let mut report = report.lock().unwrap();
+    if let Err(e) = report.write_all(line.as_bytes()) {
+      warn!("Failed to write unused inputs entry: {}", e);
+    }
+  }
+}
+
+impl CommandRunner for UnusedInputsReportingCommandRunner {
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    // A side-effecting process must not run twice, so it is not traced.
+    let traced = if req.input_files == fs::EMPTY_DIGEST || req.side_effecting {
+      None
+    } else {
+      Some(self.unused_inputs(req.clone(), context.clone()))
+    };
+    let report = self.report.clone();
+    self
+      .inner
+      .run(req, context)
+      .and_then(move |result| match traced {
+        Some(traced) if result.exit_code == 0 => traced
+          .then(move |entry| {
+            match entry {
+              Ok(ref entry) if entry.unused_inputs.is_empty() => {}
+              Ok(entry) => Self::record(&report, &entry),
+              Err(e) => warn!("Failed to report unused inputs: {}", e),
+            }
+            Ok(result)
+          })
+          .to_boxed(),
+        _ => future::ok(result).to_boxed(),
+      })
+      .to_boxed()
+  }
+}
+
+///
+/// Returns the paths which appear (as quoted string arguments) in the given strace output.
+///
+fn accessed_paths(trace: &str) -> BTreeSet<PathBuf> {
+  let mut paths = BTreeSet::new();
+  for line in trace.lines() {
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+      if c != '"' {
+        continue;
+      }
+      let mut path = String::new();
+      while let Some(c) = chars.next() {
+        match c {
+          '"' => break,
+          '\\' => {
+            if let Some(escaped) = chars.next() {
+              path.push(escaped);
+            }
+          }
+          c => path.push(c),
+        }
+      }
+      paths.insert(PathBuf::from(path));
+    }
+  }
+  paths
+}
+
+///
+/// True if the given input path (relative to the process' working directory) was accessed. Relative
+/// accesses must match the input exactly, while absolute accesses (which include the unknown path
+/// of the working directory) need only end with it.
+///
+fn was_accessed(input: &Path, accessed: &BTreeSet<PathBuf>) -> bool {
+  accessed.iter().any(|path| {
+    if path.is_absolute() {
+      path.ends_with(input)
+    } else {
+      path.strip_prefix(".").unwrap_or(path) == input
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeSet;
+  use std::path::{Path, PathBuf};
+
+  use super::{accessed_paths, was_accessed};
+
+  #[test]
+  fn parses_accessed_paths() {
+    let trace = r#"1234 execve("/usr/bin/cat", ["cat", "roland"], 0x7ffd /* 0 vars */) = 0
+1234 openat(AT_FDCWD, "/etc/ld.so.cache", O_RDONLY|O_CLOEXEC) = 3
+1234 openat(AT_FDCWD, "sub dir/\"quoted\"", O_RDONLY) = -1 ENOENT (No such file or directory)
+1234 +++ exited with 0 +++
+"#;
+    let expected = vec![
+      "/usr/bin/cat",
+      "cat",
+      "roland",
+      "/etc/ld.so.cache",
+      "sub dir/\"quoted\"",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect::<BTreeSet<_>>();
+    assert_eq!(accessed_paths(trace), expected);
+  }
+
+  #[test]
+  fn matches_relative_and_absolute_accesses() {
+    let accessed = vec![
+      "/tmp/process-execution-1/src/roland",
+      "./treats",
+      "other/catnip",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect::<BTreeSet<_>>();
+    assert!(was_accessed(Path::new("src/roland"), &accessed));
+    assert!(was_accessed(Path::new("treats"), &accessed));
+    assert!(was_accessed(Path::new("other/catnip"), &accessed));
+    assert!(!was_accessed(Path::new("catnip"), &accessed));
+    assert!(!was_accessed(Path::new("src/treats"), &accessed));
+    assert!(!was_accessed(Path::new("robin"), &accessed));
+  }
+}
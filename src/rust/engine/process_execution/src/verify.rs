@@ -0,0 +1,487 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use boxfuture::{BoxFuture, Boxable};
+use bytes::Bytes;
+use digest::{Digest as DigestTrait, FixedOutput};
+use fs::Store;
+use futures::{future, Future};
+use hashing::{Digest, Fingerprint};
+use log::warn;
+use serde_derive::Serialize;
+use serde_json;
+use sha2::Sha256;
+
+use super::{CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult};
+
+// Files larger than this (or which are not UTF8) are reported by digest, without a diff.
+const MAX_DIFF_BYTES: usize = 64 * 1024;
+
+///
+/// The outcome of executing a process with one of the runners being compared.
+///
+#[derive(Debug, Default, Serialize)]
+pub struct VerifiedOutcome {
+  pub exit_code: Option<i32>,
+  pub output_digest: Option<Digest>,
+  pub error: Option<String>,
+}
+
+impl VerifiedOutcome {
+  pub(crate) fn new(result: &Result<FallibleExecuteProcessResult, String>) -> VerifiedOutcome {
+    match result {
+      Ok(result) => VerifiedOutcome {
+        exit_code: Some(result.exit_code),
+        output_digest: Some(result.output_directory),
+        error: None,
+      },
+      Err(e) => VerifiedOutcome {
+        error: Some(e.clone()),
+        ..VerifiedOutcome::default()
+      },
+    }
+  }
+}
+
+///
+/// A file which differed between the outputs of the runners being compared. A digest of None
+/// indicates that the file was not output by that runner.
+///
+#[derive(Debug, Serialize)]
+pub struct FileDifference {
+  pub path: PathBuf,
+  pub local_digest: Option<Digest>,
+  pub remote_digest: Option<Digest>,
+  // The lines which were removed ("-") or added ("+") by the remote runner, if both versions of
+  // the file were small enough UTF8 to diff.
+  pub diff: Option<String>,
+}
+
+///
+/// A record of a process which produced different results when executed locally and remotely.
+///
+#[derive(Debug, Serialize)]
+pub struct VerificationMismatch {
+  pub description: String,
+  pub argv: Vec<String>,
+  pub input_digest: Digest,
+  pub local: VerifiedOutcome,
+  pub remote: VerifiedOutcome,
+  pub differing_files: Vec<FileDifference>,
+}
+
+///
+/// A CommandRunner which executes each request both locally and remotely, compares their exit
+/// codes and output digests, and appends a JSON VerificationMismatch line to a report file for each
+/// request whose results differed.
+///
+/// The local result is always the one returned, so that a build behaves as it would without
+/// remote execution while the remote results are verified.
+///
+pub struct VerifyingCommandRunner {
+  local: Box<dyn CommandRunner>,
+  remote: Box<dyn CommandRunner>,
+  store: Store,
+  report: Arc<Mutex<File>>,
+}
+
+impl VerifyingCommandRunner {
+  pub fn new(
+    local: Box<dyn CommandRunner>,
+    remote: Box<dyn CommandRunner>,
+    store: Store,
+    report_path: &Path,
+  ) -> Result<VerifyingCommandRunner, String> {
+    let report = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(report_path)
+      .map_err(|e| {
+        format!(
+          "Failed to open verification report {:?}: {}",
+          report_path, e
+        )
+      })?;
+    Ok(VerifyingCommandRunner {
+      local,
+      remote,
+      store,
+      report: Arc::new(Mutex::new(report)),
+    })
+  }
+
+  fn record(report: &Mutex<File>, mismatch: &VerificationMismatch) {
+    let mut line = match serde_json::to_string(mismatch) {
+      Ok(line) => line,
+      Err(e) => {
+        warn!("Failed to serialize verification mismatch: {}", e);
+        return;
+      }
+    };
+    line.push('\n');
+    // A single write per mismatch, so that concurrent mismatches are not interleaved.
+    let mut report = report.lock().unwrap();
+    if let Err(e) = report.write_all(line.as_bytes()) {
+      warn!("Failed to write verification mismatch: {}", e);
+    }
+  }
+
+  ///
+  /// Loads the files of each (present) output directory, and returns those which differ.
+  ///
+  fn differing_files(
+    store: &Store,
+    local_output: Option<Digest>,
+    remote_output: Option<Digest>,
+  ) -> BoxFuture<Vec<FileDifference>, String> {
+    compare_outputs(store, local_output, remote_output)
+      .map(|differences| {
+        differences
+          .into_iter()
+          .map(|(path, local_content, remote_content)| FileDifference {
+            diff: match (&local_content, &remote_content) {
+              (Some(local), Some(remote)) => diff(local, remote),
+              _ => None,
+            },
+            local_digest: local_content.as_ref().map(|content| digest_of(content)),
+            remote_digest: remote_content.as_ref().map(|content| digest_of(content)),
+            path,
+          })
+          .collect()
+      })
+      .to_boxed()
+  }
+}
+
+///
+/// Loads the files of each (present) output directory, and returns the path and (if present) the
+/// two versions of each file which differs, sorted by path.
+///
+pub(crate) fn compare_outputs(
+  store: &Store,
+  first_output: Option<Digest>,
+  second_output: Option<Digest>,
+) -> BoxFuture<Vec<(PathBuf, Option<Bytes>, Option<Bytes>)>, String> {
+  let load = |output: Option<Digest>| match output {
+    Some(digest) => store
+      .contents_for_directory(digest)
+      .map(|contents| {
+        contents
+          .into_iter()
+          .map(|file_content| (file_content.path, file_content.content))
+          .collect::<BTreeMap<_, _>>()
+      })
+      .to_boxed(),
+    None => future::ok(BTreeMap::new()).to_boxed(),
+  };
+  load(first_output)
+    .join(load(second_output))
+    .map(|(first_files, mut second_files)| {
+      let mut differences = Vec::new();
+      for (path, first_content) in first_files {
+        match second_files.remove(&path) {
+          Some(ref second_content) if *second_content == first_content => {}
+          second_content => differences.push((path, Some(first_content), second_content)),
+        }
+      }
+      for (path, second_content) in second_files {
+        differences.push((path, None, Some(second_content)));
+      }
+      differences.sort_by(|l, r| l.0.cmp(&r.0));
+      differences
+    })
+    .to_boxed()
+}
+
+impl CommandRunner for VerifyingCommandRunner {
+  fn run(
+    &self,
+    req: ExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    if req.side_effecting {
+      // A side-effecting process must not run twice, so it is not verified.
+      return self.local.run(req, context);
+    }
+    let description = req.description.clone();
+    let argv = req.argv.clone();
+    let input_digest = req.input_files;
+    let store = self.store.clone();
+    let report = self.report.clone();
+    self
+      .local
+      .run(req.clone(), context.clone())
+      .then(Ok::<_, String>)
+      .join(self.remote.run(req, context).then(Ok::<_, String>))
+      .and_then(move |(local_result, remote_result)| {
+        let local = VerifiedOutcome::new(&local_result);
+        let remote = VerifiedOutcome::new(&remote_result);
+        if local.exit_code == remote.exit_code
+          && local.output_digest == remote.output_digest
+          && local.error.is_some() == remote.error.is_some()
+        {
+          return future::result(local_result).to_boxed();
+        }
+        Self::differing_files(&store, local.output_digest, remote.output_digest)
+          .then(move |differing_files| {
+            let differing_files = differing_files.unwrap_or_else(|e| {
+              warn!("Failed to compare outputs of {}: {}", description, e);
+              vec![]
+            });
+            Self::record(
+              &report,
+              &VerificationMismatch {
+                description,
+                argv,
+                input_digest,
+                local,
+                remote,
+                differing_files,
+              },
+            );
+            local_result
+          })
+          .to_boxed()
+      })
+      .to_boxed()
+  }
+}
+
+pub(crate) fn digest_of(bytes: &Bytes) -> Digest {
+  let mut hasher = Sha256::default();
+  hasher.input(bytes);
+  Digest(
+    Fingerprint::from_bytes_unsafe(&hasher.fixed_result()),
+    bytes.len(),
+  )
+}
+
+///
+/// Returns the lines which would need to be removed from local and added to remote to produce
+/// remote, if both are small enough UTF8 to diff.
+///
+pub(crate) fn diff(local: &[u8], remote: &[u8]) -> Option<String> {
+  if local.len() > MAX_DIFF_BYTES || remote.len() > MAX_DIFF_BYTES {
+    return None;
+  }
+  let local = std::str::from_utf8(local).ok()?.lines().collect::<Vec<_>>();
+  let remote = std::str::from_utf8(remote)
+    .ok()?
+    .lines()
+    .collect::<Vec<_>>();
+
+  // The length of the longest common subsequence of each pair of suffixes.
+  let mut lcs = vec![vec![0; remote.len() + 1]; local.len() + 1];
+  for i in (0..local.len()).rev() {
+    for j in (0..remote.len()).rev() {
+      lcs[i][j] = if local[i] == remote[j] {
+        lcs[i + 1][j + 1] + 1
+      } else {
+        lcs[i + 1][j].max(lcs[i][j + 1])
+      };
+    }
+  }
+
+  let mut diff = String::new();
+  let (mut i, mut j) = (0, 0);
+  while i < local.len() || j < remote.len() {
+    if i < local.len() && j < remote.len() && local[i] == remote[j] {
+      i += 1;
+      j += 1;
+    } else if j == remote.len() || (i < local.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+      diff.push_str(&format!("-{}\n", local[i]));
+      i += 1;
+    } else {
+      diff.push_str(&format!("+{}\n", remote[j]));
+      j += 1;
+    }
+  }
+  Some(diff)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::read_to_string;
+  use std::path::Path;
+  use std::time::Duration;
+
+  use boxfuture::{BoxFuture, Boxable};
+  use fs::Store;
+  use futures::{future, Future};
+  use hashing::Digest;
+  use serde_json::{self, Value};
+  use tempfile::TempDir;
+  use testutil::data::{TestData, TestDirectory};
+  use testutil::{as_bytes, owned_string_vec};
+
+  use super::{diff, VerifyingCommandRunner};
+  use crate::{
+    CommandRunner, Context, ExecuteProcessRequest, ExecutionStats, FallibleExecuteProcessResult,
+  };
+
+  struct FixedCommandRunner {
+    exit_code: i32,
+    output_directory: Digest,
+    runner: &'static str,
+  }
+
+  impl CommandRunner for FixedCommandRunner {
+    fn run(
+      &self,
+      _req: ExecuteProcessRequest,
+      _context: Context,
+    ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+      future::ok(FallibleExecuteProcessResult {
+        stdout: as_bytes(""),
+        stderr: as_bytes(""),
+        exit_code: self.exit_code,
+        output_directory: self.output_directory,
+        execution_attempts: vec![ExecutionStats::default()],
+        runner: self.runner.to_owned(),
+        timed_out: None,
+      })
+      .to_boxed()
+    }
+  }
+
+  fn runner(
+    store: &Store,
+    report_path: &Path,
+    local: TestDirectory,
+    remote: TestDirectory,
+  ) -> VerifyingCommandRunner {
+    VerifyingCommandRunner::new(
+      Box::new(FixedCommandRunner {
+        exit_code: 0,
+        output_directory: local.digest(),
+        runner: "local",
+      }),
+      Box::new(FixedCommandRunner {
+        exit_code: 0,
+        output_directory: remote.digest(),
+        runner: "remote",
+      }),
+      store.clone(),
+      report_path,
+    )
+    .unwrap()
+  }
+
+  fn request() -> ExecuteProcessRequest {
+    ExecuteProcessRequest {
+      argv: owned_string_vec(&["cat", "roland"]),
+      input_files: TestDirectory::containing_roland().digest(),
+      timeout: Duration::from_millis(1000),
+      description: "cat roland".to_owned(),
+      ..ExecuteProcessRequest::default()
+    }
+  }
+
+  fn new_store(dir: &TempDir) -> Store {
+    let store = Store::local_only(dir.path()).unwrap();
+    for data in &[TestData::roland(), TestData::catnip()] {
+      store.store_file_bytes(data.bytes(), false).wait().unwrap();
+    }
+    for directory in &[
+      TestDirectory::containing_roland(),
+      TestDirectory::containing_wrong_roland(),
+    ] {
+      store
+        .record_directory(&directory.directory(), false)
+        .wait()
+        .unwrap();
+    }
+    store
+  }
+
+  #[test]
+  fn matching_results_are_not_reported() {
+    let dir = TempDir::new().unwrap();
+    let store = new_store(&dir);
+    let report_path = dir.path().join("verify.jsonl");
+    let runner = runner(
+      &store,
+      &report_path,
+      TestDirectory::containing_roland(),
+      TestDirectory::containing_roland(),
+    );
+
+    let result = runner.run(request(), Context::default()).wait().unwrap();
+    assert_eq!(result.runner, "local");
+    assert_eq!(read_to_string(&report_path).unwrap(), "");
+  }
+
+  #[test]
+  fn side_effecting_requests_are_not_verified() {
+    let dir = TempDir::new().unwrap();
+    let store = new_store(&dir);
+    let report_path = dir.path().join("verify.jsonl");
+    let runner = runner(
+      &store,
+      &report_path,
+      TestDirectory::containing_roland(),
+      TestDirectory::containing_wrong_roland(),
+    );
+
+    let mut req = request();
+    req.side_effecting = true;
+    let result = runner.run(req, Context::default()).wait().unwrap();
+    assert_eq!(result.runner, "local");
+    assert_eq!(read_to_string(&report_path).unwrap(), "");
+  }
+
+  #[test]
+  fn mismatched_results_are_reported() {
+    let dir = TempDir::new().unwrap();
+    let store = new_store(&dir);
+    let report_path = dir.path().join("verify.jsonl");
+    let runner = runner(
+      &store,
+      &report_path,
+      TestDirectory::containing_roland(),
+      TestDirectory::containing_wrong_roland(),
+    );
+
+    let result = runner.run(request(), Context::default()).wait().unwrap();
+    assert_eq!(result.runner, "local");
+
+    let content = read_to_string(&report_path).unwrap();
+    let mismatches = content
+      .lines()
+      .map(|line| serde_json::from_str::<Value>(line).unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(mismatches.len(), 1);
+    let mismatch = &mismatches[0];
+    assert_eq!(mismatch["description"], "cat roland");
+    assert_eq!(
+      mismatch["remote"]["output_digest"]["fingerprint"],
+      TestDirectory::containing_wrong_roland()
+        .fingerprint()
+        .to_hex()
+    );
+    let differing_files = mismatch["differing_files"].as_array().unwrap();
+    assert_eq!(differing_files.len(), 1);
+    assert_eq!(differing_files[0]["path"], "roland");
+    assert_eq!(
+      differing_files[0]["diff"],
+      format!(
+        "-{}\n+{}\n",
+        TestData::roland().string(),
+        TestData::catnip().string()
+      )
+    );
+  }
+
+  #[test]
+  fn diff_lines() {
+    assert_eq!(
+      diff(b"a\nb\nc\n", b"a\nc\nd\n"),
+      Some("-b\n+d\n".to_owned())
+    );
+    assert_eq!(diff(b"a\n", b"a\n"), Some("".to_owned()));
+    assert_eq!(diff(&[0xff], b"a\n"), None);
+  }
+}
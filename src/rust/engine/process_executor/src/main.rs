@@ -41,6 +41,8 @@ use std::path::PathBuf;
 use std::process::exit;
 use std::time::Duration;
 
+mod probe;
+
 /// A binary which takes args of format:
 ///  process_executor --env=FOO=bar --env=SOME=value --input-digest=abc123 --input-digest-length=80
 ///    -- /path/to/binary --flag --otherflag
@@ -48,6 +50,10 @@ use std::time::Duration;
 /// It outputs its output/err to stdout/err, and exits with its exit code.
 ///
 /// It does not perform $PATH lookup or shell expansion.
+///
+/// Alternatively, `process_executor --probe --server=... --cas-server=...` runs a matrix of small
+/// actions against a remote execution cluster, and prints a report of its capabilities and
+/// latencies.
 fn main() {
   env_logger::init();
 
@@ -68,14 +74,14 @@ fn main() {
       Arg::with_name("input-digest")
         .long("input-digest")
         .takes_value(true)
-        .required(true)
+        .required_unless("probe")
         .help("Fingerprint (hex string) of the digest to use as the input file tree."),
     )
     .arg(
       Arg::with_name("input-digest-length")
         .long("input-digest-length")
         .takes_value(true)
-        .required(true)
+        .required_unless("probe")
         .help("Length of the proto-bytes whose digest to use as the input file tree."),
     )
     .arg(
@@ -129,6 +135,21 @@ fn main() {
           .takes_value(true)
           .long("cache-key-gen-version")
           .required(false))
+      .arg(Arg::with_name("skip-cache-lookup")
+          .help("Execute remotely even if a result exists in the ActionCache (which will be replaced by the new result).")
+          .takes_value(false)
+          .long("skip-cache-lookup")
+          .required(false))
+      .arg(Arg::with_name("read-only-action-cache")
+          .help("Never write results to the ActionCache: look up cached results directly, and execute uncacheably on a miss.")
+          .takes_value(false)
+          .long("read-only-action-cache")
+          .required(false))
+      .arg(Arg::with_name("verify-cache-hits")
+          .help("Check that the CAS has the outputs of a cached result before using it, and execute the process instead if it does not.")
+          .takes_value(false)
+          .long("verify-cache-hits")
+          .required(false))
       .arg(
         Arg::with_name("upload-chunk-bytes")
             .help("Number of bytes to include per-chunk when uploading bytes. grpc imposes a hard message-size limit of around 4MB.")
@@ -163,7 +184,7 @@ fn main() {
       Arg::with_name("argv")
         .multiple(true)
         .last(true)
-        .required(true),
+        .required_unless("probe"),
     )
     .arg(
         Arg::with_name("output-file-path")
@@ -188,13 +209,27 @@ fn main() {
           .required(false)
           .help("The name of a directory (which may or may not exist), where the output tree will be materialized.")
     )
+    .arg(
+      Arg::with_name("dry-run")
+          .long("dry-run")
+          .takes_value(false)
+          .required(false)
+          .help("Rather than executing the process, print the Action and Command protos (and their digests) which would be sent to execute it remotely.")
+    )
+    .arg(
+      Arg::with_name("probe")
+          .long("probe")
+          .takes_value(false)
+          .required(false)
+          .requires_all(&["server", "cas-server"])
+          .help("Rather than executing a process, run a matrix of small actions (a CAS round-trip, a large blob upload, executing echo, an action cache hit and a timeout) against the remote cluster, and print a report of its capabilities and latencies. Exits non-zero if any fail.")
+    )
     .get_matches();
 
   let argv: Vec<String> = args
     .values_of("argv")
-    .unwrap()
-    .map(str::to_string)
-    .collect();
+    .map(|values| values.map(str::to_string).collect())
+    .unwrap_or_default();
   let env = args
     .values_of("env")
     .map(btreemap_from_keyvalues)
@@ -214,6 +249,7 @@ fn main() {
   let timer_thread = resettable::Resettable::new(|| futures_timer::HelperThread::new().unwrap());
   let server_arg = args.value_of("server");
   let remote_instance_arg = args.value_of("remote-instance-name").map(str::to_owned);
+  let proxies = fs::ProxyConfig::from_env().expect("Invalid proxy environment variables");
   let output_files = if let Some(values) = args.values_of("output-file-path") {
     values.map(PathBuf::from).collect()
   } else {
@@ -225,63 +261,54 @@ fn main() {
     BTreeSet::new()
   };
 
-  let store = match (server_arg, args.value_of("cas-server")) {
-    (Some(_server), Some(cas_server)) => {
-      let chunk_size =
-        value_t!(args.value_of("upload-chunk-bytes"), usize).expect("Bad upload-chunk-bytes flag");
+  let make_store = |local_store_path: PathBuf| {
+    match (server_arg, args.value_of("cas-server")) {
+      (Some(_server), Some(cas_server)) => {
+        let chunk_size = value_t!(args.value_of("upload-chunk-bytes"), usize)
+          .expect("Bad upload-chunk-bytes flag");
 
-      let root_ca_certs = if let Some(path) = args.value_of("cas-root-ca-cert-file") {
-        Some(std::fs::read(path).expect("Error reading root CA certs file"))
-      } else {
-        None
-      };
+        let root_ca_certs = if let Some(path) = args.value_of("cas-root-ca-cert-file") {
+          Some(std::fs::read(path).expect("Error reading root CA certs file"))
+        } else {
+          None
+        };
 
-      let oauth_bearer_token = if let Some(path) = args.value_of("cas-oauth-bearer-token-path") {
-        Some(std::fs::read_to_string(path).expect("Error reading oauth bearer token file"))
-      } else {
-        None
-      };
+        let oauth_bearer_token = if let Some(path) = args.value_of("cas-oauth-bearer-token-path") {
+          Some(std::fs::read_to_string(path).expect("Error reading oauth bearer token file"))
+        } else {
+          None
+        };
 
-      fs::Store::with_remote(
-        local_store_path,
-        &[cas_server.to_owned()],
-        remote_instance_arg.clone(),
-        &root_ca_certs,
-        oauth_bearer_token,
-        1,
-        chunk_size,
-        Duration::from_secs(30),
-        // TODO: Take a command line arg.
-        fs::BackoffConfig::new(Duration::from_secs(1), 1.2, Duration::from_secs(20)).unwrap(),
-        3,
-        timer_thread.with(futures_timer::HelperThread::handle),
-      )
+        fs::Store::with_remote(
+          local_store_path,
+          &[cas_server.to_owned()],
+          &[],
+          remote_instance_arg.clone(),
+          &root_ca_certs,
+          &proxies,
+          oauth_bearer_token,
+          1,
+          chunk_size,
+          Duration::from_secs(30),
+          // TODO: Take a command line arg.
+          fs::BackoffConfig::new(Duration::from_secs(1), 1.2, Duration::from_secs(20)).unwrap(),
+          3,
+          timer_thread.with(futures_timer::HelperThread::handle),
+        )
+      }
+      (None, None) => fs::Store::local_only(local_store_path),
+      _ => panic!("Must specify either both --server and --cas-server or neither."),
     }
-    (None, None) => fs::Store::local_only(local_store_path),
-    _ => panic!("Must specify either both --server and --cas-server or neither."),
-  }
-  .expect("Error making store");
-
-  let input_files = {
-    let fingerprint = Fingerprint::from_hex_string(args.value_of("input-digest").unwrap())
-      .expect("Bad input-digest");
-    let length = args
-      .value_of("input-digest-length")
-      .unwrap()
-      .parse::<usize>()
-      .expect("input-digest-length must be a non-negative number");
-    Digest(fingerprint, length)
+    .expect("Error making store")
   };
-
-  let request = process_execution::ExecuteProcessRequest {
-    argv,
-    env,
-    input_files,
-    output_files,
-    output_directories,
-    timeout: Duration::new(15 * 60, 0),
-    description: "process_executor".to_string(),
-    jdk_home: args.value_of("jdk").map(PathBuf::from),
+  let store = make_store(local_store_path);
+  // Reads from a store with an empty local store must be satisfied by the remote CAS.
+  let probe_store_path =
+    std::env::temp_dir().join(format!("process_executor_probe_{}", std::process::id()));
+  let probe_store = if args.is_present("probe") {
+    Some(make_store(probe_store_path.clone()))
+  } else {
+    None
   };
 
   let runner: Box<dyn process_execution::CommandRunner> = match server_arg {
@@ -302,10 +329,15 @@ fn main() {
       Box::new(process_execution::remote::CommandRunner::new(
         address,
         args.value_of("cache-key-gen-version").map(str::to_owned),
-        remote_instance_arg,
+        remote_instance_arg.clone(),
         root_ca_certs,
+        &proxies,
         oauth_bearer_token,
-        platform_properties,
+        platform_properties.clone(),
+        BTreeSet::new(),
+        args.is_present("skip-cache-lookup"),
+        !args.is_present("read-only-action-cache"),
+        args.is_present("verify-cache-hits"),
         1,
         store.clone(),
         timer_thread,
@@ -318,7 +350,72 @@ fn main() {
     )) as Box<dyn process_execution::CommandRunner>,
   };
 
-  let result = runner.run(request).wait().expect("Error executing");
+  if let Some(probe_store) = probe_store {
+    let results = probe::probe(&store, &probe_store, runner.as_ref());
+    drop(probe_store);
+    let _ = std::fs::remove_dir_all(&probe_store_path);
+    exit(if probe::print_report(&results) { 0 } else { 1 });
+  }
+
+  let input_files = {
+    let fingerprint = Fingerprint::from_hex_string(args.value_of("input-digest").unwrap())
+      .expect("Bad input-digest");
+    let length = args
+      .value_of("input-digest-length")
+      .unwrap()
+      .parse::<usize>()
+      .expect("input-digest-length must be a non-negative number");
+    Digest(fingerprint, length)
+  };
+
+  let request = process_execution::ExecuteProcessRequest {
+    argv,
+    env,
+    input_files,
+    output_files,
+    output_directories,
+    description: "process_executor".to_string(),
+    jdk_home: args.value_of("jdk").map(PathBuf::from),
+    ..process_execution::ExecuteProcessRequest::default()
+  };
+
+  if args.is_present("dry-run") {
+    let dry_run = process_execution::remote::dry_run(
+      &request,
+      &process_execution::ExecuteProcessRequestMetadata {
+        instance_name: remote_instance_arg,
+        cache_key_gen_version: args.value_of("cache-key-gen-version").map(str::to_owned),
+        platform_properties,
+        ..process_execution::ExecuteProcessRequestMetadata::default()
+      },
+    )
+    .expect("Error constructing remote execution request");
+    println!(
+      "Action digest: {}/{}",
+      dry_run.action_digest.0.to_hex(),
+      dry_run.action_digest.1
+    );
+    println!(
+      "Command digest: {}/{}",
+      dry_run.command_digest.0.to_hex(),
+      dry_run.command_digest.1
+    );
+    println!(
+      "Input root digest: {}/{}",
+      dry_run.input_root_digest.0.to_hex(),
+      dry_run.input_root_digest.1
+    );
+    println!("Serialized action: {}", dry_run.serialized_action);
+    println!("Serialized command: {}", dry_run.serialized_command);
+    println!("Action:\n{}", dry_run.action);
+    println!("Command:\n{}", dry_run.command);
+    exit(0);
+  }
+
+  let result = runner
+    .run(request, process_execution::Context::default())
+    .wait()
+    .expect("Error executing");
 
   if let Some(output) = args.value_of("materialize-output-to").map(PathBuf::from) {
     store
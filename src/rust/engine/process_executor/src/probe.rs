@@ -0,0 +1,189 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::future::Future;
+use process_execution::{
+  CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult,
+};
+
+// Larger than the default upload chunk size, so that the upload is chunked.
+const LARGE_BLOB_BYTES: usize = 16 * 1024 * 1024;
+
+// The timeout for the timeout probe, which runs a process that would otherwise take much longer.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+///
+/// The outcome of one probe of a remote execution cluster.
+///
+pub struct ProbeResult {
+  pub name: &'static str,
+  pub elapsed: Duration,
+  pub outcome: Result<String, String>,
+}
+
+///
+/// Runs a matrix of small actions against a remote execution cluster, to validate that it
+/// supports what the engine requires of it.
+///
+/// The `store` and `runner` should be configured for the cluster, and `fresh_store` should use the
+/// same remote CAS as the `store`, but with an empty local store, so that reads from it must be
+/// satisfied remotely.
+///
+pub fn probe(
+  store: &fs::Store,
+  fresh_store: &fs::Store,
+  runner: &dyn CommandRunner,
+) -> Vec<ProbeResult> {
+  // A nonce which makes the blobs and actions of this run unique, so that they miss any caches.
+  let nonce = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_nanos())
+    .unwrap_or(0);
+
+  let echo = request(
+    vec!["/bin/echo".to_owned(), format!("probe-{}", nonce)],
+    Duration::from_secs(5 * 60),
+  );
+
+  vec![
+    timed("cas round-trip", || {
+      let content = Bytes::from(format!("process_executor probe {}", nonce));
+      let digest = upload(store, content.clone())?;
+      match fresh_store
+        .load_file_bytes_with(digest, |bytes| bytes)
+        .wait()?
+      {
+        Some(ref bytes) if *bytes == content => Ok(format!("{} bytes", digest.1)),
+        Some(_) => Err(format!("Downloaded content of {:?} did not match.", digest)),
+        None => Err(format!("Uploaded {:?} was not found.", digest)),
+      }
+    }),
+    timed("large blob upload", || {
+      let digest = upload(store, large_blob(nonce))?;
+      Ok(format!("{} bytes", digest.1))
+    }),
+    timed("execute echo", || {
+      let result = runner.run(echo.clone(), Context::default()).wait()?;
+      check_echo(&result, nonce)?;
+      if result.was_cache_hit() {
+        return Err("Unexpectedly hit the action cache for a unique action.".to_owned());
+      }
+      Ok(format!("ran on {}", result.runner))
+    }),
+    timed("action cache round-trip", || {
+      let result = runner.run(echo.clone(), Context::default()).wait()?;
+      check_echo(&result, nonce)?;
+      if result.was_cache_hit() {
+        Ok("cache hit".to_owned())
+      } else {
+        Err("Re-running an identical action did not hit the action cache.".to_owned())
+      }
+    }),
+    timed("timeout", || {
+      // The duration varies so that the action is unique, although timed out actions should never
+      // be cached anyway.
+      let sleep = request(
+        vec!["/bin/sleep".to_owned(), format!("{}", 60 + nonce % 60)],
+        PROBE_TIMEOUT,
+      );
+      let result = runner.run(sleep, Context::default()).wait()?;
+      match result.timed_out {
+        Some(timed_out) => Ok(format!(
+          "timed out after {:.1}s (timeout {:.1}s)",
+          duration_secs(timed_out.elapsed),
+          duration_secs(timed_out.timeout)
+        )),
+        None => Err(format!(
+          "A process exceeding its timeout exited with {} rather than timing out.",
+          result.exit_code
+        )),
+      }
+    }),
+  ]
+}
+
+///
+/// Prints a report of the given ProbeResults, and returns true if they all passed.
+///
+pub fn print_report(results: &[ProbeResult]) -> bool {
+  let name_width = results.iter().map(|r| r.name.len()).max().unwrap_or(0);
+  for result in results {
+    let (status, detail) = match result.outcome {
+      Ok(ref detail) => ("ok", detail),
+      Err(ref detail) => ("FAILED", detail),
+    };
+    println!(
+      "{:<width$}  {:<6}  {:>8.3}s  {}",
+      result.name,
+      status,
+      duration_secs(result.elapsed),
+      detail,
+      width = name_width
+    );
+  }
+  results.iter().all(|r| r.outcome.is_ok())
+}
+
+fn timed<F: FnOnce() -> Result<String, String>>(name: &'static str, f: F) -> ProbeResult {
+  let start = Instant::now();
+  let outcome = f();
+  ProbeResult {
+    name,
+    elapsed: start.elapsed(),
+    outcome,
+  }
+}
+
+fn upload(store: &fs::Store, content: Bytes) -> Result<hashing::Digest, String> {
+  let digest = store.store_file_bytes(content, false).wait()?;
+  store.ensure_remote_has_recursive(vec![digest]).wait()?;
+  Ok(digest)
+}
+
+fn large_blob(nonce: u128) -> Bytes {
+  // A cheap pseudo-random sequence (xorshift), so that the blob is unique to this run and does not
+  // compress away in transit.
+  let mut state = (nonce as u64) | 1;
+  let mut content = Vec::with_capacity(LARGE_BLOB_BYTES);
+  while content.len() < LARGE_BLOB_BYTES {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    content.extend_from_slice(&state.to_le_bytes());
+  }
+  Bytes::from(content)
+}
+
+fn request(argv: Vec<String>, timeout: Duration) -> ExecuteProcessRequest {
+  ExecuteProcessRequest {
+    argv,
+    timeout,
+    description: "process_executor probe".to_owned(),
+    ..ExecuteProcessRequest::default()
+  }
+}
+
+fn check_echo(result: &FallibleExecuteProcessResult, nonce: u128) -> Result<(), String> {
+  let expected = format!("probe-{}\n", nonce);
+  if result.exit_code != 0 {
+    Err(format!(
+      "Exited with {}: {}",
+      result.exit_code,
+      String::from_utf8_lossy(&result.stderr)
+    ))
+  } else if result.stdout != expected.as_bytes() {
+    Err(format!(
+      "Unexpected stdout: {:?}",
+      String::from_utf8_lossy(&result.stdout)
+    ))
+  } else {
+    Ok(())
+  }
+}
+
+fn duration_secs(duration: Duration) -> f64 {
+  duration.as_secs() as f64 + f64::from(duration.subsec_millis()) / 1000.0
+}
@@ -8,27 +8,67 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use tokio::runtime::Runtime;
+use tokio::runtime::{self, Runtime};
 
 use futures::Future;
 
 use crate::core::{Failure, TypeId};
 use crate::handles::maybe_drop_handles;
+use crate::history::DurationHistory;
 use crate::nodes::{NodeKey, WrappedNode};
+use crate::panics;
 use crate::rule_graph::RuleGraph;
 use crate::tasks::Tasks;
 use crate::types::Types;
+use crate::watch::FileDigestCache;
 use boxfuture::{BoxFuture, Boxable};
 use core::clone::Clone;
-use fs::{self, safe_create_dir_all_ioerror, PosixFS, Store};
-use graph::{EntryId, Graph, NodeContext};
-use log::debug;
+use fs::{
+  self, safe_create_dir_all_ioerror, Pools, PosixFS, ProxyConfig, RemoteStoreTimeouts,
+  SnapshotSizeLimits, Store, TransferLimits, UnicodeNormalization,
+};
+use graph::{Cancellation, EntryId, Graph, InvalidationResult, NodeContext};
+use log::{debug, info, warn};
 use parking_lot::RwLock;
-use process_execution::{self, BoundedCommandRunner, CommandRunner};
+use process_execution::artifacts::{ArtifactIndex, ArtifactIndexingCommandRunner};
+use process_execution::audit::{AuditLogCommandRunner, AuditLogConfig};
+use process_execution::determinism::{DeterminismCheckConfig, DeterminismCheckingCommandRunner};
+use process_execution::hermeticity::HermeticityLint;
+use process_execution::jdk::JdkRegistry;
+use process_execution::limits::{ProcessCountLimitCommandRunner, ProcessCountLimits};
+use process_execution::provenance::ProvenanceRecordingCommandRunner;
+use process_execution::remote::{DryRun, ExecutionTimeouts};
+use process_execution::strategy::{ExecutionStrategy, StrategyCommandRunner};
+use process_execution::unused_inputs::UnusedInputsReportingCommandRunner;
+use process_execution::verify::VerifyingCommandRunner;
+use process_execution::{
+  self, BoundedCommandRunner, CommandRunner, DefaultOutputLimitCommandRunner,
+  ExecuteProcessRequest, IdempotencyTokenCommandRunner, OutputLimit, ReproducibleEnvCommandRunner,
+};
 use rand::seq::SliceRandom;
 use reqwest;
 use resettable::Resettable;
 use std::collections::btree_map::BTreeMap;
+use std::collections::{BTreeSet, HashSet};
+
+///
+/// Creates an HTTP client which routes requests via the given proxies, unless their host is
+/// excluded by `no_proxy`. Requests for https URLs are tunneled through their proxy with CONNECT.
+///
+fn http_client_with_proxies(proxies: &ProxyConfig) -> Result<reqwest::r#async::Client, String> {
+  for proxy in proxies.urls() {
+    reqwest::Url::parse(proxy).map_err(|e| format!("Invalid proxy {:?}: {}", proxy, e))?;
+  }
+  let proxies = proxies.clone();
+  reqwest::r#async::Client::builder()
+    .proxy(reqwest::Proxy::custom(move |url| {
+      proxies
+        .proxy_for(url.scheme() == "https", url.host_str()?)
+        .and_then(|proxy| reqwest::Url::parse(proxy).ok())
+    }))
+    .build()
+    .map_err(|e| format!("Error building HTTP client: {}", e))
+}
 
 ///
 /// The core context shared (via Arc) between the Scheduler and the Context objects of
@@ -47,8 +87,34 @@ pub struct Core {
   pub futures_timer_thread: Resettable<futures_timer::HelperThread>,
   store_and_command_runner_and_http_client:
     Resettable<(Store, BoundedCommandRunner, reqwest::r#async::Client)>,
+  // The historical durations of work, which are used to estimate progress. Estimates are
+  // best-effort, so this is None if the history could not be opened.
+  duration_history: Resettable<Option<DurationHistory>>,
   pub vfs: PosixFS,
+  pub file_digests: FileDigestCache,
   pub build_root: PathBuf,
+  // If true, operations which need the network fail immediately rather than being attempted.
+  pub offline: bool,
+  // The configuration of local process execution, which is used directly for interactive processes.
+  work_dir: PathBuf,
+  process_execution_cleanup_local_dirs: bool,
+  // Whether processes are executed remotely (in which case their inputs must be uploaded).
+  pub remote_execution: bool,
+  // The configuration of remote execution which affects the protos that are sent for a process.
+  remote_instance_name: Option<String>,
+  remote_execution_process_cache_namespace: Option<String>,
+  remote_execution_extra_platform_properties: BTreeMap<String, String>,
+  remote_execution_allowed_platform_property_keys: BTreeSet<String>,
+  // The limits on the number of processes executed by each Session.
+  pub process_count_limits: ProcessCountLimits,
+  // The index of the processes which produced materialized files, if one was requested.
+  pub artifact_index: Option<Arc<ArtifactIndex>>,
+  // The default limits on the sizes of captured snapshots, which may be raised per-capture.
+  pub snapshot_size_limits: SnapshotSizeLimits,
+  // Set if the outputs of processes should be scanned for non-hermetic absolute paths.
+  pub hermeticity_lint: Option<HermeticityLint>,
+  // The JDKs from which one is selected for each process which requests a JDK by version spec.
+  pub jdks: JdkRegistry,
 }
 
 impl Core {
@@ -60,7 +126,11 @@ impl Core {
     ignore_patterns: &[String],
     work_dir: PathBuf,
     local_store_dir: PathBuf,
+    local_store_metadata_dir: Option<PathBuf>,
+    offline: bool,
+    proxies: ProxyConfig,
     remote_store_servers: Vec<String>,
+    remote_store_central_servers: Vec<String>,
     remote_execution_server: Option<String>,
     remote_execution_process_cache_namespace: Option<String>,
     remote_instance_name: Option<String>,
@@ -68,20 +138,67 @@ impl Core {
     remote_oauth_bearer_token_path: Option<PathBuf>,
     remote_store_thread_count: usize,
     remote_store_chunk_bytes: usize,
-    remote_store_chunk_upload_timeout: Duration,
+    remote_store_upload_window_chunks: usize,
+    remote_store_timeouts: RemoteStoreTimeouts,
     remote_store_rpc_retries: usize,
+    remote_store_read_only: bool,
+    remote_store_transfer_limits: TransferLimits,
     remote_execution_extra_platform_properties: BTreeMap<String, String>,
+    remote_execution_allowed_platform_property_keys: BTreeSet<String>,
+    remote_execution_skip_cache_lookup: bool,
+    remote_execution_action_cache_writes: bool,
+    remote_execution_verify_cache_hits: bool,
+    remote_execution_timeouts: ExecutionTimeouts,
+    remote_execution_verify_report: Option<PathBuf>,
     process_execution_parallelism: usize,
+    process_execution_memory_budget_mb: Option<usize>,
     process_execution_cleanup_local_dirs: bool,
+    process_execution_output_limit: Option<OutputLimit>,
+    process_execution_provenance_file: Option<PathBuf>,
+    process_execution_artifact_index: Option<PathBuf>,
+    process_execution_unused_inputs_report: Option<PathBuf>,
+    process_execution_count_limits: ProcessCountLimits,
+    process_execution_audit_log: Option<AuditLogConfig>,
+    process_execution_lint_hermeticity: bool,
+    process_execution_determinism_check: Option<DeterminismCheckConfig>,
+    process_execution_jdks: JdkRegistry,
+    snapshot_size_limits: SnapshotSizeLimits,
+    snapshot_unicode_normalization: UnicodeNormalization,
+    io_threads: usize,
+    cpu_threads: usize,
   ) -> Core {
     // Randomize CAS address order to avoid thundering herds from common config.
     let mut remote_store_servers = remote_store_servers;
     remote_store_servers.shuffle(&mut rand::thread_rng());
+    let mut remote_store_central_servers = remote_store_central_servers;
+    remote_store_central_servers.shuffle(&mut rand::thread_rng());
+
+    if remote_store_servers.is_empty() && !remote_store_central_servers.is_empty() {
+      panic!("A central remote store requires a nearby remote store to be configured.");
+    }
+    if remote_store_read_only && remote_execution_server.is_some() {
+      panic!("Remote execution requires uploading inputs, so cannot use a read-only remote store.");
+    }
+    if remote_execution_verify_report.is_some() && remote_execution_server.is_none() {
+      panic!("Verifying remote execution requires a remote execution server.");
+    }
+    if process_execution_determinism_check.is_some() && remote_execution_server.is_some() {
+      panic!("Checking the determinism of processes requires local execution.");
+    }
 
-    let runtime = Resettable::new(|| {
-      Arc::new(RwLock::new(Runtime::new().unwrap_or_else(|e| {
-        panic!("Could not initialize Runtime: {:?}", e)
-      })))
+    // Futures (and the CPU-bound work that they do inline) run on the core threads of the Runtime,
+    // while its blocking threads run filesystem operations (via tokio-fs). The local Store has
+    // pools of the same sizes for its own blocking work.
+    let io_threads = io_threads.max(1);
+    let cpu_threads = cpu_threads.max(1);
+    let runtime = Resettable::new(move || {
+      Arc::new(RwLock::new(
+        runtime::Builder::new()
+          .core_threads(cpu_threads)
+          .blocking_threads(io_threads)
+          .build()
+          .unwrap_or_else(|e| panic!("Could not initialize Runtime: {:?}", e)),
+      ))
     });
     // We re-use these certs for both the execution and store service; they're generally tied together.
     let root_ca_certs = if let Some(path) = remote_root_ca_certs_path {
@@ -105,6 +222,43 @@ impl Core {
 
     let futures_timer_thread = Resettable::new(|| futures_timer::HelperThread::new().unwrap());
     let futures_timer_thread2 = futures_timer_thread.clone();
+    let remote_instance_name2 = remote_instance_name.clone();
+    let remote_execution_process_cache_namespace2 =
+      remote_execution_process_cache_namespace.clone();
+    let remote_execution_extra_platform_properties2 =
+      remote_execution_extra_platform_properties.clone();
+    let remote_execution_allowed_platform_property_keys2 =
+      remote_execution_allowed_platform_property_keys.clone();
+    let work_dir2 = work_dir.clone();
+    let remote_execution = remote_execution_server.is_some();
+    let hermeticity_lint = if process_execution_lint_hermeticity {
+      let home = std::env::var_os("HOME").map(PathBuf::from);
+      Some(HermeticityLint::new(
+        &work_dir,
+        &build_root,
+        home.as_ref().map(PathBuf::as_path),
+      ))
+    } else {
+      None
+    };
+    let artifact_index = process_execution_artifact_index.map(|path| {
+      Arc::new(
+        ArtifactIndex::new(&path)
+          .unwrap_or_else(|e| panic!("Could not initialize artifact index: {}", e)),
+      )
+    });
+    let artifact_index2 = artifact_index.clone();
+    // Durations are recorded per-repo, so if the content of the store is shared between repos,
+    // they are recorded in the metadata directory of this repo.
+    let duration_history_dir = local_store_metadata_dir
+      .as_ref()
+      .unwrap_or(&local_store_dir)
+      .join("history");
+    let duration_history = Resettable::new(move || {
+      DurationHistory::new(&duration_history_dir)
+        .map_err(|e| warn!("Progress estimates will be unavailable: {}", e))
+        .ok()
+    });
     let store_and_command_runner_and_http_client = Resettable::new(move || {
       let local_store_dir = local_store_dir.clone();
       let store = safe_create_dir_all_ioerror(&local_store_dir)
@@ -116,46 +270,209 @@ impl Core {
             Store::with_remote(
               local_store_dir,
               &remote_store_servers,
+              &remote_store_central_servers,
               remote_instance_name.clone(),
               &root_ca_certs,
+              &proxies,
               oauth_bearer_token.clone(),
               remote_store_thread_count,
               remote_store_chunk_bytes,
-              remote_store_chunk_upload_timeout,
+              remote_store_timeouts.write,
               // TODO: Take a parameter
               fs::BackoffConfig::new(Duration::from_millis(10), 1.0, Duration::from_millis(10))
                 .unwrap(),
               remote_store_rpc_retries,
               futures_timer_thread2.with(futures_timer::HelperThread::handle),
             )
+            .map(|store| {
+              let store = store
+                .with_upload_window_chunks(remote_store_upload_window_chunks)
+                .with_transfer_limits(remote_store_transfer_limits)
+                .with_remote_timeouts(remote_store_timeouts);
+              if remote_store_read_only {
+                store.into_read_only_remote()
+              } else {
+                store
+              }
+            })
+          }
+        })
+        .and_then(|store| match local_store_metadata_dir {
+          Some(ref metadata_dir) => store.with_local_metadata_dir(metadata_dir),
+          None => Ok(store),
+        })
+        .map(|store| {
+          let store = store
+            .with_unicode_normalization(snapshot_unicode_normalization)
+            .with_pools(Pools::new(io_threads, cpu_threads));
+          if offline {
+            store.into_offline()
+          } else {
+            store
           }
         })
         .unwrap_or_else(|e| panic!("Could not initialize Store: {:?}", e));
 
-      let underlying_command_runner: Box<dyn CommandRunner> = match &remote_execution_server {
-        Some(ref address) => Box::new(process_execution::remote::CommandRunner::new(
+      let local_command_runner = || {
+        process_execution::local::CommandRunner::new(
+          store.clone(),
+          work_dir.clone(),
+          process_execution_cleanup_local_dirs,
+        )
+      };
+      let remote_command_runner = remote_execution_server.as_ref().map(|address| {
+        let remote_command_runner = process_execution::remote::CommandRunner::new(
           address,
           remote_execution_process_cache_namespace.clone(),
           remote_instance_name.clone(),
           root_ca_certs.clone(),
+          &proxies,
           oauth_bearer_token.clone(),
           remote_execution_extra_platform_properties.clone(),
+          remote_execution_allowed_platform_property_keys.clone(),
+          remote_execution_skip_cache_lookup,
+          remote_execution_action_cache_writes,
+          remote_execution_verify_cache_hits,
           // Allow for some overhead for bookkeeping threads (if any).
           process_execution_parallelism + 2,
           store.clone(),
           futures_timer_thread2.clone(),
-        )),
-        None => Box::new(process_execution::local::CommandRunner::new(
+        )
+        .with_timeouts(remote_execution_timeouts);
+        if offline {
+          remote_command_runner.into_offline()
+        } else {
+          remote_command_runner
+        }
+      });
+      let underlying_command_runner: Box<dyn CommandRunner> = match &remote_command_runner {
+        Some(ref remote_command_runner) => {
+          let remote_command_runner: Box<dyn CommandRunner> =
+            Box::new(remote_command_runner.clone());
+          match remote_execution_verify_report {
+            Some(ref path) => Box::new(
+              VerifyingCommandRunner::new(
+                Box::new(local_command_runner()),
+                remote_command_runner,
+                store.clone(),
+                path,
+              )
+              .unwrap_or_else(|e| panic!("Could not initialize remote verification: {}", e)),
+            ),
+            None => remote_command_runner,
+          }
+        }
+        None => Box::new(local_command_runner()),
+      };
+
+      let underlying_command_runner: Box<dyn CommandRunner> =
+        match process_execution_determinism_check {
+          Some(ref config) => {
+            let second_work_dir = if config.vary_sandbox {
+              let dir = work_dir.join("determinism-check");
+              std::fs::create_dir_all(&dir).unwrap_or_else(|e| {
+                panic!("Could not create determinism check dir {:?}: {}", dir, e)
+              });
+              dir
+            } else {
+              work_dir.clone()
+            };
+            Box::new(
+              DeterminismCheckingCommandRunner::new(
+                underlying_command_runner,
+                Box::new(process_execution::local::CommandRunner::new(
+                  store.clone(),
+                  second_work_dir,
+                  process_execution_cleanup_local_dirs,
+                )),
+                store.clone(),
+                config,
+              )
+              .unwrap_or_else(|e| panic!("Could not initialize determinism check: {}", e)),
+            )
+          }
+          None => underlying_command_runner,
+        };
+
+      // Applied outside of the configured runners, which the strategy of a Session may bypass.
+      let underlying_command_runner: Box<dyn CommandRunner> = Box::new(StrategyCommandRunner::new(
+        underlying_command_runner,
+        Box::new(local_command_runner()),
+        remote_command_runner,
+      ));
+
+      let underlying_command_runner: Box<dyn CommandRunner> =
+        Box::new(ReproducibleEnvCommandRunner::new(underlying_command_runner));
+
+      let underlying_command_runner: Box<dyn CommandRunner> =
+        match process_execution_unused_inputs_report {
+          Some(ref path) => Box::new(
+            UnusedInputsReportingCommandRunner::new(
+              underlying_command_runner,
+              Box::new(local_command_runner()),
+              store.clone(),
+              path,
+            )
+            .unwrap_or_else(|e| panic!("Could not initialize unused inputs reporting: {}", e)),
+          ),
+          None => underlying_command_runner,
+        };
+
+      let underlying_command_runner: Box<dyn CommandRunner> =
+        match process_execution_provenance_file {
+          Some(ref path) => Box::new(
+            ProvenanceRecordingCommandRunner::new(underlying_command_runner, path)
+              .unwrap_or_else(|e| panic!("Could not initialize provenance recording: {}", e)),
+          ),
+          None => underlying_command_runner,
+        };
+
+      let underlying_command_runner: Box<dyn CommandRunner> = match artifact_index2 {
+        Some(ref index) => Box::new(ArtifactIndexingCommandRunner::new(
+          underlying_command_runner,
           store.clone(),
-          work_dir.clone(),
-          process_execution_cleanup_local_dirs,
+          index.clone(),
+        )),
+        None => underlying_command_runner,
+      };
+
+      let underlying_command_runner: Box<dyn CommandRunner> = match process_execution_audit_log {
+        Some(ref config) => Box::new(
+          AuditLogCommandRunner::new(underlying_command_runner, config)
+            .unwrap_or_else(|e| panic!("Could not initialize process execution audit log: {}", e)),
+        ),
+        None => underlying_command_runner,
+      };
+
+      let underlying_command_runner: Box<dyn CommandRunner> = match process_execution_output_limit {
+        Some(output_limit) => Box::new(DefaultOutputLimitCommandRunner::new(
+          underlying_command_runner,
+          output_limit,
         )),
+        None => underlying_command_runner,
       };
 
+      // Applied outside of every other wrapper, so that all of them see the same token.
+      let underlying_command_runner: Box<dyn CommandRunner> = Box::new(
+        IdempotencyTokenCommandRunner::new(underlying_command_runner),
+      );
+
+      // Applied inside of the bound on parallelism, so that processes are counted as they start.
+      let underlying_command_runner: Box<dyn CommandRunner> = Box::new(
+        ProcessCountLimitCommandRunner::new(underlying_command_runner),
+      );
+
       let command_runner =
         BoundedCommandRunner::new(underlying_command_runner, process_execution_parallelism);
+      // Memory estimates describe processes which run on this machine, so they do not bound
+      // remote execution.
+      let command_runner = match process_execution_memory_budget_mb {
+        Some(budget_mb) if !remote_execution => command_runner.with_memory_budget_mb(budget_mb),
+        _ => command_runner,
+      };
 
-      let http_client = reqwest::r#async::Client::new();
+      let http_client = http_client_with_proxies(&proxies)
+        .unwrap_or_else(|e| panic!("Could not initialize HTTP client: {}", e));
 
       (store, command_runner, http_client)
     });
@@ -170,12 +487,28 @@ impl Core {
       runtime: runtime,
       futures_timer_thread: futures_timer_thread,
       store_and_command_runner_and_http_client: store_and_command_runner_and_http_client,
+      duration_history,
       // TODO: Errors in initialization should definitely be exposed as python
       // exceptions, rather than as panics.
       vfs: PosixFS::new(&build_root, &ignore_patterns).unwrap_or_else(|e| {
         panic!("Could not initialize VFS: {:?}", e);
       }),
+      file_digests: FileDigestCache::default(),
       build_root: build_root,
+      offline,
+      work_dir: work_dir2,
+      process_execution_cleanup_local_dirs,
+      remote_execution,
+      remote_instance_name: remote_instance_name2,
+      remote_execution_process_cache_namespace: remote_execution_process_cache_namespace2,
+      remote_execution_extra_platform_properties: remote_execution_extra_platform_properties2,
+      remote_execution_allowed_platform_property_keys:
+        remote_execution_allowed_platform_property_keys2,
+      process_count_limits: process_execution_count_limits,
+      artifact_index,
+      snapshot_size_limits,
+      hermeticity_lint,
+      jdks: process_execution_jdks,
     }
   }
 
@@ -198,9 +531,11 @@ impl Core {
     }
     let t = self.futures_timer_thread.with_reset(|| {
       self.runtime.with_reset(|| {
-        self
-          .graph
-          .with_exclusive(|| self.store_and_command_runner_and_http_client.with_reset(f))
+        self.graph.with_exclusive(|| {
+          self
+            .duration_history
+            .with_reset(|| self.store_and_command_runner_and_http_client.with_reset(f))
+        })
       })
     });
     self
@@ -210,6 +545,48 @@ impl Core {
     t
   }
 
+  ///
+  /// Returns the historical duration of the given unit of work, if it is known.
+  ///
+  pub fn estimate_duration(&self, key: &str) -> Option<Duration> {
+    self.duration_history.with(|history| {
+      history.as_ref().and_then(|history| {
+        history
+          .estimate(key)
+          .map_err(|e| debug!("Failed to estimate duration of {}: {}", key, e))
+          .ok()
+          .and_then(|estimate| estimate)
+      })
+    })
+  }
+
+  ///
+  /// Records the duration of a completed unit of work, to improve future estimates.
+  ///
+  pub fn record_duration(&self, key: &str, duration: Duration) {
+    self.duration_history.with(|history| {
+      if let Some(history) = history {
+        if let Err(e) = history.record(key, duration) {
+          debug!("Failed to record duration of {}: {}", key, e);
+        }
+      }
+    })
+  }
+
+  ///
+  /// Returns the path and size of the data file of each LMDB map opened by the engine.
+  ///
+  pub fn lmdb_sizes(&self) -> Result<Vec<(PathBuf, u64)>, String> {
+    let mut sizes = self.store().lmdb_sizes()?;
+    if let Some(size) = self
+      .duration_history
+      .with(|history| history.as_ref().map(DurationHistory::data_file_size))
+    {
+      sizes.push(size?);
+    }
+    Ok(sizes)
+  }
+
   pub fn store(&self) -> Store {
     self.store_and_command_runner_and_http_client.get().0
   }
@@ -218,10 +595,78 @@ impl Core {
     self.store_and_command_runner_and_http_client.get().1
   }
 
+  ///
+  /// A local CommandRunner for interactive processes, which always run on this machine (regardless
+  /// of whether remote execution is configured), because they use its console.
+  ///
+  pub fn interactive_command_runner(&self) -> process_execution::local::CommandRunner {
+    process_execution::local::CommandRunner::new(
+      self.store(),
+      self.work_dir.clone(),
+      self.process_execution_cleanup_local_dirs,
+    )
+  }
+
   pub fn http_client(&self) -> reqwest::r#async::Client {
     self.store_and_command_runner_and_http_client.get().2
   }
 
+  ///
+  /// The protos which would be sent to execute the given request remotely with this Core's
+  /// configuration, without executing it.
+  ///
+  pub fn remote_execution_dry_run(&self, mut req: ExecuteProcessRequest) -> Result<DryRun, String> {
+    process_execution::add_reproducible_env(&mut req);
+    process_execution::remote::validate_platform_properties(
+      &req,
+      &self.remote_execution_allowed_platform_property_keys,
+    )?;
+    process_execution::remote::dry_run(
+      &req,
+      &process_execution::ExecuteProcessRequestMetadata {
+        instance_name: self.remote_instance_name.clone(),
+        cache_key_gen_version: self.remote_execution_process_cache_namespace.clone(),
+        platform_properties: self.remote_execution_extra_platform_properties.clone(),
+        ..process_execution::ExecuteProcessRequestMetadata::default()
+      },
+    )
+  }
+
+  ///
+  /// Invalidate the invalidation roots represented by the given Paths.
+  ///
+  pub fn invalidate(&self, paths: &HashSet<PathBuf>) -> usize {
+    let InvalidationResult { cleared, dirtied } = self.graph.invalidate_from_roots(move |node| {
+      if let Some(fs_subject) = node.fs_subject() {
+        paths.contains(fs_subject)
+      } else {
+        false
+      }
+    });
+    // TODO: The rust log level is not currently set correctly in a pantsd context. To ensure that
+    // we see this even at `info` level, we set it to warn. #6004 should address this by making
+    // rust logging re-configuration an explicit step in `src/python/pants/init/logging.py`.
+    warn!(
+      "invalidation: cleared {} and dirtied {} nodes for: {:?}",
+      cleared, dirtied, paths
+    );
+    cleared + dirtied
+  }
+
+  ///
+  /// Invalidate all filesystem dependencies in the graph.
+  ///
+  pub fn invalidate_all_paths(&self) -> usize {
+    let InvalidationResult { cleared, dirtied } = self
+      .graph
+      .invalidate_from_roots(|node| node.fs_subject().is_some());
+    info!(
+      "invalidation: cleared {} and dirtied {} nodes for all paths",
+      cleared, dirtied
+    );
+    cleared + dirtied
+  }
+
   ///
   /// Start running a Future on a tokio Runtime.
   ///
@@ -235,10 +680,12 @@ impl Core {
       .get()
       .read()
       .executor()
-      .spawn(futures::future::ok(()).and_then(move |()| {
-        logging::set_destination(logging_destination);
-        future
-      }))
+      .spawn(panics::log_unwind_task(futures::future::ok(()).and_then(
+        move |()| {
+          logging::set_destination(logging_destination);
+          future
+        },
+      )))
   }
 
   ///
@@ -270,17 +717,45 @@ impl Core {
   }
 }
 
+///
+/// Identifies the Session that a Context is running on behalf of.
+///
+/// Uncacheable Nodes include the RunId of their Session in their identity, so that they are
+/// memoized within a Session, but run again in each new Session.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RunId(pub u32);
+
 #[derive(Clone)]
 pub struct Context {
   pub entry_id: EntryId,
   pub core: Arc<Core>,
+  pub run_id: RunId,
+  // Where the processes requested by this Context are executed, which is set per-Session.
+  pub execution_strategy: ExecutionStrategy,
+  // The per-Session context of the processes requested by this Context, which holds the counts of
+  // the processes executed by the Session and the record of the digests that it has uploaded.
+  pub process_context: process_execution::Context,
+  // If set, cancels the Nodes which are run by this Context (and by the Contexts cloned from it).
+  pub cancellation: Option<Cancellation>,
 }
 
 impl Context {
-  pub fn new(entry_id: EntryId, core: Arc<Core>) -> Context {
+  pub fn new(
+    entry_id: EntryId,
+    core: Arc<Core>,
+    run_id: RunId,
+    execution_strategy: ExecutionStrategy,
+    process_context: process_execution::Context,
+    cancellation: Option<Cancellation>,
+  ) -> Context {
     Context {
       entry_id: entry_id,
       core: core,
+      run_id: run_id,
+      execution_strategy: execution_strategy,
+      process_context: process_context,
+      cancellation: cancellation,
     }
   }
 
@@ -314,6 +789,10 @@ impl NodeContext for Context {
     Context {
       entry_id: entry_id,
       core: self.core.clone(),
+      run_id: self.run_id,
+      execution_strategy: self.execution_strategy,
+      process_context: self.process_context.clone(),
+      cancellation: self.cancellation.clone(),
     }
   }
 
@@ -325,6 +804,16 @@ impl NodeContext for Context {
   where
     F: Future<Item = (), Error = ()> + Send + 'static,
   {
-    self.core.runtime.get().read().executor().spawn(future);
+    self
+      .core
+      .runtime
+      .get()
+      .read()
+      .executor()
+      .spawn(panics::log_unwind_task(future));
+  }
+
+  fn cancellation(&self) -> Option<&Cancellation> {
+    self.cancellation.as_ref()
   }
 }
@@ -0,0 +1,227 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+//!
+//! The python datatypes which the engine constructs from (or lifts into) rust values, and the
+//! names of their fields.
+//!
+//! Values of these types should be stored and lifted using the field name constants declared here
+//! rather than string literals, so that the field lists of the python types are declared in one
+//! place, and are checked against the python types when a Scheduler is created (for constructed
+//! types) or when the first value of a type is lifted (for lifted types).
+//!
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::core::{Function, Value};
+use crate::externs;
+use crate::types::Types;
+
+pub mod digest {
+  pub const FINGERPRINT: &str = "fingerprint";
+  pub const SERIALIZED_BYTES_LENGTH: &str = "serialized_bytes_length";
+}
+
+pub mod snapshot {
+  pub const DIRECTORY_DIGEST: &str = "directory_digest";
+  pub const FILES: &str = "files";
+  pub const DIRS: &str = "dirs";
+}
+
+pub mod file_content {
+  pub const PATH: &str = "path";
+  pub const CONTENT: &str = "content";
+}
+
+pub mod collection {
+  pub const DEPENDENCIES: &str = "dependencies";
+}
+
+pub mod process_request {
+  pub const ARGV: &str = "argv";
+  pub const INPUT_FILES: &str = "input_files";
+  pub const DESCRIPTION: &str = "description";
+  pub const ENV: &str = "env";
+  pub const OUTPUT_FILES: &str = "output_files";
+  pub const OUTPUT_DIRECTORIES: &str = "output_directories";
+  pub const TIMEOUT_SECONDS: &str = "timeout_seconds";
+  pub const JDK_HOME: &str = "jdk_home";
+  pub const JDK: &str = "jdk";
+  pub const MAX_OUTPUT_BYTES: &str = "max_output_bytes";
+  pub const OUTPUT_OVERFLOW_POLICY: &str = "output_overflow_policy";
+  pub const PLATFORM_PROPERTIES: &str = "platform_properties";
+  pub const STDIN_DIGEST: &str = "stdin_digest";
+  pub const SECRET_ENV: &str = "secret_env";
+  pub const PRESERVE_EMPTY_OUTPUT_DIRECTORIES: &str = "preserve_empty_output_directories";
+  pub const SIDE_EFFECTING: &str = "side_effecting";
+  pub const MEMORY_ESTIMATE_MB: &str = "memory_estimate_mb";
+}
+
+pub mod process_result {
+  pub const STDOUT: &str = "stdout";
+  pub const STDERR: &str = "stderr";
+  pub const EXIT_CODE: &str = "exit_code";
+  pub const OUTPUT_DIRECTORY_DIGEST: &str = "output_directory_digest";
+  pub const RUNNER: &str = "runner";
+  pub const ATTEMPT_COUNT: &str = "attempt_count";
+  pub const TIMEOUT_SECONDS: &str = "timeout_seconds";
+  pub const ELAPSED_SECONDS: &str = "elapsed_seconds";
+}
+
+pub static DIGEST: ExternType = ExternType::new(
+  "Digest",
+  &[digest::FINGERPRINT, digest::SERIALIZED_BYTES_LENGTH],
+);
+
+pub static SNAPSHOT: ExternType = ExternType::new(
+  "Snapshot",
+  &[snapshot::DIRECTORY_DIGEST, snapshot::FILES, snapshot::DIRS],
+);
+
+pub static FILE_CONTENT: ExternType =
+  ExternType::new("FileContent", &[file_content::PATH, file_content::CONTENT]);
+
+pub static FILE_CONTENT_VIEW: ExternType = ExternType::new(
+  "FileContentView",
+  &[file_content::PATH, file_content::CONTENT],
+);
+
+pub static FILES_CONTENT: ExternType = ExternType::new("FilesContent", &[collection::DEPENDENCIES]);
+
+pub static FILES_CONTENT_VIEW: ExternType =
+  ExternType::new("FilesContentView", &[collection::DEPENDENCIES]);
+
+pub static PROCESS_REQUEST: ExternType = ExternType::new(
+  "ExecuteProcessRequest",
+  &[
+    process_request::ARGV,
+    process_request::INPUT_FILES,
+    process_request::DESCRIPTION,
+    process_request::ENV,
+    process_request::OUTPUT_FILES,
+    process_request::OUTPUT_DIRECTORIES,
+    process_request::TIMEOUT_SECONDS,
+    process_request::JDK_HOME,
+    process_request::JDK,
+    process_request::MAX_OUTPUT_BYTES,
+    process_request::OUTPUT_OVERFLOW_POLICY,
+    process_request::PLATFORM_PROPERTIES,
+    process_request::STDIN_DIGEST,
+    process_request::SECRET_ENV,
+    process_request::PRESERVE_EMPTY_OUTPUT_DIRECTORIES,
+    process_request::SIDE_EFFECTING,
+  ],
+);
+
+pub static PROCESS_RESULT: ExternType = ExternType::new(
+  "FallibleExecuteProcessResult",
+  &[
+    process_result::STDOUT,
+    process_result::STDERR,
+    process_result::EXIT_CODE,
+    process_result::OUTPUT_DIRECTORY_DIGEST,
+    process_result::RUNNER,
+    process_result::ATTEMPT_COUNT,
+    process_result::TIMEOUT_SECONDS,
+    process_result::ELAPSED_SECONDS,
+  ],
+);
+
+///
+/// A python datatype, and the fields (in declaration order) which the engine expects it to have.
+///
+pub struct ExternType {
+  pub name: &'static str,
+  pub fields: &'static [&'static str],
+  // Whether a lifted value of this type has been checked against the fields.
+  checked: AtomicBool,
+}
+
+impl ExternType {
+  const fn new(name: &'static str, fields: &'static [&'static str]) -> ExternType {
+    ExternType {
+      name,
+      fields,
+      checked: AtomicBool::new(false),
+    }
+  }
+
+  ///
+  /// Checks that the given python datatype declares exactly the fields of this type, in the same
+  /// order.
+  ///
+  pub fn check(&self, datatype: &Value) -> Result<(), String> {
+    self.check_fields(&externs::val_to_str(datatype), datatype)
+  }
+
+  ///
+  /// Checks the given value against the fields of this type if it is the first value of this type
+  /// to be lifted.
+  ///
+  pub fn check_lifted(&self, value: &Value) -> Result<(), String> {
+    if self.checked.load(Ordering::Relaxed) {
+      return Ok(());
+    }
+    self.check_fields(&externs::type_to_str(externs::get_type_for(value)), value)?;
+    self.checked.store(true, Ordering::Relaxed);
+    Ok(())
+  }
+
+  fn check_fields(&self, python_name: &str, datatype_or_value: &Value) -> Result<(), String> {
+    // NB: Both a datatype and its instances have `_fields`, since datatypes are namedtuples.
+    let actual = externs::project_multi_strs(datatype_or_value, "_fields");
+    if actual
+      .iter()
+      .map(String::as_str)
+      .eq(self.fields.iter().cloned())
+    {
+      Ok(())
+    } else {
+      Err(format!(
+        "The python type `{}` declares the fields {:?}, but the engine expects {} to declare {:?}.",
+        python_name, actual, self.name, self.fields
+      ))
+    }
+  }
+
+  ///
+  /// Constructs a value of this type by calling the given constructor with the given fields, which
+  /// must be named in their declaration order.
+  ///
+  pub fn construct(&self, constructor: &Function, fields: &[(&'static str, Value)]) -> Value {
+    debug_assert!(
+      fields
+        .iter()
+        .map(|(name, _)| *name)
+        .eq(self.fields.iter().cloned()),
+      "Fields for {} were not named in their declaration order: {:?}",
+      self.name,
+      fields.iter().map(|(name, _)| name).collect::<Vec<_>>()
+    );
+    let args = fields
+      .iter()
+      .map(|(_, value)| value.clone())
+      .collect::<Vec<_>>();
+    externs::unsafe_call(constructor, &args)
+  }
+}
+
+///
+/// Checks that the constructors which were registered for each constructed type declare the
+/// fields which the engine will construct them with.
+///
+pub fn check_constructors(types: &Types) -> Result<(), String> {
+  let constructors = [
+    (&DIGEST, &types.construct_directory_digest),
+    (&SNAPSHOT, &types.construct_snapshot),
+    (&FILE_CONTENT, &types.construct_file_content),
+    (&FILES_CONTENT, &types.construct_files_content),
+    (&FILE_CONTENT_VIEW, &types.construct_file_content_view),
+    (&FILES_CONTENT_VIEW, &types.construct_files_content_view),
+    (&PROCESS_RESULT, &types.construct_process_result),
+  ];
+  for (extern_type, constructor) in &constructors {
+    extern_type.check(&externs::val_for(&constructor.0))?;
+  }
+  Ok(())
+}
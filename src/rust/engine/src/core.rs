@@ -82,6 +82,10 @@ impl Params {
       .binary_search_by(|probe| probe.type_id().cmp(&type_id))
   }
 
+  pub fn keys<'a>(&'a self) -> impl Iterator<Item = &'a Key> + 'a {
+    self.0.iter()
+  }
+
   pub fn type_ids<'a>(&'a self) -> impl Iterator<Item = TypeId> + 'a {
     self.0.iter().map(|k| *k.type_id())
   }
@@ -222,9 +226,22 @@ impl Key {
 ///
 /// A wrapper around a handle: soon to contain an Arc.
 ///
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone)]
 pub struct Value(Arc<Handle>);
 
+///
+/// Values are compared by python equality (rather than by the identity of their handles), so that
+/// a Node which is re-run and returns an equal result cleans its dependees rather than dirtying
+/// them.
+///
+impl PartialEq for Value {
+  fn eq(&self, other: &Value) -> bool {
+    Arc::ptr_eq(&self.0, &other.0) || externs::equals(&self.0, &other.0)
+  }
+}
+
+impl Eq for Value {}
+
 impl Value {
   pub fn new(handle: Handle) -> Value {
     Value(Arc::new(handle))
@@ -269,15 +286,32 @@ pub enum Failure {
   /// A Node failed because a filesystem change invalidated it or its inputs.
   /// A root requestor should usually immediately retry their request.
   Invalidated,
-  /// A rule raised an exception.
-  Throw(Value, String),
+  /// A rule raised an exception. The exception is accompanied by its python traceback, and by an
+  /// "engine traceback" describing the rules which were running when it was raised (innermost
+  /// first).
+  Throw(Value, String, Vec<String>),
+}
+
+impl Failure {
+  ///
+  /// If this is a Throw, records that it propagated out of the given rule invocation.
+  ///
+  pub fn with_engine_frame(self, frame: String) -> Failure {
+    match self {
+      Failure::Throw(exc, traceback, mut engine_traceback) => {
+        engine_traceback.push(frame);
+        Failure::Throw(exc, traceback, engine_traceback)
+      }
+      f @ Failure::Invalidated => f,
+    }
+  }
 }
 
 impl fmt::Display for Failure {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
       Failure::Invalidated => write!(f, "Exhausted retries due to changed files."),
-      Failure::Throw(exc, _) => write!(f, "{}", externs::val_to_str(exc)),
+      Failure::Throw(exc, _, _) => write!(f, "{}", externs::val_to_str(exc)),
     }
   }
 }
@@ -289,5 +323,6 @@ pub fn throw(msg: &str) -> Failure {
       "Traceback (no traceback):\n  <pants native internals>\nException: {}",
       msg
     ),
+    Vec::new(),
   )
 }
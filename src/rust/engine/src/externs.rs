@@ -1,6 +1,7 @@
 // Copyright 2017 Pants project contributors (see CONTRIBUTORS.md).
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::ffi::OsString;
 use std::fmt;
@@ -9,6 +10,8 @@ use std::os::raw;
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::string::FromUtf8Error;
 
+use bytes::Bytes;
+
 use crate::core::{Failure, Function, Key, TypeId, Value};
 use crate::handles::{DroppingHandle, Handle};
 use crate::interning::Interns;
@@ -37,6 +40,27 @@ pub fn key_for(val: Value) -> Key {
   interns.insert(val)
 }
 
+///
+/// See `Interns::pin`.
+///
+pub fn pin_interns() {
+  INTERNS.write().pin()
+}
+
+///
+/// See `Interns::collect`.
+///
+pub fn collect_interns(live: &HashSet<Key>) -> usize {
+  INTERNS.write().collect(live)
+}
+
+///
+/// See `Interns::memory_usage`.
+///
+pub fn interns_memory_usage() -> (usize, usize, usize) {
+  INTERNS.read().memory_usage()
+}
+
 pub fn val_for(key: &Key) -> Value {
   let interns = INTERNS.read();
   interns.get(key).clone()
@@ -88,6 +112,24 @@ pub fn store_bytes(bytes: &[u8]) -> Value {
   with_externs(|e| (e.store_bytes)(e.context, bytes.as_ptr(), bytes.len() as u64).into())
 }
 
+///
+/// Store a buffer of bytes to pass to Python without copying it. This will end up as a Python
+/// `memoryview` over memory owned by rust, which is kept alive (via the BufferOwner) until the
+/// Python side releases its last reference to the view.
+///
+pub fn store_buffer(bytes: Bytes) -> Value {
+  let ptr = bytes.as_ptr();
+  let len = bytes.len() as u64;
+  let owner = Box::into_raw(Box::new(BufferOwner(bytes)));
+  with_externs(|e| (e.store_buffer)(e.context, ptr, len, owner).into())
+}
+
+///
+/// Keeps the memory backing a buffer passed to Python via `store_buffer` alive. Python is
+/// responsible for calling `buffer_owner_destroy` once it no longer references the buffer.
+///
+pub struct BufferOwner(Bytes);
+
 ///
 /// Store an buffer of utf8 bytes to pass to Python. This will end up as a Python `unicode`.
 ///
@@ -189,6 +231,33 @@ pub fn create_exception(msg: &str) -> Value {
   with_externs(|e| (e.create_exception)(e.context, msg.as_ptr(), msg.len() as u64).into())
 }
 
+///
+/// Logs a record emitted by the engine via the python `logging` module. Returns false without
+/// logging if the externs are not available: either because they have not been set yet, or because
+/// they are held exclusively (to fork).
+///
+pub fn log(level: u64, target: &str, msg: &str) -> bool {
+  // NB: Records may be emitted while this thread already holds the externs (during a call into
+  // python), so they are acquired recursively, and without blocking.
+  match EXTERNS.try_read_recursive() {
+    Some(externs_opt) => match *externs_opt {
+      Some(ref e) => {
+        (e.log)(
+          e.context,
+          level,
+          target.as_ptr(),
+          target.len() as u64,
+          msg.as_ptr(),
+          msg.len() as u64,
+        );
+        true
+      }
+      None => false,
+    },
+    None => false,
+  }
+}
+
 // TODO: This method is currently unused, but kept as an example of how to call methods on objects.
 #[allow(dead_code)]
 pub fn call_method(value: &Value, method: &str, args: &[Value]) -> Result<Value, Failure> {
@@ -208,9 +277,29 @@ pub fn call(func: &Value, args: &[Value]) -> Result<Value, Failure> {
   .into()
 }
 
+///
+/// Resumes the given generator by sending it the given value.
+///
 pub fn generator_send(generator: &Value, arg: &Value) -> Result<GeneratorResponse, Failure> {
+  generator_resume(generator, arg, false)
+}
+
+///
+/// Resumes the given generator by raising the given exception at the point where it is paused, so
+/// that the generator may handle the failure of a Get, and so that the traceback of an exception
+/// which it does not handle includes the frame of the generator.
+///
+pub fn generator_throw(generator: &Value, exc: &Value) -> Result<GeneratorResponse, Failure> {
+  generator_resume(generator, exc, true)
+}
+
+fn generator_resume(
+  generator: &Value,
+  arg: &Value,
+  is_throw: bool,
+) -> Result<GeneratorResponse, Failure> {
   let response =
-    with_externs(|e| (e.generator_send)(e.context, generator as &Handle, arg as &Handle));
+    with_externs(|e| (e.generator_send)(e.context, generator as &Handle, arg as &Handle, is_throw));
   match response {
     PyGeneratorResponse::Broke(h) => Ok(GeneratorResponse::Break(Value::new(h))),
     PyGeneratorResponse::Throw(h) => Err(PyResult::failure_from(Value::new(h))),
@@ -239,6 +328,21 @@ pub fn generator_send(generator: &Value, arg: &Value) -> Result<GeneratorRespons
         .collect();
       Ok(GeneratorResponse::GetMulti(gets))
     }
+    PyGeneratorResponse::GetAll(product, handles, identities) => {
+      let mut interns = INTERNS.write();
+      let identities = identities.to_vec();
+      let values = handles.to_vec();
+      assert_eq!(identities.len(), values.len());
+      let gets: Vec<Get> = values
+        .into_iter()
+        .zip(identities.into_iter())
+        .map(|(v, i)| Get {
+          product,
+          subject: interns.insert_with(v, i),
+        })
+        .collect();
+      Ok(GeneratorResponse::GetMulti(gets))
+    }
   }
 }
 
@@ -325,6 +429,7 @@ pub struct Externs {
   pub store_set: StoreTupleExtern,
   pub store_dict: StoreTupleExtern,
   pub store_bytes: StoreBytesExtern,
+  pub store_buffer: StoreBufferExtern,
   pub store_utf8: StoreUtf8Extern,
   pub store_i64: StoreI64Extern,
   pub store_f64: StoreF64Extern,
@@ -334,6 +439,7 @@ pub struct Externs {
   pub type_to_str: TypeToStrExtern,
   pub val_to_str: ValToStrExtern,
   pub create_exception: CreateExceptionExtern,
+  pub log: LogExtern,
 }
 
 // The pointer to the context is safe for sharing between threads.
@@ -355,6 +461,9 @@ pub type StoreTupleExtern =
 
 pub type StoreBytesExtern = extern "C" fn(*const ExternContext, *const u8, u64) -> Handle;
 
+pub type StoreBufferExtern =
+  extern "C" fn(*const ExternContext, *const u8, u64, *mut BufferOwner) -> Handle;
+
 pub type StoreUtf8Extern = extern "C" fn(*const ExternContext, *const u8, u64) -> Handle;
 
 pub type StoreI64Extern = extern "C" fn(*const ExternContext, i64) -> Handle;
@@ -376,7 +485,30 @@ pub struct PyResult {
 impl PyResult {
   fn failure_from(v: Value) -> Failure {
     let traceback = project_str(&v, "_formatted_exc");
-    Failure::Throw(v, traceback)
+    Failure::Throw(v, traceback, Vec::new())
+  }
+
+  ///
+  /// Converts the result of a root into a PyResult. Unlike the `From` conversion (which is used for
+  /// results that python will directly return or raise), a failed root is represented as a tuple
+  /// of its exception, its python traceback, and its engine traceback (outermost rule first), so
+  /// that python can render all of them.
+  ///
+  pub fn for_root(result: Result<Value, Failure>) -> PyResult {
+    let (exc, traceback, engine_traceback) = match result {
+      Ok(val) => return Ok(val).into(),
+      Err(f @ Failure::Invalidated) => (create_exception(&format!("{}", f)), String::new(), vec![]),
+      Err(Failure::Throw(exc, traceback, engine_traceback)) => (exc, traceback, engine_traceback),
+    };
+    let engine_traceback = engine_traceback
+      .iter()
+      .rev()
+      .map(|frame| store_utf8(frame))
+      .collect::<Vec<_>>();
+    PyResult {
+      is_throw: true,
+      handle: store_tuple(&[exc, store_utf8(&traceback), store_tuple(&engine_traceback)]).into(),
+    }
   }
 }
 
@@ -390,7 +522,7 @@ impl From<Result<Value, Failure>> for PyResult {
       Err(f) => {
         let val = match f {
           f @ Failure::Invalidated => create_exception(&format!("{}", f)),
-          Failure::Throw(exc, _) => exc,
+          Failure::Throw(exc, _, _) => exc,
         };
         PyResult {
           is_throw: true,
@@ -442,6 +574,8 @@ impl From<Result<(), String>> for PyResult {
 pub enum PyGeneratorResponse {
   Get(TypeId, Handle, Ident),
   GetMulti(TypeIdBuffer, HandleBuffer, IdentBuffer),
+  // A Get of one product for each of a collection of subjects, which is executed as a GetMulti.
+  GetAll(TypeId, HandleBuffer, IdentBuffer),
   // NB: Broke not Break because C keyword.
   Broke(Handle),
   Throw(Handle),
@@ -666,11 +800,20 @@ pub type ValToStrExtern = extern "C" fn(*const ExternContext, *const Handle) ->
 pub type CreateExceptionExtern =
   extern "C" fn(*const ExternContext, str_ptr: *const u8, str_len: u64) -> Handle;
 
+pub type LogExtern = extern "C" fn(
+  *const ExternContext,
+  level: u64,
+  target_ptr: *const u8,
+  target_len: u64,
+  msg_ptr: *const u8,
+  msg_len: u64,
+);
+
 pub type CallExtern =
   extern "C" fn(*const ExternContext, *const Handle, *const *const Handle, u64) -> PyResult;
 
 pub type GeneratorSendExtern =
-  extern "C" fn(*const ExternContext, *const Handle, *const Handle) -> PyGeneratorResponse;
+  extern "C" fn(*const ExternContext, *const Handle, *const Handle, bool) -> PyGeneratorResponse;
 
 pub fn with_vec<F, C, T>(c_ptr: *mut C, c_len: usize, f: F) -> T
 where
@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use lmdb::{self, Database, DatabaseFlags, Environment, EnvironmentFlags, Transaction, WriteFlags};
+
+// The history holds one (small) entry per key, so it should never approach this size.
+const MAX_HISTORY_SIZE_BYTES: usize = 64 * 1024 * 1024;
+
+// The weight of each new duration in the moving average, as a fraction: 1/4.
+const NEW_DURATION_WEIGHT_DIVISOR: u64 = 4;
+
+///
+/// A small local database of the historical durations of units of work (such as process
+/// executions, and the roots of runs), keyed by names which are stable between runs, which is
+/// used to estimate the progress of a run.
+///
+/// Each recorded duration is blended into a moving average for its key, so that estimates follow
+/// changes in the build (or the machine) over time.
+///
+pub struct DurationHistory {
+  dir: PathBuf,
+  env: Environment,
+  database: Database,
+}
+
+impl DurationHistory {
+  pub fn new(dir: &Path) -> Result<DurationHistory, String> {
+    fs::safe_create_dir_all_ioerror(dir).map_err(|e| {
+      format!(
+        "Error making directory for duration history {:?}: {}",
+        dir, e
+      )
+    })?;
+    let env = Environment::new()
+      // See the equivalent flags of the local Store: durability is not important for estimates,
+      // and reads happen on pooled threads.
+      .set_flags(EnvironmentFlags::NO_SYNC | EnvironmentFlags::NO_TLS)
+      .set_max_dbs(1)
+      .set_map_size(MAX_HISTORY_SIZE_BYTES)
+      .open(dir)
+      .map_err(|e| format!("Error making env for duration history at {:?}: {}", dir, e))?;
+    let database = env
+      .create_db(Some("durations"), DatabaseFlags::empty())
+      .map_err(|e| {
+        format!(
+          "Error creating/opening duration history at {:?}: {}",
+          dir, e
+        )
+      })?;
+    Ok(DurationHistory {
+      dir: dir.to_owned(),
+      env,
+      database,
+    })
+  }
+
+  ///
+  /// Returns the path and size of the (memory mapped) data file of the history.
+  ///
+  pub fn data_file_size(&self) -> Result<(PathBuf, u64), String> {
+    let path = self.dir.join("data.mdb");
+    std::fs::metadata(&path)
+      .map(|metadata| (path.clone(), metadata.len()))
+      .map_err(|e| format!("Failed to stat {:?}: {}", path, e))
+  }
+
+  ///
+  /// Returns the estimated duration of the given key, if it has been recorded before.
+  ///
+  pub fn estimate(&self, key: &str) -> Result<Option<Duration>, String> {
+    let txn = self
+      .env
+      .begin_ro_txn()
+      .map_err(|e| format!("Failed to begin read transaction: {}", e))?;
+    Self::get_millis(&txn, self.database, key)
+      .map(|maybe_millis| maybe_millis.map(Duration::from_millis))
+      .map_err(|e| format!("Error reading duration history at {:?}: {}", self.dir, e))
+  }
+
+  ///
+  /// Blends the given duration into the estimate for the given key.
+  ///
+  pub fn record(&self, key: &str, duration: Duration) -> Result<(), String> {
+    let millis = duration.as_secs() * 1000 + u64::from(duration.subsec_millis());
+    let mut txn = self
+      .env
+      .begin_rw_txn()
+      .map_err(|e| format!("Failed to begin write transaction: {}", e))?;
+    let estimate = match Self::get_millis(&txn, self.database, key)? {
+      Some(previous) if previous > millis => {
+        previous - (previous - millis) / NEW_DURATION_WEIGHT_DIVISOR
+      }
+      Some(previous) => previous + (millis - previous) / NEW_DURATION_WEIGHT_DIVISOR,
+      None => millis,
+    };
+    txn
+      .put(
+        self.database,
+        &key.as_bytes(),
+        &estimate.to_be_bytes(),
+        WriteFlags::empty(),
+      )
+      .and_then(|()| txn.commit())
+      .map_err(|e| format!("Error writing duration history at {:?}: {}", self.dir, e))
+  }
+
+  fn get_millis<T: Transaction>(
+    txn: &T,
+    database: Database,
+    key: &str,
+  ) -> Result<Option<u64>, String> {
+    match txn.get(database, &key.as_bytes()) {
+      Ok(bytes) if bytes.len() == 8 => {
+        let mut millis = [0; 8];
+        millis.copy_from_slice(bytes);
+        Ok(Some(u64::from_be_bytes(millis)))
+      }
+      // An entry of an unexpected size is treated as missing, and is replaced when next recorded.
+      Ok(_) | Err(lmdb::Error::NotFound) => Ok(None),
+      Err(e) => Err(format!("{}", e)),
+    }
+  }
+}
@@ -1,8 +1,9 @@
 // Copyright 2018 Pants project contributors (see CONTRIBUTORS.md).
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash;
+use std::mem;
 
 use crate::core::{Key, Value, FNV};
 use crate::externs::{self, Ident};
@@ -18,24 +19,19 @@ use crate::externs::{self, Ident};
 /// implements `Hash` and `Eq` using the precomputed python `__hash__` for the `Value` and
 /// delegating to python's `__eq__`, respectively.
 ///
-/// Currently `Value`s are interned indefinitely as `Key`s, meaning that they can never
-/// be collected: it's possible that this can eventually be improved by either:
-///
-///   1) switching to directly linking-against or embedding python, such that the `Value`
-///      type goes away in favor of direct usage of a python object wrapper struct.
-///   2) This structure might begin storing weak-references to `Key`s and/or `Value`s, which
-///      would allow the associated `Value` handles to be dropped when they were no longer used.
-///      The challenge to this approach is that it would make it more difficult to pass
-///      `Key`/`Value` instances across the FFI boundary.
-///   3) `Value` could implement `Eq`/`Hash` directly via extern calls to python (although we've
-///      avoided doing this so far because it would hide a relatively expensive operation behind
-///      those usually-inexpensive traits).
+/// `Key`s are not reference counted, so they are collected by epoch instead: see `collect`.
+/// `Key`s which are interned before the table is pinned (such as those of the functions of the
+/// rules of a Scheduler) are never collected.
 ///
 #[derive(Default)]
 pub struct Interns {
   forward: HashMap<InternKey, Key, FNV>,
   reverse: HashMap<Key, Value, FNV>,
   id_generator: u64,
+  // Keys with ids below this are never collected.
+  pinned_below: u64,
+  // The value of the id_generator when the previous collection began.
+  epoch_start: u64,
 }
 
 impl Interns {
@@ -65,6 +61,50 @@ impl Interns {
     key
   }
 
+  ///
+  /// Returns the number of interned Keys, the number of distinct types among them, and the
+  /// approximate bytes held by the tables (but not by the python objects that they refer to).
+  ///
+  pub fn memory_usage(&self) -> (usize, usize, usize) {
+    let types = self
+      .reverse
+      .keys()
+      .map(Key::type_id)
+      .collect::<HashSet<_>>()
+      .len();
+    let bytes = self.forward.capacity() * mem::size_of::<(InternKey, Key)>()
+      + self.reverse.capacity() * mem::size_of::<(Key, Value)>();
+    (self.reverse.len(), types, bytes)
+  }
+
+  ///
+  /// Prevents all Keys interned so far from ever being collected.
+  ///
+  pub fn pin(&mut self) {
+    self.pinned_below = self.id_generator;
+    self.epoch_start = self.id_generator;
+  }
+
+  ///
+  /// Drops the (unpinned) Keys which are not in the given live set, and which were interned before
+  /// the previous collection began, and returns the number dropped.
+  ///
+  /// A Key which was interned recently might be held by work which is in progress, and which has
+  /// not yet made it live (for example, by creating a Node which refers to it): requiring a Key to
+  /// survive an entire epoch (the interval between collections, which is one Session) before it
+  /// is collected avoids racing with that work.
+  ///
+  pub fn collect(&mut self, live: &HashSet<Key>) -> usize {
+    let (pinned_below, epoch_start) = (self.pinned_below, self.epoch_start);
+    let collectable =
+      |key: &Key| key.id() >= pinned_below && key.id() < epoch_start && !live.contains(key);
+    let count_before = self.reverse.len();
+    self.forward.retain(|_, key| !collectable(key));
+    self.reverse.retain(|key, _| !collectable(key));
+    self.epoch_start = self.id_generator;
+    count_before - self.reverse.len()
+  }
+
   pub fn get(&self, k: &Key) -> &Value {
     self
       .reverse
@@ -32,16 +32,22 @@
 
 pub mod cffi_externs;
 mod context;
+mod conversions;
 mod core;
 mod externs;
 mod handles;
+mod history;
 mod interning;
 mod nodes;
+mod panics;
+mod profiling;
 mod rule_graph;
 mod scheduler;
 mod selectors;
 mod tasks;
 mod types;
+mod watch;
+mod watchman;
 
 use fs;
 use futures;
@@ -53,35 +59,44 @@ use log;
 use tar_api;
 
 use std::borrow::Borrow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::CStr;
 use std::fs::File;
 use std::io;
-use std::mem;
 use std::os::raw;
-use std::panic;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::context::Core;
-use crate::core::{Function, Key, Params, TypeId, Value};
+use crate::core::{throw, Function, Key, Params, TypeId, Value};
 use crate::externs::{
-  Buffer, BufferBuffer, CallExtern, CloneValExtern, CreateExceptionExtern, DropHandlesExtern,
-  EqualsExtern, ExternContext, Externs, GeneratorSendExtern, GetTypeForExtern, HandleBuffer,
-  IdentifyExtern, ProjectIgnoringTypeExtern, ProjectMultiExtern, PyResult, RawBuffer,
-  StoreBoolExtern, StoreBytesExtern, StoreF64Extern, StoreI64Extern, StoreTupleExtern,
-  StoreUtf8Extern, TypeIdBuffer, TypeToStrExtern, ValToStrExtern,
+  Buffer, BufferBuffer, BufferOwner, CallExtern, CloneValExtern, CreateExceptionExtern,
+  DropHandlesExtern, EqualsExtern, ExternContext, Externs, GeneratorSendExtern, GetTypeForExtern,
+  HandleBuffer, IdentifyExtern, LogExtern, ProjectIgnoringTypeExtern, ProjectMultiExtern, PyResult,
+  RawBuffer, StoreBoolExtern, StoreBufferExtern, StoreBytesExtern, StoreF64Extern, StoreI64Extern,
+  StoreTupleExtern, StoreUtf8Extern, TypeIdBuffer, TypeToStrExtern, ValToStrExtern,
 };
 use crate::handles::Handle;
 use crate::rule_graph::{GraphMaker, RuleGraph};
-use crate::scheduler::{ExecutionRequest, RootResult, Scheduler, Session};
+use crate::scheduler::{ExecutionPolicy, ExecutionRequest, RootResult, Scheduler, Session};
 use crate::tasks::Tasks;
 use crate::types::Types;
-use futures::Future;
+use crate::watch::{InvalidationGlobs, WatcherBackend};
+use boxfuture::Boxable;
+use futures::{future, Future};
 use hashing::Digest;
 use log::{error, Log};
 use logging::logger::LOGGER;
 use logging::{Destination, Logger};
+use process_execution::artifacts::ArtifactIndex;
+use process_execution::audit::AuditLogConfig;
+use process_execution::determinism::DeterminismCheckConfig;
+use process_execution::jdk::JdkRegistry;
+use process_execution::limits::ProcessCountLimits;
+use process_execution::strategy::ExecutionStrategy;
+use process_execution::{OutputLimit, OutputOverflowPolicy};
 
 // TODO: Consider renaming and making generic for collections of PyResults.
 #[repr(C)]
@@ -93,7 +108,7 @@ pub struct RawNodes {
 
 impl RawNodes {
   fn create(node_states: Vec<RootResult>) -> Box<RawNodes> {
-    let nodes = node_states.into_iter().map(PyResult::from).collect();
+    let nodes = node_states.into_iter().map(PyResult::for_root).collect();
     let mut raw_nodes = Box::new(RawNodes {
       nodes_ptr: Vec::new().as_ptr(),
       nodes_len: 0,
@@ -124,6 +139,7 @@ pub extern "C" fn externs_set(
   store_set: StoreTupleExtern,
   store_dict: StoreTupleExtern,
   store_bytes: StoreBytesExtern,
+  store_buffer: StoreBufferExtern,
   store_utf8: StoreUtf8Extern,
   store_i64: StoreI64Extern,
   store_f64: StoreF64Extern,
@@ -131,6 +147,7 @@ pub extern "C" fn externs_set(
   project_ignoring_type: ProjectIgnoringTypeExtern,
   project_multi: ProjectMultiExtern,
   create_exception: CreateExceptionExtern,
+  log: LogExtern,
 ) {
   externs::set_externs(Externs {
     context,
@@ -149,6 +166,7 @@ pub extern "C" fn externs_set(
     store_set,
     store_dict,
     store_bytes,
+    store_buffer,
     store_utf8,
     store_i64,
     store_f64,
@@ -156,7 +174,9 @@ pub extern "C" fn externs_set(
     project_ignoring_type,
     project_multi,
     create_exception,
+    log,
   });
+  LOGGER.set_python_forwarder(Box::new(externs::log));
 }
 
 #[no_mangle]
@@ -182,19 +202,29 @@ pub extern "C" fn scheduler_create(
   construct_snapshot: Function,
   construct_file_content: Function,
   construct_files_content: Function,
+  construct_file_content_view: Function,
+  construct_files_content_view: Function,
   construct_process_result: Function,
+  construct_sharded_process_results: Function,
   type_address: TypeId,
   type_path_globs: TypeId,
   type_directory_digest: TypeId,
   type_snapshot: TypeId,
   type_merge_directories_request: TypeId,
   type_directory_with_prefix_to_strip: TypeId,
+  type_directory_with_prefix_to_add: TypeId,
+  type_snapshot_subset: TypeId,
+  type_archive_to_extract: TypeId,
+  type_directory_to_archive: TypeId,
   type_files_content: TypeId,
+  type_files_content_view: TypeId,
   type_dir: TypeId,
   type_file: TypeId,
   type_link: TypeId,
   type_process_request: TypeId,
   type_process_result: TypeId,
+  type_sharded_process_request: TypeId,
+  type_sharded_process_results: TypeId,
   type_generator: TypeId,
   type_url_to_fetch: TypeId,
   type_string: TypeId,
@@ -202,9 +232,15 @@ pub extern "C" fn scheduler_create(
   build_root_buf: Buffer,
   work_dir_buf: Buffer,
   local_store_dir_buf: Buffer,
+  local_store_metadata_dir_buf: Buffer,
+  offline: bool,
+  http_proxy_buf: Buffer,
+  https_proxy_buf: Buffer,
+  no_proxy_buf: BufferBuffer,
   ignore_patterns_buf: BufferBuffer,
   root_type_ids: TypeIdBuffer,
   remote_store_servers_buf: BufferBuffer,
+  remote_store_central_servers_buf: BufferBuffer,
   remote_execution_server: Buffer,
   remote_execution_process_cache_namespace: Buffer,
   remote_instance_name: Buffer,
@@ -212,11 +248,44 @@ pub extern "C" fn scheduler_create(
   remote_oauth_bearer_token_path_buffer: Buffer,
   remote_store_thread_count: u64,
   remote_store_chunk_bytes: u64,
+  remote_store_upload_window_chunks: u64,
   remote_store_chunk_upload_timeout_seconds: u64,
+  remote_store_find_missing_blobs_timeout_seconds: u64,
+  remote_store_read_timeout_seconds: u64,
   remote_store_rpc_retries: u64,
+  remote_store_read_only: bool,
+  remote_store_max_concurrent_transfers: u64,
+  remote_store_max_bytes_per_second: u64,
   remote_execution_extra_platform_properties_buf: BufferBuffer,
+  remote_execution_allowed_platform_property_keys_buf: BufferBuffer,
+  remote_execution_skip_cache_lookup: bool,
+  remote_execution_action_cache_writes: bool,
+  remote_execution_verify_cache_hits: bool,
+  remote_execution_stream_timeout_seconds: u64,
+  remote_execution_action_cache_timeout_seconds: u64,
+  remote_execution_verify_report_buffer: Buffer,
   process_execution_parallelism: u64,
+  process_execution_memory_budget_mb: u64,
   process_execution_cleanup_local_dirs: bool,
+  process_execution_max_output_bytes: u64,
+  process_execution_output_overflow_policy: Buffer,
+  process_execution_provenance_file_buffer: Buffer,
+  process_execution_artifact_index_buffer: Buffer,
+  process_execution_unused_inputs_report_buffer: Buffer,
+  process_execution_max_per_session: u64,
+  process_execution_max_per_minute: u64,
+  process_execution_audit_log_buffer: Buffer,
+  process_execution_audit_redact_env_buf: BufferBuffer,
+  process_execution_lint_hermeticity: bool,
+  process_execution_determinism_check_report_buffer: Buffer,
+  process_execution_determinism_check_descriptions_buf: BufferBuffer,
+  process_execution_determinism_check_vary_sandbox: bool,
+  process_execution_jdk_search_paths_buf: BufferBuffer,
+  snapshot_max_file_bytes: u64,
+  snapshot_max_total_bytes: u64,
+  snapshot_unicode_normalization_buf: Buffer,
+  engine_io_threads: u64,
+  engine_cpu_threads: u64,
 ) -> *const Scheduler {
   let root_type_ids = root_type_ids.to_vec();
   let ignore_patterns = ignore_patterns_buf
@@ -227,24 +296,36 @@ pub extern "C" fn scheduler_create(
     construct_snapshot: construct_snapshot,
     construct_file_content: construct_file_content,
     construct_files_content: construct_files_content,
+    construct_file_content_view: construct_file_content_view,
+    construct_files_content_view: construct_files_content_view,
     construct_process_result: construct_process_result,
+    construct_sharded_process_results: construct_sharded_process_results,
     address: type_address,
     path_globs: type_path_globs,
     directory_digest: type_directory_digest,
     snapshot: type_snapshot,
     directories_to_merge: type_merge_directories_request,
     directory_with_prefix_to_strip: type_directory_with_prefix_to_strip,
+    directory_with_prefix_to_add: type_directory_with_prefix_to_add,
+    snapshot_subset: type_snapshot_subset,
+    archive_to_extract: type_archive_to_extract,
+    directory_to_archive: type_directory_to_archive,
     files_content: type_files_content,
+    files_content_view: type_files_content_view,
     dir: type_dir,
     file: type_file,
     link: type_link,
     process_request: type_process_request,
     process_result: type_process_result,
+    sharded_process_request: type_sharded_process_request,
+    sharded_process_results: type_sharded_process_results,
     generator: type_generator,
     url_to_fetch: type_url_to_fetch,
     string: type_string,
     bytes: type_bytes,
   };
+  conversions::check_constructors(&types)
+    .unwrap_or_else(|e| panic!("The engine and its python types are out of sync: {}", e));
   #[allow(clippy::redundant_closure)] // I couldn't find an easy way to remove this closure.
   let mut tasks = with_tasks(tasks_ptr, |tasks| tasks.clone());
   tasks.intrinsics_set(&types);
@@ -252,6 +333,9 @@ pub extern "C" fn scheduler_create(
   let remote_store_servers_vec = remote_store_servers_buf
     .to_strings()
     .expect("Failed to decode remote_store_servers");
+  let remote_store_central_servers_vec = remote_store_central_servers_buf
+    .to_strings()
+    .expect("Failed to decode remote_store_central_servers");
   let remote_execution_server_string = remote_execution_server
     .to_string()
     .expect("remote_execution_server was not valid UTF8");
@@ -271,6 +355,12 @@ pub extern "C" fn scheduler_create(
         let (value, key) = (parts.pop().unwrap().to_owned(), parts.pop().unwrap().to_owned());
         (key, value)
       }).collect();
+  let remote_execution_allowed_platform_property_keys: BTreeSet<_> =
+    remote_execution_allowed_platform_property_keys_buf
+      .to_strings()
+      .expect("Failed to decode remote_execution_allowed_platform_property_keys")
+      .into_iter()
+      .collect();
 
   let remote_root_ca_certs_path = {
     let path = remote_root_ca_certs_path_buffer.to_os_string();
@@ -290,6 +380,158 @@ pub extern "C" fn scheduler_create(
     }
   };
 
+  // A max of zero indicates that process outputs are unlimited.
+  let remote_store_transfer_limits = fs::TransferLimits {
+    max_concurrent_transfers: Some(remote_store_max_concurrent_transfers as usize)
+      .filter(|max| *max > 0),
+    max_bytes_per_second: Some(remote_store_max_bytes_per_second).filter(|max| *max > 0),
+  };
+  let remote_store_timeouts = fs::RemoteStoreTimeouts {
+    find_missing_blobs: Duration::from_secs(remote_store_find_missing_blobs_timeout_seconds),
+    read: Duration::from_secs(remote_store_read_timeout_seconds),
+    write: Duration::from_secs(remote_store_chunk_upload_timeout_seconds),
+  };
+  let remote_execution_timeouts = process_execution::remote::ExecutionTimeouts {
+    execute_stream: Duration::from_secs(remote_execution_stream_timeout_seconds),
+    action_cache: Duration::from_secs(remote_execution_action_cache_timeout_seconds),
+  };
+  let process_execution_output_limit = if process_execution_max_output_bytes == 0 {
+    None
+  } else {
+    let policy = process_execution_output_overflow_policy
+      .to_string()
+      .map_err(|e| format!("{:?}", e))
+      .and_then(|policy| OutputOverflowPolicy::new(&policy))
+      .unwrap_or_else(|e| panic!("Invalid process_execution_output_overflow_policy: {}", e));
+    Some(OutputLimit {
+      max_bytes: process_execution_max_output_bytes as usize,
+      policy,
+    })
+  };
+
+  let remote_execution_verify_report = {
+    let path = remote_execution_verify_report_buffer.to_os_string();
+    if path.is_empty() {
+      None
+    } else {
+      Some(PathBuf::from(path))
+    }
+  };
+
+  let local_store_metadata_dir = {
+    let path = local_store_metadata_dir_buf.to_os_string();
+    if path.is_empty() {
+      None
+    } else {
+      Some(PathBuf::from(path))
+    }
+  };
+
+  // Explicitly configured proxies override those configured by the environment.
+  let proxies = {
+    let explicit_proxy = |buf: &Buffer| {
+      let proxy = buf.to_string().expect("Failed to decode proxy");
+      if proxy.is_empty() {
+        None
+      } else {
+        Some(proxy)
+      }
+    };
+    let no_proxy = no_proxy_buf
+      .to_strings()
+      .expect("Failed to decode no_proxy");
+    fs::ProxyConfig::from_env()
+      .and_then(|proxies| {
+        proxies.with_overrides(
+          explicit_proxy(&http_proxy_buf),
+          explicit_proxy(&https_proxy_buf),
+          Some(no_proxy).filter(|hosts| !hosts.is_empty()),
+        )
+      })
+      .unwrap_or_else(|e| panic!("Invalid proxy configuration: {}", e))
+  };
+
+  let process_execution_provenance_file = {
+    let path = process_execution_provenance_file_buffer.to_os_string();
+    if path.is_empty() {
+      None
+    } else {
+      Some(PathBuf::from(path))
+    }
+  };
+
+  let process_execution_artifact_index = {
+    let path = process_execution_artifact_index_buffer.to_os_string();
+    if path.is_empty() {
+      None
+    } else {
+      Some(PathBuf::from(path))
+    }
+  };
+
+  let process_execution_unused_inputs_report = {
+    let path = process_execution_unused_inputs_report_buffer.to_os_string();
+    if path.is_empty() {
+      None
+    } else {
+      Some(PathBuf::from(path))
+    }
+  };
+
+  // A max of zero indicates that process executions are unlimited.
+  let process_execution_count_limits = ProcessCountLimits {
+    per_session: Some(process_execution_max_per_session as usize).filter(|max| *max > 0),
+    per_minute: Some(process_execution_max_per_minute as usize).filter(|max| *max > 0),
+  };
+
+  let process_execution_audit_log = {
+    let path = process_execution_audit_log_buffer.to_os_string();
+    if path.is_empty() {
+      None
+    } else {
+      Some(AuditLogConfig {
+        path: PathBuf::from(path),
+        redact_env_patterns: process_execution_audit_redact_env_buf
+          .to_strings()
+          .expect("Failed to decode process_execution_audit_redact_env"),
+      })
+    }
+  };
+
+  let process_execution_determinism_check = {
+    let path = process_execution_determinism_check_report_buffer.to_os_string();
+    if path.is_empty() {
+      None
+    } else {
+      Some(DeterminismCheckConfig {
+        report_path: PathBuf::from(path),
+        description_patterns: process_execution_determinism_check_descriptions_buf
+          .to_strings()
+          .expect("Failed to decode process_execution_determinism_check_descriptions"),
+        vary_sandbox: process_execution_determinism_check_vary_sandbox,
+      })
+    }
+  };
+
+  let process_execution_jdks = JdkRegistry::discover(
+    &process_execution_jdk_search_paths_buf
+      .to_os_strings()
+      .into_iter()
+      .map(PathBuf::from)
+      .collect::<Vec<_>>(),
+  );
+
+  // A max of zero indicates that captured snapshots are unlimited.
+  let snapshot_size_limits = fs::SnapshotSizeLimits {
+    max_file_bytes: Some(snapshot_max_file_bytes).filter(|max| *max > 0),
+    max_total_bytes: Some(snapshot_max_total_bytes).filter(|max| *max > 0),
+  };
+  let snapshot_unicode_normalization = snapshot_unicode_normalization_buf
+    .to_string()
+    .map_err(|e| format!("{:?}", e))
+    .and_then(|policy| fs::UnicodeNormalization::create(&policy))
+    .unwrap_or_else(|e| panic!("Invalid snapshot_unicode_normalization: {}", e));
+
   Box::into_raw(Box::new(Scheduler::new(Core::new(
     root_type_ids.clone(),
     tasks,
@@ -298,7 +540,11 @@ pub extern "C" fn scheduler_create(
     &ignore_patterns,
     PathBuf::from(work_dir_buf.to_os_string()),
     PathBuf::from(local_store_dir_buf.to_os_string()),
+    local_store_metadata_dir,
+    offline,
+    proxies,
     remote_store_servers_vec,
+    remote_store_central_servers_vec,
     if remote_execution_server_string.is_empty() {
       None
     } else {
@@ -318,11 +564,35 @@ pub extern "C" fn scheduler_create(
     remote_oauth_bearer_token_path,
     remote_store_thread_count as usize,
     remote_store_chunk_bytes as usize,
-    Duration::from_secs(remote_store_chunk_upload_timeout_seconds),
+    remote_store_upload_window_chunks as usize,
+    remote_store_timeouts,
     remote_store_rpc_retries as usize,
+    remote_store_read_only,
+    remote_store_transfer_limits,
     remote_execution_extra_platform_properties_map,
+    remote_execution_allowed_platform_property_keys,
+    remote_execution_skip_cache_lookup,
+    remote_execution_action_cache_writes,
+    remote_execution_verify_cache_hits,
+    remote_execution_timeouts,
+    remote_execution_verify_report,
     process_execution_parallelism as usize,
+    // A budget of zero indicates that memory is unbounded.
+    Some(process_execution_memory_budget_mb as usize).filter(|budget| *budget > 0),
     process_execution_cleanup_local_dirs as bool,
+    process_execution_output_limit,
+    process_execution_provenance_file,
+    process_execution_artifact_index,
+    process_execution_unused_inputs_report,
+    process_execution_count_limits,
+    process_execution_audit_log,
+    process_execution_lint_hermeticity,
+    process_execution_determinism_check,
+    process_execution_jdks,
+    snapshot_size_limits,
+    snapshot_unicode_normalization,
+    engine_io_threads as usize,
+    engine_cpu_threads as usize,
   ))))
 }
 
@@ -347,6 +617,71 @@ pub extern "C" fn scheduler_metrics(
   })
 }
 
+///
+/// Drops interned keys which are no longer referenced by the graph, and returns the number dropped.
+///
+#[no_mangle]
+pub extern "C" fn scheduler_collect_interns(scheduler_ptr: *mut Scheduler) -> u64 {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    scheduler.collect_interns() as u64
+  })
+}
+
+///
+/// Returns (as JSON) a summary of the approximate memory held by each subsystem of the engine.
+///
+#[no_mangle]
+pub extern "C" fn scheduler_memory_summary(scheduler_ptr: *mut Scheduler) -> PyResult {
+  catching_panics(|| {
+    with_scheduler(scheduler_ptr, |scheduler| {
+      scheduler
+        .memory_summary()
+        .map(|summary| externs::store_utf8(&summary.to_string()))
+        .into()
+    })
+  })
+}
+
+///
+/// Returns a Handle representing a dictionary where key is an observation metric name string and
+/// value is a dictionary summarizing the histogram of observations recorded for that metric during
+/// the given Session.
+///
+#[no_mangle]
+pub extern "C" fn scheduler_observation_metrics(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+) -> Handle {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    with_session(session_ptr, |session| {
+      let values = scheduler
+        .observation_metrics(session)
+        .into_iter()
+        .flat_map(|(metric, summary)| {
+          let summary = externs::store_dict(&[
+            externs::store_utf8("count"),
+            externs::store_i64(summary.count as i64),
+            externs::store_utf8("min"),
+            externs::store_i64(summary.min as i64),
+            externs::store_utf8("max"),
+            externs::store_i64(summary.max as i64),
+            externs::store_utf8("mean"),
+            externs::store_f64(summary.mean),
+            externs::store_utf8("p50"),
+            externs::store_i64(summary.p50 as i64),
+            externs::store_utf8("p90"),
+            externs::store_i64(summary.p90 as i64),
+            externs::store_utf8("p99"),
+            externs::store_i64(summary.p99 as i64),
+          ]);
+          vec![externs::store_utf8(metric), summary]
+        })
+        .collect::<Vec<_>>();
+      externs::store_dict(&values).into()
+    })
+  })
+}
+
 ///
 /// Prepares to fork by shutting down any background threads used for execution, and then
 /// calling the given callback function (which should execute the fork) while holding exclusive
@@ -357,11 +692,13 @@ pub extern "C" fn scheduler_fork_context(
   scheduler_ptr: *mut Scheduler,
   func: Function,
 ) -> PyResult {
-  with_scheduler(scheduler_ptr, |scheduler| {
-    scheduler.core.fork_context(|| {
-      externs::exclusive_call(&func.0)
-        .map_err(|f| format!("{:?}", f))
-        .into()
+  catching_panics(|| {
+    with_scheduler(scheduler_ptr, |scheduler| {
+      scheduler.core.fork_context(|| {
+        externs::exclusive_call(&func.0)
+          .map_err(|f| format!("{:?}", f))
+          .into()
+      })
     })
   })
 }
@@ -375,9 +712,16 @@ pub extern "C" fn scheduler_execute(
   with_scheduler(scheduler_ptr, |scheduler| {
     with_execution_request(execution_request_ptr, |execution_request| {
       with_session(session_ptr, |session| {
-        Box::into_raw(RawNodes::create(
-          scheduler.execute(execution_request, session),
-        ))
+        // A panic while executing fails all of the roots of the request.
+        let results = panics::catch_unwind(|| scheduler.execute(execution_request, session))
+          .unwrap_or_else(|msg| {
+            execution_request
+              .roots
+              .iter()
+              .map(|_| Err(throw(&msg)))
+              .collect()
+          });
+        Box::into_raw(RawNodes::create(results))
       })
     })
   })
@@ -393,15 +737,22 @@ pub extern "C" fn scheduler_destroy(scheduler_ptr: *mut Scheduler) {
 #[no_mangle]
 pub extern "C" fn execution_add_root_select(
   scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
   execution_request_ptr: *mut ExecutionRequest,
   param_vals: HandleBuffer,
   product: TypeId,
 ) -> PyResult {
-  with_scheduler(scheduler_ptr, |scheduler| {
-    with_execution_request(execution_request_ptr, |execution_request| {
-      Params::new(param_vals.to_vec().into_iter().map(externs::key_for))
-        .and_then(|params| scheduler.add_root_select(execution_request, params, product))
-        .into()
+  catching_panics(|| {
+    with_scheduler(scheduler_ptr, |scheduler| {
+      with_session(session_ptr, |session| {
+        with_execution_request(execution_request_ptr, |execution_request| {
+          Params::new(param_vals.to_vec().into_iter().map(externs::key_for))
+            .and_then(|params| {
+              scheduler.add_root_select(execution_request, session, params, product)
+            })
+            .into()
+        })
+      })
     })
   })
 }
@@ -469,6 +820,46 @@ pub extern "C" fn graph_invalidate_all_paths(scheduler_ptr: *mut Scheduler) -> u
   })
 }
 
+///
+/// Starts watching the build root for changes, and invalidating the Graph as they are observed,
+/// which makes calls to `graph_invalidate` unnecessary for changes under the build root.
+///
+/// Changes to paths matching the invalidation globs are reported by `scheduler_restart_required`.
+///
+#[no_mangle]
+pub extern "C" fn scheduler_start_watching(
+  scheduler_ptr: *mut Scheduler,
+  backend_buf: Buffer,
+  watchman_socket_path_buf: Buffer,
+  invalidation_globs_buf: BufferBuffer,
+) -> PyResult {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    let watchman_socket_path = PathBuf::from(watchman_socket_path_buf.to_os_string());
+    let watchman_socket_path = if watchman_socket_path.as_os_str().is_empty() {
+      None
+    } else {
+      Some(watchman_socket_path)
+    };
+    backend_buf
+      .to_string()
+      .map_err(|e| format!("Watcher backend was not valid UTF8: {}", e))
+      .and_then(|backend| WatcherBackend::new(&backend, watchman_socket_path))
+      .and_then(|backend| {
+        let invalidation_globs = invalidation_globs_buf
+          .to_strings()
+          .map_err(|e| format!("Invalidation globs were not valid UTF8: {}", e))?;
+        let invalidation_globs = InvalidationGlobs::new(&invalidation_globs)?;
+        scheduler.start_watching(&backend, invalidation_globs)
+      })
+      .into()
+  })
+}
+
+#[no_mangle]
+pub extern "C" fn scheduler_restart_required(scheduler_ptr: *mut Scheduler) -> bool {
+  with_scheduler(scheduler_ptr, Scheduler::restart_required)
+}
+
 #[no_mangle]
 pub extern "C" fn graph_len(scheduler_ptr: *mut Scheduler) -> u64 {
   with_scheduler(scheduler_ptr, |scheduler| scheduler.core.graph.len() as u64)
@@ -479,27 +870,29 @@ pub extern "C" fn decompress_tarball(
   tar_path: *const raw::c_char,
   output_dir: *const raw::c_char,
 ) -> PyResult {
-  let tar_path_str = PathBuf::from(
-    unsafe { CStr::from_ptr(tar_path) }
-      .to_string_lossy()
-      .into_owned(),
-  );
-  let output_dir_str = PathBuf::from(
-    unsafe { CStr::from_ptr(output_dir) }
-      .to_string_lossy()
-      .into_owned(),
-  );
+  catching_panics(|| {
+    let tar_path_str = PathBuf::from(
+      unsafe { CStr::from_ptr(tar_path) }
+        .to_string_lossy()
+        .into_owned(),
+    );
+    let output_dir_str = PathBuf::from(
+      unsafe { CStr::from_ptr(output_dir) }
+        .to_string_lossy()
+        .into_owned(),
+    );
 
-  tar_api::decompress_tgz(tar_path_str.as_path(), output_dir_str.as_path())
-    .map_err(|e| {
-      format!(
-        "Failed to untar {:?} to {:?}:\n{:?}",
-        tar_path_str.as_path(),
-        output_dir_str.as_path(),
-        e
-      )
-    })
-    .into()
+    tar_api::decompress_tgz(tar_path_str.as_path(), output_dir_str.as_path())
+      .map_err(|e| {
+        format!(
+          "Failed to untar {:?} to {:?}:\n{:?}",
+          tar_path_str.as_path(),
+          output_dir_str.as_path(),
+          e
+        )
+      })
+      .into()
+  })
 }
 
 #[no_mangle]
@@ -507,14 +900,32 @@ pub extern "C" fn graph_visualize(
   scheduler_ptr: *mut Scheduler,
   session_ptr: *mut Session,
   path_ptr: *const raw::c_char,
+  node_types_buf: BufferBuffer,
+  max_depth: i64,
 ) -> PyResult {
   with_scheduler(scheduler_ptr, |scheduler| {
     with_session(session_ptr, |session| {
       let path_str = unsafe { CStr::from_ptr(path_ptr).to_string_lossy().into_owned() };
       let path = PathBuf::from(path_str);
-      scheduler
-        .visualize(session, path.as_path())
-        .map_err(|e| format!("Failed to visualize to {}: {:?}", path.display(), e))
+      // A negative max_depth is unlimited.
+      let max_depth = if max_depth < 0 {
+        None
+      } else {
+        Some(max_depth as usize)
+      };
+      node_types_buf
+        .to_strings()
+        .map_err(|e| format!("Node types were not valid UTF8: {}", e))
+        .and_then(|node_types| {
+          scheduler
+            .visualize(
+              session,
+              path.as_path(),
+              node_types.into_iter().collect(),
+              max_depth,
+            )
+            .map_err(|e| format!("Failed to visualize to {}: {:?}", path.display(), e))
+        })
         .into()
     })
   })
@@ -544,21 +955,302 @@ pub extern "C" fn nodes_destroy(raw_nodes_ptr: *mut RawNodes) {
   let _ = unsafe { Box::from_raw(raw_nodes_ptr) };
 }
 
+///
+/// Releases the memory backing a buffer which was passed to Python via `externs::store_buffer`.
+///
+#[no_mangle]
+pub extern "C" fn buffer_owner_destroy(buffer_owner_ptr: *mut BufferOwner) {
+  let _ = unsafe { Box::from_raw(buffer_owner_ptr) };
+}
+
 #[no_mangle]
 pub extern "C" fn session_create(
   scheduler_ptr: *mut Scheduler,
   should_render_ui: bool,
   ui_worker_count: u64,
+  ui_level_buf: Buffer,
+  timeout_secs: u64,
+  execution_strategy_buf: Buffer,
+  execution_policy_buf: Buffer,
 ) -> *const Session {
+  let ui_level = ui_level_buf
+    .to_string()
+    .map_err(|e| format!("{:?}", e))
+    .and_then(|level| {
+      log::Level::from_str(&level).map_err(|_| format!("Unrecognized level: {:?}", level))
+    })
+    .unwrap_or_else(|e| panic!("Invalid ui_level: {}", e));
+  let execution_strategy = execution_strategy_buf
+    .to_string()
+    .map_err(|e| format!("{:?}", e))
+    .and_then(|strategy| ExecutionStrategy::new(&strategy))
+    .unwrap_or_else(|e| panic!("Invalid execution_strategy: {}", e));
+  let execution_policy = execution_policy_buf
+    .to_string()
+    .map_err(|e| format!("{:?}", e))
+    .and_then(|policy| ExecutionPolicy::new(&policy))
+    .unwrap_or_else(|e| panic!("Invalid execution_policy: {}", e));
   with_scheduler(scheduler_ptr, |scheduler| {
     Box::into_raw(Box::new(Session::new(
       scheduler,
       should_render_ui,
       ui_worker_count as usize,
+      ui_level,
+      if timeout_secs == 0 {
+        None
+      } else {
+        Some(Duration::from_secs(timeout_secs))
+      },
+      execution_strategy,
+      execution_policy,
     )))
   })
 }
 
+///
+/// Tees all console output written by the engine for the given Session into the given file.
+///
+#[no_mangle]
+pub extern "C" fn session_tee_console(
+  session_ptr: *mut Session,
+  path_ptr: *const raw::c_char,
+) -> PyResult {
+  catching_panics(|| {
+    let path_str = unsafe { CStr::from_ptr(path_ptr).to_string_lossy().into_owned() };
+    with_session(session_ptr, |session| {
+      session.tee_console_to(&PathBuf::from(path_str)).into()
+    })
+  })
+}
+
+///
+/// Copies console output which Python wrote on behalf of the given Session to its tee, if any.
+///
+#[no_mangle]
+pub extern "C" fn session_write_to_console_tee(session_ptr: *mut Session, buf: Buffer) {
+  with_session(session_ptr, |session| {
+    session.write_to_console_tee(&buf.to_bytes())
+  })
+}
+
+///
+/// Writes a machine-readable report of the given Session to the given file.
+///
+#[no_mangle]
+pub extern "C" fn session_write_run_report(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+  path_ptr: *const raw::c_char,
+) -> PyResult {
+  catching_panics(|| {
+    let path_str = unsafe { CStr::from_ptr(path_ptr).to_string_lossy().into_owned() };
+    with_scheduler(scheduler_ptr, |scheduler| {
+      with_session(session_ptr, |session| {
+        scheduler
+          .write_run_report(session, &PathBuf::from(path_str))
+          .into()
+      })
+    })
+  })
+}
+
+///
+/// Starts sampling the stacks of the engine for the remainder of the Session.
+///
+#[no_mangle]
+pub extern "C" fn session_start_profiling(session_ptr: *mut Session, frequency: u64) -> PyResult {
+  catching_panics(|| {
+    with_session(session_ptr, |session| {
+      session.start_profiling(frequency as i32).into()
+    })
+  })
+}
+
+///
+/// Writes the stack samples of the Session so far as either a flamegraph (for an `.svg` path) or a
+/// pprof profile.
+///
+#[no_mangle]
+pub extern "C" fn session_write_profile(
+  session_ptr: *mut Session,
+  path_ptr: *const raw::c_char,
+) -> PyResult {
+  catching_panics(|| {
+    let path_str = unsafe { CStr::from_ptr(path_ptr).to_string_lossy().into_owned() };
+    with_session(session_ptr, |session| {
+      session.write_profile(&PathBuf::from(path_str)).into()
+    })
+  })
+}
+
+///
+/// Runs an interactive process in the foreground, pausing the UI of the Session while it has
+/// exclusive use of the console, and returns its exit code.
+///
+#[no_mangle]
+pub extern "C" fn session_run_interactive_process(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+  request: Handle,
+) -> PyResult {
+  catching_panics(|| {
+    let request = match nodes::lift_interactive_process_request(&request.into()) {
+      Ok(request) => request,
+      Err(err) => {
+        let e: Result<Value, String> = Err(err);
+        return e.into();
+      }
+    };
+    with_scheduler(scheduler_ptr, |scheduler| {
+      with_session(session_ptr, |session| {
+        let runner = scheduler.core.interactive_command_runner();
+        session
+          // Interactive processes are requested by running rules, so this may be called from a
+          // thread of the Runtime, where it is not possible to `block_on`.
+          .with_console(|| runner.run_interactive(request).wait())
+          .map(|exit_code| externs::store_i64(i64::from(exit_code)))
+          .into()
+      })
+    })
+  })
+}
+
+///
+/// Starts a process in the background (such as a dev server), which runs until it exits or the
+/// Session is dropped, and returns its id.
+///
+#[no_mangle]
+pub extern "C" fn session_start_background_process(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+  request: Handle,
+) -> PyResult {
+  catching_panics(|| {
+    // A BackgroundProcessRequest has the same fields as an InteractiveProcessRequest.
+    let request = match nodes::lift_interactive_process_request(&request.into()) {
+      Ok(request) => request,
+      Err(err) => {
+        let e: Result<Value, String> = Err(err);
+        return e.into();
+      }
+    };
+    with_scheduler(scheduler_ptr, |scheduler| {
+      with_session(session_ptr, |session| {
+        scheduler
+          .core
+          .interactive_command_runner()
+          .start_background(request)
+          .wait()
+          .map(|process| {
+            externs::store_i64(session.add_background_process(Arc::new(process)) as i64)
+          })
+          .into()
+      })
+    })
+  })
+}
+
+///
+/// Returns the id of the background process for the given named service, which is started (and
+/// health-checked) if it is not already running within the Session, and is otherwise shared.
+///
+#[no_mangle]
+pub extern "C" fn session_ensure_service(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+  request: Handle,
+) -> PyResult {
+  catching_panics(|| {
+    let request = match nodes::lift_service_request(&request.into()) {
+      Ok(request) => request,
+      Err(err) => {
+        let e: Result<Value, String> = Err(err);
+        return e.into();
+      }
+    };
+    with_scheduler(scheduler_ptr, |scheduler| {
+      with_session(session_ptr, |session| {
+        let runner = scheduler.core.interactive_command_runner();
+        session
+          .services
+          .ensure(request, |process| runner.start_background(process).wait())
+          .map(|process| externs::store_i64(session.add_background_process(process) as i64))
+          .into()
+      })
+    })
+  })
+}
+
+///
+/// Blocks until the background process with the given id has written more than `stdout_seen` bytes
+/// of stdout, until it has exited, or (as a heartbeat) until the timeout has elapsed. Returns a
+/// tuple of the Digests of its stdout and stderr so far, its stdout so far, its exit code (or None
+/// if it is still running), and the number of seconds since it started.
+///
+#[no_mangle]
+pub extern "C" fn session_background_process_progress(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+  id: u64,
+  stdout_seen: u64,
+  timeout_millis: u64,
+) -> PyResult {
+  catching_panics(|| {
+    with_scheduler(scheduler_ptr, |scheduler| {
+      with_session(session_ptr, |session| {
+        let process = match session.background_process(id) {
+          Ok(process) => process,
+          Err(err) => {
+            let e: Result<Value, String> = Err(err);
+            return e.into();
+          }
+        };
+        let progress =
+          process.wait_for_progress(stdout_seen as usize, Duration::from_millis(timeout_millis));
+        let stdout = progress.stdout.clone();
+        let store = scheduler.core.store();
+        store
+          .store_file_bytes(progress.stdout, true)
+          .join(store.store_file_bytes(progress.stderr, true))
+          .wait()
+          .map(|(stdout_digest, stderr_digest)| {
+            externs::store_tuple(&[
+              nodes::Snapshot::store_directory(&scheduler.core, &stdout_digest),
+              nodes::Snapshot::store_directory(&scheduler.core, &stderr_digest),
+              externs::store_bytes(&stdout),
+              progress
+                .exit_code
+                .map(|exit_code| externs::store_i64(i64::from(exit_code)))
+                .unwrap_or_else(|| externs::none().into()),
+              externs::store_f64(
+                progress.elapsed.as_secs() as f64
+                  + f64::from(progress.elapsed.subsec_nanos()) / 1_000_000_000.0,
+              ),
+            ])
+          })
+          .into()
+      })
+    })
+  })
+}
+
+///
+/// Kills the background process with the given id (if it has not already exited), and returns its
+/// exit code.
+///
+#[no_mangle]
+pub extern "C" fn session_stop_background_process(session_ptr: *mut Session, id: u64) -> PyResult {
+  catching_panics(|| {
+    with_session(session_ptr, |session| {
+      session
+        .background_process(id)
+        .and_then(|process| process.kill())
+        .map(|exit_code| externs::store_i64(i64::from(exit_code)))
+        .into()
+    })
+  })
+}
+
 #[no_mangle]
 pub extern "C" fn session_destroy(ptr: *mut Session) {
   let _ = unsafe { Box::from_raw(ptr) };
@@ -576,8 +1268,10 @@ pub extern "C" fn execution_request_destroy(ptr: *mut ExecutionRequest) {
 
 #[no_mangle]
 pub extern "C" fn validator_run(scheduler_ptr: *mut Scheduler) -> PyResult {
-  with_scheduler(scheduler_ptr, |scheduler| {
-    scheduler.core.rule_graph.validate().into()
+  catching_panics(|| {
+    with_scheduler(scheduler_ptr, |scheduler| {
+      scheduler.core.rule_graph.validate().into()
+    })
   })
 }
 
@@ -618,26 +1312,32 @@ pub extern "C" fn rule_subgraph_visualize(
   })
 }
 
-// TODO(#4884): This produces "thread panicked while processing panic. aborting." on my linux
-// laptop, requiring me to set RUST_BACKTRACE=1 (or "full") to get the panic message.
+///
+/// Installs a panic hook which captures the backtrace of each panic, so that panics caught at the
+/// boundaries of the engine surface as Python exceptions. If a (non-empty) crash report directory
+/// is given, a report of each panic is also written below it.
+///
 #[no_mangle]
-pub extern "C" fn set_panic_handler() {
-  panic::set_hook(Box::new(|panic_info| {
-    let mut panic_str = format!(
-      "panic at '{}'",
-      panic_info.payload().downcast_ref::<&str>().unwrap()
-    );
-
-    if let Some(location) = panic_info.location() {
-      let panic_location_str = format!(", {}:{}", location.file(), location.line());
-      panic_str.push_str(&panic_location_str);
-    }
-
-    error!("{}", panic_str);
+pub extern "C" fn set_panic_handler(crash_report_dir_ptr: *const raw::c_char) {
+  let crash_report_dir = unsafe { CStr::from_ptr(crash_report_dir_ptr) }
+    .to_string_lossy()
+    .into_owned();
+  panics::set_hook(if crash_report_dir.is_empty() {
+    None
+  } else {
+    Some(PathBuf::from(crash_report_dir))
+  });
+}
 
-    let panic_file_bug_str = "Please file a bug at https://github.com/pantsbuild/pants/issues.";
-    error!("{}", panic_file_bug_str);
-  }));
+///
+/// Runs the body of an FFI entrypoint, converting a panic into a Python exception rather than
+/// unwinding into the (C) caller, which would abort the process.
+///
+fn catching_panics<F: FnOnce() -> PyResult>(f: F) -> PyResult {
+  panics::catch_unwind(f).unwrap_or_else(|msg| {
+    let e: Result<Value, String> = Err(msg);
+    e.into()
+  })
 }
 
 #[no_mangle]
@@ -666,20 +1366,22 @@ pub extern "C" fn lease_files_in_graph(scheduler_ptr: *mut Scheduler) {
 
 #[no_mangle]
 pub extern "C" fn match_path_globs(path_globs: Handle, paths_buf: BufferBuffer) -> PyResult {
-  let path_globs = match nodes::Snapshot::lift_path_globs(&path_globs.into()) {
-    Ok(path_globs) => path_globs,
-    Err(msg) => {
-      let e: Result<(), _> = Err(msg);
-      return e.into();
-    }
-  };
+  catching_panics(|| {
+    let path_globs = match nodes::Snapshot::lift_path_globs(&path_globs.into()) {
+      Ok(path_globs) => path_globs,
+      Err(msg) => {
+        let e: Result<(), _> = Err(msg);
+        return e.into();
+      }
+    };
 
-  let paths = paths_buf
-    .to_os_strings()
-    .into_iter()
-    .map(PathBuf::from)
-    .collect::<Vec<_>>();
-  path_globs.matches(&paths).map(externs::store_bool).into()
+    let paths = paths_buf
+      .to_os_strings()
+      .into_iter()
+      .map(PathBuf::from)
+      .collect::<Vec<_>>();
+    path_globs.matches(&paths).map(externs::store_bool).into()
+  })
 }
 
 #[no_mangle]
@@ -687,55 +1389,115 @@ pub extern "C" fn capture_snapshots(
   scheduler_ptr: *mut Scheduler,
   path_globs_and_root_tuple_wrapper: Handle,
 ) -> PyResult {
-  let values = externs::project_multi(&path_globs_and_root_tuple_wrapper.into(), "dependencies");
-  let path_globs_and_roots_result = values
-    .iter()
-    .map(|value| {
-      let root = PathBuf::from(externs::project_str(&value, "root"));
-      let path_globs =
-        nodes::Snapshot::lift_path_globs(&externs::project_ignoring_type(&value, "path_globs"));
-      let digest_hint = {
-        let maybe_digest = externs::project_ignoring_type(&value, "digest_hint");
-        if maybe_digest == Value::from(externs::none()) {
-          None
-        } else {
-          Some(nodes::lift_digest(&maybe_digest)?)
-        }
-      };
-      path_globs.map(|path_globs| (path_globs, root, digest_hint))
+  catching_panics(|| {
+    let values = externs::project_multi(&path_globs_and_root_tuple_wrapper.into(), "dependencies");
+    let path_globs_and_roots_result = values
+      .iter()
+      .map(|value| {
+        let root = PathBuf::from(externs::project_str(&value, "root"));
+        let path_globs_value = externs::project_ignoring_type(&value, "path_globs");
+        let path_globs = nodes::Snapshot::lift_path_globs(&path_globs_value)?;
+        let size_limit_overrides = nodes::Snapshot::lift_size_limit_overrides(&path_globs_value)?;
+        let digest_hint = {
+          let maybe_digest = externs::project_ignoring_type(&value, "digest_hint");
+          if maybe_digest == Value::from(externs::none()) {
+            None
+          } else {
+            Some(nodes::lift_digest(&maybe_digest)?)
+          }
+        };
+        let remote_only =
+          externs::project_ignoring_type(&value, "remote_only") == externs::store_bool(true);
+        Ok((
+          path_globs,
+          size_limit_overrides,
+          root,
+          digest_hint,
+          remote_only,
+        ))
+      })
+      .collect::<Result<Vec<_>, _>>();
+
+    let path_globs_and_roots = match path_globs_and_roots_result {
+      Ok(v) => v,
+      Err(err) => {
+        let e: Result<Value, String> = Err(err);
+        return e.into();
+      }
+    };
+
+    with_scheduler(scheduler_ptr, |scheduler| {
+      let core = scheduler.core.clone();
+      core.block_on(
+        futures::future::join_all(
+          path_globs_and_roots
+            .into_iter()
+            .map(
+              |(path_globs, size_limit_overrides, root, digest_hint, remote_only)| {
+                let core = core.clone();
+                fs::Snapshot::capture_snapshot_from_arbitrary_root(
+                  core.store(),
+                  root,
+                  path_globs,
+                  digest_hint,
+                  remote_only,
+                  core
+                    .snapshot_size_limits
+                    .overridden_by(size_limit_overrides),
+                )
+                .map(move |snapshot| nodes::Snapshot::store_snapshot(&core, &snapshot))
+              },
+            )
+            .collect::<Vec<_>>(),
+        )
+        .map(|values| externs::store_tuple(&values)),
+      )
     })
-    .collect::<Result<Vec<_>, _>>();
+    .into()
+  })
+}
 
-  let path_globs_and_roots = match path_globs_and_roots_result {
-    Ok(v) => v,
-    Err(err) => {
-      let e: Result<Value, String> = Err(err);
-      return e.into();
-    }
-  };
+///
+/// Returns (as JSON) the protos which would be sent to execute the given ExecuteProcessRequest
+/// remotely, without executing it.
+///
+#[no_mangle]
+pub extern "C" fn remote_execution_dry_run(
+  scheduler_ptr: *mut Scheduler,
+  process_request: Handle,
+) -> PyResult {
+  catching_panics(|| {
+    with_scheduler(scheduler_ptr, |scheduler| {
+      nodes::ExecuteProcess::lift(&process_request.into(), &scheduler.core.jdks)
+        .and_then(|process| scheduler.core.remote_execution_dry_run(process.0))
+        .and_then(|dry_run| {
+          serde_json::to_string(&dry_run).map_err(|e| format!("Failed to serialize dry run: {}", e))
+        })
+        .map(|json| externs::store_utf8(&json))
+    })
+    .into()
+  })
+}
 
-  with_scheduler(scheduler_ptr, |scheduler| {
-    let core = scheduler.core.clone();
-    core.block_on(
-      futures::future::join_all(
-        path_globs_and_roots
-          .into_iter()
-          .map(|(path_globs, root, digest_hint)| {
-            let core = core.clone();
-            fs::Snapshot::capture_snapshot_from_arbitrary_root(
-              core.store(),
-              root,
-              path_globs,
-              digest_hint,
-            )
-            .map(move |snapshot| nodes::Snapshot::store_snapshot(&core, &snapshot))
-          })
-          .collect::<Vec<_>>(),
-      )
-      .map(|values| externs::store_tuple(&values)),
-    )
+///
+/// Returns (as JSON) the home, version, vendor and fingerprint of the JDK which the given version
+/// spec selects, for JVMs (nailguns, for example) which are launched outside of the engine.
+///
+#[no_mangle]
+pub extern "C" fn jdk_select(scheduler_ptr: *mut Scheduler, spec_buf: Buffer) -> PyResult {
+  catching_panics(|| {
+    with_scheduler(scheduler_ptr, |scheduler| {
+      spec_buf
+        .to_string()
+        .map_err(|e| format!("Failed to decode JDK version spec: {:?}", e))
+        .and_then(|spec| scheduler.core.jdks.select(&spec))
+        .and_then(|jdk| {
+          serde_json::to_string(&jdk).map_err(|e| format!("Failed to serialize JDK: {}", e))
+        })
+        .map(|json| externs::store_utf8(&json))
+    })
+    .into()
   })
-  .into()
 }
 
 #[no_mangle]
@@ -743,67 +1505,141 @@ pub extern "C" fn merge_directories(
   scheduler_ptr: *mut Scheduler,
   directories_value: Handle,
 ) -> PyResult {
-  let digests_result: Result<Vec<hashing::Digest>, String> =
-    externs::project_multi(&directories_value.into(), "dependencies")
-      .iter()
-      .map(|v| nodes::lift_digest(v))
-      .collect();
-  let digests = match digests_result {
-    Ok(d) => d,
-    Err(err) => {
-      let e: Result<Value, String> = Err(err);
-      return e.into();
-    }
-  };
-
-  with_scheduler(scheduler_ptr, |scheduler| {
-    scheduler
-      .core
-      .block_on(fs::Snapshot::merge_directories(
-        scheduler.core.store(),
-        digests,
-      ))
-      .map(|dir| nodes::Snapshot::store_directory(&scheduler.core, &dir))
-      .into()
+  catching_panics(|| {
+    let digests_result: Result<Vec<hashing::Digest>, String> =
+      externs::project_multi(&directories_value.into(), "dependencies")
+        .iter()
+        .map(|v| nodes::lift_digest(v))
+        .collect();
+    let digests = match digests_result {
+      Ok(d) => d,
+      Err(err) => {
+        let e: Result<Value, String> = Err(err);
+        return e.into();
+      }
+    };
+
+    with_scheduler(scheduler_ptr, |scheduler| {
+      scheduler
+        .core
+        .block_on(fs::Snapshot::merge_directories(
+          scheduler.core.store(),
+          digests,
+        ))
+        .map(|dir| nodes::Snapshot::store_directory(&scheduler.core, &dir))
+        .into()
+    })
   })
 }
 
 #[no_mangle]
 pub extern "C" fn materialize_directories(
   scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
   directories_paths_and_digests_value: Handle,
 ) -> PyResult {
-  let values = externs::project_multi(&directories_paths_and_digests_value.into(), "dependencies");
-  let directories_paths_and_digests_results: Result<Vec<(PathBuf, Digest)>, String> = values
-    .iter()
-    .map(|value| {
-      let dir = PathBuf::from(externs::project_str(&value, "path"));
-      let dir_digest =
-        nodes::lift_digest(&externs::project_ignoring_type(&value, "directory_digest"));
-      dir_digest.map(|dir_digest| (dir, dir_digest))
-    })
-    .collect();
+  catching_panics(|| {
+    let values =
+      externs::project_multi(&directories_paths_and_digests_value.into(), "dependencies");
+    let directories_paths_and_digests_results: Result<Vec<(PathBuf, Digest)>, String> = values
+      .iter()
+      .map(|value| {
+        let dir = PathBuf::from(externs::project_str(&value, "path"));
+        let dir_digest =
+          nodes::lift_digest(&externs::project_ignoring_type(&value, "directory_digest"));
+        dir_digest.map(|dir_digest| (dir, dir_digest))
+      })
+      .collect();
 
-  let dir_and_digests = match directories_paths_and_digests_results {
-    Ok(d) => d,
-    Err(err) => {
-      let e: Result<Value, String> = Err(err);
-      return e.into();
-    }
-  };
+    let dir_and_digests = match directories_paths_and_digests_results {
+      Ok(d) => d,
+      Err(err) => {
+        let e: Result<Value, String> = Err(err);
+        return e.into();
+      }
+    };
 
-  with_scheduler(scheduler_ptr, |scheduler| {
-    scheduler.core.block_on(
-      futures::future::join_all(
-        dir_and_digests
-          .into_iter()
-          .map(|(dir, digest)| scheduler.core.store().materialize_directory(dir, digest))
-          .collect::<Vec<_>>(),
-      )
-      .map(|_| ()),
-    )
+    with_scheduler(scheduler_ptr, |scheduler| {
+      with_session(session_ptr, |session| {
+        let run_id = session.run_id();
+        scheduler.core.block_on(
+          futures::future::join_all(
+            dir_and_digests
+              .into_iter()
+              .map(|(dir, digest)| {
+                let store = scheduler.core.store();
+                let artifact_index = scheduler.core.artifact_index.clone();
+                store
+                  .materialize_directory_with_progress(dir.clone(), digest)
+                  .and_then(move |()| match artifact_index {
+                    Some(index) => {
+                      ArtifactIndex::record_materialized(index, &store, run_id.0, dir, digest)
+                    }
+                    None => future::ok(()).to_boxed(),
+                  })
+              })
+              .collect::<Vec<_>>(),
+          )
+          .map(|_| ()),
+        )
+      })
+    })
+    .into()
+  })
+}
+
+#[no_mangle]
+pub extern "C" fn write_to_workspace(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+  write: Handle,
+) -> PyResult {
+  catching_panics(|| {
+    let write: Value = write.into();
+    let destination = PathBuf::from(externs::project_str(&write, "path"));
+    let digest_and_policy =
+      nodes::lift_digest(&externs::project_ignoring_type(&write, "directory_digest")).and_then(
+        |digest| {
+          fs::ConflictPolicy::new(&externs::project_str(&write, "conflict_policy"))
+            .map(|policy| (digest, policy))
+        },
+      );
+    let (digest, policy) = match digest_and_policy {
+      Ok(digest_and_policy) => digest_and_policy,
+      Err(err) => {
+        let e: Result<Value, String> = Err(err);
+        return e.into();
+      }
+    };
+
+    with_scheduler(scheduler_ptr, |scheduler| {
+      with_session(session_ptr, |session| {
+        let run_id = session.run_id();
+        let store = scheduler.core.store();
+        let artifact_index = scheduler.core.artifact_index.clone();
+        scheduler
+          .core
+          .block_on(
+            store
+              .write_to_workspace(destination.clone(), digest, policy)
+              .and_then(move |manifest| match artifact_index {
+                Some(index) => {
+                  ArtifactIndex::record_materialized(index, &store, run_id.0, destination, digest)
+                    .map(|()| manifest)
+                    .to_boxed()
+                }
+                None => future::ok(manifest).to_boxed(),
+              }),
+          )
+          .and_then(|manifest| {
+            serde_json::to_string(&manifest)
+              .map_err(|e| format!("Failed to serialize workspace manifest: {}", e))
+          })
+          .map(|json| externs::store_utf8(&json))
+      })
+    })
+    .into()
   })
-  .into()
 }
 
 // This is called before externs are set up, so we cannot return a PyResult
@@ -839,9 +1675,7 @@ pub extern "C" fn setup_stderr_logger(level: u64) {
 pub extern "C" fn write_log(msg: *const raw::c_char, level: u64, target: *const raw::c_char) {
   let message_str = unsafe { CStr::from_ptr(msg).to_string_lossy() };
   let target_str = unsafe { CStr::from_ptr(target).to_string_lossy() };
-  LOGGER
-    .log_from_python(message_str.borrow(), level, target_str.borrow())
-    .expect("Error logging message");
+  LOGGER.log_from_python(message_str.borrow(), level, target_str.borrow());
 }
 
 #[no_mangle]
@@ -871,18 +1705,20 @@ fn write_to_file(path: &Path, graph: &RuleGraph) -> io::Result<()> {
 }
 
 ///
-/// Scheduler and Session are intended to be shared between threads, and so their context
-/// methods provide immutable references. The remaining types are not intended to be shared
-/// between threads, so mutable access is provided.
+/// Scheduler and Session are intended to be shared between threads (python releases the GIL while
+/// calling into the engine, so any number of python threads may be inside of these methods at
+/// once), and so their context methods provide immutable references. The remaining types are not
+/// intended to be shared between threads, so mutable access is provided.
+///
+/// NB: None of these methods take ownership of the pointer: reconstituting a Box here would both
+/// alias the Box held by a concurrent caller, and free the pointee if `f` panicked.
 ///
 fn with_scheduler<F, T>(scheduler_ptr: *mut Scheduler, f: F) -> T
 where
   F: FnOnce(&Scheduler) -> T,
 {
-  let scheduler = unsafe { Box::from_raw(scheduler_ptr) };
-  let t = f(&scheduler);
-  mem::forget(scheduler);
-  t
+  let scheduler = unsafe { &*scheduler_ptr };
+  f(scheduler)
 }
 
 ///
@@ -892,10 +1728,8 @@ fn with_session<F, T>(session_ptr: *mut Session, f: F) -> T
 where
   F: FnOnce(&Session) -> T,
 {
-  let session = unsafe { Box::from_raw(session_ptr) };
-  let t = f(&session);
-  mem::forget(session);
-  t
+  let session = unsafe { &*session_ptr };
+  f(session)
 }
 
 ///
@@ -905,10 +1739,8 @@ fn with_execution_request<F, T>(execution_request_ptr: *mut ExecutionRequest, f:
 where
   F: FnOnce(&mut ExecutionRequest) -> T,
 {
-  let mut execution_request = unsafe { Box::from_raw(execution_request_ptr) };
-  let t = f(&mut execution_request);
-  mem::forget(execution_request);
-  t
+  let execution_request = unsafe { &mut *execution_request_ptr };
+  f(execution_request)
 }
 
 ///
@@ -918,8 +1750,6 @@ fn with_tasks<F, T>(tasks_ptr: *mut Tasks, f: F) -> T
 where
   F: FnOnce(&mut Tasks) -> T,
 {
-  let mut tasks = unsafe { Box::from_raw(tasks_ptr) };
-  let t = f(&mut tasks);
-  mem::forget(tasks);
-  t
+  let tasks = unsafe { &mut *tasks_ptr };
+  f(tasks)
 }
@@ -1,22 +1,25 @@
 // Copyright 2017 Pants project contributors (see CONTRIBUTORS.md).
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt::Display;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{self, fmt};
 
 use futures::future::{self, Future};
 use futures::Stream;
+use futures_timer::Delay;
 use url::Url;
 
-use crate::context::{Context, Core};
+use crate::context::{Context, Core, RunId};
+use crate::conversions::{self, process_request, process_result};
 use crate::core::{throw, Failure, Key, Params, TypeId, Value};
 use crate::externs;
+use crate::panics;
 use crate::rule_graph;
 use crate::selectors;
 use crate::tasks::{self, Intrinsic};
@@ -24,10 +27,16 @@ use boxfuture::{try_future, BoxFuture, Boxable};
 use bytes::{self, BufMut};
 use fs::{
   self, Dir, DirectoryListing, File, FileContent, GlobExpansionConjunction, GlobMatching, Link,
-  PathGlobs, PathStat, StoreFileByDigest, StrictGlobMatching, VFS,
+  PathGlobs, PathStat, SnapshotSizeLimits, SpecialFileBehavior, StoreFileByDigest,
+  StrictGlobMatching, VFS,
 };
 use hashing;
-use process_execution::{self, CommandRunner};
+use log::{warn, Level};
+use metrics::Metric;
+use process_execution::jdk::{JdkRegistry, JDK_FINGERPRINT_ENV_VAR};
+use process_execution::service::ServiceRequest;
+use process_execution::strategy::ExecutionStrategy;
+use process_execution::{self, CommandRunner, OutputLimit, OutputOverflowPolicy};
 
 use graph::{Entry, Node, NodeError, NodeTracer, NodeVisualizer};
 
@@ -58,6 +67,7 @@ impl VFS<Failure> for Context {
     Failure::Throw(
       externs::create_exception(msg),
       "<pants native internals>".to_string(),
+      Vec::new(),
     )
   }
 }
@@ -96,6 +106,8 @@ pub struct Select {
   pub params: Params,
   pub product: TypeId,
   entry: rule_graph::Entry,
+  // Set for a root Select of an uncacheable Task: see `Select::salted`.
+  run_id: Option<RunId>,
 }
 
 impl Select {
@@ -108,9 +120,30 @@ impl Select {
       params,
       product,
       entry,
+      run_id: None,
     }
   }
 
+  ///
+  /// If this Select will run an uncacheable Task, keys it by the given RunId, so that it is
+  /// memoized only within the Session with that RunId.
+  ///
+  /// Only roots need to be salted: all other Selects run inline within their dependee, which will
+  /// itself have been salted if necessary.
+  ///
+  pub fn salted(mut self, run_id: RunId) -> Select {
+    self.run_id = match &self.entry {
+      &rule_graph::Entry::WithDeps(rule_graph::EntryWithDeps::Inner(ref inner)) => {
+        match inner.rule() {
+          &rule_graph::Rule::Task(ref task) => Task::run_id_for(task, run_id),
+          _ => None,
+        }
+      }
+      _ => None,
+    };
+    self
+  }
+
   pub fn new_from_edges(params: Params, product: TypeId, edges: &rule_graph::RuleEdges) -> Select {
     let select_key = rule_graph::SelectKey::JustSelect(selectors::Select::new(product));
     // TODO: Is it worth propagating an error here?
@@ -157,6 +190,7 @@ impl WrappedNode for Select {
           product: self.product,
           task: task.clone(),
           entry: Arc::new(self.entry.clone()),
+          run_id: Task::run_id_for(task, context.run_id),
         }),
         &rule_graph::Rule::Intrinsic(Intrinsic { product, input })
           if product == context.core.types.snapshot && input == context.core.types.path_globs =>
@@ -184,24 +218,17 @@ impl WrappedNode for Select {
           if product == context.core.types.directory_digest
             && input == context.core.types.directories_to_merge =>
         {
-          let request = self.select_product(
-            &context,
-            context.core.types.directories_to_merge,
-            "intrinsic",
-          );
+          let context = context.clone();
           let core = context.core.clone();
-          request
-            .and_then(move |request| {
-              let digests: Result<Vec<hashing::Digest>, Failure> =
-                externs::project_multi(&request, "directories")
-                  .into_iter()
-                  .map(|val| lift_digest(&val).map_err(|str| throw(&str)))
-                  .collect();
-              fs::Snapshot::merge_directories(core.store(), try_future!(digests))
-                .map_err(|err| throw(&err))
-                .map(move |digest| Snapshot::store_directory(&core, &digest))
-                .to_boxed()
-            })
+          self
+            .select_product(
+              &context,
+              context.core.types.directories_to_merge,
+              "intrinsic",
+            )
+            .and_then(|request| MergeDigests::lift(&request).map_err(|str| throw(&str)))
+            .and_then(move |node| context.get(node))
+            .map(move |digest| Snapshot::store_directory(&core, &digest))
             .to_boxed()
         }
         &rule_graph::Rule::Intrinsic(Intrinsic { product, input })
@@ -225,27 +252,76 @@ impl WrappedNode for Select {
           if product == context.core.types.directory_digest
             && input == context.core.types.directory_with_prefix_to_strip =>
         {
-          let request = self.select_product(
-            &context,
-            context.core.types.directory_with_prefix_to_strip,
-            "intrinsic",
-          );
+          let context = context.clone();
+          let core = context.core.clone();
+          self
+            .select_product(
+              &context,
+              context.core.types.directory_with_prefix_to_strip,
+              "intrinsic",
+            )
+            .and_then(|request| RemovePrefix::lift(&request).map_err(|str| throw(&str)))
+            .and_then(move |node| context.get(node))
+            .map(move |digest| Snapshot::store_directory(&core, &digest))
+            .to_boxed()
+        }
+        &rule_graph::Rule::Intrinsic(Intrinsic { product, input })
+          if product == context.core.types.directory_digest
+            && input == context.core.types.directory_with_prefix_to_add =>
+        {
+          let context = context.clone();
           let core = context.core.clone();
-          request
-            .and_then(move |request| {
-              let digest = lift_digest(&externs::project_ignoring_type(
-                &request,
-                "directory_digest",
-              ))
-              .map_err(|str| throw(&str))?;
-              let prefix = externs::project_str(&request, "prefix");
-              Ok((digest, prefix))
-            })
-            .and_then(|(digest, prefix)| {
-              fs::Snapshot::strip_prefix(core.store(), digest, PathBuf::from(prefix))
-                .map_err(|err| throw(&err))
-                .map(move |digest| Snapshot::store_directory(&core, &digest))
-            })
+          self
+            .select_product(
+              &context,
+              context.core.types.directory_with_prefix_to_add,
+              "intrinsic",
+            )
+            .and_then(|request| AddPrefix::lift(&request).map_err(|str| throw(&str)))
+            .and_then(move |node| context.get(node))
+            .map(move |digest| Snapshot::store_directory(&core, &digest))
+            .to_boxed()
+        }
+        &rule_graph::Rule::Intrinsic(Intrinsic { product, input })
+          if product == context.core.types.snapshot
+            && input == context.core.types.snapshot_subset =>
+        {
+          let context = context.clone();
+          let core = context.core.clone();
+          self
+            .select_product(&context, context.core.types.snapshot_subset, "intrinsic")
+            .and_then(move |request| context.get(SnapshotSubset(externs::key_for(request))))
+            .map(move |snapshot| Snapshot::store_snapshot(&core, &snapshot))
+            .to_boxed()
+        }
+        &rule_graph::Rule::Intrinsic(Intrinsic { product, input })
+          if product == context.core.types.directory_digest
+            && input == context.core.types.archive_to_extract =>
+        {
+          let context = context.clone();
+          let core = context.core.clone();
+          self
+            .select_product(&context, context.core.types.archive_to_extract, "intrinsic")
+            .and_then(|request| ExtractDigest::lift(&request).map_err(|str| throw(&str)))
+            .and_then(move |node| context.get(node))
+            .map(move |digest| Snapshot::store_directory(&core, &digest))
+            .to_boxed()
+        }
+        &rule_graph::Rule::Intrinsic(Intrinsic { product, input })
+          if product == context.core.types.directory_digest
+            && input == context.core.types.directory_to_archive =>
+        {
+          let context = context.clone();
+          let core = context.core.clone();
+          self
+            .select_product(
+              &context,
+              context.core.types.directory_to_archive,
+              "intrinsic",
+            )
+            .and_then(|request| CreateArchive::lift(&request).map_err(|str| throw(&str)))
+            .and_then(move |node| context.get(node))
+            .map(move |digest| Snapshot::store_directory(&core, &digest))
             .to_boxed()
         }
         &rule_graph::Rule::Intrinsic(Intrinsic { product, input })
@@ -268,28 +344,95 @@ impl WrappedNode for Select {
             })
             .to_boxed()
         }
+        &rule_graph::Rule::Intrinsic(Intrinsic { product, input })
+          if product == context.core.types.files_content_view
+            && input == context.core.types.directory_digest =>
+        {
+          let context = context.clone();
+          self
+            .select_product(&context, context.core.types.directory_digest, "intrinsic")
+            .and_then(|directory_digest_val| {
+              lift_digest(&directory_digest_val).map_err(|str| throw(&str))
+            })
+            .and_then(move |digest| {
+              context
+                .core
+                .store()
+                .contents_for_directory(digest)
+                .map_err(|str| throw(&str))
+                .map(move |files_content| {
+                  Snapshot::store_files_content_view(&context, files_content)
+                })
+            })
+            .to_boxed()
+        }
         &rule_graph::Rule::Intrinsic(Intrinsic { product, input })
           if product == context.core.types.process_result
             && input == context.core.types.process_request =>
         {
           let context = context.clone();
           let core = context.core.clone();
+          let lift_core = context.core.clone();
           self
             .select_product(&context, context.core.types.process_request, "intrinsic")
-            .and_then(|request| {
-              ExecuteProcess::lift(&request)
+            .and_then(move |request| {
+              ExecuteProcess::lift(&request, &lift_core.jdks)
                 .map_err(|str| throw(&format!("Error lifting ExecuteProcess: {}", str)))
             })
-            .and_then(move |process_request| context.get(process_request))
-            .map(move |result| {
+            .and_then(move |process_request| context.get(process_request.salted(&context)))
+            .map(move |result| store_process_result(&core, &result))
+            .to_boxed()
+        }
+        &rule_graph::Rule::Intrinsic(Intrinsic { product, input })
+          if product == context.core.types.sharded_process_results
+            && input == context.core.types.sharded_process_request =>
+        {
+          let context = context.clone();
+          let core = context.core.clone();
+          let lift_core = context.core.clone();
+          self
+            .select_product(
+              &context,
+              context.core.types.sharded_process_request,
+              "intrinsic",
+            )
+            .and_then(move |request| {
+              ExecuteProcess::lift_shards(&request, &lift_core.jdks)
+                .map_err(|str| throw(&format!("Error lifting sharded ExecuteProcess: {}", str)))
+            })
+            .and_then(move |shards| {
+              // The shards share their input files, so they are uploaded once up front (rather than
+              // concurrently by each shard) for remote execution.
+              let upload = if context.core.remote_execution {
+                context
+                  .core
+                  .store()
+                  .with_upload_scope(context.process_context.uploads.clone())
+                  .ensure_remote_has_recursive(vec![shards[0].0.input_files])
+                  .map(|_| ())
+                  .map_err(|e| throw(&format!("Failed to upload inputs of shards: {}", e)))
+                  .to_boxed()
+              } else {
+                ok(())
+              };
+              upload.and_then(move |()| {
+                future::join_all(
+                  shards
+                    .into_iter()
+                    .map(|shard| context.get(shard.salted(&context)))
+                    .collect::<Vec<_>>(),
+                )
+              })
+            })
+            .map(move |results| {
               externs::unsafe_call(
-                &core.types.construct_process_result,
-                &[
-                  externs::store_bytes(&result.0.stdout),
-                  externs::store_bytes(&result.0.stderr),
-                  externs::store_i64(result.0.exit_code.into()),
-                  Snapshot::store_directory(&core, &result.0.output_directory),
-                ],
+                &core.types.construct_sharded_process_results,
+                &[externs::store_tuple(
+                  &results
+                    .iter()
+                    .map(|result| store_process_result(&core, result))
+                    .collect::<Vec<_>>(),
+                )],
               )
             })
             .to_boxed()
@@ -320,8 +463,9 @@ impl From<Select> for NodeKey {
 }
 
 pub fn lift_digest(digest: &Value) -> Result<hashing::Digest, String> {
-  let fingerprint = externs::project_str(&digest, "fingerprint");
-  let digest_length = externs::project_str(&digest, "serialized_bytes_length");
+  conversions::DIGEST.check_lifted(digest)?;
+  let fingerprint = externs::project_str(&digest, conversions::digest::FINGERPRINT);
+  let digest_length = externs::project_str(&digest, conversions::digest::SERIALIZED_BYTES_LENGTH);
   let digest_length_as_usize = digest_length
     .parse::<usize>()
     .map_err(|err| format!("Length was not a usize: {:?}", err))?;
@@ -331,42 +475,164 @@ pub fn lift_digest(digest: &Value) -> Result<hashing::Digest, String> {
   ))
 }
 
+///
+/// Lifts the `env` field of a python process request value, which is a flattened tuple of
+/// alternating names and values.
+///
+pub fn lift_env(value: &Value) -> Result<BTreeMap<String, String>, String> {
+  let mut env: BTreeMap<String, String> = BTreeMap::new();
+  let env_var_parts = externs::project_multi_strs(&value, process_request::ENV);
+  if env_var_parts.len() % 2 != 0 {
+    return Err("Error parsing env: odd number of parts".to_owned());
+  }
+  for i in 0..(env_var_parts.len() / 2) {
+    env.insert(
+      env_var_parts[2 * i].clone(),
+      env_var_parts[2 * i + 1].clone(),
+    );
+  }
+  Ok(env)
+}
+
+///
+/// Lifts a python InteractiveProcessRequest value.
+///
+pub fn lift_interactive_process_request(
+  value: &Value,
+) -> Result<process_execution::InteractiveProcessRequest, String> {
+  Ok(process_execution::InteractiveProcessRequest {
+    argv: externs::project_multi_strs(&value, "argv"),
+    env: lift_env(value)?,
+    input_files: lift_digest(&externs::project_ignoring_type(&value, "input_files"))
+      .map_err(|err| format!("Error parsing digest {}", err))?,
+    description: externs::project_str(&value, "description"),
+  })
+}
+
+///
+/// Lifts a python ServiceRequest value.
+///
+pub fn lift_service_request(value: &Value) -> Result<ServiceRequest, String> {
+  let startup_timeout_str = externs::project_str(&value, "startup_timeout_seconds");
+  let startup_timeout_in_seconds = startup_timeout_str
+    .parse::<f64>()
+    .map_err(|err| format!("Startup timeout was not a float: {:?}", err))?;
+  if startup_timeout_in_seconds < 0.0 {
+    return Err(format!(
+      "Startup timeout was negative: {:?}",
+      startup_timeout_in_seconds
+    ));
+  }
+  let ready_stdout = externs::project_str(&value, "ready_stdout");
+  Ok(ServiceRequest {
+    name: externs::project_str(&value, "name"),
+    // A service is started with the same fields as an InteractiveProcessRequest.
+    process: lift_interactive_process_request(&externs::project_ignoring_type(&value, "process"))?,
+    ready_stdout: if ready_stdout.is_empty() {
+      None
+    } else {
+      Some(ready_stdout)
+    },
+    startup_timeout: Duration::from_millis((startup_timeout_in_seconds * 1000.0) as u64),
+  })
+}
+
+///
+/// Stores a ProcessResult as a python FallibleExecuteProcessResult value.
+///
+fn store_process_result(core: &Arc<Core>, result: &ProcessResult) -> Value {
+  conversions::PROCESS_RESULT.construct(
+    &core.types.construct_process_result,
+    &[
+      (
+        process_result::STDOUT,
+        externs::store_bytes(&result.0.stdout),
+      ),
+      (
+        process_result::STDERR,
+        externs::store_bytes(&result.0.stderr),
+      ),
+      (
+        process_result::EXIT_CODE,
+        externs::store_i64(result.0.exit_code.into()),
+      ),
+      (
+        process_result::OUTPUT_DIRECTORY_DIGEST,
+        Snapshot::store_directory(core, &result.0.output_directory),
+      ),
+      (
+        process_result::RUNNER,
+        externs::store_utf8(&result.0.runner),
+      ),
+      (
+        process_result::ATTEMPT_COUNT,
+        externs::store_i64(result.0.attempt_count() as i64),
+      ),
+      (
+        process_result::TIMEOUT_SECONDS,
+        result
+          .0
+          .timed_out
+          .map(|timed_out| externs::store_f64(duration_secs(timed_out.timeout)))
+          .unwrap_or_else(|| externs::none().into()),
+      ),
+      (
+        process_result::ELAPSED_SECONDS,
+        result
+          .0
+          .timed_out
+          .map(|timed_out| externs::store_f64(duration_secs(timed_out.elapsed)))
+          .unwrap_or_else(|| externs::none().into()),
+      ),
+    ],
+  )
+}
+
+fn duration_secs(duration: Duration) -> f64 {
+  duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
 ///
 /// A Node that represents executing a process.
 ///
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
-pub struct ExecuteProcess(process_execution::ExecuteProcessRequest);
+pub struct ExecuteProcess(
+  pub process_execution::ExecuteProcessRequest,
+  // Set for side-effecting processes, which should run once per Session.
+  Option<RunId>,
+  // The strategy with which the process is executed, so that results are not shared between
+  // Sessions with different strategies.
+  ExecutionStrategy,
+);
 
 impl ExecuteProcess {
   ///
   /// Lifts a Key representing a python ExecuteProcessRequest value into a ExecuteProcess Node.
   ///
-  fn lift(value: &Value) -> Result<ExecuteProcess, String> {
-    let mut env: BTreeMap<String, String> = BTreeMap::new();
-    let env_var_parts = externs::project_multi_strs(&value, "env");
-    if env_var_parts.len() % 2 != 0 {
-      return Err("Error parsing env: odd number of parts".to_owned());
-    }
-    for i in 0..(env_var_parts.len() / 2) {
-      env.insert(
-        env_var_parts[2 * i].clone(),
-        env_var_parts[2 * i + 1].clone(),
-      );
-    }
-    let digest = lift_digest(&externs::project_ignoring_type(&value, "input_files"))
-      .map_err(|err| format!("Error parsing digest {}", err))?;
+  /// A request may specify a JDK version spec rather than a `jdk_home`, in which case the JDK is
+  /// selected from the given registry, and its fingerprint is added to the env of the request.
+  ///
+  pub fn lift(value: &Value, jdks: &JdkRegistry) -> Result<ExecuteProcess, String> {
+    conversions::PROCESS_REQUEST.check_lifted(value)?;
+    let mut env = lift_env(value)?;
+    let digest = lift_digest(&externs::project_ignoring_type(
+      &value,
+      process_request::INPUT_FILES,
+    ))
+    .map_err(|err| format!("Error parsing digest {}", err))?;
 
-    let output_files = externs::project_multi_strs(&value, "output_files")
+    let output_files = externs::project_multi_strs(&value, process_request::OUTPUT_FILES)
       .into_iter()
       .map(PathBuf::from)
       .collect();
 
-    let output_directories = externs::project_multi_strs(&value, "output_directories")
-      .into_iter()
-      .map(PathBuf::from)
-      .collect();
+    let output_directories =
+      externs::project_multi_strs(&value, process_request::OUTPUT_DIRECTORIES)
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
 
-    let timeout_str = externs::project_str(&value, "timeout_seconds");
+    let timeout_str = externs::project_str(&value, process_request::TIMEOUT_SECONDS);
     let timeout_in_seconds = timeout_str
       .parse::<f64>()
       .map_err(|err| format!("Timeout was not a float: {:?}", err))?;
@@ -375,43 +641,246 @@ impl ExecuteProcess {
       return Err(format!("Timeout was negative: {:?}", timeout_in_seconds));
     }
 
-    let description = externs::project_str(&value, "description");
+    let description = externs::project_str(&value, process_request::DESCRIPTION);
 
     let jdk_home = {
-      let val = externs::project_str(&value, "jdk_home");
-      if val.is_empty() {
+      let val = externs::project_str(&value, process_request::JDK_HOME);
+      let spec = externs::project_str(&value, process_request::JDK);
+      match (val.is_empty(), spec.is_empty()) {
+        (true, true) => None,
+        (false, true) => Some(PathBuf::from(val)),
+        (true, false) => {
+          let jdk = jdks.select(&spec)?;
+          env.insert(JDK_FINGERPRINT_ENV_VAR.to_owned(), jdk.fingerprint);
+          Some(jdk.home)
+        }
+        (false, false) => return Err("Only one of jdk_home and jdk may be set.".to_owned()),
+      }
+    };
+
+    let mut platform_properties: BTreeMap<String, String> = BTreeMap::new();
+    let platform_property_parts =
+      externs::project_multi_strs(&value, process_request::PLATFORM_PROPERTIES);
+    if platform_property_parts.len() % 2 != 0 {
+      return Err("Error parsing platform_properties: odd number of parts".to_owned());
+    }
+    for i in 0..(platform_property_parts.len() / 2) {
+      platform_properties.insert(
+        platform_property_parts[2 * i].clone(),
+        platform_property_parts[2 * i + 1].clone(),
+      );
+    }
+
+    let output_limit = {
+      let max_output_bytes = externs::project_str(&value, process_request::MAX_OUTPUT_BYTES);
+      if max_output_bytes.is_empty() {
+        None
+      } else {
+        let max_bytes = max_output_bytes
+          .parse::<usize>()
+          .map_err(|err| format!("max_output_bytes was not a non-negative int: {:?}", err))?;
+        let policy = externs::project_str(&value, process_request::OUTPUT_OVERFLOW_POLICY);
+        let policy = if policy.is_empty() {
+          OutputOverflowPolicy::TruncateHead
+        } else {
+          OutputOverflowPolicy::new(&policy)?
+        };
+        Some(OutputLimit { max_bytes, policy })
+      }
+    };
+
+    let stdin_digest = {
+      let val = externs::project_ignoring_type(&value, process_request::STDIN_DIGEST);
+      if val == Value::from(externs::none()) {
+        None
+      } else {
+        Some(lift_digest(&val).map_err(|err| format!("Error parsing stdin digest {}", err))?)
+      }
+    };
+
+    let secret_env: BTreeSet<String> =
+      externs::project_multi_strs(&value, process_request::SECRET_ENV)
+        .into_iter()
+        .collect();
+    if let Some(name) = secret_env.iter().find(|name| !env.contains_key(*name)) {
+      return Err(format!("Secret env var {} was not set in env.", name));
+    }
+
+    let memory_estimate_mb = {
+      let memory_estimate_mb = externs::project_str(&value, process_request::MEMORY_ESTIMATE_MB);
+      if memory_estimate_mb.is_empty() {
         None
       } else {
-        Some(PathBuf::from(val))
+        Some(
+          memory_estimate_mb
+            .parse::<usize>()
+            .map_err(|err| format!("memory_estimate_mb was not a non-negative int: {:?}", err))?,
+        )
       }
     };
 
-    Ok(ExecuteProcess(process_execution::ExecuteProcessRequest {
-      argv: externs::project_multi_strs(&value, "argv"),
-      env: env,
-      input_files: digest,
-      output_files: output_files,
-      output_directories: output_directories,
-      timeout: Duration::from_millis((timeout_in_seconds * 1000.0) as u64),
-      description: description,
-      jdk_home: jdk_home,
-    }))
+    Ok(ExecuteProcess(
+      process_execution::ExecuteProcessRequest {
+        argv: externs::project_multi_strs(&value, process_request::ARGV),
+        env: env,
+        input_files: digest,
+        output_files: output_files,
+        output_directories: output_directories,
+        timeout: Duration::from_millis((timeout_in_seconds * 1000.0) as u64),
+        description: description,
+        jdk_home: jdk_home,
+        output_limit: output_limit,
+        platform_properties: platform_properties,
+        stdin_digest: stdin_digest,
+        secret_env: secret_env,
+        preserve_empty_output_directories: externs::project_ignoring_type(
+          &value,
+          process_request::PRESERVE_EMPTY_OUTPUT_DIRECTORIES,
+        ) == externs::store_bool(true),
+        side_effecting: externs::project_ignoring_type(&value, process_request::SIDE_EFFECTING)
+          == externs::store_bool(true),
+        memory_estimate_mb: memory_estimate_mb,
+      },
+      None,
+      ExecutionStrategy::Default,
+    ))
+  }
+
+  ///
+  /// Lifts a python ShardedExecuteProcessRequest value into one ExecuteProcess Node per shard, each
+  /// of which is the template with the args of the shard appended to its argv. Because each shard
+  /// is a separate Node, a shard which fails (or whose inputs change) can be re-executed without
+  /// re-executing the others.
+  ///
+  pub fn lift_shards(value: &Value, jdks: &JdkRegistry) -> Result<Vec<ExecuteProcess>, String> {
+    let template = ExecuteProcess::lift(&externs::project_ignoring_type(&value, "template"), jdks)?;
+    let shards = externs::project_multi(&value, "shards");
+    if shards.is_empty() {
+      return Err("A sharded process must have at least one shard.".to_owned());
+    }
+    let shard_count = shards.len();
+    Ok(
+      shards
+        .iter()
+        .enumerate()
+        .map(|(index, shard)| {
+          let mut request = template.0.clone();
+          request
+            .argv
+            .extend(externs::project_multi_strs(shard, "args"));
+          if !request.description.is_empty() {
+            request.description = format!(
+              "{} (shard {}/{})",
+              request.description,
+              index + 1,
+              shard_count
+            );
+          }
+          ExecuteProcess(request, None, ExecutionStrategy::Default)
+        })
+        .collect(),
+    )
   }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct ProcessResult(process_execution::FallibleExecuteProcessResult);
 
+///
+/// ProcessResults are equal if the processes produced the same outputs, regardless of how (or how
+/// quickly) they were executed. When a process re-runs and produces the same outputs as it did
+/// previously, this allows its dependees to be cleaned rather than re-run.
+///
+impl PartialEq for ProcessResult {
+  fn eq(&self, other: &ProcessResult) -> bool {
+    let (ours, theirs) = (&self.0, &other.0);
+    ours.stdout == theirs.stdout
+      && ours.stderr == theirs.stderr
+      && ours.exit_code == theirs.exit_code
+      && ours.output_directory == theirs.output_directory
+      && ours.timed_out.is_some() == theirs.timed_out.is_some()
+  }
+}
+
+impl Eq for ProcessResult {}
+
+impl ExecuteProcess {
+  ///
+  /// Side-effecting processes are keyed by the RunId of the given Context, so that (like
+  /// uncacheable Tasks) they are memoized only within the Session with that RunId, rather than
+  /// being served from the Graph in later Sessions. All processes are keyed by the current
+  /// ExecutionStrategy.
+  ///
+  pub fn salted(mut self, context: &Context) -> ExecuteProcess {
+    if self.0.side_effecting {
+      self.1 = Some(context.run_id);
+    }
+    self.2 = context.execution_strategy;
+    self
+  }
+
+  ///
+  /// Processes are the primary unit of work of a run, but those without a description are assumed
+  /// to be implementation details.
+  ///
+  fn level(&self) -> Level {
+    if self.0.description.is_empty() {
+      Level::Debug
+    } else {
+      Level::Info
+    }
+  }
+}
+
 impl WrappedNode for ExecuteProcess {
   type Item = ProcessResult;
 
   fn run(self, context: Context) -> NodeFuture<ProcessResult> {
+    let level = self.level();
     let request = self.0;
+    let description = request.description.clone();
+    let start_time = Instant::now();
+    // The strategy that this Node is keyed by, which is that of the Session which requested it.
+    let process_context = process_execution::Context {
+      execution_strategy: self.2,
+      ..context.process_context.clone()
+    };
 
+    let core = context.core.clone();
     context
       .core
       .command_runner()
-      .run(request)
+      .run(request, process_context)
+      .then(move |res| {
+        let elapsed = start_time.elapsed();
+        let result = match res {
+          Ok(result) => result,
+          Err(e) => {
+            metrics::record_workunit(&description, level, elapsed);
+            return future::err(e).to_boxed();
+          }
+        };
+        let warnings = match core.hermeticity_lint {
+          Some(ref lint) => lint.lint(&core.store(), &result),
+          None => future::ok(vec![]).to_boxed(),
+        };
+        warnings
+          .then(move |warnings_res| {
+            let warnings = warnings_res.unwrap_or_else(|e| {
+              vec![format!(
+                "Failed to check the outputs for hermeticity: {}",
+                e
+              )]
+            });
+            for warning in &warnings {
+              warn!("{}: {}", description, warning);
+            }
+            metrics::increment_counter(Metric::HermeticityWarnings, warnings.len() as u64);
+            metrics::record_workunit_with_warnings(&description, level, elapsed, warnings);
+            Ok(result)
+          })
+          .to_boxed()
+      })
       .map(ProcessResult)
       .map_err(|e| throw(&format!("Failed to execute process: {}", e)))
       .to_boxed()
@@ -464,18 +933,52 @@ impl WrappedNode for DigestFile {
   type Item = hashing::Digest;
 
   fn run(self, context: Context) -> NodeFuture<hashing::Digest> {
-    let file = self.0.clone();
+    // The metadata is collected before the file is read, so that a concurrent modification can
+    // only cause a cached digest to be missed, rather than to be wrong.
+    let file = self.0;
     context
       .core
       .vfs
-      .read_file(&self.0)
-      .map_err(move |e| throw(&format!("Error reading file {:?}: {:?}", file, e,)))
-      .and_then(move |c| {
+      .file_metadata(&file)
+      .then(move |metadata_res| {
+        let metadata = metadata_res.ok();
+        let cached = metadata
+          .as_ref()
+          .and_then(|metadata| context.core.file_digests.get(&file.path, metadata));
+        if let Some(digest) = cached {
+          // Renew the lease that the content would have been given if it had been stored again.
+          let store = context.core.store();
+          return future::result(
+            store
+              .lease_all(std::iter::once(&digest))
+              .map(|()| digest)
+              .map_err(|e| throw(&e)),
+          )
+          .to_boxed();
+        }
+        let file2 = file.clone();
         context
           .core
-          .store()
-          .store_file_bytes(c.content, true)
-          .map_err(|e| throw(&e))
+          .vfs
+          .read_file(&file)
+          .map_err(move |e| throw(&format!("Error reading file {:?}: {:?}", file2, e,)))
+          .and_then(move |c| {
+            context
+              .core
+              .store()
+              .store_file_bytes(c.content, true)
+              .map_err(|e| throw(&e))
+              .map(move |digest| {
+                if let Some(metadata) = metadata {
+                  context
+                    .core
+                    .file_digests
+                    .insert(file.path, &metadata, digest);
+                }
+                digest
+              })
+          })
+          .to_boxed()
       })
       .to_boxed()
   }
@@ -524,13 +1027,29 @@ impl From<Scandir> for NodeKey {
 pub struct Snapshot(Key);
 
 impl Snapshot {
-  fn create(context: Context, path_globs: PathGlobs) -> NodeFuture<fs::Snapshot> {
+  fn create(
+    context: Context,
+    path_globs: PathGlobs,
+    size_limits: SnapshotSizeLimits,
+  ) -> NodeFuture<fs::Snapshot> {
     // Recursively expand PathGlobs into PathStats.
     // We rely on Context::expand tracking dependencies for scandirs,
     // and fs::Snapshot::from_path_stats tracking dependencies for file digests.
+    let context2 = context.clone();
     context
       .expand(path_globs)
       .map_err(|e| format!("PathGlobs expansion failed: {}", e))
+      .and_then(move |path_stats| {
+        if context2.core.vfs.is_case_insensitive() {
+          try_future!(fs::Snapshot::check_case_collisions(&path_stats)
+            .map_err(|e| format!("Snapshot failed: {}", e)));
+        }
+        size_limits
+          .check(&context2.core.vfs, &path_stats)
+          .map(|()| path_stats)
+          .map_err(|e| format!("Snapshot failed: {}", e))
+          .to_boxed()
+      })
       .and_then(move |path_stats| {
         fs::Snapshot::from_path_stats(context.core.store(), &context, path_stats)
           .map_err(move |e| format!("Snapshot failed: {}", e))
@@ -552,20 +1071,54 @@ impl Snapshot {
     let conjunction_string = externs::project_str(&conjunction_obj, "value");
     let conjunction = GlobExpansionConjunction::create(&conjunction_string)?;
 
-    PathGlobs::create(&include, &exclude, strict_glob_matching, conjunction).map_err(|e| {
-      format!(
-        "Failed to parse PathGlobs for include({:?}), exclude({:?}): {}",
-        include, exclude, e
-      )
+    let special_file_behavior_obj = externs::project_ignoring_type(item, "special_file_behavior");
+    let special_file_behavior_string = externs::project_str(&special_file_behavior_obj, "value");
+    let special_file_behavior = SpecialFileBehavior::create(&special_file_behavior_string)?;
+
+    PathGlobs::create(&include, &exclude, strict_glob_matching, conjunction)
+      .map(|path_globs| path_globs.with_special_file_behavior(special_file_behavior))
+      .map_err(|e| {
+        format!(
+          "Failed to parse PathGlobs for include({:?}), exclude({:?}): {}",
+          include, exclude, e
+        )
+      })
+  }
+
+  ///
+  /// Lifts any SnapshotSizeLimits which are explicitly set on a python PathGlobs value, which
+  /// override the configured limits for its capture.
+  ///
+  pub fn lift_size_limit_overrides(item: &Value) -> Result<SnapshotSizeLimits, String> {
+    let lift_limit = |field: &str| {
+      let limit = externs::project_str(item, field);
+      if limit.is_empty() {
+        Ok(None)
+      } else {
+        limit
+          .parse::<u64>()
+          .map(Some)
+          .map_err(|err| format!("{} was not a non-negative int: {:?}", field, err))
+      }
+    };
+    Ok(SnapshotSizeLimits {
+      max_file_bytes: lift_limit("max_file_bytes")?,
+      max_total_bytes: lift_limit("max_total_bytes")?,
     })
   }
 
   pub fn store_directory(core: &Arc<Core>, item: &hashing::Digest) -> Value {
-    externs::unsafe_call(
+    conversions::DIGEST.construct(
       &core.types.construct_directory_digest,
       &[
-        externs::store_utf8(&item.0.to_hex()),
-        externs::store_i64(item.1 as i64),
+        (
+          conversions::digest::FINGERPRINT,
+          externs::store_utf8(&item.0.to_hex()),
+        ),
+        (
+          conversions::digest::SERIALIZED_BYTES_LENGTH,
+          externs::store_i64(item.1 as i64),
+        ),
       ],
     )
   }
@@ -583,12 +1136,15 @@ impl Snapshot {
         }
       }
     }
-    externs::unsafe_call(
+    conversions::SNAPSHOT.construct(
       &core.types.construct_snapshot,
       &[
-        Self::store_directory(core, &item.digest),
-        externs::store_tuple(&files),
-        externs::store_tuple(&dirs),
+        (
+          conversions::snapshot::DIRECTORY_DIGEST,
+          Self::store_directory(core, &item.digest),
+        ),
+        (conversions::snapshot::FILES, externs::store_tuple(&files)),
+        (conversions::snapshot::DIRS, externs::store_tuple(&dirs)),
       ],
     )
   }
@@ -598,23 +1154,62 @@ impl Snapshot {
   }
 
   fn store_file_content(context: &Context, item: &FileContent) -> Value {
-    externs::unsafe_call(
+    conversions::FILE_CONTENT.construct(
       &context.core.types.construct_file_content,
       &[
-        Self::store_path(&item.path),
-        externs::store_bytes(&item.content),
+        (
+          conversions::file_content::PATH,
+          Self::store_path(&item.path),
+        ),
+        (
+          conversions::file_content::CONTENT,
+          externs::store_bytes(&item.content),
+        ),
+      ],
+    )
+  }
+
+  fn store_file_content_view(context: &Context, item: FileContent) -> Value {
+    conversions::FILE_CONTENT_VIEW.construct(
+      &context.core.types.construct_file_content_view,
+      &[
+        (
+          conversions::file_content::PATH,
+          Self::store_path(&item.path),
+        ),
+        (
+          conversions::file_content::CONTENT,
+          externs::store_buffer(item.content),
+        ),
       ],
     )
   }
 
+  fn store_files_content_view(context: &Context, item: Vec<FileContent>) -> Value {
+    let entries: Vec<_> = item
+      .into_iter()
+      .map(|e| Self::store_file_content_view(context, e))
+      .collect();
+    conversions::FILES_CONTENT_VIEW.construct(
+      &context.core.types.construct_files_content_view,
+      &[(
+        conversions::collection::DEPENDENCIES,
+        externs::store_tuple(&entries),
+      )],
+    )
+  }
+
   fn store_files_content(context: &Context, item: &[FileContent]) -> Value {
     let entries: Vec<_> = item
       .iter()
       .map(|e| Self::store_file_content(context, e))
       .collect();
-    externs::unsafe_call(
+    conversions::FILES_CONTENT.construct(
       &context.core.types.construct_files_content,
-      &[externs::store_tuple(&entries)],
+      &[(
+        conversions::collection::DEPENDENCIES,
+        externs::store_tuple(&entries),
+      )],
     )
   }
 }
@@ -623,10 +1218,16 @@ impl WrappedNode for Snapshot {
   type Item = Arc<fs::Snapshot>;
 
   fn run(self, context: Context) -> NodeFuture<Arc<fs::Snapshot>> {
-    let lifted_path_globs = Self::lift_path_globs(&externs::val_for(&self.0));
+    let value = externs::val_for(&self.0);
+    let lifted_path_globs = Self::lift_path_globs(&value).and_then(|path_globs| {
+      Self::lift_size_limit_overrides(&value).map(|overrides| (path_globs, overrides))
+    });
     future::result(lifted_path_globs)
       .map_err(|e| throw(&format!("Failed to parse PathGlobs: {}", e)))
-      .and_then(move |path_globs| Self::create(context, path_globs))
+      .and_then(move |(path_globs, overrides)| {
+        let size_limits = context.core.snapshot_size_limits.overridden_by(overrides);
+        Self::create(context, path_globs, size_limits)
+      })
       .map(Arc::new)
       .to_boxed()
   }
@@ -638,6 +1239,229 @@ impl From<Snapshot> for NodeKey {
   }
 }
 
+///
+/// A Node that merges directory Digests into a single directory Digest.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MergeDigests(Vec<hashing::Digest>);
+
+impl MergeDigests {
+  ///
+  /// Lifts a python DirectoriesToMerge value into a MergeDigests Node.
+  ///
+  fn lift(value: &Value) -> Result<MergeDigests, String> {
+    externs::project_multi(value, "directories")
+      .iter()
+      .map(lift_digest)
+      .collect::<Result<Vec<_>, _>>()
+      .map(MergeDigests)
+  }
+}
+
+impl WrappedNode for MergeDigests {
+  type Item = hashing::Digest;
+
+  fn run(self, context: Context) -> NodeFuture<hashing::Digest> {
+    fs::Snapshot::merge_directories(context.core.store(), self.0)
+      .map_err(|err| throw(&err))
+      .to_boxed()
+  }
+}
+
+impl From<MergeDigests> for NodeKey {
+  fn from(n: MergeDigests) -> Self {
+    NodeKey::MergeDigests(n)
+  }
+}
+
+///
+/// A Node that nests a directory Digest beneath a relative path prefix.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct AddPrefix {
+  digest: hashing::Digest,
+  prefix: PathBuf,
+}
+
+impl AddPrefix {
+  ///
+  /// Lifts a python DirectoryWithPrefixToAdd value into an AddPrefix Node.
+  ///
+  fn lift(value: &Value) -> Result<AddPrefix, String> {
+    Ok(AddPrefix {
+      digest: lift_digest(&externs::project_ignoring_type(value, "directory_digest"))?,
+      prefix: PathBuf::from(externs::project_str(value, "prefix")),
+    })
+  }
+}
+
+impl WrappedNode for AddPrefix {
+  type Item = hashing::Digest;
+
+  fn run(self, context: Context) -> NodeFuture<hashing::Digest> {
+    fs::Snapshot::add_prefix(context.core.store(), self.digest, self.prefix)
+      .map_err(|err| throw(&err))
+      .to_boxed()
+  }
+}
+
+impl From<AddPrefix> for NodeKey {
+  fn from(n: AddPrefix) -> Self {
+    NodeKey::AddPrefix(n)
+  }
+}
+
+///
+/// A Node that strips a relative path prefix (which must contain everything in the directory)
+/// from a directory Digest.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RemovePrefix {
+  digest: hashing::Digest,
+  prefix: PathBuf,
+}
+
+impl RemovePrefix {
+  ///
+  /// Lifts a python DirectoryWithPrefixToStrip value into a RemovePrefix Node.
+  ///
+  fn lift(value: &Value) -> Result<RemovePrefix, String> {
+    Ok(RemovePrefix {
+      digest: lift_digest(&externs::project_ignoring_type(value, "directory_digest"))?,
+      prefix: PathBuf::from(externs::project_str(value, "prefix")),
+    })
+  }
+}
+
+impl WrappedNode for RemovePrefix {
+  type Item = hashing::Digest;
+
+  fn run(self, context: Context) -> NodeFuture<hashing::Digest> {
+    fs::Snapshot::strip_prefix(context.core.store(), self.digest, self.prefix)
+      .map_err(|err| throw(&err))
+      .to_boxed()
+  }
+}
+
+impl From<RemovePrefix> for NodeKey {
+  fn from(n: RemovePrefix) -> Self {
+    NodeKey::RemovePrefix(n)
+  }
+}
+
+///
+/// A Node that captures an fs::Snapshot of the files in a directory Digest which match some
+/// PathGlobs, for a SnapshotSubset subject.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SnapshotSubset(Key);
+
+impl WrappedNode for SnapshotSubset {
+  type Item = Arc<fs::Snapshot>;
+
+  fn run(self, context: Context) -> NodeFuture<Arc<fs::Snapshot>> {
+    let request = externs::val_for(&self.0);
+    let digest = try_future!(lift_digest(&externs::project_ignoring_type(
+      &request,
+      "directory_digest"
+    ))
+    .map_err(|str| throw(&str)));
+    let path_globs = try_future!(Snapshot::lift_path_globs(&externs::project_ignoring_type(
+      &request, "globs"
+    ))
+    .map_err(|e| throw(&format!("Failed to parse PathGlobs: {}", e))));
+    fs::Snapshot::get_snapshot_subset(context.core.store(), digest, path_globs)
+      .map_err(|err| throw(&err))
+      .map(Arc::new)
+      .to_boxed()
+  }
+}
+
+impl From<SnapshotSubset> for NodeKey {
+  fn from(n: SnapshotSubset) -> Self {
+    NodeKey::SnapshotSubset(n)
+  }
+}
+
+///
+/// A Node that extracts the archive file contained in a directory Digest into a directory
+/// Digest of its contents.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ExtractDigest(hashing::Digest);
+
+impl ExtractDigest {
+  ///
+  /// Lifts a python ArchiveToExtract value into an ExtractDigest Node.
+  ///
+  fn lift(value: &Value) -> Result<ExtractDigest, String> {
+    lift_digest(&externs::project_ignoring_type(value, "directory_digest")).map(ExtractDigest)
+  }
+}
+
+impl WrappedNode for ExtractDigest {
+  type Item = hashing::Digest;
+
+  fn run(self, context: Context) -> NodeFuture<hashing::Digest> {
+    fs::Snapshot::extract_archive(context.core.store(), self.0)
+      .map_err(|err| throw(&err))
+      .to_boxed()
+  }
+}
+
+impl From<ExtractDigest> for NodeKey {
+  fn from(n: ExtractDigest) -> Self {
+    NodeKey::ExtractDigest(n)
+  }
+}
+
+///
+/// A Node that deterministically packs a directory Digest into an archive file, returning a
+/// directory Digest which contains only that file.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CreateArchive {
+  digest: hashing::Digest,
+  archive_name: String,
+}
+
+impl CreateArchive {
+  ///
+  /// Lifts a python DirectoryToArchive value into a CreateArchive Node.
+  ///
+  fn lift(value: &Value) -> Result<CreateArchive, String> {
+    Ok(CreateArchive {
+      digest: lift_digest(&externs::project_ignoring_type(value, "directory_digest"))?,
+      archive_name: externs::project_str(value, "archive_name"),
+    })
+  }
+}
+
+impl WrappedNode for CreateArchive {
+  type Item = hashing::Digest;
+
+  fn run(self, context: Context) -> NodeFuture<hashing::Digest> {
+    fs::Snapshot::create_archive(context.core.store(), self.digest, self.archive_name)
+      .map_err(|err| throw(&err))
+      .to_boxed()
+  }
+}
+
+impl From<CreateArchive> for NodeKey {
+  fn from(n: CreateArchive) -> Self {
+    NodeKey::CreateArchive(n)
+  }
+}
+
+const DOWNLOAD_ATTEMPTS: u32 = 4;
+const DOWNLOAD_INITIAL_BACKOFF_MILLIS: u64 = 250;
+
+enum DownloadError {
+  // A failure which might succeed if the download is attempted again.
+  Retryable(String),
+  Fatal(String),
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct DownloadedFile(Key);
 
@@ -697,34 +1521,80 @@ impl DownloadedFile {
     )
   }
 
+  ///
+  /// Downloads the file at the given URL into the Store, retrying server and connection errors
+  /// with exponential backoff. Client errors and digest mismatches are not retried, since
+  /// repeating the request would not change the outcome.
+  ///
   fn download(
     core: Arc<Core>,
     url: Url,
     file_name: String,
     expected_digest: hashing::Digest,
   ) -> BoxFuture<(), String> {
-    // TODO: Retry failures
+    if core.offline {
+      return future::err(fs::offline_error(&format!("download {}", url))).to_boxed();
+    }
+    future::loop_fn(0, move |attempt| {
+      let core = core.clone();
+      let url = url.clone();
+      let file_name = file_name.clone();
+      DownloadedFile::attempt_download(core.clone(), url.clone(), file_name, expected_digest)
+        .map(future::Loop::Break)
+        .or_else(move |err| match err {
+          DownloadError::Retryable(ref err) if attempt + 1 < DOWNLOAD_ATTEMPTS => {
+            let backoff = DOWNLOAD_INITIAL_BACKOFF_MILLIS * 2_u64.pow(attempt);
+            warn!(
+              "Attempt {} of {} to download {} failed; retrying in {}ms: {}",
+              attempt + 1,
+              DOWNLOAD_ATTEMPTS,
+              url,
+              backoff,
+              err
+            );
+            Delay::new_handle(
+              Instant::now() + Duration::from_millis(backoff),
+              core
+                .futures_timer_thread
+                .with(futures_timer::HelperThread::handle),
+            )
+            .map_err(|e| format!("Future-Delay errored while retrying download: {}", e))
+            .map(move |()| future::Loop::Continue(attempt + 1))
+            .to_boxed()
+          }
+          DownloadError::Retryable(err) | DownloadError::Fatal(err) => future::err(err).to_boxed(),
+        })
+    })
+    .to_boxed()
+  }
+
+  fn attempt_download(
+    core: Arc<Core>,
+    url: Url,
+    file_name: String,
+    expected_digest: hashing::Digest,
+  ) -> BoxFuture<(), DownloadError> {
     core
       .http_client()
       .get(url.clone())
       .send()
-      .map_err(|err| format!("Error downloading file: {}", err))
+      .map_err(|err| DownloadError::Retryable(format!("Error downloading file: {}", err)))
       .and_then(move |response| {
         // Handle common HTTP errors.
         if response.status().is_server_error() {
-          Err(format!(
+          Err(DownloadError::Retryable(format!(
             "Server error ({}) downloading file {} from {}",
             response.status().as_str(),
             file_name,
             url,
-          ))
+          )))
         } else if response.status().is_client_error() {
-          Err(format!(
+          Err(DownloadError::Fatal(format!(
             "Client error ({}) downloading file {} from {}",
             response.status().as_str(),
             file_name,
             url,
-          ))
+          )))
         } else {
           Ok(response)
         }
@@ -764,12 +1634,13 @@ impl DownloadedFile {
 
         response
           .into_body()
-          .map_err(|err| format!("Error reading URL fetch response: {}", err))
+          .map_err(|err| {
+            DownloadError::Retryable(format!("Error reading URL fetch response: {}", err))
+          })
           .fold(hasher, |mut hasher, chunk| {
-            hasher
-              .write_all(&chunk)
-              .map(|_| hasher)
-              .map_err(|err| format!("Error hashing/writing URL fetch response: {}", err))
+            hasher.write_all(&chunk).map(|_| hasher).map_err(|err| {
+              DownloadError::Fatal(format!("Error hashing/writing URL fetch response: {}", err))
+            })
           })
           .map(|hasher| {
             let (digest, bytewriter) = hasher.finish();
@@ -778,10 +1649,10 @@ impl DownloadedFile {
       })
       .and_then(move |(actual_digest, buf)| {
         if expected_digest != actual_digest {
-          return future::err(format!(
+          return future::err(DownloadError::Fatal(format!(
             "Wrong digest for downloaded file: want {:?} got {:?}",
             expected_digest, actual_digest
-          ))
+          )))
           .to_boxed();
         }
 
@@ -789,6 +1660,7 @@ impl DownloadedFile {
           .store()
           .store_file_bytes(buf, true)
           .map(|_| ())
+          .map_err(DownloadError::Fatal)
           .to_boxed()
       })
       .to_boxed()
@@ -830,46 +1702,66 @@ pub struct Task {
   product: TypeId,
   task: tasks::Task,
   entry: Arc<rule_graph::Entry>,
+  // Set for uncacheable Tasks, which should run once per Session.
+  run_id: Option<RunId>,
 }
 
 impl Task {
+  ///
+  /// Uncacheable Tasks (such as @console_rules, which interact with the user) are keyed by the
+  /// RunId of the Session that requested them, so that they run again in each Session without
+  /// affecting the memoization of any other Node.
+  ///
+  fn run_id_for(task: &tasks::Task, run_id: RunId) -> Option<RunId> {
+    if task.cacheable {
+      None
+    } else {
+      Some(run_id)
+    }
+  }
+
+  ///
+  /// Runs a batch of Gets, which are resolved against the edges of the Task once per distinct
+  /// product and subject type (rather than once per Get), so that a large GetMulti or GetAll
+  /// creates its Nodes cheaply.
+  ///
   fn gen_get(
     context: &Context,
     params: &Params,
     entry: &Arc<rule_graph::Entry>,
     gets: Vec<externs::Get>,
   ) -> NodeFuture<Vec<Value>> {
-    let get_futures = gets
-      .into_iter()
-      .map(|get| {
-        let context = context.clone();
-        let params = params.clone();
-        let entry = entry.clone();
-        let select_key = rule_graph::SelectKey::JustGet(selectors::Get {
-          product: get.product,
-          subject: *get.subject.type_id(),
-        });
-        let entry = context
-          .core
-          .rule_graph
-          .edges_for_inner(&entry)
-          .ok_or_else(|| throw(&format!("no edges for task {:?} exist!", entry)))
-          .and_then(|edges| {
-            edges.entry_for(&select_key).cloned().ok_or_else(|| {
-              throw(&format!(
-                "{:?} did not declare a dependency on {:?}",
-                entry, select_key
-              ))
-            })
-          });
-        // The subject of the get is a new parameter that replaces an existing param of the same
-        // type.
-        let mut params = params.clone();
-        params.put(get.subject);
-        future::result(entry)
-          .and_then(move |entry| Select::new(params, get.product, entry).run(context.clone()))
-      })
-      .collect::<Vec<_>>();
+    let edges = try_future!(context
+      .core
+      .rule_graph
+      .edges_for_inner(&entry)
+      .ok_or_else(|| throw(&format!("no edges for task {:?} exist!", entry))));
+    let mut entries: HashMap<selectors::Get, rule_graph::Entry> = HashMap::new();
+    let mut get_futures = Vec::with_capacity(gets.len());
+    for get in gets {
+      let declared_get = selectors::Get {
+        product: get.product,
+        subject: *get.subject.type_id(),
+      };
+      let get_entry = if let Some(get_entry) = entries.get(&declared_get) {
+        get_entry.clone()
+      } else {
+        let select_key = rule_graph::SelectKey::JustGet(declared_get);
+        let get_entry = try_future!(edges.entry_for(&select_key).cloned().ok_or_else(|| {
+          throw(&format!(
+            "{:?} did not declare a dependency on {:?}",
+            entry, select_key
+          ))
+        }));
+        entries.insert(declared_get, get_entry.clone());
+        get_entry
+      };
+      // The subject of the get is a new parameter that replaces an existing param of the same
+      // type.
+      let mut params = params.clone();
+      params.put(get.subject);
+      get_futures.push(Select::new(params, get.product, get_entry).run(context.clone()));
+    }
     future::join_all(get_futures).to_boxed()
   }
 
@@ -877,30 +1769,50 @@ impl Task {
   /// Given a python generator Value, loop to request the generator's dependencies until
   /// it completes with a result Value.
   ///
+  /// The result of each Get is sent into the generator, and if a Get fails with an exception, that
+  /// exception is thrown into the generator so that it can either handle it, or fail with a
+  /// traceback which includes its own frame. Invalidation is never visible to the generator.
+  ///
   fn generate(
     context: Context,
     params: Params,
     entry: Arc<rule_graph::Entry>,
     generator: Value,
   ) -> NodeFuture<Value> {
-    future::loop_fn(Value::from(externs::none()), move |input| {
+    future::loop_fn(Ok(Value::from(externs::none())), move |input| {
       let context = context.clone();
       let params = params.clone();
       let entry = entry.clone();
-      future::result(externs::generator_send(&generator, &input)).and_then(move |response| {
-        match response {
-          externs::GeneratorResponse::Get(get) => {
-            Self::gen_get(&context, &params, &entry, vec![get])
-              .map(|vs| future::Loop::Continue(vs.into_iter().next().unwrap()))
-              .to_boxed()
-          }
-          externs::GeneratorResponse::GetMulti(gets) => {
-            Self::gen_get(&context, &params, &entry, gets)
-              .map(|vs| future::Loop::Continue(externs::store_tuple(&vs)))
-              .to_boxed()
-          }
-          externs::GeneratorResponse::Break(val) => future::ok(future::Loop::Break(val)).to_boxed(),
+      let response = match input {
+        Ok(value) => externs::generator_send(&generator, &value),
+        Err(Failure::Throw(exc, _, engine_traceback)) => {
+          // If the generator does not handle the exception, it will be re-raised: preserve the
+          // engine traceback of the Get that originally raised it.
+          externs::generator_throw(&generator, &exc).map_err(|failure| match failure {
+            Failure::Throw(exc, traceback, _) => Failure::Throw(exc, traceback, engine_traceback),
+            f @ Failure::Invalidated => f,
+          })
         }
+        Err(failure @ Failure::Invalidated) => Err(failure),
+      };
+      future::result(response).and_then(move |response| match response {
+        externs::GeneratorResponse::Get(get) => Self::gen_get(&context, &params, &entry, vec![get])
+          .then(|res| {
+            Ok(future::Loop::Continue(
+              res.map(|vs| vs.into_iter().next().unwrap()),
+            ))
+          })
+          .to_boxed(),
+        externs::GeneratorResponse::GetMulti(gets) => {
+          Self::gen_get(&context, &params, &entry, gets)
+            .then(|res| {
+              Ok(future::Loop::Continue(
+                res.map(|vs| externs::store_tuple(&vs)),
+              ))
+            })
+            .to_boxed()
+        }
+        externs::GeneratorResponse::Break(val) => future::ok(future::Loop::Break(val)).to_boxed(),
       })
     })
     .to_boxed()
@@ -941,6 +1853,7 @@ impl WrappedNode for Task {
     let func = self.task.func;
     let entry = self.entry;
     let product = self.product;
+    let frame_params = params.clone();
     deps
       .then(move |deps_result| match deps_result {
         Ok(deps) => externs::call(&externs::val_for(&func.0), &deps),
@@ -957,6 +1870,7 @@ impl WrappedNode for Task {
         },
         Err(failure) => err(failure),
       })
+      .map_err(move |failure| failure.with_engine_frame(format!("{} for {}", func, frame_params)))
       .to_boxed()
   }
 }
@@ -970,6 +1884,17 @@ impl From<Task> for NodeKey {
 #[derive(Default)]
 pub struct Visualizer {
   viz_colors: HashMap<String, String>,
+  // If non-empty, only Nodes with these type names are rendered.
+  node_types: HashSet<String>,
+}
+
+impl Visualizer {
+  pub fn new(node_types: HashSet<String>) -> Visualizer {
+    Visualizer {
+      viz_colors: HashMap::new(),
+      node_types,
+    }
+  }
 }
 
 impl NodeVisualizer<NodeKey> for Visualizer {
@@ -993,6 +1918,10 @@ impl NodeVisualizer<NodeKey> for Visualizer {
       }
     }
   }
+
+  fn include(&self, entry: &Entry<NodeKey>) -> bool {
+    self.node_types.is_empty() || self.node_types.contains(entry.node().type_name())
+  }
 }
 
 pub struct Tracer;
@@ -1016,7 +1945,7 @@ impl NodeTracer<NodeKey> for Tracer {
     match result {
       None => "<None>".to_string(),
       Some(Ok(ref x)) => format!("{:?}", x),
-      Some(Err(Failure::Throw(ref x, ref traceback))) => format!(
+      Some(Err(Failure::Throw(ref x, ref traceback, _))) => format!(
         "Throw({})\n{}",
         externs::val_to_str(x),
         traceback
@@ -1035,17 +1964,73 @@ impl NodeTracer<NodeKey> for Tracer {
 ///
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum NodeKey {
+  AddPrefix(AddPrefix),
   DigestFile(DigestFile),
   DownloadedFile(DownloadedFile),
   ExecuteProcess(Box<ExecuteProcess>),
+  MergeDigests(MergeDigests),
   ReadLink(ReadLink),
+  RemovePrefix(RemovePrefix),
   Scandir(Scandir),
   Select(Box<Select>),
   Snapshot(Snapshot),
+  SnapshotSubset(SnapshotSubset),
+  ExtractDigest(ExtractDigest),
+  CreateArchive(CreateArchive),
   Task(Box<Task>),
 }
 
 impl NodeKey {
+  ///
+  /// The name of the type of this Node, which may be used to filter visualizations.
+  ///
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      &NodeKey::AddPrefix(..) => "AddPrefix",
+      &NodeKey::DigestFile(..) => "DigestFile",
+      &NodeKey::DownloadedFile(..) => "DownloadedFile",
+      &NodeKey::ExecuteProcess(..) => "ExecuteProcess",
+      &NodeKey::MergeDigests(..) => "MergeDigests",
+      &NodeKey::ReadLink(..) => "ReadLink",
+      &NodeKey::RemovePrefix(..) => "RemovePrefix",
+      &NodeKey::Scandir(..) => "Scandir",
+      &NodeKey::Select(..) => "Select",
+      &NodeKey::Snapshot(..) => "Snapshot",
+      &NodeKey::SnapshotSubset(..) => "SnapshotSubset",
+      &NodeKey::ExtractDigest(..) => "ExtractDigest",
+      &NodeKey::CreateArchive(..) => "CreateArchive",
+      &NodeKey::Task(..) => "Task",
+    }
+  }
+
+  ///
+  /// The interned Keys which this Node refers to, and which must therefore not be collected while
+  /// it is in the Graph.
+  ///
+  pub fn keys(&self) -> Vec<Key> {
+    match self {
+      &NodeKey::Select(ref s) => s.params.keys().cloned().collect(),
+      &NodeKey::Task(ref s) => s
+        .params
+        .keys()
+        .cloned()
+        .chain(std::iter::once(s.task.func.0))
+        .collect(),
+      &NodeKey::Snapshot(ref s) => vec![s.0],
+      &NodeKey::SnapshotSubset(ref s) => vec![s.0],
+      &NodeKey::DownloadedFile(ref s) => vec![s.0],
+      &NodeKey::AddPrefix(..)
+      | &NodeKey::DigestFile(..)
+      | &NodeKey::ExecuteProcess(..)
+      | &NodeKey::MergeDigests(..)
+      | &NodeKey::ReadLink(..)
+      | &NodeKey::RemovePrefix(..)
+      | &NodeKey::Scandir(..)
+      | &NodeKey::ExtractDigest(..)
+      | &NodeKey::CreateArchive(..) => vec![],
+    }
+  }
+
   fn product_str(&self) -> String {
     match self {
       &NodeKey::ExecuteProcess(..) => "ProcessResult".to_string(),
@@ -1056,6 +2041,12 @@ impl NodeKey {
       &NodeKey::DigestFile(..) => "DigestFile".to_string(),
       &NodeKey::ReadLink(..) => "LinkDest".to_string(),
       &NodeKey::Scandir(..) => "DirectoryListing".to_string(),
+      &NodeKey::MergeDigests(..)
+      | &NodeKey::AddPrefix(..)
+      | &NodeKey::RemovePrefix(..)
+      | &NodeKey::ExtractDigest(..)
+      | &NodeKey::CreateArchive(..) => "Digest".to_string(),
+      &NodeKey::SnapshotSubset(..) => "Snapshot".to_string(),
     }
   }
 
@@ -1073,7 +2064,13 @@ impl NodeKey {
       | &NodeKey::Select { .. }
       | &NodeKey::Snapshot { .. }
       | &NodeKey::Task { .. }
-      | &NodeKey::DownloadedFile { .. } => None,
+      | &NodeKey::DownloadedFile { .. }
+      | &NodeKey::MergeDigests { .. }
+      | &NodeKey::AddPrefix { .. }
+      | &NodeKey::RemovePrefix { .. }
+      | &NodeKey::SnapshotSubset { .. }
+      | &NodeKey::ExtractDigest { .. }
+      | &NodeKey::CreateArchive { .. } => None,
     }
   }
 }
@@ -1085,15 +2082,37 @@ impl Node for NodeKey {
   type Error = Failure;
 
   fn run(self, context: Context) -> NodeFuture<NodeResult> {
-    match self {
+    let history_key = self.history_key();
+    let history_context = context.clone();
+    let start_time = Instant::now();
+    // A panic in a Node fails the Node (and its dependees), rather than the thread running it.
+    let result = panics::catch_unwind(|| match self {
+      NodeKey::AddPrefix(n) => n.run(context).map(NodeResult::from).to_boxed(),
       NodeKey::DigestFile(n) => n.run(context).map(NodeResult::from).to_boxed(),
       NodeKey::DownloadedFile(n) => n.run(context).map(NodeResult::from).to_boxed(),
       NodeKey::ExecuteProcess(n) => n.run(context).map(NodeResult::from).to_boxed(),
+      NodeKey::MergeDigests(n) => n.run(context).map(NodeResult::from).to_boxed(),
       NodeKey::ReadLink(n) => n.run(context).map(NodeResult::from).to_boxed(),
+      NodeKey::RemovePrefix(n) => n.run(context).map(NodeResult::from).to_boxed(),
       NodeKey::Scandir(n) => n.run(context).map(NodeResult::from).to_boxed(),
       NodeKey::Select(n) => n.run(context).map(NodeResult::from).to_boxed(),
       NodeKey::Snapshot(n) => n.run(context).map(NodeResult::from).to_boxed(),
+      NodeKey::SnapshotSubset(n) => n.run(context).map(NodeResult::from).to_boxed(),
+      NodeKey::ExtractDigest(n) => n.run(context).map(NodeResult::from).to_boxed(),
+      NodeKey::CreateArchive(n) => n.run(context).map(NodeResult::from).to_boxed(),
       NodeKey::Task(n) => n.run(context).map(NodeResult::from).to_boxed(),
+    })
+    .map(panics::catch_unwind_future)
+    .unwrap_or_else(|msg| err(throw(&msg)));
+    match history_key {
+      Some(key) => result
+        .inspect(move |_| {
+          history_context
+            .core
+            .record_duration(&key, start_time.elapsed())
+        })
+        .to_boxed(),
+      None => result,
     }
   }
 
@@ -1108,12 +2127,64 @@ impl Node for NodeKey {
     }
   }
 
-  fn cacheable(&self) -> bool {
+  fn level(&self) -> Level {
     match self {
-      &NodeKey::Task(ref s) => s.task.cacheable,
-      // TODO Select nodes are made uncacheable as a workaround to #6146. Will be worked on in #6598
-      &NodeKey::Select(_) => false,
-      _ => true,
+      // Filesystem operations are run once per file, and would flood any display of them.
+      &NodeKey::DigestFile(..) | &NodeKey::ReadLink(..) | &NodeKey::Scandir(..) => Level::Trace,
+      &NodeKey::ExecuteProcess(ref s) => s.level(),
+      &NodeKey::DownloadedFile(..) => Level::Info,
+      // Uncacheable tasks (such as @console_rules) run once per Session, and represent the goals
+      // which were requested.
+      &NodeKey::Task(ref s) if !s.task.cacheable => Level::Info,
+      &NodeKey::Task(..)
+      | &NodeKey::Select(..)
+      | &NodeKey::Snapshot(..)
+      | &NodeKey::MergeDigests(..)
+      | &NodeKey::AddPrefix(..)
+      | &NodeKey::RemovePrefix(..)
+      | &NodeKey::SnapshotSubset(..)
+      | &NodeKey::ExtractDigest(..)
+      | &NodeKey::CreateArchive(..) => Level::Debug,
+    }
+  }
+
+  fn transient_retries(&self, error: &Failure) -> usize {
+    match (self, error) {
+      // Processes and downloads fail (rather than completing with a failed result) only due to
+      // infrastructure errors, such as a lost connection to a remote service, which are worth
+      // re-attempting before failing their dependees (unless the process is side-effecting).
+      (&NodeKey::ExecuteProcess(ref s), &Failure::Throw(..)) => s.0.transient_retries(),
+      (&NodeKey::DownloadedFile(_), &Failure::Throw(..)) => process_execution::TRANSIENT_RETRIES,
+      _ => 0,
+    }
+  }
+}
+
+impl NodeKey {
+  ///
+  /// The key under which the historical durations of this Node are recorded, if it represents a
+  /// unit of work which is likely to recur (and to take a similar amount of time) between runs.
+  ///
+  pub fn history_key(&self) -> Option<String> {
+    match self {
+      &NodeKey::ExecuteProcess(ref s) if !s.0.description.is_empty() => {
+        Some(format!("process:{}", s.0.description))
+      }
+      &NodeKey::Task(ref s) if !s.task.cacheable => Some(format!("task:{}", s.task.func)),
+      _ => None,
+    }
+  }
+
+  ///
+  /// The RunId of the Session which this Node is keyed by, if it is memoized only within a single
+  /// Session. Such Nodes can never be requested again once that Session has been dropped.
+  ///
+  pub fn run_id(&self) -> Option<RunId> {
+    match self {
+      &NodeKey::ExecuteProcess(ref s) => s.1,
+      &NodeKey::Select(ref s) => s.run_id,
+      &NodeKey::Task(ref s) => s.run_id,
+      _ => None,
     }
   }
 }
@@ -1121,14 +2192,22 @@ impl Node for NodeKey {
 impl Display for NodeKey {
   fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
     match self {
+      &NodeKey::AddPrefix(ref s) => write!(f, "AddPrefix({:?}, {:?})", s.digest, s.prefix),
       &NodeKey::DigestFile(ref s) => write!(f, "DigestFile({:?})", s.0),
       &NodeKey::DownloadedFile(ref s) => write!(f, "DownloadedFile({:?})", s.0),
       &NodeKey::ExecuteProcess(ref s) => write!(f, "ExecuteProcess({:?}", s.0),
+      &NodeKey::MergeDigests(ref s) => write!(f, "MergeDigests({:?})", s.0),
       &NodeKey::ReadLink(ref s) => write!(f, "ReadLink({:?})", s.0),
+      &NodeKey::RemovePrefix(ref s) => write!(f, "RemovePrefix({:?}, {:?})", s.digest, s.prefix),
       &NodeKey::Scandir(ref s) => write!(f, "Scandir({:?})", s.0),
       &NodeKey::Select(ref s) => write!(f, "Select({}, {})", s.params, s.product,),
       &NodeKey::Task(ref s) => write!(f, "{:?}", s),
       &NodeKey::Snapshot(ref s) => write!(f, "Snapshot({})", format!("{}", &s.0)),
+      &NodeKey::SnapshotSubset(ref s) => write!(f, "SnapshotSubset({})", &s.0),
+      &NodeKey::ExtractDigest(ref s) => write!(f, "ExtractDigest({:?})", s.0),
+      &NodeKey::CreateArchive(ref s) => {
+        write!(f, "CreateArchive({:?}, {:?})", s.digest, s.archive_name)
+      }
     }
   }
 }
@@ -1254,3 +2333,142 @@ impl TryFrom<NodeResult> for Arc<DirectoryListing> {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::fmt;
+  use std::sync::{Arc, Mutex};
+  use std::thread;
+
+  use boxfuture::{BoxFuture, Boxable};
+  use bytes::Bytes;
+  use futures::{future, Future};
+  use graph::{EntryId, Graph, Node, NodeContext};
+  use hashing::Digest;
+  use log::Level;
+  use process_execution::{ExecutionStats, FallibleExecuteProcessResult};
+
+  use super::ProcessResult;
+  use crate::core::Failure;
+
+  ///
+  /// A process, and a dependee which returns its result.
+  ///
+  #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+  enum TNode {
+    Process,
+    Dependee,
+  }
+
+  impl Node for TNode {
+    type Context = TContext;
+    type Item = ProcessResult;
+    type Error = Failure;
+
+    fn run(self, context: TContext) -> BoxFuture<ProcessResult, Failure> {
+      context.runs.lock().unwrap().push(self.clone());
+      match self {
+        TNode::Process => future::ok(ProcessResult(FallibleExecuteProcessResult {
+          stdout: Bytes::from("stdout"),
+          stderr: Bytes::from("stderr"),
+          exit_code: 0,
+          output_directory: fs::EMPTY_DIGEST,
+          execution_attempts: vec![ExecutionStats::default(); context.attempts],
+          runner: "local".to_owned(),
+          timed_out: None,
+        }))
+        .to_boxed(),
+        TNode::Dependee => context
+          .graph
+          .get(context.entry_id.unwrap(), &context, TNode::Process)
+          .to_boxed(),
+      }
+    }
+
+    fn digest(_result: ProcessResult) -> Option<Digest> {
+      None
+    }
+
+    fn transient_retries(&self, _error: &Failure) -> usize {
+      0
+    }
+
+    fn level(&self) -> Level {
+      Level::Info
+    }
+  }
+
+  impl fmt::Display for TNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      write!(f, "{:?}", self)
+    }
+  }
+
+  ///
+  /// A context in which the process takes the given number of attempts to execute.
+  ///
+  #[derive(Clone)]
+  struct TContext {
+    attempts: usize,
+    graph: Arc<Graph<TNode>>,
+    runs: Arc<Mutex<Vec<TNode>>>,
+    entry_id: Option<EntryId>,
+  }
+
+  impl TContext {
+    fn new(attempts: usize, graph: Arc<Graph<TNode>>) -> TContext {
+      TContext {
+        attempts,
+        graph,
+        runs: Arc::default(),
+        entry_id: None,
+      }
+    }
+
+    fn runs(&self) -> Vec<TNode> {
+      self.runs.lock().unwrap().clone()
+    }
+  }
+
+  impl NodeContext for TContext {
+    type Node = TNode;
+
+    fn clone_for(&self, entry_id: EntryId) -> TContext {
+      TContext {
+        entry_id: Some(entry_id),
+        ..self.clone()
+      }
+    }
+
+    fn graph(&self) -> &Graph<TNode> {
+      &self.graph
+    }
+
+    fn spawn<F>(&self, future: F)
+    where
+      F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+      thread::spawn(move || {
+        future.wait().unwrap();
+      });
+    }
+  }
+
+  #[test]
+  fn rerun_process_with_equal_outputs_cleans_dependees() {
+    let graph = Arc::new(Graph::new());
+    let context = TContext::new(1, graph.clone());
+    graph.create(TNode::Dependee, &context).wait().unwrap();
+    assert_eq!(context.runs(), vec![TNode::Dependee, TNode::Process]);
+
+    // Clear the process, which dirties its dependee.
+    graph.invalidate_from_roots(|node| *node == TNode::Process);
+
+    // The process re-runs, and takes more attempts to produce the same outputs: its dependee is
+    // cleaned rather than re-run, and so still has the result of the first run.
+    let context = TContext::new(2, graph.clone());
+    let result = graph.create(TNode::Dependee, &context).wait().unwrap();
+    assert_eq!(context.runs(), vec![TNode::Process]);
+    assert_eq!(result.0.execution_attempts.len(), 1);
+  }
+}
@@ -0,0 +1,144 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe, PanicInfo};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use backtrace::Backtrace;
+use boxfuture::{BoxFuture, Boxable};
+use futures::Future;
+use log::error;
+
+use crate::core::{throw, Failure};
+
+thread_local! {
+  // The description (including the backtrace) of the most recent panic on this thread, which is
+  // taken when the panic is caught.
+  static LAST_PANIC: RefCell<Option<String>> = RefCell::new(None);
+}
+
+// Distinguishes crash reports written by this process within the same second.
+static CRASH_REPORT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+const FILE_A_BUG: &str = "Please file a bug at https://github.com/pantsbuild/pants/issues.";
+
+///
+/// Installs a panic hook which logs each panic, and records a description of it (including the
+/// Rust backtrace) on the panicking thread, so that catching the panic via `catch_unwind` or
+/// `catch_unwind_future` produces a structured error rather than aborting the process.
+///
+/// If a crash report directory is given, each description is also written to a file below it.
+///
+pub fn set_hook(crash_report_dir: Option<PathBuf>) {
+  panic::set_hook(Box::new(move |panic_info| {
+    let summary = summarize(panic_info);
+    error!("{}", summary);
+    error!("{}", FILE_A_BUG);
+
+    let description = format!("{}\n\nRust backtrace:\n{:?}", summary, Backtrace::new());
+    if let Some(ref dir) = crash_report_dir {
+      match write_crash_report(dir, &description) {
+        Ok(path) => error!("Wrote a crash report to {}", path.display()),
+        Err(e) => error!("{}", e),
+      }
+    }
+    LAST_PANIC.with(|last_panic| *last_panic.borrow_mut() = Some(description));
+  }));
+}
+
+fn summarize(panic_info: &PanicInfo) -> String {
+  let mut summary = format!("panic at '{}'", payload_message(panic_info.payload()));
+  if let Some(location) = panic_info.location() {
+    summary.push_str(&format!(", {}:{}", location.file(), location.line()));
+  }
+  summary
+}
+
+///
+/// Panic payloads are usually either a `&str` (for a literal message) or a `String` (for a
+/// formatted message).
+///
+fn payload_message(payload: &(dyn Any + Send)) -> &str {
+  if let Some(msg) = payload.downcast_ref::<&str>() {
+    msg
+  } else if let Some(msg) = payload.downcast_ref::<String>() {
+    msg
+  } else {
+    "<non-string payload>"
+  }
+}
+
+fn write_crash_report(dir: &Path, description: &str) -> Result<PathBuf, String> {
+  let timestamp = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0);
+  let path = dir.join(format!(
+    "rust-panic-{}-{}-{}.txt",
+    process::id(),
+    timestamp,
+    CRASH_REPORT_COUNT.fetch_add(1, Ordering::SeqCst)
+  ));
+  std::fs::create_dir_all(dir)
+    .and_then(|()| std::fs::write(&path, description))
+    .map_err(|e| format!("Failed to write crash report to {}: {}", path.display(), e))?;
+  Ok(path)
+}
+
+///
+/// Describes a caught panic: using the description recorded by the hook if it is installed, and
+/// otherwise only the panic payload.
+///
+fn describe(payload: Box<dyn Any + Send>) -> String {
+  let description = LAST_PANIC
+    .with(|last_panic| last_panic.borrow_mut().take())
+    .unwrap_or_else(|| format!("panic at '{}'", payload_message(&*payload)));
+  format!("{}\n\n{}", description, FILE_A_BUG)
+}
+
+///
+/// Runs the given function, converting a panic into an Err describing it.
+///
+/// NB: Callers must not observe state which the function may have left inconsistent by panicking:
+/// in practice, the engine's shared state is guarded by locks, which are poisoned (or, for
+/// parking_lot locks, released) by a panic.
+///
+pub fn catch_unwind<F: FnOnce() -> T, T>(f: F) -> Result<T, String> {
+  panic::catch_unwind(AssertUnwindSafe(f)).map_err(describe)
+}
+
+///
+/// Converts a panic while polling the given Future into a Failure describing it.
+///
+pub fn catch_unwind_future<T: Send + 'static>(
+  future: BoxFuture<T, Failure>,
+) -> BoxFuture<T, Failure> {
+  AssertUnwindSafe(future)
+    .catch_unwind()
+    .then(|res| match res {
+      Ok(res) => res,
+      Err(payload) => Err(throw(&describe(payload))),
+    })
+    .to_boxed()
+}
+
+///
+/// Logs (rather than propagating to the thread which polls it) a panic while polling the given
+/// background task.
+///
+pub fn log_unwind_task<F: Future<Item = (), Error = ()> + Send + 'static>(
+  task: F,
+) -> BoxFuture<(), ()> {
+  AssertUnwindSafe(task)
+    .catch_unwind()
+    .then(|res| match res {
+      Ok(res) => res,
+      Err(payload) => {
+        error!("A background task failed: {}", describe(payload));
+        Err(())
+      }
+    })
+    .to_boxed()
+}
@@ -0,0 +1,85 @@
+use std::path::Path;
+
+#[cfg(feature = "profiling")]
+use pprof::protos::Message;
+#[cfg(feature = "profiling")]
+use pprof::ProfilerGuard;
+#[cfg(feature = "profiling")]
+use std::ffi::OsStr;
+#[cfg(feature = "profiling")]
+use std::fs::File;
+#[cfg(feature = "profiling")]
+use std::io::Write;
+
+///
+/// A sampling profiler of the stacks of the threads of this process, which runs until it is
+/// dropped.
+///
+/// NB: Samples are taken via a process-wide signal, so only one Profiler may run at a time, and
+/// (although the stacks of Python threads are only visible as the interpreter's own frames) the
+/// samples are not limited to the engine's threads.
+///
+/// Only available when the engine is built with the `profiling` feature.
+///
+#[cfg(feature = "profiling")]
+pub struct Profiler {
+  guard: ProfilerGuard<'static>,
+}
+
+#[cfg(feature = "profiling")]
+impl Profiler {
+  ///
+  /// Starts sampling at the given frequency (in Hz).
+  ///
+  pub fn start(frequency: i32) -> Result<Profiler, String> {
+    ProfilerGuard::new(frequency)
+      .map(|guard| Profiler { guard })
+      .map_err(|e| format!("Failed to start profiling: {}", e))
+  }
+
+  ///
+  /// Writes the samples so far to the given path: as a flamegraph if the path has an `svg`
+  /// extension, and otherwise as a (protobuf) pprof profile.
+  ///
+  pub fn write_to(&self, path: &Path) -> Result<(), String> {
+    let report = self
+      .guard
+      .report()
+      .build()
+      .map_err(|e| format!("Failed to build profile: {}", e))?;
+    let mut file = File::create(path)
+      .map_err(|e| format!("Failed to create profile {}: {}", path.display(), e))?;
+    if path.extension() == Some(OsStr::new("svg")) {
+      report
+        .flamegraph(file)
+        .map_err(|e| format!("Failed to write flamegraph {}: {}", path.display(), e))
+    } else {
+      let mut content = Vec::new();
+      report
+        .pprof()
+        .map_err(|e| format!("Failed to build pprof profile: {}", e))?
+        .encode(&mut content)
+        .map_err(|e| format!("Failed to encode pprof profile: {}", e))?;
+      file
+        .write_all(&content)
+        .map_err(|e| format!("Failed to write profile {}: {}", path.display(), e))
+    }
+  }
+}
+
+#[cfg(not(feature = "profiling"))]
+pub struct Profiler;
+
+#[cfg(not(feature = "profiling"))]
+impl Profiler {
+  pub fn start(_frequency: i32) -> Result<Profiler, String> {
+    Err(
+      "Profiling is not available: the native engine was built without the `profiling` feature."
+        .to_owned(),
+    )
+  }
+
+  pub fn write_to(&self, _path: &Path) -> Result<(), String> {
+    unreachable!("A Profiler cannot be started without the `profiling` feature.")
+  }
+}
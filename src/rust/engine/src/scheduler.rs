@@ -3,21 +3,34 @@
 
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
+use std::ffi::OsStr;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{mpsc, Arc};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::future::{self, Future};
 
-use crate::context::{Context, Core};
-use crate::core::{Failure, Params, TypeId, Value};
+use crate::context::{Context, Core, RunId};
+use crate::core::{throw, Failure, Params, TypeId, Value};
+use crate::externs;
 use crate::nodes::{NodeKey, Select, Tracer, Visualizer};
+use crate::profiling::Profiler;
 use crate::selectors;
-use graph::{EntryId, Graph, InvalidationResult, NodeContext};
+use crate::watch::{InvalidationGlobs, InvalidationWatcher, WatcherBackend};
+use fs::UploadScope;
+use graph::{Cancellation, EntryId, Graph, NodeContext};
 use indexmap::IndexMap;
-use log::{debug, info, warn};
+use log::{debug, Level};
+use logging::console::{self, TeeGuard};
+use metrics;
 use parking_lot::Mutex;
+use process_execution::background::BackgroundProcess;
+use process_execution::limits::ProcessCounts;
+use process_execution::service::ServiceRegistry;
+use process_execution::strategy::ExecutionStrategy;
+use serde_json::{self, json};
 use ui::EngineDisplay;
 
 ///
@@ -34,14 +47,229 @@ pub struct Session {
   roots: Mutex<HashSet<Root>>,
   // If enabled, the display that will render the progress of the V2 engine.
   display: Option<Mutex<EngineDisplay>>,
+  // The most verbose level of work which is rendered by the display and reported for this Session.
+  level: Level,
+  // If enabled, a copy of all console output for this Session is written to a file until the
+  // Session is dropped.
+  console_tee: Mutex<Option<TeeGuard>>,
+  // If enabled, a sampling profiler of the engine which runs until the Session is dropped.
+  profiler: Mutex<Option<Profiler>>,
+  // If set, the timeout for this Session, and the time after which its executions fail rather than
+  // continuing to wait for their roots.
+  deadline: Option<(Duration, Instant)>,
+  // The salt which uncacheable Nodes that are requested within this Session are keyed by.
+  run_id: RunId,
+  // Where the processes requested within this Session are executed, which (like the RunId) is
+  // passed to the Nodes that it requests via their Context, so that concurrent Sessions may differ.
+  execution_strategy: ExecutionStrategy,
+  // The context of the processes requested within this Session, which holds the counts of the
+  // processes that it has executed (which are checked against the configured limits) and the
+  // digests that it has uploaded (which, since the remote may evict them, are not assumed to be
+  // present in later Sessions).
+  process_context: process_execution::Context,
+  // The outcome of each root that has been executed within this Session, in order.
+  root_outcomes: Mutex<Vec<RootOutcome>>,
+  // The processes started in the background within this Session (indexed by their ids), which are
+  // killed when it is dropped.
+  background_processes: Mutex<Vec<Arc<BackgroundProcess>>>,
+  // The services started within this Session, which are shared by every requester within it, and
+  // which are stopped when it is dropped.
+  pub services: ServiceRegistry,
+  // Whether the roots of an execution continue to run after one of them has failed.
+  execution_policy: ExecutionPolicy,
+  // The engine-wide metrics recorded during this Session.
+  metrics: metrics::Scope,
+  // Used to remove the Nodes keyed by this Session's RunId from the Graph when it is dropped.
+  core: Arc<Core>,
+  live_run_ids: Arc<Mutex<HashSet<RunId>>>,
+}
+
+///
+/// What happens to the other roots of an execution when one of them fails.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExecutionPolicy {
+  // Cancel the other roots (and any processes that they are running), which then fail.
+  FailFast,
+  // Run every root to completion.
+  KeepGoing,
+}
+
+impl ExecutionPolicy {
+  pub fn new(name: &str) -> Result<ExecutionPolicy, String> {
+    match name {
+      "fail_fast" => Ok(ExecutionPolicy::FailFast),
+      "keep_going" => Ok(ExecutionPolicy::KeepGoing),
+      other => Err(format!("Unknown execution policy: {:?}", other)),
+    }
+  }
+}
+
+///
+/// The outcome of executing one root, for the run report.
+///
+struct RootOutcome {
+  root: String,
+  status: &'static str,
+  elapsed: Duration,
 }
 
 impl Session {
-  pub fn new(scheduler: &Scheduler, should_render_ui: bool, ui_worker_count: usize) -> Session {
+  pub fn new(
+    scheduler: &Scheduler,
+    should_render_ui: bool,
+    ui_worker_count: usize,
+    level: Level,
+    timeout: Option<Duration>,
+    execution_strategy: ExecutionStrategy,
+    execution_policy: ExecutionPolicy,
+  ) -> Session {
+    let run_id = RunId(scheduler.run_id_generator.fetch_add(1, Ordering::SeqCst));
+    scheduler.live_run_ids.lock().insert(run_id);
+    remove_stale_nodes(&scheduler.core, &scheduler.live_run_ids);
     Session {
       preceding_graph_size: scheduler.core.graph.len(),
       roots: Mutex::new(HashSet::new()),
       display: EngineDisplay::create(ui_worker_count, should_render_ui).map(Mutex::new),
+      level,
+      console_tee: Mutex::new(None),
+      profiler: Mutex::new(None),
+      deadline: timeout.map(|timeout| (timeout, Instant::now() + timeout)),
+      run_id,
+      execution_strategy,
+      process_context: process_execution::Context {
+        execution_strategy,
+        process_counts: Arc::new(ProcessCounts::new(scheduler.core.process_count_limits)),
+        uploads: UploadScope::default(),
+        secret_env_salt: Some(process_execution::secret_env_salt(run_id.0)),
+      },
+      root_outcomes: Mutex::new(Vec::new()),
+      background_processes: Mutex::new(Vec::new()),
+      services: ServiceRegistry::default(),
+      execution_policy,
+      metrics: metrics::Scope::new(),
+      core: scheduler.core.clone(),
+      live_run_ids: scheduler.live_run_ids.clone(),
+    }
+  }
+
+  ///
+  /// Retains a process which was started in the background for the remainder of this Session, and
+  /// returns its id. A process (such as a service) which is added more than once keeps its id.
+  ///
+  pub fn add_background_process(&self, process: Arc<BackgroundProcess>) -> u64 {
+    let mut processes = self.background_processes.lock();
+    if let Some(id) = processes.iter().position(|p| Arc::ptr_eq(p, &process)) {
+      return id as u64;
+    }
+    processes.push(process);
+    (processes.len() - 1) as u64
+  }
+
+  pub fn run_id(&self) -> RunId {
+    self.run_id
+  }
+
+  pub fn background_process(&self, id: u64) -> Result<Arc<BackgroundProcess>, String> {
+    self
+      .background_processes
+      .lock()
+      .get(id as usize)
+      .cloned()
+      .ok_or_else(|| format!("No background process with id {}.", id))
+  }
+
+  ///
+  /// Runs the given function with exclusive use of the console, pausing the display (if it is
+  /// running) until the function returns.
+  ///
+  pub fn with_console<F: FnOnce() -> T, T>(&self, f: F) -> T {
+    match self.display {
+      Some(ref display) => {
+        // The display remains locked while the function runs, so that it is not rendered.
+        let mut display = display.lock();
+        let was_running = display.is_running();
+        if was_running {
+          display.finish();
+        }
+        let result = f();
+        if was_running {
+          display.start();
+        }
+        result
+      }
+      None => f(),
+    }
+  }
+
+  fn deadline_passed(&self) -> bool {
+    self
+      .deadline
+      .map(|(_, deadline)| Instant::now() >= deadline)
+      .unwrap_or(false)
+  }
+
+  ///
+  /// Tees the console output of this Session (with ANSI escapes stripped) into the given file for
+  /// the remainder of this Session: that of its display, the output written by Python on its behalf
+  /// (see `write_to_console_tee`), and the engine's log output (which is copied to every Session's
+  /// tee, since it cannot be attributed to one of them).
+  ///
+  pub fn tee_console_to(&self, path: &Path) -> Result<(), String> {
+    let guard = console::start_tee(path)?;
+    if let Some(ref display) = self.display {
+      display.lock().tee_to(Box::new(guard.writer()));
+    }
+    *self.console_tee.lock() = Some(guard);
+    Ok(())
+  }
+
+  ///
+  /// Copies console output which was written on behalf of this Session (but not by the engine) to
+  /// its tee, if it has one.
+  ///
+  pub fn write_to_console_tee(&self, buf: &[u8]) {
+    if let Some(ref tee) = *self.console_tee.lock() {
+      tee.write(buf);
+    }
+  }
+
+  ///
+  /// Starts sampling the stacks of the engine at the given frequency (in Hz) for the remainder of
+  /// this Session. Replaces any existing profiler (and its samples).
+  ///
+  pub fn start_profiling(&self, frequency: i32) -> Result<(), String> {
+    let mut profiler = self.profiler.lock();
+    // Only one profiler may run at a time, so the existing one must be stopped first.
+    *profiler = None;
+    *profiler = Some(Profiler::start(frequency)?);
+    Ok(())
+  }
+
+  ///
+  /// Writes the samples taken so far in this Session to the given path: see `Profiler::write_to`.
+  ///
+  pub fn write_profile(&self, path: &Path) -> Result<(), String> {
+    self
+      .profiler
+      .lock()
+      .as_ref()
+      .ok_or_else(|| "Profiling was not started for this Session.".to_owned())
+      .and_then(|profiler| profiler.write_to(path))
+  }
+
+  fn record_outcomes(&self, roots: &[Root], results: &[RootResult], elapsed: Duration) {
+    let mut root_outcomes = self.root_outcomes.lock();
+    for (root, result) in roots.iter().zip(results) {
+      root_outcomes.push(RootOutcome {
+        root: NodeKey::Select(Box::new(root.clone())).to_string(),
+        status: match result {
+          Ok(_) => "success",
+          Err(Failure::Invalidated) => "invalidated",
+          Err(Failure::Throw(..)) => "failure",
+        },
+        elapsed,
+      });
     }
   }
 
@@ -80,20 +308,54 @@ impl ExecutionRequest {
 ///
 pub struct Scheduler {
   pub core: Arc<Core>,
+  // If the build root is being watched, the watcher that is invalidating the Graph.
+  watcher: Mutex<Option<InvalidationWatcher>>,
+  // The source of a unique RunId for each Session.
+  run_id_generator: AtomicU32,
+  // The RunIds of the Sessions which have not yet been dropped.
+  live_run_ids: Arc<Mutex<HashSet<RunId>>>,
 }
 
 impl Scheduler {
   pub fn new(core: Core) -> Scheduler {
+    // The Keys interned while creating the Core (such as the functions of rules) must never be
+    // collected.
+    externs::pin_interns();
     Scheduler {
       core: Arc::new(core),
+      watcher: Mutex::new(None),
+      run_id_generator: AtomicU32::new(0),
+      live_run_ids: Arc::default(),
     }
   }
 
-  pub fn visualize(&self, session: &Session, path: &Path) -> io::Result<()> {
-    self
-      .core
-      .graph
-      .visualize(Visualizer::default(), &session.root_nodes(), path)
+  ///
+  /// Visualizes the portion of the Graph beneath the roots of the Session, as JSON (including the
+  /// state of each Node) if the path has a `.json` extension, and otherwise as GraphViz DOT.
+  ///
+  /// If node_types is non-empty, only Nodes of those types are rendered. If max_depth is given,
+  /// only Nodes within that many edges of the roots are rendered.
+  ///
+  pub fn visualize(
+    &self,
+    session: &Session,
+    path: &Path,
+    node_types: HashSet<String>,
+    max_depth: Option<usize>,
+  ) -> io::Result<()> {
+    let visualizer = Visualizer::new(node_types);
+    let roots = session.root_nodes();
+    if path.extension() == Some(OsStr::new("json")) {
+      self
+        .core
+        .graph
+        .visualize_json(visualizer, &roots, max_depth, path)
+    } else {
+      self
+        .core
+        .graph
+        .visualize(visualizer, &roots, max_depth, path)
+    }
   }
 
   pub fn trace(&self, request: &ExecutionRequest, path: &Path) -> Result<(), String> {
@@ -104,9 +366,14 @@ impl Scheduler {
     Ok(())
   }
 
+  ///
+  /// Adds a root to the given request. Because a root may be uncacheable (in which case it is
+  /// keyed by the RunId of the Session), the request should only be executed in the given Session.
+  ///
   pub fn add_root_select(
     &self,
     request: &mut ExecutionRequest,
+    session: &Session,
     params: Params,
     product: TypeId,
   ) -> Result<(), String> {
@@ -116,7 +383,7 @@ impl Scheduler {
       .find_root_edges(params.type_ids(), &selectors::Select::new(product))?;
     request
       .roots
-      .push(Select::new_from_edges(params, product, &edges));
+      .push(Select::new_from_edges(params, product, &edges).salted(session.run_id));
     Ok(())
   }
 
@@ -124,37 +391,69 @@ impl Scheduler {
   /// Invalidate the invalidation roots represented by the given Paths.
   ///
   pub fn invalidate(&self, paths: &HashSet<PathBuf>) -> usize {
-    let InvalidationResult { cleared, dirtied } =
-      self.core.graph.invalidate_from_roots(move |node| {
-        if let Some(fs_subject) = node.fs_subject() {
-          paths.contains(fs_subject)
-        } else {
-          false
-        }
-      });
-    // TODO: The rust log level is not currently set correctly in a pantsd context. To ensure that
-    // we see this even at `info` level, we set it to warn. #6004 should address this by making
-    // rust logging re-configuration an explicit step in `src/python/pants/init/logging.py`.
-    warn!(
-      "invalidation: cleared {} and dirtied {} nodes for: {:?}",
-      cleared, dirtied, paths
-    );
-    cleared + dirtied
+    self.core.invalidate(paths)
   }
 
   ///
   /// Invalidate all filesystem dependencies in the graph.
   ///
   pub fn invalidate_all_paths(&self) -> usize {
-    let InvalidationResult { cleared, dirtied } = self
-      .core
-      .graph
-      .invalidate_from_roots(|node| node.fs_subject().is_some());
-    info!(
-      "invalidation: cleared {} and dirtied {} nodes for all paths",
-      cleared, dirtied
+    self.core.invalidate_all_paths()
+  }
+
+  ///
+  /// Start watching the build root for changes using the given backend, and invalidating the
+  /// Graph as they occur. Replaces any existing watcher.
+  ///
+  /// Changes to paths matched by the InvalidationGlobs cause a full invalidation, and are reported
+  /// via `restart_required`.
+  ///
+  pub fn start_watching(
+    &self,
+    backend: &WatcherBackend,
+    invalidation_globs: InvalidationGlobs,
+  ) -> Result<(), String> {
+    let mut watcher = self.watcher.lock();
+    // Stop any existing watcher before starting a new one, so that they don't overlap.
+    *watcher = None;
+    *watcher = Some(InvalidationWatcher::start(
+      &self.core,
+      backend,
+      invalidation_globs,
+    )?);
+    Ok(())
+  }
+
+  ///
+  /// True if the build root is being watched, and a path that pants itself depends on has changed
+  /// since watching started.
+  ///
+  pub fn restart_required(&self) -> bool {
+    self
+      .watcher
+      .lock()
+      .as_ref()
+      .map(InvalidationWatcher::restart_required)
+      .unwrap_or(false)
+  }
+
+  ///
+  /// Drops interned Keys which are no longer referenced by any Node in the Graph (see
+  /// `Interns::collect`), and returns the number dropped.
+  ///
+  /// NB: Interned Keys are global to the process, so this is only safe while this is the only
+  /// Scheduler in use in the process, and should be called between runs.
+  ///
+  pub fn collect_interns(&self) -> usize {
+    let mut live = HashSet::new();
+    self.core.graph.visit_nodes(|node| live.extend(node.keys()));
+    let collected = externs::collect_interns(&live);
+    debug!(
+      "Collected {} interned keys ({} referenced by the graph).",
+      collected,
+      live.len()
     );
-    cleared + dirtied
+    collected
   }
 
   ///
@@ -171,9 +470,164 @@ impl Scheduler {
     );
     m.insert("preceding_graph_size", session.preceding_graph_size as i64);
     m.insert("resulting_graph_size", self.core.graph.len() as i64);
+    m.insert(
+      "process_execution_queue_depth",
+      self.core.command_runner().queue_depth() as i64,
+    );
+    if let Some(transfer_stats) = self.core.store().transfer_stats() {
+      m.insert(
+        "remote_store_queued_transfers",
+        transfer_stats.queued_transfers as i64,
+      );
+      m.insert(
+        "remote_store_in_flight_uploads",
+        transfer_stats.in_flight_uploads as i64,
+      );
+      m.insert(
+        "remote_store_in_flight_downloads",
+        transfer_stats.in_flight_downloads as i64,
+      );
+      m.insert(
+        "remote_store_bytes_uploaded",
+        transfer_stats.bytes_uploaded as i64,
+      );
+      m.insert(
+        "remote_store_bytes_downloaded",
+        transfer_stats.bytes_downloaded as i64,
+      );
+    }
+    for (metric, value) in session.metrics.snapshot().counters {
+      m.insert(metric.as_str(), value as i64);
+    }
     m
   }
 
+  ///
+  /// Summarizes the approximate memory held by each subsystem of the engine, to help triage the
+  /// growth of long-lived (daemon) processes without a heap dump.
+  ///
+  /// Sizes are shallow estimates of memory held by the engine itself: they exclude python objects,
+  /// and LMDB maps are reported by the sizes of their (memory mapped) files, which bound rather
+  /// than measure their resident memory.
+  ///
+  pub fn memory_summary(&self) -> Result<serde_json::Value, String> {
+    let graph = self
+      .core
+      .graph
+      .memory_usage(NodeKey::type_name)
+      .into_iter()
+      .map(|(node_type, (count, bytes))| {
+        (
+          node_type.to_owned(),
+          json!({"count": count, "bytes": bytes}),
+        )
+      })
+      .collect::<serde_json::Map<_, _>>();
+    let (workunit_count, workunit_bytes) = metrics::workunits_memory_usage();
+    let (key_count, type_count, interns_bytes) = externs::interns_memory_usage();
+    let lmdb = self
+      .core
+      .lmdb_sizes()?
+      .into_iter()
+      .map(|(path, bytes)| (path.to_string_lossy().into_owned(), json!(bytes)))
+      .collect::<serde_json::Map<_, _>>();
+    Ok(json!({
+      "graph": graph,
+      "workunits": {"count": workunit_count, "bytes": workunit_bytes},
+      "interns": {"keys": key_count, "types": type_count, "bytes": interns_bytes},
+      "lmdb": lmdb,
+    }))
+  }
+
+  ///
+  /// Return summaries of the histograms of observations recorded during the given Session.
+  ///
+  pub fn observation_metrics(
+    &self,
+    session: &Session,
+  ) -> Vec<(&'static str, metrics::HistogramSummary)> {
+    session
+      .metrics
+      .snapshot()
+      .observations
+      .into_iter()
+      .map(|(metric, summary)| (metric.as_str(), summary))
+      .collect()
+  }
+
+  ///
+  /// Writes a machine-readable (JSON) report of the given Session so far to the given path: the
+  /// outcome of each root, counts of executed processes, cache and transfer statistics, and the
+  /// slowest workunits at (or below) the verbosity level of the Session.
+  ///
+  pub fn write_run_report(&self, session: &Session, path: &Path) -> Result<(), String> {
+    let snapshot = session.metrics.snapshot();
+    let roots = session
+      .root_outcomes
+      .lock()
+      .iter()
+      .map(|outcome| {
+        json!({
+          "root": outcome.root,
+          "status": outcome.status,
+          "elapsed_millis": duration_millis(outcome.elapsed),
+        })
+      })
+      .collect::<Vec<_>>();
+    let counters = snapshot
+      .counters
+      .iter()
+      .map(|(metric, value)| (metric.as_str().to_owned(), json!(value)))
+      .collect::<serde_json::Map<_, _>>();
+    let transfers = self
+      .core
+      .store()
+      .transfer_stats()
+      .map(|stats| {
+        json!({
+          "bytes_uploaded": stats.bytes_uploaded,
+          "bytes_downloaded": stats.bytes_downloaded,
+        })
+      })
+      .unwrap_or(serde_json::Value::Null);
+    let slowest_workunits = snapshot
+      .slowest_workunits
+      .iter()
+      .filter(|workunit| workunit.level <= session.level)
+      .map(|workunit| {
+        json!({
+          "name": workunit.name,
+          "level": workunit.level.to_string().to_lowercase(),
+          "elapsed_millis": duration_millis(workunit.duration),
+        })
+      })
+      .collect::<Vec<_>>();
+    let workunits_with_warnings = snapshot
+      .workunits_with_warnings
+      .iter()
+      .map(|workunit| {
+        json!({
+          "name": workunit.name,
+          "level": workunit.level.to_string().to_lowercase(),
+          "warnings": workunit.warnings,
+        })
+      })
+      .collect::<Vec<_>>();
+
+    let report = json!({
+      "roots": roots,
+      "processes_executed": session.process_context.process_counts.total(),
+      "metrics": counters,
+      "remote_store_transfers": transfers,
+      "slowest_workunits": slowest_workunits,
+      "workunits_with_warnings": workunits_with_warnings,
+    });
+    let content = serde_json::to_vec_pretty(&report)
+      .map_err(|e| format!("Failed to serialize run report: {}", e))?;
+    std::fs::write(path, content)
+      .map_err(|e| format!("Failed to write run report to {}: {}", path.display(), e))
+  }
+
   ///
   /// Attempts to complete all of the given roots, retrying the entire set (up to `count`
   /// times) if any of them fail with `Failure::Invalidated`. Sends the result on the given
@@ -184,11 +638,15 @@ impl Scheduler {
   /// were (say by an automated process changing files under pants), we'd want to eventually
   /// give up.
   ///
+  /// If `fail_fast` (in which case the context has a Cancellation), the first root to fail cancels
+  /// the others, which then fail rather than being retried.
+  ///
   fn execute_helper(
     context: RootContext,
     sender: mpsc::Sender<Vec<Result<Value, Failure>>>,
     roots: Vec<Root>,
     count: usize,
+    fail_fast: bool,
   ) {
     let core = context.core.clone();
     // Attempt all roots in parallel, failing fast to retry for `Invalidated`.
@@ -197,12 +655,20 @@ impl Scheduler {
         .clone()
         .into_iter()
         .map(|root| {
+          let cancellation = context.cancellation.clone();
           context
             .core
             .graph
             .create(root.clone().into(), &context)
             .then::<_, Result<Result<Value, Failure>, Failure>>(move |r| {
+              let cancelled = cancellation
+                .as_ref()
+                .map_or(false, Cancellation::is_cancelled);
               match r {
+                Err(Failure::Invalidated) if cancelled => {
+                  debug!("Root {} was cancelled.", NodeKey::Select(Box::new(root)));
+                  Ok(Err(throw("Cancelled because another root failed.")))
+                }
                 Err(Failure::Invalidated) if count > 0 => {
                   // A node was invalidated: fail quickly so that all roots can be retried.
                   Err(Failure::Invalidated)
@@ -212,6 +678,11 @@ impl Scheduler {
                   // out of retries) recover to complete the join, which will cause the results to
                   // propagate to the user.
                   debug!("Root {} completed.", NodeKey::Select(Box::new(root)));
+                  if let (Err(Failure::Throw(..)), Some(cancellation), true) =
+                    (&other, &cancellation, fail_fast)
+                  {
+                    cancellation.cancel();
+                  }
                   Ok(other.map(|res| {
                     res
                       .try_into()
@@ -230,7 +701,7 @@ impl Scheduler {
       if let Ok(res) = res {
         sender.send(res).map_err(|_| ())
       } else {
-        Scheduler::execute_helper(context, sender, roots, count - 1);
+        Scheduler::execute_helper(context, sender, roots, count - 1, fail_fast);
         Ok(())
       }
     }));
@@ -242,17 +713,41 @@ impl Scheduler {
   pub fn execute(&self, request: &ExecutionRequest, session: &Session) -> Vec<RootResult> {
     // Bootstrap tasks for the roots, and then wait for all of them.
     debug!("Launching {} roots.", request.roots.len());
+    let start_time = Instant::now();
+    // The historical duration of requests for the same products, which is used to estimate the
+    // progress of the run as a whole.
+    let run_history_key = format!(
+      "run:{}",
+      request
+        .roots
+        .iter()
+        .map(|root| root.product.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+    );
+    let run_estimate = self.core.estimate_duration(&run_history_key);
 
     session.extend(&request.roots);
 
     // Wait for all roots to complete. Failure here should be impossible, because each
     // individual Future in the join was (eventually) mapped into success.
+    let fail_fast = session.execution_policy == ExecutionPolicy::FailFast;
+    // The roots are cancelled if one of them fails (when failing fast), or if the deadline passes.
+    let cancellation = if fail_fast || session.deadline.is_some() {
+      Some(Cancellation::default())
+    } else {
+      None
+    };
     let context = RootContext {
       core: self.core.clone(),
+      run_id: session.run_id,
+      execution_strategy: session.execution_strategy,
+      process_context: session.process_context.clone(),
+      cancellation: cancellation.clone(),
     };
     let (sender, receiver) = mpsc::channel();
 
-    Scheduler::execute_helper(context, sender, request.roots.clone(), 8);
+    Scheduler::execute_helper(context, sender, request.roots.clone(), 8, fail_fast);
     let roots: Vec<NodeKey> = request
       .roots
       .clone()
@@ -260,42 +755,101 @@ impl Scheduler {
       .map(NodeKey::from)
       .collect();
 
-    // Lock the display for the remainder of the execution, and grab a reference to it.
-    let mut maybe_display = match &session.display {
-      &Some(ref d) => Some(d.lock()),
-      &None => None,
-    };
-
     // This map keeps the k most relevant jobs in assigned possitions.
     // Keys are positions in the display (display workers) and the values are the actual jobs to print.
     let mut tasks_to_display = IndexMap::new();
 
-    if let Some(ref mut display) = maybe_display {
-      display.start();
+    // The display is locked only while rendering, so that a rule may pause it to use the console.
+    if let Some(ref display) = session.display {
+      display.lock().start();
     };
 
     let results = loop {
       if let Ok(res) = receiver.recv_timeout(Duration::from_millis(100)) {
         break res;
-      } else if let Some(ref mut display) = maybe_display {
-        Scheduler::display_ongoing_tasks(&self.core.graph, &roots, display, &mut tasks_to_display);
+      } else if session.deadline_passed() {
+        break Scheduler::timed_out(&self.core.graph, &roots, session, cancellation.as_ref());
+      } else if let Some(ref display) = session.display {
+        let mut display = display.lock();
+        if display.is_running() {
+          Scheduler::display_ongoing_tasks(
+            &self.core,
+            &roots,
+            session.level,
+            start_time.elapsed(),
+            run_estimate,
+            &mut display,
+            &mut tasks_to_display,
+          );
+        }
       }
     };
-    if let Some(ref mut display) = maybe_display {
-      display.finish();
+    if let Some(ref display) = session.display {
+      display.lock().finish();
     };
 
+    if !results.is_empty() && results.iter().all(Result::is_ok) {
+      self
+        .core
+        .record_duration(&run_history_key, start_time.elapsed());
+    }
+    session.record_outcomes(&request.roots, &results, start_time.elapsed());
     results
   }
 
-  fn display_ongoing_tasks(
+  ///
+  /// Fails each of the given roots because the deadline of the Session has passed, describing the
+  /// leaves which are still running (and so are likely to be responsible).
+  ///
+  /// Any work which is still running is then cancelled (which kills any processes that it is
+  /// waiting for), so that it neither continues in the background nor completes its Nodes.
+  ///
+  fn timed_out(
     graph: &Graph<NodeKey>,
     roots: &[NodeKey],
+    session: &Session,
+    cancellation: Option<&Cancellation>,
+  ) -> Vec<RootResult> {
+    let mut running = graph
+      .heavy_hitters(roots, TIMED_OUT_LEAVES_TO_REPORT, Level::Trace)
+      .into_iter()
+      .collect::<Vec<_>>();
+    running.sort_by(|(_, a), (_, b)| b.cmp(a));
+    let timeout = session
+      .deadline
+      .map(|(timeout, _)| timeout)
+      .unwrap_or_default();
+    let mut msg = format!(
+      "Build timed out after {}s. The longest running leaves were:",
+      timeout.as_secs()
+    );
+    for (node, elapsed) in running {
+      msg.push_str(&format!(
+        "\n  {}.{:03}s {}",
+        elapsed.as_secs(),
+        elapsed.subsec_millis(),
+        node
+      ));
+    }
+    if let Some(cancellation) = cancellation {
+      cancellation.cancel();
+    }
+    roots.iter().map(|_| Err(throw(&msg))).collect()
+  }
+
+  fn display_ongoing_tasks(
+    core: &Core,
+    roots: &[NodeKey],
+    level: Level,
+    run_elapsed: Duration,
+    run_estimate: Option<Duration>,
     display: &mut EngineDisplay,
-    tasks_to_display: &mut IndexMap<String, Duration>,
+    tasks_to_display: &mut IndexMap<NodeKey, Duration>,
   ) {
     // Update the graph. To do that, we iterate over heavy hitters.
-    let heavy_hitters = graph.heavy_hitters(&roots, display.worker_count());
+    let heavy_hitters = core
+      .graph
+      .heavy_hitters(&roots, display.worker_count(), level);
     // Insert every one in the set of tasks to display.
     // For tasks already here, the durations are overwritten.
     tasks_to_display.extend(heavy_hitters.clone().into_iter());
@@ -307,9 +861,14 @@ impl Scheduler {
     }
     let display_worker_count = display.worker_count();
     let ongoing_tasks = tasks_to_display;
-    for (i, id) in ongoing_tasks.iter().enumerate() {
-      // TODO Maybe we want to print something else besides the ID here.
-      display.update(i.to_string(), format!("{:?}", id));
+    for (i, (task, elapsed)) in ongoing_tasks.iter().enumerate() {
+      let estimate = task
+        .history_key()
+        .and_then(|key| core.estimate_duration(&key));
+      display.update(
+        i.to_string(),
+        format!("{} {}", task, progress_label(*elapsed, estimate)),
+      );
     }
     // If the number of ongoing tasks is less than the number of workers,
     // fill the rest of the workers with empty string.
@@ -317,10 +876,46 @@ impl Scheduler {
     for i in ongoing_tasks.len()..display_worker_count {
       display.update(i.to_string(), "".to_string());
     }
+    display.set_progress(
+      run_estimate.map(|estimate| format!("Run: {}", progress_label(run_elapsed, Some(estimate)))),
+    );
     display.render();
   }
 }
 
+impl Drop for Session {
+  fn drop(&mut self) {
+    // The artifact index describes the files materialized by the Sessions which are still live.
+    if let Some(ref index) = self.core.artifact_index {
+      index.end_run(self.run_id.0);
+    }
+    self.live_run_ids.lock().remove(&self.run_id);
+    remove_stale_nodes(&self.core, &self.live_run_ids);
+  }
+}
+
+///
+/// Removes the Nodes which are keyed by the RunIds of Sessions that have been dropped (and which can
+/// thus never be requested again) from the Graph, so that it does not grow with each Session.
+///
+/// Nodes which are still running are removed by a later call.
+///
+fn remove_stale_nodes(core: &Core, live_run_ids: &Mutex<HashSet<RunId>>) {
+  let live_run_ids = live_run_ids.lock().clone();
+  let removed = core.graph.remove_if(|node| {
+    node
+      .run_id()
+      .map(|run_id| !live_run_ids.contains(&run_id))
+      .unwrap_or(false)
+  });
+  if removed > 0 {
+    debug!(
+      "Removed {} Nodes of dropped Sessions from the Graph.",
+      removed
+    );
+  }
+}
+
 impl Drop for Scheduler {
   fn drop(&mut self) {
     // Because Nodes may hold references to the Core in their closure, this is intended to
@@ -336,6 +931,29 @@ type Root = Select;
 
 pub type RootResult = Result<Value, Failure>;
 
+// The maximum number of still-running leaves to report when a Session times out.
+const TIMED_OUT_LEAVES_TO_REPORT: usize = 20;
+
+fn duration_millis(duration: Duration) -> u64 {
+  duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+///
+/// Describes the elapsed time of some work, and (if it is known) its rough progress relative to
+/// the historical duration of the same work. Work which overruns its estimate remains at 99%.
+///
+fn progress_label(elapsed: Duration, estimate: Option<Duration>) -> String {
+  match estimate.map(duration_millis) {
+    Some(estimate_millis) if estimate_millis > 0 => format!(
+      "{}s (~{}% of ~{}s)",
+      elapsed.as_secs(),
+      std::cmp::min(99, duration_millis(elapsed) * 100 / estimate_millis),
+      (estimate_millis + 500) / 1000
+    ),
+    _ => format!("{}s", elapsed.as_secs()),
+  }
+}
+
 ///
 /// NB: This basic wrapper exists to allow us to implement the `NodeContext` trait (which lives
 /// outside of this crate) for the `Arc` struct (which also lives outside our crate), which is not
@@ -344,13 +962,25 @@ pub type RootResult = Result<Value, Failure>;
 #[derive(Clone)]
 struct RootContext {
   core: Arc<Core>,
+  run_id: RunId,
+  execution_strategy: ExecutionStrategy,
+  process_context: process_execution::Context,
+  // If set, the Cancellation which the roots of an execution are run with.
+  cancellation: Option<Cancellation>,
 }
 
 impl NodeContext for RootContext {
   type Node = NodeKey;
 
   fn clone_for(&self, entry_id: EntryId) -> Context {
-    Context::new(entry_id, self.core.clone())
+    Context::new(
+      entry_id,
+      self.core.clone(),
+      self.run_id,
+      self.execution_strategy,
+      self.process_context.clone(),
+      self.cancellation.clone(),
+    )
   }
 
   fn graph(&self) -> &Graph<NodeKey> {
@@ -363,4 +993,8 @@ impl NodeContext for RootContext {
   {
     self.core.spawn(future);
   }
+
+  fn cancellation(&self) -> Option<&Cancellation> {
+    self.cancellation.as_ref()
+  }
 }
@@ -85,6 +85,10 @@ impl Tasks {
         product: types.files_content,
         input: types.directory_digest,
       },
+      Intrinsic {
+        product: types.files_content_view,
+        input: types.directory_digest,
+      },
       Intrinsic {
         product: types.directory_digest,
         input: types.directories_to_merge,
@@ -93,10 +97,30 @@ impl Tasks {
         product: types.directory_digest,
         input: types.directory_with_prefix_to_strip,
       },
+      Intrinsic {
+        product: types.directory_digest,
+        input: types.directory_with_prefix_to_add,
+      },
+      Intrinsic {
+        product: types.snapshot,
+        input: types.snapshot_subset,
+      },
+      Intrinsic {
+        product: types.directory_digest,
+        input: types.archive_to_extract,
+      },
+      Intrinsic {
+        product: types.directory_digest,
+        input: types.directory_to_archive,
+      },
       Intrinsic {
         product: types.process_result,
         input: types.process_request,
       },
+      Intrinsic {
+        product: types.sharded_process_results,
+        input: types.sharded_process_request,
+      },
     ];
 
     self.intrinsics = vec![].into_iter().collect();
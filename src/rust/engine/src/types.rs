@@ -5,19 +5,29 @@ pub struct Types {
   pub construct_snapshot: Function,
   pub construct_file_content: Function,
   pub construct_files_content: Function,
+  pub construct_file_content_view: Function,
+  pub construct_files_content_view: Function,
   pub construct_process_result: Function,
+  pub construct_sharded_process_results: Function,
   pub address: TypeId,
   pub path_globs: TypeId,
   pub directory_digest: TypeId,
   pub snapshot: TypeId,
   pub directories_to_merge: TypeId,
   pub directory_with_prefix_to_strip: TypeId,
+  pub directory_with_prefix_to_add: TypeId,
+  pub snapshot_subset: TypeId,
+  pub archive_to_extract: TypeId,
+  pub directory_to_archive: TypeId,
   pub files_content: TypeId,
+  pub files_content_view: TypeId,
   pub dir: TypeId,
   pub file: TypeId,
   pub link: TypeId,
   pub process_request: TypeId,
   pub process_result: TypeId,
+  pub sharded_process_request: TypeId,
+  pub sharded_process_results: TypeId,
   pub generator: TypeId,
   pub url_to_fetch: TypeId,
   pub string: TypeId,
@@ -0,0 +1,460 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{metadata, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use fs::{Dir, File, GlobExpansionConjunction, PathGlobs, PosixFS, Stat, StrictGlobMatching};
+use hashing::Digest;
+use log::{debug, warn};
+use notify::{self, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher as NotifyWatch};
+use parking_lot::Mutex;
+
+use crate::context::Core;
+use crate::watchman::WatchmanWatcher;
+
+// The duration for which notify waits for further events for a path before reporting it, which
+// coalesces (for example) the many writes that make up saving a large file.
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(50);
+
+///
+/// A change observed by a Watcher.
+///
+#[derive(Debug)]
+pub enum WatchEvent {
+  // The given absolute paths were created, modified or removed.
+  Changed(Vec<PathBuf>),
+  // The given absolute path was renamed to the second absolute path.
+  Renamed(PathBuf, PathBuf),
+  // Events may have been missed, so any path might have changed.
+  Rescan,
+}
+
+///
+/// A source of WatchEvents for the files under a build root. Watchers send events until they are
+/// dropped.
+///
+pub trait Watcher: Send {
+  ///
+  /// A description of this Watcher, for logging.
+  ///
+  fn describe(&self) -> String;
+}
+
+///
+/// The Watcher implementations which may be used to watch a build root.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WatcherBackend {
+  // Watch using the notify crate (inotify, FSEvents, etc).
+  Notify,
+  // Subscribe to an already running watchman daemon, which is useful on platforms where inotify
+  // watch limits are a problem.
+  Watchman { socket_path: PathBuf },
+}
+
+impl WatcherBackend {
+  pub fn new(name: &str, watchman_socket_path: Option<PathBuf>) -> Result<WatcherBackend, String> {
+    match (name, watchman_socket_path) {
+      ("notify", _) => Ok(WatcherBackend::Notify),
+      ("watchman", Some(socket_path)) => Ok(WatcherBackend::Watchman { socket_path }),
+      ("watchman", None) => Err("The watchman watcher backend requires a socket path.".to_owned()),
+      (name, _) => Err(format!(
+        "Unknown watcher backend `{}`: expected one of `notify` or `watchman`.",
+        name
+      )),
+    }
+  }
+}
+
+///
+/// The digests of recently hashed files, keyed by their path relative to the build root, and
+/// validated against the size and modification time that they had when they were hashed. This
+/// allows a file which was renamed (which preserves both) to be digested under its new path
+/// without being re-read and re-hashed.
+///
+#[derive(Default)]
+pub struct FileDigestCache {
+  entries: Mutex<HashMap<PathBuf, (FileStamp, Digest)>>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FileStamp {
+  len: u64,
+  modified: SystemTime,
+}
+
+impl FileStamp {
+  fn new(metadata: &Metadata) -> Option<FileStamp> {
+    metadata.modified().ok().map(|modified| FileStamp {
+      len: metadata.len(),
+      modified,
+    })
+  }
+}
+
+impl FileDigestCache {
+  ///
+  /// The digest of the file at the given path, if it was recorded with matching metadata.
+  ///
+  pub fn get(&self, path: &Path, metadata: &Metadata) -> Option<Digest> {
+    let stamp = FileStamp::new(metadata)?;
+    match self.entries.lock().get(path) {
+      Some((cached_stamp, digest)) if *cached_stamp == stamp => Some(*digest),
+      _ => None,
+    }
+  }
+
+  ///
+  /// Records the digest of the file at the given path, which had the given metadata before it was
+  /// read. If the file was modified while it was being read, the entry will not match again.
+  ///
+  pub fn insert(&self, path: PathBuf, metadata: &Metadata, digest: Digest) {
+    if let Some(stamp) = FileStamp::new(metadata) {
+      self.entries.lock().insert(path, (stamp, digest));
+    }
+  }
+
+  fn forget(&self, path: &Path) {
+    self.entries.lock().remove(path);
+  }
+
+  ///
+  /// Aliases the entries for the given path (or for the files below it, if it is a directory) to
+  /// their new path.
+  ///
+  fn rename(&self, src: &Path, dst: &Path) {
+    let mut entries = self.entries.lock();
+    let renamed = entries
+      .keys()
+      .filter(|path| path.starts_with(src))
+      .cloned()
+      .collect::<Vec<_>>();
+    for path in renamed {
+      if let Some(entry) = entries.remove(&path) {
+        // starts_with was checked above.
+        let suffix = path.strip_prefix(src).unwrap();
+        let new_path = if suffix.as_os_str().is_empty() {
+          dst.to_owned()
+        } else {
+          dst.join(suffix)
+        };
+        entries.insert(new_path, entry);
+      }
+    }
+  }
+}
+
+///
+/// Paths which pants itself depends on (its config files, plugin requirements, or the native engine
+/// binary, for example) rather than inputs to the Graph. Changes to them cannot be safely
+/// invalidated in-process, and so cause a full invalidation and mark a restart as required.
+///
+/// Relative entries are globs matched against paths under the build root, which are observed by
+/// the watcher. Absolute entries are files outside of the build root, which are instead checked
+/// for modification when `restart_required` is called.
+///
+pub struct InvalidationGlobs {
+  globs: Option<PathGlobs>,
+  files: Vec<(PathBuf, Option<SystemTime>)>,
+  restart_required: AtomicBool,
+}
+
+impl InvalidationGlobs {
+  pub fn new(entries: &[String]) -> Result<InvalidationGlobs, String> {
+    let (absolute, relative): (Vec<_>, Vec<_>) = entries
+      .iter()
+      .partition(|entry| Path::new(entry).is_absolute());
+    let globs = if relative.is_empty() {
+      None
+    } else {
+      let relative = relative.into_iter().cloned().collect::<Vec<_>>();
+      Some(PathGlobs::create(
+        &relative,
+        &[],
+        StrictGlobMatching::Ignore,
+        GlobExpansionConjunction::AnyMatch,
+      )?)
+    };
+    let files = absolute
+      .into_iter()
+      .map(|file| {
+        let file = PathBuf::from(file);
+        let modified = Self::modified(&file);
+        (file, modified)
+      })
+      .collect();
+    Ok(InvalidationGlobs {
+      globs,
+      files,
+      restart_required: AtomicBool::new(false),
+    })
+  }
+
+  fn modified(path: &Path) -> Option<SystemTime> {
+    metadata(path).and_then(|m| m.modified()).ok()
+  }
+
+  fn matches(&self, relative_paths: &[PathBuf]) -> bool {
+    match self.globs {
+      Some(ref globs) => globs.matches(relative_paths).unwrap_or_else(|e| {
+        warn!("Failed to match invalidation globs: {}", e);
+        false
+      }),
+      None => false,
+    }
+  }
+
+  ///
+  /// True if any of the invalidation globs have matched a change since watching started, or if
+  /// any of the absolute files have been modified (or created or deleted) since then.
+  ///
+  pub fn restart_required(&self) -> bool {
+    if self.restart_required.load(Ordering::SeqCst) {
+      return true;
+    }
+    let changed = self
+      .files
+      .iter()
+      .any(|(file, modified)| Self::modified(file) != *modified);
+    if changed {
+      self.restart_required.store(true, Ordering::SeqCst);
+    }
+    changed
+  }
+}
+
+///
+/// Watches the build root for changes using a Watcher, and invalidates the affected filesystem
+/// Nodes in the Graph as changes are observed.
+///
+/// Events are consumed on a dedicated thread, which exits when the InvalidationWatcher is dropped
+/// (because dropping the Watcher closes its event channel), or when the Core it was started for
+/// has been dropped.
+///
+pub struct InvalidationWatcher {
+  // Never read: held only to keep the watch alive.
+  _watcher: Box<dyn Watcher>,
+  invalidation_globs: Arc<InvalidationGlobs>,
+}
+
+impl InvalidationWatcher {
+  pub fn start(
+    core: &Arc<Core>,
+    backend: &WatcherBackend,
+    invalidation_globs: InvalidationGlobs,
+  ) -> Result<InvalidationWatcher, String> {
+    // Events are reported for canonical paths on some platforms, so we canonicalize the build
+    // root in order to be able to relativize them.
+    let build_root = core.build_root.canonicalize().map_err(|e| {
+      format!(
+        "Failed to canonicalize build root {:?} for watching: {}",
+        core.build_root, e
+      )
+    })?;
+    let (sender, receiver) = channel();
+    let watcher: Box<dyn Watcher> = match backend {
+      WatcherBackend::Notify => Box::new(NotifyWatcher::start(&build_root, sender)?),
+      WatcherBackend::Watchman { socket_path } => {
+        Box::new(WatchmanWatcher::start(socket_path, &build_root, sender)?)
+      }
+    };
+    debug!("Watching {:?} using {}.", build_root, watcher.describe());
+
+    let core = Arc::downgrade(core);
+    let invalidation_globs = Arc::new(invalidation_globs);
+    let loop_invalidation_globs = invalidation_globs.clone();
+    thread::Builder::new()
+      .name("fs-watcher".to_owned())
+      .spawn(move || {
+        Self::invalidation_loop(&core, &build_root, &loop_invalidation_globs, &receiver)
+      })
+      .map_err(|e| format!("Failed to start filesystem watcher thread: {}", e))?;
+
+    Ok(InvalidationWatcher {
+      _watcher: watcher,
+      invalidation_globs,
+    })
+  }
+
+  ///
+  /// True if a path that pants itself depends on has changed since watching started, meaning that
+  /// the process should be restarted rather than continuing to serve requests.
+  ///
+  pub fn restart_required(&self) -> bool {
+    self.invalidation_globs.restart_required()
+  }
+
+  fn invalidation_loop(
+    core: &Weak<Core>,
+    build_root: &Path,
+    invalidation_globs: &InvalidationGlobs,
+    receiver: &Receiver<WatchEvent>,
+  ) {
+    // Block for the first event of a batch, and then coalesce any others which have already
+    // arrived into a single invalidation.
+    while let Ok(event) = receiver.recv() {
+      let core = match core.upgrade() {
+        Some(core) => core,
+        None => break,
+      };
+      let mut invalidation = Invalidation::default();
+      invalidation.add(&core.vfs, build_root, event);
+      for event in receiver.try_iter() {
+        invalidation.add(&core.vfs, build_root, event);
+      }
+      invalidation.apply(&core, invalidation_globs);
+    }
+    debug!("Filesystem watcher for {:?} exiting.", build_root);
+  }
+}
+
+///
+/// A batch of changes to be applied to the Graph.
+///
+#[derive(Default)]
+struct Invalidation {
+  all_paths: bool,
+  paths: HashSet<PathBuf>,
+  // All changed paths, including ignored paths, to be matched against the InvalidationGlobs.
+  changed: Vec<PathBuf>,
+  // Paths whose FileDigestCache entries are stale, and renames which are aliased in it instead.
+  modified: Vec<PathBuf>,
+  renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Invalidation {
+  fn add(&mut self, vfs: &PosixFS, build_root: &Path, event: WatchEvent) {
+    match event {
+      WatchEvent::Changed(paths) => {
+        for path in paths {
+          if let Ok(relative) = path.strip_prefix(build_root) {
+            self.modified.push(relative.to_owned());
+          }
+          self.add_path(vfs, build_root, &path);
+        }
+      }
+      WatchEvent::Renamed(src, dst) => {
+        // A rename still changes the listings of both parent directories and the nodes for both
+        // paths, but the content digest of the source is aliased to the destination so that it
+        // need not be re-hashed.
+        if let (Ok(src), Ok(dst)) = (src.strip_prefix(build_root), dst.strip_prefix(build_root)) {
+          self.renamed.push((src.to_owned(), dst.to_owned()));
+        }
+        self.add_path(vfs, build_root, &src);
+        self.add_path(vfs, build_root, &dst);
+      }
+      WatchEvent::Rescan => self.all_paths = true,
+    }
+  }
+
+  fn add_path(&mut self, vfs: &PosixFS, build_root: &Path, path: &Path) {
+    let relative = match path.strip_prefix(build_root) {
+      Ok(relative) => relative.to_owned(),
+      Err(_) => return,
+    };
+    // Recorded before ignore patterns are applied, because pants' own config files might well be
+    // ignored as inputs.
+    self.changed.push(relative.clone());
+    // The path might already have been deleted, in which case it is treated as a file.
+    let stat = if path.is_dir() {
+      Stat::Dir(Dir(relative.clone()))
+    } else {
+      Stat::File(File {
+        path: relative.clone(),
+        is_executable: false,
+      })
+    };
+    if vfs.is_ignored(&stat) {
+      return;
+    }
+    // The creation or deletion of a path changes the listing of its parent directory.
+    if let Some(parent) = relative.parent() {
+      self.paths.insert(parent.to_owned());
+    }
+    self.paths.insert(relative);
+  }
+
+  fn apply(self, core: &Core, invalidation_globs: &InvalidationGlobs) {
+    // Entries for modified paths would no longer match anyway, but are forgotten so that the cache
+    // does not grow without bound.
+    for path in &self.modified {
+      core.file_digests.forget(path);
+    }
+    for (src, dst) in &self.renamed {
+      core.file_digests.rename(src, dst);
+    }
+    if invalidation_globs.matches(&self.changed) {
+      warn!("A path that pants depends on has changed: a restart is required.");
+      invalidation_globs
+        .restart_required
+        .store(true, Ordering::SeqCst);
+      core.invalidate_all_paths();
+    } else if self.all_paths {
+      core.invalidate_all_paths();
+    } else if !self.paths.is_empty() {
+      core.invalidate(&self.paths);
+    }
+  }
+}
+
+///
+/// A Watcher which uses the notify crate, and thus the native watch API of the platform.
+///
+struct NotifyWatcher {
+  // Never read: held only to keep the watch alive.
+  _watcher: RecommendedWatcher,
+}
+
+impl NotifyWatcher {
+  fn start(build_root: &Path, events: Sender<WatchEvent>) -> Result<NotifyWatcher, String> {
+    let (sender, receiver) = channel();
+    let mut watcher = notify::watcher(sender, DEBOUNCE_DELAY)
+      .map_err(|e| format!("Failed to create filesystem watcher: {}", e))?;
+    watcher
+      .watch(build_root, RecursiveMode::Recursive)
+      .map_err(|e| format!("Failed to watch build root {:?}: {}", build_root, e))?;
+
+    thread::Builder::new()
+      .name("fs-watcher-notify".to_owned())
+      .spawn(move || {
+        for event in receiver.iter() {
+          let event = match event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Chmod(path)
+            | DebouncedEvent::Remove(path) => WatchEvent::Changed(vec![path]),
+            DebouncedEvent::Rename(src, dst) => WatchEvent::Renamed(src, dst),
+            // Notices are always followed by a debounced event for the same path.
+            DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) => continue,
+            DebouncedEvent::Rescan => WatchEvent::Rescan,
+            DebouncedEvent::Error(err, path) => {
+              warn!(
+                "Filesystem watcher error for {:?}; invalidating all paths: {}",
+                path, err
+              );
+              WatchEvent::Rescan
+            }
+          };
+          if events.send(event).is_err() {
+            break;
+          }
+        }
+      })
+      .map_err(|e| format!("Failed to start filesystem watcher thread: {}", e))?;
+
+    Ok(NotifyWatcher { _watcher: watcher })
+  }
+}
+
+impl Watcher for NotifyWatcher {
+  fn describe(&self) -> String {
+    "notify".to_owned()
+  }
+}
@@ -0,0 +1,191 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use log::{debug, warn};
+use serde_json::{self, json, Value};
+
+use crate::watch::{WatchEvent, Watcher};
+
+const SUBSCRIPTION_NAME: &str = "pants_native_watcher";
+
+///
+/// A Watcher which subscribes to changes from a running watchman daemon via its UNIX socket.
+///
+/// Watchman accepts both its binary BSER encoding and JSON on the same socket (detecting the
+/// encoding of each request), and responds in kind: we speak newline delimited JSON.
+///
+pub struct WatchmanWatcher {
+  socket_path: PathBuf,
+  // A handle to the socket that the subscription thread is reading from, used to shut it down.
+  socket: UnixStream,
+}
+
+impl WatchmanWatcher {
+  pub fn start(
+    socket_path: &Path,
+    build_root: &Path,
+    events: Sender<WatchEvent>,
+  ) -> Result<WatchmanWatcher, String> {
+    let socket = UnixStream::connect(socket_path)
+      .map_err(|e| format!("Failed to connect to watchman at {:?}: {}", socket_path, e))?;
+    let clone_socket = || {
+      socket
+        .try_clone()
+        .map_err(|e| format!("Failed to clone watchman socket: {}", e))
+    };
+    let mut writer = clone_socket()?;
+    let mut reader = BufReader::new(clone_socket()?);
+    let build_root_str = build_root
+      .to_str()
+      .ok_or_else(|| format!("Build root {:?} is not valid UTF-8.", build_root))?;
+
+    // Watch the project containing the build root, which might be a parent directory of it.
+    let watch = request(
+      &mut writer,
+      &mut reader,
+      &json!(["watch-project", build_root_str]),
+    )?;
+    let watch_root = watch
+      .get("watch")
+      .and_then(Value::as_str)
+      .ok_or_else(|| format!("Unexpected watchman watch-project response: {}", watch))?
+      .to_owned();
+    let mut query = json!({ "fields": ["name"] });
+    if let Some(relative_path) = watch.get("relative_path") {
+      query["relative_root"] = relative_path.clone();
+    }
+    request(
+      &mut writer,
+      &mut reader,
+      &json!(["subscribe", watch_root, SUBSCRIPTION_NAME, query]),
+    )?;
+
+    let build_root = build_root.to_owned();
+    thread::Builder::new()
+      .name("fs-watcher-watchman".to_owned())
+      .spawn(move || subscription_loop(&build_root, reader, &events))
+      .map_err(|e| format!("Failed to start watchman subscription thread: {}", e))?;
+
+    Ok(WatchmanWatcher {
+      socket_path: socket_path.to_owned(),
+      socket,
+    })
+  }
+}
+
+impl Watcher for WatchmanWatcher {
+  fn describe(&self) -> String {
+    format!("watchman at {:?}", self.socket_path)
+  }
+}
+
+impl Drop for WatchmanWatcher {
+  fn drop(&mut self) {
+    // Causes the subscription thread to observe EOF and exit.
+    let _ = self.socket.shutdown(Shutdown::Both);
+  }
+}
+
+///
+/// Sends a command, and then returns the next (non-unilateral) response PDU.
+///
+fn request<W: Write, R: BufRead>(
+  writer: &mut W,
+  reader: &mut R,
+  command: &Value,
+) -> Result<Value, String> {
+  let mut line = command.to_string();
+  line.push('\n');
+  writer
+    .write_all(line.as_bytes())
+    .map_err(|e| format!("Failed to send watchman command {}: {}", command, e))?;
+  loop {
+    let pdu = read_pdu(reader)?
+      .ok_or_else(|| format!("Watchman closed the connection in response to {}", command))?;
+    if is_unilateral(&pdu) {
+      continue;
+    }
+    if let Some(error) = pdu.get("error") {
+      return Err(format!("Watchman command {} failed: {}", command, error));
+    }
+    return Ok(pdu);
+  }
+}
+
+fn read_pdu<R: BufRead>(reader: &mut R) -> Result<Option<Value>, String> {
+  let mut line = String::new();
+  let read = reader
+    .read_line(&mut line)
+    .map_err(|e| format!("Failed to read from watchman: {}", e))?;
+  if read == 0 {
+    return Ok(None);
+  }
+  serde_json::from_str(&line)
+    .map(Some)
+    .map_err(|e| format!("Invalid watchman response {:?}: {}", line, e))
+}
+
+fn is_unilateral(pdu: &Value) -> bool {
+  pdu
+    .get("unilateral")
+    .and_then(Value::as_bool)
+    .unwrap_or(false)
+    || pdu.get("subscription").is_some()
+}
+
+fn subscription_loop<R: BufRead>(build_root: &Path, mut reader: R, events: &Sender<WatchEvent>) {
+  // The first PDU for a subscription is a "fresh instance" which lists every file, and so is
+  // skipped. A later fresh instance indicates that watchman lost track of changes (due to a
+  // restart or overflow, for example).
+  let mut is_initial = true;
+  loop {
+    let pdu = match read_pdu(&mut reader) {
+      Ok(Some(pdu)) => pdu,
+      Ok(None) => break,
+      Err(e) => {
+        warn!("Watchman subscription failed: {}", e);
+        break;
+      }
+    };
+    if pdu.get("subscription").is_none() {
+      // Logs and other unilateral messages.
+      continue;
+    }
+    let is_fresh_instance = pdu
+      .get("is_fresh_instance")
+      .and_then(Value::as_bool)
+      .unwrap_or(false);
+    let event = if is_fresh_instance {
+      if is_initial {
+        is_initial = false;
+        continue;
+      }
+      WatchEvent::Rescan
+    } else {
+      is_initial = false;
+      let files = pdu
+        .get("files")
+        .and_then(Value::as_array)
+        .map(|files| {
+          files
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|name| build_root.join(name))
+            .collect()
+        })
+        .unwrap_or_else(Vec::new);
+      WatchEvent::Changed(files)
+    };
+    if events.send(event).is_err() {
+      break;
+    }
+  }
+  debug!("Watchman subscription for {:?} exiting.", build_root);
+}
@@ -27,9 +27,12 @@
 #![allow(clippy::mutex_atomic)]
 
 use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use std::fs::File;
-use std::path::Path;
-use tar::Archive;
+use std::io::{Cursor, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, EntryType};
 
 pub fn decompress_tgz(tar_path: &Path, output_dir: &Path) -> Result<(), std::io::Error> {
   let tar_gz = File::open(tar_path)?;
@@ -39,12 +42,293 @@ pub fn decompress_tgz(tar_path: &Path, output_dir: &Path) -> Result<(), std::io:
   Ok(())
 }
 
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ArchiveFormat {
+  Tar,
+  TarGz,
+  TarZst,
+  Zip,
+}
+
+impl ArchiveFormat {
+  ///
+  /// Guesses the format of an archive from the extension of its file name.
+  ///
+  pub fn from_file_name(file_name: &str) -> Option<ArchiveFormat> {
+    let file_name = file_name.to_lowercase();
+    if file_name.ends_with(".tar") {
+      Some(ArchiveFormat::Tar)
+    } else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+      Some(ArchiveFormat::TarGz)
+    } else if file_name.ends_with(".tar.zst") || file_name.ends_with(".tzst") {
+      Some(ArchiveFormat::TarZst)
+    } else if file_name.ends_with(".zip")
+      || file_name.ends_with(".jar")
+      || file_name.ends_with(".whl")
+    {
+      Some(ArchiveFormat::Zip)
+    } else {
+      None
+    }
+  }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ArchiveEntry {
+  Dir(PathBuf),
+  File {
+    path: PathBuf,
+    is_executable: bool,
+    content: Vec<u8>,
+  },
+}
+
+impl ArchiveEntry {
+  pub fn path(&self) -> &Path {
+    match self {
+      ArchiveEntry::Dir(path) | ArchiveEntry::File { path, .. } => path,
+    }
+  }
+}
+
+///
+/// Reads the entries of an archive in the given format, passing each directory and file to `f`
+/// as it is encountered. The content of each file is read into memory in full before it is passed
+/// to `f`, and is dropped when `f` returns: callers which consume it (rather than collecting it)
+/// thus hold at most one file's content at a time, in addition to whatever `reader` buffers.
+///
+/// Entry paths are normalized to be relative, and entries which would escape the root of the
+/// archive (via absolute paths or `..` components) are an error, as are symlinks and hardlinks.
+///
+pub fn extract_archive<R, F>(format: ArchiveFormat, reader: R, f: F) -> Result<(), String>
+where
+  R: Read + Seek,
+  F: FnMut(ArchiveEntry) -> Result<(), String>,
+{
+  match format {
+    ArchiveFormat::Tar => extract_tar(reader, f),
+    ArchiveFormat::TarGz => extract_tar(GzDecoder::new(reader), f),
+    ArchiveFormat::TarZst => extract_tar(
+      zstd::stream::read::Decoder::new(reader)
+        .map_err(|e| format!("Error initializing zstd decoder: {}", e))?,
+      f,
+    ),
+    ArchiveFormat::Zip => extract_zip(reader, f),
+  }
+}
+
+fn extract_tar<R: Read, F: FnMut(ArchiveEntry) -> Result<(), String>>(
+  reader: R,
+  mut f: F,
+) -> Result<(), String> {
+  let mut archive = Archive::new(reader);
+  let entries = archive
+    .entries()
+    .map_err(|e| format!("Error reading tar entries: {}", e))?;
+  for entry in entries {
+    let mut entry = entry.map_err(|e| format!("Error reading tar entry: {}", e))?;
+    let raw_path = entry
+      .path()
+      .map_err(|e| format!("Error reading tar entry path: {}", e))?
+      .into_owned();
+    let path = match normalize_entry_path(&raw_path)? {
+      Some(path) => path,
+      None => continue,
+    };
+    match entry.header().entry_type() {
+      EntryType::Directory => f(ArchiveEntry::Dir(path))?,
+      EntryType::Regular | EntryType::Continuous => {
+        let mode = entry
+          .header()
+          .mode()
+          .map_err(|e| format!("Error reading mode of tar entry {:?}: {}", raw_path, e))?;
+        let mut content = Vec::new();
+        entry
+          .read_to_end(&mut content)
+          .map_err(|e| format!("Error reading tar entry {:?}: {}", raw_path, e))?;
+        f(ArchiveEntry::File {
+          path,
+          is_executable: mode & 0o111 != 0,
+          content,
+        })?
+      }
+      EntryType::Symlink | EntryType::Link => {
+        return Err(format!(
+          "Links are not supported in archives, but {:?} is a link.",
+          raw_path
+        ));
+      }
+      // Metadata entries (pax headers, etc) have no content of their own.
+      _ => (),
+    }
+  }
+  Ok(())
+}
+
+fn extract_zip<R: Read + Seek, F: FnMut(ArchiveEntry) -> Result<(), String>>(
+  reader: R,
+  mut f: F,
+) -> Result<(), String> {
+  let mut archive =
+    zip::ZipArchive::new(reader).map_err(|e| format!("Error reading zip archive: {}", e))?;
+  for i in 0..archive.len() {
+    let mut file = archive
+      .by_index(i)
+      .map_err(|e| format!("Error reading zip entry {}: {}", i, e))?;
+    let raw_path = PathBuf::from(file.name());
+    let path = match normalize_entry_path(&raw_path)? {
+      Some(path) => path,
+      None => continue,
+    };
+    if file.name().ends_with('/') {
+      f(ArchiveEntry::Dir(path))?;
+    } else {
+      let is_executable = file
+        .unix_mode()
+        .map(|mode| mode & 0o111 != 0)
+        .unwrap_or(false);
+      let mut content = Vec::with_capacity(file.size() as usize);
+      file
+        .read_to_end(&mut content)
+        .map_err(|e| format!("Error reading zip entry {:?}: {}", raw_path, e))?;
+      f(ArchiveEntry::File {
+        path,
+        is_executable,
+        content,
+      })?;
+    }
+  }
+  Ok(())
+}
+
+///
+/// Packs the given entries into an archive of the given format.
+///
+/// The output is deterministic: entries are written in path order, with fixed timestamps,
+/// ownership and permissions, so that equal entries always produce byte-identical archives.
+///
+pub fn create_archive(
+  format: ArchiveFormat,
+  mut entries: Vec<ArchiveEntry>,
+) -> Result<Vec<u8>, String> {
+  entries.sort_by(|a, b| a.path().cmp(b.path()));
+  match format {
+    ArchiveFormat::Tar => create_tar(Vec::new(), &entries),
+    ArchiveFormat::TarGz => {
+      create_tar(GzEncoder::new(Vec::new(), Compression::default()), &entries)?
+        .finish()
+        .map_err(|e| format!("Error compressing tar: {}", e))
+    }
+    ArchiveFormat::TarZst => create_tar(
+      zstd::stream::write::Encoder::new(Vec::new(), 0)
+        .map_err(|e| format!("Error initializing zstd encoder: {}", e))?,
+      &entries,
+    )?
+    .finish()
+    .map_err(|e| format!("Error compressing tar: {}", e)),
+    ArchiveFormat::Zip => create_zip(&entries),
+  }
+}
+
+fn create_tar<W: Write>(writer: W, entries: &[ArchiveEntry]) -> Result<W, String> {
+  let mut builder = tar::Builder::new(writer);
+  for entry in entries {
+    let mut header = tar::Header::new_gnu();
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    let appended = match entry {
+      ArchiveEntry::Dir(path) => {
+        header.set_entry_type(EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_size(0);
+        builder.append_data(&mut header, path, std::io::empty())
+      }
+      ArchiveEntry::File {
+        path,
+        is_executable,
+        content,
+      } => {
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(if *is_executable { 0o755 } else { 0o644 });
+        header.set_size(content.len() as u64);
+        builder.append_data(&mut header, path, &content[..])
+      }
+    };
+    appended.map_err(|e| format!("Error adding {:?} to tar: {}", entry.path(), e))?;
+  }
+  builder
+    .into_inner()
+    .map_err(|e| format!("Error finishing tar: {}", e))
+}
+
+fn create_zip(entries: &[ArchiveEntry]) -> Result<Vec<u8>, String> {
+  let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+  let options = zip::write::FileOptions::default()
+    .compression_method(zip::CompressionMethod::Deflated)
+    .last_modified_time(zip::DateTime::default());
+  for entry in entries {
+    let name = entry
+      .path()
+      .to_str()
+      .ok_or_else(|| format!("Archive entry {:?} is not valid UTF-8.", entry.path()))?;
+    let added = match entry {
+      ArchiveEntry::Dir(_) => writer.add_directory(name, options.unix_permissions(0o755)),
+      ArchiveEntry::File {
+        is_executable,
+        content,
+        ..
+      } => writer
+        .start_file(
+          name,
+          options.unix_permissions(if *is_executable { 0o755 } else { 0o644 }),
+        )
+        .and_then(|()| {
+          writer
+            .write_all(content)
+            .map_err(zip::result::ZipError::from)
+        }),
+    };
+    added.map_err(|e| format!("Error adding {:?} to zip: {}", entry.path(), e))?;
+  }
+  writer
+    .finish()
+    .map(Cursor::into_inner)
+    .map_err(|e| format!("Error finishing zip: {}", e))
+}
+
+///
+/// Strips `.` components from an archive entry path, and errors for paths which would escape the
+/// archive root. Returns None for paths which refer to the root itself.
+///
+fn normalize_entry_path(path: &Path) -> Result<Option<PathBuf>, String> {
+  let mut normalized = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::Normal(name) => normalized.push(name),
+      Component::CurDir => (),
+      Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+        return Err(format!(
+          "Archive entry {:?} would be extracted outside of the archive root.",
+          path
+        ));
+      }
+    }
+  }
+  if normalized.as_os_str().is_empty() {
+    Ok(None)
+  } else {
+    Ok(Some(normalized))
+  }
+}
+
 #[cfg(test)]
 pub mod tar_tests {
-  use super::decompress_tgz;
+  use super::{create_archive, decompress_tgz, extract_archive, ArchiveEntry, ArchiveFormat};
   use flate2::write::GzEncoder;
   use flate2::Compression;
   use std::fs::File;
+  use std::io::{Cursor, Write};
   use std::path::{Path, PathBuf};
   use tempfile::TempDir;
   use testutil::file::contents;
@@ -96,6 +380,145 @@ pub mod tar_tests {
     assert!(result.is_err())
   }
 
+  #[test]
+  fn archive_format_from_file_name() {
+    assert_eq!(
+      ArchiveFormat::from_file_name("jdk.tar.gz"),
+      Some(ArchiveFormat::TarGz)
+    );
+    assert_eq!(
+      ArchiveFormat::from_file_name("protoc.ZIP"),
+      Some(ArchiveFormat::Zip)
+    );
+    assert_eq!(
+      ArchiveFormat::from_file_name("node.tar.zst"),
+      Some(ArchiveFormat::TarZst)
+    );
+    assert_eq!(ArchiveFormat::from_file_name("README"), None);
+  }
+
+  #[test]
+  fn extract_tgz_entries() {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(11);
+    header.set_mode(0o755);
+    header.set_cksum();
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    builder
+      .append_data(&mut header, "./bin/hello", &b"hello world"[..])
+      .unwrap();
+    let tgz = builder.into_inner().unwrap().finish().unwrap();
+
+    assert_eq!(
+      extract_all(ArchiveFormat::TarGz, tgz),
+      Ok(vec![ArchiveEntry::File {
+        path: PathBuf::from("bin/hello"),
+        is_executable: true,
+        content: b"hello world".to_vec(),
+      }])
+    );
+  }
+
+  #[test]
+  fn extract_zip_entries() {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    writer
+      .add_directory("a/", zip::write::FileOptions::default())
+      .unwrap();
+    writer
+      .start_file(
+        "a/b.txt",
+        zip::write::FileOptions::default().unix_permissions(0o644),
+      )
+      .unwrap();
+    writer.write_all(b"zipped").unwrap();
+    let zip = writer.finish().unwrap().into_inner();
+
+    assert_eq!(
+      extract_all(ArchiveFormat::Zip, zip),
+      Ok(vec![
+        ArchiveEntry::Dir(PathBuf::from("a")),
+        ArchiveEntry::File {
+          path: PathBuf::from("a/b.txt"),
+          is_executable: false,
+          content: b"zipped".to_vec(),
+        },
+      ])
+    );
+  }
+
+  #[test]
+  fn extract_rejects_parent_components() {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    writer
+      .start_file("../escaped.txt", zip::write::FileOptions::default())
+      .unwrap();
+    let zip = writer.finish().unwrap().into_inner();
+
+    let err = extract_all(ArchiveFormat::Zip, zip).expect_err("Want error");
+    assert!(err.contains("outside of the archive root"), "{}", err);
+  }
+
+  #[test]
+  fn create_archive_roundtrips() {
+    let entries = vec![
+      ArchiveEntry::Dir(PathBuf::from("bin")),
+      ArchiveEntry::File {
+        path: PathBuf::from("bin/tool"),
+        is_executable: true,
+        content: b"#!/bin/sh".to_vec(),
+      },
+      ArchiveEntry::File {
+        path: PathBuf::from("README"),
+        is_executable: false,
+        content: b"Read me".to_vec(),
+      },
+    ];
+    let mut sorted_entries = entries.clone();
+    sorted_entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+    for format in &[
+      ArchiveFormat::Tar,
+      ArchiveFormat::TarGz,
+      ArchiveFormat::TarZst,
+      ArchiveFormat::Zip,
+    ] {
+      let archive = create_archive(*format, entries.clone()).unwrap();
+      assert_eq!(
+        extract_all(*format, archive),
+        Ok(sorted_entries.clone()),
+        "{:?}",
+        format
+      );
+    }
+  }
+
+  #[test]
+  fn create_archive_is_deterministic() {
+    let file = |name: &str| ArchiveEntry::File {
+      path: PathBuf::from(name),
+      is_executable: false,
+      content: name.as_bytes().to_vec(),
+    };
+    for format in &[ArchiveFormat::TarGz, ArchiveFormat::Zip] {
+      assert_eq!(
+        create_archive(*format, vec![file("a"), file("b")]),
+        create_archive(*format, vec![file("b"), file("a")]),
+        "{:?}",
+        format
+      );
+    }
+  }
+
+  fn extract_all(format: ArchiveFormat, archive: Vec<u8>) -> Result<Vec<ArchiveEntry>, String> {
+    let mut entries = Vec::new();
+    extract_archive(format, Cursor::new(archive), |entry| {
+      entries.push(entry);
+      Ok(())
+    })?;
+    Ok(entries)
+  }
+
   fn compress(
     txt_full_path: &Path,
     path_in_tar: &str,
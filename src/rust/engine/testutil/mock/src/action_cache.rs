@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bazel_protos;
+use grpcio;
+
+use hashing::{Digest, Fingerprint};
+use parking_lot::Mutex;
+
+use crate::faults::{FaultInjector, Faults};
+
+///
+/// Implements the ActionCache gRPC API, answering lookups with the ActionResults which have been
+/// inserted (or updated by clients), and NotFound for any other Action.
+///
+pub struct StubActionCache {
+  server_transport: grpcio::Server,
+  faults: FaultInjector,
+  pub action_map: Arc<Mutex<HashMap<Fingerprint, bazel_protos::remote_execution::ActionResult>>>,
+}
+
+impl StubActionCache {
+  pub fn empty() -> StubActionCache {
+    StubActionCache::with_faults(Faults::default())
+  }
+
+  ///
+  /// Creates an empty ActionCache which injects the given Faults into every request.
+  ///
+  pub fn with_faults(faults: Faults) -> StubActionCache {
+    let env = Arc::new(grpcio::Environment::new(1));
+    let action_map = Arc::new(Mutex::new(HashMap::new()));
+    let faults = FaultInjector::new(faults);
+    let responder = StubActionCacheResponder {
+      action_map: action_map.clone(),
+      faults: faults.clone(),
+    };
+    let mut server_transport = grpcio::ServerBuilder::new(env)
+      .register_service(bazel_protos::remote_execution_grpc::create_action_cache(
+        responder,
+      ))
+      .bind("localhost", 0)
+      .build()
+      .unwrap();
+    server_transport.start();
+
+    StubActionCache {
+      server_transport,
+      faults,
+      action_map,
+    }
+  }
+
+  ///
+  /// The address on which this server is listening over insecure HTTP transport.
+  ///
+  pub fn address(&self) -> String {
+    let bind_addr = self.server_transport.bind_addrs().first().unwrap();
+    format!("{}:{}", bind_addr.0, bind_addr.1)
+  }
+
+  pub fn insert(
+    &self,
+    action_digest: Digest,
+    action_result: bazel_protos::remote_execution::ActionResult,
+  ) {
+    self
+      .action_map
+      .lock()
+      .insert(action_digest.0, action_result);
+  }
+
+  ///
+  /// The number of requests which have been received, including those which failed.
+  ///
+  pub fn request_count(&self) -> usize {
+    self.faults.request_count()
+  }
+}
+
+#[derive(Clone, Debug)]
+struct StubActionCacheResponder {
+  action_map: Arc<Mutex<HashMap<Fingerprint, bazel_protos::remote_execution::ActionResult>>>,
+  faults: FaultInjector,
+}
+
+impl StubActionCacheResponder {
+  fn action_fingerprint(
+    digest: &bazel_protos::remote_execution::Digest,
+  ) -> Result<Fingerprint, grpcio::RpcStatus> {
+    let digest: Result<Digest, String> = digest.into();
+    digest.map(|digest| digest.0).map_err(|e| {
+      grpcio::RpcStatus::new(
+        grpcio::RpcStatusCode::InvalidArgument,
+        Some(format!("Bad action digest: {}", e)),
+      )
+    })
+  }
+
+  fn get_action_result_internal(
+    &self,
+    req: &bazel_protos::remote_execution::GetActionResultRequest,
+  ) -> Result<bazel_protos::remote_execution::ActionResult, grpcio::RpcStatus> {
+    self.faults.before_request()?;
+    let fingerprint = Self::action_fingerprint(req.get_action_digest())?;
+    let mut action_map = self.action_map.lock();
+    let result = action_map.get(&fingerprint).cloned();
+    if self.faults.faults.gc_between_calls {
+      action_map.clear();
+    }
+    result.ok_or_else(|| {
+      grpcio::RpcStatus::new(
+        grpcio::RpcStatusCode::NotFound,
+        Some(format!("No ActionResult for {}", fingerprint)),
+      )
+    })
+  }
+
+  fn update_action_result_internal(
+    &self,
+    req: &bazel_protos::remote_execution::UpdateActionResultRequest,
+  ) -> Result<bazel_protos::remote_execution::ActionResult, grpcio::RpcStatus> {
+    self.faults.before_request()?;
+    let fingerprint = Self::action_fingerprint(req.get_action_digest())?;
+    let mut action_map = self.action_map.lock();
+    if !self.faults.faults.gc_between_calls {
+      action_map.insert(fingerprint, req.get_action_result().clone());
+    }
+    Ok(req.get_action_result().clone())
+  }
+}
+
+impl bazel_protos::remote_execution_grpc::ActionCache for StubActionCacheResponder {
+  fn get_action_result(
+    &self,
+    _ctx: grpcio::RpcContext<'_>,
+    req: bazel_protos::remote_execution::GetActionResultRequest,
+    sink: grpcio::UnarySink<bazel_protos::remote_execution::ActionResult>,
+  ) {
+    match self.get_action_result_internal(&req) {
+      Ok(action_result) => sink.success(action_result),
+      Err(status) => sink.fail(status),
+    };
+  }
+
+  fn update_action_result(
+    &self,
+    _ctx: grpcio::RpcContext<'_>,
+    req: bazel_protos::remote_execution::UpdateActionResultRequest,
+    sink: grpcio::UnarySink<bazel_protos::remote_execution::ActionResult>,
+  ) {
+    match self.update_action_result_internal(&req) {
+      Ok(action_result) => sink.success(action_result),
+      Err(status) => sink.fail(status),
+    };
+  }
+}
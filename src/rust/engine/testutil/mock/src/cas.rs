@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
 
 use bazel_protos;
 use futures;
@@ -11,6 +13,8 @@ use hashing::{Digest, Fingerprint};
 use parking_lot::Mutex;
 use testutil::data::{TestData, TestDirectory};
 
+use crate::faults::{FaultInjector, Faults};
+
 ///
 /// Implements the ContentAddressableStorage gRPC API, answering read requests with either known
 /// content, NotFound for valid but unknown content, or InvalidArguments for bad arguments.
@@ -18,6 +22,7 @@ use testutil::data::{TestData, TestDirectory};
 pub struct StubCAS {
   server_transport: grpcio::Server,
   read_request_count: Arc<Mutex<usize>>,
+  faults: FaultInjector,
   pub write_message_sizes: Arc<Mutex<Vec<usize>>>,
   pub blobs: Arc<Mutex<HashMap<Fingerprint, Bytes>>>,
 }
@@ -29,6 +34,8 @@ pub struct StubCASBuilder {
   port: Option<u16>,
   instance_name: Option<String>,
   required_auth_token: Option<String>,
+  read_delay: Option<Duration>,
+  faults: Option<Faults>,
 }
 
 impl StubCASBuilder {
@@ -40,6 +47,8 @@ impl StubCASBuilder {
       port: None,
       instance_name: None,
       required_auth_token: None,
+      read_delay: None,
+      faults: None,
     }
   }
 }
@@ -99,6 +108,28 @@ impl StubCASBuilder {
     self
   }
 
+  ///
+  /// Delays the response to each read request by the given duration.
+  ///
+  pub fn read_delay(mut self, read_delay: Duration) -> Self {
+    if self.read_delay.is_some() {
+      panic!("Can't set read_delay twice");
+    }
+    self.read_delay = Some(read_delay);
+    self
+  }
+
+  ///
+  /// Injects the given Faults into every request.
+  ///
+  pub fn faults(mut self, faults: Faults) -> Self {
+    if self.faults.is_some() {
+      panic!("Can't set faults twice");
+    }
+    self.faults = Some(faults);
+    self
+  }
+
   pub fn build(self) -> StubCAS {
     StubCAS::new(
       self.chunk_size_bytes.unwrap_or(1024),
@@ -107,6 +138,8 @@ impl StubCASBuilder {
       self.always_errors,
       self.instance_name,
       self.required_auth_token,
+      self.read_delay,
+      self.faults.unwrap_or_default(),
     )
   }
 }
@@ -125,6 +158,8 @@ impl StubCAS {
   /// * `blobs`            - Known Fingerprints and their content responses. These are not checked
   ///                        for correctness.
   /// * `port`             - The port for the CAS to listen to.
+  /// * `read_delay`       - A delay before responding to each read request.
+  /// * `faults`           - Failures to inject into every request.
   fn new(
     chunk_size_bytes: usize,
     blobs: HashMap<Fingerprint, Bytes>,
@@ -132,11 +167,14 @@ impl StubCAS {
     always_errors: bool,
     instance_name: Option<String>,
     required_auth_token: Option<String>,
+    read_delay: Option<Duration>,
+    faults: Faults,
   ) -> StubCAS {
     let env = Arc::new(grpcio::Environment::new(1));
     let read_request_count = Arc::new(Mutex::new(0));
     let write_message_sizes = Arc::new(Mutex::new(Vec::new()));
     let blobs = Arc::new(Mutex::new(blobs));
+    let faults = FaultInjector::new(faults);
     let responder = StubCASResponder {
       chunk_size_bytes: chunk_size_bytes,
       instance_name: instance_name,
@@ -145,6 +183,8 @@ impl StubCAS {
       read_request_count: read_request_count.clone(),
       write_message_sizes: write_message_sizes.clone(),
       required_auth_header: required_auth_token.map(|t| format!("Bearer {}", t)),
+      read_delay: read_delay,
+      faults: faults.clone(),
     };
     let mut server_transport = grpcio::ServerBuilder::new(env)
       .register_service(bazel_protos::bytestream_grpc::create_byte_stream(
@@ -161,6 +201,7 @@ impl StubCAS {
     StubCAS {
       server_transport,
       read_request_count,
+      faults,
       write_message_sizes,
       blobs,
     }
@@ -185,6 +226,20 @@ impl StubCAS {
   pub fn read_request_count(&self) -> usize {
     *self.read_request_count.lock()
   }
+
+  ///
+  /// The number of requests of any kind which have been received, including those which failed.
+  ///
+  pub fn request_count(&self) -> usize {
+    self.faults.request_count()
+  }
+
+  ///
+  /// Evicts all blobs, as a garbage collection of the CAS would.
+  ///
+  pub fn gc(&self) {
+    self.blobs.lock().clear();
+  }
 }
 
 #[derive(Clone, Debug)]
@@ -194,10 +249,21 @@ pub struct StubCASResponder {
   blobs: Arc<Mutex<HashMap<Fingerprint, Bytes>>>,
   always_errors: bool,
   required_auth_header: Option<String>,
+  read_delay: Option<Duration>,
+  faults: FaultInjector,
   pub read_request_count: Arc<Mutex<usize>>,
   pub write_message_sizes: Arc<Mutex<Vec<usize>>>,
 }
 
+macro_rules! inject_faults {
+  ($self:ident, $sink:ident) => {
+    if let Err(status) = $self.faults.before_request() {
+      $sink.fail(status);
+      return;
+    }
+  };
+}
+
 macro_rules! check_auth {
   ($self:ident, $ctx:ident, $sink:ident) => {
     if let Some(ref required_auth_header) = $self.required_auth_header {
@@ -229,6 +295,12 @@ impl StubCASResponder {
     self.instance_name.clone().unwrap_or_default()
   }
 
+  fn gc_if_between_calls(&self) {
+    if self.faults.faults.gc_between_calls {
+      self.blobs.lock().clear();
+    }
+  }
+
   fn read_internal(
     &self,
     req: &bazel_protos::bytestream::ReadRequest,
@@ -308,8 +380,15 @@ impl bazel_protos::bytestream_grpc::ByteStream for StubCASResponder {
       *request_count += 1;
     }
     check_auth!(self, ctx, sink);
+    inject_faults!(self, sink);
+
+    if let Some(read_delay) = self.read_delay {
+      sleep(read_delay);
+    }
 
-    match self.read_internal(&req) {
+    let result = self.read_internal(&req);
+    self.gc_if_between_calls();
+    match result {
       Ok(response) => self.send(
         &ctx,
         sink,
@@ -332,8 +411,11 @@ impl bazel_protos::bytestream_grpc::ByteStream for StubCASResponder {
     sink: grpcio::ClientStreamingSink<bazel_protos::bytestream::WriteResponse>,
   ) {
     check_auth!(self, ctx, sink);
+    inject_faults!(self, sink);
 
     let always_errors = self.always_errors;
+    let partial_write_bytes = self.faults.faults.partial_write_bytes;
+    let gc_between_calls = self.faults.faults.gc_between_calls;
     let write_message_sizes = self.write_message_sizes.clone();
     let blobs = self.blobs.clone();
     let instance_name = self.instance_name();
@@ -438,9 +520,20 @@ impl bazel_protos::bytestream_grpc::ByteStream for StubCASResponder {
                 ));
               }
 
+              if let Some(partial_write_bytes) = partial_write_bytes {
+                if partial_write_bytes < size {
+                  let mut response = bazel_protos::bytestream::WriteResponse::new();
+                  response.set_committed_size(partial_write_bytes as i64);
+                  return Ok(response);
+                }
+              }
+
               {
                 let mut blobs = blobs.lock();
                 blobs.insert(fingerprint, bytes);
+                if gc_between_calls {
+                  blobs.clear();
+                }
               }
 
               let mut response = bazel_protos::bytestream::WriteResponse::new();
@@ -478,6 +571,7 @@ impl bazel_protos::remote_execution_grpc::ContentAddressableStorage for StubCASR
     sink: grpcio::UnarySink<bazel_protos::remote_execution::FindMissingBlobsResponse>,
   ) {
     check_auth!(self, ctx, sink);
+    inject_faults!(self, sink);
 
     if self.always_errors {
       sink.fail(grpcio::RpcStatus::new(
@@ -497,15 +591,18 @@ impl bazel_protos::remote_execution_grpc::ContentAddressableStorage for StubCASR
       ));
       return;
     }
-    let blobs = self.blobs.lock();
     let mut response = bazel_protos::remote_execution::FindMissingBlobsResponse::new();
-    for digest in req.get_blob_digests() {
-      let hashing_digest_result: Result<Digest, String> = digest.into();
-      let hashing_digest = hashing_digest_result.expect("Bad digest");
-      if !blobs.contains_key(&hashing_digest.0) {
-        response.mut_missing_blob_digests().push(digest.clone())
+    {
+      let blobs = self.blobs.lock();
+      for digest in req.get_blob_digests() {
+        let hashing_digest_result: Result<Digest, String> = digest.into();
+        let hashing_digest = hashing_digest_result.expect("Bad digest");
+        if !blobs.contains_key(&hashing_digest.0) {
+          response.mut_missing_blob_digests().push(digest.clone())
+        }
       }
     }
+    self.gc_if_between_calls();
     sink.success(response);
   }
 
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use grpcio;
+use parking_lot::Mutex;
+
+///
+/// Failures which a stub server injects into the requests that it receives, so that clients can be
+/// tested against the failure modes of real servers.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+  // A delay before each request is answered.
+  pub latency: Option<Duration>,
+  // The fraction of requests which fail as `Unavailable`. Failures are spread evenly over requests
+  // rather than randomly, so that tests are deterministic: at a rate of 0.5, the first request and
+  // every second request after it fail.
+  pub error_rate: f64,
+  // If set, each write commits only (up to) this many bytes, and the blob is not stored, as if the
+  // server lost the rest of the upload.
+  pub partial_write_bytes: Option<usize>,
+  // If true, everything which the server has stored is evicted after each request is answered, as
+  // if a garbage collection had run between calls.
+  pub gc_between_calls: bool,
+}
+
+///
+/// Applies Faults to the requests received by a stub server.
+///
+#[derive(Clone, Debug)]
+pub struct FaultInjector {
+  pub faults: Faults,
+  request_count: Arc<Mutex<usize>>,
+}
+
+impl FaultInjector {
+  pub fn new(faults: Faults) -> FaultInjector {
+    FaultInjector {
+      faults,
+      request_count: Arc::new(Mutex::new(0)),
+    }
+  }
+
+  ///
+  /// Called before a request is answered: waits for the latency (if any), and then returns an
+  /// error if the request should fail.
+  ///
+  pub fn before_request(&self) -> Result<(), grpcio::RpcStatus> {
+    let request_number = {
+      let mut request_count = self.request_count.lock();
+      *request_count += 1;
+      *request_count
+    };
+    if let Some(latency) = self.faults.latency {
+      sleep(latency);
+    }
+    let failures_through = |n: usize| (n as f64 * self.faults.error_rate).ceil() as usize;
+    if failures_through(request_number) > failures_through(request_number - 1) {
+      Err(grpcio::RpcStatus::new(
+        grpcio::RpcStatusCode::Unavailable,
+        Some(format!(
+          "Injected failure of request {} (error rate {})",
+          request_number, self.faults.error_rate
+        )),
+      ))
+    } else {
+      Ok(())
+    }
+  }
+
+  ///
+  /// The number of requests which have been received, including those which failed.
+  ///
+  pub fn request_count(&self) -> usize {
+    *self.request_count.lock()
+  }
+}
@@ -26,6 +26,10 @@
 // Arc<Mutex> can be more clear than needing to grok Orderings:
 #![allow(clippy::mutex_atomic)]
 
+mod action_cache;
+pub use crate::action_cache::StubActionCache;
 mod cas;
 pub use crate::cas::StubCAS;
 pub mod execution_server;
+mod faults;
+pub use crate::faults::{FaultInjector, Faults};
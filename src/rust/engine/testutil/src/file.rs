@@ -1,7 +1,6 @@
 use bytes;
 use std;
 use std::io::Read;
-use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
 pub fn list_dir(path: &Path) -> Vec<String> {
@@ -27,7 +26,9 @@ pub fn contents(path: &Path) -> bytes::Bytes {
   bytes::Bytes::from(contents)
 }
 
+#[cfg(unix)]
 pub fn is_executable(path: &Path) -> bool {
+  use std::os::unix::fs::PermissionsExt;
   std::fs::metadata(path)
     .expect("Getting file metadata")
     .permissions()
@@ -28,7 +28,6 @@
 
 use bytes::Bytes;
 use std::io::Write;
-use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
 pub mod data;
@@ -46,10 +45,23 @@ pub fn as_bytes(str: &str) -> Bytes {
   Bytes::from(str.as_bytes())
 }
 
+///
+/// Creates a file with the given contents and (on unix) mode. Windows has no mode bits, so the
+/// mode is ignored there.
+///
 pub fn make_file(path: &Path, contents: &[u8], mode: u32) {
   let mut file = std::fs::File::create(&path).unwrap();
   file.write_all(contents).unwrap();
+  set_mode(&file, path, mode);
+}
+
+#[cfg(unix)]
+fn set_mode(file: &std::fs::File, path: &Path, mode: u32) {
+  use std::os::unix::fs::PermissionsExt;
   let mut permissions = std::fs::metadata(path).unwrap().permissions();
   permissions.set_mode(mode);
   file.set_permissions(permissions).unwrap();
 }
+
+#[cfg(windows)]
+fn set_mode(_file: &std::fs::File, _path: &Path, _mode: u32) {}
@@ -52,7 +52,12 @@ pub struct EngineDisplay {
   padding: String,
   terminal: Console,
   action_map: BTreeMap<String, String>,
+  // An optional summary of the progress of the run, rendered below the actions.
+  progress: Option<String>,
   logs: VecDeque<String>,
+  // If set, a copy of the output of the display which outlives its rendering: its log entries, and
+  // the final summary of the progress of the run.
+  tee: Option<Box<dyn Write + Send>>,
   running: bool,
   cursor_start: (u16, u16),
   terminal_size: (u16, u16),
@@ -95,10 +100,12 @@ impl EngineDisplay {
         Err(_) => Console::Pipe(stdout()),
       },
       action_map: BTreeMap::new(),
+      progress: None,
       // This is arbitrary based on a guesstimated peak terminal row size for modern displays.
       // The reason this can't be capped to e.g. the starting size is because of resizing - we
       // want to be able to fill the entire screen if resized much larger than when we started.
       logs: VecDeque::with_capacity(500),
+      tee: None,
       running: false,
       // N.B. This will cause the screen to clear - but with some improved position
       // tracking logic we could avoid screen clearing in favor of using the value
@@ -159,7 +166,12 @@ impl EngineDisplay {
   fn get_max_log_rows(&self) -> usize {
     // TODO: If the terminal size is smaller than the action map, we should fall back
     // to non-tty mode output to avoid.
-    self.terminal_size.1 as usize - self.action_map.len() - 1
+    self.terminal_size.1 as usize - self.rendered_action_rows() - 1
+  }
+
+  // The number of rows below the logs: one per action, plus the progress summary (if any).
+  fn rendered_action_rows(&self) -> usize {
+    self.action_map.len() + self.progress.iter().count()
   }
 
   // Prep the screen for painting by clearing it from the cursor start position.
@@ -274,6 +286,28 @@ impl EngineDisplay {
         ))
         .expect("could not write to terminal");
     }
+
+    if let Some(progress) = self.progress.clone() {
+      let line_shortened_output: String = format!(
+        "{padding}{progress}",
+        padding = self.padding,
+        progress = progress
+      )
+      .graphemes(true)
+      .take(self.terminal_size.0 as usize)
+      .collect();
+
+      self
+        .write(&format!(
+          "{pos}{entry}",
+          pos = cursor::Goto(
+            1,
+            cursor_start.1 + start_row as u16 + worker_states.len() as u16
+          ),
+          entry = line_shortened_output
+        ))
+        .expect("could not write to terminal");
+    }
   }
 
   // Paints one screen of rendering.
@@ -323,13 +357,31 @@ impl EngineDisplay {
     self.action_map.insert(worker_name, action);
   }
 
+  // Sets (or clears) the summary of the progress of the run.
+  pub fn set_progress(&mut self, progress: Option<String>) {
+    self.progress = progress;
+  }
+
   // Removes a worker/thread from the visual representation.
   pub fn remove_worker(&mut self, worker_id: &str) {
     self.action_map.remove(worker_id);
   }
 
+  // Copies the log entries and final progress of the display to the given writer.
+  pub fn tee_to(&mut self, tee: Box<dyn Write + Send>) {
+    self.tee = Some(tee);
+  }
+
+  // Writes a line to the tee, if any. Failures are ignored, since they should not affect rendering.
+  fn write_to_tee(&mut self, line: &str) {
+    if let Some(ref mut tee) = self.tee {
+      let _ = writeln!(tee, "{}", line).and_then(|()| tee.flush());
+    }
+  }
+
   // Adds a log entry for display.
   pub fn log(&mut self, log_entry: String) {
+    self.write_to_tee(&log_entry);
     self.logs.push_front(log_entry)
   }
 
@@ -340,8 +392,11 @@ impl EngineDisplay {
   // Terminates the EngineDisplay and returns the cursor to a static position.
   pub fn finish(&mut self) {
     self.running = false;
+    if let Some(progress) = self.progress.clone() {
+      self.write_to_tee(&progress);
+    }
     let current_pos = self.get_cursor_pos();
-    let action_count = self.action_map.len() as u16;
+    let action_count = self.rendered_action_rows() as u16;
     self
       .write(&format!(
         "{park_cursor}{clear_after_cursor}{reveal_cursor}",
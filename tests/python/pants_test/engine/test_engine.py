@@ -4,13 +4,17 @@
 
 from __future__ import absolute_import, division, print_function, unicode_literals
 
+import json
+import os
 import unittest
 from builtins import object, str
 from textwrap import dedent
 
+from pants.engine.nodes import Return, Throw
 from pants.engine.rules import RootRule, rule
 from pants.engine.scheduler import ExecutionError
-from pants.engine.selectors import Get
+from pants.engine.selectors import Get, GetAll
+from pants.util.contextutil import temporary_dir
 from pants.util.objects import datatype
 from pants_test.engine.scheduler_test_base import SchedulerTestBase
 from pants_test.engine.util import assert_equal_with_printing, remove_locations_from_traceback
@@ -63,6 +67,25 @@ def upcast(n):
   yield MyFloat(float(n.val))
 
 
+class Total(datatype([('val', float)])): pass
+
+
+@rule(Total, [int])
+def total_of_upcasts(n):
+  floats = yield GetAll(MyFloat, MyInt, [MyInt(i) for i in range(n)])
+  yield Total(sum(f.val for f in floats))
+
+
+class Even(datatype([('val', int)])): pass
+
+
+@rule(Even, [int])
+def even(n):
+  if n % 2:
+    raise ValueError('{} is odd'.format(n))
+  return Even(n)
+
+
 class EngineTest(unittest.TestCase, SchedulerTestBase):
 
   assert_equal_with_printing = assert_equal_with_printing
@@ -81,6 +104,49 @@ class EngineTest(unittest.TestCase, SchedulerTestBase):
 
     self.assertEqual(55, fib_10.val)
 
+  def test_get_all(self):
+    rules = [
+      total_of_upcasts,
+      upcast,
+      RootRule(int),
+    ]
+
+    total, = self.mk_scheduler(rules=rules).product_request(Total, subjects=[5])
+
+    self.assertEqual(10.0, total.val)
+
+  def test_partial_product_request(self):
+    rules = [
+      even,
+      RootRule(int),
+    ]
+
+    one, two, three = self.mk_scheduler(rules=rules).partial_product_request(Even, [1, 2, 3])
+
+    self.assertEqual(Return(Even(2)), two)
+    for failure, n in ((one, 1), (three, 3)):
+      self.assertIsInstance(failure, Throw)
+      self.assertEqual('{} is odd'.format(n), str(failure.exc))
+
+  def test_run_report(self):
+    rules = [
+      fib,
+      RootRule(int),
+    ]
+    scheduler = self.mk_scheduler(rules=rules)
+    scheduler.product_request(Fib, subjects=[10])
+
+    with temporary_dir() as tmpdir:
+      path = os.path.join(tmpdir, 'run_report.json')
+      scheduler.write_run_report(path)
+      with open(path, 'r') as f:
+        report = json.load(f)
+
+    self.assertEqual(['success'], [root['status'] for root in report['roots']])
+    self.assertEqual(0, report['processes_executed'])
+    self.assertIn('remote_execution_cache_hits', report['metrics'])
+    self.assertEqual([], report['slowest_workunits'])
+
   def test_no_include_trace_error_raises_boring_error(self):
     rules = [
       RootRule(B),
@@ -123,20 +189,55 @@ class EngineTest(unittest.TestCase, SchedulerTestBase):
 
     self.assert_equal_with_printing(dedent('''
       1 Exception encountered:
-      Computing Select(<pants_test.engine.test_engine.B object at 0xEEEEEEEEE>, A)
-        Computing Task(nested_raise(), <pants_test.engine.test_engine.B object at 0xEEEEEEEEE>, A, true)
-          Throw(An exception for B)
-            Traceback (most recent call last):
-              File LOCATION-INFO, in call
-                val = func(*args)
-              File LOCATION-INFO, in nested_raise
-                fn_raises(x)
-              File LOCATION-INFO, in fn_raises
-                raise Exception('An exception for {}'.format(type(x).__name__))
-            Exception: An exception for B
-      ''').lstrip()+'\n',
+
+      Engine traceback:
+        in nested_raise() for <pants_test.engine.test_engine.B object at 0xEEEEEEEEE>
+      Traceback (most recent call last):
+        File LOCATION-INFO, in call
+          val = func(*args)
+        File LOCATION-INFO, in nested_raise
+          fn_raises(x)
+        File LOCATION-INFO, in fn_raises
+          raise Exception('An exception for {}'.format(type(x).__name__))
+      Exception: An exception for B
+      ''').lstrip(),
       remove_locations_from_traceback(str(cm.exception)))
 
+  def test_include_trace_error_includes_rule_chain(self):
+    @rule(C, [B])
+    def c_from_b_nested_raise(b):
+      fn_raises(b)
+
+    @rule(A, [C])
+    def a_from_c(c):
+      return A()
+
+    @rule(D, [B])
+    def d_from_b_get(b):
+      _ = yield Get(A, B, b) # noqa: F841
+      yield D()
+
+    rules = [
+      RootRule(B),
+      c_from_b_nested_raise,
+      a_from_c,
+      d_from_b_get,
+    ]
+
+    scheduler = self.scheduler(rules, include_trace_on_error=True)
+    with self.assertRaises(ExecutionError) as cm:
+      list(scheduler.product_request(D, subjects=[(B())]))
+
+    exc_str = remove_locations_from_traceback(str(cm.exception))
+    self.assertIn(dedent('''
+      Engine traceback:
+        in d_from_b_get() for <pants_test.engine.test_engine.B object at 0xEEEEEEEEE>
+        in a_from_c() for <pants_test.engine.test_engine.B object at 0xEEEEEEEEE>
+        in c_from_b_nested_raise() for <pants_test.engine.test_engine.B object at 0xEEEEEEEEE>
+      Traceback (most recent call last):
+      ''').lstrip(), exc_str)
+    self.assertTrue(exc_str.endswith('Exception: An exception for B\n'), exc_str)
+
   def test_fork_context(self):
     # A smoketest that confirms that we can successfully enter and exit the fork context, which
     # implies acquiring and releasing all relevant Engine resources.
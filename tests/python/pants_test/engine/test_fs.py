@@ -7,21 +7,26 @@ from __future__ import absolute_import, division, print_function, unicode_litera
 import hashlib
 import logging
 import os
+import socket
 import tarfile
+import time
 import unittest
+import zipfile
 from builtins import open, str
 from contextlib import contextmanager
 
 from future.utils import PY2, text_type
 
-from pants.engine.fs import (EMPTY_DIRECTORY_DIGEST, Digest, DirectoriesToMerge,
-                             DirectoryToMaterialize, DirectoryWithPrefixToStrip, FilesContent,
-                             PathGlobs, PathGlobsAndRoot, Snapshot, UrlToFetch, create_fs_rules)
+from pants.engine.fs import (EMPTY_DIRECTORY_DIGEST, ArchiveToExtract, Digest, DirectoriesToMerge,
+                             DirectoryToArchive, DirectoryToMaterialize, DirectoryWithPrefixToAdd,
+                             DirectoryWithPrefixToStrip, FilesContent, FilesContentView, PathGlobs,
+                             PathGlobsAndRoot, Snapshot, SnapshotSubset, SpecialFileBehavior,
+                             UrlToFetch, WorkspaceWrite, create_fs_rules)
 from pants.engine.scheduler import ExecutionError
 from pants.option.global_options import GlobMatchErrorBehavior
 from pants.util.collections import assert_single_element
 from pants.util.contextutil import http_server, temporary_dir
-from pants.util.dirutil import relative_symlink, safe_file_dump
+from pants.util.dirutil import relative_symlink, safe_file_dump, touch
 from pants.util.meta import AbstractClass
 from pants_test.engine.scheduler_test_base import SchedulerTestBase
 from pants_test.test_base import TestBase
@@ -226,6 +231,102 @@ class FSTest(TestBase, SchedulerTestBase, AbstractClass):
   def test_files_content_symlink(self):
     self.assert_content(['c.ln/../3.txt'], {'c.ln/../3.txt': b'three\n'})
 
+  def test_files_content_view(self):
+    with self.mk_project_tree() as project_tree:
+      scheduler = self.mk_scheduler(rules=create_fs_rules(), project_tree=project_tree)
+      snapshot = self.execute_expecting_one_result(scheduler, Snapshot, self.specs(['4.txt'])).value
+      result = self.execute_expecting_one_result(scheduler, FilesContentView, snapshot.directory_digest).value
+      view = assert_single_element(result.dependencies)
+      self.assertEqual('4.txt', view.path)
+      self.assertIsInstance(view.content, memoryview)
+      self.assertEqual(b'four\n', view.content.tobytes())
+
+  def test_files_content_view_is_read_only(self):
+    with self.mk_project_tree() as project_tree:
+      scheduler = self.mk_scheduler(rules=create_fs_rules(), project_tree=project_tree)
+      snapshot = self.execute_expecting_one_result(scheduler, Snapshot, self.specs(['4.txt'])).value
+      result = self.execute_expecting_one_result(scheduler, FilesContentView, snapshot.directory_digest).value
+      view = assert_single_element(result.dependencies)
+      self.assertTrue(view.content.readonly)
+      with self.assertRaises(TypeError):
+        view.content[0:1] = b'F'
+      self.assertEqual(b'four\n', view.content.tobytes())
+
+  def test_watcher_invalidates_changed_files(self):
+    with self.mk_project_tree() as project_tree:
+      scheduler = self.mk_scheduler(rules=create_fs_rules(), project_tree=project_tree)
+      scheduler.start_watching()
+
+      def content():
+        snapshot = self.execute_expecting_one_result(scheduler, Snapshot, self.specs(['4.txt'])).value
+        result = self.execute_expecting_one_result(scheduler, FilesContent, snapshot.directory_digest)
+        return assert_single_element(result.value.dependencies).content
+
+      self.assertEqual(b'four\n', content())
+      with open(os.path.join(project_tree.build_root, '4.txt'), 'wb') as f:
+        f.write(b'four, changed\n')
+      # The watcher invalidates asynchronously, so we poll briefly for the change to be observed.
+      deadline = time.time() + 10
+      while content() != b'four, changed\n' and time.time() < deadline:
+        time.sleep(0.1)
+      self.assertEqual(b'four, changed\n', content())
+
+  def test_watcher_renamed_files(self):
+    with self.mk_project_tree() as project_tree:
+      scheduler = self.mk_scheduler(rules=create_fs_rules(), project_tree=project_tree)
+      scheduler.start_watching()
+
+      def contents(globs):
+        snapshot = self.execute_expecting_one_result(scheduler, Snapshot, self.specs(globs)).value
+        result = self.execute_expecting_one_result(scheduler, FilesContent, snapshot.directory_digest)
+        return {fc.path: fc.content for fc in result.value.dependencies}
+
+      # Digest the files, so that the digests of their renamed paths are aliased rather than
+      # re-computed.
+      self.assertEqual({'4.txt': b'four\n', 'a/3.txt': b'three\n'}, contents(['4.txt', 'a/3.txt']))
+      build_root = project_tree.build_root
+      os.rename(os.path.join(build_root, '4.txt'), os.path.join(build_root, 'renamed.txt'))
+      os.rename(os.path.join(build_root, 'a'), os.path.join(build_root, 'renamed_dir'))
+
+      expected = {'renamed.txt': b'four\n', 'renamed_dir/3.txt': b'three\n'}
+      globs = ['4.txt', 'a/3.txt', 'renamed.txt', 'renamed_dir/3.txt']
+      deadline = time.time() + 10
+      while contents(globs) != expected and time.time() < deadline:
+        time.sleep(0.1)
+      self.assertEqual(expected, contents(globs))
+
+  def test_watcher_invalidation_globs(self):
+    with self.mk_project_tree() as project_tree, temporary_dir() as temp_dir:
+      binary = os.path.join(temp_dir, 'native_engine.so')
+      touch(binary)
+      scheduler = self.mk_scheduler(rules=create_fs_rules(), project_tree=project_tree)
+      scheduler.start_watching(invalidation_globs=['pants.toml', binary])
+      self.assertFalse(scheduler.restart_required())
+
+      # A file outside of the build root is checked for modification on demand.
+      os.utime(binary, (0, 0))
+      self.assertTrue(scheduler.restart_required())
+
+      # Restarting the watcher resets the state, and changes within the build root are observed
+      # asynchronously.
+      scheduler.start_watching(invalidation_globs=['pants.toml', binary])
+      self.assertFalse(scheduler.restart_required())
+      with open(os.path.join(project_tree.build_root, 'pants.toml'), 'w') as f:
+        f.write('[GLOBAL]\n')
+      deadline = time.time() + 10
+      while not scheduler.restart_required() and time.time() < deadline:
+        time.sleep(0.1)
+      self.assertTrue(scheduler.restart_required())
+
+  def test_watcher_unknown_backend(self):
+    scheduler = self.mk_scheduler(rules=create_fs_rules())
+    with self.assertRaises(Exception) as cm:
+      scheduler.start_watching(backend='carrier-pigeon')
+    self.assertIn('Unknown watcher backend', str(cm.exception))
+    with self.assertRaises(Exception) as cm:
+      scheduler.start_watching(backend='watchman')
+    self.assertIn('requires a socket path', str(cm.exception))
+
   def test_files_digest_literal(self):
     self.assert_digest(['a/3.txt', '4.txt'], ['a/3.txt', '4.txt'])
 
@@ -272,6 +373,38 @@ class FSTest(TestBase, SchedulerTestBase, AbstractClass):
         scheduler.capture_snapshots((PathGlobsAndRoot(globs, text_type(os.path.join(temp_dir, "doesnotexist"))),))
       self.assertIn("doesnotexist", str(cm.exception))
 
+  def test_snapshot_max_file_bytes(self):
+    with temporary_dir() as temp_dir:
+      with open(os.path.join(temp_dir, "roland"), "w") as f:
+        f.write("European Burmese")
+      scheduler = self.mk_scheduler(rules=create_fs_rules())
+      globs = PathGlobs(("*",), (), max_file_bytes=10)
+      with self.assertRaises(Exception) as cm:
+        scheduler.capture_snapshots((PathGlobsAndRoot(globs, text_type(temp_dir)),))
+      self.assertIn("roland", str(cm.exception))
+      self.assertIn("exceeds the limit of 10 bytes", str(cm.exception))
+
+  def test_snapshot_special_files(self):
+    with temporary_dir() as temp_dir:
+      with open(os.path.join(temp_dir, "roland"), "w") as f:
+        f.write("European Burmese")
+      catflap = socket.socket(socket.AF_UNIX)
+      try:
+        catflap.bind(os.path.join(temp_dir, "catflap"))
+        scheduler = self.mk_scheduler(rules=create_fs_rules())
+
+        snapshot = scheduler.capture_snapshots((
+          PathGlobsAndRoot(PathGlobs(("*",), ()), text_type(temp_dir)),
+        ))[0]
+        self.assertEqual(("roland",), snapshot.files)
+
+        globs = PathGlobs(("*",), (), special_file_behavior=SpecialFileBehavior.error)
+        with self.assertRaises(Exception) as cm:
+          scheduler.capture_snapshots((PathGlobsAndRoot(globs, text_type(temp_dir)),))
+        self.assertIn("\"catflap\" is a socket", str(cm.exception))
+      finally:
+        catflap.close()
+
   def assert_snapshot_equals(self, snapshot, files, directory_digest):
     self.assertEqual(list(snapshot.files), files)
     self.assertEqual(snapshot.directory_digest, directory_digest)
@@ -373,6 +506,35 @@ class FSTest(TestBase, SchedulerTestBase, AbstractClass):
         content = f.read()
         self.assertEqual(content, "European Burmese")
 
+  def test_write_to_workspace(self):
+    self.prime_store_with_roland_digest()
+    digest = Digest(
+      text_type("63949aa823baf765eff07b946050d76ec0033144c785a94d3ebd82baa931cd16"),
+      80
+    )
+
+    with temporary_dir() as temp_dir:
+      dir_path = os.path.join(temp_dir, "dist")
+      roland = os.path.join(dir_path, "roland")
+      safe_file_dump(roland, "Stale roland")
+
+      with self.assertRaisesRegexp(Exception, "already exist"):
+        self.scheduler.write_to_workspace(WorkspaceWrite(text_type(dir_path), digest))
+
+      manifest = self.scheduler.write_to_workspace(
+        WorkspaceWrite(text_type(dir_path), digest, conflict_policy='backup'))
+      self.assertEqual(manifest.written, (roland,))
+      self.assertEqual(manifest.backed_up, (roland + '.bak',))
+      with open(roland, 'r') as f:
+        self.assertEqual(f.read(), "European Burmese")
+      with open(roland + '.bak', 'r') as f:
+        self.assertEqual(f.read(), "Stale roland")
+
+      manifest = self.scheduler.write_to_workspace(
+        WorkspaceWrite(text_type(dir_path), digest, conflict_policy='overwrite'))
+      self.assertEqual(manifest.written, (roland,))
+      self.assertEqual(manifest.backed_up, ())
+
   def test_strip_prefix(self):
     # Set up files:
 
@@ -445,6 +607,133 @@ class FSTest(TestBase, SchedulerTestBase, AbstractClass):
           [DirectoryWithPrefixToStrip(snapshot_with_extra_files.directory_digest, text_type("characters/dark_tower"))]
         )
 
+  def test_extract_archive(self):
+    with temporary_dir() as temp_dir:
+      tower_dir = os.path.join(temp_dir, "tower")
+      safe_file_dump(os.path.join(tower_dir, "roland"), "European Burmese", makedirs=True)
+      safe_file_dump(os.path.join(tower_dir, "gunslingers", "susannah"), "Not sure actually",
+                     makedirs=True)
+      archives_dir = os.path.join(temp_dir, "archives")
+      os.makedirs(archives_dir)
+      with zipfile.ZipFile(os.path.join(archives_dir, "tower.zip"), "w") as zf:
+        zf.write(os.path.join(tower_dir, "roland"), "roland")
+        zf.write(os.path.join(tower_dir, "gunslingers", "susannah"), "gunslingers/susannah")
+      with tarfile.open(os.path.join(archives_dir, "tower.tar.gz"), "w:gz") as tf:
+        tf.add(tower_dir, arcname=".")
+
+      expected, zipped, tarred = self.scheduler.capture_snapshots((
+        PathGlobsAndRoot(PathGlobs(("**",)), text_type(tower_dir)),
+        PathGlobsAndRoot(PathGlobs(("tower.zip",)), text_type(archives_dir)),
+        PathGlobsAndRoot(PathGlobs(("tower.tar.gz",)), text_type(archives_dir)),
+      ))
+
+      for archive in (zipped, tarred):
+        extracted_digest = assert_single_element(self.scheduler.product_request(
+          Digest,
+          [ArchiveToExtract(archive.directory_digest)],
+        ))
+        self.assertEquals(expected.directory_digest, extracted_digest)
+
+      # Only directories containing exactly one archive may be extracted.
+      with self.assertRaises(ExecutionError) as cm:
+        self.scheduler.product_request(Digest, [ArchiveToExtract(expected.directory_digest)])
+      self.assertIn("exactly one archive file", str(cm.exception))
+
+  def test_create_archive(self):
+    with temporary_dir() as temp_dir:
+      safe_file_dump(os.path.join(temp_dir, "roland"), "European Burmese")
+      safe_file_dump(os.path.join(temp_dir, "gunslingers", "susannah"), "Not sure actually",
+                     makedirs=True)
+      snapshot, = self.scheduler.capture_snapshots((
+        PathGlobsAndRoot(PathGlobs(("**",)), text_type(temp_dir)),
+      ))
+
+      for archive_name in ("tower.zip", "tower.tar.gz"):
+        archive_digest = assert_single_element(self.scheduler.product_request(
+          Digest,
+          [DirectoryToArchive(snapshot.directory_digest, text_type(archive_name))],
+        ))
+        archive_snapshot = assert_single_element(
+          self.scheduler.product_request(Snapshot, [archive_digest]))
+        self.assertEquals((archive_name,), archive_snapshot.files)
+
+        extracted_digest = assert_single_element(self.scheduler.product_request(
+          Digest,
+          [ArchiveToExtract(archive_digest)],
+        ))
+        self.assertEquals(snapshot.directory_digest, extracted_digest)
+
+        with temporary_dir() as materialize_dir:
+          self.scheduler.materialize_directories((
+            DirectoryToMaterialize(text_type(materialize_dir), archive_digest),
+          ))
+          archive_path = os.path.join(materialize_dir, archive_name)
+          if archive_name.endswith(".zip"):
+            with zipfile.ZipFile(archive_path) as zf:
+              self.assertEquals(["gunslingers/", "gunslingers/susannah", "roland"], zf.namelist())
+              self.assertEquals({(1980, 1, 1, 0, 0, 0)}, {i.date_time for i in zf.infolist()})
+          else:
+            with tarfile.open(archive_path) as tf:
+              self.assertEquals(["gunslingers", "gunslingers/susannah", "roland"], tf.getnames())
+              self.assertEquals({0}, {m.mtime for m in tf.getmembers()})
+
+  def test_add_prefix(self):
+    with temporary_dir() as temp_dir:
+      tower_dir = os.path.join(temp_dir, "characters", "dark_tower")
+      safe_file_dump(os.path.join(tower_dir, "roland"), "European Burmese", makedirs=True)
+      safe_file_dump(os.path.join(tower_dir, "susannah"), "Not sure actually", makedirs=True)
+
+      prefixed, unprefixed = self.scheduler.capture_snapshots((
+        PathGlobsAndRoot(PathGlobs(("**",)), text_type(temp_dir)),
+        PathGlobsAndRoot(PathGlobs(("**",)), text_type(tower_dir)),
+      ))
+
+      # Add an empty prefix:
+      zero_prefix_added_digest = assert_single_element(self.scheduler.product_request(
+        Digest,
+        [DirectoryWithPrefixToAdd(unprefixed.directory_digest, text_type(""))],
+      ))
+      self.assertEquals(unprefixed.directory_digest, zero_prefix_added_digest)
+
+      # Add a multi-component prefix:
+      added_digest = assert_single_element(self.scheduler.product_request(
+        Digest,
+        [DirectoryWithPrefixToAdd(unprefixed.directory_digest, text_type("characters/dark_tower"))],
+      ))
+      self.assertEquals(prefixed.directory_digest, added_digest)
+
+      # Prefixes may not escape the directory:
+      with self.assertRaisesWithMessageContaining(Exception, "Cannot add prefix ../dark_tower"):
+        self.scheduler.product_request(
+          Digest,
+          [DirectoryWithPrefixToAdd(unprefixed.directory_digest, text_type("../dark_tower"))],
+        )
+
+  def test_snapshot_subset(self):
+    with temporary_dir() as temp_dir:
+      safe_file_dump(os.path.join(temp_dir, "index"), "books\ncharacters\n")
+      tower_dir = os.path.join(temp_dir, "characters", "dark_tower")
+      safe_file_dump(os.path.join(tower_dir, "roland"), "European Burmese", makedirs=True)
+      safe_file_dump(os.path.join(tower_dir, "susannah"), "Not sure actually", makedirs=True)
+
+      everything, expected = self.scheduler.capture_snapshots((
+        PathGlobsAndRoot(PathGlobs(("**",)), text_type(temp_dir)),
+        PathGlobsAndRoot(PathGlobs(("characters/**/roland",)), text_type(temp_dir)),
+      ))
+
+      subset = assert_single_element(self.scheduler.product_request(
+        Snapshot,
+        [SnapshotSubset(everything.directory_digest, PathGlobs(("characters/**/roland",)))],
+      ))
+      self.assertEquals(("characters/dark_tower/roland",), subset.files)
+      self.assertEquals(expected.directory_digest, subset.directory_digest)
+
+      empty_subset = assert_single_element(self.scheduler.product_request(
+        Snapshot,
+        [SnapshotSubset(everything.directory_digest, PathGlobs(("books/**",)))],
+      ))
+      self.assertTrue(empty_subset.is_empty)
+
   def test_lift_directory_digest_to_snapshot(self):
     digest = self.prime_store_with_roland_digest()
     snapshot = assert_single_element(self.scheduler.product_request(Snapshot, [digest]))
@@ -554,6 +843,18 @@ class FSTest(TestBase, SchedulerTestBase, AbstractClass):
           self.scheduler.product_request(Snapshot, subjects=[url])
         self.assertIn('wrong digest', str(cm.exception).lower())
 
+  def test_download_retries_server_errors(self):
+    with self.isolated_local_store():
+      FlakyStubHandler.failures_remaining = 2
+      with http_server(FlakyStubHandler) as port:
+        url = UrlToFetch("http://localhost:{}/CNAME".format(port), self.pantsbuild_digest)
+        snapshot, = self.scheduler.product_request(Snapshot, subjects=[url])
+        self.assert_snapshot_equals(snapshot, ["CNAME"], Digest(
+          text_type("16ba2118adbe5b53270008790e245bbf7088033389461b08640a4092f7f647cf"),
+          81
+        ))
+      self.assertEqual(0, FlakyStubHandler.failures_remaining)
+
   # It's a shame that this isn't hermetic, but setting up valid local HTTPS certificates is a pain.
   def test_download_https(self):
     with self.isolated_local_store():
@@ -602,3 +903,23 @@ class StubHandler(BaseHTTPRequestHandler):
     self.send_header("Content-Type", "text/utf-8")
     self.send_header("Content-Length", "{}".format(len(self.response_text)))
     self.end_headers()
+
+
+class FlakyStubHandler(StubHandler):
+  """Responds with a server error to the first `failures_remaining` requests."""
+  failures_remaining = 0
+
+  def send_headers(self):
+    if FlakyStubHandler.failures_remaining > 0:
+      FlakyStubHandler.failures_remaining -= 1
+      self.send_response(503)
+      self.send_header("Content-Length", "0")
+      self.end_headers()
+    else:
+      StubHandler.send_headers(self)
+
+  def do_GET(self):
+    if FlakyStubHandler.failures_remaining > 0:
+      self.send_headers()
+    else:
+      StubHandler.do_GET(self)
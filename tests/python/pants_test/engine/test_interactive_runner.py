@@ -0,0 +1,82 @@
+# coding=utf-8
+# Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+# Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+from __future__ import absolute_import, division, print_function, unicode_literals
+
+import unittest
+
+from pants.engine.fs import EMPTY_DIRECTORY_DIGEST
+from pants.engine.interactive_runner import (BackgroundProcessRequest, InteractiveProcessRequest,
+                                             InteractiveProcessResult, InteractiveRunner,
+                                             ServiceRequest)
+from pants.engine.isolated_process import ExecuteProcessRequest, ExecuteProcessResult
+from pants_test.test_base import TestBase
+
+
+class InteractiveRunnerTest(TestBase, unittest.TestCase):
+
+  def run_interactive(self, request):
+    return InteractiveRunner(self.scheduler).run_local_interactive_process(request)
+
+  def test_exit_code(self):
+    result = self.run_interactive(InteractiveProcessRequest(argv=('/bin/bash', '-c', 'exit 3')))
+    self.assertEqual(InteractiveProcessResult(3), result)
+
+  def test_input_files(self):
+    output_digest = self.scheduler.product_request(ExecuteProcessResult, [ExecuteProcessRequest(
+      argv=('/bin/bash', '-c', "echo -n 'European Burmese' > roland"),
+      description='echo roland',
+      output_files=('roland',),
+      input_files=EMPTY_DIRECTORY_DIGEST,
+    )])[0].output_directory_digest
+
+    result = self.run_interactive(InteractiveProcessRequest(
+      argv=('/bin/bash', '-c', 'test "$(< roland)" = "$EXPECTED"'),
+      env={'EXPECTED': 'European Burmese'},
+      input_files=output_digest,
+    ))
+    self.assertEqual(0, result.exit_code)
+
+  def test_description_defaults_to_argv(self):
+    request = InteractiveProcessRequest(argv=('/bin/echo', 'hi'))
+    self.assertEqual('/bin/echo hi', request.description)
+
+  def test_background_process_until_ready(self):
+    process = InteractiveRunner(self.scheduler).start_background_process(BackgroundProcessRequest(
+      argv=('/bin/bash', '-c', 'echo starting; sleep 0.2; echo ready; sleep 60'),
+    ))
+    progress = process.wait_until_ready(lambda p: b'ready' in p.stdout, timeout_seconds=30)
+    self.assertTrue(progress.running)
+    self.assertEqual(b'starting\nready\n', progress.stdout)
+    self.assertLess(process.stop(), 0)
+
+  def test_background_process_exits_before_ready(self):
+    process = InteractiveRunner(self.scheduler).start_background_process(BackgroundProcessRequest(
+      argv=('/bin/bash', '-c', 'echo failed >&2; exit 2'),
+    ))
+    with self.assertRaisesRegexp(ValueError, 'exited with code 2'):
+      process.wait_until_ready(lambda p: b'ready' in p.stdout, timeout_seconds=30)
+
+  def test_service_is_shared(self):
+    runner = InteractiveRunner(self.scheduler)
+    request = ServiceRequest(
+      name='db',
+      process=BackgroundProcessRequest(
+        argv=('/bin/bash', '-c', 'echo "listening on $$"; sleep 60'),
+      ),
+      ready_stdout='listening',
+    )
+    first = runner.ensure_service(request)
+    second = runner.ensure_service(request)
+    self.assertEqual(first._process_id, second._process_id)
+    self.assertIn(b'listening', first.wait_for_progress(heartbeat_seconds=0).stdout)
+
+  def test_service_exits_before_healthy(self):
+    request = ServiceRequest(
+      name='broken',
+      process=BackgroundProcessRequest(argv=('/bin/bash', '-c', 'exit 4')),
+      ready_stdout='listening',
+    )
+    with self.assertRaisesRegexp(Exception, 'exited with code 4 before it was healthy'):
+      InteractiveRunner(self.scheduler).ensure_service(request)
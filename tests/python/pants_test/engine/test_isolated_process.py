@@ -4,6 +4,8 @@
 
 from __future__ import absolute_import, division, print_function, unicode_literals
 
+import hashlib
+import json
 import os
 import unittest
 from builtins import str
@@ -13,7 +15,9 @@ from future.utils import text_type
 from pants.engine.fs import (EMPTY_DIRECTORY_DIGEST, Digest, FileContent, FilesContent, PathGlobs,
                              Snapshot)
 from pants.engine.isolated_process import (ExecuteProcessRequest, ExecuteProcessResult,
-                                           FallibleExecuteProcessResult, ProcessExecutionFailure)
+                                           FallibleExecuteProcessResult, ProcessExecutionFailure,
+                                           ProcessTimedOut, ShardedExecuteProcessRequest,
+                                           ShardedFallibleExecuteProcessResults)
 from pants.engine.rules import RootRule, rule
 from pants.engine.scheduler import ExecutionError
 from pants.engine.selectors import Get
@@ -261,6 +265,54 @@ class ExecuteProcessRequestTest(unittest.TestCase):
     )
     self.assertEqual(req.env, ('VAR', 'VAL'))
 
+  def test_create_with_platform_properties(self):
+    req = ExecuteProcessRequest(
+      argv=('foo',),
+      description="Some process",
+      platform_properties={'zone': 'west', 'pool': 'gpu'},
+      input_files=EMPTY_DIRECTORY_DIGEST,
+    )
+    self.assertEqual(req.platform_properties, ('pool', 'gpu', 'zone', 'west'))
+
+  def test_create_with_invalid_platform_properties(self):
+    with self.assertRaisesRegexp(TypeCheckError, "must be a dict"):
+      ExecuteProcessRequest(
+        argv=('foo',),
+        description="Some process",
+        platform_properties=('pool', 'gpu'),
+        input_files=EMPTY_DIRECTORY_DIGEST,
+      )
+
+  def test_create_with_secret_env(self):
+    req = ExecuteProcessRequest(
+      argv=('foo',),
+      description="Some process",
+      env={'TOKEN': 'hunter2', 'USER': 'roland'},
+      secret_env=['TOKEN'],
+      input_files=EMPTY_DIRECTORY_DIGEST,
+    )
+    self.assertEqual(req.secret_env, ('TOKEN',))
+
+  def test_create_with_undefined_secret_env(self):
+    with self.assertRaisesRegexp(TypeCheckError, "must be set in env"):
+      ExecuteProcessRequest(
+        argv=('foo',),
+        description="Some process",
+        env={'USER': 'roland'},
+        secret_env=['TOKEN'],
+        input_files=EMPTY_DIRECTORY_DIGEST,
+      )
+
+  def test_create_with_jdk_home_and_jdk(self):
+    with self.assertRaisesRegexp(TypeCheckError, "mutually exclusive"):
+      ExecuteProcessRequest(
+        argv=('java',),
+        description="Some process",
+        input_files=EMPTY_DIRECTORY_DIGEST,
+        jdk_home='/usr/lib/jvm/java-11',
+        jdk='11',
+      )
+
 
 class IsolatedProcessTest(TestBase, unittest.TestCase):
 
@@ -320,19 +372,167 @@ class IsolatedProcessTest(TestBase, unittest.TestCase):
       (FileContent("roland", b"European Burmese"),)
     )
 
-  def test_exercise_python_side_of_timeout_implementation(self):
-    # Local execution currently doesn't support timeouts,
-    # but this allows us to ensure that all of the setup
-    # on the python side does not blow up.
+  def test_preserve_empty_output_directories(self):
+    def output_digest(preserve_empty_output_directories):
+      request = ExecuteProcessRequest(
+        argv=('/bin/bash', '-c', 'true'),
+        description='create nothing',
+        output_directories=('falcons',),
+        input_files=EMPTY_DIRECTORY_DIGEST,
+        preserve_empty_output_directories=preserve_empty_output_directories,
+      )
+      return self.scheduler.product_request(
+        ExecuteProcessResult, [request])[0].output_directory_digest
+
+    self.assertEqual(output_digest(False), EMPTY_DIRECTORY_DIGEST)
+    self.assertNotEqual(output_digest(True), EMPTY_DIRECTORY_DIGEST)
+
+  def test_stdin(self):
+    # Store the content as a file, which can then be referenced by its (file) Digest.
+    content = b'European Burmese'
+    self.scheduler.product_request(ExecuteProcessResult, [ExecuteProcessRequest(
+      argv=('/bin/bash', '-c', "echo -n 'European Burmese' > roland"),
+      description='echo roland',
+      output_files=('roland',),
+      input_files=EMPTY_DIRECTORY_DIGEST,
+    )])
+    request = ExecuteProcessRequest(
+      argv=('/bin/cat',),
+      description='cat stdin',
+      input_files=EMPTY_DIRECTORY_DIGEST,
+      stdin_digest=Digest(text_type(hashlib.sha256(content).hexdigest()), len(content)),
+    )
+    result = self.scheduler.product_request(ExecuteProcessResult, [request])[0]
+    self.assertEqual(result.stdout, content)
+
+  def test_side_effecting_runs_once_per_session_with_a_token(self):
+    def run(session, side_effecting):
+      return session.product_request(ExecuteProcessResult, [ExecuteProcessRequest(
+        argv=('/bin/bash', '-c', 'echo -n "${PANTS_IDEMPOTENCY_TOKEN}"'),
+        description='publish',
+        input_files=EMPTY_DIRECTORY_DIGEST,
+        side_effecting=side_effecting,
+      )])[0].stdout
+
+    first_session = self.scheduler._scheduler.new_session()
+    second_session = self.scheduler._scheduler.new_session()
+
+    token = run(first_session, True)
+    self.assertTrue(token)
+    # Memoized within a Session, but run again (with a new token) in another.
+    self.assertEqual(token, run(first_session, True))
+    self.assertNotEqual(token, run(second_session, True))
+    # Other processes have no token.
+    self.assertEqual(b'', run(first_session, False))
+
+  def test_side_effecting_nodes_are_removed_with_their_session(self):
+    def run_in_new_session():
+      session = self.scheduler._scheduler.new_session()
+      session.product_request(ExecuteProcessResult, [ExecuteProcessRequest(
+        argv=('/bin/echo', '-n', 'publish'),
+        description='publish',
+        input_files=EMPTY_DIRECTORY_DIGEST,
+        side_effecting=True,
+      )])
+      return session.graph_len()
+
+    # Each Session runs the process again, but the Nodes of the previous Sessions (which can never
+    # be requested again) do not accumulate in the Graph.
+    graph_lens = [run_in_new_session() for _ in range(4)]
+    self.assertEqual(graph_lens[1:], [graph_lens[0]] * 3)
+
+  def test_metrics_are_not_reset_by_other_sessions(self):
+    first_session = self.scheduler._scheduler.new_session()
+    first_session.product_request(ExecuteProcessResult, [ExecuteProcessRequest(
+      argv=('/bin/echo', '-n', 'metrics'),
+      description='echo metrics',
+      input_files=EMPTY_DIRECTORY_DIGEST,
+    )])
+    executed = first_session.metrics()['local_execution_requests']
+    self.assertGreater(executed, 0)
+
+    # Creating another Session does not clear the metrics of the first, and the metrics of the new
+    # Session do not include work which happened before it was created.
+    second_session = self.scheduler._scheduler.new_session()
+    self.assertEqual(executed, first_session.metrics()['local_execution_requests'])
+    self.assertEqual(0, second_session.metrics()['local_execution_requests'])
+
+  def test_sharded_process(self):
+    template = ExecuteProcessRequest(
+      argv=('/bin/echo', '-n'),
+      description='echo shards',
+      input_files=EMPTY_DIRECTORY_DIGEST,
+    )
+    request = ShardedExecuteProcessRequest(template, [('one',), ['two', 'three']])
+    results = self.scheduler.product_request(ShardedFallibleExecuteProcessResults, [request])[0]
+    self.assertEqual([b'one', b'two three'], [r.stdout for r in results.results])
+    self.assertEqual([0, 0], [r.exit_code for r in results.results])
+
+  def test_sharded_process_requires_shards(self):
+    template = ExecuteProcessRequest(
+      argv=('/bin/echo',),
+      description='echo shards',
+      input_files=EMPTY_DIRECTORY_DIGEST,
+    )
+    with self.assertRaises(TypeCheckError):
+      ShardedExecuteProcessRequest(template, [])
+
+  def test_execution_strategy_is_per_session(self):
+    request = ExecuteProcessRequest(
+      argv=('/bin/echo', '-n', 'strategy'),
+      description='echo strategy',
+      input_files=EMPTY_DIRECTORY_DIGEST,
+    )
+    # No remote execution server is configured, so remote-only execution fails...
+    remote_session = self.scheduler._scheduler.new_session(execution_strategy='remote_only')
+    with self.assertRaisesRegexp(ExecutionError, 'requires a remote execution server'):
+      remote_session.product_request(ExecuteProcessResult, [request])
+    # ...while local-only execution succeeds in a later session.
+    local_session = self.scheduler._scheduler.new_session(execution_strategy='local_only')
+    result = local_session.product_request(ExecuteProcessResult, [request])[0]
+    self.assertEqual(b'strategy', result.stdout)
+
+  def test_run_report_filters_workunits_by_level(self):
+    # A fresh session, so that only these processes are reported.
+    session = self.scheduler._scheduler.new_session()
+    # A process without a description is at the debug level, so is not reported at the (default)
+    # info level.
+    for description in ('described', ''):
+      session.product_request(ExecuteProcessResult, [ExecuteProcessRequest(
+        argv=('/bin/bash', '-c', 'echo {}'.format(description or 'undescribed')),
+        description=description,
+        input_files=EMPTY_DIRECTORY_DIGEST,
+      )])
+
+    with temporary_dir() as tmpdir:
+      path = os.path.join(tmpdir, 'run_report.json')
+      session.write_run_report(path)
+      with open(path, 'r') as f:
+        workunits = json.load(f)['slowest_workunits']
 
+    self.assertEqual([('described', 'info')], [(w['name'], w['level']) for w in workunits])
+
+  def test_timeout(self):
     request = ExecuteProcessRequest(
-      argv=("/bin/bash", "-c", "/bin/sleep 1; echo -n 'European Burmese'"),
-      timeout_seconds=0.1,
+      argv=("/bin/bash", "-c", "echo -n 'European Burmese'; /bin/sleep 10"),
+      timeout_seconds=0.5,
       description='sleepy-cat',
       input_files=EMPTY_DIRECTORY_DIGEST,
     )
 
-    self.scheduler.product_request(ExecuteProcessResult, [request])[0]
+    result = self.scheduler.product_request(FallibleExecuteProcessResult, [request])[0]
+    self.assertTrue(result.timed_out)
+    self.assertEqual(0.5, result.timeout_seconds)
+    self.assertGreaterEqual(result.elapsed_seconds, 0.5)
+    self.assertEqual(b'European Burmese', result.stdout)
+
+    with self.assertRaises(ExecutionError) as cm:
+      self.scheduler.product_request(ExecuteProcessResult, [request])
+    e = cm.exception.wrapped_exceptions[0]
+    self.assertIsInstance(e, ProcessTimedOut)
+    self.assertEqual(0.5, e.timeout_seconds)
+    self.assertIn("process 'sleepy-cat' timed out after", str(e))
+    self.assertIn("European Burmese", str(e))
 
   def test_javac_compilation_example_success(self):
     self.create_dir('simple')
@@ -393,6 +593,43 @@ class Broken {
       result = self.scheduler.product_request(ExecuteProcessResult, [request])[0]
       self.assertEqual(result.stdout, b'European Burmese')
 
+  def test_jdk_unknown_spec(self):
+    request = ExecuteProcessRequest(
+      argv=('/bin/cat', '.jdk/release'),
+      input_files=EMPTY_DIRECTORY_DIGEST,
+      description='cat JDK release',
+      jdk='nonexistent-vendor-11',
+    )
+    with self.assertRaises(ExecutionError) as cm:
+      self.scheduler.product_request(ExecuteProcessResult, [request])
+    self.assertIn('No JDK matching `nonexistent-vendor-11` was found', str(cm.exception))
+
+  def test_max_output_bytes(self):
+    request = ExecuteProcessRequest(
+      argv=('/bin/bash', '-c', 'echo -n abcdefghij'),
+      input_files=EMPTY_DIRECTORY_DIGEST,
+      description='echo alphabet',
+      max_output_bytes=4,
+      output_overflow_policy='truncate_tail',
+    )
+    result = self.scheduler.product_request(ExecuteProcessResult, [request])[0]
+    self.assertEqual(
+      result.stdout,
+      b'abcd\n[stdout truncated: the last 6 of 10 bytes were omitted]\n',
+    )
+
+  def test_max_output_bytes_fail(self):
+    request = ExecuteProcessRequest(
+      argv=('/bin/bash', '-c', 'echo -n abcdefghij'),
+      input_files=EMPTY_DIRECTORY_DIGEST,
+      description='echo alphabet',
+      max_output_bytes=4,
+      output_overflow_policy='fail',
+    )
+    with self.assertRaises(ExecutionError) as cm:
+      self.scheduler.product_request(ExecuteProcessResult, [request])
+    self.assertIn('Process stdout exceeded the limit of 4 bytes.', str(cm.exception))
+
   def test_fallible_failing_command_returns_exited_result(self):
     request = ExecuteProcessRequest(
       argv=("/bin/bash", "-c", "exit 1"),
@@ -403,6 +640,8 @@ class Broken {
     result = self.scheduler.product_request(FallibleExecuteProcessResult, [request])[0]
 
     self.assertEqual(result.exit_code, 1)
+    self.assertEqual(result.runner, 'local')
+    self.assertEqual(result.attempt_count, 1)
 
   def test_non_fallible_failing_command_raises(self):
     request = ExecuteProcessRequest(
@@ -413,4 +652,32 @@ class Broken {
 
     with self.assertRaises(ExecutionError) as cm:
       self.scheduler.product_request(ExecuteProcessResult, [request])
-    self.assertIn("process 'one-cat' failed with exit code 1.", str(cm.exception))
+    self.assertIn("process 'one-cat' failed with exit code 1 (runner: local, attempts: 1).",
+                  str(cm.exception))
+
+  def test_remote_execution_dry_run(self):
+    request = ExecuteProcessRequest(
+      argv=('/bin/echo', 'yo'),
+      input_files=EMPTY_DIRECTORY_DIGEST,
+      description='echo yo',
+    )
+
+    dry_run = self.scheduler.remote_execution_dry_run(request)
+
+    self.assertEqual(dry_run['input_root_digest']['fingerprint'],
+                     EMPTY_DIRECTORY_DIGEST.fingerprint)
+    self.assertIn('arguments: "/bin/echo"', dry_run['command'])
+    self.assertIn('"SOURCE_DATE_EPOCH"', dry_run['command'])
+    self.assertEqual(dry_run['action_digest'],
+                     self.scheduler.remote_execution_dry_run(request)['action_digest'])
+
+  def test_remote_execution_dry_run_disallowed_platform_properties(self):
+    request = ExecuteProcessRequest(
+      argv=('/bin/echo', 'yo'),
+      input_files=EMPTY_DIRECTORY_DIGEST,
+      description='echo yo',
+      platform_properties={'pool': 'gpu'},
+    )
+
+    with self.assertRaisesRegexp(Exception, "not allowed"):
+      self.scheduler.remote_execution_dry_run(request)
@@ -4,18 +4,26 @@
 
 from __future__ import absolute_import, division, print_function, unicode_literals
 
+import json
+import os
 import re
 import sys
+import threading
+import time
 from builtins import object, str
 from contextlib import contextmanager
+from io import StringIO
 from textwrap import dedent
 
 import mock
 
+from pants.engine.console import Console
+from pants.engine.fs import PathGlobs, Snapshot
 from pants.engine.native import Native
 from pants.engine.rules import RootRule, UnionRule, rule, union
 from pants.engine.scheduler import ExecutionError, SchedulerSession
 from pants.engine.selectors import Get, Params
+from pants.util.contextutil import temporary_dir
 from pants.util.objects import datatype
 from pants_test.engine.util import assert_equal_with_printing, remove_locations_from_traceback
 from pants_test.test_base import TestBase
@@ -126,6 +134,18 @@ def c_unhashable(_):
   yield C()
 
 
+@rule(str, [B])
+def catches_nested_raise(b):
+  # The Get below will fail in `nested_raise`: the failure should be thrown into this generator
+  # at the `yield`, where it can be handled like any other exception.
+  try:
+    _ = yield Get(A, B, b) # noqa: F841
+  except Exception as e:
+    yield str('Caught: {}'.format(e))
+  else:
+    yield str('Not caught!')
+
+
 class CollectionType(datatype(['items'])):
   pass
 
@@ -138,6 +158,46 @@ def c_unhashable_datatype(_):
   yield C()
 
 
+class E(object):
+  pass
+
+
+# The times at which `sleeps` has been called.
+_sleeps_calls = []
+
+
+@rule(E, [A])
+def sleeps(a):
+  _sleeps_calls.append(time.time())
+  time.sleep(3)
+  return E()
+
+
+class FileCount(datatype(['count'])):
+  pass
+
+
+class CountedFiles(datatype(['count'])):
+  pass
+
+
+# The arguments with which `count_files` and `counted_files` have been called.
+_count_files_calls = []
+_counted_files_calls = []
+
+
+@rule(FileCount, [Snapshot])
+def count_files(snapshot):
+  _count_files_calls.append(snapshot)
+  return FileCount(len(snapshot.files))
+
+
+@rule(CountedFiles, [FileCount])
+def counted_files(file_count):
+  _counted_files_calls.append(file_count)
+  return CountedFiles(file_count.count)
+
+
 @contextmanager
 def assert_execution_error(test_case, expected_msg):
   with test_case.assertRaises(ExecutionError) as cm:
@@ -198,6 +258,107 @@ class SchedulerTest(TestBase):
     # we're just testing transitively resolving products in this file.
     self.assertTrue(isinstance(result_d, D))
 
+  def test_concurrent_product_requests(self):
+    # Confirm that a single Scheduler can be used from multiple python threads at once.
+    results = {}
+
+    def request(i):
+      a, b = A(), B()
+      results[i] = (self.scheduler.product_request(str, [Params(a, b)]), consumes_a_and_b(a, b))
+
+    threads = [threading.Thread(target=request, args=(i,)) for i in range(8)]
+    for thread in threads:
+      thread.start()
+    for thread in threads:
+      thread.join()
+
+    self.assertEqual(8, len(results))
+    for (result_str,), expected in results.values():
+      self.assertEqual(expected, result_str)
+
+  def test_visualize_json(self):
+    self.scheduler.product_request(str, [Params(A(), B())])
+    with temporary_dir() as tmpdir:
+      path = os.path.join(tmpdir, 'graph.json')
+      self.scheduler.visualize_graph_to_file(path, node_types=('Select',), max_depth=0)
+      with open(path, 'r') as f:
+        nodes = json.load(f)['nodes']
+
+    self.assertTrue(nodes)
+    for node in nodes:
+      self.assertTrue(node['node'].startswith('Select('))
+      self.assertEqual('completed', node['state'])
+      self.assertEqual([], node['dependencies'])
+
+  def test_memory_summary(self):
+    self.scheduler.product_request(str, [Params(A(), B())])
+    summary = self.scheduler.memory_summary()
+    self.assertGreater(summary['graph']['Select']['count'], 0)
+    self.assertGreater(summary['graph']['Task']['bytes'], 0)
+    self.assertGreater(summary['interns']['keys'], 0)
+    self.assertIn('count', summary['workunits'])
+    self.assertTrue(summary['lmdb'])
+    for path, size in summary['lmdb'].items():
+      self.assertTrue(path.endswith('data.mdb'))
+
+  def test_collect_interned_keys(self):
+    scheduler = self.scheduler._scheduler
+    # Intern a key which is not referenced by the graph.
+    scheduler._to_key(C())
+    a, b = A(), B()
+    self.scheduler.product_request(str, [Params(a, b)])
+    # Keys are only collected once they have survived a complete epoch.
+    scheduler.collect_interned_keys()
+    self.assertGreaterEqual(scheduler.collect_interned_keys(), 1)
+    # Keys which are referenced by the graph survive collection.
+    result_str, = self.scheduler.product_request(str, [Params(a, b)])
+    self.assertEqual(result_str, consumes_a_and_b(a, b))
+
+  def test_write_profile(self):
+    session = self.scheduler._scheduler.new_session()
+    try:
+      session.start_profiling(99)
+    except Exception as e:
+      if 'built without the `profiling` feature' in str(e):
+        self.skipTest(str(e))
+      raise
+    session.product_request(str, [Params(A(), B())])
+    with temporary_dir() as tmpdir:
+      path = os.path.join(tmpdir, 'profile.svg')
+      session.write_profile(path)
+      with open(path, 'r') as f:
+        self.assertIn('<svg', f.read())
+
+  def test_console_output_is_teed_to_its_session(self):
+    first_session = self.scheduler._scheduler.new_session()
+    second_session = self.scheduler._scheduler.new_session()
+    with temporary_dir() as tmpdir:
+      first_path = os.path.join(tmpdir, 'first.log')
+      second_path = os.path.join(tmpdir, 'second.log')
+      first_session.tee_console_to(first_path)
+      second_session.tee_console_to(second_path)
+
+      stdout = StringIO()
+      console = Console(stdout=stdout, use_colors=False, tee=first_session.write_to_console_tee)
+      console.print_stdout('\x1b[32mfirst\x1b[0m')
+      Console(stdout=StringIO(), tee=second_session.write_to_console_tee).write_stdout('second')
+
+      self.assertEqual('\x1b[32mfirst\x1b[0m\n', stdout.getvalue())
+      # Each copy is stripped of ANSI escapes, and reaches only the tee of its own session.
+      with open(first_path, 'r') as f:
+        first_tee = f.read()
+      with open(second_path, 'r') as f:
+        second_tee = f.read()
+      self.assertIn('first\n', first_tee)
+      self.assertNotIn('second', first_tee)
+      self.assertIn('second', second_tee)
+      self.assertNotIn('first', second_tee)
+
+  def test_write_profile_requires_profiling(self):
+    with temporary_dir() as tmpdir:
+      with self.assertRaisesRegexp(Exception, 'Profiling was not started'):
+        self.scheduler.write_profile(os.path.join(tmpdir, 'profile.svg'))
+
   @contextmanager
   def _assert_execution_error(self, expected_msg):
     with assert_execution_error(self, expected_msg):
@@ -217,6 +378,58 @@ Exception: WithDeps(Inner(InnerEntry { params: {UnionWrapper}, rule: Task(Task {
       self.scheduler.product_request(A, [Params(UnionWrapper(A()))])
 
 
+class SchedulerTimeoutTest(TestBase):
+
+  @classmethod
+  def rules(cls):
+    return super(SchedulerTimeoutTest, cls).rules() + [
+      RootRule(A),
+      sleeps,
+    ]
+
+  def test_session_timeout(self):
+    session = self.scheduler._scheduler.new_session(timeout_secs=1)
+    with assert_execution_error(self, 'Build timed out after 1s.'):
+      session.product_request(E, [Params(A())])
+
+  def test_session_timeout_cancels_running_work(self):
+    calls = len(_sleeps_calls)
+    session = self.scheduler._scheduler.new_session(timeout_secs=1)
+    with assert_execution_error(self, 'Build timed out after 1s.'):
+      session.product_request(E, [Params(A())])
+    # Had it been left running in the background, the rule would complete during this sleep.
+    time.sleep(3)
+    # But it was cancelled, so its Node was not completed, and the rule runs again.
+    self.scheduler.product_request(E, [Params(A())])
+    self.assertEqual(calls + 2, len(_sleeps_calls))
+
+
+class SchedulerCleaningTest(TestBase):
+
+  @classmethod
+  def rules(cls):
+    return super(SchedulerCleaningTest, cls).rules() + [
+      count_files,
+      counted_files,
+    ]
+
+  def test_rerun_task_with_equal_result_cleans_dependees(self):
+    self.create_file('src/a.txt', 'a')
+    path_globs = PathGlobs(('src/*.txt',))
+    result, = self.scheduler.product_request(CountedFiles, [path_globs])
+    self.assertEqual(CountedFiles(1), result)
+    count_calls = len(_count_files_calls)
+    counted_calls = len(_counted_files_calls)
+
+    # A whitespace-only edit changes the Snapshot, and so re-runs the rule which counts it.
+    self.create_file('src/a.txt', 'a\n')
+    result, = self.scheduler.product_request(CountedFiles, [path_globs])
+    self.assertEqual(CountedFiles(1), result)
+    self.assertEqual(count_calls + 1, len(_count_files_calls))
+    # But the count is equal, so the rule which consumes it is cleaned rather than re-run.
+    self.assertEqual(counted_calls, len(_counted_files_calls))
+
+
 class SchedulerWithNestedRaiseTest(TestBase):
 
   @classmethod
@@ -229,6 +442,7 @@ class SchedulerWithNestedRaiseTest(TestBase):
       c_unhashable,
       c_unhashable_datatype,
       nested_raise,
+      catches_nested_raise,
     ]
 
   def test_get_type_match_failure(self):
@@ -240,6 +454,10 @@ Exception: WithDeps(Inner(InnerEntry { params: {TypeCheckFailWrapper}, rule: Tas
       # `a_typecheck_fail_test` above expects `wrapper.inner` to be a `B`.
       self.scheduler.product_request(A, [Params(TypeCheckFailWrapper(A()))])
 
+  def test_get_failure_is_thrown_into_rule(self):
+    result_str, = self.scheduler.product_request(str, [Params(B())])
+    self.assertEqual('Caught: An exception for B', result_str)
+
   def test_unhashable_failure(self):
     """Test that unhashable Get(...) params result in a structured error."""
 
@@ -8,7 +8,7 @@ import ast
 import unittest
 from builtins import object, str
 
-from pants.engine.selectors import Get
+from pants.engine.selectors import Get, GetAll
 
 
 class AClass(object):
@@ -74,3 +74,18 @@ Three arg form of Get expected (product_type, subject_declared_type, subject), b
   def test_create_statically_for_rule_graph(self):
     self.assertEqual(Get(AClass, BClass, None),
                      Get.create_statically_for_rule_graph(AClass, BClass))
+
+
+class GetAllTest(unittest.TestCase):
+  def test_create(self):
+    self.assertEqual(GetAll(AClass, BClass, [BClass(), SubBClass()]),
+                     GetAll(AClass, BClass, (BClass(), SubBClass())))
+
+  def test_extract_constraints(self):
+    parsed_call = ast.parse("GetAll(A, B, xs)").body[0].value
+    self.assertEqual(('A', 'B'), GetAll.extract_constraints(parsed_call))
+
+    with self.assertRaises(ValueError) as cm:
+      GetAll.extract_constraints(ast.parse("GetAll(A, B(x))").body[0].value)
+    self.assertEqual(str(cm.exception), """\
+GetAll expected (product_type, subject_declared_type, subjects), but got: (A, Call)""")
@@ -5,16 +5,41 @@
 from __future__ import absolute_import, division, print_function, unicode_literals
 
 import logging
+import sys
 from builtins import open
 from contextlib import contextmanager
 
+from pants.engine.rules import RootRule, rule
+from pants.engine.selectors import Params
 from pants.init.logging import get_numeric_level, setup_logging
 from pants.util.contextutil import temporary_dir
+from pants.util.objects import datatype
 from pants_test.test_base import TestBase
 
 
+class Message(datatype(['text'])):
+  pass
+
+
+class Logged(datatype(['text'])):
+  pass
+
+
+@rule(Logged, [Message])
+def log_message(message):
+  logging.getLogger(__name__).info(message.text)
+  return Logged(message.text)
+
+
 class LoggingTest(TestBase):
 
+  @classmethod
+  def rules(cls):
+    return super(LoggingTest, cls).rules() + [
+      RootRule(Message),
+      log_message,
+    ]
+
   def post_scheduler_init(self):
     self.native = self.scheduler._scheduler._native
     # Initialize it with the least verbose level.
@@ -22,13 +47,30 @@ class LoggingTest(TestBase):
     self.native.init_rust_logging(get_numeric_level("ERROR"), False)
 
   @contextmanager
-  def logger(self, level):
+  def logger(self, level, console_stream=None):
     native = self.scheduler._scheduler._native
     logger = logging.getLogger('my_file_logger')
     with temporary_dir() as log_dir:
-      logging_setup_result = setup_logging(level, log_dir=log_dir, scope=logger.name, native=native)
+      logging_setup_result = setup_logging(level, console_stream=console_stream, log_dir=log_dir,
+                                           scope=logger.name, native=native)
       yield logger, logging_setup_result
 
+  @contextmanager
+  def root_logger(self, level):
+    """Sets up logging for the root logger, which records emitted by the engine propagate to."""
+    native = self.scheduler._scheduler._native
+    root_logger = logging.getLogger()
+    handlers, root_level = list(root_logger.handlers), root_logger.level
+    try:
+      with temporary_dir() as log_dir:
+        yield setup_logging(level, log_dir=log_dir, native=native)
+    finally:
+      for handler in list(root_logger.handlers):
+        root_logger.removeHandler(handler)
+      for handler in handlers:
+        root_logger.addHandler(handler)
+      root_logger.setLevel(root_level)
+
   def test_utf8_logging(self):
     with self.logger('INFO') as (file_logger, logging_setup_result):
       cat = "🐈"
@@ -50,3 +92,46 @@ class LoggingTest(TestBase):
         self.assertEqual(2, len(loglines))
         self.assertIn("[WARN] this is a warning", loglines[0])
         self.assertIn("[INFO] this is some info", loglines[1])
+
+  def test_custom_level_logging(self):
+    with self.logger('INFO') as (file_logger, logging_setup_result):
+      file_logger.log(25, 'this is between info and warn')
+      logging_setup_result.log_handler.flush()
+
+      with open(logging_setup_result.log_filename, 'r') as fp:
+        loglines = fp.read().splitlines()
+        self.assertEqual(1, len(loglines))
+        self.assertIn("[INFO] this is between info and warn", loglines[0])
+
+  def test_console_and_file_logging_not_duplicated(self):
+    with self.logger('INFO', console_stream=sys.stderr) as (file_logger, logging_setup_result):
+      self.assertEqual(1, len(file_logger.handlers))
+      file_logger.warn('this is a warning')
+      logging_setup_result.log_handler.flush()
+
+      with open(logging_setup_result.log_filename, 'r') as fp:
+        loglines = fp.read().splitlines()
+        self.assertEqual(1, len(loglines))
+        self.assertIn("[WARN] this is a warning", loglines[0])
+
+  def test_engine_and_rule_logging_interleave(self):
+    with self.root_logger('DEBUG') as logging_setup_result:
+      logged, = self.scheduler.product_request(Logged, [Params(Message('logged by a rule'))])
+      self.assertEqual(Logged('logged by a rule'), logged)
+      logging_setup_result.log_handler.flush()
+
+      with open(logging_setup_result.log_filename, 'r') as fp:
+        loglines = fp.read().splitlines()
+
+    def index_of(message):
+      return next(i for i, line in enumerate(loglines) if message in line)
+
+    # The rule logs while its root is running: between the engine launching and completing it.
+    launched = index_of('engine.scheduler: Launching 1 roots.')
+    logged = index_of('[INFO] logged by a rule')
+    completed = index_of('engine.scheduler: Root ')
+    self.assertLess(launched, logged)
+    self.assertLess(logged, completed)
+    # And every record has the same timestamp format, regardless of where it was emitted.
+    for line in loglines:
+      self.assertRegexpMatches(line, r'^\d{2}:\d{2}:\d{2} \[[A-Z]+\] ')
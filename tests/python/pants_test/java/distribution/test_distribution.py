@@ -112,6 +112,23 @@ class DistributionValidationTest(unittest.TestCase):
                    minimum_version='1.7.0_25',
                    maximum_version='1.7.999').validate()
 
+  def test_resolved_identity_follows_symlink(self):
+    with distribution(executables=EXE('bin/java', '1.7.0_25')) as java7_root:
+      with distribution(executables=EXE('bin/java', '1.8.0_1')) as java8_root:
+        with temporary_dir() as link_dir:
+          jdk_home = os.path.join(link_dir, 'jdk')
+          os.symlink(java7_root, jdk_home)
+          dist = Distribution(home_path=jdk_home)
+          dist.validate()
+          java7_identity = dist.resolved_identity
+          self.assertIn('1.7.0_25', java7_identity)
+          self.assertEqual(java7_identity, dist.resolved_identity)
+
+          # Switching the JDK under the same path changes the identity.
+          os.unlink(jdk_home)
+          os.symlink(java8_root, jdk_home)
+          self.assertIn('1.8.0_1', dist.resolved_identity)
+
   def test_validated_binary(self):
     with distribution(files='bin/jar', executables=EXE('bin/java')) as dist_root:
       with self.assertRaises(Distribution.Error):
@@ -5,12 +5,15 @@
 from __future__ import absolute_import, division, print_function, unicode_literals
 
 import os
+from collections import OrderedDict
 from contextlib import contextmanager
 
 import mock
 import psutil
 
 from pants.java.nailgun_executor import NailgunExecutor
+from pants.util.contextutil import temporary_dir
+from pants.util.dirutil import safe_file_dump
 from pants_test.test_base import TestBase
 
 
@@ -93,3 +96,46 @@ Stdout:
 Stderr:
 err"""):
         self.executor._await_socket(timeout=0.0001)
+
+  def _crashed_executor(self, workdir, pid=3):
+    executor = NailgunExecutor(identity='test',
+                               workdir=workdir,
+                               nailgun_classpath=[],
+                               distribution=mock.Mock(),
+                               metadata_base_dir=self.subprocess_dir)
+    executor.write_pid(pid)
+    return executor
+
+  def test_collect_crash_logs_alive(self):
+    with temporary_dir() as workdir,\
+         mock.patch.object(NailgunExecutor, 'is_alive', **PATCH_OPTS) as mock_is_alive:
+      mock_is_alive.return_value = True
+      self.assertIsNone(self._crashed_executor(workdir)._collect_crash_logs())
+
+  def test_collect_crash_logs(self):
+    with temporary_dir() as workdir,\
+         mock.patch.object(NailgunExecutor, 'is_alive', **PATCH_OPTS) as mock_is_alive:
+      mock_is_alive.return_value = False
+      safe_file_dump(os.path.join(workdir, 'hs_err_pid3.log'), b'SIGSEGV', mode='wb')
+      safe_file_dump(os.path.join(workdir, 'hs_err_pid4.log'), b'stale', mode='wb')
+      safe_file_dump(os.path.join(workdir, 'stderr'),
+                     b'x' * NailgunExecutor._CRASH_LOG_TAIL_BYTES + b'tail',
+                     mode='wb')
+
+      crash_logs = self._crashed_executor(workdir)._collect_crash_logs()
+      self.assertEqual(['hs_err_pid3', 'nailgun_stderr'], list(crash_logs.keys()))
+      self.assertEqual(b'SIGSEGV', crash_logs['hs_err_pid3'])
+      self.assertEqual(NailgunExecutor._CRASH_LOG_TAIL_BYTES, len(crash_logs['nailgun_stderr']))
+      self.assertTrue(crash_logs['nailgun_stderr'].endswith(b'tail'))
+
+  def test_crashed_error_message(self):
+    error = NailgunExecutor.NailgunServerCrashed(identity='test',
+                                                 pid=3,
+                                                 main='Main',
+                                                 args=['a', 'b'],
+                                                 cause='connection reset',
+                                                 crash_logs=OrderedDict(hs_err_pid3=b''))
+    self.assertIsInstance(error, NailgunExecutor.Error)
+    self.assertEqual('Nailgun server for test (pid=3) crashed while running Main a b: '
+                     'connection reset\nCollected crash logs: hs_err_pid3',
+                     str(error))
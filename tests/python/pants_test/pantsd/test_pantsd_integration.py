@@ -347,6 +347,71 @@ class TestPantsDaemonIntegration(PantsDaemonIntegrationTestBase):
 
       self.assertIn('saw file events covered by invalidation globs', full_pantsd_log())
 
+  def _assert_engine_watcher(self, watcher):
+    config = {'GLOBAL': {'pantsd_watcher': watcher}}
+    with self.pantsd_successful_run_context(extra_config=config) as (
+      pantsd_run, checker, workdir, _
+    ):
+      with temporary_dir('.') as directory:
+        safe_file_dump(os.path.join(directory, 'A.py'), mode='w')
+        if directory.startswith('./'):
+          directory = directory[2:]
+
+        def list_and_verify(*targets):
+          result = pantsd_run(['list', '{}:'.format(directory)])
+          checker.assert_started()
+          self.assert_success(result)
+          expected_targets = {'{}:{}'.format(directory, target) for target in targets}
+          self.assertEqual(expected_targets, set(result.stdout_data.strip().split('\n')))
+
+        safe_file_dump(os.path.join(directory, 'BUILD'), "python_library(name='A')\n", mode='w')
+        list_and_verify('A')
+        backend = watcher[len('engine-'):]
+        self.assertIn('watching the build root using the {} backend'.format(backend),
+                      '\n'.join(read_pantsd_log(workdir)))
+
+        # The change is observed by the engine's watcher, rather than by the FSEventService.
+        safe_file_dump(os.path.join(directory, 'BUILD'),
+                       "python_library(name='A')\npython_library(name='B')\n",
+                       mode='w')
+        # Permit time for the async file event to propagate.
+        time.sleep(5)
+        list_and_verify('A', 'B')
+        checker.assert_running()
+
+  def test_pantsd_engine_notify_watcher(self):
+    self._assert_engine_watcher('engine-notify')
+
+  def test_pantsd_engine_watchman_watcher(self):
+    self._assert_engine_watcher('engine-watchman')
+
+  def test_pantsd_engine_watcher_invalidation(self):
+    test_dir = 'testprojects/src/python/print_env'
+    config = {'GLOBAL': {
+      'pantsd_watcher': 'engine-notify',
+      'pantsd_invalidation_globs': '["%s/*"]' % test_dir,
+    }}
+    with self.pantsd_successful_run_context(extra_config=config) as (
+      pantsd_run, checker, workdir, _
+    ):
+      pantsd_run(['help'])
+      checker.assert_started()
+
+      # Let any fs events quiesce.
+      time.sleep(5)
+      checker.assert_running()
+
+      # Create a new file in test_dir, which the engine reports via `restart_required`.
+      with temporary_file(suffix='.py', binary_mode=False, root_dir=test_dir) as temp_f:
+        temp_f.write("import that\n")
+        temp_f.close()
+
+        time.sleep(10)
+        checker.assert_stopped()
+
+      self.assertIn('saw file events covered by invalidation globs',
+                    '\n'.join(read_pantsd_log(workdir)))
+
   def test_pantsd_invalidation_pants_ini_file(self):
     # Test tmp_pants_ini (--pants-config-files=$tmp_pants_ini)'s removal
     tmp_pants_ini = os.path.abspath("testprojects/test_pants.ini")